@@ -83,10 +83,54 @@ fn main() {
     // core::probe::kernel
     build_probe("src/core/probe/kernel/bpf/kprobe.bpf.c");
     build_probe("src/core/probe/kernel/bpf/raw_tracepoint.bpf.c");
+    build_probe("src/core/probe/kernel/bpf/softirq.bpf.c");
+    // Hooks only used by the core::probe::kernel tests.
+    build_hook("src/core/probe/kernel/bpf/test/discard_hook.bpf.c");
+    build_hook("src/core/probe/kernel/bpf/test/count_hook.bpf.c");
 
     // collector::skb_tracking
     build_hook("src/collector/skb_tracking/bpf/tracking_hook.bpf.c");
 
+    // collector::skb
+    build_hook("src/collector/skb/bpf/skb_hook.bpf.c");
+    build_hook("src/collector/skb/bpf/icmp_hook.bpf.c");
+
+    // collector::xfrm
+    build_hook("src/collector/xfrm/bpf/xfrm_hook.bpf.c");
+
+    // collector::bridge
+    build_hook("src/collector/bridge/bpf/bridge_hook.bpf.c");
+
+    // collector::cgroup
+    build_probe("src/collector/cgroup/bpf/cgroup.bpf.c");
+
+    // collector::kprobe
+    build_hook("src/collector/kprobe/bpf/kprobe_hook.bpf.c");
+
+    // collector::xdp
+    build_hook("src/collector/xdp/bpf/xdp_hook.bpf.c");
+
+    // collector::sockfilter
+    build_probe("src/collector/sockfilter/bpf/sockfilter.bpf.c");
+
+    // collector::tc_bpf
+    build_probe("src/collector/tc_bpf/bpf/tc_bpf.bpf.c");
+
+    // collector::tcp_retrans
+    build_hook("src/collector/tcp_retrans/bpf/tcp_retrans_hook.bpf.c");
+
+    // collector::sockdrop
+    build_hook("src/collector/sockdrop/bpf/sockdrop_hook.bpf.c");
+
+    // collector::netfilter
+    build_hook("src/collector/netfilter/bpf/netfilter_hook.bpf.c");
+
+    // collector::dropmon
+    build_hook("src/collector/dropmon/bpf/dropmon_hook.bpf.c");
+
+    // collector::wireguard
+    build_hook("src/collector/wireguard/bpf/wireguard_hook.bpf.c");
+
     for inc in INCLUDE_PATHS.iter() {
         println!("cargo:rerun-if-changed={}", inc);
     }
@@ -6,11 +6,12 @@
 use std::{any::Any, collections::HashMap, env, ffi::OsString, fmt::Debug};
 
 use anyhow::{anyhow, bail, Result};
-use clap::error::Error as ClapError;
+use clap::error::{Error as ClapError, ErrorKind};
 use clap::{ArgMatches, Args, Command, FromArgMatches};
 
-use super::cmd::Collect;
+use super::cmd::{Capabilities, Collect, ListTracepoints, SelfTest};
 use super::dynamic::DynamicCommand;
+use super::profile::expand_profiles;
 
 /// SubCommand defines the way to handle SubCommands.
 /// SubCommands arguments are parsed in two rounds, the "thin" and the "full" round.
@@ -73,7 +74,22 @@ impl Debug for dyn SubCommand {
 ///
 /// packet-tracer is a tool for capturing networking-related events from the system using ebpf and analyzing them.
 #[derive(Args, Default, Debug)]
-pub(crate) struct MainConfig {}
+pub(crate) struct MainConfig {
+    /// Log level
+    #[arg(
+        long,
+        default_value = "debug",
+        value_parser = ["off", "error", "warn", "info", "debug", "trace"],
+    )]
+    pub(crate) log_level: String,
+    /// Only display errors on the terminal. This does not affect the log file.
+    #[arg(long, short)]
+    pub(crate) quiet: bool,
+    /// Also write logs to a file, as JSON lines. Logs are never written to the
+    /// events output.
+    #[arg(long)]
+    pub(crate) log_file: Option<String>,
+}
 
 /// ThinCli handles the first (a.k.a "thin") round of Command Line Interface parsing.
 ///
@@ -116,6 +132,16 @@ impl ThinCli {
         T: Into<OsString> + Clone,
     {
         let args: Vec<OsString> = args.into_iter().map(|x| x.into()).collect();
+        let args = match expand_profiles(args) {
+            Ok(args) => args,
+            Err(e) => {
+                let err = ClapError::raw(ErrorKind::InvalidValue, format!("{}\n", e));
+                match dry_run {
+                    true => return Err(err),
+                    false => err.exit(),
+                }
+            }
+        };
         let mut command =
             MainConfig::augment_args(Command::new("packet-tracer")).subcommand_required(true);
         // Add thin subcommands so that the main help shows them.
@@ -130,6 +156,11 @@ impl ThinCli {
             .ignore_errors(true)
             .try_get_matches_from(args.iter())?;
 
+        // Main arguments are known at this point, retrieve them early so they
+        // can be used before the full parsing (e.g. to set up logging).
+        let mut main_config = MainConfig::default();
+        main_config.update_from_arg_matches(&matches)?;
+
         let ran_subcommand = matches.subcommand_name();
 
         if ran_subcommand.is_none()
@@ -155,6 +186,7 @@ impl ThinCli {
             args,
             command,
             dry_run,
+            main_config,
             subcommand: self
                 .subcommands
                 .remove(&ran_subcommand.unwrap().to_string())
@@ -173,6 +205,7 @@ pub(crate) struct FullCli {
     args: Vec<OsString>,
     command: Command,
     dry_run: bool,
+    main_config: MainConfig,
     subcommand: Box<dyn SubCommand>,
 }
 
@@ -220,6 +253,11 @@ impl FullCli {
         })
     }
 
+    /// Returns the main configuration, as found during the thin parsing.
+    pub(crate) fn get_main_config(&self) -> Result<&MainConfig> {
+        Ok(&self.main_config)
+    }
+
     pub(crate) fn get_subcommand(&self) -> Result<&dyn SubCommand> {
         Ok(self.subcommand.as_ref())
     }
@@ -258,6 +296,9 @@ impl CliConfig {
 pub(crate) fn get_cli() -> Result<ThinCli> {
     let mut cli = ThinCli::new()?;
     cli.add_subcommand(Box::new(Collect::new()?))?;
+    cli.add_subcommand(Box::new(Capabilities::new()?))?;
+    cli.add_subcommand(Box::new(ListTracepoints::new()?))?;
+    cli.add_subcommand(Box::new(SelfTest::new()?))?;
     Ok(cli)
 }
 
@@ -0,0 +1,56 @@
+//! # Capabilities
+//!
+//! Capabilities is a simple CLI subcommand reporting what the tool can do on the running system.
+
+use anyhow::Result;
+use std::any::Any;
+
+use clap::error::Error as ClapError;
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use super::super::SubCommand;
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct Capabilities {
+    /// Output format
+    #[arg(long, default_value = "json", value_parser = ["json"])]
+    pub(crate) format: String,
+}
+
+impl SubCommand for Capabilities {
+    fn new() -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Capabilities::default())
+    }
+
+    fn name(&self) -> &'static str {
+        "capabilities"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn thin(&self) -> Result<Command> {
+        Ok(Command::new("capabilities").about("Report the tool capabilities on this system"))
+    }
+
+    fn full(&self) -> Result<Command> {
+        let long_about = "Report the tool capabilities on this system: kernel version, \
+            supported BPF features and probe types, and which collectors can be used. \
+            The output is versioned and meant to be consumed by other tools."
+            .to_string();
+
+        Ok(Capabilities::augment_args(
+            Command::new("capabilities")
+                .about("Report the tool capabilities on this system")
+                .long_about(long_about),
+        ))
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), ClapError> {
+        <Self as FromArgMatches>::update_from_arg_matches(self, matches)
+    }
+}
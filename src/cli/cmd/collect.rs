@@ -3,23 +3,495 @@
 //! Collect is a dynamic CLI subcommand that allows collectors to register their arguments.
 #![allow(dead_code)] // FIXME
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use std::any::Any;
 
 use clap::error::Error as ClapError;
-use clap::{builder::PossibleValuesParser, error::ErrorKind, Arg, ArgMatches, Args, Command};
+use clap::{error::ErrorKind, parser::ValueSource, Arg, ArgMatches, Args, Command};
 
 use super::super::dynamic::DynamicCommand;
 use super::super::SubCommand;
 
 #[derive(Args, Debug, Default)]
 pub(crate) struct CollectArgs {
+    /// Use a named set of collectors and options for a common task: "drops"
+    /// (dropped packets and where) or "ovs" (packets going through
+    /// OpenvSwitch), or a user-defined one. Options given explicitly take
+    /// precedence over the profile ones.
+    #[arg(long)]
+    pub(crate) profile: Option<String>,
+    /// JSON file defining profiles, as an object mapping profile names to
+    /// their list of arguments. Defaults to /etc/packet-tracer/profiles.json,
+    /// if it exists.
+    #[arg(long)]
+    pub(crate) profile_file: Option<String>,
     #[arg(long, default_value = "false")]
     pub(crate) ebpf_debug: Option<bool>,
+    /// Write the messages BPF programs emit using bpf_printk() to the log,
+    /// for debugging hooks. They are read from the tracefs trace pipe, which
+    /// must be available, and are consumed: other trace pipe readers won't
+    /// see them.
+    #[arg(long)]
+    pub(crate) bpf_trace: bool,
+    /// Write the loaded probe and hook programs to a directory, for
+    /// debugging: their instructions as translated by the kernel (after CO-RE
+    /// relocations) and a description of the maps they use. Files are named
+    /// after the probe type, its target and the program name.
+    #[arg(long)]
+    pub(crate) dump_bpf: Option<String>,
+    /// Pin the filter maps (pid filter, match set, interface filter) in a
+    /// bpffs directory, for them to be updated by other tools (e.g. bpftool)
+    /// while collecting. Compatible maps pinned by a previous run are reused.
+    #[arg(long, value_name = "DIR")]
+    pub(crate) pin_maps: Option<String>,
+    /// Only report events generated in the context of the given process (tgid).
+    /// Packets processed in softirq context (e.g. forwarded packets) can't be
+    /// attributed to a process and are dropped.
+    #[arg(long)]
+    pub(crate) pid: Option<u32>,
+    /// When filtering on a pid, also report events generated by its
+    /// descendants. The process tree is rescanned periodically.
+    #[arg(long, default_value = "false")]
+    pub(crate) pid_children: Option<bool>,
+    /// Only report events for packets attached to the given interfaces, by
+    /// name or glob pattern (e.g. "eth*"). Comma separated list. Names which
+    /// aren't patterns must exist; interfaces matching a pattern are picked
+    /// up while collecting. Filtering is done in the kernel by the skb
+    /// collector, which is enabled automatically. Packets not attached to a
+    /// device yet (e.g. early in the local output path) are not reported.
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) interface: Vec<String>,
+    /// Only report events for the packets of a single flow, in both
+    /// directions, e.g. "10.0.0.1:1234-10.0.0.2:443/tcp". Endpoints are
+    /// "<addr>[:<port>]" (IPv6 addresses in brackets when a port is given),
+    /// addresses and ports can be wildcards ("*") and the protocol (tcp, udp
+    /// or sctp) is optional. Filtering is done in the kernel by the skb
+    /// collector; it and the skb-tracking collector are enabled automatically.
+    #[arg(long)]
+    pub(crate) flow: Option<String>,
+    /// Only report the first N packets of each flow (5-tuple), e.g. to sample
+    /// the setup of connections. Packets are accounted for in the kernel by
+    /// the skb collector, which is enabled automatically; when too many flows
+    /// are seen the least recently active ones are forgotten. Packets are
+    /// identified by their data buffer, so a recycled buffer can be reported
+    /// as an already seen packet.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) flow_head: Option<u32>,
+    /// Only report the first packet of each new flow, both directions of a
+    /// flow being accounted for together, e.g. to log connection attempts.
+    /// This is a bidirectional --flow-head 1 whose flows expire after
+    /// --flow-timeout (60s by default). The number of flows tracked is
+    /// reported in the statistics of the flow_head_map map.
+    #[arg(long, conflicts_with = "flow_head")]
+    pub(crate) new_flows_only: bool,
+    /// Inactivity, in seconds, after which a flow is forgotten and its next
+    /// packets are accounted for as a new flow (see --flow-head and
+    /// --new-flows-only). Defaults to 60 with --new-flows-only and to never
+    /// expiring flows otherwise.
+    #[arg(long)]
+    pub(crate) flow_timeout: Option<u32>,
+    /// Only report packets matching a set of 5-tuples and IP prefixes, read
+    /// from a file. The file has one entry per line, either a prefix
+    /// ("10.0.0.0/8", "2001:db8::1") matching the source or destination
+    /// address of packets, or a 5-tuple using the --flow syntax without
+    /// wildcards matching in both directions; lines starting with '#' are
+    /// ignored. Filtering is done in the kernel by the skb collector, which is
+    /// enabled automatically. The set can be replaced at runtime using the
+    /// control socket.
+    #[arg(long)]
+    pub(crate) match_set: Option<String>,
+    /// Maximum size of the event data, in bytes. Events are built in the
+    /// kernel up to the ring buffer slot size (1022 bytes): sections not
+    /// fitting in it are not added. Larger events than this limit see their
+    /// last sections dropped and are flagged as "truncated". Both are
+    /// accounted for in the stop event.
+    #[arg(long, default_value = "1022", value_parser = clap::value_parser!(u64).range(64..=1022))]
+    pub(crate) max_event_size: Option<u64>,
+    /// Number of worker threads processing events in parallel.
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..=64))]
+    pub(crate) workers: Option<u32>,
+    /// Report events in the order they were retrieved. Consumers not needing
+    /// ordering can disable it to lower the latency when using multiple
+    /// workers.
+    #[arg(long, default_value = "true")]
+    pub(crate) ordered: Option<bool>,
+    /// Only write events matching an expression on their fields, e.g.
+    /// "drop_reason == NO_SOCKET && l4proto == 17". Comparisons (==, !=, <,
+    /// <=, >, >=) can be combined using && and || and grouped using
+    /// parentheses. Fields are referenced by key or as "section.key".
+    /// Events classified by collectors have "category" (info, forward, error,
+    /// drop) and "severity" (debug, info, warn, error) fields, e.g.
+    /// "category == drop".
+    #[arg(long = "where")]
+    pub(crate) r#where: Option<String>,
+    /// Annotate addresses with their host name, using cached reverse DNS
+    /// lookups. Lookups are asynchronous: addresses are annotated once their
+    /// name is known.
+    #[arg(long)]
+    pub(crate) resolve_names: bool,
+    /// Annotate events of packets on veth interfaces with the ifindex of the
+    /// other end of the pair (skb.veth_peer), which can be in another network
+    /// namespace. Always enabled with --path, where crossing a veth pair is
+    /// shown as a single hop.
+    #[arg(long)]
+    pub(crate) veth_peers: bool,
+    /// Detect asymmetric routing: flows (protocol and addresses) whose two
+    /// directions go through different interfaces, a common cause of
+    /// firewall and conntrack drops. Asymmetric flows are reported once the
+    /// collection ends, as warning events listing the interfaces seen per
+    /// direction. Requires the skb collector packet bytes (see
+    /// --capture-bytes) and device (ifindex).
+    #[arg(long)]
+    pub(crate) detect_asymmetry: bool,
+    /// Redact event fields which may contain private information (e.g.
+    /// "saddr,daddr") before writing events, in all formats, so captures can
+    /// be shared. Fields are given as "<key>", in all sections, or
+    /// "<section>.<key>". Comma separated list, or repeated option. Annotations
+    /// derived from redacted fields (e.g. host names) should be redacted too.
+    /// Meta events are redacted as well. When redacting, raw packet bytes and
+    /// meta fields summarizing flows or filters are always replaced by a
+    /// placeholder.
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) redact: Vec<String>,
+    /// How redacted values are replaced: by a hash, preserving correlation
+    /// between events, or by a fixed placeholder.
+    #[arg(long, default_value = "hash", value_parser = ["hash", "placeholder"])]
+    pub(crate) redact_mode: Option<String>,
+    /// Salt (HMAC key) of the redaction hashes. Captures sharing a salt can be
+    /// correlated; it must be kept private. A random key is used by default.
+    #[arg(long)]
+    pub(crate) redact_salt: Option<String>,
+    /// Annotate public addresses with their country and autonomous system,
+    /// using local MaxMind DB databases (e.g. GeoLite2-Country and
+    /// GeoLite2-ASN). Comma separated list, or repeated option. Requires
+    /// building with the "geoip" feature.
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) geoip_db: Vec<String>,
+    /// Maximum number of events written per second. Excess events are dropped
+    /// (and accounted for in the stop event). This acts in userspace, as a
+    /// last resort safety valve.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) max_eps: Option<u64>,
+    /// Maximum number of events reported per second, per probe. This gives
+    /// each probe a fair share of the events buffer, preventing a single busy
+    /// probe from starving the others. Excess events are dropped by the probes
+    /// before being built and accounted for, per probe and per collector, in
+    /// the stop event.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) max_eps_per_probe: Option<u64>,
+    /// Memory budget, in MiB, of the userspace caches and aggregates growing
+    /// with the traffic. Once over it, entries are evicted from the least
+    /// critical structures first: the --resolve-names cache (oldest lookups,
+    /// looked up again when needed), then the --path packet paths (oldest
+    /// packets) and finally the --summary aggregates (entries with the fewest
+    /// events). Evictions are reported in the stop event.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) max_memory: Option<u64>,
+    /// Clock used for event timestamps. "mono" only reports the monotonic
+    /// timestamp; "tai" also reports a TAI timestamp (timestamp_tai), e.g. to
+    /// correlate events with NIC hardware timestamps in PTP deployments. TAI
+    /// timestamps require Linux v6.1, only the monotonic one is reported
+    /// otherwise.
+    #[arg(long, default_value = "mono", value_parser = ["mono", "tai"])]
+    pub(crate) clock: Option<String>,
+    /// Report the context events were generated in (common.context): "task"
+    /// (process context, with its pid and comm), "softirq" (with the softirq
+    /// being run, e.g. NET_RX), "hardirq" or "nmi". Useful to tell whether a
+    /// packet was processed in the driver softirq or by a process.
+    #[arg(long)]
+    pub(crate) context: bool,
+    /// Stop the collection after a given time, in seconds.
+    #[arg(long)]
+    pub(crate) duration: Option<u64>,
+    /// Run a command (using /bin/sh) for the duration of the collection. The
+    /// command starts once probes are attached and the collection stops when
+    /// it exits, with its exit code. If the collection is stopped first (e.g.
+    /// by --duration or a signal) the command is terminated. Its output is not
+    /// redirected and might be mixed with events written to stdout. The
+    /// command runs in its own (background) process group: it is stopped by
+    /// SIGTTIN if it reads from the terminal, its input should be redirected.
+    #[arg(long, value_name = "CMD", conflicts_with_all = ["list_probes", "estimate_overhead"])]
+    pub(crate) while_running: Option<String>,
+    /// Only report events generated by the --while-running command and its
+    /// descendants (see --pid and --pid-children).
+    #[arg(long, requires = "while_running", conflicts_with = "pid")]
+    pub(crate) filter_command: bool,
+    /// Report statistics every given number of seconds while collecting:
+    /// events retrieved per collector, events not written (filtered out or
+    /// throttled) and the event rate. Statistics are logged to stderr and are
+    /// not reported when --quiet is used.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) stats_interval: Option<u64>,
+    /// Do not report individual events: hooks only increment counters in the
+    /// kernel (packets by drop reason, ethertype and L4 protocol), which are
+    /// written as a "counters" meta event periodically (see --count-interval)
+    /// and when the collection ends. Filters still apply. Counters are
+    /// cumulative and a packet is counted once per probe it goes through.
+    #[arg(long)]
+    pub(crate) count_only: bool,
+    /// Interval, in seconds, at which counters are written when using
+    /// --count-only.
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) count_interval: Option<u64>,
+    /// Do not write events to the standard output but aggregate them, and
+    /// write a report once the collection ends (events per probe, drop
+    /// reasons, interfaces, protocols and top talkers). Protocols and talkers
+    /// are based on the packet bytes, see the skb collector --capture-bytes
+    /// option.
+    #[arg(long)]
+    pub(crate) summary: bool,
+    /// Number of entries reported per aggregation in the summary, or per
+    /// table in the --top view.
+    #[arg(long, default_value = "10")]
+    pub(crate) summary_top: Option<usize>,
+    /// Do not write events to the standard output but render a live view of
+    /// the flows, interfaces and protocols with the highest rates, refreshed
+    /// every second. Flows and protocols are based on the packet bytes, see
+    /// the skb collector --capture-bytes option.
+    #[arg(long)]
+    pub(crate) top: bool,
+    /// Counter the entries of the --top view are ranked by.
+    #[arg(long, default_value = "packets", value_parser = ["bytes", "packets", "drops"])]
+    pub(crate) top_by: Option<String>,
+    /// Do not write events to the standard output but aggregate a numeric
+    /// field, e.g. "ovs.latency" or "len", into a log2 histogram written once
+    /// the collection ends. Can be used multiple times.
+    #[arg(long)]
+    pub(crate) histogram: Vec<String>,
+    /// Group histograms by the value of a field, e.g. "ovs.dp".
+    #[arg(long)]
+    pub(crate) histogram_by: Option<String>,
+    /// Output format of the histograms.
+    #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+    pub(crate) histogram_format: Option<String>,
+    /// Do not write events to the standard output but render the path of
+    /// tracked packets once the collection ends: the probes they went
+    /// through, in order, and where they were dropped. Takes the id of a
+    /// single packet to render, as reported in skb-tracking.orig_head; all
+    /// packets are rendered otherwise. Requires the skb-tracking collector.
+    #[arg(long, num_args = 0..=1, default_missing_value = "all")]
+    pub(crate) path: Option<String>,
+    /// Output format of the packet paths: indented text, or a DOT graph.
+    #[arg(long, default_value = "text", value_parser = ["text", "dot"])]
+    pub(crate) path_format: Option<String>,
+    /// Export the path of tracked packets as OpenTelemetry traces to an
+    /// OTLP/HTTP endpoint, e.g. "http://localhost:4318". Each packet is a
+    /// trace made of a span per probe it went through, exported once no new
+    /// event was seen for it for a few seconds. Only plain http:// endpoints
+    /// are supported and failed exports are not retried. Requires the
+    /// skb-tracking collector and the "otlp" build feature.
+    #[arg(long)]
+    pub(crate) output_otlp: Option<String>,
+    /// Service name the exported packet traces are reported under.
+    #[arg(long, default_value = "packet-tracer")]
+    pub(crate) otlp_service: Option<String>,
+    /// Aggregate events into flow records (5-tuple, packet and byte counts,
+    /// start and end times, TCP flags) and export them as IPFIX over UDP to a
+    /// collector, e.g. "10.0.0.1:4739" (the port defaults to 4739). Flows are
+    /// exported once inactive (see --ipfix-idle-timeout), after a TCP FIN or
+    /// RST, and when the collection ends. Requires the skb collector packet
+    /// bytes (see --capture-bytes); the skb-tracking collector is used to
+    /// account for packets seen by multiple probes once. Can't be used with
+    /// --redact, which always redacts the packet bytes.
+    #[arg(long, value_name = "HOST[:PORT]", conflicts_with = "redact")]
+    pub(crate) output_ipfix: Option<String>,
+    /// Inactivity, in seconds, after which a flow is exported to the IPFIX
+    /// collector.
+    #[arg(long, default_value = "15", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) ipfix_idle_timeout: Option<u64>,
+    /// Maximum time, in milliseconds, events can stay buffered before being
+    /// written out. Writes are batched within this interval, which helps
+    /// under load; 0 writes events as soon as they are retrieved.
+    #[arg(long, default_value = "100")]
+    pub(crate) flush_interval: Option<u64>,
+    /// Format of the events written to the standard output, --output-file,
+    /// --ring-dir, --output-socket, --output-fifo and --output-syslog. Sinks
+    /// given with --output have their own format. The verbose format writes
+    /// each event field on its own line, with its section and type. The csv
+    /// format writes one row per event, made of the fields given by --fields,
+    /// after a header row in the standard output and files.
+    #[arg(long, default_value = "json", value_parser = ["json", "text", "verbose", "csv"])]
+    pub(crate) format: Option<String>,
+    /// Write event timestamps in the text format as a delta from the capture
+    /// start, e.g. "+0.001234s", instead of absolute ones. Other formats keep
+    /// absolute timestamps.
+    #[arg(long)]
+    pub(crate) relative_time: bool,
+    /// Fields written in the csv format, as "section.key", e.g.
+    /// "common.timestamp,skb.len". Missing fields are left empty. Defaults to
+    /// a set of fields per requested collector. Also projects JSON events when
+    /// used with --rename. Expensive skb fields (packet bytes, checksums, L3
+    /// header flags, conntrack and socket ones) are then only computed when
+    /// selected, or used by --where, --histogram, --summary or --top.
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) fields: Vec<String>,
+    /// Rename fields in JSON events, as "<field>=<name>", e.g.
+    /// "saddr=src_ip,daddr=dst_ip". Fields are designated by their key or as
+    /// "section.key". When --fields is also given, JSON events are written as
+    /// a flat object holding those fields only, in order, and renamed fields
+    /// must be part of them. Meta events are not renamed.
+    #[arg(long, value_delimiter = ',')]
+    pub(crate) rename: Vec<String>,
+    /// Wrap JSON events, meta ones included, in an envelope holding fields at
+    /// the top level, the event being nested under "event", e.g. for log
+    /// backends expecting a consistent shape. Fields are given as
+    /// "<field>[=<name>]", among "host", "labels" (see --label), "tool_version"
+    /// and "timestamp" (the event time, as a RFC 3339 UTC date), e.g.
+    /// "host,timestamp=@timestamp". All of them are written if none is given.
+    #[arg(long, value_delimiter = ',', num_args = 0..)]
+    pub(crate) envelope: Option<Vec<String>>,
+    /// Write events to a sink, in a given format, instead of the standard
+    /// output: "<sink>[:<path>][:<format>]". Sinks are "stdout", "file:<path>",
+    /// "socket:<path>" and "fifo:<path>"; formats are "json" (default), "text",
+    /// "verbose" and "csv", e.g. "stdout:text" or "file:events.json". Can be
+    /// used multiple times.
+    #[arg(long)]
+    pub(crate) output: Vec<String>,
+    /// Stamp all events, including the start and stop meta events, with a
+    /// capture label (e.g. a ticket id), to tell captures apart once merged.
+    /// Labels are reported, comma separated, in the "labels" field. Can be
+    /// used multiple times.
+    #[arg(long)]
+    pub(crate) label: Vec<String>,
+    /// Write events to a file instead of the standard output, one per line,
+    /// formatted as per --format. Files ending in .gz or .zst are compressed
+    /// accordingly.
+    #[arg(long)]
+    pub(crate) output_file: Option<String>,
+    /// Compression of the output file, overriding the one guessed from its
+    /// extension.
+    #[arg(long, value_parser = ["none", "gzip", "zstd"])]
+    pub(crate) compress: Option<String>,
+    /// Compression level, algorithm specific (gzip: 0-9, zstd: 1-22).
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..=22))]
+    pub(crate) compress_level: Option<u32>,
+    /// Write events continuously to a directory, as a ring of zstd compressed
+    /// files bounded by --ring-size, e.g. as a flight recorder persisted on
+    /// disk. The oldest files are deleted once over the size, and an "index"
+    /// file lists the remaining ones with the timestamps of their first and
+    /// last events. Events are formatted as per --format. An existing ring is
+    /// resumed.
+    #[arg(long)]
+    pub(crate) ring_dir: Option<String>,
+    /// Size budget of --ring-dir, in bytes or with a K, M or G suffix, e.g.
+    /// "2G".
+    #[arg(long, default_value = "1G", value_parser = parse_size)]
+    pub(crate) ring_size: Option<u64>,
+    /// Send events to a Unix datagram socket instead of the standard output,
+    /// one per datagram, formatted as per --format. Events are dropped (and
+    /// accounted for in the stop event) when the socket is absent or full.
+    #[arg(long)]
+    pub(crate) output_socket: Option<String>,
+    /// Write events to a named pipe (FIFO), one per line, formatted as per
+    /// --format. The pipe is created if it does not exist. Writing never
+    /// blocks: events are dropped (and accounted for in the stop event) while
+    /// no reader is attached or the pipe is full. Readers can attach and
+    /// detach at any time.
+    #[arg(long)]
+    pub(crate) output_fifo: Option<String>,
+    /// Send events to the local syslog, one message per event, formatted as
+    /// per --format. Events are dropped (and accounted for in the stop event)
+    /// while syslog is unavailable. Requires the "syslog" build feature.
+    #[arg(long)]
+    pub(crate) output_syslog: bool,
+    /// Syslog facility of the events sent to syslog: user, daemon or local0-7.
+    #[arg(long, default_value = "user")]
+    pub(crate) syslog_facility: Option<String>,
+    /// Syslog severity of the events sent to syslog.
+    #[arg(long, default_value = "info", value_parser = ["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"])]
+    pub(crate) syslog_severity: Option<String>,
+    /// Only write events once a condition on an event field is met, e.g.
+    /// "skb-tracking.drop_reason=NO_SOCKET". Events preceding the trigger are
+    /// kept and written too, see --trigger-pre and --trigger-post.
+    #[arg(long)]
+    pub(crate) trigger: Option<String>,
+    /// Number of events preceding a trigger to write.
+    #[arg(long, default_value = "100")]
+    pub(crate) trigger_pre: Option<usize>,
+    /// Time, in seconds, events are written after a trigger. The trigger is
+    /// re-armed afterwards.
+    #[arg(long, default_value = "5")]
+    pub(crate) trigger_post: Option<u64>,
+    /// Keep the last N events in memory, as a flight recorder, to be dumped
+    /// on request using the control socket ("recorder dump [<file>]"). Events
+    /// are not written anywhere else unless other outputs are requested.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) recorder: Option<u64>,
+    /// Path of a Unix socket accepting control commands (stats, config,
+    /// filter, recorder) while the collection is running.
+    #[arg(long)]
+    pub(crate) control_socket: Option<String>,
+    /// Maximum time, in seconds, a collector can take to initialize. When
+    /// exceeded the collector initialization is interrupted and the collector
+    /// considered as failed (see --require-all-collectors). A collector which
+    /// can't be interrupted fails the whole initialization 5s later.
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) collector_timeout: Option<u64>,
+    /// Fail if any of the requested collectors (or their dependencies) can't
+    /// be initialized, reporting all failures. By default such collectors
+    /// are unregistered and the collection goes on without them.
+    #[arg(long)]
+    pub(crate) require_all_collectors: bool,
+    /// Override the priority of collectors, as "<collector>=<priority>", e.g.
+    /// "ovs=-20". Collectors are initialized and started, and their probes
+    /// and hooks assembled, by increasing priority; dependencies still come
+    /// first. By default collectors filtering events (skb, -10) come before
+    /// the others (0), after the skb-tracking one whose hook always runs
+    /// first.
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+    pub(crate) collector_priority: Vec<String>,
+    /// Attach kprobes to all kernel functions matching a regex (e.g. "^tcp_"),
+    /// reporting their entry. The number of probes is limited, functions in
+    /// excess are not probed.
+    #[arg(long)]
+    pub(crate) kprobe_regex: Option<String>,
+    /// List the probes each collector would attach to, then exit without
+    /// attaching them.
+    #[arg(long)]
+    pub(crate) list_probes: bool,
+    /// Estimate the CPU overhead of the collection, per collector and in
+    /// total, then exit without collecting events. Probes are attached in a
+    /// lightweight counting mode to measure their rate (see
+    /// --estimate-window); as filters are not applied the estimate is an
+    /// upper bound.
+    #[arg(long)]
+    pub(crate) estimate_overhead: bool,
+    /// Sampling window, in seconds, used to measure the probes' rate when
+    /// estimating the overhead.
+    #[arg(long, default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) estimate_window: Option<u64>,
+    /// Attach probes in a lightweight counting mode first, to observe their
+    /// rate (see --adaptive-window), and only enable them if it is below a
+    /// threshold (see --adaptive-threshold). Otherwise probes stay in counting
+    /// mode and no event is reported, which is logged as a warning. Guards
+    /// against overwhelming a busy system. The outcome is reported in the stop
+    /// event (adaptive_mode and adaptive_rate).
+    #[arg(long, conflicts_with = "estimate_overhead")]
+    pub(crate) adaptive: bool,
+    /// Observation window, in seconds, of the adaptive mode.
+    #[arg(long, default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) adaptive_window: Option<u64>,
+    /// Maximum rate, in probe hits per second across all probes, under which
+    /// probes are enabled in adaptive mode.
+    #[arg(long, default_value = "10000")]
+    pub(crate) adaptive_threshold: Option<u64>,
+    /// Delay, in milliseconds, between two probe attachments. Staggering them
+    /// avoids a CPU spike when attaching many probes on latency sensitive
+    /// systems.
+    #[arg(long, default_value = "0")]
+    pub(crate) probe_delay: Option<u64>,
+    /// Do not probe a given symbol, even if collectors request it. Can be used
+    /// multiple times.
+    #[arg(long)]
+    pub(crate) disable_probe: Vec<String>,
     // Some of the options that we want for this arg are not available in clap's derive interface
     // so both the argument definition and the field population will be done manually.
     #[arg(skip)]
     pub(crate) collectors: Vec<String>,
+    /// Whether the collectors were not explicitly requested, all of them being
+    /// enabled by default.
+    #[arg(skip)]
+    pub(crate) default_collectors: bool,
 }
 
 #[derive(Debug)]
@@ -41,7 +513,10 @@ impl SubCommand for Collect {
                         .long("collectors")
                         .short('c')
                         .value_delimiter(',')
-                        .help("comma-separated list of collectors to enable"),
+                        .help(
+                            "comma-separated list of collectors to enable, globs (e.g. \"skb*\") \
+                             are supported",
+                        ),
                 ),
                 "collector",
             )?,
@@ -72,9 +547,11 @@ impl SubCommand for Collect {
             .to_string();
 
         // Determine all registerd collectors and specify both the possible values and the default
-        // value of the "collectors" argument
-        let possible_collectors =
+        // value of the "collectors" argument. Possible values are only used for documentation
+        // purposes, as globs are matched against them.
+        let mut possible_collectors =
             Vec::from_iter(self.collectors.modules().iter().map(|x| x.to_owned()));
+        possible_collectors.sort();
 
         let full_command = self
             .collectors
@@ -83,8 +560,12 @@ impl SubCommand for Collect {
             .about("Collect events")
             .long_about(long_about)
             .mut_arg("collectors", |a| {
-                a.value_parser(PossibleValuesParser::new(possible_collectors.clone()))
-                    .default_value(possible_collectors.join(","))
+                a.default_value(possible_collectors.join(","))
+                    .long_help(format!(
+                        "comma-separated list of collectors to enable, globs (e.g. \"skb*\") \
+                         are supported [possible values: {}]",
+                        possible_collectors.join(", ")
+                    ))
             });
 
         Ok(full_command)
@@ -99,12 +580,32 @@ impl SubCommand for Collect {
             .get_main::<CollectArgs>()
             .map_err(|_| ClapError::new(ErrorKind::InvalidValue))?;
 
-        // Manually set collectors argument.
-        self.args.collectors = args
-            .get_many("collectors")
+        // Manually set collectors argument, expanding globs.
+        let mut modules = Vec::from_iter(self.collectors.modules().iter());
+        modules.sort();
+
+        self.args.collectors.clear();
+        self.args.default_collectors =
+            args.value_source("collectors") == Some(ValueSource::DefaultValue);
+        for pattern in args
+            .get_many::<String>("collectors")
             .ok_or_else(|| ClapError::new(ErrorKind::MissingRequiredArgument))?
-            .map(|x: &String| x.to_owned())
-            .collect();
+        {
+            let matched: Vec<&&String> =
+                modules.iter().filter(|x| glob_match(pattern, x)).collect();
+            if matched.is_empty() {
+                return Err(ClapError::raw(
+                    ErrorKind::InvalidValue,
+                    format!("'{}' does not match any collector\n", pattern),
+                ));
+            }
+
+            for name in matched {
+                if !self.args.collectors.contains(name) {
+                    self.args.collectors.push(name.to_string());
+                }
+            }
+        }
         Ok(())
     }
 
@@ -118,4 +619,73 @@ impl Collect {
     pub(crate) fn args(&self) -> Result<&CollectArgs> {
         Ok(&self.args)
     }
+
+    /// Returns the main Collect arguments, for modification in tests.
+    #[cfg(test)]
+    pub(crate) fn args_mut(&mut self) -> Result<&mut CollectArgs> {
+        Ok(&mut self.args)
+    }
+}
+
+/// Parse a size, in bytes or with a binary K, M or G suffix, e.g. "2G".
+pub(crate) fn parse_size(size: &str) -> Result<u64> {
+    let (num, shift) = match size.char_indices().last() {
+        Some((i, 'K')) => (&size[..i], 10),
+        Some((i, 'M')) => (&size[..i], 20),
+        Some((i, 'G')) => (&size[..i], 30),
+        _ => (size, 0),
+    };
+    let num = num
+        .parse::<u64>()
+        .or_else(|_| bail!("Invalid size '{}'", size))?;
+    num.checked_mul(1 << shift)
+        .ok_or_else(|| anyhow!("Size '{}' is too large", size))
+}
+
+/// Match a name against a glob pattern, supporting `*` (any sequence of
+/// characters, including none) and `?` (any single character).
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match (pattern.first(), name.first()) {
+            (None, None) => true,
+            (Some('*'), _) => {
+                matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..]))
+            }
+            (Some('?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+            (Some(p), Some(n)) if p == n => matches(&pattern[1..], &name[1..]),
+            _ => false,
+        }
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&pattern, &name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("skb", "skb"));
+        assert!(!glob_match("skb", "skb-tracking"));
+        assert!(glob_match("skb*", "skb"));
+        assert!(glob_match("skb*", "skb-tracking"));
+        assert!(glob_match("*-tracking", "skb-tracking"));
+        assert!(glob_match("*", "ovs"));
+        assert!(glob_match("o?s", "ovs"));
+        assert!(!glob_match("o?s", "os"));
+        assert!(!glob_match("ovs*", "skb"));
+    }
+
+    #[test]
+    fn size() {
+        assert!(parse_size("4096").unwrap() == 4096);
+        assert!(parse_size("2K").unwrap() == 2048);
+        assert!(parse_size("2G").unwrap() == 2 << 30);
+        assert!(parse_size("2T").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("99999999999G").is_err());
+    }
 }
@@ -0,0 +1,56 @@
+//! # ListTracepoints
+//!
+//! ListTracepoints is a simple CLI subcommand listing the tracepoints available on the running
+//! system, to help choosing probe targets.
+
+use anyhow::Result;
+use std::any::Any;
+
+use clap::error::Error as ClapError;
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use super::super::SubCommand;
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct ListTracepoints {
+    /// Only list the tracepoints of a given subsystem (e.g. "skb").
+    pub(crate) subsystem: Option<String>,
+}
+
+impl SubCommand for ListTracepoints {
+    fn new() -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(ListTracepoints::default())
+    }
+
+    fn name(&self) -> &'static str {
+        "list-tracepoints"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn thin(&self) -> Result<Command> {
+        Ok(Command::new("list-tracepoints").about("List the available tracepoints"))
+    }
+
+    fn full(&self) -> Result<Command> {
+        let long_about = "List the tracepoints available on this system, as \
+            \"subsystem:name\", optionally restricted to a given subsystem. The \
+            list is read from tracefs, which must be mounted."
+            .to_string();
+
+        Ok(ListTracepoints::augment_args(
+            Command::new("list-tracepoints")
+                .about("List the available tracepoints")
+                .long_about(long_about),
+        ))
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), ClapError> {
+        <Self as FromArgMatches>::update_from_arg_matches(self, matches)
+    }
+}
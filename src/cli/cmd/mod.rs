@@ -2,7 +2,13 @@
 //!
 //! Cmd module, that defines the different SubCommands that the Cli supports.
 
+pub(crate) mod capabilities;
 pub(crate) mod collect;
+pub(crate) mod list_tracepoints;
+pub(crate) mod self_test;
 
-// Re-export collect.rs
+// Re-export capabilities.rs, collect.rs, list_tracepoints.rs and self_test.rs
+pub(crate) use capabilities::*;
 pub(crate) use collect::*;
+pub(crate) use list_tracepoints::*;
+pub(crate) use self_test::*;
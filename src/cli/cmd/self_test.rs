@@ -0,0 +1,59 @@
+//! # SelfTest
+//!
+//! SelfTest is a simple CLI subcommand validating the tool works on the running
+//! system, by tracing packets it generates itself.
+
+use anyhow::Result;
+use std::any::Any;
+
+use clap::error::Error as ClapError;
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use super::super::SubCommand;
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct SelfTest {
+    /// Time to wait for the test packet to be reported, in seconds.
+    #[arg(long, default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) timeout: u64,
+}
+
+impl SubCommand for SelfTest {
+    fn new() -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(SelfTest::default())
+    }
+
+    fn name(&self) -> &'static str {
+        "self-test"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn thin(&self) -> Result<Command> {
+        Ok(Command::new("self-test").about("Verify packets can be traced on this system"))
+    }
+
+    fn full(&self) -> Result<Command> {
+        let long_about = "Verify packets can be traced on this system: a collection is \
+            started using the skb collector, UDP packets are sent over the loopback \
+            interface and the corresponding events are checked to be reported with \
+            the expected fields. No external tool is required. Exits with an error if \
+            the test fails."
+            .to_string();
+
+        Ok(SelfTest::augment_args(
+            Command::new("self-test")
+                .about("Verify packets can be traced on this system")
+                .long_about(long_about),
+        ))
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), ClapError> {
+        <Self as FromArgMatches>::update_from_arg_matches(self, matches)
+    }
+}
@@ -11,12 +11,14 @@
 //! In order to achieve this, the command line arguments have to be parsed twice. One first round,
 //! called "thin", just validates the subcommand that was run. After that, modules can know which
 //! subcommand was run and register arguments to it before the final argument parsing and
-//! validation, called "full" is performed.
+//! validation, called "full" is performed. Profiles (see profile) are expanded
+//! in the arguments beforehand.
 
 #[allow(clippy::module_inception)]
 pub(crate) mod cli;
 pub(crate) mod cmd;
 pub(crate) mod dynamic;
+pub(crate) mod profile;
 
 // Re-export cli.rs
 pub(crate) use cli::*;
@@ -0,0 +1,198 @@
+//! # Profiles
+//!
+//! Profiles are named sets of collect arguments for common tasks, selected
+//! with `--profile <name>`. They are expanded in the command line arguments
+//! before those are parsed (and thus before collectors are selected), only
+//! adding the arguments not explicitly given: explicit ones take precedence.
+//!
+//! Built-in profiles can be extended or overridden by user-defined ones, read
+//! from a JSON file (--profile-file, PROFILES_FILE by default) mapping profile
+//! names to their arguments, e.g.
+//! `{"drops": ["--collectors", "skb,skb-tracking", "--where", "category == drop"]}`.
+
+use std::{collections::HashMap, ffi::OsString, fs, path::Path};
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+/// Default user-defined profiles file, read if it exists.
+pub(crate) const PROFILES_FILE: &str = "/etc/packet-tracer/profiles.json";
+
+/// Built-in profiles.
+const BUILTIN_PROFILES: &[(&str, &[&str])] = &[
+    // Packets being dropped, and where.
+    (
+        "drops",
+        &[
+            "--collectors",
+            "skb,skb-tracking",
+            "--where",
+            "category == drop",
+        ],
+    ),
+    // Packets going through OpenvSwitch.
+    ("ovs", &["--collectors", "ovs,skb,skb-tracking"]),
+];
+
+/// Expand the profile selected in the command line arguments, if any.
+pub(crate) fn expand_profiles(args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let name = match arg_value(&args, "--profile") {
+        Some(name) => name,
+        None => return Ok(args),
+    };
+
+    let mut profiles = match arg_value(&args, "--profile-file") {
+        Some(path) => read_profiles(&path)?,
+        None if Path::new(PROFILES_FILE).exists() => read_profiles(PROFILES_FILE)?,
+        None => HashMap::new(),
+    };
+
+    let profile = match profiles.remove(&name) {
+        Some(profile) => profile,
+        None => match BUILTIN_PROFILES.iter().find(|(n, _)| *n == name) {
+            Some((_, profile)) => profile.iter().map(|x| x.to_string()).collect(),
+            None => bail!("Unknown profile '{}'", name),
+        },
+    };
+
+    Ok(apply_profile(args, &profile))
+}
+
+/// Value of a "--name value" or "--name=value" argument, if any.
+fn arg_value(args: &[OsString], name: &str) -> Option<String> {
+    let mut iter = args.iter().map(|x| x.to_string_lossy());
+    while let Some(arg) = iter.next() {
+        if arg == name {
+            return iter.next().map(|x| x.to_string());
+        }
+        if let Some(value) = arg.strip_prefix(name).and_then(|x| x.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// Append the arguments of a profile not already given. Profile arguments are
+/// grouped by option, an option starting with "--" and followed by its
+/// values.
+fn apply_profile(mut args: Vec<OsString>, profile: &[String]) -> Vec<OsString> {
+    let given = |option: &str| {
+        args.iter().any(|x| {
+            let x = x.to_string_lossy();
+            x == option || x.starts_with(&format!("{}=", option))
+        })
+    };
+
+    let mut extra = Vec::new();
+    let mut skip = false;
+    for arg in profile.iter() {
+        if arg.starts_with("--") {
+            let option = arg.split('=').next().unwrap_or(arg);
+            // Profiles can't select other profiles.
+            skip = option == "--profile" || option == "--profile-file" || given(option);
+        }
+        if !skip {
+            extra.push(OsString::from(arg));
+        }
+    }
+
+    args.append(&mut extra);
+    args
+}
+
+fn read_profiles(path: &str) -> Result<HashMap<String, Vec<String>>> {
+    let content =
+        fs::read_to_string(path).or_else(|e| bail!("Could not read profiles {}: {}", path, e))?;
+    parse_profiles(&content).or_else(|e| bail!("Invalid profiles {}: {}", path, e))
+}
+
+/// Parse user-defined profiles, see the module documentation for the format.
+fn parse_profiles(content: &str) -> Result<HashMap<String, Vec<String>>> {
+    let profiles = match serde_json::from_str::<Value>(content)? {
+        Value::Object(profiles) => profiles,
+        _ => bail!("expected an object of profiles"),
+    };
+
+    let mut parsed = HashMap::new();
+    for (name, args) in profiles.into_iter() {
+        let args = match args {
+            Value::Array(args) => args,
+            _ => bail!("profile '{}' is not a list of arguments", name),
+        };
+        let args = args
+            .into_iter()
+            .map(|x| match x {
+                Value::String(x) => Ok(x),
+                _ => bail!("profile '{}' has a non-string argument", name),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        parsed.insert(name, args);
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn profiles() -> Result<()> {
+        // No profile.
+        let cmd = args(&["packet-tracer", "collect", "--collectors", "skb"]);
+        assert!(expand_profiles(cmd.clone())? == cmd);
+
+        // Built-in profile.
+        assert!(
+            expand_profiles(args(&["packet-tracer", "collect", "--profile", "drops"]))?
+                == args(&[
+                    "packet-tracer",
+                    "collect",
+                    "--profile",
+                    "drops",
+                    "--collectors",
+                    "skb,skb-tracking",
+                    "--where",
+                    "category == drop",
+                ])
+        );
+
+        // Explicit arguments take precedence.
+        assert!(
+            apply_profile(
+                args(&["collect", "--profile=x", "--where=ifindex == 2"]),
+                &["--where".to_string(), "category == drop".to_string()],
+            ) == args(&["collect", "--profile=x", "--where=ifindex == 2"])
+        );
+        assert!(
+            apply_profile(
+                args(&["collect"]),
+                &[
+                    "--skb-debug".to_string(),
+                    "--profile".to_string(),
+                    "drops".to_string(),
+                    "--ethertype=ipv4".to_string(),
+                ],
+            ) == args(&["collect", "--skb-debug", "--ethertype=ipv4"])
+        );
+
+        assert!(expand_profiles(args(&["collect", "--profile", "foo"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn profiles_file() -> Result<()> {
+        let profiles = parse_profiles(r#"{"tcp": ["--collectors", "skb", "--proto", "tcp"]}"#)?;
+        assert!(profiles.get("tcp").map(|x| x.len()) == Some(4));
+
+        assert!(parse_profiles("[]").is_err());
+        assert!(parse_profiles(r#"{"tcp": "--proto tcp"}"#).is_err());
+        assert!(parse_profiles(r#"{"tcp": ["--proto", 6]}"#).is_err());
+        assert!(parse_profiles("{").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+#include <vmlinux.h>
+#include <bpf/bpf_core_read.h>
+
+#include <common.h>
+
+/* Please keep in sync with its Rust counterpart in collector::bridge. */
+#define BRIDGE_VERDICT_FORWARD	1
+#define BRIDGE_VERDICT_FLOOD	2
+
+/* Bridge configuration, indexed in the bridge_config_map by the function ksym
+ * address.
+ *
+ * Please keep in sync with its Rust counterpart in collector::bridge.
+ */
+struct bridge_config {
+	/* Forwarding decision the probed function represents */
+	u8 verdict;
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, struct bridge_config);
+} bridge_config_map SEC(".maps");
+
+/* Please keep in sync with its Rust counterpart in collector::bridge. */
+struct bridge_event {
+	u32 bridge_ifindex;
+	u32 port_ifindex;
+	u16 port_no;
+	u8 verdict;
+	u8 pkt_type;
+} __attribute__((packed));
+
+DEFINE_HOOK(
+	struct bridge_config *cfg;
+	struct net_bridge_port *port;
+	struct bridge_event *e;
+	struct net_bridge *br;
+	u64 ksym = ctx->ksym;
+
+	cfg = bpf_map_lookup_elem(&bridge_config_map, &ksym);
+	if (!cfg)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_BRIDGE, 1, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->verdict = cfg->verdict;
+
+	switch (cfg->verdict) {
+	case BRIDGE_VERDICT_FORWARD:
+		/* void br_forward(const struct net_bridge_port *to,
+		 *                 struct sk_buff *skb, bool local_rcv,
+		 *                 bool local_orig)
+		 */
+		port = trace_get_param(ctx, 0, struct net_bridge_port *);
+		if (!port)
+			break;
+
+		e->bridge_ifindex = BPF_CORE_READ(port, br, dev, ifindex);
+		e->port_ifindex = BPF_CORE_READ(port, dev, ifindex);
+		e->port_no = BPF_CORE_READ(port, port_no);
+		break;
+	case BRIDGE_VERDICT_FLOOD:
+		/* void br_flood(struct net_bridge *br, struct sk_buff *skb,
+		 *               enum br_pkt_type pkt_type, ...)
+		 */
+		br = trace_get_param(ctx, 0, struct net_bridge *);
+		if (!br)
+			break;
+
+		e->bridge_ifindex = BPF_CORE_READ(br, dev, ifindex);
+		e->pkt_type = trace_get_param(ctx, 2, u8);
+		break;
+	}
+
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
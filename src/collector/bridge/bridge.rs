@@ -0,0 +1,225 @@
+use std::mem;
+
+use anyhow::{bail, Result};
+use plain::Plain;
+
+use super::bridge_hook;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            EventField,
+        },
+        kernel_config::Requirements,
+        probe::kernel::{self, Hook, ProbeType},
+    },
+};
+
+const BRIDGE_COLLECTOR: &str = "bridge";
+
+// Forwarding decisions. Please keep in sync with its BPF counterpart in
+// bpf/bridge_hook.bpf.c
+const BRIDGE_VERDICT_FORWARD: u8 = 1;
+const BRIDGE_VERDICT_FLOOD: u8 = 2;
+
+// Kernel enum br_pkt_type, unicast value.
+const BR_PKT_UNICAST: u8 = 0;
+
+// Probed functions and the forwarding decision they represent.
+const BRIDGE_TARGETS: &[(&str, u8)] = &[
+    ("br_forward", BRIDGE_VERDICT_FORWARD),
+    ("br_flood", BRIDGE_VERDICT_FLOOD),
+];
+
+// Bridge event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct BridgeEvent {
+    bridge_ifindex: u32,
+    port_ifindex: u32,
+    port_no: u16,
+    verdict: u8,
+    pkt_type: u8,
+}
+unsafe impl Plain for BridgeEvent {}
+
+// Please keep in sync with its BPF counterpart in bpf/bridge_hook.bpf.c
+#[repr(C)]
+struct BridgeConfig {
+    verdict: u8,
+}
+unsafe impl Plain for BridgeConfig {}
+
+pub(in crate::collector) struct BridgeCollector {}
+
+impl Collector for BridgeCollector {
+    fn new() -> Result<BridgeCollector> {
+        Ok(BridgeCollector {})
+    }
+
+    fn name(&self) -> &'static str {
+        BRIDGE_COLLECTOR
+    }
+
+    fn requirements(&self) -> Requirements {
+        Requirements {
+            config: &["CONFIG_BRIDGE"],
+            ..Default::default()
+        }
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module_noargs(BRIDGE_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        _: &CliConfig,
+        kernel: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        self.check(kernel)?;
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorBridge,
+            Box::new(|raw_section, fields| {
+                if raw_section.header.data_type != 1 {
+                    bail!("Unknown data type");
+                }
+
+                unmarshal_bridge(raw_section, fields)
+            }),
+        )?;
+
+        // The bridge internals aren't part of the kernel ABI. If they don't
+        // match our expectations, still report the events without the bridge
+        // section rather than failing the probes.
+        let mut config_map = Self::bridge_config_map()?;
+        let hook = Hook::from(bridge_hook::DATA)
+            .reuse_map("bridge_config_map", config_map.fd())?
+            .optional_relo("net_bridge_port")
+            .optional_relo("net_bridge")
+            .to_owned();
+
+        for (target, verdict) in BRIDGE_TARGETS.iter() {
+            let key = kernel
+                .inspect
+                .get_ksym(&ProbeType::Kprobe, target)?
+                .to_ne_bytes();
+            let cfg = BridgeConfig { verdict: *verdict };
+            let cfg = unsafe { plain::as_bytes(&cfg) };
+            config_map.update(&key, cfg, libbpf_rs::MapFlags::NO_EXIST)?;
+
+            kernel.register_hook_to(hook.clone(), ProbeType::Kprobe, target)?;
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn check(&self, kernel: &kernel::Kernel) -> Result<()> {
+        // The bridge module might not be loaded, in which case there is
+        // nothing to do for this collector.
+        if kernel
+            .inspect
+            .get_ksym(&ProbeType::Kprobe, "br_forward")
+            .is_err()
+        {
+            bail!("The bridge module is not loaded");
+        }
+        Ok(())
+    }
+}
+
+impl BridgeCollector {
+    fn bridge_config_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in
+        // bpf/bridge_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("bridge_config_map"),
+            mem::size_of::<u64>() as u32,
+            mem::size_of::<BridgeConfig>() as u32,
+            kernel::PROBE_MAX as u32,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the bridge config map: {}", e))
+    }
+}
+
+fn unmarshal_bridge(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<BridgeEvent>(raw_section)?;
+
+    fields.push(event_field!("bridge", event.bridge_ifindex));
+    match event.verdict {
+        BRIDGE_VERDICT_FORWARD => {
+            fields.push(event_field!("verdict", "forward".to_string()));
+            fields.push(event_field!("port", event.port_no as u32));
+            fields.push(event_field!("port_ifindex", event.port_ifindex));
+            fields.push(event_field!("fdb", "hit".to_string()));
+        }
+        BRIDGE_VERDICT_FLOOD => {
+            fields.push(event_field!("verdict", "flood".to_string()));
+            // Only unicast frames are flooded because of an FDB miss, others
+            // are always flooded.
+            if event.pkt_type == BR_PKT_UNICAST {
+                fields.push(event_field!("fdb", "miss".to_string()));
+            }
+        }
+        x => bail!("Unknown bridge verdict {}", x),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::{bpf::unmarshal_section, Event};
+
+    fn unmarshal(event: &BridgeEvent) -> Result<Event> {
+        unmarshal_section(BRIDGE_COLLECTOR, 1, event, unmarshal_bridge)
+    }
+
+    #[test]
+    fn unmarshal_events() -> Result<()> {
+        let event = unmarshal(&BridgeEvent {
+            bridge_ifindex: 3,
+            port_ifindex: 4,
+            port_no: 1,
+            verdict: BRIDGE_VERDICT_FORWARD,
+            ..Default::default()
+        })?;
+        let get = |key| event.get::<String>(BRIDGE_COLLECTOR, key);
+        assert!(get("verdict")? == Some(&"forward".to_string()));
+        assert!(event.get::<u32>(BRIDGE_COLLECTOR, "port_ifindex")? == Some(&4));
+
+        let event = unmarshal(&BridgeEvent {
+            bridge_ifindex: 3,
+            verdict: BRIDGE_VERDICT_FLOOD,
+            pkt_type: BR_PKT_UNICAST,
+            ..Default::default()
+        })?;
+        let get = |key| event.get::<String>(BRIDGE_COLLECTOR, key);
+        assert!(get("verdict")? == Some(&"flood".to_string()));
+        assert!(get("fdb")? == Some(&"miss".to_string()));
+
+        assert!(unmarshal(&BridgeEvent {
+            verdict: 42,
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,15 @@
+//! # BridgeCollector
+//!
+//! Probe the Linux bridge forwarding path to report the forwarding decisions
+//! taken for frames: forwarded to a specific port (the FDB lookup found the
+//! destination) or flooded to all ports. Frames being dropped are reported by
+//! the skb-tracking collector, using drop reasons.
+
+// Re-export bridge.rs
+#[allow(clippy::module_inception)]
+pub(super) mod bridge;
+pub(super) use bridge::*;
+
+mod bridge_hook {
+    include!("bpf/.out/bridge_hook.rs");
+}
@@ -0,0 +1,87 @@
+#include <vmlinux.h>
+#include <bpf/bpf_helpers.h>
+#include <bpf/bpf_endian.h>
+
+#include <events.h>
+
+/* Cgroup section data types. Please keep in sync with its Rust counterpart in
+ * collector::cgroup.
+ */
+#define SECTION_SKB	1
+#define SECTION_CONNECT	2
+
+/* Please keep in sync with its Rust counterpart in collector::cgroup. */
+struct cgroup_skb_event {
+	u64 timestamp;
+	/* Cgroup (v2) of the socket the packet belongs to */
+	u64 cgroup_id;
+	u32 len;
+	u32 ifindex;
+	u16 protocol;
+} __attribute__((packed));
+
+/* Please keep in sync with its Rust counterpart in collector::cgroup. */
+struct cgroup_connect_event {
+	u64 timestamp;
+	/* Cgroup (v2) of the connecting task */
+	u64 cgroup_id;
+	/* Network order */
+	u32 daddr;
+	u16 dport;
+} __attribute__((packed));
+
+SEC("cgroup_skb/egress")
+int cgroup_skb_egress(struct __sk_buff *skb)
+{
+	struct trace_raw_event *event;
+	struct cgroup_skb_event *e;
+
+	event = get_event();
+	if (!event)
+		return 1;
+
+	e = get_event_section(event, COLLECTOR_CGROUP, SECTION_SKB, sizeof(*e));
+	if (!e) {
+		discard_event(event);
+		return 1;
+	}
+
+	e->timestamp = bpf_ktime_get_ns();
+	e->cgroup_id = bpf_skb_cgroup_id(skb);
+	e->len = skb->len;
+	e->ifindex = skb->ifindex;
+	e->protocol = bpf_ntohs(skb->protocol);
+
+	send_event(event);
+	/* Always let the packet through. */
+	return 1;
+}
+
+SEC("cgroup/connect4")
+int cgroup_connect4(struct bpf_sock_addr *ctx)
+{
+	struct cgroup_connect_event *e;
+	struct trace_raw_event *event;
+
+	event = get_event();
+	if (!event)
+		return 1;
+
+	e = get_event_section(event, COLLECTOR_CGROUP, SECTION_CONNECT,
+			      sizeof(*e));
+	if (!e) {
+		discard_event(event);
+		return 1;
+	}
+
+	e->timestamp = bpf_ktime_get_ns();
+	e->cgroup_id = bpf_get_current_cgroup_id();
+	e->daddr = ctx->user_ip4;
+	e->dport = bpf_ntohs(ctx->user_port);
+
+	send_event(event);
+	/* Always let the connection through. */
+	return 1;
+}
+
+char __license[] SEC("license") = "GPL";
@@ -0,0 +1,336 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    net::Ipv4Addr,
+    os::unix::{fs::MetadataExt, io::AsRawFd},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use log::debug;
+use plain::Plain;
+
+use super::cgroup_bpf::CgroupSkelBuilder;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            EventField,
+        },
+        kernel_config::Requirements,
+        probe::{get_ebpf_debug, kernel},
+    },
+};
+
+const CGROUP_COLLECTOR: &str = "cgroup";
+
+// Cgroup section data types. Please keep in sync with its BPF counterpart in
+// bpf/cgroup.bpf.c
+const SECTION_SKB: u8 = 1;
+const SECTION_CONNECT: u8 = 2;
+
+// Programs attached to the cgroup.
+const CGROUP_PROGS: &[&str] = &["cgroup_skb_egress", "cgroup_connect4"];
+
+// Root of the (v2) cgroup hierarchy, used to resolve cgroup ids to paths.
+const CGROUPFS: &str = "/sys/fs/cgroup";
+// Minimum time between two scans of the cgroup hierarchy triggered by unknown
+// cgroup ids, so events of short-lived cgroups do not keep us scanning.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct CgroupCollectorArgs {
+    /// Path of the (v2) cgroup to attach to, e.g. /sys/fs/cgroup/system.slice.
+    /// Required by the cgroup collector.
+    #[arg(id = "cgroup", long)]
+    cgroup: Option<String>,
+    /// Resolve the cgroup ids reported in events to their path in the cgroup
+    /// hierarchy, reported as cgroup_path.
+    #[arg(id = "cgroup-resolve-path", long)]
+    cgroup_resolve_path: bool,
+}
+
+// Skb event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct CgroupSkbEvent {
+    timestamp: u64,
+    cgroup_id: u64,
+    len: u32,
+    ifindex: u32,
+    protocol: u16,
+}
+unsafe impl Plain for CgroupSkbEvent {}
+
+// Connect event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct CgroupConnectEvent {
+    timestamp: u64,
+    cgroup_id: u64,
+    daddr: u32,
+    dport: u16,
+}
+unsafe impl Plain for CgroupConnectEvent {}
+
+/// Resolves cgroup ids to cgroup paths. The (v2) cgroup id is the inode
+/// number of the cgroup directory, so the mapping is built by scanning the
+/// cgroup hierarchy. The cache is rebuilt when an unknown id is looked up, as
+/// cgroups come and go; this also invalidates the entries of removed cgroups.
+struct CgroupPaths {
+    root: PathBuf,
+    paths: HashMap<u64, String>,
+    last_scan: Option<Instant>,
+}
+
+impl CgroupPaths {
+    fn new<P: AsRef<Path>>(root: P) -> CgroupPaths {
+        CgroupPaths {
+            root: root.as_ref().to_path_buf(),
+            paths: HashMap::new(),
+            last_scan: None,
+        }
+    }
+
+    /// Rebuild the id to path mapping from the cgroup hierarchy.
+    fn scan(&mut self) {
+        let mut paths = HashMap::new();
+        let mut dirs = vec![self.root.clone()];
+
+        while let Some(dir) = dirs.pop() {
+            let ino = match fs::metadata(&dir) {
+                Ok(metadata) => metadata.ino(),
+                // Cgroups can be removed while we scan the hierarchy.
+                Err(_) => continue,
+            };
+            let path = match dir.strip_prefix(&self.root) {
+                Ok(rel) => format!("/{}", rel.display()),
+                Err(_) => continue,
+            };
+            paths.insert(ino, path);
+
+            if let Ok(entries) = fs::read_dir(&dir) {
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+                    .for_each(|e| dirs.push(e.path()));
+            }
+        }
+
+        debug!("Found {} cgroups in {}", paths.len(), self.root.display());
+        self.paths = paths;
+        self.last_scan = Some(Instant::now());
+    }
+
+    /// Resolve a cgroup id to its path, relative to the cgroup hierarchy root.
+    fn resolve(&mut self, id: u64) -> Option<String> {
+        if !self.paths.contains_key(&id)
+            && self
+                .last_scan
+                .map_or(true, |t| t.elapsed() >= RESCAN_INTERVAL)
+        {
+            self.scan();
+        }
+        self.paths.get(&id).cloned()
+    }
+}
+
+#[derive(Default)]
+pub(in crate::collector) struct CgroupCollector {
+    /// Cgroup being targeted, kept open for attaching the programs.
+    cgroup: Option<File>,
+    obj: Option<libbpf_rs::Object>,
+    links: Vec<libbpf_rs::Link>,
+}
+
+impl Collector for CgroupCollector {
+    fn new() -> Result<CgroupCollector> {
+        Ok(CgroupCollector::default())
+    }
+
+    fn name(&self) -> &'static str {
+        CGROUP_COLLECTOR
+    }
+
+    fn requirements(&self) -> Requirements {
+        // Connect hooks were introduced in Linux v4.17, the cgroup id helpers
+        // in v4.18.
+        Requirements {
+            kernel: Some((4, 18)),
+            config: &["CONFIG_CGROUP_BPF"],
+        }
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module::<CgroupCollectorArgs>(CGROUP_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        cli: &CliConfig,
+        _: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        deadline: &InitDeadline,
+    ) -> Result<()> {
+        let args = cli.get_section::<CgroupCollectorArgs>(CGROUP_COLLECTOR)?;
+        let path = args
+            .cgroup
+            .ok_or_else(|| anyhow!("No cgroup given (see --cgroup)"))?;
+
+        if !Path::new(&path).is_dir() {
+            bail!("Invalid cgroup {}: not a directory", path);
+        }
+        self.cgroup = Some(File::open(&path)?);
+
+        let paths = match args.cgroup_resolve_path {
+            true => {
+                // Large hierarchies can take a while to scan.
+                let paths = deadline.run(|| {
+                    let mut paths = CgroupPaths::new(CGROUPFS);
+                    paths.scan();
+                    Ok(paths)
+                })?;
+                Some(Arc::new(Mutex::new(paths)))
+            }
+            false => None,
+        };
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorCgroup,
+            Box::new(move |raw_section, fields| {
+                let cgroup_id = match raw_section.header.data_type {
+                    SECTION_SKB => unmarshal_skb(raw_section, fields)?,
+                    SECTION_CONNECT => unmarshal_connect(raw_section, fields)?,
+                    _ => bail!("Unknown data type"),
+                };
+
+                if let Some(paths) = &paths {
+                    let path = paths
+                        .lock()
+                        .map_err(|e| anyhow!("Could not lock the cgroup paths: {}", e))?
+                        .resolve(cgroup_id);
+                    if let Some(path) = path {
+                        fields.push(event_field!("cgroup_path", path));
+                    }
+                }
+                Ok(())
+            }),
+        )?;
+
+        let mut skel = CgroupSkelBuilder::default();
+        skel.obj_builder.debug(get_ebpf_debug());
+        let mut skel = skel.open()?;
+        skel.maps_mut().events_map().reuse_fd(events.map_fd())?;
+        skel.maps_mut()
+            .events_stats_map()
+            .reuse_fd(events.stats_map_fd())?;
+
+        self.obj = Some(skel.obj.load()?);
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let (obj, cgroup) = match (&mut self.obj, &self.cgroup) {
+            (Some(obj), Some(cgroup)) => (obj, cgroup),
+            _ => bail!("Cgroup collector is uninitialized"),
+        };
+
+        for name in CGROUP_PROGS.iter() {
+            self.links.push(
+                obj.prog_mut(name)
+                    .ok_or_else(|| anyhow!("Couldn't get program {}", name))?
+                    .attach_cgroup(cgroup.as_raw_fd())?,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Unmarshal an skb section, returning its cgroup id.
+fn unmarshal_skb(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<u64> {
+    let event = parse_raw_section::<CgroupSkbEvent>(raw_section)?;
+
+    fields.push(event_field!("timestamp", event.timestamp));
+    fields.push(event_field!("cgroup_id", event.cgroup_id));
+    fields.push(event_field!("type", "egress".to_string()));
+    fields.push(event_field!("len", event.len));
+    fields.push(event_field!("ifindex", event.ifindex));
+    fields.push(event_field!("protocol", event.protocol as u32));
+    Ok(event.cgroup_id)
+}
+
+/// Unmarshal a connect section, returning its cgroup id.
+fn unmarshal_connect(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<u64> {
+    let event = parse_raw_section::<CgroupConnectEvent>(raw_section)?;
+
+    // The destination address is stored in network order.
+    let daddr = Ipv4Addr::from(event.daddr.to_ne_bytes());
+
+    fields.push(event_field!("timestamp", event.timestamp));
+    fields.push(event_field!("cgroup_id", event.cgroup_id));
+    fields.push(event_field!("type", "connect".to_string()));
+    fields.push(event_field!("daddr", daddr.to_string()));
+    fields.push(event_field!("dport", event.dport as u32));
+    Ok(event.cgroup_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::{bpf::raw_section, registry::FieldRegistry};
+
+    #[test]
+    fn unmarshal_sections() -> Result<()> {
+        let mut fields = Vec::new();
+        let skb = CgroupSkbEvent {
+            cgroup_id: 42,
+            len: 100,
+            ifindex: 2,
+            protocol: 0x0800,
+            ..Default::default()
+        };
+        assert!(unmarshal_skb(&raw_section(SECTION_SKB, &skb), &mut fields)? == 42);
+
+        let connect = CgroupConnectEvent {
+            cgroup_id: 43,
+            daddr: u32::from_ne_bytes([10, 0, 0, 1]),
+            dport: 443,
+            ..Default::default()
+        };
+        assert!(unmarshal_connect(&raw_section(SECTION_CONNECT, &connect), &mut fields)? == 43);
+        fields.push(event_field!("cgroup_path", "/system.slice".to_string()));
+
+        FieldRegistry::get().check(fields.iter().map(|f| (CGROUP_COLLECTOR, f)))?;
+        Ok(())
+    }
+
+    #[test]
+    fn cgroup_paths() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("pt-cgroup-{}", std::process::id()));
+        let ino = |path: &Path| -> Result<u64> { Ok(fs::metadata(path)?.ino()) };
+
+        fs::create_dir_all(root.join("system.slice/foo.service"))?;
+        let mut paths = CgroupPaths::new(&root);
+        assert!(paths.resolve(ino(&root)?) == Some("/".to_string()));
+        assert!(
+            paths.resolve(ino(&root.join("system.slice/foo.service"))?)
+                == Some("/system.slice/foo.service".to_string())
+        );
+
+        // Unknown ids trigger a rescan, at most once per interval.
+        fs::create_dir(root.join("bar"))?;
+        let bar = ino(&root.join("bar"))?;
+        assert!(paths.resolve(bar).is_none());
+        paths.last_scan = None;
+        assert!(paths.resolve(bar) == Some("/bar".to_string()));
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
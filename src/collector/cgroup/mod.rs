@@ -0,0 +1,23 @@
+//! # CgroupCollector
+//!
+//! Attach BPF programs to cgroup hook points to observe, for a given cgroup
+//! (e.g. a container or a pod), its egress packets and its connection
+//! attempts. Unlike other collectors, this one does not rely on kernel probes
+//! and hooks: its programs are attached directly to the cgroup and report
+//! their own events. Those programs never alter the verdict and always let
+//! packets and connections through.
+//!
+//! Events report the id of the cgroup the packet or connection originates
+//! from, which can be a descendant of the targeted cgroup. With
+//! --cgroup-resolve-path the cgroup path (relative to /sys/fs/cgroup) is
+//! reported as well; ids are resolved by scanning the cgroup hierarchy, which
+//! is scanned again when an unknown id shows up.
+
+// Re-export cgroup.rs
+#[allow(clippy::module_inception)]
+pub(super) mod cgroup;
+pub(super) use cgroup::*;
+
+mod cgroup_bpf {
+    include!("bpf/.out/cgroup.skel.rs");
+}
@@ -1,16 +1,54 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, bail, Result};
-use log::{error, warn};
+use log::{debug, error, info, warn};
+use regex::Regex;
 
+use super::bridge::BridgeCollector;
+use super::cgroup::CgroupCollector;
+use super::dropmon::DropmonCollector;
+use super::kprobe::KprobeCollector;
+use super::netfilter::NetfilterCollector;
+use super::overhead;
 use super::ovs::OvsCollector;
 use super::skb::SkbCollector;
 use super::skb_tracking::SkbTrackingCollector;
+use super::sockdrop::SockdropCollector;
+use super::sockfilter::SockfilterCollector;
+use super::tc_bpf::TcBpfCollector;
+use super::tcp_retrans::TcpRetransCollector;
+use super::wireguard::WireguardCollector;
+use super::xdp::XdpCollector;
+use super::xfrm::XfrmCollector;
 use crate::cli::{cmd::collect::Collect, dynamic::DynamicCommand, CliConfig};
 use crate::core::{
-    events::{bpf::BpfEvents, Event},
-    probe,
+    bpf_trace::start_bpf_trace,
+    control::ControlState,
+    events::{bpf::BpfEvents, meta, Event, EventField},
+    filters::{
+        flow::Flow,
+        iface::{set_iface_filter, IfaceFilter},
+        match_set::{set_match_set, MatchSet},
+        pid::PidFilter,
+        pin::set_pin_maps,
+    },
+    kernel_config::Requirements,
+    kernel_symbols,
+    probe::{
+        self,
+        kernel::{softirq::SoftirqTracker, ProbeType, PROBE_MAX},
+    },
 };
+use crate::event_field;
 
 /// Generic trait representing a collector. All collectors are required to
 /// implement this, as they'll be manipulated through this trait.
@@ -25,20 +63,58 @@ pub(super) trait Collector {
     /// Return the name of the collector. It *has* to be unique among all the
     /// collectors.
     fn name(&self) -> &'static str;
+    /// Return the names of the collectors this one depends on. Dependencies
+    /// are automatically enabled and are initialized before the collector.
+    fn depends_on(&self) -> &[&'static str] {
+        &[]
+    }
+    /// Return the priority of the collector, defining the order in which
+    /// collectors are initialized and started, and thus the order in which
+    /// their probes and hooks are assembled: lower priorities come first.
+    /// Dependencies are still initialized before the collectors depending on
+    /// them and collectors of the same priority keep the order they were
+    /// requested in. Defaults to 0; collectors filtering events use a negative
+    /// priority, for their hooks to run before the others. Bookkeeping
+    /// collectors (skb-tracking) use the lowest one, their hooks must see all
+    /// events.
+    fn priority(&self) -> i32 {
+        0
+    }
+    /// Return the requirements on the running kernel (version, configuration)
+    /// of the collector. Collectors not meeting them are not initialized.
+    fn requirements(&self) -> Requirements {
+        Requirements::default()
+    }
     /// Initialize the collector, likely to be used to pass configuration data
     /// such as filters or command line arguments. We need to split the new &
     /// the init phase for collectors, to allow giving information to the core
     /// as part of the collector registration and only then feed the collector
     /// with data coming from the core. Checks for the mandatory part of the
-    /// collector should be done here.
+    /// collector should be done here. Potentially long operations must be
+    /// bounded using `deadline`.
     fn init(
         &mut self,
         cli: &CliConfig,
         kernel: &mut probe::Kernel,
         events: &mut BpfEvents,
+        deadline: &InitDeadline,
     ) -> Result<()>;
     /// Start the group of events (non-probes).
     fn start(&mut self) -> Result<()>;
+    /// Stop the group of events, undoing what `start()` set up which would
+    /// outlive us (e.g. programs attached to an interface). Collectors should
+    /// still clean up on drop, as this is not called on error paths.
+    fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// Add the collector summary, if any, to the stop meta event.
+    fn summary(&self, _: &mut Event) {}
+    /// Check the collector can run on this system, without side effects
+    /// (e.g. required kernel symbols are available). This is used to report
+    /// capabilities; `init()` should still fail if the collector can't run.
+    fn check(&self, _: &probe::Kernel) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Group of collectors. Used to handle a set of collectors and to perform
@@ -47,6 +123,27 @@ pub(crate) struct Group {
     list: HashMap<String, Box<dyn Collector>>,
     kernel: probe::Kernel,
     events: BpfEvents,
+    pid_filter: Option<Arc<PidFilter>>,
+    /// Pid of the --while-running command, when events are filtered on it.
+    workload_pid: Option<u32>,
+    match_set: Option<Arc<MatchSet>>,
+    iface_filter: Option<Arc<IfaceFilter>>,
+    softirq: Option<SoftirqTracker>,
+    /// Number of events retrieved so far. Shared with the control socket.
+    count: Arc<AtomicU64>,
+    /// When the event retrieval was started.
+    started: Option<Instant>,
+    /// Outcome of the adaptive mode, if used: whether probes were enabled and
+    /// their observed rate, in hits per second.
+    adaptive: Option<(bool, f64)>,
+    /// Probe plan of the initialized collectors, in initialization order:
+    /// probes added by a collector and whether it hooks to all probes.
+    plan: Vec<(String, Vec<String>, bool)>,
+    /// Initialized collectors, in initialization order. They are started in
+    /// the same order.
+    order: Vec<String>,
+    /// Collector priorities set on the command line, overriding their own.
+    priorities: HashMap<String, i32>,
 }
 
 impl Group {
@@ -58,6 +155,17 @@ impl Group {
             list: HashMap::new(),
             kernel,
             events,
+            pid_filter: None,
+            workload_pid: None,
+            match_set: None,
+            iface_filter: None,
+            softirq: None,
+            count: Arc::new(AtomicU64::new(0)),
+            started: None,
+            adaptive: None,
+            plan: Vec::new(),
+            order: Vec::new(),
+            priorities: HashMap::new(),
         })
     }
 
@@ -86,7 +194,7 @@ impl Group {
     }
 
     /// Initialize all collectors by calling their `init()` function. Collectors
-    /// failing to initialize will be removed from the group.
+    /// failing to initialize, or not requested, will be removed from the group.
     pub(crate) fn init(&mut self, cli: &CliConfig) -> Result<()> {
         let collect = cli
             .subcommand
@@ -94,35 +202,427 @@ impl Group {
             .downcast_ref::<Collect>()
             .ok_or_else(|| anyhow!("wrong subcommand"))?;
 
-        probe::common::set_ebpf_debug(collect.args()?.ebpf_debug.unwrap_or(false))?;
-
-        // Try initializing all collectors in the group. Failing ones are
-        // put on a list for future removal.
-        let mut to_remove = Vec::new();
-        for name in &collect.args()?.collectors {
-            let c = self
-                .list
-                .get_mut(name)
-                .ok_or_else(|| anyhow!("unknown collector: {}", &name))?;
-            if let Err(e) = c.init(cli, &mut self.kernel, &mut self.events) {
-                to_remove.push(c.name());
-                error!(
-                    "Could not initialize collector '{}', unregistering: {}",
-                    c.name(),
+        // The softirq being run is tracked separately, in a map shared with
+        // the probes.
+        if collect.args()?.context {
+            match SoftirqTracker::new() {
+                Ok(tracker) => {
+                    self.kernel.reuse_map("softirq_map", tracker.map_fd()?)?;
+                    self.softirq = Some(tracker);
+                }
+                Err(e) => warn!(
+                    "Could not track softirqs ({}), their vector won't be reported",
                     e
-                );
+                ),
+            }
+        }
+
+        // Set up the pid filter before collectors are initialized, so the
+        // filter map is known to all probes.
+        let pid = match (self.workload_pid, collect.args()?.pid) {
+            (Some(pid), _) => Some((pid, true)),
+            (None, Some(pid)) => Some((pid, collect.args()?.pid_children.unwrap_or(false))),
+            (None, None) => None,
+        };
+        if let Some((pid, children)) = pid {
+            let filter = PidFilter::new(pid, children)?;
+            self.kernel.reuse_map("pid_filter_map", filter.map_fd())?;
+            probe::map_usage::track_map("pid_filter_map", filter.map_fd())?;
+            self.pid_filter = Some(Arc::new(filter));
+        }
+
+        for target in collect.args()?.disable_probe.iter() {
+            self.kernel.disable_probe(target);
+        }
+        if let Some(delay) = collect.args()?.probe_delay {
+            self.kernel.set_probe_delay(Duration::from_millis(delay));
+        }
+        if collect.args()?.adaptive {
+            self.kernel.set_observe();
+        }
+
+        // Resolve the dependencies of the requested collectors, this gives us
+        // the list of collectors to initialize, in order.
+        // Tracing a flow requires the skb collector, which does the filtering,
+        // and is best followed using skb-tracking.
+        let mut requested = collect.args()?.collectors.clone();
+        let flow = match &collect.args()?.flow {
+            Some(flow) => Some(Flow::parse(flow).or_else(|e| bail!("Invalid flow: {}", e))?),
+            None => None,
+        };
+        if flow.is_some() {
+            for name in ["skb", "skb-tracking"] {
+                if !requested.iter().any(|x| x == name) {
+                    info!("Enabling collector '{}' to trace the flow", name);
+                    requested.push(name.to_string());
+                }
+            }
+        }
+        // Sampling the head of flows is done by the skb collector too.
+        let flow_head = {
+            let args = collect.args()?;
+            args.flow_head.is_some() || args.new_flows_only
+        };
+        if flow_head && !requested.iter().any(|x| x == "skb") {
+            info!("Enabling collector 'skb' to sample the head of flows");
+            requested.push("skb".to_string());
+        }
+        // So is matching packets against a match set. The set is loaded
+        // before collectors are initialized, for the skb one to use its maps.
+        if let Some(path) = &collect.args()?.match_set {
+            let set = Arc::new(MatchSet::new(path)?);
+            set_match_set(Arc::clone(&set))?;
+            self.match_set = Some(set);
+
+            if !requested.iter().any(|x| x == "skb") {
+                info!("Enabling collector 'skb' to filter on the match set");
+                requested.push("skb".to_string());
+            }
+        }
+        // And so is filtering on interfaces.
+        if !collect.args()?.interface.is_empty() {
+            let filter = Arc::new(IfaceFilter::new(&collect.args()?.interface)?);
+            set_iface_filter(Arc::clone(&filter))?;
+            self.iface_filter = Some(filter);
+
+            if !requested.iter().any(|x| x == "skb") {
+                info!("Enabling collector 'skb' to filter on interfaces");
+                requested.push("skb".to_string());
             }
         }
+        // Counters are incremented by the skb collector hook.
+        let count_only = collect.args()?.count_only;
+        if count_only && !requested.iter().any(|x| x == "skb") {
+            info!("Enabling collector 'skb' to count packets");
+            requested.push("skb".to_string());
+        }
 
-        // Remove all collectors that failed their initialization at the
-        // previous step.
-        for name in to_remove.iter() {
-            self.list.remove(*name);
+        for def in collect.args()?.collector_priority.iter() {
+            match def.split_once('=').map(|(n, p)| (n, p.parse::<i32>())) {
+                Some((name, Ok(priority))) if self.list.contains_key(name) => {
+                    self.priorities.insert(name.to_string(), priority);
+                }
+                Some((name, Ok(_))) => {
+                    bail!("Invalid collector priority: unknown collector {}", name)
+                }
+                _ => bail!(
+                    "Invalid collector priority '{}': expected \"<collector>=<priority>\"",
+                    def
+                ),
+            }
+        }
+
+        let requested = &requested;
+        let resolved = self.resolve_dependencies(requested)?;
+        for name in resolved.iter().filter(|x| !requested.contains(*x)) {
+            info!("Enabling collector '{}' as a dependency", name);
+        }
+
+        let timeout = Duration::from_secs(collect.args()?.collector_timeout.unwrap_or(10));
+
+        // Try initializing all requested collectors in the group. Successful
+        // ones are put on a list so we can remove the others. Failed ones are
+        // reported once all collectors were tried.
+        let mut to_keep = Vec::new();
+        let mut failed = Vec::new();
+        for name in &resolved {
+            // Unwrap can't fail as the dependency resolution checked all
+            // collectors exist.
+            let c = self.list.get_mut(name).unwrap();
+
+            if let Some(dep) = c.depends_on().iter().find(|x| !to_keep.contains(*x)) {
+                failed.push((c.name(), format!("dependency '{}' is not available", dep)));
+                continue;
+            }
+
+            if let Err(e) = c.requirements().check_running(c.name()) {
+                failed.push((c.name(), e.to_string()));
+                continue;
+            }
+
+            let probes = self.probe_names();
+            let hooks = self.kernel.generic_hooks();
+            let snapshot = self.kernel.snapshot();
+
+            let deadline = InitDeadline::new(timeout);
+            let watchdog = InitWatchdog::start(c.name(), timeout);
+            let ret = c.init(cli, &mut self.kernel, &mut self.events, &deadline);
+            watchdog.stop();
+            // Whatever the initialization returned, a collector which ran
+            // past its deadline is not trusted to be in a sane state.
+            let ret = match deadline.expired() {
+                true => Err(anyhow!("did not initialize within {}s", timeout.as_secs())),
+                false => ret,
+            };
+
+            match ret {
+                Ok(()) => {
+                    to_keep.push(c.name());
+                    self.plan.push((
+                        c.name().to_string(),
+                        self.probe_names()
+                            .into_iter()
+                            .filter(|p| !probes.contains(p))
+                            .collect(),
+                        self.kernel.generic_hooks() > hooks,
+                    ));
+                }
+                Err(e) => {
+                    // Probes and hooks partially set up by the collector
+                    // must not be attached.
+                    self.kernel.rollback(snapshot);
+                    failed.push((c.name(), e.to_string()));
+                }
+            }
+        }
+
+        if !failed.is_empty() && collect.args()?.require_all_collectors {
+            bail!(
+                "Could not initialize collector(s): {}",
+                failed
+                    .iter()
+                    .map(|(name, e)| format!("'{}' ({})", name, e))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        for (name, e) in failed.iter() {
+            // Collectors enabled by default are expected not to all be
+            // supported or configured, only explicitly requested ones are
+            // reported as errors.
+            match collect.args()?.default_collectors {
+                true => info!("Skipping collector '{}', unregistering: {}", name, e),
+                false => error!(
+                    "Could not initialize collector '{}', unregistering: {}",
+                    name, e
+                ),
+            }
+        }
+
+        // Remove all collectors that weren't requested or that failed their
+        // initialization at the previous step.
+        self.list.retain(|name, _| to_keep.contains(&name.as_str()));
+        self.order = to_keep.iter().map(|x| x.to_string()).collect();
+
+        // Without the skb collector the flow would not be filtered at all.
+        if flow.is_some() && !to_keep.contains(&"skb") {
+            bail!("Can't trace the flow: the skb collector could not be initialized");
+        }
+        if flow_head && !to_keep.contains(&"skb") {
+            bail!("Can't sample the head of flows: the skb collector could not be initialized");
+        }
+        if self.match_set.is_some() && !to_keep.contains(&"skb") {
+            bail!("Can't filter on the match set: the skb collector could not be initialized");
+        }
+        if self.iface_filter.is_some() && !to_keep.contains(&"skb") {
+            bail!("Can't filter on interfaces: the skb collector could not be initialized");
+        }
+        if count_only && !to_keep.contains(&"skb") {
+            bail!("Can't count packets: the skb collector could not be initialized");
+        }
+
+        if let Some(pattern) = &collect.args()?.kprobe_regex {
+            self.add_regex_probes(pattern)?;
         }
 
         Ok(())
     }
 
+    /// Add kprobes to all functions matching a regex, up to PROBE_MAX probes
+    /// in total (including the ones already registered). Those probes report
+    /// entry events, with the function name in the common section. Functions
+    /// which can't be probed are skipped.
+    fn add_regex_probes(&mut self, pattern: &str) -> Result<()> {
+        let re =
+            Regex::new(pattern).or_else(|e| bail!("Invalid kprobe regex '{}': {}", pattern, e))?;
+
+        let functions = kernel_symbols::matching_functions(|f| re.is_match(f))?;
+        if functions.is_empty() {
+            bail!("No kernel function matches '{}'", pattern);
+        }
+        let mut count = self.kernel.probe_count();
+        let available = PROBE_MAX.saturating_sub(count);
+        if functions.len() > available {
+            warn!(
+                "{} functions match '{}', only probing up to {} of them ({} probes already registered)",
+                functions.len(),
+                pattern,
+                available,
+                count
+            );
+        }
+
+        let probes = self.probe_names();
+        let (mut probed, mut failed) = (0, 0);
+        for function in functions.iter() {
+            if probed == available {
+                break;
+            }
+
+            match self.kernel.add_probe(ProbeType::Kprobe, function) {
+                // Disabled targets and targets already probed are not added.
+                Ok(()) => {
+                    let new = self.kernel.probe_count();
+                    if new > count {
+                        probed += 1;
+                        count = new;
+                    }
+                }
+                Err(e) => {
+                    // Reported individually at the debug level, as there can
+                    // be a lot of them.
+                    debug!("Skipping function {}: {}", function, e);
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed > 0 {
+            warn!(
+                "Could not probe {} functions matching '{}' (see the debug logs for details)",
+                failed, pattern
+            );
+        }
+        info!("Probing {} functions matching '{}'", probed, pattern);
+        self.plan.push((
+            format!("kprobe-regex '{}'", pattern),
+            self.probe_names()
+                .into_iter()
+                .filter(|p| !probes.contains(p))
+                .collect(),
+            false,
+        ));
+        Ok(())
+    }
+
+    /// Describe the probes the initialized collectors will attach to, without
+    /// attaching them. Probes shared between collectors are only listed for
+    /// the first one requesting them.
+    pub(crate) fn probe_plan(&self) -> String {
+        let mut plan = String::new();
+
+        for (name, probes, generic) in self.plan.iter() {
+            plan.push_str(&format!("{}:\n", name));
+            if *generic {
+                plan.push_str("  (hook on all probes)\n");
+            }
+            for probe in probes.iter() {
+                plan.push_str(&format!("  {}\n", probe));
+            }
+        }
+
+        plan
+    }
+
+    /// Estimate the overhead of the initialized collectors, by attaching their
+    /// probes in counting mode for `window` to measure their rate. Returns a
+    /// report, per collector and in total. Collectors are not started.
+    pub(crate) fn estimate_overhead(&mut self, window: Duration) -> Result<String> {
+        if !probe::common::get_count_only() {
+            bail!("Probes are not in counting mode");
+        }
+
+        self.kernel.attach()?;
+        self.kernel.watch_modules()?;
+        info!("Sampling the probes' rate for {}s", window.as_secs());
+        thread::sleep(window);
+
+        let estimates = overhead::estimate(
+            &self.plan,
+            &self.kernel.probes(),
+            &self.kernel.probe_hits()?,
+            window,
+        );
+        Ok(overhead::report(&estimates, window))
+    }
+
+    /// Resolve the dependencies of the requested collectors. Returns the full
+    /// list of collectors to initialize, by priority, with dependencies coming
+    /// before the collectors depending on them. Unknown collectors and
+    /// dependency cycles are reported as errors.
+    fn resolve_dependencies(&self, requested: &[String]) -> Result<Vec<String>> {
+        let mut resolved = Vec::new();
+        let mut visiting = Vec::new();
+
+        // Unknown collectors are reported by resolve_collector().
+        let mut requested = requested.to_vec();
+        requested.sort_by_key(|name| self.priority(name).unwrap_or(0));
+
+        for name in requested.iter() {
+            self.resolve_collector(name, &mut visiting, &mut resolved)?;
+        }
+
+        Ok(resolved)
+    }
+
+    // Depth-first resolution of a single collector dependencies. `visiting`
+    // holds the current dependency chain and is used to detect cycles.
+    fn resolve_collector(
+        &self,
+        name: &str,
+        visiting: &mut Vec<String>,
+        resolved: &mut Vec<String>,
+    ) -> Result<()> {
+        if resolved.iter().any(|x| x == name) {
+            return Ok(());
+        }
+
+        if visiting.iter().any(|x| x == name) {
+            bail!(
+                "Collector dependency cycle: {} -> {}",
+                visiting.join(" -> "),
+                name
+            );
+        }
+
+        let c = self
+            .list
+            .get(name)
+            .ok_or_else(|| anyhow!("unknown collector: {}", name))?;
+
+        visiting.push(name.to_string());
+        for dep in c.depends_on() {
+            self.resolve_collector(dep, visiting, resolved)?;
+        }
+        visiting.pop();
+
+        resolved.push(name.to_string());
+        Ok(())
+    }
+
+    /// Effective priority of a collector, taking the command line overrides
+    /// into account.
+    fn priority(&self, name: &str) -> Option<i32> {
+        match self.priorities.get(name) {
+            Some(priority) => Some(*priority),
+            None => self.list.get(name).map(|c| c.priority()),
+        }
+    }
+
+    /// Check all collectors in the group can run on this system, by calling
+    /// their `check()` function. Results are sorted by collector name.
+    pub(crate) fn check(&self) -> Vec<(String, Result<()>)> {
+        let mut results: Vec<(String, Result<()>)> = self
+            .list
+            .iter()
+            .map(|(name, c)| {
+                let res = c
+                    .requirements()
+                    .check_running(name)
+                    .and_then(|_| c.check(&self.kernel));
+                (name.clone(), res)
+            })
+            .collect();
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        results
+    }
+
+    /// Only report events generated by the --while-running command, and its
+    /// descendants. Must be called before `init()`, overrides --pid.
+    pub(crate) fn filter_workload(&mut self, pid: u32) {
+        self.workload_pid = Some(pid);
+    }
+
     /// Register all collectors' command line arguments by calling their register_cli function.
     pub(crate) fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
         for (_, c) in self.list.iter() {
@@ -132,25 +632,331 @@ impl Group {
         Ok(())
     }
 
-    /// Poll an event from the events channel. This is a blocking call.
-    pub(crate) fn poll_event(&self) -> Result<Event> {
-        self.events.poll()
+    /// Poll an event from the events channel. This is a blocking call, unless
+    /// a timeout is given; in which case None is returned if no event was
+    /// retrieved in time.
+    pub(crate) fn poll_event(&mut self, timeout: Option<Duration>) -> Result<Option<Event>> {
+        // Probes on reloaded modules need to be reattached.
+        if let Err(e) = self.kernel.check_modules() {
+            warn!("Could not check for module reloads: {}", e);
+        }
+
+        let event = self.events.poll(timeout)?;
+        if event.is_some() {
+            self.count.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(event)
+    }
+
+    /// Build the start meta event, describing the collection configuration.
+    pub(crate) fn start_event(&self) -> Result<Event> {
+        let mut collectors: Vec<&str> = self.list.keys().map(|x| x.as_str()).collect();
+        collectors.sort();
+
+        let mut filters = Vec::new();
+        if let Some(filter) = &self.pid_filter {
+            filters.push(filter.to_string());
+        }
+        if let Some(set) = &self.match_set {
+            filters.push(set.to_string());
+        }
+        if let Some(filter) = &self.iface_filter {
+            filters.push(filter.to_string());
+        }
+
+        meta::start_event(&collectors, &filters)
+    }
+
+    /// Build a counters meta event, holding the current event counters (see
+    /// probe::common::set_count_events()).
+    pub(crate) fn counters_event(&self) -> Event {
+        meta::counters_event(&self.events.counters())
+    }
+
+    /// Build the stop meta event, summarizing the collection.
+    pub(crate) fn stop_event(&self) -> Event {
+        let duration = match self.started {
+            Some(started) => started.elapsed().as_millis() as u64,
+            None => 0,
+        };
+
+        let mut event = meta::stop_event(self.count.load(Ordering::Relaxed), duration);
+
+        // Only report event losses when there are some.
+        let stats = self.events.stats();
+        for (key, val) in [
+            ("events_lost", stats.lost),
+            ("sections_oversized", stats.oversized),
+            ("events_truncated", stats.truncated),
+        ] {
+            if val > 0 {
+                event.insert(meta::META_SECTION, event_field!(key, val));
+            }
+        }
+        match self.kernel.probe_throttled() {
+            Ok(dropped) if !dropped.is_empty() => {
+                let (probes, collectors) = throttled_report(&self.plan, &dropped);
+                event.insert(
+                    meta::META_SECTION,
+                    event_field!("throttled_per_probe", probes),
+                );
+                event.insert(
+                    meta::META_SECTION,
+                    event_field!("throttled_per_collector", collectors),
+                );
+            }
+            Ok(_) => (),
+            Err(e) => warn!("Could not retrieve the per-probe drops: {}", e),
+        }
+        for name in self.order.iter() {
+            // Unwrap can't fail as only initialized collectors are ordered.
+            self.list.get(name).unwrap().summary(&mut event);
+        }
+        if let Some((enabled, rate)) = self.adaptive {
+            let mode = match enabled {
+                true => "full",
+                false => "count",
+            };
+            event.insert(
+                meta::META_SECTION,
+                event_field!("adaptive_mode", mode.to_string()),
+            );
+            event.insert(
+                meta::META_SECTION,
+                event_field!("adaptive_rate", rate as u64),
+            );
+        }
+        event
+    }
+
+    /// Get a handle on the collection state, for inspecting and adjusting it
+    /// while it is running.
+    pub(crate) fn control_state(&self) -> Result<ControlState> {
+        Ok(ControlState {
+            count: Arc::clone(&self.count),
+            started: self.started.unwrap_or_else(Instant::now),
+            collectors: self.order.clone(),
+            pid_filter: self.pid_filter.as_ref().map(Arc::clone),
+            match_set: self.match_set.as_ref().map(Arc::clone),
+            iface_filter: self.iface_filter.as_ref().map(Arc::clone),
+            output: None,
+            recorder: None,
+        })
     }
 
     /// Start the event retrieval for all collectors in the group by calling
-    /// their `start()` function. Collectors failing to start the event
-    /// retrieval will be kept in the group.
-    pub(crate) fn start(&mut self, _: &CliConfig) -> Result<()> {
-        self.events.start_polling()?;
+    /// their `start()` function, in initialization order. Collectors failing
+    /// to start the event retrieval will be kept in the group.
+    pub(crate) fn start(&mut self, cli: &CliConfig) -> Result<()> {
+        let collect = cli
+            .subcommand
+            .as_any()
+            .downcast_ref::<Collect>()
+            .ok_or_else(|| anyhow!("wrong subcommand"))?;
+
+        if let Some(size) = collect.args()?.max_event_size {
+            self.events.set_max_event_size(size as usize);
+        }
+        self.events.start_polling(
+            collect.args()?.workers.unwrap_or(1) as usize,
+            collect.args()?.ordered.unwrap_or(true),
+        )?;
+        self.started = Some(Instant::now());
         self.kernel.attach()?;
+        self.kernel.watch_modules()?;
+        if collect.args()?.adaptive {
+            self.adapt(
+                Duration::from_secs(collect.args()?.adaptive_window.unwrap_or(5)),
+                collect.args()?.adaptive_threshold.unwrap_or(10000),
+            )?;
+        }
 
-        for (_, c) in self.list.iter_mut() {
+        for name in self.order.iter() {
+            // Unwrap can't fail as only initialized collectors are ordered.
+            let c = self.list.get_mut(name).unwrap();
             if c.start().is_err() {
                 warn!("Could not start '{}'", c.name());
             }
         }
         Ok(())
     }
+
+    /// Names of the probes added so far, as "type:target". Used to find the
+    /// probes added by a collector: probes are compared by name only, as the
+    /// number of hooks of an existing probe changes when a collector targets
+    /// it.
+    fn probe_names(&self) -> Vec<String> {
+        self.kernel.probes().into_iter().map(|(p, _)| p).collect()
+    }
+
+    /// Stop the event retrieval for all collectors in the group by calling
+    /// their `stop()` function, in reverse initialization order. Failing to
+    /// stop a collector is not fatal, the others are still stopped.
+    pub(crate) fn stop(&mut self) {
+        for name in self.order.iter().rev() {
+            // Unwrap can't fail as only initialized collectors are ordered.
+            let c = self.list.get_mut(name).unwrap();
+            if let Err(e) = c.stop() {
+                warn!("Could not stop '{}': {}", c.name(), e);
+            }
+        }
+    }
+
+    /// Observe the rate of the probes, attached in counting mode, for
+    /// `window` and only enable them if it is below `threshold` hits per
+    /// second. Otherwise they are left counting their hits.
+    fn adapt(&mut self, window: Duration, threshold: u64) -> Result<()> {
+        info!("Observing the probes' rate for {}s", window.as_secs());
+        thread::sleep(window);
+
+        let rate = overhead::total_rate(&self.kernel.probe_hits()?, window);
+        let enable = rate < threshold as f64;
+        match enable {
+            true => {
+                info!("Probes rate is {:.0} hits/s, enabling them", rate);
+                self.kernel.upgrade()?;
+            }
+            false => warn!(
+                "Probes rate is {:.0} hits/s (threshold {}/s), staying in counting mode: no event will be reported",
+                rate, threshold
+            ),
+        }
+        self.adaptive = Some((enable, rate));
+        Ok(())
+    }
+}
+
+/// Deadline of a collector initialization, see --collector-timeout.
+/// Collectors doing potentially long operations in `init()` (scanning /proc,
+/// waiting on a daemon, etc.) must bound them: loops should call `check()`
+/// and blocking calls be run using `run()`.
+pub(super) struct InitDeadline {
+    at: Instant,
+}
+
+impl InitDeadline {
+    pub(super) fn new(timeout: Duration) -> InitDeadline {
+        InitDeadline {
+            at: Instant::now() + timeout,
+        }
+    }
+
+    /// Was the deadline reached?
+    pub(super) fn expired(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    /// Return an error if the deadline was reached.
+    pub(super) fn check(&self) -> Result<()> {
+        if self.expired() {
+            bail!("initialization timed out");
+        }
+        Ok(())
+    }
+
+    /// Run a blocking operation on a separate thread and wait for it, up to
+    /// the deadline. Once reached the thread is abandoned, its result being
+    /// discarded whenever it completes.
+    pub(super) fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            // The receiver is gone if the deadline was reached.
+            let _ = tx.send(f());
+        });
+
+        match rx.recv_timeout(self.at.saturating_duration_since(Instant::now())) {
+            Ok(ret) => ret,
+            Err(mpsc::RecvTimeoutError::Timeout) => bail!("initialization timed out"),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                bail!("initialization thread exited unexpectedly")
+            }
+        }
+    }
+}
+
+/// Time given to a collector to return from `init()` once its deadline was
+/// reached, see InitWatchdog.
+const INIT_GRACE: Duration = Duration::from_secs(5);
+
+/// Watchdog for a collector initialization. Collectors are expected to honor
+/// their InitDeadline; one still initializing INIT_GRACE after it can't be
+/// interrupted safely (it might have partially set up probes, maps, etc.), so
+/// the whole initialization fails and the tool exits.
+struct InitWatchdog {
+    tx: Option<mpsc::Sender<()>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl InitWatchdog {
+    /// Start a watchdog for the initialization of collector `name`.
+    fn start(name: &'static str, timeout: Duration) -> InitWatchdog {
+        let (tx, rx) = mpsc::channel::<()>();
+
+        let handle = thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(timeout + INIT_GRACE) {
+                error!(
+                    "Collector '{}' did not initialize within {}s and can't be interrupted, giving up",
+                    name,
+                    (timeout + INIT_GRACE).as_secs()
+                );
+                process::exit(1);
+            }
+        });
+
+        InitWatchdog {
+            tx: Some(tx),
+            handle: Some(handle),
+        }
+    }
+
+    /// Stop the watchdog.
+    fn stop(mut self) {
+        // The watchdog stops when its channel is closed.
+        self.tx = None;
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+/// Set up the process-wide configuration shared by all probes (debug, clock,
+/// counting modes, pinned maps, etc). Those can only be set once, so this must
+/// be called once, before initializing the collectors (see Group::init()).
+pub(crate) fn set_probe_config(cli: &CliConfig) -> Result<()> {
+    let args = cli
+        .subcommand
+        .as_any()
+        .downcast_ref::<Collect>()
+        .ok_or_else(|| anyhow!("wrong subcommand"))?
+        .args()?;
+
+    probe::common::set_ebpf_debug(args.ebpf_debug.unwrap_or(false))?;
+    if args.bpf_trace {
+        start_bpf_trace().or_else(|e| bail!("Could not capture BPF traces: {}", e))?;
+    }
+    probe::common::set_count_only(args.estimate_overhead)?;
+    probe::common::set_count_events(args.count_only)?;
+    if let Some(dir) = &args.dump_bpf {
+        probe::common::set_dump_bpf(dir)?;
+    }
+    probe::common::set_clock_tai(match args.clock.as_deref() {
+        Some("tai") if !probe::common::tai_clock_available() => {
+            warn!("The TAI clock is not available on this kernel (requires Linux v6.1), only using the monotonic one");
+            false
+        }
+        Some("tai") => true,
+        _ => false,
+    })?;
+    probe::common::set_exec_context(args.context)?;
+    probe::common::set_probe_rate(args.max_eps_per_probe.unwrap_or(0))?;
+    if let Some(dir) = &args.pin_maps {
+        set_pin_maps(dir)?;
+    }
+    Ok(())
 }
 
 /// Allocate collectors and retrieve a group containing them, used to perform
@@ -163,68 +969,97 @@ pub(crate) fn get_collectors() -> Result<Group> {
     group
         .register(Box::new(SkbTrackingCollector::new()?))?
         .register(Box::new(SkbCollector::new()?))?
-        .register(Box::new(OvsCollector::new()?))?;
+        .register(Box::new(OvsCollector::new()?))?
+        .register(Box::new(XfrmCollector::new()?))?
+        .register(Box::new(BridgeCollector::new()?))?
+        .register(Box::new(CgroupCollector::new()?))?
+        .register(Box::new(KprobeCollector::new()?))?
+        .register(Box::new(XdpCollector::new()?))?
+        .register(Box::new(SockfilterCollector::new()?))?
+        .register(Box::new(TcpRetransCollector::new()?))?
+        .register(Box::new(SockdropCollector::new()?))?
+        .register(Box::new(NetfilterCollector::new()?))?
+        .register(Box::new(TcBpfCollector::new()?))?
+        .register(Box::new(DropmonCollector::new()?))?
+        .register(Box::new(WireguardCollector::new()?))?;
 
     Ok(group)
 }
 
+/// Build the per-probe and per-collector reports of the events dropped by the
+/// per-probe rate limit, from the probe plan (see Group::probe_plan()) and the
+/// drops of each probe (see Kernel::probe_throttled()). As for the overhead
+/// estimation, probes shared between collectors are accounted to the first one
+/// requesting them. Collectors without drops are not reported.
+fn throttled_report(
+    plan: &[(String, Vec<String>, bool)],
+    dropped: &[(String, u64)],
+) -> (Vec<serde_json::Value>, Vec<serde_json::Value>) {
+    let probes = dropped
+        .iter()
+        .map(|(probe, count)| serde_json::json!({"probe": probe, "dropped": count}))
+        .collect();
+
+    let mut collectors: Vec<(&String, u64)> = plan
+        .iter()
+        .map(|(name, probes, _)| {
+            let count = dropped
+                .iter()
+                .filter(|(probe, _)| probes.contains(probe))
+                .map(|(_, count)| count)
+                .sum();
+            (name, count)
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    collectors.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+    let collectors = collectors
+        .iter()
+        .map(|(name, count)| serde_json::json!({"collector": name, "dropped": count}))
+        .collect();
+
+    (probes, collectors)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::cli::{cmd::collect::Collect, MainConfig, SubCommand};
+    use crate::collector::mock::{Journal, MockCollector, Phase, MOCK_SECTION};
 
-    struct DummyCollectorA;
-    struct DummyCollectorB;
+    // Build a collect configuration enabling the given collectors.
+    fn config(collectors: &[&str]) -> Result<CliConfig> {
+        let mut collect = Collect::new()?;
+        collect.args_mut()?.collectors = collectors.iter().map(|x| x.to_string()).collect();
 
-    impl Collector for DummyCollectorA {
-        fn new() -> Result<DummyCollectorA> {
-            Ok(DummyCollectorA)
-        }
-        fn name(&self) -> &'static str {
-            "dummy-a"
-        }
-        fn register_cli(&self, _: &mut DynamicCommand) -> Result<()> {
-            Ok(())
-        }
-        fn init(&mut self, _: &CliConfig, _: &mut probe::Kernel, _: &mut BpfEvents) -> Result<()> {
-            Ok(())
-        }
-        fn start(&mut self) -> Result<()> {
-            Ok(())
-        }
-    }
-
-    impl Collector for DummyCollectorB {
-        fn new() -> Result<DummyCollectorB> {
-            Ok(DummyCollectorB)
-        }
-        fn name(&self) -> &'static str {
-            "dummy-b"
-        }
-        fn register_cli(&self, _: &mut DynamicCommand) -> Result<()> {
-            Ok(())
-        }
-        fn init(&mut self, _: &CliConfig, _: &mut probe::Kernel, _: &mut BpfEvents) -> Result<()> {
-            bail!("Could not initialize")
-        }
-        fn start(&mut self) -> Result<()> {
-            bail!("Could not start");
-        }
+        Ok(CliConfig {
+            main_config: MainConfig::default(),
+            subcommand: Box::new(collect),
+        })
     }
 
     #[test]
     fn register_collectors() -> Result<()> {
         let mut group = Group::new()?;
-        assert!(group.register(Box::new(DummyCollectorA::new()?)).is_ok());
-        assert!(group.register(Box::new(DummyCollectorB::new()?)).is_ok());
+        assert!(group
+            .register(Box::new(MockCollector::named("dummy-a")))
+            .is_ok());
+        assert!(group
+            .register(Box::new(MockCollector::named("dummy-b")))
+            .is_ok());
         Ok(())
     }
 
     #[test]
     fn register_uniqueness() -> Result<()> {
         let mut group = Group::new()?;
-        assert!(group.register(Box::new(DummyCollectorA::new()?)).is_ok());
-        assert!(group.register(Box::new(DummyCollectorA::new()?)).is_err());
+        assert!(group
+            .register(Box::new(MockCollector::named("dummy-a")))
+            .is_ok());
+        assert!(group
+            .register(Box::new(MockCollector::named("dummy-a")))
+            .is_err());
         Ok(())
     }
 
@@ -233,44 +1068,398 @@ mod tests {
         assert!(super::get_collectors().is_ok());
     }
 
+    // Arguments some collectors can't be initialized without.
+    fn load_args(name: &str) -> Vec<String> {
+        match name {
+            "kprobe" => vec!["--kprobe".to_string(), "tcp_sendmsg(size=%dx)".to_string()],
+            "sockfilter" => vec!["--sockfilter-pid".to_string(), process::id().to_string()],
+            _ => Vec::new(),
+        }
+    }
+
+    // Initialize each registered collector and load its BPF objects in the
+    // running kernel, without attaching them, to catch verifier regressions.
+    // Collectors not supported by the running kernel are skipped.
+    #[test]
+    #[cfg_attr(not(feature = "test_cap_bpf"), ignore)]
+    fn load_collectors() -> Result<()> {
+        // Gives the sockfilter collector a socket to filter.
+        let _socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+
+        let mut names: Vec<String> = super::get_collectors()?.list.into_keys().collect();
+        names.sort();
+
+        for name in names.iter() {
+            let mut group = super::get_collectors()?;
+            let mut cli = crate::cli::get_cli()?.build_from(
+                [
+                    vec!["packet-tracer".to_string(), "collect".to_string()],
+                    load_args(name),
+                ]
+                .concat(),
+                true,
+            )?;
+            group.register_cli(cli.get_subcommand_mut()?.dynamic_mut().unwrap())?;
+            let config = cli.run()?;
+
+            let mut supported = true;
+            for dep in group.resolve_dependencies(&[name.clone()])?.iter() {
+                let c = group.list.get_mut(dep).unwrap();
+                if c.requirements().check_running(dep).is_err() || c.check(&group.kernel).is_err() {
+                    supported = false;
+                    break;
+                }
+
+                let deadline = InitDeadline::new(Duration::from_secs(10));
+                c.init(&config, &mut group.kernel, &mut group.events, &deadline)
+                    .or_else(|e| bail!("Could not initialize collector '{}': {}", dep, e))?;
+            }
+            if !supported {
+                info!("Skipping collector '{}', not supported", name);
+                continue;
+            }
+
+            group
+                .kernel
+                .load()
+                .or_else(|e| bail!("Could not load collector '{}': {}", name, e))?;
+        }
+        Ok(())
+    }
+
     #[test]
     fn init_collectors() -> Result<()> {
-        let config = CliConfig {
-            main_config: MainConfig::default(),
-            subcommand: Box::new(Collect::new()?),
-        };
+        let journal = Journal::default();
+        let mut group = Group::new()?;
+
+        group
+            .register(Box::new(
+                MockCollector::named("dummy-a").record_to(&journal),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-b")
+                    .fail_at(Phase::Init)
+                    .record_to(&journal),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-c").record_to(&journal),
+            ))?;
+
+        assert!(group.init(&config(&["dummy-a", "dummy-b"])?).is_ok());
+
+        // Only requested collectors are initialized, and only the successful
+        // ones are kept.
+        assert!(*journal.borrow() == vec!["dummy-a:init", "dummy-b:init"]);
+        assert!(group.list.len() == 1 && group.list.contains_key("dummy-a"));
+
+        // Unknown collectors are reported.
+        let err = group.init(&config(&["dummy-z"])?).unwrap_err();
+        assert!(err.to_string().contains("unknown collector: dummy-z"));
+        Ok(())
+    }
+
+    #[test]
+    fn init_rollback() -> Result<()> {
+        let mut group = Group::new()?;
+
+        group
+            .register(Box::new(
+                MockCollector::named("dummy-a").with_probe("consume_skb"),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-b")
+                    .with_probe("kfree_skb_reason")
+                    .fail_at(Phase::Init),
+            ))?;
+
+        assert!(group.init(&config(&["dummy-a", "dummy-b"])?).is_ok());
+
+        // Probes registered by collectors failing to initialize are removed.
+        assert!(group.probe_names() == ["kprobe:consume_skb"]);
+        Ok(())
+    }
+
+    #[test]
+    fn init_deadline() -> Result<()> {
+        let deadline = InitDeadline::new(Duration::from_millis(200));
+        assert!(deadline.check().is_ok());
+        assert!(deadline.run(|| Ok(42))? == 42);
+
+        // Blocking operations are abandoned once the deadline is reached.
+        assert!(deadline
+            .run(|| {
+                thread::sleep(Duration::from_secs(5));
+                Ok(())
+            })
+            .is_err());
+        assert!(deadline.expired() && deadline.check().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn init_dependencies() -> Result<()> {
+        let journal = Journal::default();
+        let mut group = Group::new()?;
+
+        group
+            .register(Box::new(
+                MockCollector::named("dummy-a").record_to(&journal),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-b")
+                    .fail_at(Phase::Init)
+                    .record_to(&journal),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-c")
+                    .with_deps(&["dummy-a"])
+                    .record_to(&journal),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-d")
+                    .with_deps(&["dummy-b"])
+                    .record_to(&journal),
+            ))?;
+
+        assert!(group.init(&config(&["dummy-c", "dummy-d"])?).is_ok());
+
+        // Dependencies are initialized first and collectors whose
+        // dependencies failed are not initialized.
+        assert!(*journal.borrow() == vec!["dummy-a:init", "dummy-c:init", "dummy-b:init"]);
+        assert!(group.list.len() == 2);
+        assert!(group.list.contains_key("dummy-a") && group.list.contains_key("dummy-c"));
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_dependencies() -> Result<()> {
+        let mut group = Group::new()?;
+        group
+            .register(Box::new(MockCollector::named("dummy-a")))?
+            .register(Box::new(MockCollector::named("dummy-b")))?
+            .register(Box::new(
+                MockCollector::named("dummy-c").with_deps(&["dummy-a"]),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-d").with_deps(&["dummy-e"]),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-e").with_deps(&["dummy-d"]),
+            ))?;
+
+        let to_vec = |list: &[&str]| list.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            group.resolve_dependencies(&to_vec(&["dummy-c"]))?,
+            to_vec(&["dummy-a", "dummy-c"])
+        );
+        assert_eq!(
+            group.resolve_dependencies(&to_vec(&["dummy-c", "dummy-b", "dummy-a"]))?,
+            to_vec(&["dummy-a", "dummy-c", "dummy-b"])
+        );
+        assert!(group.resolve_dependencies(&to_vec(&["dummy-d"])).is_err());
+        assert!(group.resolve_dependencies(&to_vec(&["dummy-f"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn priorities() -> Result<()> {
+        let journal = Journal::default();
         let mut group = Group::new()?;
-        let mut dummy_a = Box::new(DummyCollectorA::new()?);
-        let mut dummy_b = Box::new(DummyCollectorB::new()?);
 
-        group.register(Box::new(DummyCollectorA::new()?))?;
-        group.register(Box::new(DummyCollectorB::new()?))?;
+        group
+            .register(Box::new(
+                MockCollector::named("dummy-a").record_to(&journal),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-b")
+                    .with_priority(10)
+                    .with_deps(&["dummy-c"])
+                    .record_to(&journal),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-c")
+                    .with_priority(20)
+                    .record_to(&journal),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-d")
+                    .with_priority(-10)
+                    .record_to(&journal),
+            ))?;
 
-        let mut events = BpfEvents::new()?;
-        let mut kernel = probe::Kernel::new(&events)?;
+        // Lower priorities first, dependencies still coming before the
+        // collectors depending on them; start follows the init order.
+        let config = config(&["dummy-a", "dummy-b", "dummy-d"])?;
+        group.init(&config)?;
+        assert!(group.order == ["dummy-d", "dummy-a", "dummy-c", "dummy-b"]);
+        group.start(&config)?;
+        assert!(
+            *journal.borrow()
+                == vec![
+                    "dummy-d:init",
+                    "dummy-a:init",
+                    "dummy-c:init",
+                    "dummy-b:init",
+                    "dummy-d:start",
+                    "dummy-a:start",
+                    "dummy-c:start",
+                    "dummy-b:start",
+                ]
+        );
 
-        assert!(dummy_a.init(&config, &mut kernel, &mut events).is_ok());
-        assert!(dummy_b.init(&config, &mut kernel, &mut events).is_err());
-        assert!(group.init(&config).is_ok());
+        // Priorities can be overridden.
+        let mut group = Group::new()?;
+        group
+            .register(Box::new(MockCollector::named("dummy-a")))?
+            .register(Box::new(MockCollector::named("dummy-b").with_priority(-1)))?;
+        group.priorities.insert("dummy-a".to_string(), -2);
+        let to_vec = |list: &[&str]| list.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            group.resolve_dependencies(&to_vec(&["dummy-b", "dummy-a"]))?,
+            to_vec(&["dummy-a", "dummy-b"])
+        );
         Ok(())
     }
 
     #[test]
     fn start_collectors() -> Result<()> {
-        let config = CliConfig {
-            main_config: MainConfig::default(),
-            subcommand: Box::new(Collect::new()?),
-        };
+        let journal = Journal::default();
         let mut group = Group::new()?;
-        let mut dummy_a = Box::new(DummyCollectorA::new()?);
-        let mut dummy_b = Box::new(DummyCollectorB::new()?);
 
-        group.register(Box::new(DummyCollectorA::new()?))?;
-        group.register(Box::new(DummyCollectorB::new()?))?;
+        group
+            .register(Box::new(
+                MockCollector::named("dummy-a").record_to(&journal),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-b")
+                    .fail_at(Phase::Start)
+                    .record_to(&journal),
+            ))?;
 
-        assert!(dummy_a.start().is_ok());
-        assert!(dummy_b.start().is_err());
+        let config = config(&["dummy-a", "dummy-b"])?;
+        group.init(&config)?;
         assert!(group.start(&config).is_ok());
+        assert!(group.kernel.watching_modules());
+
+        // Collectors failing to start are kept.
+        assert!(journal.borrow().contains(&"dummy-a:start".to_string()));
+        assert!(journal.borrow().contains(&"dummy-b:start".to_string()));
+        assert!(group.list.len() == 2);
+        Ok(())
+    }
+
+    #[test]
+    fn stop_collectors() -> Result<()> {
+        let journal = Journal::default();
+        let mut group = Group::new()?;
+
+        group
+            .register(Box::new(
+                MockCollector::named("dummy-a").record_to(&journal),
+            ))?
+            .register(Box::new(
+                MockCollector::named("dummy-b")
+                    .fail_at(Phase::Stop)
+                    .record_to(&journal),
+            ))?;
+
+        let config = config(&["dummy-a", "dummy-b"])?;
+        group.init(&config)?;
+        assert!(group.order == ["dummy-a", "dummy-b"]);
+        group.start(&config)?;
+        group.stop();
+
+        // Collectors are stopped in reverse order, even if one fails to.
+        let journal = journal.borrow();
+        let stops: Vec<_> = journal.iter().filter(|c| c.ends_with(":stop")).collect();
+        assert!(stops == ["dummy-b:stop", "dummy-a:stop"]);
         Ok(())
     }
+
+    #[test]
+    fn regex_probes() -> Result<()> {
+        let mut group = Group::new()?;
+
+        assert!(group.add_regex_probes("^kfree_skb_reason$").is_ok());
+        assert!(group.kernel.probe_count() == 1);
+        // Probes already registered are not added, nor accounted, again.
+        assert!(group.add_regex_probes("^kfree_skb_reason$").is_ok());
+        assert!(group.kernel.probe_count() == 1);
+        assert!(group.plan.last().unwrap().1.is_empty());
+        assert!(group.add_regex_probes("^no_such_function$").is_err());
+        assert!(group.add_regex_probes("(").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn event_flow() -> Result<()> {
+        let mut group = Group::new()?;
+        group.register(Box::new(MockCollector::named("dummy-a").emit(3)))?;
+
+        let config = config(&["dummy-a"])?;
+        group.init(&config)?;
+        group.start(&config)?;
+
+        for id in 0..3_u64 {
+            let event = group.poll_event(None)?.unwrap();
+            assert!(
+                event.get::<String>(MOCK_SECTION, "collector")? == Some(&"dummy-a".to_string())
+            );
+            assert!(event.get::<u64>(MOCK_SECTION, "id")? == Some(&id));
+        }
+
+        // Events retrieved so far are accounted in the stop event.
+        let stop = group.stop_event();
+        assert!(stop.get::<u64>(meta::META_SECTION, "events")? == Some(&3));
+
+        let start = group.start_event()?;
+        assert!(
+            start.get::<String>(meta::META_SECTION, "collectors")? == Some(&"dummy-a".to_string())
+        );
+
+        let state = group.control_state()?;
+        assert!(state.count.load(Ordering::Relaxed) == 3);
+        Ok(())
+    }
+
+    #[test]
+    fn throttled_report() {
+        let plan = vec![
+            (
+                "skb".to_string(),
+                vec![
+                    "kprobe:kfree_skb".to_string(),
+                    "kprobe:consume_skb".to_string(),
+                ],
+                false,
+            ),
+            (
+                "ovs".to_string(),
+                vec!["kprobe:ovs_dp_upcall".to_string()],
+                false,
+            ),
+            (
+                "nft".to_string(),
+                vec!["kprobe:nft_do_chain".to_string()],
+                false,
+            ),
+        ];
+        let dropped = vec![
+            ("kprobe:kfree_skb".to_string(), 8),
+            ("kprobe:ovs_dp_upcall".to_string(), 3),
+            ("kprobe:consume_skb".to_string(), 1),
+        ];
+
+        let (probes, collectors) = super::throttled_report(&plan, &dropped);
+        assert!(probes.len() == 3);
+        assert!(probes[0] == serde_json::json!({"probe": "kprobe:kfree_skb", "dropped": 8}));
+        assert!(
+            collectors
+                == vec![
+                    serde_json::json!({"collector": "skb", "dropped": 9}),
+                    serde_json::json!({"collector": "ovs", "dropped": 3}),
+                ]
+        );
+    }
 }
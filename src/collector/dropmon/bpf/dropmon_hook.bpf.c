@@ -0,0 +1,32 @@
+#include <vmlinux.h>
+#include <bpf/bpf_core_read.h>
+
+#include <common.h>
+
+/* Please keep in sync with its Rust counterpart in collector::dropmon. */
+struct dropmon_event {
+	/* Address of the dropping instruction */
+	u64 location;
+} __attribute__((packed));
+
+/* Probed tracepoint arguments:
+ *
+ * kfree_skb(struct sk_buff *skb, void *location, ...)
+ */
+DEFINE_HOOK(
+	struct dropmon_event *e;
+	u64 location;
+
+	location = trace_get_param(ctx, 1, u64);
+	if (!location)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_DROPMON, 1, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->location = location;
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
@@ -0,0 +1,159 @@
+use anyhow::{bail, Result};
+use plain::Plain;
+
+use super::dropmon_hook;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            class::{classify, Category, Severity},
+            EventField,
+        },
+        kernel_symbols,
+        probe::kernel::{self, Hook, ProbeType},
+    },
+};
+
+const DROPMON_COLLECTOR: &str = "dropmon";
+
+// Tracepoint reporting drops, as used by the drop monitor.
+const DROPMON_TARGET: &str = "skb:kfree_skb";
+
+// Dropmon event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct DropmonEvent {
+    location: u64,
+}
+unsafe impl Plain for DropmonEvent {}
+
+pub(in crate::collector) struct DropmonCollector {}
+
+impl Collector for DropmonCollector {
+    fn new() -> Result<DropmonCollector> {
+        Ok(DropmonCollector {})
+    }
+
+    fn name(&self) -> &'static str {
+        DROPMON_COLLECTOR
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module_noargs(DROPMON_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        _: &CliConfig,
+        kernel: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        self.check(kernel)?;
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorDropmon,
+            Box::new(|raw_section, fields| {
+                if raw_section.header.data_type != 1 {
+                    bail!("Unknown data type");
+                }
+                unmarshal_dropmon(raw_section, fields, kernel_symbols::resolve)
+            }),
+        )?;
+
+        kernel.register_hook_to(
+            Hook::from(dropmon_hook::DATA),
+            ProbeType::RawTracepoint,
+            DROPMON_TARGET,
+        )
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn check(&self, kernel: &kernel::Kernel) -> Result<()> {
+        if kernel
+            .inspect
+            .get_ksym(&ProbeType::RawTracepoint, DROPMON_TARGET)
+            .is_err()
+        {
+            bail!("{} can't be probed on this kernel", DROPMON_TARGET);
+        }
+        Ok(())
+    }
+}
+
+fn unmarshal_dropmon(
+    raw_section: &BpfRawSection,
+    fields: &mut Vec<EventField>,
+    resolve: fn(u64) -> Result<(String, u64)>,
+) -> Result<()> {
+    let event = parse_raw_section::<DropmonEvent>(raw_section)?;
+    let location = event.location;
+
+    fields.push(event_field!("location", location));
+    // Locations outside of known symbols (e.g. in a module loaded after the
+    // symbols were read, or when kernel addresses are hidden) are reported as
+    // addresses only.
+    if let Ok((symbol, offset)) = resolve(location) {
+        fields.push(event_field!(
+            "function",
+            format!("{}+{:#x}", symbol, offset)
+        ));
+    }
+    classify(fields, Category::Drop, Severity::Warn);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+    use crate::core::events::{bpf::unmarshal_section, Event};
+
+    fn unmarshal(location: u64) -> Result<Event> {
+        unmarshal_section(
+            DROPMON_COLLECTOR,
+            1,
+            &DropmonEvent { location },
+            |section, fields| {
+                unmarshal_dropmon(section, fields, |addr| match addr {
+                    0xffffffff95617530..=0xffffffff956175ff => {
+                        Ok(("tcp_v4_rcv".to_string(), addr - 0xffffffff95617530))
+                    }
+                    _ => Err(anyhow!("No symbol")),
+                })
+            },
+        )
+    }
+
+    #[test]
+    fn unmarshal_location() -> Result<()> {
+        let event = unmarshal(0xffffffff95617530)?;
+        assert!(event.get::<u64>(DROPMON_COLLECTOR, "location")? == Some(&0xffffffff95617530));
+        assert!(
+            event.get::<String>(DROPMON_COLLECTOR, "function")?
+                == Some(&"tcp_v4_rcv+0x0".to_string())
+        );
+        assert!(event.get::<String>(DROPMON_COLLECTOR, "category")? == Some(&"drop".to_string()));
+
+        let event = unmarshal(0xffffffff956175a4)?;
+        assert!(
+            event.get::<String>(DROPMON_COLLECTOR, "function")?
+                == Some(&"tcp_v4_rcv+0x74".to_string())
+        );
+
+        // Unknown locations are reported as addresses only.
+        let event = unmarshal(0xffffffffc0001234)?;
+        assert!(event.get::<u64>(DROPMON_COLLECTOR, "location")? == Some(&0xffffffffc0001234));
+        assert!(event
+            .get::<String>(DROPMON_COLLECTOR, "function")?
+            .is_none());
+        Ok(())
+    }
+}
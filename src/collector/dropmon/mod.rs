@@ -0,0 +1,22 @@
+//! # DropmonCollector
+//!
+//! Report packet drops with the location of the dropping instruction, as the
+//! kernel drop monitor (net_dm) does, resolved to the function it is part of
+//! and the offset in it (e.g. `tcp_v4_rcv+0x1a4`). This pinpoints where a
+//! packet was dropped even when no specific drop reason was given.
+//!
+//! Drops are retrieved from the skb:kfree_skb tracepoint, on which the drop
+//! monitor itself relies, rather than using its netlink interface: events are
+//! thus part of the regular event stream and carry the other collectors
+//! sections (e.g. skb, skb-tracking and its drop reason), which allows
+//! correlating drops with the rest of the packet history. The collector is
+//! skipped if the tracepoint isn't available.
+
+// Re-export dropmon.rs
+#[allow(clippy::module_inception)]
+pub(super) mod dropmon;
+pub(super) use dropmon::*;
+
+mod dropmon_hook {
+    include!("bpf/.out/dropmon_hook.rs");
+}
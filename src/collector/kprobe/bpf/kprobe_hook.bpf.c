@@ -0,0 +1,59 @@
+#include <vmlinux.h>
+
+#include <common.h>
+
+/* Please keep in sync with its Rust counterpart in collector::kprobe. */
+#define KPROBE_ARGS_MAX	5
+
+/* Arguments configuration, indexed in the kprobe_config_map by the function
+ * ksym address.
+ *
+ * Please keep in sync with its Rust counterpart in collector::kprobe.
+ */
+struct kprobe_config {
+	/* Number of arguments to report */
+	u8 nargs;
+	/* Index of the arguments to report */
+	u8 idx[KPROBE_ARGS_MAX];
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, struct kprobe_config);
+} kprobe_config_map SEC(".maps");
+
+/* Please keep in sync with its Rust counterpart in collector::kprobe. */
+struct kprobe_event {
+	u64 ksym;
+	u8 nargs;
+	u64 args[KPROBE_ARGS_MAX];
+} __attribute__((packed));
+
+DEFINE_HOOK(
+	struct kprobe_config *cfg;
+	struct kprobe_event *e;
+	u64 ksym = ctx->ksym;
+	int i;
+
+	cfg = bpf_map_lookup_elem(&kprobe_config_map, &ksym);
+	if (!cfg)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_KPROBE, 1, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->ksym = ksym;
+	e->nargs = cfg->nargs;
+
+	for (i = 0; i < KPROBE_ARGS_MAX; i++) {
+		if (i >= cfg->nargs)
+			break;
+		e->args[i] = trace_get_param(ctx, cfg->idx[i], u64);
+	}
+
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
@@ -0,0 +1,286 @@
+use std::{collections::HashMap, mem};
+
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use plain::Plain;
+
+use super::kprobe_hook;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents},
+            EventField,
+        },
+        kernel_config::Requirements,
+        probe::kernel::{self, Hook, ProbeType},
+    },
+};
+
+const KPROBE_COLLECTOR: &str = "kprobe";
+
+// Please keep in sync with its BPF counterpart in bpf/kprobe_hook.bpf.c
+const KPROBE_ARGS_MAX: usize = 5;
+
+// Registers holding the function arguments, in order, as named in the
+// targets definition.
+#[cfg(target_arch = "x86_64")]
+const ARG_REGISTERS: &[&str] = &["di", "si", "dx", "cx", "r8"];
+#[cfg(target_arch = "aarch64")]
+const ARG_REGISTERS: &[&str] = &["x0", "x1", "x2", "x3", "x4"];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const ARG_REGISTERS: &[&str] = &[];
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct KprobeCollectorArgs {
+    /// Kernel function to probe, optionally with arguments to report named
+    /// after the registers holding them, e.g. "tcp_sendmsg(size=%dx)". Can be
+    /// used multiple times. Required by the kprobe collector. Functions found
+    /// multiple times in kallsyms default to the first one, another can be
+    /// picked using "<module>:<function>" or "<function>@<address>".
+    #[arg(id = "kprobe", long)]
+    kprobe: Vec<String>,
+}
+
+// Kprobe event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct KprobeEvent {
+    ksym: u64,
+    nargs: u8,
+    args: [u64; KPROBE_ARGS_MAX],
+}
+unsafe impl Plain for KprobeEvent {}
+
+// Please keep in sync with its BPF counterpart in bpf/kprobe_hook.bpf.c
+#[derive(Default)]
+#[repr(C)]
+struct KprobeConfig {
+    nargs: u8,
+    idx: [u8; KPROBE_ARGS_MAX],
+}
+unsafe impl Plain for KprobeConfig {}
+
+/// Probe target, as given on the command line.
+#[derive(Debug, PartialEq)]
+struct KprobeTarget {
+    function: String,
+    /// Arguments to report: name and argument index.
+    args: Vec<(String, u8)>,
+}
+
+pub(in crate::collector) struct KprobeCollector {}
+
+impl Collector for KprobeCollector {
+    fn new() -> Result<KprobeCollector> {
+        Ok(KprobeCollector {})
+    }
+
+    fn name(&self) -> &'static str {
+        KPROBE_COLLECTOR
+    }
+
+    fn requirements(&self) -> Requirements {
+        Requirements {
+            config: &["CONFIG_KPROBES"],
+            ..Default::default()
+        }
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module::<KprobeCollectorArgs>(KPROBE_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        cli: &CliConfig,
+        kernel: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        let args = cli.get_section::<KprobeCollectorArgs>(KPROBE_COLLECTOR)?;
+        if args.kprobe.is_empty() {
+            bail!("No kprobe given (see --kprobe)");
+        }
+
+        let targets = args
+            .kprobe
+            .iter()
+            .map(|t| parse_target(t))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut config_map = Self::kprobe_config_map()?;
+        let hook = Hook::from(kprobe_hook::DATA)
+            .reuse_map("kprobe_config_map", config_map.fd())?
+            .to_owned();
+
+        // Argument names, by function ksym, for the unmarshaler to use.
+        let mut names = HashMap::new();
+
+        for target in targets.iter() {
+            let ksym = kernel
+                .inspect
+                .get_ksym(&ProbeType::Kprobe, &target.function)?;
+
+            let mut cfg = KprobeConfig {
+                nargs: target.args.len() as u8,
+                ..Default::default()
+            };
+            for (i, (_, idx)) in target.args.iter().enumerate() {
+                cfg.idx[i] = *idx;
+            }
+
+            let cfg = unsafe { plain::as_bytes(&cfg) };
+            config_map
+                .update(&ksym.to_ne_bytes(), cfg, libbpf_rs::MapFlags::NO_EXIST)
+                .or_else(|_| bail!("Function {} is given multiple times", target.function))?;
+
+            kernel.register_hook_to(hook.clone(), ProbeType::Kprobe, &target.function)?;
+            names.insert(
+                ksym,
+                target
+                    .args
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorKprobe,
+            Box::new(move |raw_section, fields| {
+                if raw_section.header.data_type != 1 {
+                    bail!("Unknown data type");
+                }
+
+                let event = parse_raw_section::<KprobeEvent>(raw_section)?;
+
+                let ksym = event.ksym;
+                let args = event.args;
+                let names = names
+                    .get(&ksym)
+                    .ok_or_else(|| anyhow!("Unknown kprobe target {:#x}", ksym))?;
+
+                for (name, val) in names.iter().zip(args.iter()).take(event.nargs as usize) {
+                    fields.push(event_field!(name, *val));
+                }
+                Ok(())
+            }),
+        )?;
+
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl KprobeCollector {
+    fn kprobe_config_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in bpf/kprobe_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("kprobe_config_map"),
+            mem::size_of::<u64>() as u32,
+            mem::size_of::<KprobeConfig>() as u32,
+            kernel::PROBE_MAX as u32,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the kprobe config map: {}", e))
+    }
+}
+
+/// Parse a probe target definition, "function" or
+/// "function(name=%reg, ...)".
+fn parse_target(def: &str) -> Result<KprobeTarget> {
+    let def = def.trim();
+
+    let (function, args) = match def.split_once('(') {
+        Some((function, args)) => match args.strip_suffix(')') {
+            Some(args) => (function.trim(), args),
+            None => bail!("Invalid kprobe '{}': missing ')'", def),
+        },
+        None => (def, ""),
+    };
+
+    if function.is_empty() {
+        bail!("Invalid kprobe '{}': no function given", def);
+    }
+
+    let mut target = KprobeTarget {
+        function: function.to_string(),
+        args: Vec::new(),
+    };
+
+    for arg in args.split(',').map(|a| a.trim()).filter(|a| !a.is_empty()) {
+        let (name, reg) = arg
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid argument '{}': expected name=%reg", arg))?;
+        let (name, reg) = (name.trim(), reg.trim());
+
+        if name.is_empty() || target.args.iter().any(|(n, _)| n == name) {
+            bail!("Invalid argument '{}': missing or duplicate name", arg);
+        }
+
+        let reg = reg
+            .strip_prefix('%')
+            .ok_or_else(|| anyhow!("Invalid argument '{}': registers start with '%'", arg))?;
+        let idx = ARG_REGISTERS
+            .iter()
+            .position(|r| *r == reg)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Invalid register '{}', argument registers are: {}",
+                    reg,
+                    ARG_REGISTERS.join(", ")
+                )
+            })?;
+
+        target.args.push((name.to_string(), idx as u8));
+    }
+
+    if target.args.len() > KPROBE_ARGS_MAX {
+        bail!("Too many arguments for {}", target.function);
+    }
+
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn parse_targets() -> Result<()> {
+        assert!(
+            parse_target("kfree_skb_reason")?
+                == KprobeTarget {
+                    function: "kfree_skb_reason".to_string(),
+                    args: Vec::new(),
+                }
+        );
+        assert!(
+            parse_target("tcp_sendmsg(sk=%di, size=%dx)")?
+                == KprobeTarget {
+                    function: "tcp_sendmsg".to_string(),
+                    args: vec![("sk".to_string(), 0), ("size".to_string(), 2)],
+                }
+        );
+
+        assert!(parse_target("tcp_sendmsg(size=%dx").is_err());
+        assert!(parse_target("tcp_sendmsg(size=dx)").is_err());
+        assert!(parse_target("tcp_sendmsg(size=%rax)").is_err());
+        assert!(parse_target("tcp_sendmsg(size=%dx, size=%si)").is_err());
+        assert!(parse_target("(size=%dx)").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+//! # KprobeCollector
+//!
+//! Probe arbitrary kernel functions given on the command line and report
+//! some of their arguments, e.g. `--kprobe "tcp_sendmsg(size=%dx)"`. This
+//! allows ad-hoc investigations beyond what the other collectors decode.
+//! Arguments are named after the registers holding them, following the
+//! calling convention of the architecture; only the first five arguments can
+//! be reported.
+
+// Re-export kprobe.rs
+#[allow(clippy::module_inception)]
+pub(super) mod kprobe;
+pub(super) use kprobe::*;
+
+mod kprobe_hook {
+    include!("bpf/.out/kprobe_hook.rs");
+}
@@ -0,0 +1,168 @@
+//! # MockCollector
+//!
+//! Test-only collector recording its lifecycle calls, which can be told to
+//! fail at a chosen phase and to emit synthetic events. This allows testing
+//! the collectors group and the event flow without the BPF parts.
+
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::{bail, Result};
+
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{bpf::BpfEvents, Event, EventField},
+        probe,
+    },
+};
+
+/// Section used by synthetic events.
+pub(crate) const MOCK_SECTION: &str = "mock";
+
+/// Collector lifecycle phases a mock collector can fail at.
+pub(crate) enum Phase {
+    Init,
+    Start,
+    Stop,
+}
+
+/// Journal of the lifecycle calls, as "<name>:<call>". It can be shared by
+/// multiple collectors to check the ordering of calls.
+pub(crate) type Journal = Rc<RefCell<Vec<String>>>;
+
+pub(crate) struct MockCollector {
+    name: &'static str,
+    deps: &'static [&'static str],
+    priority: i32,
+    fail: Option<Phase>,
+    /// Kprobe target to register at init time, before failing if requested.
+    probe: Option<&'static str>,
+    /// Number of synthetic events to emit at init time.
+    events: u64,
+    journal: Journal,
+}
+
+impl MockCollector {
+    /// Create a mock collector with a given name.
+    pub(crate) fn named(name: &'static str) -> MockCollector {
+        MockCollector {
+            name,
+            deps: &[],
+            priority: 0,
+            fail: None,
+            probe: None,
+            events: 0,
+            journal: Journal::default(),
+        }
+    }
+
+    /// Set the collectors this one depends on.
+    pub(crate) fn with_deps(mut self, deps: &'static [&'static str]) -> Self {
+        self.deps = deps;
+        self
+    }
+
+    /// Set the collector priority.
+    pub(crate) fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Fail at a given lifecycle phase.
+    pub(crate) fn fail_at(mut self, phase: Phase) -> Self {
+        self.fail = Some(phase);
+        self
+    }
+
+    /// Register a kprobe on `target` when initialized.
+    pub(crate) fn with_probe(mut self, target: &'static str) -> Self {
+        self.probe = Some(target);
+        self
+    }
+
+    /// Emit `events` synthetic events when initialized.
+    pub(crate) fn emit(mut self, events: u64) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Record the lifecycle calls in a given journal.
+    pub(crate) fn record_to(mut self, journal: &Journal) -> Self {
+        self.journal = Rc::clone(journal);
+        self
+    }
+
+    fn record(&self, call: &str) {
+        self.journal
+            .borrow_mut()
+            .push(format!("{}:{}", self.name, call));
+    }
+}
+
+impl Collector for MockCollector {
+    fn new() -> Result<MockCollector> {
+        Ok(MockCollector::named("mock"))
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn depends_on(&self) -> &[&'static str] {
+        self.deps
+    }
+
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        self.record("register_cli");
+        cmd.register_module_noargs(self.name)
+    }
+
+    fn init(
+        &mut self,
+        _: &CliConfig,
+        kernel: &mut probe::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        self.record("init");
+        if let Some(target) = self.probe {
+            kernel.add_probe(probe::kernel::ProbeType::Kprobe, target)?;
+        }
+        if let Some(Phase::Init) = self.fail {
+            bail!("Could not initialize");
+        }
+
+        for id in 0..self.events {
+            let mut event = Event::new();
+            event.insert(
+                MOCK_SECTION,
+                event_field!("collector", self.name.to_string()),
+            );
+            event.insert(MOCK_SECTION, event_field!("id", id));
+            events.inject(event);
+        }
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.record("start");
+        if let Some(Phase::Start) = self.fail {
+            bail!("Could not start");
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.record("stop");
+        if let Some(Phase::Stop) = self.fail {
+            bail!("Could not stop");
+        }
+        Ok(())
+    }
+}
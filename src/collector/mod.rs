@@ -20,6 +20,22 @@
 pub(crate) mod collector;
 pub(crate) use collector::*;
 
+mod bridge;
+mod cgroup;
+mod dropmon;
+mod kprobe;
+#[cfg(test)]
+mod mock;
+mod netfilter;
+mod overhead;
 mod ovs;
 mod skb;
 mod skb_tracking;
+mod sock;
+mod sockdrop;
+mod sockfilter;
+mod tc_bpf;
+mod tcp_retrans;
+mod wireguard;
+mod xdp;
+mod xfrm;
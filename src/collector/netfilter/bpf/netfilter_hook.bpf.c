@@ -0,0 +1,198 @@
+#include <vmlinux.h>
+#include <bpf/bpf_core_read.h>
+
+#include <common.h>
+
+/* Please keep in sync with its Rust counterpart in collector::netfilter. */
+#define NF_PROBE_HOOK	1
+#define NF_PROBE_XT	2
+#define NF_PROBE_NFT	3
+
+/* Maximum number of netfilter events reported per packet, and time after
+ * which the count of a packet is reset. Please keep in sync with its Rust
+ * counterpart in collector::netfilter.
+ */
+#define NF_TRAVERSALS_MAX	64
+#define NF_TRAVERSALS_RESET_NS	1000000000ULL
+
+#define NF_NAME_LEN	32
+
+/* Netfilter configuration, indexed in the netfilter_config_map by the function
+ * ksym address.
+ *
+ * Please keep in sync with its Rust counterpart in collector::netfilter.
+ */
+struct netfilter_config {
+	/* NF_PROBE_* */
+	u8 kind;
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, struct netfilter_config);
+} netfilter_config_map SEC(".maps");
+
+/* Number of netfilter events reported per packet, indexed by the skb data
+ * address. Data areas are reused once freed; counts not updated for
+ * NF_TRAVERSALS_RESET_NS are considered to belong to a previous packet.
+ */
+struct netfilter_traversals {
+	u64 last_seen;
+	u32 count;
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_LRU_HASH);
+	__uint(max_entries, 8192);
+	__type(key, u64);
+	__type(value, struct netfilter_traversals);
+} netfilter_traversals_map SEC(".maps");
+
+/* Please keep in sync with its Rust counterpart in collector::netfilter. */
+struct netfilter_event {
+	/* NF_PROBE_* */
+	u8 kind;
+	/* NFPROTO_* */
+	u8 pf;
+	/* NF_INET_*, or family specific hook number */
+	u8 hook;
+	/* NF_PROBE_HOOK: index of the first hook entry to run, and number of
+	 * entries registered on the hook.
+	 */
+	u16 index;
+	u16 entries;
+	/* NF_PROBE_XT, NF_PROBE_NFT: priority of the table or base chain */
+	s32 priority;
+	/* NF_PROBE_XT, NF_PROBE_NFT: table name */
+	char table[NF_NAME_LEN];
+	/* NF_PROBE_NFT: chain name */
+	char chain[NF_NAME_LEN];
+} __attribute__((packed));
+
+/* nf_tables is a module and its types are not part of vmlinux.h; only the
+ * fields we use are described, for CO-RE to relocate them.
+ */
+struct nft_table___nf {
+	char *name;
+} __attribute__((preserve_access_index));
+
+struct nft_chain___nf {
+	struct nft_table___nf *table;
+	char *name;
+} __attribute__((preserve_access_index));
+
+struct nft_base_chain___nf {
+	struct nf_hook_ops ops;
+	struct nft_chain___nf chain;
+} __attribute__((preserve_access_index));
+
+/* Account for a netfilter event of a packet and return true if it can be
+ * reported.
+ */
+static __always_inline bool nf_account(struct trace_context *ctx,
+				       struct sk_buff *skb)
+{
+	struct netfilter_traversals *t, new = {};
+	u64 head;
+
+	head = (u64)BPF_CORE_READ(skb, head);
+	if (!head)
+		return true;
+
+	t = bpf_map_lookup_elem(&netfilter_traversals_map, &head);
+	if (!t) {
+		new.last_seen = ctx->timestamp;
+		new.count = 1;
+		bpf_map_update_elem(&netfilter_traversals_map, &head, &new,
+				    BPF_ANY);
+		return true;
+	}
+
+	if (ctx->timestamp - t->last_seen > NF_TRAVERSALS_RESET_NS)
+		t->count = 0;
+	t->last_seen = ctx->timestamp;
+
+	return ++t->count <= NF_TRAVERSALS_MAX;
+}
+
+DEFINE_HOOK(
+	struct nft_base_chain___nf *basechain;
+	struct nft_chain___nf *chain;
+	struct netfilter_config *cfg;
+	struct nf_hook_entries *entries;
+	struct nf_hook_state *state;
+	struct netfilter_event *e;
+	struct xt_table *table;
+	u64 ksym = ctx->ksym;
+	struct sk_buff *skb;
+	s8 skb_off;
+
+	cfg = bpf_map_lookup_elem(&netfilter_config_map, &ksym);
+	if (!cfg)
+		return 0;
+
+	skb = trace_get_sk_buff(ctx);
+	if (!skb)
+		return 0;
+
+	if (!nf_account(ctx, skb))
+		return HOOK_DISCARD;
+
+	/* The hook state always follows the skb:
+	 * - nf_hook_slow(skb, state, entries, index)
+	 * - ipt_do_table(priv, skb, state), since v5.19, or
+	 *   ipt_do_table(skb, state, table) before.
+	 * - nft_do_chain_*(priv, skb, state)
+	 */
+	skb_off = ctx->offsets.sk_buff;
+	state = trace_get_param(ctx, skb_off + 1, struct nf_hook_state *);
+	if (!state)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_NETFILTER, 1, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->kind = cfg->kind;
+	e->pf = BPF_CORE_READ(state, pf);
+	e->hook = BPF_CORE_READ(state, hook);
+
+	switch (cfg->kind) {
+	case NF_PROBE_HOOK:
+		entries = trace_get_param(ctx, skb_off + 2,
+					  struct nf_hook_entries *);
+		if (entries)
+			e->entries = BPF_CORE_READ(entries, num_hook_entries);
+		e->index = trace_get_param(ctx, skb_off + 3, u32);
+		break;
+	case NF_PROBE_XT:
+		table = skb_off ?
+			trace_get_param(ctx, 0, struct xt_table *) :
+			trace_get_param(ctx, 2, struct xt_table *);
+		if (!table)
+			break;
+		e->priority = BPF_CORE_READ(table, priority);
+		bpf_core_read_str(e->table, sizeof(e->table), &table->name);
+		break;
+	case NF_PROBE_NFT:
+		/* The hook private data is the chain (see
+		 * nft_basechain_hook_init), embedded in its base chain.
+		 */
+		chain = trace_get_param(ctx, 0, struct nft_chain___nf *);
+		if (!chain)
+			break;
+		basechain = (void *)chain -
+			    bpf_core_field_offset(struct nft_base_chain___nf,
+						  chain);
+		e->priority = BPF_CORE_READ(basechain, ops.priority);
+		bpf_probe_read_kernel_str(e->table, sizeof(e->table),
+					  BPF_CORE_READ(chain, table, name));
+		bpf_probe_read_kernel_str(e->chain, sizeof(e->chain),
+					  BPF_CORE_READ(chain, name));
+		break;
+	}
+
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
@@ -0,0 +1,31 @@
+//! # NetfilterCollector
+//!
+//! Report the netfilter hooks, tables and chains packets traverse, to answer
+//! questions like "which table dropped my packet". Events are correlated with
+//! other events of the same packet by the skb-tracking collector, and show up
+//! in the packet paths (see --path) as netfilter steps.
+//!
+//! Three kinds of events are reported, all carrying the protocol family and
+//! hook (e.g. ipv4 FORWARD):
+//! - `hook`: the packet enters a netfilter hook (nf_hook_slow), with the number
+//!   of functions registered on it.
+//! - `iptables`: the packet goes through an iptables (x_tables) table, with
+//!   its name and priority, e.g. filter/FORWARD.
+//! - `nftables`: the packet goes through an nftables base chain, with its
+//!   table, name and priority.
+//!
+//! Verdicts are not reported as such, as this requires return value probes
+//! which are not supported yet; packets dropped by netfilter are reported by
+//! the skb-tracking collector drop reason (NETFILTER_DROP) right after the
+//! table or chain which dropped them. The number of netfilter events reported
+//! per packet is bounded (64, until the packet is not seen for a second), to
+//! limit the cost of packets looping or going through many hooks.
+
+// Re-export netfilter.rs
+#[allow(clippy::module_inception)]
+pub(super) mod netfilter;
+pub(super) use netfilter::*;
+
+mod netfilter_hook {
+    include!("bpf/.out/netfilter_hook.rs");
+}
@@ -0,0 +1,323 @@
+use std::mem;
+
+use anyhow::{bail, Result};
+use log::warn;
+use plain::Plain;
+
+use super::netfilter_hook;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            EventField,
+        },
+        kernel_config::Requirements,
+        probe::kernel::{self, Hook, ProbeType},
+    },
+};
+
+const NETFILTER_COLLECTOR: &str = "netfilter";
+
+// Probe kinds. Please keep in sync with its BPF counterpart in
+// bpf/netfilter_hook.bpf.c
+const NF_PROBE_HOOK: u8 = 1;
+const NF_PROBE_XT: u8 = 2;
+const NF_PROBE_NFT: u8 = 3;
+
+// Probed functions and their kind. Tables and chains not available on the
+// running kernel (e.g. module not loaded) are skipped.
+const NETFILTER_TARGETS: &[(&str, u8)] = &[
+    ("nf_hook_slow", NF_PROBE_HOOK),
+    ("ipt_do_table", NF_PROBE_XT),
+    ("ip6t_do_table", NF_PROBE_XT),
+    ("nft_do_chain_ipv4", NF_PROBE_NFT),
+    ("nft_do_chain_ipv6", NF_PROBE_NFT),
+    ("nft_do_chain_inet", NF_PROBE_NFT),
+];
+
+// Protocol families. Not part of vmlinux.h, see
+// include/uapi/linux/netfilter.h
+const NFPROTO_INET: u8 = 1;
+const NFPROTO_IPV4: u8 = 2;
+const NFPROTO_ARP: u8 = 3;
+const NFPROTO_NETDEV: u8 = 5;
+const NFPROTO_BRIDGE: u8 = 7;
+const NFPROTO_IPV6: u8 = 10;
+
+// Netfilter event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct NetfilterEvent {
+    kind: u8,
+    pf: u8,
+    hook: u8,
+    index: u16,
+    entries: u16,
+    priority: i32,
+    table: [u8; 32],
+    chain: [u8; 32],
+}
+unsafe impl Plain for NetfilterEvent {}
+
+// Please keep in sync with its BPF counterpart in bpf/netfilter_hook.bpf.c
+#[repr(C)]
+struct NetfilterConfig {
+    kind: u8,
+}
+unsafe impl Plain for NetfilterConfig {}
+
+pub(in crate::collector) struct NetfilterCollector {}
+
+impl Collector for NetfilterCollector {
+    fn new() -> Result<NetfilterCollector> {
+        Ok(NetfilterCollector {})
+    }
+
+    fn name(&self) -> &'static str {
+        NETFILTER_COLLECTOR
+    }
+
+    fn requirements(&self) -> Requirements {
+        Requirements {
+            config: &["CONFIG_NETFILTER"],
+            ..Default::default()
+        }
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module_noargs(NETFILTER_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        _: &CliConfig,
+        kernel: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        self.check(kernel)?;
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorNetfilter,
+            Box::new(|raw_section, fields| {
+                if raw_section.header.data_type != 1 {
+                    bail!("Unknown data type");
+                }
+                unmarshal_netfilter(raw_section, fields)
+            }),
+        )?;
+
+        let mut config_map = Self::netfilter_config_map()?;
+        let hook = Hook::from(netfilter_hook::DATA)
+            .reuse_map("netfilter_config_map", config_map.fd())?
+            .to_owned();
+
+        for (target, kind) in NETFILTER_TARGETS.iter() {
+            let ksym = match kernel.inspect.get_ksym(&ProbeType::Kprobe, target) {
+                Ok(ksym) => ksym,
+                Err(e) => {
+                    warn!("Can't probe {}: {}", target, e);
+                    continue;
+                }
+            };
+
+            let cfg = NetfilterConfig { kind: *kind };
+            let cfg = unsafe { plain::as_bytes(&cfg) };
+            config_map.update(&ksym.to_ne_bytes(), cfg, libbpf_rs::MapFlags::NO_EXIST)?;
+
+            kernel.register_hook_to(hook.clone(), ProbeType::Kprobe, target)?;
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn check(&self, kernel: &kernel::Kernel) -> Result<()> {
+        if !NETFILTER_TARGETS
+            .iter()
+            .any(|(target, _)| kernel.inspect.get_ksym(&ProbeType::Kprobe, target).is_ok())
+        {
+            bail!("No netfilter probe point can be probed on this kernel");
+        }
+        Ok(())
+    }
+}
+
+impl NetfilterCollector {
+    fn netfilter_config_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in
+        // bpf/netfilter_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("netfilter_config_map"),
+            mem::size_of::<u64>() as u32,
+            mem::size_of::<NetfilterConfig>() as u32,
+            kernel::PROBE_MAX as u32,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the netfilter config map: {}", e))
+    }
+}
+
+/// Name of a protocol family.
+fn family_name(pf: u8) -> String {
+    match pf {
+        NFPROTO_INET => "inet".to_string(),
+        NFPROTO_IPV4 => "ipv4".to_string(),
+        NFPROTO_ARP => "arp".to_string(),
+        NFPROTO_NETDEV => "netdev".to_string(),
+        NFPROTO_BRIDGE => "bridge".to_string(),
+        NFPROTO_IPV6 => "ipv6".to_string(),
+        x => x.to_string(),
+    }
+}
+
+/// Name of a hook, which depends on the protocol family. See
+/// include/uapi/linux/netfilter.h and include/uapi/linux/netfilter_arp.h
+fn hook_name(pf: u8, hook: u8) -> String {
+    let name = match (pf, hook) {
+        (NFPROTO_NETDEV, 0) => "INGRESS",
+        (NFPROTO_NETDEV, 1) => "EGRESS",
+        (NFPROTO_ARP, 0) => "IN",
+        (NFPROTO_ARP, 1) => "OUT",
+        (NFPROTO_ARP, 2) => "FORWARD",
+        (NFPROTO_NETDEV | NFPROTO_ARP, x) => return x.to_string(),
+        (_, 0) => "PREROUTING",
+        (_, 1) => "INPUT",
+        (_, 2) => "FORWARD",
+        (_, 3) => "OUTPUT",
+        (_, 4) => "POSTROUTING",
+        (_, 5) => "INGRESS",
+        (_, x) => return x.to_string(),
+    };
+    name.to_string()
+}
+
+/// Convert a NUL terminated name to a String.
+fn c_name(raw: &[u8]) -> String {
+    let len = raw.iter().position(|c| *c == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..len]).to_string()
+}
+
+fn unmarshal_netfilter(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<NetfilterEvent>(raw_section)?;
+
+    let kind = match event.kind {
+        NF_PROBE_HOOK => "hook",
+        NF_PROBE_XT => "iptables",
+        NF_PROBE_NFT => "nftables",
+        x => bail!("Unknown netfilter probe kind {}", x),
+    };
+
+    fields.push(event_field!("kind", kind.to_string()));
+    fields.push(event_field!("family", family_name(event.pf)));
+    fields.push(event_field!("hook", hook_name(event.pf, event.hook)));
+
+    match event.kind {
+        NF_PROBE_HOOK => {
+            fields.push(event_field!("index", event.index as u32));
+            fields.push(event_field!("entries", event.entries as u32));
+        }
+        _ => {
+            fields.push(event_field!("table", c_name(&event.table)));
+            // iptables tables use built-in chains, named after their hook.
+            let chain = match event.kind {
+                NF_PROBE_NFT => c_name(&event.chain),
+                _ => hook_name(event.pf, event.hook),
+            };
+            fields.push(event_field!("chain", chain));
+            fields.push(event_field!("priority", event.priority));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::{bpf::unmarshal_section, Event};
+
+    fn unmarshal(event: &NetfilterEvent) -> Result<Event> {
+        unmarshal_section(NETFILTER_COLLECTOR, 1, event, unmarshal_netfilter)
+    }
+
+    fn name(name: &str) -> [u8; 32] {
+        let mut raw = [0; 32];
+        raw[..name.len()].copy_from_slice(name.as_bytes());
+        raw
+    }
+
+    #[test]
+    fn unmarshal_events() -> Result<()> {
+        let event = unmarshal(&NetfilterEvent {
+            kind: NF_PROBE_XT,
+            pf: NFPROTO_IPV4,
+            hook: 2,
+            priority: 0,
+            table: name("filter"),
+            ..Default::default()
+        })?;
+        let get = |key| event.get::<String>(NETFILTER_COLLECTOR, key);
+        assert!(get("kind")? == Some(&"iptables".to_string()));
+        assert!(get("family")? == Some(&"ipv4".to_string()));
+        assert!(get("table")? == Some(&"filter".to_string()));
+        assert!(get("chain")? == Some(&"FORWARD".to_string()));
+        assert!(event.get::<i32>(NETFILTER_COLLECTOR, "priority")? == Some(&0));
+
+        let event = unmarshal(&NetfilterEvent {
+            kind: NF_PROBE_NFT,
+            pf: NFPROTO_INET,
+            hook: 0,
+            priority: -150,
+            table: name("firewalld"),
+            chain: name("mangle_PREROUTING"),
+            ..Default::default()
+        })?;
+        let get = |key| event.get::<String>(NETFILTER_COLLECTOR, key);
+        assert!(get("kind")? == Some(&"nftables".to_string()));
+        assert!(get("hook")? == Some(&"PREROUTING".to_string()));
+        assert!(get("chain")? == Some(&"mangle_PREROUTING".to_string()));
+        assert!(event.get::<i32>(NETFILTER_COLLECTOR, "priority")? == Some(&-150));
+
+        let event = unmarshal(&NetfilterEvent {
+            kind: NF_PROBE_HOOK,
+            pf: NFPROTO_NETDEV,
+            hook: 1,
+            index: 1,
+            entries: 3,
+            ..Default::default()
+        })?;
+        assert!(event.get::<String>(NETFILTER_COLLECTOR, "hook")? == Some(&"EGRESS".to_string()));
+        assert!(event.get::<u32>(NETFILTER_COLLECTOR, "entries")? == Some(&3));
+        assert!(event.get::<String>(NETFILTER_COLLECTOR, "table")?.is_none());
+
+        assert!(unmarshal(&NetfilterEvent {
+            kind: 42,
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn names() {
+        assert!(hook_name(NFPROTO_IPV6, 4) == "POSTROUTING");
+        assert!(hook_name(NFPROTO_ARP, 2) == "FORWARD");
+        assert!(hook_name(NFPROTO_ARP, 3) == "3");
+        assert!(hook_name(NFPROTO_BRIDGE, 9) == "9");
+        assert!(family_name(42) == "42");
+        assert!(c_name(&name("nat")) == "nat");
+        assert!(c_name(b"raw") == "raw");
+    }
+}
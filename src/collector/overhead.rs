@@ -0,0 +1,174 @@
+//! # Overhead estimation
+//!
+//! Estimates the CPU cost of a collection before running it, from the probe
+//! plan of the initialized collectors and the rate of their probes. The rate
+//! is measured by attaching the probes in counting mode for a short sampling
+//! window: probes only count their hits, without building events nor running
+//! hooks. The cost is then derived from a per-hit cost model, which is a rough
+//! approximation of the work done by the probes, their hooks and the events
+//! processing in userspace.
+//!
+//! As filtering hooks do not run in counting mode the estimate is an upper
+//! bound: events filtered in the kernel (e.g. using --flow) cost less.
+//!
+//! The same measure is used by the adaptive mode (see --adaptive), to decide
+//! whether probes can be enabled.
+
+use std::time::Duration;
+
+/// Cost of a probe hit (probe entry, context and configuration retrieval), in
+/// nanoseconds.
+const PROBE_COST_NS: f64 = 150.0;
+/// Cost of building and sending an event, in nanoseconds.
+const EVENT_COST_NS: f64 = 300.0;
+/// Cost of running a hook, in nanoseconds.
+const HOOK_COST_NS: f64 = 100.0;
+/// Cost of retrieving, unmarshaling and writing an event in userspace, in
+/// nanoseconds.
+const USER_COST_NS: f64 = 2000.0;
+
+/// Estimated overhead of a collector.
+pub(super) struct Estimate {
+    pub(super) name: String,
+    /// Probe hits per second the collector is accounted for.
+    pub(super) rate: f64,
+    /// Estimated CPU time used per second, in nanoseconds.
+    pub(super) cost: f64,
+    /// Whether the collector uses probes at all; others can't be estimated.
+    pub(super) probed: bool,
+}
+
+/// Estimate the overhead of each collector of a probe plan, see
+/// Group::probe_plan(). `probes` are the probes and their number of targeted
+/// hooks (see Kernel::probes()) and `hits` their number of hits during
+/// `window`. Probes shared between collectors are accounted to the first one
+/// requesting them; generic hooks to the collector registering them.
+pub(super) fn estimate(
+    plan: &[(String, Vec<String>, bool)],
+    probes: &[(String, usize)],
+    hits: &[(String, u64)],
+    window: Duration,
+) -> Vec<Estimate> {
+    let secs = window.as_secs_f64().max(f64::EPSILON);
+    let rate = |probe: &str| {
+        hits.iter()
+            .find(|(p, _)| p == probe)
+            .map_or(0.0, |(_, hits)| *hits as f64 / secs)
+    };
+    let hooks = |probe: &str| {
+        probes
+            .iter()
+            .find(|(p, _)| p == probe)
+            .map_or(0, |(_, hooks)| *hooks)
+    };
+    let total_rate = total_rate(hits, window);
+
+    plan.iter()
+        .map(|(name, probes, generic)| {
+            let mut estimate = Estimate {
+                name: name.clone(),
+                rate: 0.0,
+                cost: 0.0,
+                probed: !probes.is_empty() || *generic,
+            };
+
+            for probe in probes.iter() {
+                let rate = rate(probe);
+                estimate.rate += rate;
+                estimate.cost += rate
+                    * (PROBE_COST_NS
+                        + EVENT_COST_NS
+                        + USER_COST_NS
+                        + hooks(probe) as f64 * HOOK_COST_NS);
+            }
+            if *generic {
+                estimate.cost += total_rate * HOOK_COST_NS;
+            }
+            estimate
+        })
+        .collect()
+}
+
+/// Total rate of the probes, in hits per second, given their number of hits
+/// during `window`.
+pub(super) fn total_rate(hits: &[(String, u64)], window: Duration) -> f64 {
+    let secs = window.as_secs_f64().max(f64::EPSILON);
+    hits.iter().map(|(_, hits)| *hits as f64 / secs).sum()
+}
+
+/// Format the estimates, per collector and in total, as a share of a CPU.
+pub(super) fn report(estimates: &[Estimate], window: Duration) -> String {
+    let cpu = |cost: f64| cost / 1e9 * 100.0;
+    let mut report = format!(
+        "Estimated overhead (probes sampled for {}s, upper bound):\n",
+        window.as_secs()
+    );
+
+    for estimate in estimates.iter() {
+        match estimate.probed {
+            true => report.push_str(&format!(
+                "  {}: {:.2}% CPU ({:.0} probe hits/s)\n",
+                estimate.name,
+                cpu(estimate.cost),
+                estimate.rate
+            )),
+            false => report.push_str(&format!(
+                "  {}: not estimated (does not use probes)\n",
+                estimate.name
+            )),
+        }
+    }
+
+    report.push_str(&format!(
+        "  total: {:.2}% CPU\n",
+        cpu(estimates.iter().map(|e| e.cost).sum())
+    ));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate() {
+        let plan = vec![
+            (
+                "skb-tracking".to_string(),
+                vec!["raw_tracepoint:skb:kfree_skb".to_string()],
+                true,
+            ),
+            (
+                "ovs".to_string(),
+                vec!["kprobe:ovs_dp_upcall".to_string()],
+                false,
+            ),
+            ("cgroup".to_string(), Vec::new(), false),
+        ];
+        let probes = vec![
+            ("kprobe:ovs_dp_upcall".to_string(), 2),
+            ("raw_tracepoint:skb:kfree_skb".to_string(), 0),
+        ];
+        let hits = vec![
+            ("kprobe:ovs_dp_upcall".to_string(), 1000),
+            ("raw_tracepoint:skb:kfree_skb".to_string(), 3000),
+        ];
+
+        let estimates = super::estimate(&plan, &probes, &hits, Duration::from_secs(2));
+        assert!(estimates.len() == 3);
+
+        // The generic hook runs on all probes.
+        let base = PROBE_COST_NS + EVENT_COST_NS + USER_COST_NS;
+        assert!(estimates[0].rate == 1500.0);
+        assert!(estimates[0].cost == 1500.0 * base + 2000.0 * HOOK_COST_NS);
+        assert!(estimates[1].rate == 500.0);
+        assert!(estimates[1].cost == 500.0 * (base + 2.0 * HOOK_COST_NS));
+        assert!(!estimates[2].probed && estimates[2].cost == 0.0);
+        assert!(total_rate(&hits, Duration::from_secs(2)) == 2000.0);
+
+        let report = report(&estimates, Duration::from_secs(2));
+        assert!(report.contains("  ovs: 0.13% CPU (500 probe hits/s)\n"));
+        assert!(report.contains("  cgroup: not estimated"));
+        assert!(report.ends_with("  total: 0.52% CPU\n"));
+    }
+}
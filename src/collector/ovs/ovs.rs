@@ -2,7 +2,7 @@ use anyhow::Result;
 
 use crate::{
     cli::{dynamic::DynamicCommand, CliConfig},
-    collector::Collector,
+    collector::{Collector, InitDeadline},
     core::{events::bpf::BpfEvents, probe::kernel},
 };
 
@@ -28,6 +28,7 @@ impl Collector for OvsCollector {
         _: &CliConfig,
         _kernel: &mut kernel::Kernel,
         _events: &mut BpfEvents,
+        _: &InitDeadline,
     ) -> Result<()> {
         Ok(())
     }
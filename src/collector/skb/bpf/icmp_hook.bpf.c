@@ -0,0 +1,269 @@
+#include <vmlinux.h>
+#include <bpf/bpf_core_read.h>
+#include <bpf/bpf_endian.h>
+
+#include <common.h>
+
+/* Skb section data types, please keep in sync with their Rust counterparts in
+ * collector::skb and with the ones in skb_hook.bpf.c.
+ */
+#define SECTION_MTU	6
+#define SECTION_ICMP	7
+
+/* Not part of vmlinux.h, see include/uapi/linux/icmp.h and icmpv6.h */
+#define ICMP_DEST_UNREACH	3
+#define ICMP_FRAG_NEEDED	4
+#define ICMPV6_PKT_TOOBIG	2
+
+/* Not part of vmlinux.h, see include/uapi/linux/in.h */
+#define IPPROTO_TCP	6
+#define IPPROTO_UDP	17
+
+/* IPv6 extension headers, see include/uapi/linux/in6.h */
+#define NEXTHDR_HOP	0
+#define NEXTHDR_ROUTING	43
+#define NEXTHDR_FRAGMENT	44
+#define NEXTHDR_AUTH	51
+#define NEXTHDR_DEST	60
+
+/* Maximum number of IPv6 extension headers followed. This bounds the parsing
+ * of crafted or corrupted packets, and keeps the verifier happy.
+ */
+#define IPV6_EXT_MAX	8
+
+/* Parsing flags. Please keep in sync with their Rust counterparts in
+ * collector::skb.
+ */
+#define PARSE_TRUNCATED	(1 << 0)
+#define PARSE_ERROR	(1 << 1)
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+#define ICMP_FAMILY_IPV4	1
+#define ICMP_FAMILY_IPV6	2
+
+/* ICMP configuration, indexed in the icmp_config_map by the function ksym
+ * address.
+ *
+ * Please keep in sync with its Rust counterpart in collector::skb.
+ */
+struct icmp_config {
+	/* Family of the ICMP function being probed */
+	u8 family;
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, struct icmp_config);
+} icmp_config_map SEC(".maps");
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_mtu_event {
+	u32 mtu;
+	u32 dev_mtu;
+} __attribute__((packed));
+
+/* Generated ICMP message and tuple of the packet which triggered it. Addresses
+ * are in network order, IPv4 ones only using the first 4 bytes. Ports are only
+ * set for TCP and UDP.
+ *
+ * Please keep in sync with its Rust counterpart in collector::skb.
+ */
+struct skb_icmp_event {
+	u8 family;
+	u8 type;
+	u8 code;
+	u8 protocol;
+	u8 saddr[16];
+	u8 daddr[16];
+	u16 sport;
+	u16 dport;
+	/* PARSE_* flags, set when the tuple could not be fully parsed */
+	u8 parse_flags;
+} __attribute__((packed));
+
+/* Common start of IPv6 extension headers */
+struct ipv6_ext_hdr {
+	u8 nexthdr;
+	u8 hdrlen;
+};
+
+static __always_inline bool ipv6_is_ext(u8 nexthdr)
+{
+	switch (nexthdr) {
+	case NEXTHDR_HOP:
+	case NEXTHDR_ROUTING:
+	case NEXTHDR_FRAGMENT:
+	case NEXTHDR_AUTH:
+	case NEXTHDR_DEST:
+		return true;
+	default:
+		return false;
+	}
+}
+
+/* Read the ports of a TCP or UDP header. Both start with the source and
+ * destination ports.
+ */
+static __always_inline void read_ports(struct skb_icmp_event *e, void *l4)
+{
+	struct udphdr udp;
+
+	if (e->protocol != IPPROTO_TCP && e->protocol != IPPROTO_UDP)
+		return;
+
+	if (bpf_probe_read_kernel(&udp, sizeof(udp), l4))
+		return;
+
+	e->sport = bpf_ntohs(udp.source);
+	e->dport = bpf_ntohs(udp.dest);
+}
+
+/* Fill the tuple of the packet which triggered the ICMP message. */
+static __always_inline void process_tuple(struct skb_icmp_event *e,
+					  struct sk_buff *skb)
+{
+	unsigned char *head = BPF_CORE_READ(skb, head);
+	u16 nh = BPF_CORE_READ(skb, network_header);
+	struct ipv6_ext_hdr ext;
+	struct ipv6hdr ip6;
+	struct iphdr ip;
+	u32 off;
+	int i;
+
+	if (!head)
+		return;
+
+	switch (e->family) {
+	case ICMP_FAMILY_IPV4:
+		if (bpf_probe_read_kernel(&ip, sizeof(ip), head + nh)) {
+			e->parse_flags |= PARSE_ERROR;
+			return;
+		}
+
+		__builtin_memcpy(e->saddr, &ip.saddr, 4);
+		__builtin_memcpy(e->daddr, &ip.daddr, 4);
+		e->protocol = ip.protocol;
+
+		/* The header length can't be shorter than the fixed header. */
+		if (ip.ihl < 5) {
+			e->parse_flags |= PARSE_ERROR;
+			return;
+		}
+		read_ports(e, head + nh + ip.ihl * 4);
+		break;
+	case ICMP_FAMILY_IPV6:
+		if (bpf_probe_read_kernel(&ip6, sizeof(ip6), head + nh)) {
+			e->parse_flags |= PARSE_ERROR;
+			return;
+		}
+
+		__builtin_memcpy(e->saddr, &ip6.saddr, 16);
+		__builtin_memcpy(e->daddr, &ip6.daddr, 16);
+		e->protocol = ip6.nexthdr;
+
+		/* Follow the extension headers, up to IPV6_EXT_MAX of them. */
+		off = nh + sizeof(ip6);
+		for (i = 0; i < IPV6_EXT_MAX; i++) {
+			if (!ipv6_is_ext(e->protocol))
+				break;
+
+			if (bpf_probe_read_kernel(&ext, sizeof(ext), head + off)) {
+				e->parse_flags |= PARSE_ERROR;
+				return;
+			}
+
+			switch (e->protocol) {
+			case NEXTHDR_FRAGMENT:
+				off += 8;
+				break;
+			case NEXTHDR_AUTH:
+				off += (ext.hdrlen + 2) << 2;
+				break;
+			default:
+				off += (ext.hdrlen + 1) << 3;
+				break;
+			}
+			e->protocol = ext.nexthdr;
+		}
+
+		/* Too many extension headers, the L4 protocol is unknown. */
+		if (ipv6_is_ext(e->protocol)) {
+			e->parse_flags |= PARSE_TRUNCATED;
+			return;
+		}
+		read_ports(e, head + off);
+		break;
+	}
+}
+
+/* Both probed functions share the same first parameters:
+ *
+ * void __icmp_send(struct sk_buff *skb_in, int type, int code, __be32 info,
+ *                  const struct ip_options *opt)
+ * void icmp6_send(struct sk_buff *skb, u8 type, u8 code, __u32 info,
+ *                 const struct in6_addr *force_saddr,
+ *                 const struct inet6_skb_parm *parm)
+ */
+DEFINE_HOOK(
+	struct skb_icmp_event *icmp;
+	struct skb_mtu_event *e;
+	struct icmp_config *cfg;
+	u64 ksym = ctx->ksym;
+	struct net_device *dev;
+	struct sk_buff *skb;
+	int type, code;
+	u32 mtu;
+
+	cfg = bpf_map_lookup_elem(&icmp_config_map, &ksym);
+	if (!cfg)
+		return 0;
+
+	type = trace_get_param(ctx, 1, int);
+	code = trace_get_param(ctx, 2, int);
+	skb = trace_get_sk_buff(ctx);
+
+	icmp = get_event_section(event, COLLECTOR_SKB, SECTION_ICMP,
+				 sizeof(*icmp));
+	if (!icmp)
+		return 0;
+
+	__builtin_memset(icmp, 0, sizeof(*icmp));
+	icmp->family = cfg->family;
+	icmp->type = type;
+	icmp->code = code;
+	if (skb)
+		process_tuple(icmp, skb);
+
+	switch (cfg->family) {
+	case ICMP_FAMILY_IPV4:
+		if (type != ICMP_DEST_UNREACH || code != ICMP_FRAG_NEEDED)
+			return 0;
+		mtu = bpf_ntohl(trace_get_param(ctx, 3, u32));
+		break;
+	case ICMP_FAMILY_IPV6:
+		if ((u8)type != ICMPV6_PKT_TOOBIG)
+			return 0;
+		mtu = trace_get_param(ctx, 3, u32);
+		break;
+	default:
+		return 0;
+	}
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_MTU, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->mtu = mtu;
+	e->dev_mtu = 0;
+
+	if (skb) {
+		dev = BPF_CORE_READ(skb, dev);
+		if (dev)
+			e->dev_mtu = BPF_CORE_READ(dev, mtu);
+	}
+
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
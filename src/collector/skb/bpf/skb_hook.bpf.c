@@ -0,0 +1,1368 @@
+#include <vmlinux.h>
+#include <bpf/bpf_core_read.h>
+#include <bpf/bpf_endian.h>
+
+#include <common.h>
+
+/* Skb section data types. Please keep in sync with its Rust counterpart in
+ * collector::skb.
+ */
+#define SECTION_GSO	1
+#define SECTION_SOCK	2
+#define SECTION_LEN	3
+#define SECTION_MARK	4
+#define SECTION_CT	5
+/* SECTION_MTU (6) and SECTION_ICMP (7) are used by icmp_hook.bpf.c */
+#define SECTION_PACKET	8
+#define SECTION_CSUM	9
+#define SECTION_L3	10
+#define SECTION_QUEUE	11
+#define SECTION_SECMARK	12
+#define SECTION_DEBUG	13
+#define SECTION_DEV	14
+
+/* Maximum number of packet bytes copied in events. Please keep in sync with
+ * its Rust counterpart in collector::skb.
+ */
+#define CAPTURE_MAX	256
+
+/* Maximum number of ethertypes and of L4 protocols to filter on. Please keep
+ * in sync with its Rust counterpart in collector::skb.
+ */
+#define FILTER_MAX	8
+
+/* Not part of vmlinux.h, see include/uapi/linux/if_ether.h */
+#define ETH_P_IP	0x0800
+#define ETH_P_IPV6	0x86DD
+
+/* Not part of vmlinux.h, see include/uapi/linux/in.h */
+#define IPPROTO_TCP	6
+#define IPPROTO_UDP	17
+#define IPPROTO_SCTP	132
+
+/* Flow filter flags. Please keep in sync with their Rust counterparts in
+ * collector::skb.
+ */
+#define FLOW_ENABLED	(1 << 0)
+#define FLOW_HAS_SADDR	(1 << 1)
+#define FLOW_HAS_DADDR	(1 << 2)
+
+/* Flow filter families. Please keep in sync with their Rust counterparts in
+ * collector::skb.
+ */
+#define FLOW_FAMILY_IPV4	4
+#define FLOW_FAMILY_IPV6	6
+
+/* Flow filter, see core::filters::flow. Packets match in both directions.
+ * Please keep in sync with its Rust counterpart in collector::skb.
+ */
+struct skb_flow {
+	u8 flags;
+	/* 0 if no address is given */
+	u8 family;
+	/* L4 protocol, 0 for any */
+	u8 proto;
+	u8 pad;
+	/* In network order, IPv4 addresses only use the first 4 bytes */
+	u8 saddr[16];
+	u8 daddr[16];
+	/* In host order, 0 for any */
+	u16 sport;
+	u16 dport;
+};
+
+/* Skb configuration. Please keep in sync with its Rust counterpart in
+ * collector::skb.
+ */
+struct skb_config {
+	/* Number of packet bytes to copy in events, 0 to disable it */
+	u32 capture_bytes;
+	/* Ethertypes to filter on, in host order */
+	u16 etypes[FILTER_MAX];
+	/* L4 protocols to filter on */
+	u8 protos[FILTER_MAX];
+	/* Number of valid entries in the above, 0 to disable the filter */
+	u8 n_etypes;
+	u8 n_protos;
+	struct skb_flow flow;
+	/* Verify the packets checksums */
+	u8 verify_csum;
+	/* Only report the first packets of each flow, 0 to disable it */
+	u32 flow_head;
+	/* Only report packets matching the match set, see
+	 * core::filters::match_set.
+	 */
+	u8 match_set;
+	/* Report raw skb fields, see process_skb_debug() */
+	u8 debug;
+	/* Only report packets attached to the interfaces found in
+	 * iface_filter_map, see core::filters::iface.
+	 */
+	u8 iface_filter;
+	/* Expensive sections to compute, as a mask of (1 << SECTION_x) */
+	u32 sections;
+	/* Inactivity after which a flow is considered new again, in seconds,
+	 * 0 to never expire flows. See flow_head.
+	 */
+	u32 flow_timeout;
+	/* Account for both directions of a flow together. See flow_head. */
+	u8 flow_bidir;
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_ARRAY);
+	__uint(max_entries, 1);
+	__type(key, u32);
+	__type(value, struct skb_config);
+} skb_config_map SEC(".maps");
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_gso_event {
+	u32 gso_size;
+	u32 gso_segs;
+} __attribute__((packed));
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_sock_event {
+	u32 rmem_alloc;
+	u32 rcvbuf;
+	/* Socket cookie, 0 if none was generated yet */
+	u64 cookie;
+} __attribute__((packed));
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_len_event {
+	u32 len;
+	u32 data_len;
+	u32 truesize;
+} __attribute__((packed));
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_mark_event {
+	u32 mark;
+} __attribute__((packed));
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_secmark_event {
+	u32 secmark;
+} __attribute__((packed));
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_dev_event {
+	u32 ifindex;
+} __attribute__((packed));
+
+/* Debug event flags. Please keep in sync with their Rust counterparts in
+ * collector::skb.
+ */
+#define DEBUG_CLONED	(1 << 0)
+#define DEBUG_NOHDR	(1 << 1)
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_debug_event {
+	/* Offsets from skb->head */
+	u32 data;
+	u32 tail;
+	u32 end;
+	/* Offsets from skb->head, ~0U (u16) when unset */
+	u16 mac_header;
+	u16 network_header;
+	u16 transport_header;
+	/* In host order */
+	u16 protocol;
+	/* PACKET_* */
+	u8 pkt_type;
+	u8 flags;
+} __attribute__((packed));
+
+/* Conntrack event fields availability. Please keep in sync with its Rust
+ * counterpart in collector::skb.
+ */
+#define CT_HAS_MARK	(1 << 0)
+#define CT_HAS_ZONE	(1 << 1)
+#define CT_HAS_STATUS	(1 << 2)
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_ct_event {
+	u32 mark;
+	u16 zone;
+	u8 flags;
+	/* enum ip_conntrack_info */
+	u8 state;
+	u32 status;
+} __attribute__((packed));
+
+/* Packet data, followed by capture_len bytes of the packet starting at its
+ * mac header (or network header, if the former isn't set).
+ *
+ * Please keep in sync with its Rust counterpart in collector::skb.
+ */
+struct skb_packet_event {
+	u32 len;
+	u32 capture_len;
+	u8 data[];
+} __attribute__((packed));
+
+/* Checksum verification statuses. Please keep in sync with their Rust
+ * counterparts in collector::skb.
+ */
+#define CSUM_UNVERIFIED	0
+#define CSUM_OK		1
+#define CSUM_BAD	2
+/* The checksum is computed by the device and is not in the packet yet */
+#define CSUM_OFFLOADED	3
+/* The packet has no checksum, e.g. UDP over IPv4 with a zero checksum */
+#define CSUM_ABSENT	4
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_csum_event {
+	/* skb->ip_summed: CHECKSUM_NONE, _UNNECESSARY, _COMPLETE or _PARTIAL */
+	u8 ip_summed;
+	/* IPv4 header checksum status */
+	u8 l3;
+	/* TCP or UDP checksum status */
+	u8 l4;
+	u8 l4_proto;
+} __attribute__((packed));
+
+/* Not part of vmlinux.h, see include/linux/skbuff.h */
+#define CHECKSUM_PARTIAL	3
+
+/* Not part of vmlinux.h, see include/net/ipv6.h */
+#define NEXTHDR_HOP		0
+#define NEXTHDR_ROUTING		43
+#define NEXTHDR_FRAGMENT	44
+#define NEXTHDR_AUTH		51
+#define NEXTHDR_DEST		60
+
+/* Maximum number of IPv6 extension headers walked. */
+#define IPV6_EXT_MAX	8
+
+/* L3 header flags. Please keep in sync with their Rust counterparts in
+ * collector::skb.
+ */
+#define L3_IP_OPTIONS		(1 << 0)
+#define L3_HBH			(1 << 1)
+#define L3_ROUTING		(1 << 2)
+#define L3_FRAGMENT		(1 << 3)
+/* The extension headers chain was longer than IPV6_EXT_MAX */
+#define L3_EXT_TRUNCATED	(1 << 4)
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_l3_event {
+	/* 4 or 6 */
+	u8 version;
+	u8 flags;
+} __attribute__((packed));
+
+/* Please keep in sync with its Rust counterpart in collector::skb. */
+struct skb_queue_event {
+	/* skb->hash, 0 if not computed */
+	u32 hash;
+	/* Rx queue + 1 (0 if not recorded) on receive, Tx queue on transmit */
+	u16 queue_mapping;
+	/* The hash is computed over the L4 tuple */
+	u8 l4_hash;
+	/* CPU processing the skb */
+	u32 cpu;
+} __attribute__((packed));
+
+/* Before Linux v3.14 the skb hash was stored in skb->rxhash and its L4 flag in
+ * skb->l4_rxhash.
+ */
+struct sk_buff___rxhash {
+	__u32 rxhash;
+	__u8 l4_rxhash:1;
+} __attribute__((preserve_access_index));
+
+/* Scratch buffer for computing L4 checksums, as it doesn't fit the stack. */
+struct csum_buf {
+	u8 data[CAPTURE_MAX];
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+	__uint(max_entries, 1);
+	__type(key, u32);
+	__type(value, struct csum_buf);
+} csum_buf_map SEC(".maps");
+
+/* Before Linux v4.11 the conntrack entry was stored in skb->nfct and its info
+ * in skb->nfctinfo.
+ */
+struct sk_buff___nfct {
+	struct nf_conntrack *nfct;
+	__u8 nfctinfo:3;
+} __attribute__((preserve_access_index));
+
+/* Not part of vmlinux.h, see include/linux/skbuff.h and
+ * include/uapi/linux/netfilter/nf_conntrack_common.h
+ */
+#define NFCT_INFOMASK	7UL
+#define NFCT_PTRMASK	~(7UL)
+#define IP_CT_UNTRACKED	7
+
+/* Retrieve the shared info of an skb. On 64-bit arches skb->end is an offset
+ * from skb->head.
+ */
+static __always_inline struct skb_shared_info *skb_shinfo(struct sk_buff *skb)
+{
+	unsigned char *head = BPF_CORE_READ(skb, head);
+
+	if (!head)
+		return NULL;
+
+	return (struct skb_shared_info *)(head + BPF_CORE_READ(skb, end));
+}
+
+static __always_inline int process_skb_gso(struct trace_raw_event *event,
+					   struct sk_buff *skb)
+{
+	struct skb_shared_info *si;
+	struct skb_gso_event *e;
+	u16 gso_size;
+
+	si = skb_shinfo(skb);
+	if (!si)
+		return 0;
+
+	/* Non-GSO skbs do not report the GSO section at all. */
+	gso_size = BPF_CORE_READ(si, gso_size);
+	if (!gso_size)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_GSO, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->gso_size = gso_size;
+	e->gso_segs = BPF_CORE_READ(si, gso_segs);
+
+	return 0;
+}
+
+/* Report the receive buffer usage of the socket associated with the skb, or
+ * of the socket being passed to the probed function when the skb is not (yet)
+ * owned by one (e.g. when being queued to a socket).
+ */
+static __always_inline int process_skb_sock(struct trace_context *ctx,
+					    struct trace_raw_event *event,
+					    struct sk_buff *skb)
+{
+	struct skb_sock_event *e;
+	struct sock *sk;
+
+	sk = BPF_CORE_READ(skb, sk);
+	if (!sk)
+		sk = trace_get_sock(ctx);
+	if (!sk)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_SOCK, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->rmem_alloc = BPF_CORE_READ(sk, sk_backlog.rmem_alloc.counter);
+	e->rcvbuf = BPF_CORE_READ(sk, sk_rcvbuf);
+	e->cookie = 0;
+	if (bpf_core_field_exists(sk->__sk_common.skc_cookie))
+		e->cookie = BPF_CORE_READ(sk, __sk_common.skc_cookie.counter);
+
+	return 0;
+}
+
+static __always_inline int process_skb_len(struct trace_raw_event *event,
+					   struct sk_buff *skb)
+{
+	struct skb_len_event *e;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_LEN, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->len = BPF_CORE_READ(skb, len);
+	e->data_len = BPF_CORE_READ(skb, data_len);
+	e->truesize = BPF_CORE_READ(skb, truesize);
+
+	return 0;
+}
+
+static __always_inline int process_skb_mark(struct trace_raw_event *event,
+					    struct sk_buff *skb)
+{
+	struct skb_mark_event *e;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_MARK, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->mark = BPF_CORE_READ(skb, mark);
+
+	return 0;
+}
+
+/* Report the security mark of packets, e.g. set by the SECMARK iptables target
+ * or by nftables secmark objects. Only available with CONFIG_NETWORK_SECMARK,
+ * unmarked packets (0) are not reported.
+ */
+static __always_inline int process_skb_secmark(struct trace_raw_event *event,
+					       struct sk_buff *skb)
+{
+	struct skb_secmark_event *e;
+	u32 secmark;
+
+	if (!bpf_core_field_exists(skb->secmark))
+		return 0;
+
+	secmark = BPF_CORE_READ(skb, secmark);
+	if (!secmark)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_SECMARK, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->secmark = secmark;
+	return 0;
+}
+
+/* Report the device the packet is attached to, if any. */
+static __always_inline int process_skb_dev(struct trace_raw_event *event,
+					   struct sk_buff *skb)
+{
+	struct skb_dev_event *e;
+	struct net_device *dev;
+	u32 ifindex;
+
+	dev = BPF_CORE_READ(skb, dev);
+	if (!dev)
+		return 0;
+
+	ifindex = BPF_CORE_READ(dev, ifindex);
+	if (!ifindex)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_DEV, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->ifindex = ifindex;
+	return 0;
+}
+
+/* Report raw skb fields, for debugging skb handling in the kernel itself. This
+ * is best effort: fields not available on the running kernel are reported as
+ * 0. On 64-bit arches skb->tail and skb->end are offsets from skb->head.
+ */
+static __always_inline int process_skb_debug(struct trace_raw_event *event,
+					     struct sk_buff *skb)
+{
+	struct skb_debug_event *e;
+	struct skb_config *cfg;
+	unsigned char *head;
+	u32 key = 0;
+
+	cfg = bpf_map_lookup_elem(&skb_config_map, &key);
+	if (!cfg || !cfg->debug)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_DEBUG, sizeof(*e));
+	if (!e)
+		return 0;
+
+	head = BPF_CORE_READ(skb, head);
+	e->data = head ? BPF_CORE_READ(skb, data) - head : 0;
+	e->tail = BPF_CORE_READ(skb, tail);
+	e->end = BPF_CORE_READ(skb, end);
+	e->mac_header = BPF_CORE_READ(skb, mac_header);
+	e->network_header = BPF_CORE_READ(skb, network_header);
+	e->transport_header = BPF_CORE_READ(skb, transport_header);
+	e->protocol = bpf_ntohs(BPF_CORE_READ(skb, protocol));
+
+	e->pkt_type = 0;
+	if (bpf_core_field_exists(skb->pkt_type))
+		e->pkt_type = BPF_CORE_READ_BITFIELD_PROBED(skb, pkt_type);
+
+	e->flags = 0;
+	if (bpf_core_field_exists(skb->cloned) &&
+	    BPF_CORE_READ_BITFIELD_PROBED(skb, cloned))
+		e->flags |= DEBUG_CLONED;
+	if (bpf_core_field_exists(skb->nohdr) &&
+	    BPF_CORE_READ_BITFIELD_PROBED(skb, nohdr))
+		e->flags |= DEBUG_NOHDR;
+
+	return 0;
+}
+
+static __always_inline int process_skb_queue(struct trace_raw_event *event,
+					     struct sk_buff *skb)
+{
+	struct sk_buff___rxhash *old_skb = (void *)skb;
+	struct skb_queue_event *e;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_QUEUE, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->queue_mapping = BPF_CORE_READ(skb, queue_mapping);
+	if (bpf_core_field_exists(skb->hash)) {
+		e->hash = BPF_CORE_READ(skb, hash);
+		e->l4_hash = BPF_CORE_READ_BITFIELD_PROBED(skb, l4_hash);
+	} else {
+		e->hash = BPF_CORE_READ(old_skb, rxhash);
+		e->l4_hash = BPF_CORE_READ_BITFIELD_PROBED(old_skb, l4_rxhash);
+	}
+	e->cpu = bpf_get_smp_processor_id();
+
+	return 0;
+}
+
+/* Report the conntrack mark and zone, when a conntrack entry is attached to the
+ * skb. The conntrack entry is only available if compiled in the kernel
+ * (CONFIG_NF_CONNTRACK), and the mark and zone also depend on
+ * CONFIG_NF_CONNTRACK_MARK and CONFIG_NF_CONNTRACK_ZONES. Fields not available
+ * on the running kernel are not reported.
+ */
+static __always_inline int process_skb_ct(struct trace_raw_event *event,
+					  struct sk_buff *skb)
+{
+	struct sk_buff___nfct *old_skb = (void *)skb;
+	struct skb_ct_event *e;
+	struct nf_conn *ct;
+	unsigned long nfct;
+	u8 state;
+
+	/* When conntrack is a module, its types are only known once it is
+	 * loaded. Without this guard the relocations below would fail and the
+	 * whole hook would be rejected.
+	 */
+	if (!bpf_core_type_exists(struct nf_conn))
+		return 0;
+
+	if (bpf_core_field_exists(skb->_nfct)) {
+		nfct = BPF_CORE_READ(skb, _nfct);
+		ct = (struct nf_conn *)(nfct & NFCT_PTRMASK);
+		state = nfct & NFCT_INFOMASK;
+	} else if (bpf_core_field_exists(old_skb->nfct)) {
+		ct = (struct nf_conn *)BPF_CORE_READ(old_skb, nfct);
+		state = BPF_CORE_READ_BITFIELD_PROBED(old_skb, nfctinfo);
+	} else {
+		return 0;
+	}
+	/* Untracked packets do not have a conntrack entry since v4.12 but are
+	 * still worth reporting.
+	 */
+	if (!ct && state != IP_CT_UNTRACKED)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_CT, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->flags = 0;
+	e->state = state;
+	e->mark = 0;
+	e->zone = 0;
+	e->status = 0;
+
+	if (!ct)
+		return 0;
+
+	e->status = BPF_CORE_READ(ct, status);
+	e->flags |= CT_HAS_STATUS;
+
+	if (bpf_core_field_exists(ct->mark)) {
+		e->mark = BPF_CORE_READ(ct, mark);
+		e->flags |= CT_HAS_MARK;
+	}
+
+	if (bpf_core_field_exists(ct->zone)) {
+		e->zone = BPF_CORE_READ(ct, zone.id);
+		e->flags |= CT_HAS_ZONE;
+	}
+
+	return 0;
+}
+
+/* Copy the first bytes of the packet. Only the linear part of the skb is
+ * copied.
+ */
+static __always_inline int process_skb_packet(struct trace_raw_event *event,
+					      struct sk_buff *skb)
+{
+	unsigned char *head = BPF_CORE_READ(skb, head);
+	struct skb_packet_event *e;
+	struct skb_config *cfg;
+	u16 mac, start;
+	u32 key = 0, n;
+	int linear;
+
+	cfg = bpf_map_lookup_elem(&skb_config_map, &key);
+	if (!cfg || !cfg->capture_bytes || !head)
+		return 0;
+
+	/* The mac header is unset when equal to ~0U (u16). */
+	mac = BPF_CORE_READ(skb, mac_header);
+	start = mac != (u16)~0U ? mac : BPF_CORE_READ(skb, network_header);
+
+	/* On 64-bit arches skb->tail is an offset from skb->head. */
+	linear = (int)BPF_CORE_READ(skb, tail) - start;
+	if (linear <= 0)
+		return 0;
+
+	n = cfg->capture_bytes < linear ? cfg->capture_bytes : linear;
+	if (n > CAPTURE_MAX)
+		n = CAPTURE_MAX;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_PACKET,
+			      sizeof(*e) + n);
+	if (!e)
+		return 0;
+
+	e->len = BPF_CORE_READ(skb, len);
+	e->capture_len = n;
+	/* Masking helps the verifier bound the size, n is already bounded. */
+	if (bpf_probe_read_kernel(e->data, n & (CAPTURE_MAX * 2 - 1),
+				  head + start))
+		e->capture_len = 0;
+
+	return 0;
+}
+
+static __always_inline u16 csum_fold(u32 sum)
+{
+	sum = (sum & 0xffff) + (sum >> 16);
+	sum = (sum & 0xffff) + (sum >> 16);
+	return (u16)sum;
+}
+
+/* Add len bytes of buf to a one's complement sum. Words are summed in memory
+ * order, which gives the right result once folded whatever the endianness.
+ */
+static __always_inline u32 csum_add(u32 sum, const u8 *buf, u32 len)
+{
+	u8 last[2] = {};
+	int i;
+
+	for (i = 0; i < CAPTURE_MAX / 2; i++) {
+		if (i * 2 + 1 >= len)
+			break;
+		sum += *(u16 *)(buf + i * 2);
+	}
+
+	if (len & 1) {
+		last[0] = buf[(len - 1) & (CAPTURE_MAX - 1)];
+		sum += *(u16 *)last;
+	}
+	return sum;
+}
+
+/* Verify the IPv4 header checksum, and the TCP/UDP one when the whole segment
+ * is in the linear part of the skb and fits in the capture length (the same
+ * bytes users would see). Checksums offloaded to the device (CHECKSUM_PARTIAL)
+ * are not in the packet yet and are only reported as such. IPv6 extension
+ * headers are not followed and IPv4 fragments are not verified.
+ */
+static __always_inline int process_skb_csum(struct trace_raw_event *event,
+					    struct sk_buff *skb)
+{
+	u32 sum = 0, key = 0, l3_len, l4_len, tail;
+	u8 l4_proto, hdr[60], ip_summed;
+	struct skb_csum_event *e;
+	struct skb_config *cfg;
+	unsigned char *head;
+	struct ipv6hdr ip6;
+	struct csum_buf *buf;
+	struct iphdr ip;
+	u16 etype, nh;
+	int i;
+
+	cfg = bpf_map_lookup_elem(&skb_config_map, &key);
+	if (!cfg || !cfg->verify_csum)
+		return 0;
+
+	head = BPF_CORE_READ(skb, head);
+	nh = BPF_CORE_READ(skb, network_header);
+	if (!head || nh == (u16)~0U)
+		return 0;
+
+	etype = bpf_ntohs(BPF_CORE_READ(skb, protocol));
+	if (etype != ETH_P_IP && etype != ETH_P_IPV6)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_CSUM, sizeof(*e));
+	if (!e)
+		return 0;
+
+	ip_summed = BPF_CORE_READ_BITFIELD_PROBED(skb, ip_summed);
+	e->ip_summed = ip_summed;
+	e->l3 = CSUM_UNVERIFIED;
+	e->l4 = CSUM_UNVERIFIED;
+	e->l4_proto = 0;
+
+	if (etype == ETH_P_IP) {
+		if (bpf_probe_read_kernel(&ip, sizeof(ip), head + nh) ||
+		    ip.ihl < 5)
+			return 0;
+
+		l3_len = ip.ihl * 4;
+		if (l3_len > sizeof(hdr) ||
+		    bpf_probe_read_kernel(hdr, l3_len, head + nh))
+			return 0;
+
+		for (i = 0; i < sizeof(hdr) / 2; i++) {
+			if (i * 2 >= l3_len)
+				break;
+			sum += *(u16 *)(hdr + i * 2);
+		}
+		e->l3 = csum_fold(sum) == 0xffff ? CSUM_OK : CSUM_BAD;
+
+		/* Fragments can't be verified without reassembly. */
+		if (bpf_ntohs(ip.frag_off) & 0x3fff)
+			return 0;
+
+		l4_proto = ip.protocol;
+		l4_len = bpf_ntohs(ip.tot_len) - l3_len;
+		sum = (ip.saddr & 0xffff) + (ip.saddr >> 16) +
+		      (ip.daddr & 0xffff) + (ip.daddr >> 16);
+	} else {
+		if (bpf_probe_read_kernel(&ip6, sizeof(ip6), head + nh))
+			return 0;
+
+		l3_len = sizeof(ip6);
+		l4_proto = ip6.nexthdr;
+		l4_len = bpf_ntohs(ip6.payload_len);
+		sum = 0;
+		for (i = 0; i < 8; i++) {
+			sum += ip6.saddr.in6_u.u6_addr16[i];
+			sum += ip6.daddr.in6_u.u6_addr16[i];
+		}
+	}
+
+	if (l4_proto != IPPROTO_TCP && l4_proto != IPPROTO_UDP)
+		return 0;
+	e->l4_proto = l4_proto;
+
+	if (ip_summed == CHECKSUM_PARTIAL) {
+		e->l4 = CSUM_OFFLOADED;
+		return 0;
+	}
+
+	/* On 64-bit arches skb->tail is an offset from skb->head. */
+	tail = BPF_CORE_READ(skb, tail);
+	if (!l4_len || l4_len > cfg->capture_bytes || l4_len > CAPTURE_MAX ||
+	    nh + l3_len + l4_len > tail)
+		return 0;
+
+	buf = bpf_map_lookup_elem(&csum_buf_map, &key);
+	if (!buf)
+		return 0;
+
+	/* Masking helps the verifier bound the size, l4_len is already bounded. */
+	if (bpf_probe_read_kernel(buf->data, l4_len & (CAPTURE_MAX * 2 - 1),
+				  head + nh + l3_len))
+		return 0;
+
+	/* UDP over IPv4 can omit its checksum. */
+	if (l4_proto == IPPROTO_UDP && etype == ETH_P_IP && l4_len >= 8 &&
+	    !buf->data[6] && !buf->data[7]) {
+		e->l4 = CSUM_ABSENT;
+		return 0;
+	}
+
+	/* Pseudo-header protocol and length, in network order. */
+	sum += bpf_htons(l4_proto) + bpf_htons(l4_len);
+	sum = csum_add(sum, buf->data, l4_len);
+	e->l4 = csum_fold(sum) == 0xffff ? CSUM_OK : CSUM_BAD;
+
+	return 0;
+}
+
+/* Report the presence of IPv4 options and of IPv6 extension headers. The IPv6
+ * extension headers chain is walked up to IPV6_EXT_MAX headers.
+ */
+static __always_inline int process_skb_l3(struct trace_raw_event *event,
+					  struct sk_buff *skb)
+{
+	struct ipv6_opt_hdr ext;
+	struct skb_l3_event *e;
+	unsigned char *head;
+	struct ipv6hdr ip6;
+	struct iphdr ip;
+	u8 flags = 0, nexthdr, version;
+	u16 etype, nh;
+	u32 off;
+	int i;
+
+	head = BPF_CORE_READ(skb, head);
+	nh = BPF_CORE_READ(skb, network_header);
+	if (!head || nh == (u16)~0U)
+		return 0;
+
+	etype = bpf_ntohs(BPF_CORE_READ(skb, protocol));
+	switch (etype) {
+	case ETH_P_IP:
+		if (bpf_probe_read_kernel(&ip, sizeof(ip), head + nh) ||
+		    ip.ihl < 5)
+			return 0;
+		version = 4;
+		if (ip.ihl > 5)
+			flags |= L3_IP_OPTIONS;
+		break;
+	case ETH_P_IPV6:
+		if (bpf_probe_read_kernel(&ip6, sizeof(ip6), head + nh))
+			return 0;
+		version = 6;
+		nexthdr = ip6.nexthdr;
+		off = nh + sizeof(ip6);
+
+		for (i = 0; i < IPV6_EXT_MAX; i++) {
+			switch (nexthdr) {
+			case NEXTHDR_HOP:
+				flags |= L3_HBH;
+				break;
+			case NEXTHDR_ROUTING:
+				flags |= L3_ROUTING;
+				break;
+			case NEXTHDR_FRAGMENT:
+				flags |= L3_FRAGMENT;
+				break;
+			case NEXTHDR_AUTH:
+			case NEXTHDR_DEST:
+				break;
+			default:
+				goto done;
+			}
+
+			if (bpf_probe_read_kernel(&ext, sizeof(ext), head + off))
+				goto done;
+
+			/* The fragment header has a fixed size, the AH length
+			 * is in 4-octet units and others' in 8-octet units, not
+			 * including the first 8 octets.
+			 */
+			if (nexthdr == NEXTHDR_FRAGMENT)
+				off += 8;
+			else if (nexthdr == NEXTHDR_AUTH)
+				off += (ext.hdrlen + 2) * 4;
+			else
+				off += (ext.hdrlen + 1) * 8;
+			nexthdr = ext.nexthdr;
+		}
+
+		switch (nexthdr) {
+		case NEXTHDR_HOP:
+		case NEXTHDR_ROUTING:
+		case NEXTHDR_FRAGMENT:
+		case NEXTHDR_AUTH:
+		case NEXTHDR_DEST:
+			flags |= L3_EXT_TRUNCATED;
+			break;
+		}
+		break;
+	default:
+		return 0;
+	}
+
+done:
+	e = get_event_section(event, COLLECTOR_SKB, SECTION_L3, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->version = version;
+	e->flags = flags;
+	return 0;
+}
+
+/* Check an expensive section should be computed, see collector::skb
+ * expensive_sections(). They all are when the configuration is unknown.
+ */
+static __always_inline bool skb_section(struct skb_config *cfg, u8 section)
+{
+	return !cfg || cfg->sections & (1 << section);
+}
+
+/* Check a packet matches the ethertype and L4 protocol filters, if any. Only
+ * IPv4 and IPv6 packets can match the L4 protocol filter, and IPv6 extension
+ * headers are not followed.
+ */
+static __always_inline bool skb_filter_match(struct skb_config *cfg,
+					     struct sk_buff *skb)
+{
+	unsigned char *head;
+	struct ipv6hdr ip6;
+	struct iphdr ip;
+	u16 etype, nh;
+	bool found;
+	u8 proto;
+	int i;
+
+	etype = bpf_ntohs(BPF_CORE_READ(skb, protocol));
+
+	if (cfg->n_etypes) {
+		found = false;
+		for (i = 0; i < FILTER_MAX; i++) {
+			if (i < cfg->n_etypes && cfg->etypes[i] == etype) {
+				found = true;
+				break;
+			}
+		}
+		if (!found)
+			return false;
+	}
+
+	if (!cfg->n_protos)
+		return true;
+
+	/* The network header is unset when equal to ~0U (u16). */
+	head = BPF_CORE_READ(skb, head);
+	nh = BPF_CORE_READ(skb, network_header);
+	if (!head || nh == (u16)~0U)
+		return false;
+
+	switch (etype) {
+	case ETH_P_IP:
+		if (bpf_probe_read_kernel(&ip, sizeof(ip), head + nh))
+			return false;
+		proto = ip.protocol;
+		break;
+	case ETH_P_IPV6:
+		if (bpf_probe_read_kernel(&ip6, sizeof(ip6), head + nh))
+			return false;
+		proto = ip6.nexthdr;
+		break;
+	default:
+		return false;
+	}
+
+	for (i = 0; i < FILTER_MAX; i++) {
+		if (i < cfg->n_protos && cfg->protos[i] == proto)
+			return true;
+	}
+	return false;
+}
+
+static __always_inline bool flow_addr_eq(const u8 *a, const u8 *b)
+{
+	int i;
+
+	for (i = 0; i < 16; i++) {
+		if (a[i] != b[i])
+			return false;
+	}
+	return true;
+}
+
+static __always_inline bool flow_dir_match(struct skb_flow *flow,
+					   const u8 *saddr, u16 sport,
+					   const u8 *daddr, u16 dport)
+{
+	if (flow->flags & FLOW_HAS_SADDR && !flow_addr_eq(flow->saddr, saddr))
+		return false;
+	if (flow->flags & FLOW_HAS_DADDR && !flow_addr_eq(flow->daddr, daddr))
+		return false;
+	if (flow->sport && flow->sport != sport)
+		return false;
+	if (flow->dport && flow->dport != dport)
+		return false;
+	return true;
+}
+
+/* 5-tuple of a packet, also used as the flow_head_map key. IPv4 addresses only
+ * use the first 4 bytes.
+ */
+struct skb_tuple {
+	u8 saddr[16];
+	u8 daddr[16];
+	/* In host order, 0 for protocols without ports */
+	u16 sport;
+	u16 dport;
+	/* FLOW_FAMILY_* */
+	u8 family;
+	u8 proto;
+	u8 pad[2];
+};
+
+/* Retrieve the 5-tuple of a packet. Packets without a network header have no
+ * tuple. Ports are only retrieved for TCP, UDP and SCTP; IPv6 extension
+ * headers are not followed.
+ */
+static __always_inline bool skb_tuple(struct sk_buff *skb, struct skb_tuple *t)
+{
+	unsigned char *head;
+	struct ipv6hdr ip6;
+	struct udphdr l4;
+	struct iphdr ip;
+	u16 etype, nh;
+	u32 l4_off;
+
+	head = BPF_CORE_READ(skb, head);
+	nh = BPF_CORE_READ(skb, network_header);
+	if (!head || nh == (u16)~0U)
+		return false;
+
+	etype = bpf_ntohs(BPF_CORE_READ(skb, protocol));
+	switch (etype) {
+	case ETH_P_IP:
+		if (bpf_probe_read_kernel(&ip, sizeof(ip), head + nh) ||
+		    ip.ihl < 5)
+			return false;
+		t->family = FLOW_FAMILY_IPV4;
+		t->proto = ip.protocol;
+		__builtin_memcpy(t->saddr, &ip.saddr, 4);
+		__builtin_memcpy(t->daddr, &ip.daddr, 4);
+		l4_off = nh + ip.ihl * 4;
+		break;
+	case ETH_P_IPV6:
+		if (bpf_probe_read_kernel(&ip6, sizeof(ip6), head + nh))
+			return false;
+		t->family = FLOW_FAMILY_IPV6;
+		t->proto = ip6.nexthdr;
+		__builtin_memcpy(t->saddr, &ip6.saddr, 16);
+		__builtin_memcpy(t->daddr, &ip6.daddr, 16);
+		l4_off = nh + sizeof(ip6);
+		break;
+	default:
+		return false;
+	}
+
+	/* TCP, UDP and SCTP all start with the source and destination ports. */
+	if (t->proto == IPPROTO_TCP || t->proto == IPPROTO_UDP ||
+	    t->proto == IPPROTO_SCTP) {
+		if (!bpf_probe_read_kernel(&l4, sizeof(l4), head + l4_off)) {
+			t->sport = bpf_ntohs(l4.source);
+			t->dport = bpf_ntohs(l4.dest);
+		}
+	}
+	return true;
+}
+
+/* Check if an skb is part of the flow, in either direction. Packets without a
+ * network header can't be matched and are filtered out. IPv6 extension headers
+ * are not followed.
+ */
+static __always_inline bool skb_flow_match(struct skb_flow *flow,
+					   struct sk_buff *skb)
+{
+	struct skb_tuple t = {};
+
+	if (!skb_tuple(skb, &t))
+		return false;
+
+	if (flow->family && flow->family != t.family)
+		return false;
+	if (flow->proto && flow->proto != t.proto)
+		return false;
+	if ((flow->sport || flow->dport) && t.proto != IPPROTO_TCP &&
+	    t.proto != IPPROTO_UDP && t.proto != IPPROTO_SCTP)
+		return false;
+
+	return flow_dir_match(flow, t.saddr, t.sport, t.daddr, t.dport) ||
+	       flow_dir_match(flow, t.daddr, t.dport, t.saddr, t.sport);
+}
+
+/* Maximum number of tuples, and of prefixes, in a match set. Please keep in
+ * sync with its Rust counterpart in core::filters::match_set.
+ */
+#define MATCH_SET_MAX	65536
+
+/* Match set keys. Sets are double buffered, keys carrying the generation they
+ * belong to. Please keep in sync with their Rust counterparts in
+ * core::filters::match_set.
+ */
+struct match_tuple_key {
+	u8 gen;
+	u8 pad[3];
+	struct skb_tuple tuple;
+};
+
+struct match_prefix_key {
+	/* Number of significant bits, starting at gen */
+	u32 prefixlen;
+	u8 gen;
+	/* FLOW_FAMILY_* */
+	u8 family;
+	/* In network order, IPv4 addresses only use the first 4 bytes */
+	u8 addr[16];
+	u8 pad[2];
+};
+
+/* Bits of the prefix key data before the address. */
+#define MATCH_PREFIX_KEY_HDR_BITS	16
+
+/* Maps are created and filled by userspace, see core::filters::match_set. */
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, MATCH_SET_MAX * 2);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+	__type(key, struct match_tuple_key);
+	__type(value, u8);
+} match_tuple_map SEC(".maps");
+
+struct {
+	__uint(type, BPF_MAP_TYPE_LPM_TRIE);
+	__uint(max_entries, MATCH_SET_MAX * 2);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+	__type(key, struct match_prefix_key);
+	__type(value, u8);
+} match_prefix_map SEC(".maps");
+
+/* Active generation */
+struct {
+	__uint(type, BPF_MAP_TYPE_ARRAY);
+	__uint(max_entries, 1);
+	__type(key, u32);
+	__type(value, u32);
+} match_gen_map SEC(".maps");
+
+/* Check if a packet matches the match set: if its tuple is part of it, in
+ * either direction, or if one of its addresses is in one of its prefixes.
+ * Packets without a tuple never match.
+ */
+static __always_inline bool skb_match_set(struct sk_buff *skb)
+{
+	struct match_prefix_key pk = {};
+	struct match_tuple_key tk = {};
+	u32 key = 0, *gen;
+	u16 port;
+
+	gen = bpf_map_lookup_elem(&match_gen_map, &key);
+	if (!gen || !skb_tuple(skb, &tk.tuple))
+		return false;
+
+	tk.gen = *gen;
+	if (bpf_map_lookup_elem(&match_tuple_map, &tk))
+		return true;
+
+	/* Reverse direction, using the prefix key address as a scratch buffer. */
+	__builtin_memcpy(pk.addr, tk.tuple.saddr, 16);
+	__builtin_memcpy(tk.tuple.saddr, tk.tuple.daddr, 16);
+	__builtin_memcpy(tk.tuple.daddr, pk.addr, 16);
+	port = tk.tuple.sport;
+	tk.tuple.sport = tk.tuple.dport;
+	tk.tuple.dport = port;
+	if (bpf_map_lookup_elem(&match_tuple_map, &tk))
+		return true;
+
+	pk.prefixlen = MATCH_PREFIX_KEY_HDR_BITS + 128;
+	pk.gen = tk.gen;
+	pk.family = tk.tuple.family;
+	/* The tuple is reversed: pk.addr holds the source address. */
+	if (bpf_map_lookup_elem(&match_prefix_map, &pk))
+		return true;
+
+	__builtin_memcpy(pk.addr, tk.tuple.saddr, 16);
+	return bpf_map_lookup_elem(&match_prefix_map, &pk) != NULL;
+}
+
+/* Keep in sync with its Rust counterpart in core::filters::iface */
+#define IFACE_FILTER_MAX	256
+
+/* Interfaces to report packets of; the key is an ifindex. Created and filled by
+ * userspace, see core::filters::iface.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, IFACE_FILTER_MAX);
+	__type(key, u32);
+	__type(value, u8);
+} iface_filter_map SEC(".maps");
+
+/* Check if a packet is attached to one of the filtered interfaces. Packets
+ * without a device never match.
+ */
+static __always_inline bool skb_iface_match(struct sk_buff *skb)
+{
+	struct net_device *dev;
+	u32 ifindex;
+
+	dev = BPF_CORE_READ(skb, dev);
+	if (!dev)
+		return false;
+
+	ifindex = BPF_CORE_READ(dev, ifindex);
+	return bpf_map_lookup_elem(&iface_filter_map, &ifindex) != NULL;
+}
+
+/* Maximum number of flows and of packets tracked when only reporting the first
+ * packets of each flow. The least recently used entries are evicted when the
+ * maps are full. Please keep in sync with their Rust counterparts in
+ * collector::skb.
+ */
+#define FLOW_HEAD_FLOWS		65536
+#define FLOW_HEAD_PACKETS	16384
+
+/* Flow accounting, see flow_head_map. Please keep in sync with its Rust
+ * counterpart in collector::skb.
+ */
+struct flow_head_entry {
+	/* Last time a packet of the flow was seen, in ns */
+	u64 last_seen;
+	/* Number of packets seen */
+	u32 count;
+	u32 pad;
+};
+
+/* Packets seen per flow. Created by userspace, to be shared by all probes. */
+struct {
+	__uint(type, BPF_MAP_TYPE_LRU_HASH);
+	__uint(max_entries, FLOW_HEAD_FLOWS);
+	__type(key, struct skb_tuple);
+	__type(value, struct flow_head_entry);
+} flow_head_map SEC(".maps");
+
+/* Packets already accounted for, as they go through multiple probes, and
+ * whether they are reported. The key is the packet data buffer (skb->head).
+ * Created by userspace, to be shared by all probes.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_LRU_HASH);
+	__uint(max_entries, FLOW_HEAD_PACKETS);
+	__type(key, u64);
+	__type(value, u8);
+} flow_head_packets SEC(".maps");
+
+/* Order the endpoints of a tuple, so both directions of a flow share the
+ * same one.
+ */
+static __always_inline void skb_tuple_order(struct skb_tuple *t)
+{
+	u8 addr[16];
+	u16 port;
+	int i;
+
+	for (i = 0; i < 16; i++) {
+		if (t->saddr[i] < t->daddr[i])
+			return;
+		if (t->saddr[i] > t->daddr[i])
+			goto swap;
+	}
+	if (t->sport <= t->dport)
+		return;
+
+swap:
+	__builtin_memcpy(addr, t->saddr, 16);
+	__builtin_memcpy(t->saddr, t->daddr, 16);
+	__builtin_memcpy(t->daddr, addr, 16);
+	port = t->sport;
+	t->sport = t->dport;
+	t->dport = port;
+}
+
+/* Check if a packet is one of the first cfg->flow_head ones of its flow.
+ * Packets are accounted for the first time they are seen; packets without a
+ * tuple are always reported. Flows inactive for cfg->flow_timeout are
+ * accounted for as new ones.
+ */
+static __always_inline bool skb_flow_head(struct skb_config *cfg,
+					  struct sk_buff *skb)
+{
+	u64 head = (u64)BPF_CORE_READ(skb, head);
+	struct flow_head_entry *entry, new = {};
+	struct skb_tuple t = {};
+	u8 *reported, report;
+	u64 now;
+
+	reported = bpf_map_lookup_elem(&flow_head_packets, &head);
+	if (reported)
+		return *reported;
+
+	if (!skb_tuple(skb, &t))
+		return true;
+	if (cfg->flow_bidir)
+		skb_tuple_order(&t);
+
+	now = bpf_ktime_get_ns();
+	entry = bpf_map_lookup_elem(&flow_head_map, &t);
+	if (entry && (!cfg->flow_timeout ||
+		      now - entry->last_seen < (u64)cfg->flow_timeout * 1000000000)) {
+		report = __sync_fetch_and_add(&entry->count, 1) < cfg->flow_head;
+		entry->last_seen = now;
+	} else {
+		new.last_seen = now;
+		new.count = 1;
+		/* Expired entries are replaced. */
+		bpf_map_update_elem(&flow_head_map, &t, &new,
+				    entry ? BPF_ANY : BPF_NOEXIST);
+		report = 1;
+	}
+
+	bpf_map_update_elem(&flow_head_packets, &head, &report, BPF_ANY);
+	return report;
+}
+
+/* Count a packet instead of reporting it, see count_events: by drop reason
+ * when the probe provides one, by ethertype and by L4 protocol.
+ */
+static __always_inline void skb_count(struct trace_context *ctx,
+				      struct sk_buff *skb)
+{
+	struct skb_tuple t = {};
+
+	if (trace_arg_valid(ctx, skb_drop_reason))
+		event_counter_inc(COUNTER_DROP_REASON,
+				  trace_get_skb_drop_reason(ctx));
+
+	event_counter_inc(COUNTER_ETHERTYPE,
+			  bpf_ntohs(BPF_CORE_READ(skb, protocol)));
+
+	if (skb_tuple(skb, &t))
+		event_counter_inc(COUNTER_L4_PROTO, t.proto);
+}
+
+/* Must be called with a valid skb pointer */
+static __always_inline int process_skb(struct trace_context *ctx,
+				       struct trace_raw_event *event,
+				       struct sk_buff *skb)
+{
+	struct skb_config *cfg;
+	u32 key = 0;
+
+	cfg = bpf_map_lookup_elem(&skb_config_map, &key);
+	if (cfg && !skb_filter_match(cfg, skb))
+		return HOOK_DISCARD;
+	if (cfg && cfg->flow.flags & FLOW_ENABLED &&
+	    !skb_flow_match(&cfg->flow, skb))
+		return HOOK_DISCARD;
+	if (cfg && cfg->match_set && !skb_match_set(skb))
+		return HOOK_DISCARD;
+	if (cfg && cfg->iface_filter && !skb_iface_match(skb))
+		return HOOK_DISCARD;
+	if (cfg && cfg->flow_head && !skb_flow_head(cfg, skb))
+		return HOOK_DISCARD;
+
+	if (ctx->count_events) {
+		skb_count(ctx, skb);
+		return 0;
+	}
+
+	process_skb_len(event, skb);
+	process_skb_mark(event, skb);
+	process_skb_secmark(event, skb);
+	process_skb_dev(event, skb);
+	process_skb_queue(event, skb);
+	if (skb_section(cfg, SECTION_CT))
+		process_skb_ct(event, skb);
+	process_skb_gso(event, skb);
+	if (skb_section(cfg, SECTION_SOCK))
+		process_skb_sock(ctx, event, skb);
+	if (skb_section(cfg, SECTION_PACKET))
+		process_skb_packet(event, skb);
+	if (skb_section(cfg, SECTION_CSUM))
+		process_skb_csum(event, skb);
+	if (skb_section(cfg, SECTION_L3))
+		process_skb_l3(event, skb);
+	process_skb_debug(event, skb);
+
+	return 0;
+}
+
+DEFINE_HOOK(
+	struct sk_buff *skb;
+
+	skb = trace_get_sk_buff(ctx);
+	if (!skb)
+		return 0;
+
+	return process_skb(ctx, event, skb);
+)
+
+char __license[] SEC("license") = "GPL";
@@ -3,8 +3,86 @@
 //! Provide a generic way to probe kernel functions and tracepoints (having a
 //! `struct sk_buff *` as a parameter), to filter skbs, and to track them;
 //! allowing to reconstruct their path in the Linux networking stack.
+//!
+//! The collector also probes the ICMP error emission points, reporting the
+//! messages generated by the kernel (e.g. "fragmentation needed", "redirect")
+//! along with the tuple of the packet which triggered them. ICMP "fragmentation
+//! needed" and "packet too big" messages flag packets dropped because they
+//! exceeded the MTU. ICMPv6 redirects are not sent from those points and are
+//! not reported; messages might still be rate limited after being reported.
+//!
+//! The first bytes of packets can be reported (see --capture-bytes), as an
+//! hex string in the `packet` field. The MPLS label stack of Ethernet packets
+//! is decoded from those bytes, as a list of labels with their traffic class,
+//! bottom of stack bit and TTL in `mpls_labels`. GTP-U tunnels (UDP port 2152)
+//! are decoded too: the tunnel endpoint identifier and message type are
+//! reported in `gtp_teid` and `gtp_msg_type`, and the tuple of the inner packet
+//! of G-PDUs in the `gtp_inner_*` fields. Up to 4 GTP-U extension headers are
+//! skipped; `gtp_truncated` is set when the inner packet could not be fully
+//! decoded, e.g. when cut by the capture length.
+//!
+//! The presence of IPv4 options (`has_ip_options`) and of IPv6 extension
+//! headers (`has_hbh`, `has_routing_header` and `has_fragment_header`) is
+//! reported for IP packets. The IPv6 extension headers chain is only walked
+//! up to 8 headers, `ext_headers_truncated` is set when it is longer.
+//!
+//! The index of the device an skb is attached to (`ifindex`) is reported, when
+//! set.
+//!
+//! The queue an skb was received on or is sent to (`queue_mapping`) and its
+//! flow hash (`hash`, with `l4_hash` set when computed over the L4 tuple, e.g.
+//! by RSS) are reported, to look into multi-queue balancing. On receive the
+//! queue mapping is the Rx queue + 1 (0 if not recorded); on transmit it is
+//! the Tx queue. A zero hash was not computed (yet). The CPU processing the skb
+//! (`cpu`) is reported along, to look into RSS/RPS steering; see also the
+//! skb-tracking collector `orig_cpu` and `steered` fields.
+//!
+//! Raw sk_buff fields can be reported for debugging skb handling in the kernel
+//! (see --skb-debug): the `data_offset`, `tail_offset` and `end_offset` of the
+//! data area, the `mac_header`, `network_header` and `transport_header`
+//! offsets (when set), `protocol` (host order), `pkt_type`, `cloned` and
+//! `nohdr`. Those are best effort across kernel versions, fields not available
+//! on the running kernel being reported as 0.
+//!
+//! Only the first packets of each flow can be reported (see --flow-head), or
+//! the first packet of each new flow in either direction (see
+//! --new-flows-only), flows expiring after a period of inactivity (see
+//! --flow-timeout).
+//!
+//! Some fields are expensive to compute: the packet bytes, checksums, L3
+//! header flags, conntrack and socket ones. When fields are selected (see
+//! --fields), those are only computed if selected or otherwise used, e.g. in
+//! --where.
+//! Packets are counted per 5-tuple in the kernel, each direction being its own
+//! flow; packets without an IP header are always reported.
+//!
+//! When only counting packets (see the collect --count-only option), packets
+//! passing the filters are counted by ethertype and L4 protocol, and by drop
+//! reason on probes reporting one (e.g. the skb:kfree_skb tracepoint, probed
+//! by the skb-tracking collector). No other field is retrieved.
+//!
+//! Fields are read using CO-RE so a single build works across kernels. Some
+//! of them depend on the kernel configuration and are only reported when
+//! available:
+//! - `ct_state`, `ct_direction` and `ct_status` require CONFIG_NF_CONNTRACK,
+//!   and are only reported for packets having a conntrack entry or explicitly
+//!   untracked.
+//! - `ct_mark` and `ct_zone` require CONFIG_NF_CONNTRACK, and respectively
+//!   CONFIG_NF_CONNTRACK_MARK and CONFIG_NF_CONNTRACK_ZONES.
+//! - `secmark` requires CONFIG_NETWORK_SECMARK, and is only reported for
+//!   packets having a security mark (e.g. set by the SECMARK iptables target).
+//!   With SELinux it is the secid of the packet label; the kernel does not
+//!   expose the secid to context mapping to userspace, so it is not resolved.
 
 // Re-export skb.rs
 #[allow(clippy::module_inception)]
 pub(super) mod skb;
 pub(super) use skb::*;
+
+mod skb_hook {
+    include!("bpf/.out/skb_hook.rs");
+}
+
+mod icmp_hook {
+    include!("bpf/.out/icmp_hook.rs");
+}
@@ -1,13 +1,578 @@
-use anyhow::Result;
+use std::{
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use log::warn;
+use plain::Plain;
+use serde_json::json;
 
-use crate::cli::{dynamic::DynamicCommand, CliConfig};
+use super::{icmp_hook, skb_hook};
+use crate::event_field;
 use crate::{
-    collector::Collector,
-    core::{events::bpf::BpfEvents, probe::kernel},
+    cli::{
+        cmd::collect::{Collect, CollectArgs},
+        dynamic::DynamicCommand,
+        CliConfig,
+    },
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{
+                parse_raw_section, parse_raw_section_var, BpfEventOwner, BpfEvents, BpfRawSection,
+            },
+            class::{classify, Category, Severity},
+            EventField,
+        },
+        filters::{flow::Flow, iface::get_iface_filter, match_set::get_match_set},
+        probe::{
+            kernel::{self, Hook, ProbeType},
+            map_usage::track_map,
+        },
+    },
 };
 
 const SKB_COLLECTOR: &str = "skb";
 
+// Skb section data types. Please keep in sync with its BPF counterpart in
+// bpf/skb_hook.bpf.c
+const SECTION_GSO: u8 = 1;
+const SECTION_SOCK: u8 = 2;
+const SECTION_LEN: u8 = 3;
+const SECTION_MARK: u8 = 4;
+const SECTION_CT: u8 = 5;
+const SECTION_PACKET: u8 = 8;
+const SECTION_CSUM: u8 = 9;
+const SECTION_L3: u8 = 10;
+const SECTION_QUEUE: u8 = 11;
+const SECTION_SECMARK: u8 = 12;
+const SECTION_DEBUG: u8 = 13;
+const SECTION_DEV: u8 = 14;
+// Emitted by bpf/icmp_hook.bpf.c
+const SECTION_MTU: u8 = 6;
+const SECTION_ICMP: u8 = 7;
+
+// Maximum number of packet bytes copied in events. Please keep in sync with
+// its BPF counterpart in bpf/skb_hook.bpf.c
+const CAPTURE_MAX: u32 = 256;
+
+// Maximum number of flows and of packets tracked when only reporting the first
+// packets of each flow. Please keep in sync with their BPF counterparts in
+// bpf/skb_hook.bpf.c
+const FLOW_HEAD_FLOWS: u32 = 65536;
+const FLOW_HEAD_PACKETS: u32 = 16384;
+/// Default inactivity timeout of flows with --new-flows-only, in seconds.
+const NEW_FLOWS_TIMEOUT: u32 = 60;
+// Size of the 5-tuple keying flows (struct skb_tuple). Please keep in sync
+// with its BPF counterpart in bpf/skb_hook.bpf.c
+const SKB_TUPLE_SIZE: usize = 40;
+
+// Maximum number of ethertypes and of L4 protocols to filter on. Please keep in
+// sync with its BPF counterpart in bpf/skb_hook.bpf.c
+const FILTER_MAX: usize = 8;
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct SkbCollectorArgs {
+    /// Number of bytes of each packet, starting at its mac header, to report
+    /// in events (0 to disable it). Only the linear part of packets is
+    /// reported. Copying packet bytes is expensive, keep it low.
+    #[arg(long, default_value = "64", value_parser = clap::value_parser!(u32).range(0..=CAPTURE_MAX as i64))]
+    capture_bytes: u32,
+    /// Only report events for packets of the given ethertypes, by name (ipv4,
+    /// ipv6, arp, vlan, mpls, lldp) or number (e.g. 0x88cc). Comma separated
+    /// list. Events without a packet are not filtered.
+    #[arg(long, value_delimiter = ',')]
+    ethertype: Vec<String>,
+    /// Only report events for packets of the given L4 protocols, by name (tcp,
+    /// udp, icmp, icmpv6, sctp, gre, esp) or number. Comma separated list. Only
+    /// IPv4 and IPv6 packets can match, IPv6 extension headers are not
+    /// followed. Combined with --ethertype, both have to match.
+    #[arg(long, value_delimiter = ',')]
+    proto: Vec<String>,
+    /// Verify the IPv4 header and TCP/UDP checksums of packets, in the kernel,
+    /// and report the result. L4 checksums are only verified when the whole
+    /// segment is part of the linear data and fits in --capture-bytes; the ones
+    /// offloaded to the device (not in the packet yet) are reported as such.
+    #[arg(long)]
+    verify_csum: bool,
+    /// Report raw sk_buff fields (data, tail and end offsets, header offsets,
+    /// protocol, packet type, cloned and nohdr flags), for debugging skb
+    /// handling in the kernel. This is verbose and best effort: fields not
+    /// available on the running kernel are reported as 0.
+    #[arg(long)]
+    skb_debug: bool,
+}
+
+// ICMP families. Please keep in sync with its BPF counterpart in
+// bpf/icmp_hook.bpf.c
+const ICMP_FAMILY_IPV4: u8 = 1;
+const ICMP_FAMILY_IPV6: u8 = 2;
+
+// ICMP tuple parsing flags. Please keep in sync with their BPF counterparts in
+// bpf/icmp_hook.bpf.c
+const PARSE_TRUNCATED: u8 = 1 << 0;
+const PARSE_ERROR: u8 = 1 << 1;
+
+// Functions emitting ICMP errors, and their family.
+const ICMP_TARGETS: &[(&str, u8)] = &[
+    ("__icmp_send", ICMP_FAMILY_IPV4),
+    ("icmp6_send", ICMP_FAMILY_IPV6),
+];
+
+// GSO event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbGsoEvent {
+    gso_size: u32,
+    gso_segs: u32,
+}
+unsafe impl Plain for SkbGsoEvent {}
+
+// Socket event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbSockEvent {
+    rmem_alloc: u32,
+    rcvbuf: u32,
+    cookie: u64,
+}
+unsafe impl Plain for SkbSockEvent {}
+
+// Length event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbLenEvent {
+    len: u32,
+    data_len: u32,
+    truesize: u32,
+}
+unsafe impl Plain for SkbLenEvent {}
+
+// Mark event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbMarkEvent {
+    mark: u32,
+}
+unsafe impl Plain for SkbMarkEvent {}
+
+// Security mark event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbSecmarkEvent {
+    secmark: u32,
+}
+unsafe impl Plain for SkbSecmarkEvent {}
+
+// Device event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbDevEvent {
+    ifindex: u32,
+}
+unsafe impl Plain for SkbDevEvent {}
+
+// Debug event flags. Please keep in sync with their BPF counterparts in
+// bpf/skb_hook.bpf.c
+const DEBUG_CLONED: u8 = 1 << 0;
+const DEBUG_NOHDR: u8 = 1 << 1;
+
+// Debug event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbDebugEvent {
+    data: u32,
+    tail: u32,
+    end: u32,
+    mac_header: u16,
+    network_header: u16,
+    transport_header: u16,
+    protocol: u16,
+    pkt_type: u8,
+    flags: u8,
+}
+unsafe impl Plain for SkbDebugEvent {}
+
+// Conntrack event fields availability. Please keep in sync with its BPF
+// counterpart in bpf/skb_hook.bpf.c
+const CT_HAS_MARK: u8 = 1 << 0;
+const CT_HAS_ZONE: u8 = 1 << 1;
+const CT_HAS_STATUS: u8 = 1 << 2;
+
+// Conntrack event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbCtEvent {
+    mark: u32,
+    zone: u16,
+    flags: u8,
+    state: u8,
+    status: u32,
+}
+unsafe impl Plain for SkbCtEvent {}
+
+// Checksum verification statuses. Please keep in sync with their BPF
+// counterparts in bpf/skb_hook.bpf.c
+const CSUM_UNVERIFIED: u8 = 0;
+const CSUM_OK: u8 = 1;
+const CSUM_BAD: u8 = 2;
+const CSUM_OFFLOADED: u8 = 3;
+const CSUM_ABSENT: u8 = 4;
+
+// Checksum event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbCsumEvent {
+    ip_summed: u8,
+    l3: u8,
+    l4: u8,
+    l4_proto: u8,
+}
+unsafe impl Plain for SkbCsumEvent {}
+
+// L3 header flags. Please keep in sync with their BPF counterparts in
+// bpf/skb_hook.bpf.c
+const L3_IP_OPTIONS: u8 = 1 << 0;
+const L3_HBH: u8 = 1 << 1;
+const L3_ROUTING: u8 = 1 << 2;
+const L3_FRAGMENT: u8 = 1 << 3;
+const L3_EXT_TRUNCATED: u8 = 1 << 4;
+
+// L3 event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbL3Event {
+    version: u8,
+    flags: u8,
+}
+unsafe impl Plain for SkbL3Event {}
+
+// Queue event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbQueueEvent {
+    hash: u32,
+    queue_mapping: u16,
+    l4_hash: u8,
+    cpu: u32,
+}
+unsafe impl Plain for SkbQueueEvent {}
+
+// Packet event, followed by the packet bytes. Please keep in sync with its BPF
+// counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbPacketEvent {
+    len: u32,
+    capture_len: u32,
+}
+unsafe impl Plain for SkbPacketEvent {}
+
+// Flow filter flags. Please keep in sync with their BPF counterparts in
+// bpf/skb_hook.bpf.c
+const FLOW_ENABLED: u8 = 1 << 0;
+const FLOW_HAS_SADDR: u8 = 1 << 1;
+const FLOW_HAS_DADDR: u8 = 1 << 2;
+
+// Flow filter families. Please keep in sync with their BPF counterparts in
+// bpf/skb_hook.bpf.c
+const FLOW_FAMILY_IPV4: u8 = 4;
+const FLOW_FAMILY_IPV6: u8 = 6;
+
+// Please keep in sync with its BPF counterpart in bpf/skb_hook.bpf.c
+#[derive(Default)]
+#[repr(C)]
+struct SkbFlowConfig {
+    flags: u8,
+    family: u8,
+    proto: u8,
+    pad: u8,
+    saddr: [u8; 16],
+    daddr: [u8; 16],
+    sport: u16,
+    dport: u16,
+}
+
+impl SkbFlowConfig {
+    fn new(flow: &Flow) -> SkbFlowConfig {
+        let mut cfg = SkbFlowConfig {
+            flags: FLOW_ENABLED,
+            proto: flow.proto.unwrap_or(0),
+            sport: flow.src.port.unwrap_or(0),
+            dport: flow.dst.port.unwrap_or(0),
+            ..Default::default()
+        };
+
+        if let Some((family, addr)) = flow.src.addr.map(raw_addr) {
+            cfg.family = family;
+            cfg.saddr = addr;
+            cfg.flags |= FLOW_HAS_SADDR;
+        }
+        if let Some((family, addr)) = flow.dst.addr.map(raw_addr) {
+            cfg.family = family;
+            cfg.daddr = addr;
+            cfg.flags |= FLOW_HAS_DADDR;
+        }
+        cfg
+    }
+}
+
+// Sections whose computation is expensive (copying packet bytes, verifying
+// checksums, parsing L3 headers, reading the conntrack entry or the socket),
+// along with the fields they report. Please keep in sync with the
+// unmarshalers below.
+const EXPENSIVE_SECTIONS: &[(u8, &[&str])] = &[
+    (
+        SECTION_PACKET,
+        &[
+            "packet",
+            "packet_len",
+            "mpls_labels",
+            "gtp_teid",
+            "gtp_msg_type",
+            "gtp_inner_saddr",
+            "gtp_inner_daddr",
+            "gtp_inner_proto",
+            "gtp_inner_sport",
+            "gtp_inner_dport",
+            "gtp_truncated",
+        ],
+    ),
+    (
+        SECTION_CSUM,
+        &[
+            "ip_summed",
+            "csum_ok",
+            "csum_bad",
+            "csum_absent",
+            "offloaded",
+        ],
+    ),
+    (
+        SECTION_L3,
+        &[
+            "has_ip_options",
+            "has_hbh",
+            "has_routing_header",
+            "has_fragment_header",
+            "ext_headers_truncated",
+        ],
+    ),
+    (
+        SECTION_CT,
+        &[
+            "ct_state",
+            "ct_direction",
+            "ct_status",
+            "ct_mark",
+            "ct_zone",
+        ],
+    ),
+    (
+        SECTION_SOCK,
+        &["sk_rmem_alloc", "sk_rcvbuf", "sk_rcvbuf_full", "sk_cookie"],
+    ),
+];
+
+fn section_mask(sections: impl Iterator<Item = u8>) -> u32 {
+    sections.fold(0, |mask, section| mask | 1 << section)
+}
+
+/// Get the expensive sections to compute, as a mask. All of them are computed
+/// unless fields are selected using --fields, in which case only the sections
+/// reporting one of them are; fields used in --where, --trigger, --histogram
+/// or --histogram-by are selected too, as are the packet bytes when
+/// aggregating them (--summary, --top, --detect-asymmetry, --output-ipfix).
+fn expensive_sections(args: &CollectArgs) -> u32 {
+    let needed = |field: &str| {
+        let full = format!("{}.{}", SKB_COLLECTOR, field);
+        args.fields.contains(&full)
+            || args.histogram.iter().any(|h| h == field || *h == full)
+            || args.histogram_by.as_ref().map_or(false, |h| h == field || *h == full)
+            || args
+                .trigger
+                .as_ref()
+                .and_then(|t| t.split_once('='))
+                .map_or(false, |(f, _)| f.trim() == full)
+            // Conservative: the expression might only contain a field name.
+            || args.r#where.as_ref().map_or(false, |w| w.contains(field))
+    };
+
+    section_mask(
+        EXPENSIVE_SECTIONS
+            .iter()
+            .filter(|(section, fields)| {
+                args.fields.is_empty()
+                    || fields.iter().any(|f| needed(f))
+                    || (*section == SECTION_PACKET
+                        && (args.summary
+                            || args.top
+                            || args.detect_asymmetry
+                            || args.output_ipfix.is_some()))
+            })
+            .map(|(section, _)| *section),
+    )
+}
+
+/// Convert an address to its flow filter family and raw representation. IPv4
+/// addresses only use the first 4 bytes.
+fn raw_addr(addr: IpAddr) -> (u8, [u8; 16]) {
+    let mut raw = [0; 16];
+    match addr {
+        IpAddr::V4(addr) => {
+            raw[..4].copy_from_slice(&addr.octets());
+            (FLOW_FAMILY_IPV4, raw)
+        }
+        IpAddr::V6(addr) => (FLOW_FAMILY_IPV6, addr.octets()),
+    }
+}
+
+// Please keep in sync with its BPF counterpart in bpf/skb_hook.bpf.c
+#[derive(Default)]
+#[repr(C)]
+struct SkbConfig {
+    capture_bytes: u32,
+    etypes: [u16; FILTER_MAX],
+    protos: [u8; FILTER_MAX],
+    n_etypes: u8,
+    n_protos: u8,
+    flow: SkbFlowConfig,
+    verify_csum: u8,
+    flow_head: u32,
+    match_set: u8,
+    debug: u8,
+    iface_filter: u8,
+    sections: u32,
+    flow_timeout: u32,
+    flow_bidir: u8,
+}
+unsafe impl Plain for SkbConfig {}
+
+// Please keep in sync with its BPF counterpart in bpf/skb_hook.bpf.c
+#[repr(C)]
+struct FlowHeadEntry {
+    last_seen: u64,
+    count: u32,
+    pad: u32,
+}
+
+impl SkbConfig {
+    fn new(args: &SkbCollectorArgs, flow: Option<&Flow>) -> Result<SkbConfig> {
+        let mut cfg = SkbConfig {
+            capture_bytes: args.capture_bytes,
+            flow: flow.map(SkbFlowConfig::new).unwrap_or_default(),
+            verify_csum: args.verify_csum as u8,
+            debug: args.skb_debug as u8,
+            sections: section_mask(EXPENSIVE_SECTIONS.iter().map(|(s, _)| *s)),
+            ..Default::default()
+        };
+
+        if args.ethertype.len() > FILTER_MAX || args.proto.len() > FILTER_MAX {
+            bail!(
+                "Can't filter on more than {} ethertypes or protocols",
+                FILTER_MAX
+            );
+        }
+
+        for (i, etype) in args.ethertype.iter().enumerate() {
+            cfg.etypes[i] = parse_ethertype(etype)?;
+        }
+        for (i, proto) in args.proto.iter().enumerate() {
+            cfg.protos[i] = parse_proto(proto)?;
+        }
+        cfg.n_etypes = args.ethertype.len() as u8;
+        cfg.n_protos = args.proto.len() as u8;
+        Ok(cfg)
+    }
+
+    /// Set up the sampling of the head of flows (--flow-head and
+    /// --new-flows-only).
+    fn set_flow_head(&mut self, collect: &CollectArgs) {
+        if collect.new_flows_only {
+            self.flow_head = 1;
+            self.flow_bidir = 1;
+            self.flow_timeout = collect.flow_timeout.unwrap_or(NEW_FLOWS_TIMEOUT);
+        } else {
+            self.flow_head = collect.flow_head.unwrap_or(0);
+            self.flow_timeout = collect.flow_timeout.unwrap_or(0);
+        }
+    }
+}
+
+/// Parse a number, in decimal or in hexadecimal when prefixed by "0x".
+fn parse_number<T: TryFrom<u64>>(s: &str) -> Option<T> {
+    let val = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => s.parse::<u64>().ok()?,
+    };
+    T::try_from(val).ok()
+}
+
+fn parse_ethertype(etype: &str) -> Result<u16> {
+    Ok(match etype {
+        "ipv4" => 0x0800,
+        "ipv6" => 0x86dd,
+        "arp" => 0x0806,
+        "vlan" => 0x8100,
+        "mpls" => 0x8847,
+        "lldp" => 0x88cc,
+        x => match parse_number(x) {
+            Some(etype) => etype,
+            None => bail!("Invalid ethertype '{}'", x),
+        },
+    })
+}
+
+fn parse_proto(proto: &str) -> Result<u8> {
+    Ok(match proto {
+        "icmp" => 1,
+        "tcp" => 6,
+        "udp" => 17,
+        "gre" => 47,
+        "esp" => 50,
+        "icmpv6" => 58,
+        "sctp" => 132,
+        x => match parse_number(x) {
+            Some(proto) => proto,
+            None => bail!("Invalid L4 protocol '{}'", x),
+        },
+    })
+}
+
+// MTU event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbMtuEvent {
+    mtu: u32,
+    dev_mtu: u32,
+}
+unsafe impl Plain for SkbMtuEvent {}
+
+// ICMP event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SkbIcmpEvent {
+    family: u8,
+    r#type: u8,
+    code: u8,
+    protocol: u8,
+    saddr: [u8; 16],
+    daddr: [u8; 16],
+    sport: u16,
+    dport: u16,
+    parse_flags: u8,
+}
+unsafe impl Plain for SkbIcmpEvent {}
+
+// Please keep in sync with its BPF counterpart in bpf/icmp_hook.bpf.c
+#[repr(C)]
+struct IcmpConfig {
+    family: u8,
+}
+unsafe impl Plain for IcmpConfig {}
+
 pub(in crate::collector) struct SkbCollector {}
 
 impl Collector for SkbCollector {
@@ -19,16 +584,120 @@ impl Collector for SkbCollector {
         SKB_COLLECTOR
     }
 
+    // The skb collector filters events (flows, interfaces, match sets), its
+    // hook should run before the other collectors' ones.
+    fn priority(&self) -> i32 {
+        -10
+    }
+
     fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
-        cmd.register_module_noargs(SKB_COLLECTOR)
+        cmd.register_module::<SkbCollectorArgs>(SKB_COLLECTOR)
     }
 
     fn init(
         &mut self,
-        _: &CliConfig,
-        _kernel: &mut kernel::Kernel,
-        _events: &mut BpfEvents,
+        cli: &CliConfig,
+        kernel: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
     ) -> Result<()> {
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorSkb,
+            Box::new(|raw_section, fields| match raw_section.header.data_type {
+                SECTION_GSO => unmarshal_gso(raw_section, fields),
+                SECTION_SOCK => unmarshal_sock(raw_section, fields),
+                SECTION_LEN => unmarshal_len(raw_section, fields),
+                SECTION_MARK => unmarshal_mark(raw_section, fields),
+                SECTION_SECMARK => unmarshal_secmark(raw_section, fields),
+                SECTION_DEBUG => unmarshal_debug(raw_section, fields),
+                SECTION_DEV => unmarshal_dev(raw_section, fields),
+                SECTION_QUEUE => unmarshal_queue(raw_section, fields),
+                SECTION_CT => unmarshal_ct(raw_section, fields),
+                SECTION_MTU => unmarshal_mtu(raw_section, fields),
+                SECTION_ICMP => unmarshal_icmp(raw_section, fields),
+                SECTION_PACKET => unmarshal_packet(raw_section, fields),
+                SECTION_CSUM => unmarshal_csum(raw_section, fields),
+                SECTION_L3 => unmarshal_l3(raw_section, fields),
+                _ => bail!("Unknown data type"),
+            }),
+        )?;
+
+        let args = cli.get_section::<SkbCollectorArgs>(SKB_COLLECTOR)?;
+        let mut config_map = Self::skb_config_map()?;
+        let collect = cli
+            .subcommand
+            .as_any()
+            .downcast_ref::<Collect>()
+            .ok_or_else(|| anyhow!("wrong subcommand"))?
+            .args()?;
+        let flow = match &collect.flow {
+            Some(flow) => Some(Flow::parse(flow)?),
+            None => None,
+        };
+        let mut cfg = SkbConfig::new(&args, flow.as_ref())?;
+        cfg.set_flow_head(collect);
+        cfg.match_set = get_match_set().is_some() as u8;
+        cfg.iface_filter = get_iface_filter().is_some() as u8;
+        cfg.sections = expensive_sections(collect);
+        let cfg = unsafe { plain::as_bytes(&cfg) };
+        config_map.update(&0_u32.to_ne_bytes(), cfg, libbpf_rs::MapFlags::ANY)?;
+
+        // Register the skb hook to all probes.
+        let mut hook = Hook::from(skb_hook::DATA);
+        hook.reuse_map("skb_config_map", config_map.fd())?;
+        if let Some(set) = get_match_set() {
+            hook.reuse_map("match_tuple_map", set.tuple_map_fd())?
+                .reuse_map("match_prefix_map", set.prefix_map_fd())?
+                .reuse_map("match_gen_map", set.gen_map_fd())?;
+        }
+        if let Some(filter) = get_iface_filter() {
+            hook.reuse_map("iface_filter_map", filter.map_fd())?;
+        }
+        // Flows and packets must be accounted for across all probes.
+        if collect.flow_head.is_some() || collect.new_flows_only {
+            let flows = Self::flow_head_map(
+                "flow_head_map",
+                SKB_TUPLE_SIZE,
+                mem::size_of::<FlowHeadEntry>(),
+                FLOW_HEAD_FLOWS,
+            )?;
+            let packets = Self::flow_head_map(
+                "flow_head_packets",
+                mem::size_of::<u64>(),
+                mem::size_of::<u8>(),
+                FLOW_HEAD_PACKETS,
+            )?;
+            hook.reuse_map("flow_head_map", flows.fd())?
+                .reuse_map("flow_head_packets", packets.fd())?;
+            track_map("flow_head_map", flows.fd())?;
+            track_map("flow_head_packets", packets.fd())?;
+        }
+        kernel.register_hook(hook)?;
+
+        // Register the ICMP hook to the ICMP error emission points. Those
+        // might not be available (e.g. IPv6 being a module not loaded), which
+        // is not fatal.
+        let mut config_map = Self::icmp_config_map()?;
+        let hook = Hook::from(icmp_hook::DATA)
+            .reuse_map("icmp_config_map", config_map.fd())?
+            .to_owned();
+
+        for (target, family) in ICMP_TARGETS.iter() {
+            let ksym = match kernel.inspect.get_ksym(&ProbeType::Kprobe, target) {
+                Ok(ksym) => ksym,
+                Err(e) => {
+                    warn!("Can't report ICMP errors using {}: {}", target, e);
+                    continue;
+                }
+            };
+
+            let cfg = IcmpConfig { family: *family };
+            let cfg = unsafe { plain::as_bytes(&cfg) };
+            config_map.update(&ksym.to_ne_bytes(), cfg, libbpf_rs::MapFlags::NO_EXIST)?;
+
+            kernel.register_hook_to(hook.clone(), ProbeType::Kprobe, target)?;
+        }
+
         Ok(())
     }
 
@@ -36,3 +705,1263 @@ impl Collector for SkbCollector {
         Ok(())
     }
 }
+
+impl SkbCollector {
+    fn skb_config_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in bpf/skb_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Array,
+            Some("skb_config_map"),
+            mem::size_of::<u32>() as u32,
+            mem::size_of::<SkbConfig>() as u32,
+            1,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the skb config map: {}", e))
+    }
+
+    fn flow_head_map(
+        name: &str,
+        key_size: usize,
+        value_size: usize,
+        max_entries: u32,
+    ) -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in bpf/skb_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::LruHash,
+            Some(name),
+            key_size as u32,
+            value_size as u32,
+            max_entries,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the {} map: {}", name, e))
+    }
+
+    fn icmp_config_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in bpf/icmp_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("icmp_config_map"),
+            mem::size_of::<u64>() as u32,
+            mem::size_of::<IcmpConfig>() as u32,
+            kernel::PROBE_MAX as u32,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the icmp config map: {}", e))
+    }
+}
+
+fn unmarshal_gso(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbGsoEvent>(raw_section)?;
+
+    fields.push(event_field!("gso_size", event.gso_size));
+    fields.push(event_field!("gso_segs", event.gso_segs));
+    Ok(())
+}
+
+fn unmarshal_sock(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbSockEvent>(raw_section)?;
+
+    fields.push(event_field!("sk_rmem_alloc", event.rmem_alloc));
+    fields.push(event_field!("sk_rcvbuf", event.rcvbuf));
+    // Same check as the one done when queueing an skb to a socket, see
+    // __sock_queue_rcv_skb() in the kernel.
+    fields.push(event_field!(
+        "sk_rcvbuf_full",
+        event.rmem_alloc >= event.rcvbuf
+    ));
+    // The cookie is only generated on demand (e.g. by inet_diag), it allows
+    // correlating events with the tcp-retrans and sockdrop collector ones.
+    if event.cookie != 0 {
+        fields.push(event_field!("sk_cookie", event.cookie));
+    }
+    Ok(())
+}
+
+fn unmarshal_len(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbLenEvent>(raw_section)?;
+
+    fields.push(event_field!("len", event.len));
+    fields.push(event_field!("data_len", event.data_len));
+    fields.push(event_field!("truesize", event.truesize));
+    Ok(())
+}
+
+fn unmarshal_mark(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbMarkEvent>(raw_section)?;
+
+    fields.push(event_field!("mark", event.mark));
+    Ok(())
+}
+
+fn unmarshal_secmark(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbSecmarkEvent>(raw_section)?;
+
+    fields.push(event_field!("secmark", event.secmark));
+    Ok(())
+}
+
+fn unmarshal_dev(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbDevEvent>(raw_section)?;
+
+    fields.push(event_field!("ifindex", event.ifindex));
+    Ok(())
+}
+
+fn unmarshal_debug(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbDebugEvent>(raw_section)?;
+
+    fields.push(event_field!("data_offset", event.data));
+    fields.push(event_field!("tail_offset", event.tail));
+    fields.push(event_field!("end_offset", event.end));
+    // Unset headers are not reported.
+    for (key, offset) in [
+        ("mac_header", event.mac_header),
+        ("network_header", event.network_header),
+        ("transport_header", event.transport_header),
+    ] {
+        if offset != u16::MAX {
+            fields.push(event_field!(key, offset as u32));
+        }
+    }
+    fields.push(event_field!("protocol", event.protocol as u32));
+    fields.push(event_field!("pkt_type", event.pkt_type as u32));
+    fields.push(event_field!("cloned", event.flags & DEBUG_CLONED != 0));
+    fields.push(event_field!("nohdr", event.flags & DEBUG_NOHDR != 0));
+    Ok(())
+}
+
+// Conntrack state of a packet, see enum ip_conntrack_info in the kernel.
+// Returns the state and the direction of the packet.
+fn ct_state(state: u8) -> (&'static str, Option<&'static str>) {
+    match state {
+        0 => ("established", Some("original")),
+        1 => ("related", Some("original")),
+        2 => ("new", Some("original")),
+        3 => ("established", Some("reply")),
+        4 => ("related", Some("reply")),
+        7 => ("untracked", None),
+        _ => ("unknown", None),
+    }
+}
+
+// Conntrack status flags, see enum ip_conntrack_status in the kernel.
+fn ct_status(status: u32) -> String {
+    const FLAGS: [&str; 16] = [
+        "expected",
+        "seen_reply",
+        "assured",
+        "confirmed",
+        "src_nat",
+        "dst_nat",
+        "seq_adjust",
+        "src_nat_done",
+        "dst_nat_done",
+        "dying",
+        "fixed_timeout",
+        "template",
+        "untracked",
+        "helper",
+        "offload",
+        "hw_offload",
+    ];
+
+    FLAGS
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| status & (1 << bit) != 0)
+        .map(|(_, flag)| *flag)
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn unmarshal_queue(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbQueueEvent>(raw_section)?;
+
+    fields.push(event_field!("queue_mapping", event.queue_mapping as u32));
+    fields.push(event_field!("hash", event.hash));
+    fields.push(event_field!("l4_hash", event.l4_hash != 0));
+    fields.push(event_field!("cpu", event.cpu));
+    Ok(())
+}
+
+fn unmarshal_ct(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbCtEvent>(raw_section)?;
+
+    let (state, direction) = ct_state(event.state);
+    fields.push(event_field!("ct_state", state.to_string()));
+    if let Some(direction) = direction {
+        fields.push(event_field!("ct_direction", direction.to_string()));
+    }
+    if event.flags & CT_HAS_STATUS != 0 {
+        fields.push(event_field!("ct_status", ct_status(event.status)));
+    }
+
+    if event.flags & CT_HAS_MARK != 0 {
+        fields.push(event_field!("ct_mark", event.mark));
+    }
+    if event.flags & CT_HAS_ZONE != 0 {
+        fields.push(event_field!("ct_zone", event.zone as u32));
+    }
+    Ok(())
+}
+
+fn unmarshal_packet(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let (event, data) = parse_raw_section_var::<SkbPacketEvent>(raw_section)?;
+
+    // The capture length is reset if the packet couldn't be read.
+    let capture_len = event.capture_len as usize;
+    if capture_len > data.len() {
+        bail!(
+            "Packet capture length is bigger than its data {} > {}",
+            capture_len,
+            data.len()
+        );
+    }
+    if capture_len == 0 {
+        return Ok(());
+    }
+
+    fields.push(event_field!("packet_len", event.len));
+    fields.push(event_field!(
+        "packet",
+        data[..capture_len]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    ));
+    if let Some(labels) = mpls_labels(&data[..capture_len]) {
+        fields.push(event_field!("mpls_labels", labels));
+    }
+    if let Some(gtp) = gtp_tunnel(&data[..capture_len]) {
+        fields.push(event_field!("gtp_teid", gtp.teid));
+        fields.push(event_field!("gtp_msg_type", gtp.msg_type as u32));
+        if let Some((saddr, daddr, proto)) = gtp.inner {
+            fields.push(event_field!("gtp_inner_saddr", saddr.to_string()));
+            fields.push(event_field!("gtp_inner_daddr", daddr.to_string()));
+            fields.push(event_field!("gtp_inner_proto", proto as u32));
+        }
+        if let Some((sport, dport)) = gtp.inner_ports {
+            fields.push(event_field!("gtp_inner_sport", sport as u32));
+            fields.push(event_field!("gtp_inner_dport", dport as u32));
+        }
+        if gtp.truncated {
+            fields.push(event_field!("gtp_truncated", true));
+        }
+    }
+    Ok(())
+}
+
+/// Maximum number of VLAN tags skipped when looking for an MPLS label stack.
+const MPLS_VLAN_MAX: usize = 2;
+/// Maximum number of MPLS labels decoded, malformed packets could have no
+/// bottom of stack label.
+const MPLS_MAX: usize = 8;
+
+// Decode the MPLS label stack of an Ethernet packet, if any. Stacks cut by the
+// capture length are reported up to their last complete label.
+fn mpls_labels(packet: &[u8]) -> Option<Vec<serde_json::Value>> {
+    let ethertype = |off: usize| {
+        Some(u16::from_be_bytes([
+            *packet.get(off)?,
+            *packet.get(off + 1)?,
+        ]))
+    };
+
+    let mut off = 12;
+    for _ in 0..MPLS_VLAN_MAX {
+        if !matches!(ethertype(off), Some(0x8100) | Some(0x88a8)) {
+            break;
+        }
+        off += 4;
+    }
+    if !matches!(ethertype(off), Some(0x8847) | Some(0x8848)) {
+        return None;
+    }
+    off += 2;
+
+    let mut labels = Vec::new();
+    while labels.len() < MPLS_MAX {
+        let entry = match packet.get(off..off + 4) {
+            Some(entry) => u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]),
+            None => break,
+        };
+        let bos = entry & 0x100 != 0;
+        labels.push(json!({
+            "label": entry >> 12,
+            "tc": (entry >> 9) & 0x7,
+            "bos": bos,
+            "ttl": entry & 0xff,
+        }));
+        if bos {
+            break;
+        }
+        off += 4;
+    }
+
+    match labels.is_empty() {
+        true => None,
+        false => Some(labels),
+    }
+}
+
+/// UDP port GTP-U is carried over.
+const GTP_U_PORT: u16 = 2152;
+/// GTP-U message type of packets carrying user data (G-PDU).
+const GTP_U_GPDU: u8 = 0xff;
+/// Maximum number of GTP-U extension headers skipped to reach the inner packet.
+const GTP_EXT_MAX: usize = 4;
+
+/// GTP-U tunnel decoded from the captured bytes of a packet.
+#[derive(Default)]
+struct GtpTunnel {
+    teid: u32,
+    msg_type: u8,
+    /// Inner source and destination addresses, and protocol.
+    inner: Option<(IpAddr, IpAddr, u8)>,
+    /// Inner source and destination ports, for TCP and UDP packets.
+    inner_ports: Option<(u16, u16)>,
+    /// The inner packet could not be fully decoded (cut by the capture length,
+    /// too many extension headers, etc).
+    truncated: bool,
+}
+
+fn be16(packet: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *packet.get(off)?,
+        *packet.get(off + 1)?,
+    ]))
+}
+
+// Decode the GTP-U header of an Ethernet packet carried over UDP, if any, and
+// the tuple of the inner packet for G-PDUs. Only the first fragment of the
+// outer packet can be decoded.
+fn gtp_tunnel(packet: &[u8]) -> Option<GtpTunnel> {
+    let mut off = 12;
+    for _ in 0..MPLS_VLAN_MAX {
+        if !matches!(be16(packet, off), Some(0x8100) | Some(0x88a8)) {
+            break;
+        }
+        off += 4;
+    }
+
+    let ip = off + 2;
+    off = match be16(packet, off)? {
+        0x0800 => {
+            let vihl = *packet.get(ip)?;
+            if vihl >> 4 != 4
+                || vihl & 0xf < 5
+                || *packet.get(ip + 9)? != 17
+                || be16(packet, ip + 6)? & 0x1fff != 0
+            {
+                return None;
+            }
+            ip + (vihl & 0xf) as usize * 4
+        }
+        // IPv6 extension headers are not walked here.
+        0x86dd => {
+            if *packet.get(ip)? >> 4 != 6 || *packet.get(ip + 6)? != 17 {
+                return None;
+            }
+            ip + 40
+        }
+        _ => return None,
+    };
+    if be16(packet, off)? != GTP_U_PORT && be16(packet, off + 2)? != GTP_U_PORT {
+        return None;
+    }
+    off += 8;
+
+    // GTPv1 header, with the protocol type bit set (GTP, not GTP').
+    let header = packet.get(off..off + 8)?;
+    if header[0] >> 5 != 1 || header[0] & 0x10 == 0 {
+        return None;
+    }
+    let mut gtp = GtpTunnel {
+        teid: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+        msg_type: header[1],
+        ..Default::default()
+    };
+    if gtp.msg_type == GTP_U_GPDU {
+        gtp.truncated = gtp_inner(packet, header[0], off + 8, &mut gtp).is_none();
+    }
+    Some(gtp)
+}
+
+// Decode the tuple of the packet carried by a G-PDU, starting right after the
+// mandatory GTP-U header. Returns None if decoding stopped early.
+fn gtp_inner(packet: &[u8], flags: u8, mut off: usize, gtp: &mut GtpTunnel) -> Option<()> {
+    // Sequence number, N-PDU number and next extension header type, present
+    // if any of the E, S or PN flags is set.
+    if flags & 0x7 != 0 {
+        let mut next = if flags & 0x4 != 0 {
+            *packet.get(off + 3)?
+        } else {
+            0
+        };
+        off += 4;
+        for _ in 0..GTP_EXT_MAX {
+            if next == 0 {
+                break;
+            }
+            // Extension headers length is in 4 bytes units, including the
+            // length and next extension header type bytes.
+            let len = *packet.get(off)? as usize * 4;
+            if len == 0 {
+                return None;
+            }
+            next = *packet.get(off + len - 1)?;
+            off += len;
+        }
+        if next != 0 {
+            return None;
+        }
+    }
+
+    let l4 = match *packet.get(off)? >> 4 {
+        4 => {
+            let ip = packet.get(off..off + 20)?;
+            let ihl = (ip[0] & 0xf) as usize * 4;
+            if ihl < 20 {
+                return None;
+            }
+            gtp.inner = Some((
+                IpAddr::from([ip[12], ip[13], ip[14], ip[15]]),
+                IpAddr::from([ip[16], ip[17], ip[18], ip[19]]),
+                ip[9],
+            ));
+            // Only the first fragment holds the L4 header.
+            if u16::from_be_bytes([ip[6], ip[7]]) & 0x1fff != 0 {
+                return Some(());
+            }
+            off + ihl
+        }
+        6 => {
+            let ip = packet.get(off..off + 40)?;
+            let addr = |o: usize| IpAddr::from(<[u8; 16]>::try_from(&ip[o..o + 16]).unwrap());
+            gtp.inner = Some((addr(8), addr(24), ip[6]));
+            off + 40
+        }
+        _ => return None,
+    };
+
+    if matches!(gtp.inner, Some((_, _, 6)) | Some((_, _, 17))) {
+        gtp.inner_ports = Some((be16(packet, l4)?, be16(packet, l4 + 2)?));
+    }
+    Some(())
+}
+
+fn unmarshal_csum(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbCsumEvent>(raw_section)?;
+
+    fields.push(event_field!(
+        "ip_summed",
+        match event.ip_summed {
+            0 => "none",
+            1 => "unnecessary",
+            2 => "complete",
+            _ => "partial",
+        }
+        .to_string()
+    ));
+
+    let l4 = match event.l4_proto {
+        6 => "tcp",
+        17 => "udp",
+        _ => "l4",
+    };
+    let bad = [("ip", event.l3), (l4, event.l4)]
+        .iter()
+        .filter(|(_, status)| *status == CSUM_BAD)
+        .map(|(layer, _)| *layer)
+        .collect::<Vec<_>>();
+
+    // Only report a result if a checksum was actually verified.
+    if event.l3 == CSUM_OK || event.l4 == CSUM_OK || !bad.is_empty() {
+        fields.push(event_field!("csum_ok", bad.is_empty()));
+    }
+    if !bad.is_empty() {
+        fields.push(event_field!("csum_bad", bad.join(",")));
+    }
+
+    match event.l4 {
+        CSUM_OFFLOADED => fields.push(event_field!("offloaded", true)),
+        CSUM_ABSENT => fields.push(event_field!("csum_absent", true)),
+        CSUM_UNVERIFIED | CSUM_OK | CSUM_BAD => (),
+        x => bail!("Unknown checksum status {}", x),
+    }
+    Ok(())
+}
+
+fn unmarshal_l3(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbL3Event>(raw_section)?;
+    let has = |flag| event.flags & flag != 0;
+
+    match event.version {
+        4 => fields.push(event_field!("has_ip_options", has(L3_IP_OPTIONS))),
+        6 => {
+            fields.push(event_field!("has_hbh", has(L3_HBH)));
+            fields.push(event_field!("has_routing_header", has(L3_ROUTING)));
+            fields.push(event_field!("has_fragment_header", has(L3_FRAGMENT)));
+            if has(L3_EXT_TRUNCATED) {
+                fields.push(event_field!("ext_headers_truncated", true));
+            }
+        }
+        x => bail!("Unknown IP version {}", x),
+    }
+    Ok(())
+}
+
+// The packet size is reported by the length section, emitted for the same
+// event.
+fn unmarshal_mtu(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbMtuEvent>(raw_section)?;
+
+    fields.push(event_field!("mtu_drop", true));
+    classify(fields, Category::Drop, Severity::Warn);
+    fields.push(event_field!("mtu", event.mtu));
+    fields.push(event_field!("dev_mtu", event.dev_mtu));
+    Ok(())
+}
+
+// ICMP message description, e.g. "dest_unreach:frag_needed". See
+// include/uapi/linux/icmp.h and icmpv6.h in the kernel.
+fn icmp_name(family: u8, r#type: u8, code: u8) -> String {
+    let (r#type, code) = match (family, r#type) {
+        (ICMP_FAMILY_IPV4, 3) => (
+            "dest_unreach",
+            match code {
+                0 => Some("net_unreach"),
+                1 => Some("host_unreach"),
+                2 => Some("prot_unreach"),
+                3 => Some("port_unreach"),
+                4 => Some("frag_needed"),
+                5 => Some("sr_failed"),
+                9 => Some("net_ano"),
+                10 => Some("host_ano"),
+                13 => Some("pkt_filtered"),
+                _ => None,
+            },
+        ),
+        (ICMP_FAMILY_IPV4, 5) => (
+            "redirect",
+            match code {
+                0 => Some("redir_net"),
+                1 => Some("redir_host"),
+                2 => Some("redir_nettos"),
+                3 => Some("redir_hosttos"),
+                _ => None,
+            },
+        ),
+        (ICMP_FAMILY_IPV4, 11) => (
+            "time_exceeded",
+            match code {
+                0 => Some("exc_ttl"),
+                1 => Some("exc_fragtime"),
+                _ => None,
+            },
+        ),
+        (ICMP_FAMILY_IPV4, 12) => ("parameterprob", None),
+        (ICMP_FAMILY_IPV6, 1) => (
+            "dest_unreach",
+            match code {
+                0 => Some("noroute"),
+                1 => Some("adm_prohibited"),
+                2 => Some("not_neighbour"),
+                3 => Some("addr_unreach"),
+                4 => Some("port_unreach"),
+                5 => Some("policy_fail"),
+                6 => Some("reject_route"),
+                _ => None,
+            },
+        ),
+        (ICMP_FAMILY_IPV6, 2) => ("pkt_toobig", None),
+        (ICMP_FAMILY_IPV6, 3) => (
+            "time_exceed",
+            match code {
+                0 => Some("exc_hoplimit"),
+                1 => Some("exc_fragtime"),
+                _ => None,
+            },
+        ),
+        (ICMP_FAMILY_IPV6, 4) => ("paramprob", None),
+        _ => return format!("{}:{}", r#type, code),
+    };
+
+    match code {
+        Some(code) => format!("{}:{}", r#type, code),
+        None => r#type.to_string(),
+    }
+}
+
+fn unmarshal_icmp(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbIcmpEvent>(raw_section)?;
+
+    let (saddr, daddr) = match event.family {
+        ICMP_FAMILY_IPV4 => {
+            let addr = |a: [u8; 16]| Ipv4Addr::new(a[0], a[1], a[2], a[3]).to_string();
+            (addr(event.saddr), addr(event.daddr))
+        }
+        ICMP_FAMILY_IPV6 => (
+            Ipv6Addr::from(event.saddr).to_string(),
+            Ipv6Addr::from(event.daddr).to_string(),
+        ),
+        family => bail!("Unknown ICMP family {}", family),
+    };
+
+    fields.push(event_field!("icmp_type", event.r#type as u32));
+    fields.push(event_field!("icmp_code", event.code as u32));
+    fields.push(event_field!(
+        "icmp_name",
+        icmp_name(event.family, event.r#type, event.code)
+    ));
+    // Tuple of the packet which triggered the ICMP message.
+    fields.push(event_field!("icmp_orig_saddr", saddr));
+    fields.push(event_field!("icmp_orig_daddr", daddr));
+    fields.push(event_field!("icmp_orig_proto", event.protocol as u32));
+    if event.sport != 0 || event.dport != 0 {
+        fields.push(event_field!("icmp_orig_sport", event.sport as u32));
+        fields.push(event_field!("icmp_orig_dport", event.dport as u32));
+    }
+    // Parsing of the tuple hit a bound (e.g. too many IPv6 extension headers)
+    // or failed; the fields above might be incomplete.
+    if event.parse_flags & PARSE_TRUNCATED != 0 {
+        fields.push(event_field!("parse_truncated", true));
+    }
+    if event.parse_flags & PARSE_ERROR != 0 {
+        fields.push(event_field!("parse_error", true));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::{
+        bpf::{raw_section, to_event},
+        Event,
+    };
+
+    #[test]
+    fn filter_config() -> Result<()> {
+        let args = SkbCollectorArgs {
+            capture_bytes: 64,
+            ethertype: vec!["ipv4".to_string(), "0x88cc".to_string()],
+            proto: vec!["tcp".to_string(), "icmp".to_string(), "132".to_string()],
+            verify_csum: true,
+            skb_debug: true,
+        };
+        let cfg = SkbConfig::new(&args, None)?;
+        assert!(cfg.n_etypes == 2 && cfg.etypes[..2] == [0x0800, 0x88cc]);
+        assert!(cfg.n_protos == 3 && cfg.protos[..3] == [6, 1, 132]);
+        assert!(cfg.verify_csum == 1);
+        assert!(cfg.debug == 1);
+
+        // No filter by default.
+        let cfg = SkbConfig::new(&SkbCollectorArgs::default(), None)?;
+        assert!(cfg.n_etypes == 0 && cfg.n_protos == 0);
+        assert!(cfg.flow.flags == 0);
+        assert!(cfg.flow_head == 0);
+
+        // Head of flows.
+        let mut cfg = SkbConfig::new(&SkbCollectorArgs::default(), None)?;
+        let mut collect = CollectArgs {
+            flow_head: Some(3),
+            ..Default::default()
+        };
+        cfg.set_flow_head(&collect);
+        assert!(cfg.flow_head == 3 && cfg.flow_bidir == 0 && cfg.flow_timeout == 0);
+        collect = CollectArgs {
+            new_flows_only: true,
+            ..Default::default()
+        };
+        cfg.set_flow_head(&collect);
+        assert!(cfg.flow_head == 1 && cfg.flow_bidir == 1);
+        assert!(cfg.flow_timeout == NEW_FLOWS_TIMEOUT);
+        collect.flow_timeout = Some(5);
+        cfg.set_flow_head(&collect);
+        assert!(cfg.flow_timeout == 5);
+
+        // Flow filter.
+        let flow = Flow::parse("10.0.0.1:1234-*:443/tcp")?;
+        let cfg = SkbConfig::new(&SkbCollectorArgs::default(), Some(&flow))?;
+        assert!(cfg.flow.flags == FLOW_ENABLED | FLOW_HAS_SADDR);
+        assert!(cfg.flow.family == FLOW_FAMILY_IPV4 && cfg.flow.proto == 6);
+        assert!(cfg.flow.saddr[..4] == [10, 0, 0, 1] && cfg.flow.saddr[4..] == [0; 12]);
+        assert!(cfg.flow.daddr == [0; 16]);
+        assert!(cfg.flow.sport == 1234 && cfg.flow.dport == 443);
+
+        // All sections are computed by default.
+        assert!(
+            cfg.sections
+                == (1 << SECTION_PACKET
+                    | 1 << SECTION_CSUM
+                    | 1 << SECTION_L3
+                    | 1 << SECTION_CT
+                    | 1 << SECTION_SOCK) as u32
+        );
+
+        let flow = Flow::parse("*-[2001:db8::2]:*")?;
+        let cfg = SkbConfig::new(&SkbCollectorArgs::default(), Some(&flow))?;
+        assert!(cfg.flow.flags == FLOW_ENABLED | FLOW_HAS_DADDR);
+        assert!(cfg.flow.family == FLOW_FAMILY_IPV6 && cfg.flow.proto == 0);
+        assert!(cfg.flow.daddr[..2] == [0x20, 0x01] && cfg.flow.daddr[15] == 2);
+        assert!(cfg.flow.sport == 0 && cfg.flow.dport == 0);
+
+        assert!(parse_ethertype("ipx").is_err());
+        assert!(parse_ethertype("0x10000").is_err());
+        assert!(parse_proto("256").is_err());
+        assert!(parse_proto("quic").is_err());
+
+        let args = SkbCollectorArgs {
+            proto: vec!["tcp".to_string(); FILTER_MAX + 1],
+            ..Default::default()
+        };
+        assert!(SkbConfig::new(&args, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sections() {
+        let all = section_mask(EXPENSIVE_SECTIONS.iter().map(|(s, _)| *s));
+        let mut args = CollectArgs::default();
+        assert!(expensive_sections(&args) == all);
+
+        // Only the sections of the selected fields are computed.
+        args.fields = vec!["common.timestamp".to_string(), "skb.ct_state".to_string()];
+        assert!(expensive_sections(&args) == 1 << SECTION_CT);
+        // Fields used elsewhere are needed too.
+        args.r#where = Some("csum_ok == false".to_string());
+        args.histogram = vec!["skb.sk_rcvbuf".to_string()];
+        assert!(
+            expensive_sections(&args) == 1 << SECTION_CT | 1 << SECTION_CSUM | 1 << SECTION_SOCK
+        );
+        args.histogram_by = Some("csum_ok".to_string());
+        args.trigger = Some("skb.ct_zone=0".to_string());
+        args.fields = vec!["skb.sk_cookie".to_string()];
+        args.r#where = None;
+        args.histogram = Vec::new();
+        assert!(
+            expensive_sections(&args) == 1 << SECTION_CSUM | 1 << SECTION_CT | 1 << SECTION_SOCK
+        );
+        args.summary = true;
+        assert!(expensive_sections(&args) & 1 << SECTION_PACKET != 0);
+        args.summary = false;
+        args.output_ipfix = Some("10.0.0.1".to_string());
+        assert!(expensive_sections(&args) & 1 << SECTION_PACKET != 0);
+    }
+
+    #[test]
+    fn unmarshal_len_section() -> Result<()> {
+        let section = raw_section(
+            SECTION_LEN,
+            &SkbLenEvent {
+                len: 1500,
+                data_len: 1400,
+                truesize: 2304,
+            },
+        );
+
+        let mut fields = Vec::new();
+        unmarshal_len(&section, &mut fields)?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+
+        assert!(event.get::<u32>(SKB_COLLECTOR, "len")? == Some(&1500));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "data_len")? == Some(&1400));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "truesize")? == Some(&2304));
+
+        // Sections of an unexpected size are rejected.
+        let mut section = section;
+        section.data.pop();
+        assert!(unmarshal_len(&section, &mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_secmark_section() -> Result<()> {
+        let section = raw_section(SECTION_SECMARK, &SkbSecmarkEvent { secmark: 42 });
+
+        let mut fields = Vec::new();
+        unmarshal_secmark(&section, &mut fields)?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+        assert!(event.get::<u32>(SKB_COLLECTOR, "secmark")? == Some(&42));
+
+        let mut section = section;
+        section.data.pop();
+        assert!(unmarshal_secmark(&section, &mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_dev_section() -> Result<()> {
+        let section = raw_section(SECTION_DEV, &SkbDevEvent { ifindex: 3 });
+
+        let mut fields = Vec::new();
+        unmarshal_dev(&section, &mut fields)?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+        assert!(event.get::<u32>(SKB_COLLECTOR, "ifindex")? == Some(&3));
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_debug_section() -> Result<()> {
+        let section = raw_section(
+            SECTION_DEBUG,
+            &SkbDebugEvent {
+                data: 64,
+                tail: 162,
+                end: 192,
+                mac_header: 64,
+                network_header: 78,
+                transport_header: u16::MAX,
+                protocol: 0x0800,
+                pkt_type: 0,
+                flags: DEBUG_CLONED,
+            },
+        );
+
+        let mut fields = Vec::new();
+        unmarshal_debug(&section, &mut fields)?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+
+        assert!(event.get::<u32>(SKB_COLLECTOR, "data_offset")? == Some(&64));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "tail_offset")? == Some(&162));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "network_header")? == Some(&78));
+        assert!(event
+            .get::<u32>(SKB_COLLECTOR, "transport_header")?
+            .is_none());
+        assert!(event.get::<u32>(SKB_COLLECTOR, "protocol")? == Some(&0x0800));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "cloned")? == Some(&true));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "nohdr")? == Some(&false));
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_queue_section() -> Result<()> {
+        let section = raw_section(
+            SECTION_QUEUE,
+            &SkbQueueEvent {
+                hash: 0xdeadbeef,
+                queue_mapping: 3,
+                l4_hash: 1,
+                cpu: 5,
+            },
+        );
+
+        let mut fields = Vec::new();
+        unmarshal_queue(&section, &mut fields)?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+
+        assert!(event.get::<u32>(SKB_COLLECTOR, "queue_mapping")? == Some(&3));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "hash")? == Some(&0xdeadbeef));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "l4_hash")? == Some(&true));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "cpu")? == Some(&5));
+
+        let mut section = section;
+        section.data.pop();
+        assert!(unmarshal_queue(&section, &mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_packet_section() -> Result<()> {
+        let mut section = raw_section(
+            SECTION_PACKET,
+            &SkbPacketEvent {
+                len: 1500,
+                capture_len: 4,
+            },
+        );
+        section.data.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+        let mut fields = Vec::new();
+        unmarshal_packet(&section, &mut fields)?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+
+        assert!(event.get::<u32>(SKB_COLLECTOR, "packet_len")? == Some(&1500));
+        assert!(event.get::<String>(SKB_COLLECTOR, "packet")? == Some(&"deadbeef".to_string()));
+
+        // Sections holding less data than advertised are rejected.
+        section.data.pop();
+        assert!(unmarshal_packet(&section, &mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mpls() {
+        // Ethernet header, followed by MPLS labels and an IPv4 header.
+        let packet = |ethertype: &[u8], labels: &[u32]| {
+            let mut packet = vec![0; 12];
+            packet.extend_from_slice(ethertype);
+            labels
+                .iter()
+                .for_each(|l| packet.extend_from_slice(&l.to_be_bytes()));
+            packet.extend_from_slice(&[0x45, 0, 0, 20]);
+            packet
+        };
+
+        // Label 100, tc 5, bottom of stack, ttl 64.
+        let single = packet(&[0x88, 0x47], &[100 << 12 | 5 << 9 | 0x100 | 64]);
+        assert!(
+            mpls_labels(&single)
+                == Some(vec![json!({"label": 100, "tc": 5, "bos": true, "ttl": 64})])
+        );
+
+        // Multicast, behind a VLAN tag.
+        let stack = packet(
+            &[0x81, 0x00, 0x00, 0x0a, 0x88, 0x48],
+            &[16 << 12 | 255, 17 << 12 | 254, 1048575 << 12 | 0x100 | 1],
+        );
+        let labels = mpls_labels(&stack).unwrap();
+        assert!(labels.len() == 3);
+        assert!(labels[0]["label"] == 16 && labels[0]["bos"] == false && labels[0]["ttl"] == 255);
+        assert!(labels[2]["label"] == 1048575 && labels[2]["bos"] == true);
+
+        // Stacks without a bottom of stack label are bounded, truncated ones
+        // are reported up to their last label.
+        let looping = packet(&[0x88, 0x47], &[42 << 12; MPLS_MAX + 4]);
+        assert!(mpls_labels(&looping).unwrap().len() == MPLS_MAX);
+        assert!(mpls_labels(&stack[..26]).unwrap().len() == 2);
+        assert!(mpls_labels(&stack[..20]).is_none());
+
+        // Not MPLS.
+        assert!(mpls_labels(&packet(&[0x08, 0x00], &[])).is_none());
+        assert!(mpls_labels(&[0x45]).is_none());
+    }
+
+    #[test]
+    fn gtp() -> Result<()> {
+        // Ethernet, IPv4 and UDP headers followed by a GTP-U header, its
+        // optional fields and an inner IPv4 UDP packet.
+        let packet = |port: u16, gtp: &[u8]| {
+            let mut packet = vec![0; 12];
+            packet.extend_from_slice(&[0x08, 0x00]);
+            packet.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0]);
+            packet.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+            packet.extend_from_slice(&port.to_be_bytes());
+            packet.extend_from_slice(&port.to_be_bytes());
+            packet.extend_from_slice(&[0; 4]);
+            packet.extend_from_slice(gtp);
+            packet.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0]);
+            packet.extend_from_slice(&[192, 168, 0, 1, 192, 168, 0, 2]);
+            packet.extend_from_slice(&[0x30, 0x39, 0, 53, 0, 0, 0, 0]);
+            packet
+        };
+        let inner = Some((
+            IpAddr::from([192, 168, 0, 1]),
+            IpAddr::from([192, 168, 0, 2]),
+            17,
+        ));
+
+        // G-PDU, TEID 0x1234.
+        let gpdu = packet(GTP_U_PORT, &[0x30, 0xff, 0, 28, 0, 0, 0x12, 0x34]);
+        let gtp = gtp_tunnel(&gpdu).unwrap();
+        assert!(gtp.teid == 0x1234 && gtp.msg_type == GTP_U_GPDU);
+        assert!(gtp.inner == inner && gtp.inner_ports == Some((12345, 53)));
+        assert!(!gtp.truncated);
+
+        // With a PDU session container extension header.
+        let ext = packet(
+            GTP_U_PORT,
+            &[
+                0x34, 0xff, 0, 36, 0, 0, 0, 1, 0, 0, 0, 0x85, 1, 0x10, 0x01, 0,
+            ],
+        );
+        let gtp = gtp_tunnel(&ext).unwrap();
+        assert!(gtp.teid == 1 && gtp.inner_ports == Some((12345, 53)));
+
+        // Extension headers chains are bounded.
+        let mut looping = vec![0x34, 0xff, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0x85];
+        (0..GTP_EXT_MAX).for_each(|_| looping.extend_from_slice(&[1, 0, 0, 0x85]));
+        let gtp = gtp_tunnel(&packet(GTP_U_PORT, &looping)).unwrap();
+        assert!(gtp.inner.is_none() && gtp.truncated);
+
+        // Cut in the inner L4 header, or in the GTP-U optional fields.
+        let gtp = gtp_tunnel(&gpdu[..gpdu.len() - 6]).unwrap();
+        assert!(gtp.inner == inner && gtp.inner_ports.is_none() && gtp.truncated);
+        let gtp = gtp_tunnel(&ext[..52]).unwrap();
+        assert!(gtp.teid == 1 && gtp.inner.is_none() && gtp.truncated);
+        assert!(gtp_tunnel(&gpdu[..48]).is_none());
+
+        // Echo request, nothing to decode past the header.
+        let gtp = gtp_tunnel(&packet(
+            GTP_U_PORT,
+            &[0x32, 1, 0, 4, 0, 0, 0, 0, 0, 1, 0, 0],
+        ))
+        .unwrap();
+        assert!(gtp.msg_type == 1 && gtp.inner.is_none() && !gtp.truncated);
+
+        // Not GTP-U: other port, GTP' and non-IP packets.
+        assert!(gtp_tunnel(&packet(53, &[0x30, 0xff, 0, 28, 0, 0, 0x12, 0x34])).is_none());
+        assert!(gtp_tunnel(&packet(GTP_U_PORT, &[0x20, 0xff, 0, 28, 0, 0, 0x12, 0x34])).is_none());
+        let mut arp = gpdu.clone();
+        arp[12..14].copy_from_slice(&[0x08, 0x06]);
+        assert!(gtp_tunnel(&arp).is_none());
+
+        // Fields reported in the packet section.
+        let mut section = raw_section(
+            SECTION_PACKET,
+            &SkbPacketEvent {
+                len: gpdu.len() as u32,
+                capture_len: gpdu.len() as u32,
+            },
+        );
+        section.data.extend_from_slice(&gpdu);
+        let mut fields = Vec::new();
+        unmarshal_packet(&section, &mut fields)?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+
+        assert!(event.get::<u32>(SKB_COLLECTOR, "gtp_teid")? == Some(&0x1234));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "gtp_msg_type")? == Some(&255));
+        assert!(
+            event.get::<String>(SKB_COLLECTOR, "gtp_inner_saddr")?
+                == Some(&"192.168.0.1".to_string())
+        );
+        assert!(event.get::<u32>(SKB_COLLECTOR, "gtp_inner_dport")? == Some(&53));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "gtp_truncated")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_csum_section() -> Result<()> {
+        let csum = |l3, l4, l4_proto| -> Result<Event> {
+            let mut fields = Vec::new();
+            unmarshal_csum(
+                &raw_section(
+                    SECTION_CSUM,
+                    &SkbCsumEvent {
+                        ip_summed: 3,
+                        l3,
+                        l4,
+                        l4_proto,
+                    },
+                ),
+                &mut fields,
+            )?;
+            to_event(SKB_COLLECTOR, fields)
+        };
+
+        let event = csum(CSUM_OK, CSUM_OK, 6)?;
+        assert!(event.get::<String>(SKB_COLLECTOR, "ip_summed")? == Some(&"partial".to_string()));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "csum_ok")? == Some(&true));
+        assert!(event.get::<String>(SKB_COLLECTOR, "csum_bad")?.is_none());
+
+        let event = csum(CSUM_OK, CSUM_BAD, 17)?;
+        assert!(event.get::<bool>(SKB_COLLECTOR, "csum_ok")? == Some(&false));
+        assert!(event.get::<String>(SKB_COLLECTOR, "csum_bad")? == Some(&"udp".to_string()));
+
+        let event = csum(CSUM_OK, CSUM_OFFLOADED, 6)?;
+        assert!(event.get::<bool>(SKB_COLLECTOR, "csum_ok")? == Some(&true));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "offloaded")? == Some(&true));
+
+        // Nothing verified, e.g. IPv6 packets whose segment wasn't captured.
+        let event = csum(CSUM_UNVERIFIED, CSUM_UNVERIFIED, 6)?;
+        assert!(event.get::<bool>(SKB_COLLECTOR, "csum_ok")?.is_none());
+        assert!(event.get::<bool>(SKB_COLLECTOR, "offloaded")?.is_none());
+
+        assert!(csum(CSUM_OK, 42, 6).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_l3_section() -> Result<()> {
+        let l3 = |version, flags| -> Result<Event> {
+            let mut fields = Vec::new();
+            unmarshal_l3(
+                &raw_section(SECTION_L3, &SkbL3Event { version, flags }),
+                &mut fields,
+            )?;
+            to_event(SKB_COLLECTOR, fields)
+        };
+
+        // IPv4 with options.
+        let event = l3(4, L3_IP_OPTIONS)?;
+        assert!(event.get::<bool>(SKB_COLLECTOR, "has_ip_options")? == Some(&true));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "has_hbh")?.is_none());
+        let event = l3(4, 0)?;
+        assert!(event.get::<bool>(SKB_COLLECTOR, "has_ip_options")? == Some(&false));
+
+        // IPv6 with hop-by-hop options and a fragment header.
+        let event = l3(6, L3_HBH | L3_FRAGMENT)?;
+        assert!(event.get::<bool>(SKB_COLLECTOR, "has_hbh")? == Some(&true));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "has_routing_header")? == Some(&false));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "has_fragment_header")? == Some(&true));
+        assert!(event
+            .get::<bool>(SKB_COLLECTOR, "has_ip_options")?
+            .is_none());
+        assert!(event
+            .get::<bool>(SKB_COLLECTOR, "ext_headers_truncated")?
+            .is_none());
+
+        // IPv6 with a routing header, and too many extension headers to walk.
+        let event = l3(6, L3_ROUTING | L3_EXT_TRUNCATED)?;
+        assert!(event.get::<bool>(SKB_COLLECTOR, "has_routing_header")? == Some(&true));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "ext_headers_truncated")? == Some(&true));
+
+        assert!(l3(5, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_mtu_section() -> Result<()> {
+        let section = raw_section(
+            SECTION_MTU,
+            &SkbMtuEvent {
+                mtu: 1400,
+                dev_mtu: 1500,
+            },
+        );
+
+        let mut fields = Vec::new();
+        unmarshal_mtu(&section, &mut fields)?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+
+        assert!(event.get::<bool>(SKB_COLLECTOR, "mtu_drop")? == Some(&true));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "mtu")? == Some(&1400));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "dev_mtu")? == Some(&1500));
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_icmp_section() -> Result<()> {
+        let mut saddr = [0; 16];
+        saddr[..4].copy_from_slice(&[10, 0, 0, 1]);
+        let mut daddr = [0; 16];
+        daddr[..4].copy_from_slice(&[10, 0, 0, 2]);
+
+        let section = raw_section(
+            SECTION_ICMP,
+            &SkbIcmpEvent {
+                family: ICMP_FAMILY_IPV4,
+                r#type: 3,
+                code: 4,
+                protocol: 17,
+                saddr,
+                daddr,
+                sport: 4242,
+                dport: 53,
+                parse_flags: 0,
+            },
+        );
+
+        let mut fields = Vec::new();
+        unmarshal_icmp(&section, &mut fields)?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+
+        assert!(event.get::<u32>(SKB_COLLECTOR, "icmp_type")? == Some(&3));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "icmp_code")? == Some(&4));
+        assert!(
+            event.get::<String>(SKB_COLLECTOR, "icmp_name")?
+                == Some(&"dest_unreach:frag_needed".to_string())
+        );
+        assert!(
+            event.get::<String>(SKB_COLLECTOR, "icmp_orig_saddr")? == Some(&"10.0.0.1".to_string())
+        );
+        assert!(
+            event.get::<String>(SKB_COLLECTOR, "icmp_orig_daddr")? == Some(&"10.0.0.2".to_string())
+        );
+        assert!(event.get::<u32>(SKB_COLLECTOR, "icmp_orig_proto")? == Some(&17));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "icmp_orig_dport")? == Some(&53));
+        assert!(event
+            .get::<bool>(SKB_COLLECTOR, "parse_truncated")?
+            .is_none());
+
+        // Packet with too many IPv6 extension headers.
+        let section = raw_section(
+            SECTION_ICMP,
+            &SkbIcmpEvent {
+                family: ICMP_FAMILY_IPV6,
+                r#type: 1,
+                code: 0,
+                protocol: 60,
+                saddr,
+                daddr,
+                sport: 0,
+                dport: 0,
+                parse_flags: PARSE_TRUNCATED,
+            },
+        );
+        let mut fields = Vec::new();
+        unmarshal_icmp(&section, &mut fields)?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+        assert!(event.get::<bool>(SKB_COLLECTOR, "parse_truncated")? == Some(&true));
+        assert!(event.get::<bool>(SKB_COLLECTOR, "parse_error")?.is_none());
+        assert!(event
+            .get::<u32>(SKB_COLLECTOR, "icmp_orig_sport")?
+            .is_none());
+
+        assert!(icmp_name(ICMP_FAMILY_IPV4, 5, 1) == "redirect:redir_host");
+        assert!(icmp_name(ICMP_FAMILY_IPV6, 2, 0) == "pkt_toobig");
+        assert!(icmp_name(ICMP_FAMILY_IPV6, 42, 1) == "42:1");
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_ct_section() -> Result<()> {
+        let mut fields = Vec::new();
+        unmarshal_ct(
+            &raw_section(
+                SECTION_CT,
+                &SkbCtEvent {
+                    mark: 42,
+                    zone: 0,
+                    flags: CT_HAS_MARK | CT_HAS_STATUS,
+                    state: 3,
+                    status: 0xe,
+                },
+            ),
+            &mut fields,
+        )?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+
+        // Fields not available on the running kernel are not reported.
+        assert!(event.get::<u32>(SKB_COLLECTOR, "ct_mark")? == Some(&42));
+        assert!(event.get::<u32>(SKB_COLLECTOR, "ct_zone")?.is_none());
+
+        assert!(
+            event.get::<String>(SKB_COLLECTOR, "ct_state")? == Some(&"established".to_string())
+        );
+        assert!(event.get::<String>(SKB_COLLECTOR, "ct_direction")? == Some(&"reply".to_string()));
+        assert!(
+            event.get::<String>(SKB_COLLECTOR, "ct_status")?
+                == Some(&"seen_reply|assured|confirmed".to_string())
+        );
+
+        // Untracked packets have no conntrack entry.
+        let mut fields = Vec::new();
+        unmarshal_ct(
+            &raw_section(
+                SECTION_CT,
+                &SkbCtEvent {
+                    state: 7,
+                    ..Default::default()
+                },
+            ),
+            &mut fields,
+        )?;
+        let event = to_event(SKB_COLLECTOR, fields)?;
+
+        assert!(event.get::<String>(SKB_COLLECTOR, "ct_state")? == Some(&"untracked".to_string()));
+        assert!(event
+            .get::<String>(SKB_COLLECTOR, "ct_direction")?
+            .is_none());
+        assert!(event.get::<String>(SKB_COLLECTOR, "ct_status")?.is_none());
+        Ok(())
+    }
+}
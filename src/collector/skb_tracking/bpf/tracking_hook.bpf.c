@@ -39,6 +39,12 @@ struct tracking_info {
 	u64 last_seen;
 	/* Original head address; useful when the head is invalidated */
 	u64 orig_head;
+	/* Address of the first skb seen using this data area. Other skbs
+	 * using it are clones of this one.
+	 */
+	u64 skb;
+	/* CPU the skb was first seen on */
+	u32 cpu;
 } __attribute__((packed));
 struct {
 	__uint(type, BPF_MAP_TYPE_HASH);
@@ -53,6 +59,12 @@ struct skb_tracking_event {
 	u64 timestamp;
 	u64 skb;
 	u32 drop_reason;
+	/* Address of the skb this one was cloned from, 0 if not a clone */
+	u64 cloned_from;
+	/* CPU the skb was first seen on */
+	u32 orig_cpu;
+	/* The skb is processed on another CPU than orig_cpu */
+	u8 steered;
 } __attribute__((packed));
 
 /* Must be called with a valid skb pointer */
@@ -65,6 +77,7 @@ static __always_inline int track_skb(struct trace_context *ctx,
 	struct skb_tracking_event *e;
 	struct tracking_config *cfg;
 	u64 head, ksym = ctx->ksym;
+	u32 cpu = bpf_get_smp_processor_id();
 
 	/* Try to retrieve the tracking configuration for this symbol. Only
 	 * specific ones will be found while we want to track skb in all
@@ -106,6 +119,8 @@ static __always_inline int track_skb(struct trace_context *ctx,
 		ti->timestamp = ctx->timestamp;
 		ti->last_seen = ctx->timestamp;
 		ti->orig_head = head;
+		ti->skb = (u64)skb;
+		ti->cpu = cpu;
 
 		/* No need to globally track it if the first time we see this
 		 * skb is when it is freed.
@@ -140,6 +155,15 @@ static __always_inline int track_skb(struct trace_context *ctx,
 	e->timestamp = ti->timestamp;
 	e->skb = (u64)skb;
 	e->drop_reason = drop_reason;
+	/* Clones share the data area of the original skb, thus its tracking
+	 * info, but not its address.
+	 */
+	e->cloned_from = ti->skb != (u64)skb ? ti->skb : 0;
+	/* A packet processed on another CPU than the one it was first seen on
+	 * was steered (e.g. by RPS/RFS) or handed over (e.g. backlog, veth).
+	 */
+	e->orig_cpu = ti->cpu;
+	e->steered = ti->cpu != cpu;
 
 	return 0;
 }
@@ -147,6 +171,10 @@ static __always_inline int track_skb(struct trace_context *ctx,
 DEFINE_HOOK(
 	struct sk_buff *skb;
 
+	/* Tracking information is only useful to events. */
+	if (ctx->count_events)
+		return 0;
+
 	skb = trace_get_sk_buff(ctx);
 	if (!skb)
 		return 0;
@@ -41,6 +41,19 @@
 //! as being the same packet while allowing to distinguish them. One easy way is
 //! to provide the skb own address. We end up reporting `(unique_id, &skb)`.
 //!
+//! The address of the first skb seen using a data area is kept in its tracking
+//! info: events for other skbs using the same data area are reported with a
+//! `cloned_from` field holding it. Copies (e.g. `pskb_copy`) get their own data
+//! area and are thus reported as new packets.
+//!
+//! ## CPUs
+//!
+//! The CPU a packet was first seen on is kept in its tracking info and
+//! reported as `orig_cpu`. Packets processed on another CPU, e.g. steered by
+//! RPS/RFS from the CPU handling the NIC interrupts, are reported with
+//! `steered` set. Together with the skb collector `cpu`, `queue_mapping` and
+//! `hash` fields this gives the RSS/RPS picture of a flow.
+//!
 //! ## Internal tracking
 //!
 //! While the events will report `((original_skb_head << 64 | initial_timestamp), &skb)`
@@ -60,7 +73,10 @@
 //! 2. We don't need to react to clone events as the data address won't change
 //!    and we'll be reusing the unique id. A new skb will show in the logs and
 //!    we'll be able to both identify it as being part of the flow and as being
-//!    a clone (different skb address). Fast clones are not special either.
+//!    a clone (different skb address). Fast clones are not special either. We
+//!    still probe `skb_clone` so the original skb is seen before its clones.
+//!    The clone to original mapping goes away with the tracking info, when
+//!    the data area is freed.
 //!
 //! 3. To track data address modifications we need to map those packets to the
 //!    original unique id. In addition, we can't know the new data location when
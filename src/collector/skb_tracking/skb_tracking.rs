@@ -9,13 +9,17 @@ use super::tracking_hook;
 use crate::event_field;
 use crate::{
     cli::{dynamic::DynamicCommand, CliConfig},
-    collector::Collector,
+    collector::{Collector, InitDeadline},
     core::{
         events::{
-            bpf::{BpfEventOwner, BpfEvents},
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            class::{classify, Category, Severity},
             EventField,
         },
-        probe::kernel::{self, Hook, ProbeType},
+        probe::{
+            kernel::{self, Hook, ProbeType},
+            map_usage::track_map,
+        },
         workaround::SendableMap,
     },
 };
@@ -40,6 +44,9 @@ struct SkbTrackingEvent {
     timestamp: u64,
     skb: u64,
     drop_reason: u32,
+    cloned_from: u64,
+    orig_cpu: u32,
+    steered: u8,
 }
 unsafe impl Plain for SkbTrackingEvent {}
 
@@ -57,6 +64,11 @@ impl Collector for SkbTrackingCollector {
         SKB_TRACKING_COLLECTOR
     }
 
+    // Tracking is bookkeeping other collectors rely on, it comes first.
+    fn priority(&self) -> i32 {
+        i32::MIN
+    }
+
     fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
         cmd.register_module_noargs(SKB_TRACKING_COLLECTOR)
     }
@@ -66,6 +78,7 @@ impl Collector for SkbTrackingCollector {
         _: &CliConfig,
         kernel: &mut kernel::Kernel,
         events: &mut BpfEvents,
+        _: &InitDeadline,
     ) -> Result<()> {
         events.register_unmarshaler(
             BpfEventOwner::CollectorSkbTracking,
@@ -73,24 +86,7 @@ impl Collector for SkbTrackingCollector {
                 if raw_section.header.data_type != 1 {
                     bail!("Unknown data type");
                 }
-
-                if raw_section.data.len() != mem::size_of::<SkbTrackingEvent>() {
-                    bail!(
-                        "Section data is not the expected size {} != {}",
-                        raw_section.data.len(),
-                        mem::size_of::<SkbTrackingEvent>(),
-                    );
-                }
-
-                let mut event = SkbTrackingEvent::default();
-                plain::copy_from_bytes(&mut event, &raw_section.data)
-                    .or_else(|_| bail!("Could not parse the raw section"))?;
-
-                fields.push(event_field!("orig_head", event.orig_head));
-                fields.push(event_field!("timestamp", event.timestamp));
-                fields.push(event_field!("skb", event.skb));
-                fields.push(event_field!("drop_reason", event.drop_reason));
-                Ok(())
+                unmarshal_tracking(raw_section, fields)
             }),
         )?;
 
@@ -122,6 +118,14 @@ impl Collector for SkbTrackingCollector {
     fn start(&mut self) -> Result<()> {
         Ok(())
     }
+
+    fn check(&self, kernel: &kernel::Kernel) -> Result<()> {
+        // Those are mandatory for tracking skbs, see init_tracking().
+        for target in ["skb_free_head", "pskb_expand_head", "skb_clone"] {
+            kernel.inspect.get_ksym(&ProbeType::Kprobe, target)?;
+        }
+        Ok(())
+    }
 }
 
 impl SkbTrackingCollector {
@@ -167,12 +171,17 @@ impl SkbTrackingCollector {
         let mut tracking_config_map = Self::tracking_config_map()?;
         let mut tracking_map = SendableMap::from(Self::tracking_map()?);
         let tracking_fd = tracking_map.get().fd();
+        track_map("tracking_map", tracking_fd)?;
 
-        // Register the tracking hook to all probes.
+        // Register the tracking hook to all probes. It must run before any
+        // hook discarding events (e.g. the skb filters), whatever the
+        // collectors priorities, or the tracking state would miss updates
+        // (e.g. skbs being freed).
         kernel.register_hook(
             Hook::from(tracking_hook::DATA)
                 .reuse_map("tracking_config_map", tracking_config_map.fd())?
                 .reuse_map("tracking_map", tracking_fd)?
+                .priority(i32::MIN)
                 .to_owned(),
         )?;
 
@@ -203,6 +212,13 @@ impl SkbTrackingCollector {
         tracking_config_map.update(&key, cfg, libbpf_rs::MapFlags::NO_EXIST)?;
         kernel.add_probe(ProbeType::Kprobe, "pskb_expand_head")?;
 
+        // Finally make sure the original skb is seen before its clones, so
+        // the clones can be reported as such. Clones share the data area of
+        // the original skb and do not need any special handling otherwise.
+        // Copies (e.g. pskb_copy) have their own data area and are reported as
+        // new skbs.
+        kernel.add_probe(ProbeType::Kprobe, "skb_clone")?;
+
         // Take care of gargabe collection of tracking info. This should be done
         // in the BPF part for most if not all skbs but we might lose some
         // information (and tracked functions might fail resulting in incorrect
@@ -262,11 +278,56 @@ unsafe impl Plain for TrackingConfig {}
 
 // Please keep in sync with its BPF counterpart in bpf/tracking_hook.ebpf.c
 #[derive(Default)]
-#[repr(C)]
+#[repr(C, packed)]
 struct TrackingInfo {
     timestamp: u64,
     last_seen: u64,
     orig_head: u64,
+    skb: u64,
+    cpu: u32,
 }
 
 unsafe impl Plain for TrackingInfo {}
+
+fn unmarshal_tracking(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SkbTrackingEvent>(raw_section)?;
+
+    fields.push(event_field!("orig_head", event.orig_head));
+    fields.push(event_field!("timestamp", event.timestamp));
+    fields.push(event_field!("skb", event.skb));
+    fields.push(event_field!("drop_reason", event.drop_reason));
+    if event.drop_reason != 0 {
+        classify(fields, Category::Drop, Severity::Warn);
+    }
+    if event.cloned_from != 0 {
+        fields.push(event_field!("cloned_from", event.cloned_from));
+    }
+    fields.push(event_field!("orig_cpu", event.orig_cpu));
+    fields.push(event_field!("steered", event.steered != 0));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::bpf::unmarshal_section;
+
+    #[test]
+    fn unmarshal_tracking_section() -> Result<()> {
+        let event = SkbTrackingEvent {
+            orig_head: 0xffff0000,
+            skb: 0xffff1000,
+            drop_reason: 2,
+            cloned_from: 0xffff2000,
+            orig_cpu: 3,
+            steered: 1,
+            ..Default::default()
+        };
+        let event = unmarshal_section(SKB_TRACKING_COLLECTOR, 1, &event, unmarshal_tracking)?;
+
+        assert!(event.get::<u32>(SKB_TRACKING_COLLECTOR, "drop_reason")? == Some(&2));
+        assert!(event.get::<u64>(SKB_TRACKING_COLLECTOR, "cloned_from")? == Some(&0xffff2000));
+        assert!(event.get::<bool>(SKB_TRACKING_COLLECTOR, "steered")? == Some(&true));
+        Ok(())
+    }
+}
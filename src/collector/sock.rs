@@ -0,0 +1,124 @@
+//! # Socket addresses
+//!
+//! Helpers shared by the socket based collectors (sockdrop, tcp-retrans) to
+//! report socket addresses.
+//!
+//! Dual-stack IPv6 sockets carry IPv4 traffic using v4-mapped addresses
+//! (::ffff:a.b.c.d). Such sockets are reported with their effective family
+//! and unmapped addresses, and flagged as `v4_mapped`, so a connection to an
+//! IPv4 host does not show up as an IPv6 one.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use anyhow::{bail, Result};
+
+use crate::{core::events::EventField, event_field};
+
+// Not part of vmlinux.h, see include/linux/socket.h
+pub(super) const AF_INET: u8 = 2;
+pub(super) const AF_INET6: u8 = 10;
+
+/// Add the socket family and addresses to an event, given the socket family
+/// and its raw source and destination addresses (IPv4 ones using the first 4
+/// bytes).
+pub(super) fn push_sock_addrs(
+    fields: &mut Vec<EventField>,
+    family: u8,
+    saddr: [u8; 16],
+    daddr: [u8; 16],
+) -> Result<()> {
+    let v4 = |a: [u8; 16]| Ipv4Addr::new(a[0], a[1], a[2], a[3]);
+
+    let (family, saddr, daddr, mapped) = match family {
+        AF_INET => ("ipv4", v4(saddr).to_string(), v4(daddr).to_string(), false),
+        AF_INET6 => {
+            let (saddr, daddr) = (Ipv6Addr::from(saddr), Ipv6Addr::from(daddr));
+            // An unbound end (e.g. listening socket) is unspecified, not
+            // mapped.
+            let unmap = |a: Ipv6Addr| match a.is_unspecified() {
+                true => Some(Ipv4Addr::UNSPECIFIED),
+                false => a.to_ipv4_mapped(),
+            };
+
+            match (unmap(saddr), unmap(daddr)) {
+                (Some(s), Some(d)) if !(saddr.is_unspecified() && daddr.is_unspecified()) => {
+                    ("ipv4", s.to_string(), d.to_string(), true)
+                }
+                _ => ("ipv6", saddr.to_string(), daddr.to_string(), false),
+            }
+        }
+        family => bail!("Unknown socket family {}", family),
+    };
+
+    fields.push(event_field!("family", family.to_string()));
+    fields.push(event_field!("saddr", saddr));
+    fields.push(event_field!("daddr", daddr));
+    if mapped {
+        fields.push(event_field!("v4_mapped", true));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::{registry::FieldRegistry, Event};
+
+    fn addrs(family: u8, saddr: &str, daddr: &str) -> Result<Event> {
+        let raw = |a: &str| -> [u8; 16] {
+            match a.parse::<Ipv4Addr>() {
+                Ok(a) => {
+                    let mut raw = [0; 16];
+                    raw[..4].copy_from_slice(&a.octets());
+                    raw
+                }
+                Err(_) => a.parse::<Ipv6Addr>().unwrap().octets(),
+            }
+        };
+
+        let mut fields = Vec::new();
+        push_sock_addrs(&mut fields, family, raw(saddr), raw(daddr))?;
+        let mut event = Event::new();
+        fields.into_iter().for_each(|f| event.insert("sock", f));
+        FieldRegistry::get().check(event.fields())?;
+        Ok(event)
+    }
+
+    #[test]
+    fn sock_addrs() -> Result<()> {
+        let get = |event: &Event, key| event.get::<String>("sock", key).unwrap().cloned();
+        let mapped = |event: &Event| event.get::<bool>("sock", "v4_mapped").unwrap().cloned();
+
+        let event = addrs(AF_INET, "10.0.0.1", "10.0.0.2")?;
+        assert!(get(&event, "family") == Some("ipv4".to_string()));
+        assert!(get(&event, "saddr") == Some("10.0.0.1".to_string()));
+        assert!(mapped(&event).is_none());
+
+        let event = addrs(AF_INET6, "2001:db8::1", "2001:db8::2")?;
+        assert!(get(&event, "family") == Some("ipv6".to_string()));
+        assert!(get(&event, "daddr") == Some("2001:db8::2".to_string()));
+        assert!(mapped(&event).is_none());
+
+        // Dual-stack socket carrying IPv4 traffic.
+        let event = addrs(AF_INET6, "::ffff:10.0.0.1", "::ffff:10.0.0.2")?;
+        assert!(get(&event, "family") == Some("ipv4".to_string()));
+        assert!(get(&event, "saddr") == Some("10.0.0.1".to_string()));
+        assert!(get(&event, "daddr") == Some("10.0.0.2".to_string()));
+        assert!(mapped(&event) == Some(true));
+
+        let event = addrs(AF_INET6, "::", "::ffff:10.0.0.2")?;
+        assert!(get(&event, "saddr") == Some("0.0.0.0".to_string()));
+        assert!(mapped(&event) == Some(true));
+
+        // Mixed or unspecified addresses are left as-is.
+        let event = addrs(AF_INET6, "2001:db8::1", "::ffff:10.0.0.2")?;
+        assert!(get(&event, "family") == Some("ipv6".to_string()));
+        assert!(get(&event, "daddr") == Some("::ffff:10.0.0.2".to_string()));
+        let event = addrs(AF_INET6, "::", "::")?;
+        assert!(get(&event, "family") == Some("ipv6".to_string()));
+        assert!(mapped(&event).is_none());
+
+        assert!(addrs(1, "10.0.0.1", "10.0.0.2").is_err());
+        Ok(())
+    }
+}
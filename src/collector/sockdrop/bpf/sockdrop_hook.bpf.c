@@ -0,0 +1,182 @@
+#include <vmlinux.h>
+#include <bpf/bpf_core_read.h>
+#include <bpf/bpf_endian.h>
+
+#include <common.h>
+
+/* Please keep in sync with its Rust counterpart in collector::sockdrop. */
+#define SOCKDROP_RCVBUF		1
+#define SOCKDROP_BACKLOG	2
+
+/* Backlog limit computations, as they changed across kernel versions. Please
+ * keep in sync with its Rust counterpart in collector::sockdrop.
+ */
+/* rcvbuf + sndbuf */
+#define BACKLOG_LIMIT_BASE	0
+/* rcvbuf + sndbuf + 64KB, since v5.0 */
+#define BACKLOG_LIMIT_HEADROOM	1
+/* rcvbuf * 2 + sndbuf / 2 + 64KB, since v6.10 */
+#define BACKLOG_LIMIT_SCALED	2
+
+/* Not part of vmlinux.h, see include/linux/socket.h */
+#define AF_INET		2
+#define AF_INET6	10
+
+/* Sockdrop configuration, indexed in the sockdrop_config_map by the function
+ * or tracepoint ksym address.
+ *
+ * Please keep in sync with its Rust counterpart in collector::sockdrop.
+ */
+struct sockdrop_config {
+	/* Kind of drop reported by the probed function or tracepoint */
+	u8 kind;
+	/* The probe point is hit for all packets being queued, drops have to
+	 * be inferred from the queue occupancy. Otherwise it is only hit on
+	 * drops.
+	 */
+	u8 check;
+	/* BACKLOG_LIMIT_* */
+	u8 backlog_limit;
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, struct sockdrop_config);
+} sockdrop_config_map SEC(".maps");
+
+/* Socket fields availability. Please keep in sync with its Rust counterpart
+ * in collector::sockdrop.
+ */
+#define SOCKDROP_HAS_DROPS	(1 << 0)
+
+/* Please keep in sync with its Rust counterpart in collector::sockdrop. */
+struct sockdrop_event {
+	u8 kind;
+	u8 family;
+	/* sk_protocol */
+	u8 protocol;
+	u8 flags;
+	/* Local and remote addresses, in network order. IPv4 addresses only
+	 * use the first 4 bytes.
+	 */
+	u8 saddr[16];
+	u8 daddr[16];
+	/* Local and remote ports, in host order */
+	u16 sport;
+	u16 dport;
+	/* Receive queue occupancy and size */
+	u32 rmem_alloc;
+	u32 rcvbuf;
+	/* Backlog occupancy and limit, for backlog drops */
+	u32 backlog_len;
+	u32 backlog_limit;
+	/* Size of the dropped packet */
+	u32 truesize;
+	/* Packets dropped by the socket so far */
+	u32 drops;
+	/* Socket cookie, 0 if none was generated yet */
+	u64 cookie;
+} __attribute__((packed));
+
+static __always_inline u32 backlog_limit(struct sock *sk, u8 computation)
+{
+	u32 rcvbuf = BPF_CORE_READ(sk, sk_rcvbuf);
+	u32 sndbuf = BPF_CORE_READ(sk, sk_sndbuf);
+
+	switch (computation) {
+	case BACKLOG_LIMIT_BASE:
+		return rcvbuf + sndbuf;
+	case BACKLOG_LIMIT_SCALED:
+		return (rcvbuf << 1) + (sndbuf >> 1) + 64 * 1024;
+	default:
+		return rcvbuf + sndbuf + 64 * 1024;
+	}
+}
+
+/* Probed functions and tracepoints arguments:
+ *
+ * int __udp_enqueue_schedule_skb(struct sock *sk, struct sk_buff *skb)
+ * bool tcp_add_backlog(struct sock *sk, struct sk_buff *skb, ...)
+ * sock_rcvqueue_full(struct sock *sk, struct sk_buff *skb)
+ *
+ * The functions are hit for all packets being queued, events not describing
+ * a drop are discarded.
+ */
+DEFINE_HOOK(
+	u32 rmem_alloc, rcvbuf, backlog_len = 0, limit = 0;
+	struct sockdrop_config *cfg;
+	struct sockdrop_event *e;
+	struct sock_common *skc;
+	u64 ksym = ctx->ksym;
+	struct sk_buff *skb;
+	struct sock *sk;
+
+	cfg = bpf_map_lookup_elem(&sockdrop_config_map, &ksym);
+	if (!cfg)
+		return 0;
+
+	sk = trace_get_param(ctx, 0, struct sock *);
+	skb = trace_get_param(ctx, 1, struct sk_buff *);
+	if (!sk || !skb)
+		return 0;
+
+	rmem_alloc = BPF_CORE_READ(sk, sk_backlog.rmem_alloc.counter);
+	rcvbuf = BPF_CORE_READ(sk, sk_rcvbuf);
+
+	if (cfg->kind == SOCKDROP_BACKLOG) {
+		backlog_len = BPF_CORE_READ(sk, sk_backlog.len);
+		limit = backlog_limit(sk, cfg->backlog_limit);
+	}
+
+	/* Same checks as __udp_enqueue_schedule_skb and sk_add_backlog. */
+	if (cfg->check) {
+		if (cfg->kind == SOCKDROP_RCVBUF && rmem_alloc <= rcvbuf)
+			return HOOK_DISCARD;
+		if (cfg->kind == SOCKDROP_BACKLOG &&
+		    backlog_len + rmem_alloc <= limit)
+			return HOOK_DISCARD;
+	}
+
+	e = get_event_section(event, COLLECTOR_SOCKDROP, 1, sizeof(*e));
+	if (!e)
+		return 0;
+
+	__builtin_memset(e, 0, sizeof(*e));
+	e->kind = cfg->kind;
+	e->rmem_alloc = rmem_alloc;
+	e->rcvbuf = rcvbuf;
+	e->backlog_len = backlog_len;
+	e->backlog_limit = limit;
+	e->truesize = BPF_CORE_READ(skb, truesize);
+	e->protocol = BPF_CORE_READ_BITFIELD_PROBED(sk, sk_protocol);
+
+	skc = &sk->__sk_common;
+	e->family = BPF_CORE_READ(skc, skc_family);
+	e->sport = BPF_CORE_READ(skc, skc_num);
+	e->dport = bpf_ntohs(BPF_CORE_READ(skc, skc_dport));
+
+	switch (e->family) {
+	case AF_INET:
+		bpf_probe_read_kernel(e->saddr, 4, &skc->skc_rcv_saddr);
+		bpf_probe_read_kernel(e->daddr, 4, &skc->skc_daddr);
+		break;
+	case AF_INET6:
+		bpf_probe_read_kernel(e->saddr, 16, &skc->skc_v6_rcv_saddr);
+		bpf_probe_read_kernel(e->daddr, 16, &skc->skc_v6_daddr);
+		break;
+	}
+
+	/* sk_drops is not available on all kernels. */
+	if (bpf_core_field_exists(sk->sk_drops)) {
+		e->drops = BPF_CORE_READ(sk, sk_drops.counter);
+		e->flags |= SOCKDROP_HAS_DROPS;
+	}
+
+	if (bpf_core_field_exists(sk->__sk_common.skc_cookie))
+		e->cookie = BPF_CORE_READ(sk, __sk_common.skc_cookie.counter);
+
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
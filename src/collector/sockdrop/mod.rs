@@ -0,0 +1,26 @@
+//! # SockdropCollector
+//!
+//! Report packets dropped because a socket receive buffer (UDP) or backlog
+//! (TCP) was full, a common cause of silent losses. Events carry the socket
+//! 4-tuple, its queue occupancy and limit, the size of the dropped packet and
+//! the number of drops of the socket so far. Events carry the socket cookie,
+//! as does the skb collector's socket section, to correlate them with other
+//! events of the same socket. Dual-stack sockets carrying IPv4 traffic are
+//! reported as IPv4, flagged as `v4_mapped`.
+//!
+//! UDP receive buffer drops are reported by probing __udp_enqueue_schedule_skb
+//! and backlog drops by probing tcp_add_backlog, using the same checks as the
+//! kernel on the queue occupancy; TCP backlog drops are approximate, as
+//! packets might still be coalesced with the backlog tail. Sockets queueing
+//! packets using sock_queue_rcv_skb (e.g. UDP on older kernels) are covered by
+//! the sock_rcvqueue_full tracepoint. Probe points not available on the
+//! running kernel are skipped.
+
+// Re-export sockdrop.rs
+#[allow(clippy::module_inception)]
+pub(super) mod sockdrop;
+pub(super) use sockdrop::*;
+
+mod sockdrop_hook {
+    include!("bpf/.out/sockdrop_hook.rs");
+}
@@ -0,0 +1,343 @@
+use std::mem;
+
+use anyhow::{bail, Result};
+use log::warn;
+use plain::Plain;
+
+use super::sockdrop_hook;
+use crate::collector::sock::push_sock_addrs;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            class::{classify, Category, Severity},
+            EventField,
+        },
+        kernel_config::kernel_version,
+        probe::kernel::{self, Hook, ProbeType},
+    },
+};
+
+const SOCKDROP_COLLECTOR: &str = "sockdrop";
+
+// Drop kinds. Please keep in sync with its BPF counterpart in
+// bpf/sockdrop_hook.bpf.c
+const SOCKDROP_RCVBUF: u8 = 1;
+const SOCKDROP_BACKLOG: u8 = 2;
+
+// Backlog limit computations. Please keep in sync with its BPF counterpart in
+// bpf/sockdrop_hook.bpf.c
+const BACKLOG_LIMIT_BASE: u8 = 0;
+const BACKLOG_LIMIT_HEADROOM: u8 = 1;
+const BACKLOG_LIMIT_SCALED: u8 = 2;
+
+// Probed functions and tracepoints, their probe type, their drop kind and
+// whether drops have to be inferred from the queue occupancy (the probe point
+// is hit for all packets being queued).
+const SOCKDROP_TARGETS: &[(&str, ProbeType, u8, bool)] = &[
+    (
+        "__udp_enqueue_schedule_skb",
+        ProbeType::Kprobe,
+        SOCKDROP_RCVBUF,
+        true,
+    ),
+    ("tcp_add_backlog", ProbeType::Kprobe, SOCKDROP_BACKLOG, true),
+    (
+        "sock:sock_rcvqueue_full",
+        ProbeType::RawTracepoint,
+        SOCKDROP_RCVBUF,
+        false,
+    ),
+];
+
+// Socket fields availability. Please keep in sync with its BPF counterpart in
+// bpf/sockdrop_hook.bpf.c
+const SOCKDROP_HAS_DROPS: u8 = 1 << 0;
+
+// Sockdrop event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SockdropEvent {
+    kind: u8,
+    family: u8,
+    protocol: u8,
+    flags: u8,
+    saddr: [u8; 16],
+    daddr: [u8; 16],
+    sport: u16,
+    dport: u16,
+    rmem_alloc: u32,
+    rcvbuf: u32,
+    backlog_len: u32,
+    backlog_limit: u32,
+    truesize: u32,
+    drops: u32,
+    cookie: u64,
+}
+unsafe impl Plain for SockdropEvent {}
+
+// Please keep in sync with its BPF counterpart in bpf/sockdrop_hook.bpf.c
+#[repr(C)]
+struct SockdropConfig {
+    kind: u8,
+    check: u8,
+    backlog_limit: u8,
+}
+unsafe impl Plain for SockdropConfig {}
+
+pub(in crate::collector) struct SockdropCollector {}
+
+impl Collector for SockdropCollector {
+    fn new() -> Result<SockdropCollector> {
+        Ok(SockdropCollector {})
+    }
+
+    fn name(&self) -> &'static str {
+        SOCKDROP_COLLECTOR
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module_noargs(SOCKDROP_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        _: &CliConfig,
+        kernel: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        self.check(kernel)?;
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorSockdrop,
+            Box::new(|raw_section, fields| {
+                if raw_section.header.data_type != 1 {
+                    bail!("Unknown data type");
+                }
+                unmarshal_sockdrop(raw_section, fields)
+            }),
+        )?;
+
+        let backlog_limit = match kernel_version() {
+            Ok(version) => backlog_limit(version),
+            Err(e) => {
+                warn!(
+                    "Could not get the kernel version ({}), TCP backlog drops might be misreported",
+                    e
+                );
+                BACKLOG_LIMIT_HEADROOM
+            }
+        };
+
+        let mut config_map = Self::sockdrop_config_map()?;
+        // Run first, so other hooks do not process events being discarded.
+        let hook = Hook::from(sockdrop_hook::DATA)
+            .reuse_map("sockdrop_config_map", config_map.fd())?
+            .priority(-1)
+            .to_owned();
+
+        for (target, r#type, kind, check) in SOCKDROP_TARGETS.iter() {
+            let ksym = match kernel.inspect.get_ksym(r#type, target) {
+                Ok(ksym) => ksym,
+                Err(e) => {
+                    warn!("Can't probe {}: {}", target, e);
+                    continue;
+                }
+            };
+
+            let cfg = SockdropConfig {
+                kind: *kind,
+                check: *check as u8,
+                backlog_limit,
+            };
+            let cfg = unsafe { plain::as_bytes(&cfg) };
+            config_map.update(&ksym.to_ne_bytes(), cfg, libbpf_rs::MapFlags::NO_EXIST)?;
+
+            kernel.register_hook_to(hook.clone(), *r#type, target)?;
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn check(&self, kernel: &kernel::Kernel) -> Result<()> {
+        if !SOCKDROP_TARGETS
+            .iter()
+            .any(|(target, r#type, _, _)| kernel.inspect.get_ksym(r#type, target).is_ok())
+        {
+            bail!("No socket drop probe point can be probed on this kernel");
+        }
+        Ok(())
+    }
+}
+
+impl SockdropCollector {
+    fn sockdrop_config_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in
+        // bpf/sockdrop_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("sockdrop_config_map"),
+            mem::size_of::<u64>() as u32,
+            mem::size_of::<SockdropConfig>() as u32,
+            kernel::PROBE_MAX as u32,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the sockdrop config map: {}", e))
+    }
+}
+
+/// Backlog limit computation used by tcp_add_backlog, given the kernel
+/// (major, minor) version.
+fn backlog_limit(version: (u32, u32)) -> u8 {
+    match version {
+        v if v < (5, 0) => BACKLOG_LIMIT_BASE,
+        v if v < (6, 10) => BACKLOG_LIMIT_HEADROOM,
+        _ => BACKLOG_LIMIT_SCALED,
+    }
+}
+
+fn unmarshal_sockdrop(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<SockdropEvent>(raw_section)?;
+
+    let kind = match event.kind {
+        SOCKDROP_RCVBUF => "rcvbuf",
+        SOCKDROP_BACKLOG => "backlog",
+        x => bail!("Unknown sockdrop kind {}", x),
+    };
+
+    fields.push(event_field!("kind", kind.to_string()));
+    classify(fields, Category::Drop, Severity::Warn);
+    fields.push(event_field!(
+        "protocol",
+        match event.protocol {
+            6 => "tcp".to_string(),
+            17 => "udp".to_string(),
+            x => x.to_string(),
+        }
+    ));
+    push_sock_addrs(fields, event.family, event.saddr, event.daddr)?;
+    fields.push(event_field!("sport", event.sport as u32));
+    fields.push(event_field!("dport", event.dport as u32));
+    fields.push(event_field!("rmem_alloc", event.rmem_alloc));
+    fields.push(event_field!("rcvbuf", event.rcvbuf));
+    if event.kind == SOCKDROP_BACKLOG {
+        fields.push(event_field!("backlog_len", event.backlog_len));
+        fields.push(event_field!("backlog_limit", event.backlog_limit));
+    }
+    fields.push(event_field!("truesize", event.truesize));
+    if event.flags & SOCKDROP_HAS_DROPS != 0 {
+        fields.push(event_field!("sk_drops", event.drops));
+    }
+    // The cookie is only generated on demand (e.g. by inet_diag).
+    if event.cookie != 0 {
+        fields.push(event_field!("sk_cookie", event.cookie));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collector::sock::{AF_INET, AF_INET6};
+    use crate::core::events::{bpf::unmarshal_section, Event};
+
+    fn unmarshal(event: &SockdropEvent) -> Result<Event> {
+        unmarshal_section(SOCKDROP_COLLECTOR, 1, event, unmarshal_sockdrop)
+    }
+
+    #[test]
+    fn unmarshal_rcvbuf() -> Result<()> {
+        let mut saddr = [0; 16];
+        saddr[..4].copy_from_slice(&[10, 0, 0, 1]);
+
+        let event = unmarshal(&SockdropEvent {
+            kind: SOCKDROP_RCVBUF,
+            family: AF_INET,
+            protocol: 17,
+            flags: SOCKDROP_HAS_DROPS,
+            saddr,
+            sport: 53,
+            rmem_alloc: 213248,
+            rcvbuf: 212992,
+            truesize: 768,
+            drops: 42,
+            cookie: 4242,
+            ..Default::default()
+        })?;
+
+        let get = |key| event.get::<String>(SOCKDROP_COLLECTOR, key);
+        assert!(get("kind")? == Some(&"rcvbuf".to_string()));
+        assert!(get("protocol")? == Some(&"udp".to_string()));
+        assert!(get("saddr")? == Some(&"10.0.0.1".to_string()));
+        assert!(get("daddr")? == Some(&"0.0.0.0".to_string()));
+        assert!(event.get::<u32>(SOCKDROP_COLLECTOR, "sport")? == Some(&53));
+        assert!(event.get::<u32>(SOCKDROP_COLLECTOR, "rmem_alloc")? == Some(&213248));
+        assert!(event.get::<u32>(SOCKDROP_COLLECTOR, "rcvbuf")? == Some(&212992));
+        assert!(event.get::<u32>(SOCKDROP_COLLECTOR, "sk_drops")? == Some(&42));
+        assert!(event.get::<u64>(SOCKDROP_COLLECTOR, "sk_cookie")? == Some(&4242));
+        // Backlog fields are only reported for backlog drops.
+        assert!(event
+            .get::<u32>(SOCKDROP_COLLECTOR, "backlog_len")?
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn unmarshal_backlog() -> Result<()> {
+        let event = unmarshal(&SockdropEvent {
+            kind: SOCKDROP_BACKLOG,
+            family: AF_INET6,
+            protocol: 6,
+            backlog_len: 300000,
+            backlog_limit: 395136,
+            rmem_alloc: 100000,
+            ..Default::default()
+        })?;
+
+        let get = |key| event.get::<String>(SOCKDROP_COLLECTOR, key);
+        assert!(get("kind")? == Some(&"backlog".to_string()));
+        assert!(get("protocol")? == Some(&"tcp".to_string()));
+        assert!(get("saddr")? == Some(&"::".to_string()));
+        assert!(get("family")? == Some(&"ipv6".to_string()));
+        assert!(event.get::<u32>(SOCKDROP_COLLECTOR, "backlog_len")? == Some(&300000));
+        assert!(event.get::<u32>(SOCKDROP_COLLECTOR, "backlog_limit")? == Some(&395136));
+        assert!(event.get::<u32>(SOCKDROP_COLLECTOR, "sk_drops")?.is_none());
+        assert!(event.get::<u64>(SOCKDROP_COLLECTOR, "sk_cookie")?.is_none());
+
+        // Unknown families and kinds are rejected.
+        assert!(unmarshal(&SockdropEvent {
+            kind: SOCKDROP_BACKLOG,
+            family: 1,
+            ..Default::default()
+        })
+        .is_err());
+        assert!(unmarshal(&SockdropEvent {
+            kind: 42,
+            family: AF_INET,
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn limits() {
+        assert!(backlog_limit((4, 19)) == BACKLOG_LIMIT_BASE);
+        assert!(backlog_limit((5, 0)) == BACKLOG_LIMIT_HEADROOM);
+        assert!(backlog_limit((6, 9)) == BACKLOG_LIMIT_HEADROOM);
+        assert!(backlog_limit((6, 10)) == BACKLOG_LIMIT_SCALED);
+    }
+}
@@ -0,0 +1,51 @@
+#include <vmlinux.h>
+#include <bpf/bpf_helpers.h>
+#include <bpf/bpf_endian.h>
+
+#include <events.h>
+
+/* Sockfilter section data types. Please keep in sync with its Rust
+ * counterpart in collector::sockfilter.
+ */
+#define SECTION_SKB	1
+
+/* Please keep in sync with its Rust counterpart in collector::sockfilter. */
+struct sockfilter_skb_event {
+	u64 timestamp;
+	u64 cookie;
+	u32 len;
+	u32 ifindex;
+	u16 protocol;
+	u8 pkt_type;
+} __attribute__((packed));
+
+SEC("socket")
+int sockfilter(struct __sk_buff *skb)
+{
+	struct sockfilter_skb_event *e;
+	struct trace_raw_event *event;
+
+	event = get_event();
+	if (!event)
+		return skb->len;
+
+	e = get_event_section(event, COLLECTOR_SOCKFILTER, SECTION_SKB,
+			      sizeof(*e));
+	if (!e) {
+		discard_event(event);
+		return skb->len;
+	}
+
+	e->timestamp = bpf_ktime_get_ns();
+	e->cookie = bpf_get_socket_cookie(skb);
+	e->len = skb->len;
+	e->ifindex = skb->ifindex;
+	e->protocol = bpf_ntohs(skb->protocol);
+	e->pkt_type = skb->pkt_type;
+
+	send_event(event);
+	/* The return value is the number of bytes to keep, keep them all. */
+	return skb->len;
+}
+
+char __license[] SEC("license") = "GPL";
@@ -0,0 +1,21 @@
+//! # SockfilterCollector
+//!
+//! Capture the traffic of a single process with a low overhead, by attaching
+//! a socket filter (BPF_PROG_TYPE_SOCKET_FILTER) to its sockets instead of
+//! probing the whole system. Sockets are resolved using /proc/<pid>/fd and
+//! duplicated in our process (pidfd_getfd, Linux v5.6) to attach the filter.
+//!
+//! Socket filters only see the packets received by a socket. Like the cgroup
+//! collector, the filter does not rely on kernel probes and hooks: it writes
+//! its own events to the shared events map, which then follow the common
+//! pipeline (unmarshaling, filtering and output). The filter never alters the
+//! packets and is detached when the collector is dropped.
+
+// Re-export sockfilter.rs
+#[allow(clippy::module_inception)]
+pub(super) mod sockfilter;
+pub(super) use sockfilter::*;
+
+mod sockfilter_bpf {
+    include!("bpf/.out/sockfilter.skel.rs");
+}
@@ -0,0 +1,347 @@
+use std::{collections::HashMap, fs, mem, os::unix::io::RawFd};
+
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use log::{info, warn};
+use nix::{errno::Errno, libc, unistd::close};
+use plain::Plain;
+
+use super::sockfilter_bpf::SockfilterSkelBuilder;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            EventField,
+        },
+        kernel_config::Requirements,
+        probe::{get_ebpf_debug, kernel},
+    },
+};
+
+const SOCKFILTER_COLLECTOR: &str = "sockfilter";
+
+// Sockfilter section data types. Please keep in sync with its BPF counterpart
+// in bpf/sockfilter.bpf.c
+const SECTION_SKB: u8 = 1;
+
+// Program attached to the sockets.
+const SOCKFILTER_PROG: &str = "sockfilter";
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct SockfilterCollectorArgs {
+    /// Process whose sockets are captured, by attaching a socket filter to
+    /// them. Only packets received by the sockets are reported. Required by
+    /// the sockfilter collector.
+    #[arg(id = "sockfilter-pid", long)]
+    sockfilter_pid: Option<u32>,
+    /// Only attach to the sockets behind the given file descriptors of the
+    /// process. Comma separated list. All the sockets opened when the
+    /// collection starts are used by default.
+    #[arg(id = "sockfilter-fd", long, value_delimiter = ',')]
+    sockfilter_fd: Vec<RawFd>,
+}
+
+// Skb event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct SockfilterSkbEvent {
+    timestamp: u64,
+    cookie: u64,
+    len: u32,
+    ifindex: u32,
+    protocol: u16,
+    pkt_type: u8,
+}
+unsafe impl Plain for SockfilterSkbEvent {}
+
+/// Socket of the target process, duplicated in ours.
+struct Socket {
+    /// File descriptor in the target process.
+    target_fd: RawFd,
+    inode: u64,
+    /// File descriptor in our process.
+    fd: RawFd,
+    /// Whether the socket filter was attached.
+    attached: bool,
+}
+
+#[derive(Default)]
+pub(in crate::collector) struct SockfilterCollector {
+    sockets: Vec<Socket>,
+    obj: Option<libbpf_rs::Object>,
+}
+
+impl Collector for SockfilterCollector {
+    fn new() -> Result<SockfilterCollector> {
+        Ok(SockfilterCollector::default())
+    }
+
+    fn name(&self) -> &'static str {
+        SOCKFILTER_COLLECTOR
+    }
+
+    fn requirements(&self) -> Requirements {
+        // pidfd_getfd was introduced in Linux v5.6.
+        Requirements {
+            kernel: Some((5, 6)),
+            config: &[],
+        }
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module::<SockfilterCollectorArgs>(SOCKFILTER_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        cli: &CliConfig,
+        _: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        deadline: &InitDeadline,
+    ) -> Result<()> {
+        let args = cli.get_section::<SockfilterCollectorArgs>(SOCKFILTER_COLLECTOR)?;
+        let pid = args
+            .sockfilter_pid
+            .ok_or_else(|| anyhow!("No process given (see --sockfilter-pid)"))?;
+
+        // Scanning /proc/<pid>/fd can block, e.g. on a process being traced
+        // or stuck in the kernel.
+        let fds = args.sockfilter_fd.clone();
+        self.sockets = deadline.run(move || get_sockets(pid, &fds))?;
+        if self.sockets.is_empty() {
+            bail!("No socket found for process {}", pid);
+        }
+
+        // Map socket cookies, as reported by the filter, to the sockets of the
+        // target process.
+        let mut cookies = HashMap::new();
+        for socket in self.sockets.iter() {
+            match socket_cookie(socket.fd) {
+                Ok(cookie) => {
+                    cookies.insert(cookie, (socket.target_fd, socket.inode));
+                }
+                Err(e) => warn!("Can't get the cookie of fd {}: {}", socket.target_fd, e),
+            }
+        }
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorSockfilter,
+            Box::new(
+                move |raw_section, fields| match raw_section.header.data_type {
+                    SECTION_SKB => unmarshal_skb(raw_section, fields, &cookies),
+                    _ => bail!("Unknown data type"),
+                },
+            ),
+        )?;
+
+        let mut skel = SockfilterSkelBuilder::default();
+        skel.obj_builder.debug(get_ebpf_debug());
+        let mut skel = skel.open()?;
+        skel.maps_mut().events_map().reuse_fd(events.map_fd())?;
+        skel.maps_mut()
+            .events_stats_map()
+            .reuse_fd(events.stats_map_fd())?;
+
+        self.obj = Some(skel.obj.load()?);
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let prog_fd = match &self.obj {
+            Some(obj) => obj
+                .prog(SOCKFILTER_PROG)
+                .ok_or_else(|| anyhow!("Couldn't get program {}", SOCKFILTER_PROG))?
+                .fd(),
+            None => bail!("Sockfilter collector is uninitialized"),
+        };
+
+        for socket in self.sockets.iter_mut() {
+            setsockopt_int(socket.fd, libc::SO_ATTACH_BPF, prog_fd).or_else(|e| {
+                bail!(
+                    "Could not attach the socket filter to fd {}: {}",
+                    socket.target_fd,
+                    e
+                )
+            })?;
+            socket.attached = true;
+        }
+
+        info!("Socket filter attached to {} socket(s)", self.sockets.len());
+        Ok(())
+    }
+}
+
+// The filter stays attached to the sockets of the target process after we
+// close our duplicated file descriptors, it must be explicitly detached.
+impl Drop for SockfilterCollector {
+    fn drop(&mut self) {
+        for socket in self.sockets.iter() {
+            if socket.attached {
+                if let Err(e) = setsockopt_int(socket.fd, libc::SO_DETACH_BPF, 0) {
+                    warn!(
+                        "Could not detach the socket filter from fd {}: {}",
+                        socket.target_fd, e
+                    );
+                }
+            }
+            let _ = close(socket.fd);
+        }
+    }
+}
+
+/// Parse the target of a /proc/<pid>/fd/<fd> link, returning the inode of
+/// sockets.
+fn socket_inode(link: &str) -> Option<u64> {
+    link.strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+/// Resolve the sockets of a process using /proc/<pid>/fd, optionally
+/// restricted to a list of file descriptors, and duplicate them in our
+/// process.
+fn get_sockets(pid: u32, fds: &[RawFd]) -> Result<Vec<Socket>> {
+    let mut targets = Vec::new();
+    for entry in fs::read_dir(format!("/proc/{}/fd", pid))? {
+        let entry = entry?;
+        let target_fd = match entry.file_name().to_str().and_then(|x| x.parse().ok()) {
+            Some(fd) => fd,
+            None => continue,
+        };
+        if !fds.is_empty() && !fds.contains(&target_fd) {
+            continue;
+        }
+
+        // File descriptors can be closed while we're looking at them.
+        let link = match fs::read_link(entry.path()) {
+            Ok(link) => link,
+            Err(_) => continue,
+        };
+        match link.to_str().and_then(socket_inode) {
+            Some(inode) => targets.push((target_fd, inode)),
+            None if !fds.is_empty() => bail!("fd {} of process {} is not a socket", target_fd, pid),
+            None => (),
+        }
+    }
+
+    if let Some(fd) = fds.iter().find(|fd| !targets.iter().any(|(x, _)| x == *fd)) {
+        bail!("fd {} of process {} does not exist", fd, pid);
+    }
+
+    let pidfd = Errno::result(unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) })
+        .or_else(|e| bail!("Could not open process {}: {}", pid, e))? as RawFd;
+
+    let mut sockets = Vec::new();
+    let mut ret = Ok(());
+    for (target_fd, inode) in targets {
+        match Errno::result(unsafe { libc::syscall(libc::SYS_pidfd_getfd, pidfd, target_fd, 0) }) {
+            Ok(fd) => sockets.push(Socket {
+                target_fd,
+                inode,
+                fd: fd as RawFd,
+                attached: false,
+            }),
+            Err(e) => {
+                ret = Err(anyhow!(
+                    "Could not get fd {} of process {}: {}",
+                    target_fd,
+                    pid,
+                    e
+                ));
+                break;
+            }
+        }
+    }
+    let _ = close(pidfd);
+
+    if let Err(e) = ret {
+        sockets.iter().for_each(|s| {
+            let _ = close(s.fd);
+        });
+        return Err(e);
+    }
+    Ok(sockets)
+}
+
+fn setsockopt_int(fd: RawFd, opt: libc::c_int, val: libc::c_int) -> Result<()> {
+    Errno::result(unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            opt,
+            &val as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    })?;
+    Ok(())
+}
+
+fn socket_cookie(fd: RawFd) -> Result<u64> {
+    let mut cookie: u64 = 0;
+    let mut len = mem::size_of::<u64>() as libc::socklen_t;
+    Errno::result(unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_COOKIE,
+            &mut cookie as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    })?;
+    Ok(cookie)
+}
+
+fn unmarshal_skb(
+    raw_section: &BpfRawSection,
+    fields: &mut Vec<EventField>,
+    cookies: &HashMap<u64, (RawFd, u64)>,
+) -> Result<()> {
+    let event = parse_raw_section::<SockfilterSkbEvent>(raw_section)?;
+
+    fields.push(event_field!("timestamp", event.timestamp));
+    fields.push(event_field!("len", event.len));
+    fields.push(event_field!("ifindex", event.ifindex));
+    fields.push(event_field!("protocol", event.protocol as u32));
+    fields.push(event_field!("pkt_type", event.pkt_type as u32));
+    fields.push(event_field!("socket_cookie", event.cookie));
+    if let Some((fd, inode)) = cookies.get(&{ event.cookie }) {
+        fields.push(event_field!("fd", *fd as i64));
+        fields.push(event_field!("socket_inode", *inode));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::{bpf::raw_section, registry::FieldRegistry};
+
+    #[test]
+    fn unmarshal_skb_section() -> Result<()> {
+        let raw = SockfilterSkbEvent {
+            cookie: 42,
+            len: 100,
+            ifindex: 2,
+            protocol: 0x0800,
+            ..Default::default()
+        };
+        let mut fields = Vec::new();
+        let cookies = HashMap::from([(42, (3, 1234))]);
+        unmarshal_skb(&raw_section(SECTION_SKB, &raw), &mut fields, &cookies)?;
+        FieldRegistry::get().check(fields.iter().map(|f| (SOCKFILTER_COLLECTOR, f)))?;
+        assert!(fields.iter().any(|f| f.key() == "socket_inode"));
+        Ok(())
+    }
+
+    #[test]
+    fn socket_link() {
+        assert!(socket_inode("socket:[123456]") == Some(123456));
+        assert!(socket_inode("pipe:[123456]").is_none());
+        assert!(socket_inode("/dev/null").is_none());
+        assert!(socket_inode("socket:[abc]").is_none());
+    }
+}
@@ -0,0 +1,76 @@
+#include <vmlinux.h>
+#include <bpf/bpf_helpers.h>
+#include <bpf/bpf_endian.h>
+
+#include <events.h>
+
+/* Tc-bpf section data types. Please keep in sync with its Rust counterpart in
+ * collector::tc_bpf.
+ */
+#define SECTION_SKB	1
+
+/* Directions. Please keep in sync with their Rust counterparts in
+ * collector::tc_bpf.
+ */
+#define DIRECTION_INGRESS	1
+#define DIRECTION_EGRESS	2
+
+/* Let the packet continue through the other filters, see TC_ACT_UNSPEC. */
+#define TC_ACT_UNSPEC	-1
+
+/* Please keep in sync with its Rust counterpart in collector::tc_bpf. */
+struct tc_bpf_skb_event {
+	u64 timestamp;
+	u32 ifindex;
+	u32 len;
+	u32 mark;
+	u32 hash;
+	u16 protocol;
+	u16 queue_mapping;
+	u8 direction;
+} __attribute__((packed));
+
+static __always_inline void tc_bpf_report(struct __sk_buff *skb, u8 direction)
+{
+	struct tc_bpf_skb_event *e;
+	struct trace_raw_event *event;
+
+	event = get_event();
+	if (!event)
+		return;
+
+	e = get_event_section(event, COLLECTOR_TC_BPF, SECTION_SKB, sizeof(*e));
+	if (!e) {
+		discard_event(event);
+		return;
+	}
+
+	e->timestamp = bpf_ktime_get_ns();
+	e->ifindex = skb->ifindex;
+	e->len = skb->len;
+	e->mark = skb->mark;
+	e->hash = skb->hash;
+	e->protocol = bpf_ntohs(skb->protocol);
+	e->queue_mapping = skb->queue_mapping;
+	e->direction = direction;
+
+	send_event(event);
+}
+
+SEC("tc")
+int tc_bpf_ingress(struct __sk_buff *skb)
+{
+	tc_bpf_report(skb, DIRECTION_INGRESS);
+	/* Never alter the verdict. */
+	return TC_ACT_UNSPEC;
+}
+
+SEC("tc")
+int tc_bpf_egress(struct __sk_buff *skb)
+{
+	tc_bpf_report(skb, DIRECTION_EGRESS);
+	/* Never alter the verdict. */
+	return TC_ACT_UNSPEC;
+}
+
+char __license[] SEC("license") = "GPL";
@@ -0,0 +1,23 @@
+//! # TcBpfCollector
+//!
+//! Attach classifier programs (BPF_PROG_TYPE_SCHED_CLS) to the tc ingress
+//! and/or egress hooks of a given interface, to observe its packets at that
+//! layer with a lower overhead than probing the whole stack. On ingress
+//! packets are seen right after the device (and XDP, GRO), before the IP
+//! stack; on egress they are seen before being enqueued to the device qdisc.
+//!
+//! Like the cgroup collector, this one does not rely on kernel probes and
+//! hooks: its programs report their own events and never alter the verdict,
+//! letting packets continue through the other filters. The programs are
+//! attached using a clsact qdisc, which is created if the interface doesn't
+//! have one yet. Programs are detached, and the qdisc removed if we created
+//! it, when the collector is dropped.
+
+// Re-export tc_bpf.rs
+#[allow(clippy::module_inception)]
+pub(super) mod tc_bpf;
+pub(super) use tc_bpf::*;
+
+mod tc_bpf_bpf {
+    include!("bpf/.out/tc_bpf.skel.rs");
+}
@@ -0,0 +1,332 @@
+use std::mem;
+
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use log::{info, warn};
+use nix::{errno::Errno, libc, net::if_::if_nametoindex};
+use plain::Plain;
+
+use super::tc_bpf_bpf::TcBpfSkelBuilder;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            EventField,
+        },
+        kernel_config::Requirements,
+        probe::{
+            get_ebpf_debug,
+            kernel::{self, dump, relo},
+        },
+    },
+};
+
+const TC_BPF_COLLECTOR: &str = "tc-bpf";
+
+// Tc-bpf section data types. Please keep in sync with its BPF counterpart in
+// bpf/tc_bpf.bpf.c
+const SECTION_SKB: u8 = 1;
+
+// Directions. Please keep in sync with their BPF counterparts in
+// bpf/tc_bpf.bpf.c
+const DIRECTION_INGRESS: u8 = 1;
+const DIRECTION_EGRESS: u8 = 2;
+
+// Programs, per direction, and the tc hook they are attached to.
+const TC_PROGS: &[(&str, &str, libbpf_sys::bpf_tc_attach_point)] = &[
+    ("ingress", "tc_bpf_ingress", libbpf_sys::BPF_TC_INGRESS),
+    ("egress", "tc_bpf_egress", libbpf_sys::BPF_TC_EGRESS),
+];
+
+#[derive(Args, Debug, Default)]
+pub(crate) struct TcBpfCollectorArgs {
+    /// Interface to attach the tc programs to, e.g. eth0. Required by the
+    /// tc-bpf collector.
+    #[arg(id = "tc-bpf-interface", long)]
+    tc_bpf_interface: Option<String>,
+    /// Directions to observe the interface packets in.
+    #[arg(id = "tc-bpf-direction", long, default_value = "both", value_parser = ["ingress", "egress", "both"])]
+    tc_bpf_direction: String,
+}
+
+// Skb event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct TcBpfSkbEvent {
+    timestamp: u64,
+    ifindex: u32,
+    len: u32,
+    mark: u32,
+    hash: u32,
+    protocol: u16,
+    queue_mapping: u16,
+    direction: u8,
+}
+unsafe impl Plain for TcBpfSkbEvent {}
+
+#[derive(Default)]
+pub(in crate::collector) struct TcBpfCollector {
+    /// Interface being targeted, and its index.
+    iface: String,
+    ifindex: i32,
+    /// Directions to attach to, as program names.
+    progs: Vec<&'static str>,
+    obj: Option<libbpf_rs::Object>,
+    /// Attached programs: hook and filter options, used for detaching them.
+    attached: Vec<(libbpf_sys::bpf_tc_hook, libbpf_sys::bpf_tc_opts)>,
+    /// Whether we created the clsact qdisc, and must remove it.
+    qdisc_created: bool,
+}
+
+impl Collector for TcBpfCollector {
+    fn new() -> Result<TcBpfCollector> {
+        Ok(TcBpfCollector::default())
+    }
+
+    fn name(&self) -> &'static str {
+        TC_BPF_COLLECTOR
+    }
+
+    fn requirements(&self) -> Requirements {
+        // The clsact qdisc was introduced in Linux v4.5.
+        Requirements {
+            kernel: Some((4, 5)),
+            config: &["CONFIG_NET_CLS_BPF", "CONFIG_NET_SCH_INGRESS"],
+        }
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module::<TcBpfCollectorArgs>(TC_BPF_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        cli: &CliConfig,
+        _: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        let args = cli.get_section::<TcBpfCollectorArgs>(TC_BPF_COLLECTOR)?;
+        let iface = args
+            .tc_bpf_interface
+            .ok_or_else(|| anyhow!("No interface given (see --tc-bpf-interface)"))?;
+
+        self.ifindex = if_nametoindex(iface.as_str())
+            .or_else(|e| bail!("Invalid interface {}: {}", iface, e))?
+            as i32;
+        self.iface = iface;
+        self.progs = TC_PROGS
+            .iter()
+            .filter(|(dir, _, _)| args.tc_bpf_direction == "both" || args.tc_bpf_direction == *dir)
+            .map(|(_, prog, _)| *prog)
+            .collect();
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorTcBpf,
+            Box::new(|raw_section, fields| match raw_section.header.data_type {
+                SECTION_SKB => unmarshal_skb(raw_section, fields),
+                _ => bail!("Unknown data type"),
+            }),
+        )?;
+
+        let mut skel = TcBpfSkelBuilder::default();
+        skel.obj_builder.debug(get_ebpf_debug());
+        let mut skel = skel.open()?;
+        skel.maps_mut().events_map().reuse_fd(events.map_fd())?;
+        skel.maps_mut()
+            .events_stats_map()
+            .reuse_fd(events.stats_map_fd())?;
+
+        // The tc programs are not probes: they are not attached to a kernel
+        // function and do not run hooks, hence not using a probe builder. They
+        // are still loaded and dumped the same way.
+        let open_obj = skel.obj;
+        let obj = relo::load_with_report(|| Ok(open_obj.load()?))?;
+        for (_, name, _) in TC_PROGS.iter() {
+            if let Some(prog) = obj.prog(name) {
+                dump::dump_prog(prog.fd(), &format!("tc:{}.{}", self.iface, name));
+            }
+        }
+
+        self.obj = Some(obj);
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if let Err(e) = self.attach() {
+            // Do not leave a partial setup behind.
+            self.detach();
+            return Err(e);
+        }
+
+        info!(
+            "Tc programs attached to ifindex {} ({})",
+            self.ifindex,
+            self.progs.join(", ")
+        );
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.detach();
+        Ok(())
+    }
+}
+
+impl TcBpfCollector {
+    /// Attach the tc programs, creating the clsact qdisc if needed. Attached
+    /// programs are tracked for detach() to remove them, including on error.
+    fn attach(&mut self) -> Result<()> {
+        let obj = match &self.obj {
+            Some(obj) => obj,
+            None => bail!("Tc-bpf collector is uninitialized"),
+        };
+
+        // Create the clsact qdisc, unless the interface already has one.
+        let mut hook = libbpf_sys::bpf_tc_hook {
+            sz: mem::size_of::<libbpf_sys::bpf_tc_hook>() as libbpf_sys::size_t,
+            ifindex: self.ifindex,
+            attach_point: libbpf_sys::BPF_TC_INGRESS | libbpf_sys::BPF_TC_EGRESS,
+            ..Default::default()
+        };
+        match unsafe { libbpf_sys::bpf_tc_hook_create(&mut hook) } {
+            0 => self.qdisc_created = true,
+            err if err == -libc::EEXIST => (),
+            err => bail!(
+                "Could not create the clsact qdisc: {}",
+                Errno::from_i32(-err)
+            ),
+        }
+
+        for (dir, name, attach_point) in TC_PROGS.iter() {
+            if !self.progs.contains(name) {
+                continue;
+            }
+            let prog = obj
+                .prog(name)
+                .ok_or_else(|| anyhow!("Couldn't get program {}", name))?;
+
+            hook.attach_point = *attach_point;
+            // Handle and priority are chosen by the kernel, so existing
+            // filters are never replaced.
+            let mut opts = libbpf_sys::bpf_tc_opts {
+                sz: mem::size_of::<libbpf_sys::bpf_tc_opts>() as libbpf_sys::size_t,
+                prog_fd: prog.fd(),
+                ..Default::default()
+            };
+            let err = unsafe { libbpf_sys::bpf_tc_attach(&hook, &mut opts) };
+            if err != 0 {
+                bail!(
+                    "Could not attach to the tc {} hook: {}",
+                    dir,
+                    Errno::from_i32(-err)
+                );
+            }
+            self.attached.push((hook, opts));
+        }
+        Ok(())
+    }
+
+    /// Detach the tc programs and remove the clsact qdisc if we created it.
+    /// Failures are only reported, as there is nothing more we can do.
+    fn detach(&mut self) {
+        for (hook, opts) in self.attached.drain(..) {
+            // Filters are designated by their handle and priority only.
+            let opts = libbpf_sys::bpf_tc_opts {
+                sz: opts.sz,
+                handle: opts.handle,
+                priority: opts.priority,
+                ..Default::default()
+            };
+            let err = unsafe { libbpf_sys::bpf_tc_detach(&hook, &opts) };
+            if err != 0 {
+                warn!(
+                    "Could not detach the tc program from ifindex {}: {}",
+                    self.ifindex,
+                    Errno::from_i32(-err)
+                );
+            }
+        }
+
+        if self.qdisc_created {
+            self.qdisc_created = false;
+            let mut hook = libbpf_sys::bpf_tc_hook {
+                sz: mem::size_of::<libbpf_sys::bpf_tc_hook>() as libbpf_sys::size_t,
+                ifindex: self.ifindex,
+                attach_point: libbpf_sys::BPF_TC_INGRESS | libbpf_sys::BPF_TC_EGRESS,
+                ..Default::default()
+            };
+            let err = unsafe { libbpf_sys::bpf_tc_hook_destroy(&mut hook) };
+            if err != 0 {
+                warn!(
+                    "Could not remove the clsact qdisc from ifindex {}: {}",
+                    self.ifindex,
+                    Errno::from_i32(-err)
+                );
+            }
+        }
+    }
+}
+
+// Tc filters stay attached to the interface once we exit, they must be
+// explicitly detached. This is done when the collector is stopped, and here as
+// a fallback on error paths.
+impl Drop for TcBpfCollector {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+fn unmarshal_skb(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<TcBpfSkbEvent>(raw_section)?;
+
+    let direction = match event.direction {
+        DIRECTION_INGRESS => "ingress",
+        DIRECTION_EGRESS => "egress",
+        x => bail!("Unknown direction {}", x),
+    };
+
+    fields.push(event_field!("timestamp", event.timestamp));
+    fields.push(event_field!("direction", direction.to_string()));
+    fields.push(event_field!("ifindex", event.ifindex));
+    fields.push(event_field!("len", event.len));
+    fields.push(event_field!("mark", event.mark));
+    fields.push(event_field!("hash", event.hash));
+    fields.push(event_field!("protocol", event.protocol as u32));
+    fields.push(event_field!("queue_mapping", event.queue_mapping as u32));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::{bpf::raw_section, registry::FieldRegistry};
+
+    #[test]
+    fn unmarshal_skb_section() -> Result<()> {
+        let event = TcBpfSkbEvent {
+            timestamp: 42,
+            ifindex: 2,
+            len: 100,
+            protocol: 0x0800,
+            direction: DIRECTION_EGRESS,
+            ..Default::default()
+        };
+        let mut fields = Vec::new();
+        unmarshal_skb(&raw_section(SECTION_SKB, &event), &mut fields)?;
+        FieldRegistry::get().check(fields.iter().map(|f| (TC_BPF_COLLECTOR, f)))?;
+        let fields: Vec<_> = fields.iter().map(|f| f.to_json()).collect();
+        assert!(fields[1] == "egress" && fields[2] == 2 && fields[3] == 100);
+        assert!(fields[6] == 0x0800);
+
+        let event = TcBpfSkbEvent {
+            direction: 42,
+            ..Default::default()
+        };
+        assert!(unmarshal_skb(&raw_section(SECTION_SKB, &event), &mut Vec::new()).is_err());
+        Ok(())
+    }
+}
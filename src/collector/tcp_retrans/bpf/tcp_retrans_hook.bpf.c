@@ -0,0 +1,246 @@
+#include <vmlinux.h>
+#include <bpf/bpf_core_read.h>
+#include <bpf/bpf_endian.h>
+
+#include <common.h>
+
+/* Please keep in sync with its Rust counterpart in collector::tcp_retrans. */
+#define TCP_RETRANS_SKB		1
+#define TCP_RETRANS_SYNACK	2
+#define TCP_RETRANS_LOSS	3
+#define TCP_RETRANS_CONG_STATE	4
+#define TCP_RETRANS_STATE	5
+
+/* Not part of vmlinux.h, see include/linux/socket.h */
+#define AF_INET		2
+#define AF_INET6	10
+
+/* Tcp-retrans configuration, indexed in the tcp_retrans_config_map by the
+ * function or tracepoint ksym address.
+ *
+ * Please keep in sync with its Rust counterpart in collector::tcp_retrans.
+ */
+struct tcp_retrans_config {
+	/* Kind of the probed function or tracepoint */
+	u8 kind;
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, struct tcp_retrans_config);
+} tcp_retrans_config_map SEC(".maps");
+
+/* Congestion fields availability. Please keep in sync with its Rust
+ * counterpart in collector::tcp_retrans.
+ */
+#define TCP_HAS_CWND	(1 << 0)
+#define TCP_HAS_RTT	(1 << 1)
+#define TCP_HAS_CC	(1 << 2)
+
+/* See TCP_CA_NAME_MAX in include/net/tcp.h */
+#define TCP_CA_NAME_MAX	16
+
+/* Congestion control algorithm name per socket, indexed by the socket
+ * address. The name is only read again when the socket algorithm changes
+ * (e.g. setsockopt(TCP_CONGESTION)) or when the address is reused by
+ * another socket using a different one.
+ */
+struct tcp_cc {
+	u64 ops;
+	char name[TCP_CA_NAME_MAX];
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_LRU_HASH);
+	__uint(max_entries, 8192);
+	__type(key, u64);
+	__type(value, struct tcp_cc);
+} tcp_cc_map SEC(".maps");
+
+/* Please keep in sync with its Rust counterpart in collector::tcp_retrans. */
+struct tcp_retrans_event {
+	u8 kind;
+	u8 family;
+	/* sk_state */
+	u8 state;
+	/* enum tcp_ca_state */
+	u8 ca_state;
+	/* Previous sk_state, on state changes */
+	u8 old_state;
+	/* Local and remote addresses, in network order. IPv4 addresses only
+	 * use the first 4 bytes.
+	 */
+	u8 saddr[16];
+	u8 daddr[16];
+	/* Local and remote ports, in host order */
+	u16 sport;
+	u16 dport;
+	/* Retransmissions of the current packet (or SYN-ACK) */
+	u32 retransmits;
+	/* Total retransmissions of the connection */
+	u32 total_retrans;
+	u32 snd_cwnd;
+	u32 snd_ssthresh;
+	/* Smoothed RTT and its mean deviation, in us (unscaled) */
+	u32 srtt_us;
+	u32 rttvar_us;
+	/* Socket cookie, 0 if none was generated yet */
+	u64 cookie;
+	u8 flags;
+	/* Congestion control algorithm name */
+	char cc[TCP_CA_NAME_MAX];
+} __attribute__((packed));
+
+/* Fill the 4-tuple from a socket common part, shared by full and request
+ * sockets.
+ */
+static __always_inline void tcp_retrans_tuple(struct tcp_retrans_event *e,
+					      struct sock_common *skc)
+{
+	e->family = BPF_CORE_READ(skc, skc_family);
+	e->sport = BPF_CORE_READ(skc, skc_num);
+	e->dport = bpf_ntohs(BPF_CORE_READ(skc, skc_dport));
+
+	switch (e->family) {
+	case AF_INET:
+		bpf_probe_read_kernel(e->saddr, 4, &skc->skc_rcv_saddr);
+		bpf_probe_read_kernel(e->daddr, 4, &skc->skc_daddr);
+		break;
+	case AF_INET6:
+		bpf_probe_read_kernel(e->saddr, 16, &skc->skc_v6_rcv_saddr);
+		bpf_probe_read_kernel(e->daddr, 16, &skc->skc_v6_daddr);
+		break;
+	}
+}
+
+static __always_inline void tcp_retrans_cc(struct tcp_retrans_event *e,
+					   struct inet_connection_sock *icsk)
+{
+	u64 key = (u64)icsk, ops;
+	struct tcp_cc *cc;
+
+	ops = (u64)BPF_CORE_READ(icsk, icsk_ca_ops);
+	if (!ops)
+		return;
+
+	cc = bpf_map_lookup_elem(&tcp_cc_map, &key);
+	if (!cc || cc->ops != ops) {
+		struct tcp_cc new = { .ops = ops };
+
+		if (bpf_probe_read_kernel_str(new.name, sizeof(new.name),
+			(void *)ops + bpf_core_field_offset(struct tcp_congestion_ops, name)) <= 0)
+			return;
+
+		bpf_map_update_elem(&tcp_cc_map, &key, &new, BPF_ANY);
+		__builtin_memcpy(e->cc, new.name, sizeof(e->cc));
+	} else {
+		__builtin_memcpy(e->cc, cc->name, sizeof(e->cc));
+	}
+	e->flags |= TCP_HAS_CC;
+}
+
+static __always_inline void tcp_retrans_sock(struct tcp_retrans_event *e,
+					     struct sock *sk)
+{
+	struct inet_connection_sock *icsk = (void *)sk;
+	struct tcp_sock *tp = (void *)sk;
+
+	tcp_retrans_tuple(e, &sk->__sk_common);
+	e->state = BPF_CORE_READ(sk, __sk_common.skc_state);
+	e->ca_state = BPF_CORE_READ_BITFIELD_PROBED(icsk, icsk_ca_state);
+	e->retransmits = BPF_CORE_READ(icsk, icsk_retransmits);
+	e->total_retrans = BPF_CORE_READ(tp, total_retrans);
+
+	if (bpf_core_field_exists(tp->snd_cwnd)) {
+		e->snd_cwnd = BPF_CORE_READ(tp, snd_cwnd);
+		e->snd_ssthresh = BPF_CORE_READ(tp, snd_ssthresh);
+		e->flags |= TCP_HAS_CWND;
+	}
+
+	/* The kernel RTT estimate, stored scaled (srtt_us << 3 and
+	 * rttvar_us << 2). It is 0 until a first RTT sample was taken.
+	 */
+	if (bpf_core_field_exists(tp->srtt_us) &&
+	    bpf_core_field_exists(tp->rttvar_us)) {
+		e->srtt_us = BPF_CORE_READ(tp, srtt_us) >> 3;
+		e->rttvar_us = BPF_CORE_READ(tp, rttvar_us) >> 2;
+		if (e->srtt_us)
+			e->flags |= TCP_HAS_RTT;
+	}
+
+	tcp_retrans_cc(e, icsk);
+
+	if (bpf_core_field_exists(sk->__sk_common.skc_cookie))
+		e->cookie = BPF_CORE_READ(sk, __sk_common.skc_cookie.counter);
+}
+
+/* Probed functions and tracepoints arguments:
+ *
+ * int tcp_retransmit_skb(struct sock *sk, struct sk_buff *skb, int segs)
+ * void tcp_enter_loss(struct sock *sk)
+ * tcp_retransmit_synack(const struct sock *sk, const struct request_sock *req)
+ * tcp_cong_state_set(struct sock *sk, const u8 ca_state)
+ * inet_sock_set_state(const struct sock *sk, const int oldstate,
+ *                     const int newstate)
+ */
+DEFINE_HOOK(
+	struct tcp_retrans_config *cfg;
+	struct tcp_retrans_event *e;
+	struct request_sock *req;
+	u64 ksym = ctx->ksym;
+	struct sock *sk;
+
+	cfg = bpf_map_lookup_elem(&tcp_retrans_config_map, &ksym);
+	if (!cfg)
+		return 0;
+
+	sk = trace_get_param(ctx, 0, struct sock *);
+	if (!sk)
+		return 0;
+
+	/* Socket state changes are reported for other protocols too. */
+	if (cfg->kind == TCP_RETRANS_STATE &&
+	    BPF_CORE_READ_BITFIELD_PROBED(sk, sk_protocol) != IPPROTO_TCP)
+		return HOOK_DISCARD;
+
+	e = get_event_section(event, COLLECTOR_TCP_RETRANS, 1, sizeof(*e));
+	if (!e)
+		return 0;
+
+	__builtin_memset(e, 0, sizeof(*e));
+	e->kind = cfg->kind;
+
+	switch (cfg->kind) {
+	case TCP_RETRANS_SYNACK:
+		/* The socket is the listener, the connection being established
+		 * is described by the request socket.
+		 */
+		req = trace_get_param(ctx, 1, struct request_sock *);
+		if (!req)
+			return 0;
+
+		tcp_retrans_tuple(e, &req->__req_common);
+		e->state = BPF_CORE_READ(req, __req_common.skc_state);
+		e->retransmits = BPF_CORE_READ(req, num_retrans);
+		e->total_retrans = e->retransmits;
+		break;
+	case TCP_RETRANS_CONG_STATE:
+		tcp_retrans_sock(e, sk);
+		/* The new state isn't set yet when the tracepoint is hit. */
+		e->ca_state = trace_get_param(ctx, 1, u8);
+		break;
+	case TCP_RETRANS_STATE:
+		tcp_retrans_sock(e, sk);
+		/* Likewise, the new state isn't set yet. */
+		e->old_state = trace_get_param(ctx, 1, int);
+		e->state = trace_get_param(ctx, 2, int);
+		break;
+	default:
+		tcp_retrans_sock(e, sk);
+		break;
+	}
+
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
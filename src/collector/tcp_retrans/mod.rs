@@ -0,0 +1,22 @@
+//! # TcpRetransCollector
+//!
+//! Report TCP retransmissions (of data and of SYN-ACKs), losses (RTO),
+//! congestion state changes and connection state changes, with the connection
+//! 4-tuple, its retransmission counters, its congestion window and control
+//! algorithm (`cc_algo`, e.g. cubic or bbr) and the kernel RTT estimate
+//! (`srtt_us` and `rttvar_us`, once a first sample was taken) when available.
+//! This surfaces loss, congestion and latency issues directly, from the
+//! kernel's own measurements, without having to infer them from the packets.
+//! Events carry the socket cookie, as does the skb collector's socket
+//! section, to correlate them with other events (e.g. drops) of the same
+//! connection. Dual-stack sockets carrying IPv4 traffic are reported as IPv4,
+//! flagged as `v4_mapped`.
+
+// Re-export tcp_retrans.rs
+#[allow(clippy::module_inception)]
+pub(super) mod tcp_retrans;
+pub(super) use tcp_retrans::*;
+
+mod tcp_retrans_hook {
+    include!("bpf/.out/tcp_retrans_hook.rs");
+}
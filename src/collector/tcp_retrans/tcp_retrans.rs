@@ -0,0 +1,403 @@
+use std::mem;
+
+use anyhow::{bail, Result};
+use log::warn;
+use plain::Plain;
+
+use super::tcp_retrans_hook;
+use crate::collector::sock::push_sock_addrs;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            EventField,
+        },
+        probe::kernel::{self, Hook, ProbeType},
+    },
+};
+
+const TCP_RETRANS_COLLECTOR: &str = "tcp-retrans";
+
+// Event kinds. Please keep in sync with its BPF counterpart in
+// bpf/tcp_retrans_hook.bpf.c
+const TCP_RETRANS_SKB: u8 = 1;
+const TCP_RETRANS_SYNACK: u8 = 2;
+const TCP_RETRANS_LOSS: u8 = 3;
+const TCP_RETRANS_CONG_STATE: u8 = 4;
+const TCP_RETRANS_STATE: u8 = 5;
+
+// Probed functions and tracepoints, their probe type and their kind. Only
+// tcp_retransmit_skb is mandatory, others depend on the kernel version.
+const TCP_RETRANS_TARGETS: &[(&str, ProbeType, u8)] = &[
+    ("tcp_retransmit_skb", ProbeType::Kprobe, TCP_RETRANS_SKB),
+    ("tcp_enter_loss", ProbeType::Kprobe, TCP_RETRANS_LOSS),
+    (
+        "tcp:tcp_retransmit_synack",
+        ProbeType::RawTracepoint,
+        TCP_RETRANS_SYNACK,
+    ),
+    (
+        "tcp:tcp_cong_state_set",
+        ProbeType::RawTracepoint,
+        TCP_RETRANS_CONG_STATE,
+    ),
+    (
+        "sock:inet_sock_set_state",
+        ProbeType::RawTracepoint,
+        TCP_RETRANS_STATE,
+    ),
+];
+
+// Congestion fields availability. Please keep in sync with its BPF
+// counterpart in bpf/tcp_retrans_hook.bpf.c
+const TCP_HAS_CWND: u8 = 1 << 0;
+const TCP_HAS_RTT: u8 = 1 << 1;
+const TCP_HAS_CC: u8 = 1 << 2;
+
+// See TCP_CA_NAME_MAX in include/net/tcp.h
+const TCP_CA_NAME_MAX: usize = 16;
+
+// Tcp-retrans event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct TcpRetransEvent {
+    kind: u8,
+    family: u8,
+    state: u8,
+    ca_state: u8,
+    old_state: u8,
+    saddr: [u8; 16],
+    daddr: [u8; 16],
+    sport: u16,
+    dport: u16,
+    retransmits: u32,
+    total_retrans: u32,
+    snd_cwnd: u32,
+    snd_ssthresh: u32,
+    srtt_us: u32,
+    rttvar_us: u32,
+    cookie: u64,
+    flags: u8,
+    cc: [u8; TCP_CA_NAME_MAX],
+}
+unsafe impl Plain for TcpRetransEvent {}
+
+// Please keep in sync with its BPF counterpart in bpf/tcp_retrans_hook.bpf.c
+#[repr(C)]
+struct TcpRetransConfig {
+    kind: u8,
+}
+unsafe impl Plain for TcpRetransConfig {}
+
+pub(in crate::collector) struct TcpRetransCollector {}
+
+impl Collector for TcpRetransCollector {
+    fn new() -> Result<TcpRetransCollector> {
+        Ok(TcpRetransCollector {})
+    }
+
+    fn name(&self) -> &'static str {
+        TCP_RETRANS_COLLECTOR
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module_noargs(TCP_RETRANS_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        _: &CliConfig,
+        kernel: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        self.check(kernel)?;
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorTcpRetrans,
+            Box::new(|raw_section, fields| {
+                if raw_section.header.data_type != 1 {
+                    bail!("Unknown data type");
+                }
+                unmarshal_tcp_retrans(raw_section, fields)
+            }),
+        )?;
+
+        let mut config_map = Self::tcp_retrans_config_map()?;
+        let hook = Hook::from(tcp_retrans_hook::DATA)
+            .reuse_map("tcp_retrans_config_map", config_map.fd())?
+            .to_owned();
+
+        for (target, r#type, kind) in TCP_RETRANS_TARGETS.iter() {
+            let ksym = match kernel.inspect.get_ksym(r#type, target) {
+                Ok(ksym) => ksym,
+                Err(e) => {
+                    warn!("Can't probe {}: {}", target, e);
+                    continue;
+                }
+            };
+
+            let cfg = TcpRetransConfig { kind: *kind };
+            let cfg = unsafe { plain::as_bytes(&cfg) };
+            config_map.update(&ksym.to_ne_bytes(), cfg, libbpf_rs::MapFlags::NO_EXIST)?;
+
+            kernel.register_hook_to(hook.clone(), *r#type, target)?;
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn check(&self, kernel: &kernel::Kernel) -> Result<()> {
+        if kernel
+            .inspect
+            .get_ksym(&ProbeType::Kprobe, "tcp_retransmit_skb")
+            .is_err()
+        {
+            bail!("tcp_retransmit_skb can't be probed on this kernel");
+        }
+        Ok(())
+    }
+}
+
+impl TcpRetransCollector {
+    fn tcp_retrans_config_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in
+        // bpf/tcp_retrans_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("tcp_retrans_config_map"),
+            mem::size_of::<u64>() as u32,
+            mem::size_of::<TcpRetransConfig>() as u32,
+            kernel::PROBE_MAX as u32,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the tcp-retrans config map: {}", e))
+    }
+}
+
+/// Name of a TCP state, see include/net/tcp_states.h
+fn tcp_state(state: u8) -> &'static str {
+    match state {
+        1 => "established",
+        2 => "syn_sent",
+        3 => "syn_recv",
+        4 => "fin_wait1",
+        5 => "fin_wait2",
+        6 => "time_wait",
+        7 => "close",
+        8 => "close_wait",
+        9 => "last_ack",
+        10 => "listen",
+        11 => "closing",
+        12 => "new_syn_recv",
+        _ => "unknown",
+    }
+}
+
+/// Name of a congestion control state, see enum tcp_ca_state.
+fn tcp_ca_state(state: u8) -> &'static str {
+    match state {
+        0 => "open",
+        1 => "disorder",
+        2 => "cwr",
+        3 => "recovery",
+        4 => "loss",
+        _ => "unknown",
+    }
+}
+
+fn unmarshal_tcp_retrans(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<TcpRetransEvent>(raw_section)?;
+
+    let kind = match event.kind {
+        TCP_RETRANS_SKB => "retransmit",
+        TCP_RETRANS_SYNACK => "retransmit_synack",
+        TCP_RETRANS_LOSS => "loss",
+        TCP_RETRANS_CONG_STATE => "cong_state",
+        TCP_RETRANS_STATE => "state_change",
+        x => bail!("Unknown tcp-retrans kind {}", x),
+    };
+
+    fields.push(event_field!("kind", kind.to_string()));
+    push_sock_addrs(fields, event.family, event.saddr, event.daddr)?;
+    fields.push(event_field!("sport", event.sport as u32));
+    fields.push(event_field!("dport", event.dport as u32));
+    fields.push(event_field!("state", tcp_state(event.state).to_string()));
+    if event.kind == TCP_RETRANS_STATE {
+        fields.push(event_field!(
+            "old_state",
+            tcp_state(event.old_state).to_string()
+        ));
+    }
+    fields.push(event_field!("retransmits", event.retransmits));
+    fields.push(event_field!("total_retrans", event.total_retrans));
+
+    // Request sockets (SYN-ACKs) have no congestion state.
+    if event.kind != TCP_RETRANS_SYNACK {
+        fields.push(event_field!(
+            "ca_state",
+            tcp_ca_state(event.ca_state).to_string()
+        ));
+    }
+    if event.flags & TCP_HAS_CWND != 0 {
+        fields.push(event_field!("snd_cwnd", event.snd_cwnd));
+        fields.push(event_field!("snd_ssthresh", event.snd_ssthresh));
+    }
+    // No RTT sample was taken yet, e.g. before the connection is established.
+    if event.flags & TCP_HAS_RTT != 0 {
+        fields.push(event_field!("srtt_us", event.srtt_us));
+        fields.push(event_field!("rttvar_us", event.rttvar_us));
+    }
+    if event.flags & TCP_HAS_CC != 0 {
+        let len = event
+            .cc
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(TCP_CA_NAME_MAX);
+        fields.push(event_field!(
+            "cc_algo",
+            String::from_utf8_lossy(&event.cc[..len]).to_string()
+        ));
+    }
+    // The cookie is only generated on demand (e.g. by inet_diag).
+    if event.cookie != 0 {
+        fields.push(event_field!("sk_cookie", event.cookie));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collector::sock::{AF_INET, AF_INET6};
+    use crate::core::events::bpf::{raw_section, unmarshal_section};
+
+    #[test]
+    fn unmarshal() -> Result<()> {
+        let mut saddr = [0; 16];
+        saddr[..4].copy_from_slice(&[10, 0, 0, 1]);
+        let mut daddr = [0; 16];
+        daddr[..4].copy_from_slice(&[10, 0, 0, 2]);
+
+        let event = unmarshal_section(
+            TCP_RETRANS_COLLECTOR,
+            1,
+            &TcpRetransEvent {
+                kind: TCP_RETRANS_SKB,
+                family: AF_INET,
+                state: 1,
+                ca_state: 4,
+                saddr,
+                daddr,
+                sport: 42000,
+                dport: 443,
+                retransmits: 2,
+                total_retrans: 5,
+                snd_cwnd: 1,
+                snd_ssthresh: 7,
+                cookie: 0,
+                flags: TCP_HAS_CWND,
+                ..Default::default()
+            },
+            unmarshal_tcp_retrans,
+        )?;
+
+        let get = |key| event.get::<String>(TCP_RETRANS_COLLECTOR, key);
+        assert!(get("kind")? == Some(&"retransmit".to_string()));
+        assert!(get("saddr")? == Some(&"10.0.0.1".to_string()));
+        assert!(get("daddr")? == Some(&"10.0.0.2".to_string()));
+        assert!(get("state")? == Some(&"established".to_string()));
+        assert!(get("ca_state")? == Some(&"loss".to_string()));
+        assert!(event.get::<u32>(TCP_RETRANS_COLLECTOR, "dport")? == Some(&443));
+        assert!(event.get::<u32>(TCP_RETRANS_COLLECTOR, "total_retrans")? == Some(&5));
+        assert!(event.get::<u32>(TCP_RETRANS_COLLECTOR, "snd_cwnd")? == Some(&1));
+        assert!(event
+            .get::<u32>(TCP_RETRANS_COLLECTOR, "srtt_us")?
+            .is_none());
+        assert!(get("cc_algo")?.is_none());
+        // No cookie was generated for the socket.
+        assert!(event
+            .get::<u64>(TCP_RETRANS_COLLECTOR, "sk_cookie")?
+            .is_none());
+
+        // Dual-stack sockets carrying IPv4 traffic are reported as IPv4.
+        let mut saddr = [0; 16];
+        saddr[10..].copy_from_slice(&[0xff, 0xff, 10, 0, 0, 1]);
+        let mut daddr = [0; 16];
+        daddr[10..].copy_from_slice(&[0xff, 0xff, 10, 0, 0, 2]);
+
+        let event = unmarshal_section(
+            TCP_RETRANS_COLLECTOR,
+            1,
+            &TcpRetransEvent {
+                kind: TCP_RETRANS_SKB,
+                family: AF_INET6,
+                saddr,
+                daddr,
+                ..Default::default()
+            },
+            unmarshal_tcp_retrans,
+        )?;
+
+        let get = |key| event.get::<String>(TCP_RETRANS_COLLECTOR, key);
+        assert!(get("family")? == Some(&"ipv4".to_string()));
+        assert!(get("saddr")? == Some(&"10.0.0.1".to_string()));
+        assert!(get("daddr")? == Some(&"10.0.0.2".to_string()));
+        assert!(event.get::<bool>(TCP_RETRANS_COLLECTOR, "v4_mapped")? == Some(&true));
+
+        // State changes carry the previous state and the RTT estimate.
+        let event = unmarshal_section(
+            TCP_RETRANS_COLLECTOR,
+            1,
+            &TcpRetransEvent {
+                kind: TCP_RETRANS_STATE,
+                family: AF_INET,
+                state: 8,
+                old_state: 1,
+                srtt_us: 1250,
+                rttvar_us: 300,
+                cookie: 4096,
+                flags: TCP_HAS_RTT | TCP_HAS_CC,
+                cc: *b"bbr\0\0\0\0\0\0\0\0\0\0\0\0\0",
+                ..Default::default()
+            },
+            unmarshal_tcp_retrans,
+        )?;
+
+        let get = |key| event.get::<String>(TCP_RETRANS_COLLECTOR, key);
+        assert!(get("kind")? == Some(&"state_change".to_string()));
+        assert!(get("state")? == Some(&"close_wait".to_string()));
+        assert!(get("old_state")? == Some(&"established".to_string()));
+        assert!(event.get::<u32>(TCP_RETRANS_COLLECTOR, "srtt_us")? == Some(&1250));
+        assert!(event.get::<u32>(TCP_RETRANS_COLLECTOR, "rttvar_us")? == Some(&300));
+        assert!(event.get::<u64>(TCP_RETRANS_COLLECTOR, "sk_cookie")? == Some(&4096));
+        assert!(get("cc_algo")? == Some(&"bbr".to_string()));
+
+        // Unknown families are rejected.
+        assert!(unmarshal_tcp_retrans(
+            &raw_section(
+                1,
+                &TcpRetransEvent {
+                    kind: TCP_RETRANS_SKB,
+                    family: 1,
+                    ..Default::default()
+                }
+            ),
+            &mut Vec::new()
+        )
+        .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+#include <vmlinux.h>
+#include <bpf/bpf_core_read.h>
+
+#include <common.h>
+
+/* Please keep in sync with its Rust counterpart in collector::wireguard. */
+#define WG_KIND_XMIT		1
+#define WG_KIND_RECEIVE		2
+#define WG_KIND_ENCRYPTED	3
+#define WG_KIND_DECRYPTED	4
+#define WG_KIND_HANDSHAKE	5
+#define WG_KIND_PURGED		6
+
+/* Number of bytes of the peer public key reported, enough to identify a peer
+ * (its base64 encoding is the prefix shown by `wg show`).
+ */
+#define WG_KEY_PREFIX_LEN	9
+
+/* Not part of vmlinux.h, see include/linux/socket.h */
+#define AF_INET		2
+#define AF_INET6	10
+
+/* WireGuard internal types, not part of the kernel BTF but of the module
+ * one. Only the fields we use are described.
+ */
+struct endpoint___wg {
+	union {
+		struct sockaddr addr;
+		struct sockaddr_in addr4;
+		struct sockaddr_in6 addr6;
+	};
+} __attribute__((preserve_access_index));
+
+struct noise_handshake___wg {
+	u8 remote_static[32];
+} __attribute__((preserve_access_index));
+
+struct wg_peer___wg {
+	struct endpoint___wg endpoint;
+	struct noise_handshake___wg handshake;
+	u64 internal_id;
+} __attribute__((preserve_access_index));
+
+/* WireGuard configuration, indexed in the wireguard_config_map by the
+ * function ksym address.
+ *
+ * Please keep in sync with its Rust counterpart in collector::wireguard.
+ */
+struct wireguard_config {
+	/* Kind of event the probed function represents */
+	u8 kind;
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, struct wireguard_config);
+} wireguard_config_map SEC(".maps");
+
+/* Please keep in sync with its Rust counterpart in collector::wireguard. */
+struct wireguard_event {
+	u64 peer_id;
+	u8 key[WG_KEY_PREFIX_LEN];
+	u8 kind;
+	u16 family;
+	u16 port;
+	u8 addr[16];
+} __attribute__((packed));
+
+static __always_inline void wg_set_endpoint(struct wireguard_event *e,
+					    struct endpoint___wg *endpoint)
+{
+	e->family = BPF_CORE_READ(endpoint, addr.sa_family);
+	switch (e->family) {
+	case AF_INET:
+		e->port = BPF_CORE_READ(endpoint, addr4.sin_port);
+		bpf_core_read(e->addr, 4, &endpoint->addr4.sin_addr);
+		break;
+	case AF_INET6:
+		e->port = BPF_CORE_READ(endpoint, addr6.sin6_port);
+		bpf_core_read(e->addr, 16, &endpoint->addr6.sin6_addr);
+		break;
+	default:
+		/* No endpoint known (yet) */
+		e->family = 0;
+	}
+}
+
+DEFINE_HOOK(
+	struct endpoint___wg *endpoint;
+	struct wireguard_config *cfg;
+	struct wireguard_event *e;
+	struct wg_peer___wg *peer;
+	u64 ksym = ctx->ksym;
+
+	cfg = bpf_map_lookup_elem(&wireguard_config_map, &ksym);
+	if (!cfg)
+		return 0;
+
+	e = get_event_section(event, COLLECTOR_WIREGUARD, 1, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->kind = cfg->kind;
+
+	switch (cfg->kind) {
+	case WG_KIND_XMIT:
+	case WG_KIND_RECEIVE:
+		/* netdev_tx_t wg_xmit(struct sk_buff *skb, struct net_device *dev)
+		 * void wg_packet_receive(struct wg_device *wg, struct sk_buff *skb)
+		 *
+		 * The peer isn't known yet, packets are reported by the skb
+		 * related collectors.
+		 */
+		return 0;
+	case WG_KIND_DECRYPTED:
+		/* void wg_packet_consume_data_done(struct wg_peer *peer,
+		 *                                  struct sk_buff *skb,
+		 *                                  struct endpoint *endpoint)
+		 *
+		 * Report the endpoint the packet came from, which can differ
+		 * from the one of the peer (roaming).
+		 */
+		endpoint = trace_get_param(ctx, 2, struct endpoint___wg *);
+		if (endpoint)
+			wg_set_endpoint(e, endpoint);
+		break;
+	}
+
+	/* void wg_packet_create_data_done(struct wg_peer *peer,
+	 *                                 struct sk_buff *first)
+	 * void wg_packet_consume_data_done(struct wg_peer *peer, ...)
+	 * void wg_packet_send_queued_handshake_initiation(struct wg_peer *peer,
+	 *                                                 bool is_retry)
+	 * void wg_packet_purge_staged_packets(struct wg_peer *peer)
+	 */
+	peer = trace_get_param(ctx, 0, struct wg_peer___wg *);
+	if (!peer)
+		return 0;
+
+	e->peer_id = BPF_CORE_READ(peer, internal_id);
+	bpf_core_read(e->key, WG_KEY_PREFIX_LEN, &peer->handshake.remote_static);
+	if (cfg->kind != WG_KIND_DECRYPTED)
+		wg_set_endpoint(e, &peer->endpoint);
+
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
@@ -0,0 +1,23 @@
+//! # WireguardCollector
+//!
+//! Probe the WireGuard module to report tunnel events: packets entering the
+//! tunnel (xmit) or received from it, packets encrypted for or decrypted from
+//! a peer, handshake initiations (a handshake is needed before sending data)
+//! and packets dropped because no handshake could be completed with their
+//! peer. Peers are reported by the beginning of their base64 encoded public
+//! key, as shown by `wg show`, along with their endpoint.
+//!
+//! Other drops in the WireGuard transmit path (e.g. no peer matching the
+//! destination) aren't specific to the module and are reported by the
+//! skb-tracking and dropmon collectors. The collector is skipped when the
+//! module isn't loaded; if it is unloaded and loaded again while collecting,
+//! its probes are reattached.
+
+// Re-export wireguard.rs
+#[allow(clippy::module_inception)]
+pub(super) mod wireguard;
+pub(super) use wireguard::*;
+
+mod wireguard_hook {
+    include!("bpf/.out/wireguard_hook.rs");
+}
@@ -0,0 +1,329 @@
+use std::{
+    mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use anyhow::{bail, Result};
+use log::warn;
+use plain::Plain;
+
+use super::wireguard_hook;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{
+        sock::{AF_INET, AF_INET6},
+        Collector, InitDeadline,
+    },
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            class::{classify, Category, Severity},
+            EventField,
+        },
+        kernel_config::Requirements,
+        probe::kernel::{self, Hook, ProbeType},
+    },
+};
+
+const WIREGUARD_COLLECTOR: &str = "wireguard";
+
+// Kinds of events. Please keep in sync with its BPF counterpart in
+// bpf/wireguard_hook.bpf.c
+const WG_KIND_XMIT: u8 = 1;
+const WG_KIND_RECEIVE: u8 = 2;
+const WG_KIND_ENCRYPTED: u8 = 3;
+const WG_KIND_DECRYPTED: u8 = 4;
+const WG_KIND_HANDSHAKE: u8 = 5;
+const WG_KIND_PURGED: u8 = 6;
+
+// Number of bytes of the peer public key reported. Please keep in sync with
+// its BPF counterpart.
+const WG_KEY_PREFIX_LEN: usize = 9;
+
+// Function always probed, also used to check the module is loaded.
+const WIREGUARD_XMIT: &str = "wg_xmit";
+
+// Probed functions and the kind of event they represent. Some of them are
+// static and might be inlined, in which case they are skipped.
+const WIREGUARD_TARGETS: &[(&str, u8)] = &[
+    (WIREGUARD_XMIT, WG_KIND_XMIT),
+    ("wg_packet_receive", WG_KIND_RECEIVE),
+    ("wg_packet_create_data_done", WG_KIND_ENCRYPTED),
+    ("wg_packet_consume_data_done", WG_KIND_DECRYPTED),
+    (
+        "wg_packet_send_queued_handshake_initiation",
+        WG_KIND_HANDSHAKE,
+    ),
+    ("wg_packet_purge_staged_packets", WG_KIND_PURGED),
+];
+
+// WireGuard event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct WireguardEvent {
+    peer_id: u64,
+    key: [u8; WG_KEY_PREFIX_LEN],
+    kind: u8,
+    family: u16,
+    // Network order.
+    port: u16,
+    addr: [u8; 16],
+}
+unsafe impl Plain for WireguardEvent {}
+
+// Please keep in sync with its BPF counterpart in bpf/wireguard_hook.bpf.c
+#[repr(C)]
+struct WireguardConfig {
+    kind: u8,
+}
+unsafe impl Plain for WireguardConfig {}
+
+pub(in crate::collector) struct WireguardCollector {}
+
+impl Collector for WireguardCollector {
+    fn new() -> Result<WireguardCollector> {
+        Ok(WireguardCollector {})
+    }
+
+    fn name(&self) -> &'static str {
+        WIREGUARD_COLLECTOR
+    }
+
+    fn requirements(&self) -> Requirements {
+        Requirements {
+            config: &["CONFIG_WIREGUARD"],
+            ..Default::default()
+        }
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module_noargs(WIREGUARD_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        _: &CliConfig,
+        kernel: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        self.check(kernel)?;
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorWireguard,
+            Box::new(|raw_section, fields| {
+                if raw_section.header.data_type != 1 {
+                    bail!("Unknown data type");
+                }
+                unmarshal_wireguard(raw_section, fields)
+            }),
+        )?;
+
+        // The WireGuard internals aren't part of the kernel ABI. If they
+        // don't match our expectations, still report the events without the
+        // wireguard section rather than failing the probes.
+        let mut config_map = Self::wireguard_config_map()?;
+        let hook = Hook::from(wireguard_hook::DATA)
+            .reuse_map("wireguard_config_map", config_map.fd())?
+            .optional_relo("wg_peer___wg")
+            .optional_relo("endpoint___wg")
+            .optional_relo("noise_handshake___wg")
+            .to_owned();
+
+        for (target, kind) in WIREGUARD_TARGETS.iter() {
+            let ksym = match kernel.inspect.get_ksym(&ProbeType::Kprobe, target) {
+                Ok(ksym) => ksym,
+                Err(e) => {
+                    warn!("Can't probe {}: {}", target, e);
+                    continue;
+                }
+            };
+
+            let cfg = WireguardConfig { kind: *kind };
+            let cfg = unsafe { plain::as_bytes(&cfg) };
+            config_map.update(&ksym.to_ne_bytes(), cfg, libbpf_rs::MapFlags::NO_EXIST)?;
+
+            kernel.register_hook_to(hook.clone(), ProbeType::Kprobe, target)?;
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn check(&self, kernel: &kernel::Kernel) -> Result<()> {
+        // The wireguard module might not be loaded, in which case there is
+        // nothing to do for this collector.
+        if kernel
+            .inspect
+            .get_ksym(&ProbeType::Kprobe, WIREGUARD_XMIT)
+            .is_err()
+        {
+            bail!("The wireguard module is not loaded");
+        }
+        Ok(())
+    }
+}
+
+impl WireguardCollector {
+    fn wireguard_config_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in
+        // bpf/wireguard_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("wireguard_config_map"),
+            mem::size_of::<u64>() as u32,
+            mem::size_of::<WireguardConfig>() as u32,
+            kernel::PROBE_MAX as u32,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the wireguard config map: {}", e))
+    }
+}
+
+fn unmarshal_wireguard(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<WireguardEvent>(raw_section)?;
+
+    let kind = match event.kind {
+        WG_KIND_XMIT => "xmit",
+        WG_KIND_RECEIVE => "receive",
+        WG_KIND_ENCRYPTED => "encrypted",
+        WG_KIND_DECRYPTED => "decrypted",
+        WG_KIND_HANDSHAKE => "handshake_initiation",
+        WG_KIND_PURGED => "drop",
+        x => bail!("Unknown wireguard event kind {}", x),
+    };
+    fields.push(event_field!("kind", kind.to_string()));
+
+    // The peer isn't known when packets enter or come from the tunnel.
+    if event.kind != WG_KIND_XMIT && event.kind != WG_KIND_RECEIVE {
+        fields.push(event_field!("peer", key_prefix(&event.key)));
+        fields.push(event_field!("peer_id", event.peer_id));
+
+        let port = u16::from_be(event.port);
+        let endpoint = match event.family {
+            f if f == AF_INET as u16 => {
+                let a = event.addr;
+                Some(SocketAddr::new(
+                    Ipv4Addr::new(a[0], a[1], a[2], a[3]).into(),
+                    port,
+                ))
+            }
+            f if f == AF_INET6 as u16 => {
+                Some(SocketAddr::new(Ipv6Addr::from(event.addr).into(), port))
+            }
+            // The peer endpoint isn't known (yet).
+            _ => None,
+        };
+        if let Some(endpoint) = endpoint {
+            fields.push(event_field!("endpoint", endpoint.to_string()));
+        }
+    }
+
+    // Packets staged while waiting for a handshake are purged when no
+    // handshake could be completed (or the peer is removed).
+    if event.kind == WG_KIND_PURGED {
+        fields.push(event_field!("reason", "no_handshake".to_string()));
+        classify(fields, Category::Drop, Severity::Warn);
+    }
+    Ok(())
+}
+
+/// Base64 encoding of the beginning of a peer public key, matching the
+/// prefix of its usual representation.
+fn key_prefix(key: &[u8; WG_KEY_PREFIX_LEN]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    // The prefix length is a multiple of 3, no padding is needed.
+    key.chunks(3)
+        .flat_map(|c| {
+            let n = (c[0] as u32) << 16 | (c[1] as u32) << 8 | c[2] as u32;
+            (0..4)
+                .rev()
+                .map(move |i| ALPHABET[(n >> (6 * i)) as usize & 0x3f] as char)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::{bpf::unmarshal_section, Event};
+
+    fn unmarshal(event: WireguardEvent) -> Result<Event> {
+        unmarshal_section(WIREGUARD_COLLECTOR, 1, &event, unmarshal_wireguard)
+    }
+
+    #[test]
+    fn peer_key() {
+        let mut key = [0; WG_KEY_PREFIX_LEN];
+        key.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        assert!(key_prefix(&key) == "AAECAwQFBgcI");
+        assert!(key_prefix(&[0xff; WG_KEY_PREFIX_LEN]) == "////////////");
+    }
+
+    #[test]
+    fn unmarshal_events() -> Result<()> {
+        let mut addr = [0; 16];
+        addr[..4].copy_from_slice(&[192, 0, 2, 1]);
+        let event = unmarshal(WireguardEvent {
+            peer_id: 3,
+            kind: WG_KIND_ENCRYPTED,
+            family: AF_INET as u16,
+            port: 51820_u16.to_be(),
+            addr,
+            ..Default::default()
+        })?;
+        let get = |name: &str| event.get::<String>(WIREGUARD_COLLECTOR, name);
+        assert!(get("kind")? == Some(&"encrypted".to_string()));
+        assert!(get("peer")? == Some(&"AAAAAAAAAAAA".to_string()));
+        assert!(get("endpoint")? == Some(&"192.0.2.1:51820".to_string()));
+        assert!(event.get::<u64>(WIREGUARD_COLLECTOR, "peer_id")? == Some(&3));
+        assert!(get("category")?.is_none());
+
+        let event = unmarshal(WireguardEvent {
+            kind: WG_KIND_DECRYPTED,
+            family: AF_INET6 as u16,
+            port: 51820_u16.to_be(),
+            addr: Ipv6Addr::LOCALHOST.octets(),
+            ..Default::default()
+        })?;
+        assert!(
+            event.get::<String>(WIREGUARD_COLLECTOR, "endpoint")?
+                == Some(&"[::1]:51820".to_string())
+        );
+
+        // No endpoint known yet.
+        let event = unmarshal(WireguardEvent {
+            kind: WG_KIND_PURGED,
+            ..Default::default()
+        })?;
+        let get = |name: &str| event.get::<String>(WIREGUARD_COLLECTOR, name);
+        assert!(get("kind")? == Some(&"drop".to_string()));
+        assert!(get("reason")? == Some(&"no_handshake".to_string()));
+        assert!(get("category")? == Some(&"drop".to_string()));
+        assert!(get("endpoint")?.is_none());
+
+        // No peer for packets entering the tunnel.
+        let event = unmarshal(WireguardEvent {
+            kind: WG_KIND_XMIT,
+            ..Default::default()
+        })?;
+        assert!(event.get::<String>(WIREGUARD_COLLECTOR, "peer")?.is_none());
+
+        assert!(unmarshal(WireguardEvent {
+            kind: 42,
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+}
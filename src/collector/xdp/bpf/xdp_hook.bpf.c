@@ -0,0 +1,87 @@
+#include <vmlinux.h>
+#include <bpf/bpf_core_read.h>
+
+#include <common.h>
+
+/* Please keep in sync with its Rust counterpart in collector::xdp. */
+#define XDP_TP_EXCEPTION	1
+#define XDP_TP_REDIRECT		2
+
+/* XDP configuration, indexed in the xdp_config_map by the tracepoint ksym
+ * address.
+ *
+ * Please keep in sync with its Rust counterpart in collector::xdp.
+ */
+struct xdp_config {
+	/* Tracepoint kind */
+	u8 kind;
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, struct xdp_config);
+} xdp_config_map SEC(".maps");
+
+/* Please keep in sync with its Rust counterpart in collector::xdp. */
+struct xdp_event {
+	u32 ifindex;
+	u32 prog_id;
+	u32 action;
+	s32 err;
+} __attribute__((packed));
+
+/* Tracepoints arguments:
+ *
+ * xdp_exception(const struct net_device *dev, const struct bpf_prog *xdp,
+ *               u32 act)
+ * xdp_redirect(const struct net_device *dev, const struct bpf_prog *xdp,
+ *              const void *tgt, int err, enum bpf_map_type map_type,
+ *              u32 map_id, u32 index)
+ * xdp_redirect_err(<same as xdp_redirect>)
+ */
+DEFINE_HOOK(
+	struct xdp_config *cfg;
+	struct net_device *dev;
+	struct bpf_prog *prog;
+	struct xdp_event *e;
+	u64 ksym = ctx->ksym;
+	u32 action;
+	s32 err;
+
+	cfg = bpf_map_lookup_elem(&xdp_config_map, &ksym);
+	if (!cfg)
+		return 0;
+
+	/* Resolve the tracepoint kind first so no section is reserved (and left
+	 * uninitialized) for unknown kinds.
+	 */
+	switch (cfg->kind) {
+	case XDP_TP_EXCEPTION:
+		action = trace_get_param(ctx, 2, u32);
+		err = 0;
+		break;
+	case XDP_TP_REDIRECT:
+		action = XDP_REDIRECT;
+		err = trace_get_param(ctx, 3, int);
+		break;
+	default:
+		return 0;
+	}
+
+	e = get_event_section(event, COLLECTOR_XDP, 1, sizeof(*e));
+	if (!e)
+		return 0;
+
+	dev = trace_get_param(ctx, 0, struct net_device *);
+	prog = trace_get_param(ctx, 1, struct bpf_prog *);
+
+	e->ifindex = dev ? BPF_CORE_READ(dev, ifindex) : 0;
+	e->prog_id = prog ? BPF_CORE_READ(prog, aux, id) : 0;
+	e->action = action;
+	e->err = err;
+
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
@@ -0,0 +1,17 @@
+//! # XdpCollector
+//!
+//! Report XDP-stage events, happening before an skb is allocated: programs
+//! aborting or returning an invalid action, and redirects (successful or not).
+//! This uses the xdp tracepoints and as no skb exists at this stage, events
+//! report the device ifindex, the XDP program id and the raw action. Actions
+//! of programs running normally (e.g. XDP_DROP) do not trigger a tracepoint
+//! and can't be reported.
+
+// Re-export xdp.rs
+#[allow(clippy::module_inception)]
+pub(super) mod xdp;
+pub(super) use xdp::*;
+
+mod xdp_hook {
+    include!("bpf/.out/xdp_hook.rs");
+}
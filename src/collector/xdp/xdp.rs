@@ -0,0 +1,199 @@
+use std::mem;
+
+use anyhow::{bail, Result};
+use log::warn;
+use plain::Plain;
+
+use super::xdp_hook;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            EventField,
+        },
+        probe::kernel::{self, Hook, ProbeType},
+    },
+};
+
+const XDP_COLLECTOR: &str = "xdp";
+
+// Tracepoint kinds. Please keep in sync with its BPF counterpart in
+// bpf/xdp_hook.bpf.c
+const XDP_TP_EXCEPTION: u8 = 1;
+const XDP_TP_REDIRECT: u8 = 2;
+
+// Probed tracepoints and their kind.
+const XDP_TARGETS: &[(&str, u8)] = &[
+    ("xdp:xdp_exception", XDP_TP_EXCEPTION),
+    ("xdp:xdp_redirect", XDP_TP_REDIRECT),
+    ("xdp:xdp_redirect_err", XDP_TP_REDIRECT),
+];
+
+// XDP event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct XdpEvent {
+    ifindex: u32,
+    prog_id: u32,
+    action: u32,
+    err: i32,
+}
+unsafe impl Plain for XdpEvent {}
+
+// Please keep in sync with its BPF counterpart in bpf/xdp_hook.bpf.c
+#[repr(C)]
+struct XdpConfig {
+    kind: u8,
+}
+unsafe impl Plain for XdpConfig {}
+
+pub(in crate::collector) struct XdpCollector {}
+
+impl Collector for XdpCollector {
+    fn new() -> Result<XdpCollector> {
+        Ok(XdpCollector {})
+    }
+
+    fn name(&self) -> &'static str {
+        XDP_COLLECTOR
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module_noargs(XDP_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        _: &CliConfig,
+        kernel: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        self.check(kernel)?;
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorXdp,
+            Box::new(|raw_section, fields| {
+                if raw_section.header.data_type != 1 {
+                    bail!("Unknown data type");
+                }
+
+                unmarshal_xdp(raw_section, fields)
+            }),
+        )?;
+
+        let mut config_map = Self::xdp_config_map()?;
+        let hook = Hook::from(xdp_hook::DATA)
+            .reuse_map("xdp_config_map", config_map.fd())?
+            .to_owned();
+
+        // Tracepoints might not all be available, depending on the kernel
+        // version. Only the ones found are probed.
+        for (target, kind) in XDP_TARGETS.iter() {
+            let ksym = match kernel.inspect.get_ksym(&ProbeType::RawTracepoint, target) {
+                Ok(ksym) => ksym,
+                Err(e) => {
+                    warn!("Can't probe {}: {}", target, e);
+                    continue;
+                }
+            };
+
+            let cfg = XdpConfig { kind: *kind };
+            let cfg = unsafe { plain::as_bytes(&cfg) };
+            config_map.update(&ksym.to_ne_bytes(), cfg, libbpf_rs::MapFlags::NO_EXIST)?;
+
+            kernel.register_hook_to(hook.clone(), ProbeType::RawTracepoint, target)?;
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn check(&self, kernel: &kernel::Kernel) -> Result<()> {
+        if kernel
+            .inspect
+            .get_ksym(&ProbeType::RawTracepoint, "xdp:xdp_exception")
+            .is_err()
+        {
+            bail!("XDP tracepoints are not available on this kernel");
+        }
+        Ok(())
+    }
+}
+
+impl XdpCollector {
+    fn xdp_config_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in bpf/xdp_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("xdp_config_map"),
+            mem::size_of::<u64>() as u32,
+            mem::size_of::<XdpConfig>() as u32,
+            kernel::PROBE_MAX as u32,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the xdp config map: {}", e))
+    }
+}
+
+fn unmarshal_xdp(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<XdpEvent>(raw_section)?;
+
+    fields.push(event_field!("ifindex", event.ifindex));
+    fields.push(event_field!("prog_id", event.prog_id));
+    fields.push(event_field!("action", xdp_action(event.action)));
+    if event.err != 0 {
+        fields.push(event_field!("err", event.err));
+    }
+    Ok(())
+}
+
+/// Convert a raw XDP action (enum xdp_action) to a string.
+fn xdp_action(action: u32) -> String {
+    match action {
+        0 => "aborted".to_string(),
+        1 => "drop".to_string(),
+        2 => "pass".to_string(),
+        3 => "tx".to_string(),
+        4 => "redirect".to_string(),
+        x => format!("invalid ({})", x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::bpf::unmarshal_section;
+
+    #[test]
+    fn actions() {
+        assert!(xdp_action(0) == "aborted");
+        assert!(xdp_action(4) == "redirect");
+        assert!(xdp_action(42) == "invalid (42)");
+    }
+    #[test]
+    fn unmarshal_event() -> Result<()> {
+        let raw = XdpEvent {
+            ifindex: 2,
+            prog_id: 42,
+            action: 4,
+            err: -6,
+        };
+        let event = unmarshal_section(XDP_COLLECTOR, 1, &raw, unmarshal_xdp)?;
+
+        assert!(event.get::<String>(XDP_COLLECTOR, "action")? == Some(&"redirect".to_string()));
+        assert!(event.get::<i32>(XDP_COLLECTOR, "err")? == Some(&-6));
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+#include <vmlinux.h>
+#include <bpf/bpf_core_read.h>
+#include <bpf/bpf_endian.h>
+
+#include <common.h>
+
+/* Not part of vmlinux.h, see include/linux/skbuff.h */
+#define SKB_DST_PTRMASK	~(1UL)
+
+/* Please keep in sync with its Rust counterpart in collector::xfrm. */
+#define XFRM_DIR_IN	1
+#define XFRM_DIR_OUT	2
+
+/* XFRM configuration, indexed in the xfrm_config_map by the function ksym
+ * address.
+ *
+ * Please keep in sync with its Rust counterpart in collector::xfrm.
+ */
+struct xfrm_config {
+	/* Direction of the probed function */
+	u8 dir;
+};
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, struct xfrm_config);
+} xfrm_config_map SEC(".maps");
+
+/* Please keep in sync with its Rust counterpart in collector::xfrm. */
+struct xfrm_event {
+	u32 spi;
+	u8 dir;
+} __attribute__((packed));
+
+/* Retrieve the SPI of the state attached to an skb dst, if any. */
+static __always_inline u32 skb_dst_spi(struct sk_buff *skb)
+{
+	struct dst_entry *dst;
+	struct xfrm_state *x;
+
+	if (!skb)
+		return 0;
+
+	dst = (struct dst_entry *)(BPF_CORE_READ(skb, _skb_refdst) &
+				   SKB_DST_PTRMASK);
+	if (!dst)
+		return 0;
+
+	x = BPF_CORE_READ(dst, xfrm);
+	if (!x)
+		return 0;
+
+	return BPF_CORE_READ(x, id.spi);
+}
+
+DEFINE_HOOK(
+	struct xfrm_config *cfg;
+	struct xfrm_event *e;
+	u64 ksym = ctx->ksym;
+	u32 spi;
+
+	cfg = bpf_map_lookup_elem(&xfrm_config_map, &ksym);
+	if (!cfg)
+		return 0;
+
+	switch (cfg->dir) {
+	case XFRM_DIR_IN:
+		/* int xfrm_input(struct sk_buff *skb, int nexthdr, __be32 spi,
+		 *                int encap_type)
+		 */
+		spi = trace_get_param(ctx, 2, u32);
+		break;
+	case XFRM_DIR_OUT:
+		spi = skb_dst_spi(trace_get_sk_buff(ctx));
+		break;
+	default:
+		return 0;
+	}
+
+	e = get_event_section(event, COLLECTOR_XFRM, 1, sizeof(*e));
+	if (!e)
+		return 0;
+
+	e->spi = bpf_ntohl(spi);
+	e->dir = cfg->dir;
+
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
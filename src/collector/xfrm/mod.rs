@@ -0,0 +1,25 @@
+//! # XfrmCollector
+//!
+//! Probe the XFRM (IPsec) input and output paths to report which security
+//! association (SPI) a packet is being processed against, and in which
+//! direction. Combined with the skb tracking and drop reasons, this helps
+//! understanding packets dropped because of missing states or policy
+//! mismatches.
+//!
+//! The result of the XFRM processing (e.g. no state found, blocked by a
+//! policy) is the return value of the probed functions, which can't be
+//! retrieved as return probes are not supported. Instead, the XFRM error
+//! counters (see /proc/net/xfrm_stat) which increased during the collection
+//! are reported by name in the stop event, e.g. "in_no_states" or
+//! "out_pol_block". Those are per network namespace and not tied to a given
+//! packet. For the same reason xfrm_lookup (which does not take a packet) is
+//! not probed, its failures being accounted for in the output error counters.
+
+// Re-export xfrm.rs
+#[allow(clippy::module_inception)]
+pub(super) mod xfrm;
+pub(super) use xfrm::*;
+
+mod xfrm_hook {
+    include!("bpf/.out/xfrm_hook.rs");
+}
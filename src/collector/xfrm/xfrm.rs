@@ -0,0 +1,274 @@
+use std::{collections::HashMap, fs, mem};
+
+use anyhow::{bail, Result};
+use log::warn;
+use plain::Plain;
+
+use super::xfrm_hook;
+use crate::event_field;
+use crate::{
+    cli::{dynamic::DynamicCommand, CliConfig},
+    collector::{Collector, InitDeadline},
+    core::{
+        events::{
+            bpf::{parse_raw_section, BpfEventOwner, BpfEvents, BpfRawSection},
+            meta::META_SECTION,
+            Event, EventField,
+        },
+        kernel_config::Requirements,
+        probe::kernel::{self, Hook, ProbeType},
+    },
+};
+
+const XFRM_COLLECTOR: &str = "xfrm";
+
+// Directions. Please keep in sync with its BPF counterpart in
+// bpf/xfrm_hook.bpf.c
+const XFRM_DIR_IN: u8 = 1;
+const XFRM_DIR_OUT: u8 = 2;
+
+// Probed functions and their direction.
+const XFRM_TARGETS: &[(&str, u8)] = &[("xfrm_input", XFRM_DIR_IN), ("xfrm_output", XFRM_DIR_OUT)];
+
+// XFRM error counters, in the current network namespace.
+const XFRM_STAT: &str = "/proc/net/xfrm_stat";
+
+// XFRM event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct XfrmEvent {
+    spi: u32,
+    dir: u8,
+}
+unsafe impl Plain for XfrmEvent {}
+
+// Please keep in sync with its BPF counterpart in bpf/xfrm_hook.bpf.c
+#[repr(C)]
+struct XfrmConfig {
+    dir: u8,
+}
+unsafe impl Plain for XfrmConfig {}
+
+#[derive(Default)]
+pub(in crate::collector) struct XfrmCollector {
+    /// XFRM error counters when the collection started, by name.
+    errors: Option<HashMap<String, u64>>,
+}
+
+impl Collector for XfrmCollector {
+    fn new() -> Result<XfrmCollector> {
+        Ok(XfrmCollector::default())
+    }
+
+    fn name(&self) -> &'static str {
+        XFRM_COLLECTOR
+    }
+
+    fn requirements(&self) -> Requirements {
+        Requirements {
+            config: &["CONFIG_XFRM"],
+            ..Default::default()
+        }
+    }
+
+    fn register_cli(&self, cmd: &mut DynamicCommand) -> Result<()> {
+        cmd.register_module_noargs(XFRM_COLLECTOR)
+    }
+
+    fn init(
+        &mut self,
+        _: &CliConfig,
+        kernel: &mut kernel::Kernel,
+        events: &mut BpfEvents,
+        _: &InitDeadline,
+    ) -> Result<()> {
+        self.check(kernel)?;
+
+        events.register_unmarshaler(
+            BpfEventOwner::CollectorXfrm,
+            Box::new(|raw_section, fields| {
+                if raw_section.header.data_type != 1 {
+                    bail!("Unknown data type");
+                }
+
+                unmarshal_xfrm(raw_section, fields)
+            }),
+        )?;
+
+        let mut config_map = Self::xfrm_config_map()?;
+        let hook = Hook::from(xfrm_hook::DATA)
+            .reuse_map("xfrm_config_map", config_map.fd())?
+            .to_owned();
+
+        for (target, dir) in XFRM_TARGETS.iter() {
+            let key = kernel
+                .inspect
+                .get_ksym(&ProbeType::Kprobe, target)?
+                .to_ne_bytes();
+            let cfg = XfrmConfig { dir: *dir };
+            let cfg = unsafe { plain::as_bytes(&cfg) };
+            config_map.update(&key, cfg, libbpf_rs::MapFlags::NO_EXIST)?;
+
+            kernel.register_hook_to(hook.clone(), ProbeType::Kprobe, target)?;
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        match fs::read_to_string(XFRM_STAT) {
+            Ok(stat) => self.errors = Some(parse_xfrm_stat(&stat)),
+            Err(e) => warn!(
+                "Could not read {}, XFRM errors won't be reported: {}",
+                XFRM_STAT, e
+            ),
+        }
+        Ok(())
+    }
+
+    /// Report the XFRM errors which happened during the collection, e.g.
+    /// packets without a matching state or blocked by a policy.
+    fn summary(&self, event: &mut Event) {
+        let start = match &self.errors {
+            Some(start) => start,
+            None => return,
+        };
+        let end = match fs::read_to_string(XFRM_STAT) {
+            Ok(stat) => parse_xfrm_stat(&stat),
+            Err(_) => return,
+        };
+
+        let errors = xfrm_errors(start, &end);
+        if !errors.is_empty() {
+            event.insert(META_SECTION, event_field!("xfrm_errors", errors));
+        }
+    }
+
+    fn check(&self, kernel: &kernel::Kernel) -> Result<()> {
+        // XFRM might not be compiled in, in which case there is nothing to
+        // do for this collector.
+        if kernel
+            .inspect
+            .get_ksym(&ProbeType::Kprobe, "xfrm_input")
+            .is_err()
+        {
+            bail!("XFRM is not available on this kernel");
+        }
+        Ok(())
+    }
+}
+
+impl XfrmCollector {
+    fn xfrm_config_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in bpf/xfrm_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("xfrm_config_map"),
+            mem::size_of::<u64>() as u32,
+            mem::size_of::<XfrmConfig>() as u32,
+            kernel::PROBE_MAX as u32,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the xfrm config map: {}", e))
+    }
+}
+
+/// Parse the XFRM error counters, as found in /proc/net/xfrm_stat (e.g.
+/// "XfrmInNoStates\t\t3"), by name.
+fn parse_xfrm_stat(stat: &str) -> HashMap<String, u64> {
+    stat.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some((parts.next()?.to_string(), parts.next()?.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Convert an XFRM counter name to an error name, e.g. XfrmInNoStates to
+/// "in_no_states".
+fn xfrm_error_name(counter: &str) -> String {
+    let mut name = String::new();
+    for c in counter.strip_prefix("Xfrm").unwrap_or(counter).chars() {
+        if c.is_ascii_uppercase() && !name.is_empty() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    name
+}
+
+/// Errors which happened between two snapshots of the counters, as a list of
+/// {"error": <name>, "count": <count>} sorted by name.
+fn xfrm_errors(start: &HashMap<String, u64>, end: &HashMap<String, u64>) -> Vec<serde_json::Value> {
+    let mut errors: Vec<_> = end
+        .iter()
+        .map(|(counter, val)| {
+            let count = val.saturating_sub(start.get(counter).copied().unwrap_or(0));
+            (xfrm_error_name(counter), count)
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    errors.sort();
+
+    errors
+        .into_iter()
+        .map(|(error, count)| serde_json::json!({"error": error, "count": count}))
+        .collect()
+}
+
+fn unmarshal_xfrm(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<XfrmEvent>(raw_section)?;
+
+    let dir = match event.dir {
+        XFRM_DIR_IN => "in",
+        XFRM_DIR_OUT => "out",
+        _ => "unknown",
+    };
+
+    fields.push(event_field!("spi", event.spi));
+    fields.push(event_field!("direction", dir.to_string()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::bpf::unmarshal_section;
+
+    #[test]
+    fn unmarshal_event() -> Result<()> {
+        let raw = XfrmEvent {
+            spi: 0x1000,
+            dir: XFRM_DIR_OUT,
+        };
+        let event = unmarshal_section(XFRM_COLLECTOR, 1, &raw, unmarshal_xfrm)?;
+
+        assert!(event.get::<u32>(XFRM_COLLECTOR, "spi")? == Some(&0x1000));
+        assert!(event.get::<String>(XFRM_COLLECTOR, "direction")? == Some(&"out".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        let start =
+            parse_xfrm_stat("XfrmInError\t\t0\nXfrmInNoStates\t\t3\nXfrmOutPolBlock\t\t1\n");
+        let end = parse_xfrm_stat(
+            "XfrmInError\t\t0\nXfrmInNoStates\t\t5\nXfrmOutPolBlock\t\t1\nXfrmInPolBlock\t\t2\n",
+        );
+        assert!(end.len() == 4 && end["XfrmInNoStates"] == 5);
+
+        assert!(xfrm_error_name("XfrmOutBundleGenError") == "out_bundle_gen_error");
+        assert!(
+            xfrm_errors(&start, &end)
+                == vec![
+                    serde_json::json!({"error": "in_no_states", "count": 2}),
+                    serde_json::json!({"error": "in_pol_block", "count": 2}),
+                ]
+        );
+    }
+}
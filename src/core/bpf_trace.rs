@@ -0,0 +1,80 @@
+//! # BPF trace
+//!
+//! Messages written by BPF programs using bpf_printk() end up in the tracefs
+//! trace pipe, separately from events. To ease debugging hooks, the trace pipe
+//! can be read in the background and those messages written to our log,
+//! tagged with the task and CPU they were emitted from. The trace pipe does not
+//! tell which program emitted a message; messages should be prefixed
+//! accordingly.
+//!
+//! Reading the trace pipe consumes its content: other readers (e.g. a
+//! concurrent `cat trace_pipe`) will miss the messages we read.
+
+use std::{
+    io::{BufRead, BufReader},
+    thread,
+};
+
+use anyhow::Result;
+use log::{info, warn};
+
+use super::tracefs::open_tracefs;
+
+/// Marker of the bpf_printk() lines in the trace pipe.
+const PRINTK_MARKER: &str = " bpf_trace_printk: ";
+
+/// Start reading the trace pipe in a background thread, writing messages from
+/// BPF programs to the log. Fails if tracefs is not available.
+pub(crate) fn start_bpf_trace() -> Result<()> {
+    let pipe = open_tracefs("trace_pipe")?;
+
+    thread::spawn(move || {
+        for line in BufReader::new(pipe).lines() {
+            match line {
+                Ok(line) => {
+                    if let Some(msg) = parse_trace_line(&line) {
+                        info!("bpf_printk: {}", msg);
+                    }
+                }
+                Err(e) => {
+                    warn!("Stopped reading the trace pipe: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Parse a trace pipe line, returning the bpf_printk() message tagged with the
+/// task and CPU it was emitted from. Other lines are ignored. Lines are
+/// formatted as "<task>-<pid> [<cpu>] <flags> <timestamp>: bpf_trace_printk:
+/// <message>".
+fn parse_trace_line(line: &str) -> Option<String> {
+    let (header, msg) = line.split_once(PRINTK_MARKER)?;
+    let mut header = header.split_whitespace();
+    let task = header.next()?;
+    let cpu = header.next()?.trim_matches(|c| c == '[' || c == ']');
+
+    Some(format!("[{} cpu{}] {}", task, cpu, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_line() {
+        assert!(
+            parse_trace_line(
+                "          <idle>-0       [003] d.s21  1234.567890: bpf_trace_printk: skb len=84"
+            ) == Some("[<idle>-0 cpu003] skb len=84".to_string())
+        );
+        assert!(
+            parse_trace_line("  ping-4242 [000] ..... 1.000000: bpf_trace_printk: a: b")
+                == Some("[ping-4242 cpu000] a: b".to_string())
+        );
+        assert!(parse_trace_line("  ping-4242 [000] ..... 1.000000: sched_switch: x").is_none());
+        assert!(parse_trace_line("").is_none());
+    }
+}
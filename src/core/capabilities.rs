@@ -0,0 +1,78 @@
+//! # Capabilities
+//!
+//! Detect what the tool can do on the running system, to be reported to users
+//! or to other tools (e.g. to decide whether tracing can be enabled on a given
+//! node). The report format is versioned: fields can be added without bumping
+//! the version, while removing or changing existing ones requires a bump.
+
+use std::{path::Path, ptr};
+
+use anyhow::Result;
+use nix::sys::utsname::uname;
+use serde_json::json;
+
+/// Version of the capabilities report format.
+pub(crate) const CAPABILITIES_VERSION: u32 = 1;
+
+/// Build the capabilities report. `collectors` holds the result of the
+/// collectors checks, by collector name.
+pub(crate) fn capabilities(collectors: &[(String, Result<()>)]) -> Result<serde_json::Value> {
+    let mut report = serde_json::Map::new();
+    for (name, res) in collectors.iter() {
+        let collector = match res {
+            Ok(()) => json!({ "usable": true }),
+            Err(e) => json!({ "usable": false, "reason": e.to_string() }),
+        };
+        report.insert(name.clone(), collector);
+    }
+
+    Ok(json!({
+        "version": CAPABILITIES_VERSION,
+        "tool_version": env!("CARGO_PKG_VERSION"),
+        "kernel": uname()?.release().to_string_lossy(),
+        "btf": Path::new("/sys/kernel/btf/vmlinux").exists(),
+        "features": {
+            "ringbuf": map_type_supported(libbpf_sys::BPF_MAP_TYPE_RINGBUF),
+            // Hooks are attached using freplace (extension) programs.
+            "hooks": prog_type_supported(libbpf_sys::BPF_PROG_TYPE_EXT),
+        },
+        "probe_types": {
+            "kprobe": prog_type_supported(libbpf_sys::BPF_PROG_TYPE_KPROBE),
+            "raw_tracepoint": prog_type_supported(libbpf_sys::BPF_PROG_TYPE_RAW_TRACEPOINT),
+        },
+        "collectors": report,
+    }))
+}
+
+fn map_type_supported(r#type: libbpf_sys::bpf_map_type) -> bool {
+    // Safety: the options parameter must be NULL.
+    unsafe { libbpf_sys::libbpf_probe_bpf_map_type(r#type, ptr::null()) == 1 }
+}
+
+fn prog_type_supported(r#type: libbpf_sys::bpf_prog_type) -> bool {
+    // Safety: the options parameter must be NULL.
+    unsafe { libbpf_sys::libbpf_probe_bpf_prog_type(r#type, ptr::null()) == 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn report() -> Result<()> {
+        let report = capabilities(&[
+            ("foo".to_string(), Ok(())),
+            ("bar".to_string(), Err(anyhow!("not available"))),
+        ])?;
+
+        assert!(report["version"] == CAPABILITIES_VERSION);
+        assert!(report["kernel"].is_string());
+        assert!(report["features"]["ringbuf"].is_boolean());
+        assert!(report["probe_types"]["kprobe"].is_boolean());
+        assert!(report["collectors"]["foo"]["usable"] == true);
+        assert!(report["collectors"]["bar"]["usable"] == false);
+        assert!(report["collectors"]["bar"]["reason"] == "not available");
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+//! # Control socket
+//!
+//! Optional Unix socket allowing to inspect and adjust a running collection.
+//! The protocol is line based: each request is a single line made of a
+//! command and its arguments, separated by spaces. Each request gets a single
+//! JSON line as a reply; errors are reported as `{"error": "<reason>"}`.
+//!
+//! Supported commands:
+//! - `stats`: number of events retrieved so far, collection duration and usage
+//!   of the BPF maps having a bounded capacity.
+//! - `config`: current collection configuration, as reported in the start
+//!   event but reflecting runtime changes (e.g. filters), and the output
+//!   state.
+//! - `filter pid <pid>`: change the process being filtered on. This is only
+//!   available when the collection was started with a pid filter, as the
+//!   filtering logic is otherwise left out of the probes.
+//! - `match-set reload [<path>]`: atomically replace the match set with the
+//!   content of a file, or reload the current one. This is only available when
+//!   the collection was started with a match set.
+//! - `recorder dump [<path>]`: dump the events kept by the flight recorder,
+//!   oldest first, either in the reply (as an "events" array) or to a file as
+//!   JSON lines. This is only available when the collection was started with
+//!   a recorder.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use log::{info, warn};
+use serde_json::json;
+
+use crate::{
+    cli::{cmd::collect::Collect, CliConfig},
+    collector::Group,
+    core::{
+        events::meta,
+        filters::{iface::IfaceFilter, match_set::MatchSet, pid::PidFilter},
+        probe::map_usage::map_usage,
+    },
+    output::{
+        recorder::{get_recorder, Recorder},
+        Output, OutputStatus,
+    },
+};
+
+/// Clients idle for longer than this are disconnected.
+const CLIENT_TIMEOUT_SECS: u64 = 60;
+
+/// Collection state the control socket can inspect and adjust.
+pub(crate) struct ControlState {
+    /// Number of events retrieved so far.
+    pub(crate) count: Arc<AtomicU64>,
+    /// When the collection was started.
+    pub(crate) started: Instant,
+    /// Collectors of the collection.
+    pub(crate) collectors: Vec<String>,
+    pub(crate) pid_filter: Option<Arc<PidFilter>>,
+    pub(crate) match_set: Option<Arc<MatchSet>>,
+    pub(crate) iface_filter: Option<Arc<IfaceFilter>>,
+    pub(crate) output: Option<Arc<OutputStatus>>,
+    pub(crate) recorder: Option<Arc<Recorder>>,
+}
+
+impl ControlState {
+    /// Current collection configuration, built like the start event (see
+    /// Group::start_event()) from the live state.
+    fn config(&self) -> Result<serde_json::Value> {
+        let mut collectors: Vec<&str> = self.collectors.iter().map(|x| x.as_str()).collect();
+        collectors.sort();
+
+        let mut filters = Vec::new();
+        if let Some(filter) = &self.pid_filter {
+            filters.push(filter.to_string());
+        }
+        if let Some(set) = &self.match_set {
+            filters.push(set.to_string());
+        }
+        if let Some(filter) = &self.iface_filter {
+            filters.push(filter.to_string());
+        }
+
+        let mut config = meta::start_event(&collectors, &filters)?.to_json();
+        if let Some(output) = &self.output {
+            config["output"] = json!({
+                "dropped": output.dropped.load(Ordering::Relaxed),
+                "triggered": output.triggered.load(Ordering::Relaxed),
+            });
+        }
+        Ok(config)
+    }
+}
+
+/// Control socket handle. The socket is removed when dropped.
+pub(crate) struct ControlSocket {
+    path: PathBuf,
+    // The listener thread is only kept alive here.
+    #[allow(dead_code)]
+    listener: thread::JoinHandle<()>,
+}
+
+impl ControlSocket {
+    /// Create the control socket at `path` and start handling requests in a
+    /// dedicated thread. A stale socket at the same path is replaced.
+    pub(crate) fn new(path: &str, state: ControlState) -> Result<ControlSocket> {
+        let path = PathBuf::from(path);
+
+        if let Ok(meta) = fs::symlink_metadata(&path) {
+            if !meta.file_type().is_socket() {
+                bail!(
+                    "Can't create control socket {}: file exists",
+                    path.display()
+                );
+            }
+            fs::remove_file(&path)?;
+        }
+
+        let listener = UnixListener::bind(&path)
+            .or_else(|e| bail!("Could not create control socket {}: {}", path.display(), e))?;
+        info!("Control socket listening on {}", path.display());
+
+        let state = Arc::new(state);
+        let listener = thread::spawn(move || {
+            // Each client is handled in its own thread, so an idle or slow
+            // client does not block the others. Idle clients are
+            // disconnected.
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Control socket error: {}", e);
+                        continue;
+                    }
+                };
+                let state = Arc::clone(&state);
+                thread::spawn(move || {
+                    if let Err(e) = handle_client(stream, &state) {
+                        warn!("Control socket client error: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(ControlSocket { path, listener })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Create the control socket, if requested on the command line.
+pub(crate) fn get_control_socket(
+    cli: &CliConfig,
+    group: &Group,
+    output: &Output,
+) -> Result<Option<ControlSocket>> {
+    let collect = cli
+        .subcommand
+        .as_any()
+        .downcast_ref::<Collect>()
+        .ok_or_else(|| anyhow!("wrong subcommand"))?;
+
+    match &collect.args()?.control_socket {
+        Some(path) => {
+            let mut state = group.control_state()?;
+            state.output = Some(output.status());
+            state.recorder = get_recorder().map(Arc::clone);
+            Ok(Some(ControlSocket::new(path, state)?))
+        }
+        None => Ok(None),
+    }
+}
+
+fn handle_client(stream: UnixStream, state: &ControlState) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(CLIENT_TIMEOUT_SECS)))?;
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match handle_request(&line, state) {
+            Ok(reply) => reply,
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+
+    Ok(())
+}
+
+/// Handle a single request and return its reply.
+fn handle_request(request: &str, state: &ControlState) -> Result<serde_json::Value> {
+    let args: Vec<&str> = request.split_whitespace().collect();
+
+    match args.as_slice() {
+        ["stats"] => {
+            let maps: serde_json::Map<_, _> = map_usage()
+                .into_iter()
+                .map(|m| {
+                    let usage = json!({ "entries": m.entries, "max_entries": m.max_entries });
+                    (m.name, usage)
+                })
+                .collect();
+
+            Ok(json!({
+                "events": state.count.load(Ordering::Relaxed),
+                "duration_ms": state.started.elapsed().as_millis() as u64,
+                "maps": maps,
+            }))
+        }
+        ["config"] => state.config(),
+        ["filter", "pid", pid] => {
+            let pid = pid
+                .parse::<u32>()
+                .or_else(|_| bail!("Invalid pid '{}'", pid))?;
+            let filter = state
+                .pid_filter
+                .as_ref()
+                .ok_or_else(|| anyhow!("The collection was not started with a pid filter"))?;
+
+            filter.set_pid(pid)?;
+            info!("Pid filter updated: {}", filter);
+            Ok(json!({ "filter": filter.to_string() }))
+        }
+        ["match-set", "reload", path @ ..] if path.len() <= 1 => {
+            let set = state
+                .match_set
+                .as_ref()
+                .ok_or_else(|| anyhow!("The collection was not started with a match set"))?;
+
+            set.load(path.first().copied())?;
+            let (tuples, prefixes) = set.len();
+            info!(
+                "Match set reloaded: {} ({} tuples, {} prefixes)",
+                set, tuples, prefixes
+            );
+            Ok(json!({
+                "filter": set.to_string(),
+                "tuples": tuples,
+                "prefixes": prefixes,
+            }))
+        }
+        ["recorder", "dump", path @ ..] if path.len() <= 1 => {
+            let recorder = state
+                .recorder
+                .as_ref()
+                .ok_or_else(|| anyhow!("The collection was not started with a recorder"))?;
+
+            match path.first() {
+                Some(path) => {
+                    let events = recorder.dump_to(path)?;
+                    info!("Recorder dumped to {} ({} events)", path, events);
+                    Ok(json!({ "file": path, "events": events }))
+                }
+                None => Ok(json!({ "events": recorder.dump()? })),
+            }
+        }
+        _ => bail!("Unknown command '{}'", request.trim()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> ControlState {
+        ControlState {
+            count: Arc::new(AtomicU64::new(42)),
+            started: Instant::now(),
+            collectors: vec!["skb".to_string(), "ovs".to_string()],
+            pid_filter: None,
+            match_set: None,
+            iface_filter: None,
+            output: Some(Arc::new(OutputStatus::default())),
+            recorder: None,
+        }
+    }
+
+    #[test]
+    fn requests() -> Result<()> {
+        let state = state();
+
+        assert!(handle_request("stats", &state)?["events"] == 42);
+        let config = handle_request(" config ", &state)?;
+        assert!(config["meta"]["collectors"] == "ovs,skb");
+        assert!(config["output"]["dropped"] == 0);
+        state
+            .output
+            .as_ref()
+            .unwrap()
+            .dropped
+            .store(3, Ordering::Relaxed);
+        assert!(handle_request("config", &state)?["output"]["dropped"] == 3);
+        assert!(handle_request("filter pid 1", &state).is_err());
+        assert!(handle_request("filter pid foo", &state).is_err());
+        assert!(handle_request("match-set reload", &state).is_err());
+        assert!(handle_request("match-set reload a b", &state).is_err());
+        assert!(handle_request("recorder dump", &state).is_err());
+
+        let mut state = state;
+        state.recorder = Some(Arc::new(Recorder::new(10)));
+        assert!(handle_request("recorder dump", &state)?["events"] == json!([]));
+        assert!(handle_request("recorder dump a b", &state).is_err());
+        assert!(handle_request("sample 10", &state).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn socket() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("pt-control-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let control = ControlSocket::new(path, state())?;
+
+        // An idle client does not block the others.
+        let _idle = UnixStream::connect(path)?;
+        let mut stream = UnixStream::connect(path)?;
+        writeln!(stream, "stats")?;
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply)?;
+        assert!(serde_json::from_str::<serde_json::Value>(&reply)?["events"] == 42);
+
+        drop(control);
+        assert!(fs::metadata(path).is_err());
+        Ok(())
+    }
+}
@@ -4,18 +4,24 @@
 #![cfg_attr(test, allow(unused_imports))]
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt, mem,
-    sync::{mpsc, Arc},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Result};
-use log::error;
+use log::{error, warn};
+use nix::libc;
+use once_cell::sync::Lazy;
 use plain::Plain;
 
-use super::{Event, EventField};
+use super::{class::Class, Event, EventField};
 use crate::{
     core::{kernel_symbols, workaround::SendableRingBuffer},
     event_field,
@@ -24,71 +30,81 @@ use crate::{
 /// Timeout when polling for new events from BPF.
 const BPF_EVENTS_POLL_TIMEOUT_MS: u64 = 200;
 
+/// Maximum number of events queued between each event processing stage (per
+/// worker, then in total). When full, the stage before it blocks, up to the
+/// ring buffer polling: events are then dropped in the BPF part and accounted
+/// for.
+const EVENTS_QUEUE_SIZE: usize = 4096;
+
+/// Minimum interval between two warnings about unknown sections.
+const UNKNOWN_SECTIONS_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Unknown sections skipped since the last warning, and when it was issued.
+/// Shared by all event processing workers.
+static UNKNOWN_SECTIONS: Lazy<Mutex<(u64, Option<Instant>)>> = Lazy::new(|| Mutex::new((0, None)));
+
 /// Type of the unmarshaler closures. Takes a raw section as an input and
 /// returns an unmarshaled event section. The closure is chosen based on the
-/// unique owner id of the raw event.
-pub(crate) type EventUnmarshaler = dyn Fn(&BpfRawSection, &mut Vec<EventField>) -> Result<()>;
+/// unique owner id of the raw event. Unmarshalers can be run concurrently from
+/// multiple event processing workers.
+pub(crate) type EventUnmarshaler =
+    dyn Fn(&BpfRawSection, &mut Vec<EventField>) -> Result<()> + Send + Sync;
 
 // Define a private type for unmarshalers as we'll use it more than once.
 type Unmarshalers = HashMap<BpfEventOwner, Box<EventUnmarshaler>>;
 
+/// Event statistics, as reported in the stop event.
+#[derive(Default)]
+pub(crate) struct BpfEventsStats {
+    /// Events lost in the BPF part, as the events map was full.
+    pub(crate) lost: u64,
+    /// Sections not added to an event in the BPF part, as they did not fit in
+    /// it (see BPF_RAW_EVENT_DATA_SIZE).
+    pub(crate) oversized: u64,
+    /// Events truncated in userspace, as they exceeded the maximum event size
+    /// (see set_max_event_size()).
+    pub(crate) truncated: u64,
+}
+
 /// API to retrieve and unmarshal events coming from the BPF parts.
 #[cfg(not(test))]
 pub(crate) struct BpfEvents {
     map: libbpf_rs::Map,
+    /// Statistics of the BPF part, see BPF_EVENTS_STATS_*.
+    stats_map: libbpf_rs::Map,
+    /// Counters incremented in place of events, see BPF_COUNTER_*.
+    counters_map: libbpf_rs::Map,
     /// HashMap of unmarshalers.
     unmarshalers: Arc<Unmarshalers>,
     /// Receiver channel to retrieve events from the processing loop.
     rxc: Option<mpsc::Receiver<Event>>,
+    /// Maximum size of the event data, larger events are truncated.
+    max_size: usize,
+    /// Number of events truncated.
+    truncated: Arc<AtomicU64>,
 }
 
 #[cfg(not(test))]
 impl BpfEvents {
     pub(crate) fn new() -> Result<BpfEvents> {
-        let opts = libbpf_sys::bpf_map_create_opts {
-            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
-            ..Default::default()
-        };
-
-        let map = libbpf_rs::Map::create(
-            libbpf_rs::MapType::RingBuf,
-            Some("events_map"),
-            0,
-            0,
-            mem::size_of::<RawEvent>() as u32 * BPF_EVENTS_MAX,
-            &opts,
-        )
-        .or_else(|e| bail!("Failed to create events map: {}", e))?;
+        let map = events_map()?;
 
         let mut events = BpfEvents {
             map,
+            stats_map: events_stats_map()?,
+            counters_map: event_counters_map()?,
             unmarshalers: Arc::new(HashMap::new()),
             rxc: None,
+            max_size: BPF_RAW_EVENT_DATA_SIZE,
+            truncated: Arc::new(AtomicU64::new(0)),
         };
 
         events.register_unmarshaler(
             BpfEventOwner::Common,
-            Box::new(|raw_section, fields| {
-                if raw_section.header.data_type != 1 {
-                    bail!("Unknown data type");
-                }
-
-                if raw_section.data.len() != 16 {
-                    bail!(
-                        "Section data is not the expected size {} != 16",
-                        raw_section.data.len()
-                    );
-                }
-
-                let symbol = u64::from_ne_bytes(raw_section.data[0..8].try_into()?);
-                let timestamp = u64::from_ne_bytes(raw_section.data[8..16].try_into()?);
-
-                fields.push(event_field!(
-                    "symbol",
-                    kernel_symbols::get_symbol_name(symbol)?
-                ));
-                fields.push(event_field!("timestamp", timestamp));
-                Ok(())
+            Box::new(|raw_section, fields| match raw_section.header.data_type {
+                1 => unmarshal_common(raw_section, fields),
+                2 => unmarshal_exec_context(raw_section, fields),
+                _ => bail!("Unknown data type"),
             }),
         )?;
 
@@ -96,7 +112,8 @@ impl BpfEvents {
     }
 
     /// Register a new unmarshaler closure to convert raw sections into event
-    /// sections.
+    /// sections. Unmarshalers are only given sections matching the current
+    /// format version of their owner, see BpfEventOwner::version().
     pub(crate) fn register_unmarshaler(
         &mut self,
         owner: BpfEventOwner,
@@ -111,35 +128,30 @@ impl BpfEvents {
     }
 
     /// This starts the event polling mechanism. A dedicated thread is started
-    /// and events are retrieved and processed there. This is a non-blocking
-    /// call.
-    pub(crate) fn start_polling(&mut self) -> Result<()> {
-        // self.unmarshalers is an Arc<> so we're still pointing to the common
-        // unmarshalers map.
-        let unmarshalers = self.unmarshalers.clone();
-
+    /// and raw events are retrieved there, then dispatched to `workers`
+    /// threads for being processed in parallel. If `ordered` is set, events
+    /// are put back in the order they were retrieved before being made
+    /// available. This is a non-blocking call.
+    ///
+    /// Note: the data shared by unmarshalers across workers (e.g. the kernel
+    /// symbols) must be safe to access concurrently.
+    pub(crate) fn start_polling(&mut self, workers: usize, ordered: bool) -> Result<()> {
         // Create the sending and receiving channels.
-        let (txc, rxc) = mpsc::channel();
+        let (txc, rxc) = mpsc::sync_channel(EVENTS_QUEUE_SIZE);
         self.rxc = Some(rxc);
 
-        // Closure to handle the raw events coming from the BPF part. We're
-        // moving our Arc clone pointing to unmarshalers there and the tx
-        // channel.
+        // Start the workers. Raw events are copied and dispatched to them,
+        // along a sequence number.
+        let mut workers = Workers::start(
+            workers,
+            ordered,
+            self.unmarshalers.clone(),
+            self.max_size,
+            self.truncated.clone(),
+            txc,
+        );
         let process_event = move |data: &[u8]| -> i32 {
-            // Parse the raw event.
-            let event = match parse_raw_event(data, &unmarshalers) {
-                Ok(event) => event,
-                Err(e) => {
-                    error!("Could not parse raw event: {}", e);
-                    return 0;
-                }
-            };
-
-            // Send the event into the events channel for future retrieval.
-            if let Err(e) = txc.send(event) {
-                error!("Could not send event: {}", e);
-            }
-
+            workers.dispatch(data);
             0
         };
 
@@ -153,28 +165,294 @@ impl BpfEvents {
         thread::spawn(move || {
             let rb = rb.get();
             loop {
-                rb.poll(Duration::from_millis(BPF_EVENTS_POLL_TIMEOUT_MS))
-                    .unwrap();
+                match rb.poll(Duration::from_millis(BPF_EVENTS_POLL_TIMEOUT_MS)) {
+                    // Polling is interrupted by signals, e.g. SIGINT when
+                    // stopping the collection; poll again.
+                    Err(libbpf_rs::Error::System(errno)) if errno == libc::EINTR => continue,
+                    ret => ret.unwrap(),
+                }
             }
         });
 
         Ok(())
     }
 
-    /// Retrieve the next event. This is a blocking call.
-    pub(crate) fn poll(&self) -> Result<Event> {
-        match &self.rxc {
-            Some(rxc) => Ok(rxc.recv()?),
+    /// Retrieve the next event. This is a blocking call, unless a timeout is
+    /// given; in which case None is returned if no event was retrieved in
+    /// time.
+    pub(crate) fn poll(&self, timeout: Option<Duration>) -> Result<Option<Event>> {
+        let rxc = match &self.rxc {
+            Some(rxc) => rxc,
             None => bail!("Can't get event, no rx channel found."),
+        };
+
+        Ok(match timeout {
+            Some(timeout) => match rxc.recv_timeout(timeout) {
+                Ok(event) => Some(event),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(e) => bail!(e),
+            },
+            None => Some(rxc.recv()?),
+        })
+    }
+
+    /// Set the maximum size of the event data, up to BPF_RAW_EVENT_DATA_SIZE.
+    /// Sections past it are dropped in userspace and the event flagged as
+    /// truncated. Must be called before start_polling().
+    pub(crate) fn set_max_event_size(&mut self, size: usize) {
+        self.max_size = size.min(BPF_RAW_EVENT_DATA_SIZE);
+    }
+
+    /// Retrieve the event statistics.
+    pub(crate) fn stats(&self) -> BpfEventsStats {
+        let stat = |stat: u32| -> u64 {
+            match self
+                .stats_map
+                .lookup(&stat.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+            {
+                Ok(Some(val)) if val.len() == 8 => u64::from_ne_bytes(val[..8].try_into().unwrap()),
+                _ => 0,
+            }
+        };
+
+        BpfEventsStats {
+            lost: stat(BPF_EVENTS_STATS_LOST),
+            oversized: stat(BPF_EVENTS_STATS_OVERSIZED),
+            truncated: self.truncated.load(Ordering::Relaxed),
         }
     }
 
+    /// Get the events statistics map fd for reuse.
+    pub(crate) fn stats_map_fd(&self) -> i32 {
+        self.stats_map.fd()
+    }
+
+    /// Retrieve the event counters (see probe::common::set_count_events()),
+    /// sorted by name. Counters are cumulative.
+    pub(crate) fn counters(&self) -> Vec<(String, u64)> {
+        let mut counters: Vec<(String, u64)> = self
+            .counters_map
+            .keys()
+            .filter_map(|key| {
+                if key.len() != 8 {
+                    return None;
+                }
+                let count = match self.counters_map.lookup(&key, libbpf_rs::MapFlags::ANY) {
+                    Ok(Some(val)) if val.len() == 8 => {
+                        u64::from_ne_bytes(val[..8].try_into().unwrap())
+                    }
+                    _ => return None,
+                };
+                counter_name(
+                    u32::from_ne_bytes(key[..4].try_into().unwrap()),
+                    u32::from_ne_bytes(key[4..8].try_into().unwrap()),
+                )
+                .map(|name| (name, count))
+            })
+            .collect();
+        counters.sort();
+        counters
+    }
+
+    /// Get the event counters map fd for reuse.
+    pub(crate) fn counters_map_fd(&self) -> i32 {
+        self.counters_map.fd()
+    }
+
     /// Get the events map fd for reuse.
     pub(crate) fn map_fd(&self) -> i32 {
         self.map.fd()
     }
 }
 
+/// Pool of event processing workers, unmarshaling raw events in parallel.
+/// Raw events are dispatched to the workers in a round-robin fashion, along a
+/// sequence number, then put back in order if requested (see ReorderBuffer)
+/// and sent to the events channel.
+struct Workers {
+    /// Raw event channel of each worker.
+    txr: Vec<mpsc::SyncSender<(u64, Vec<u8>)>>,
+    /// Sequence number of the next raw event.
+    seq: u64,
+}
+
+impl Workers {
+    fn start(
+        workers: usize,
+        ordered: bool,
+        unmarshalers: Arc<Unmarshalers>,
+        max_size: usize,
+        truncated: Arc<AtomicU64>,
+        txc: mpsc::SyncSender<Event>,
+    ) -> Workers {
+        // Channel used by the workers to send back processed events, along
+        // their sequence number.
+        let (txw, rxw) = mpsc::sync_channel::<(u64, Option<Event>)>(EVENTS_QUEUE_SIZE);
+
+        // Start the workers, each having its own raw event channel.
+        let mut txr = Vec::new();
+        for _ in 0..workers.max(1) {
+            // unmarshalers is an Arc<> so we're still pointing to the common
+            // unmarshalers map.
+            let unmarshalers = unmarshalers.clone();
+            let txw = txw.clone();
+            let (tx, rxr) = mpsc::sync_channel::<(u64, Vec<u8>)>(EVENTS_QUEUE_SIZE);
+            let truncated = truncated.clone();
+
+            thread::spawn(move || {
+                while let Ok((seq, data)) = rxr.recv() {
+                    // Parse the raw event. Failures, including panics, are
+                    // still reported to not stall the ordering stage.
+                    let event = match panic::catch_unwind(AssertUnwindSafe(|| {
+                        process_raw_event(&data, max_size, &unmarshalers, &truncated)
+                    })) {
+                        Ok(event) => event,
+                        Err(_) => {
+                            error!("Event processing panicked, event dropped");
+                            None
+                        }
+                    };
+
+                    if txw.send((seq, event)).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            txr.push(tx);
+        }
+
+        // Start the ordering stage, which sends the events into the events
+        // channel for future retrieval.
+        thread::spawn(move || {
+            let mut reorder = ReorderBuffer::new(ordered);
+
+            while let Ok((seq, event)) = rxw.recv() {
+                for event in reorder.push(seq, event) {
+                    if let Err(e) = txc.send(event) {
+                        error!("Could not send event: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+
+        Workers { txr, seq: 0 }
+    }
+
+    /// Dispatch a raw event to the next worker. Blocks if its queue is full.
+    fn dispatch(&mut self, data: &[u8]) {
+        let worker = &self.txr[(self.seq % self.txr.len() as u64) as usize];
+        if let Err(e) = worker.send((self.seq, data.to_vec())) {
+            error!("Could not send raw event: {}", e);
+        }
+        self.seq += 1;
+    }
+}
+
+/// Process a raw event: truncate it if needed and parse it. Returns None if
+/// parsing failed.
+fn process_raw_event(
+    data: &[u8],
+    max_size: usize,
+    unmarshalers: &Unmarshalers,
+    truncated: &AtomicU64,
+) -> Option<Event> {
+    // Drop the sections past the maximum event size, if any.
+    let truncated_data = truncate_raw_event(data, max_size);
+    if truncated_data.is_some() {
+        truncated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    match parse_raw_event(truncated_data.as_deref().unwrap_or(data), unmarshalers) {
+        Ok(mut event) => {
+            if truncated_data.is_some() {
+                event.insert("common", event_field!("truncated", true));
+            }
+            Some(event)
+        }
+        Err(e) => {
+            error!("Could not parse raw event: {}", e);
+            None
+        }
+    }
+}
+
+/// Puts events back in order given their sequence number, as they can be
+/// processed out of order by workers. Sequence numbers must be contiguous, a
+/// None event is used to report a sequence number without an event (e.g. when
+/// parsing failed). When ordering isn't requested, events are released as
+/// soon as they are received.
+struct ReorderBuffer {
+    ordered: bool,
+    /// Next sequence number to be released.
+    next: u64,
+    /// Events waiting for the ones before them to be released.
+    pending: BTreeMap<u64, Option<Event>>,
+}
+
+impl ReorderBuffer {
+    fn new(ordered: bool) -> ReorderBuffer {
+        ReorderBuffer {
+            ordered,
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Push a new event and get back the list of events ready to be released,
+    /// in order.
+    fn push(&mut self, seq: u64, event: Option<Event>) -> Vec<Event> {
+        if !self.ordered {
+            return event.into_iter().collect();
+        }
+
+        self.pending.insert(seq, event);
+
+        let mut ready = Vec::new();
+        while let Some(event) = self.pending.remove(&self.next) {
+            if let Some(event) = event {
+                ready.push(event);
+            }
+            self.next += 1;
+        }
+        ready
+    }
+}
+
+/// Truncate a raw event to at most `max_size` bytes of data, by dropping the
+/// sections past it. Returns None if the event does not need to be truncated.
+fn truncate_raw_event(data: &[u8], max_size: usize) -> Option<Vec<u8>> {
+    if data.len() < 2 {
+        return None;
+    }
+    let size = u16::from_ne_bytes(data[..2].try_into().unwrap()) as usize;
+    if size <= max_size {
+        return None;
+    }
+
+    // Keep whole sections only; the section size is the last header field.
+    let header_size = mem::size_of::<BpfRawSectionHeader>();
+    let mut cursor = 2;
+    while cursor + header_size <= data.len() {
+        let section_size = u16::from_ne_bytes(
+            data[cursor + header_size - 2..cursor + header_size]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        if cursor + header_size + section_size - 2 > max_size
+            || cursor + header_size + section_size > data.len()
+        {
+            break;
+        }
+        cursor += header_size + section_size;
+    }
+
+    let mut truncated = ((cursor - 2) as u16).to_ne_bytes().to_vec();
+    truncated.extend_from_slice(&data[2..cursor]);
+    Some(truncated)
+}
+
 fn parse_raw_event(data: &[u8], unmarshalers: &Unmarshalers) -> Result<Event> {
     // First retrieve the buffer length.
     let data_size = data.len();
@@ -206,6 +484,8 @@ fn parse_raw_event(data: &[u8], unmarshalers: &Unmarshalers) -> Result<Event> {
     // to sizeof(u16) as we already read the raw event size above.
     let mut cursor = 2;
     let mut event = Event::new();
+    let mut class = Class::default();
+    let mut unknown_sections: u64 = 0;
     while cursor < raw_event_size {
         // Get the current raw section header.
         let mut raw_section = BpfRawSection::default();
@@ -232,12 +512,14 @@ fn parse_raw_event(data: &[u8], unmarshalers: &Unmarshalers) -> Result<Event> {
         }
 
         // Try converting the raw owner id into something we can use.
+        // Sections of unknown owners (e.g. BPF and userspace parts not being
+        // in sync) are skipped, the rest of the event can still be reported.
         let owner = match BpfEventOwner::from_u8(raw_section.header.owner) {
             Ok(owner) => owner,
-            Err(e) => {
-                // Skip the section.
+            Err(_) => {
                 cursor += raw_section.header.size as usize;
-                error!("Could not convert the raw owner: {}", e);
+                unknown_sections += 1;
+                warn_unknown_section(&format!("owner {}", raw_section.header.owner));
                 continue;
             }
         };
@@ -246,11 +528,24 @@ fn parse_raw_event(data: &[u8], unmarshalers: &Unmarshalers) -> Result<Event> {
         raw_section.data = data[cursor..raw_section_end].to_vec();
         cursor += raw_section.header.size as usize;
 
+        // Do not try to parse sections having a different layout than the one
+        // known by the unmarshalers.
+        if raw_section.header.version != owner.version() {
+            error!(
+                "Section version mismatch for owner {}: {} != {} (BPF and userspace parts are out of sync)",
+                owner,
+                raw_section.header.version,
+                owner.version()
+            );
+            continue;
+        }
+
         // Try getting the right unmarshaler.
         let unmarshaler = match unmarshalers.get(&owner) {
             Some(unmarshaler) => unmarshaler,
             None => {
-                error!("Could not get unmarshaler for owner {}", owner);
+                unknown_sections += 1;
+                warn_unknown_section(&format!("owner {} (no unmarshaler)", owner));
                 continue;
             }
         };
@@ -268,23 +563,136 @@ fn parse_raw_event(data: &[u8], unmarshalers: &Unmarshalers) -> Result<Event> {
 
         // Fill the event with unmarshaled sections. Unwrap as we know
         // it's a valid owner.
+        class.take(&mut fields);
         for field in fields {
             event.insert(owner.to_str_ref().unwrap(), field);
         }
     }
 
+    class.apply(&mut event);
+    if unknown_sections > 0 {
+        event.insert("common", event_field!("unknown_sections", unknown_sections));
+    }
     Ok(event)
 }
 
+/// Report a section of an unknown owner was skipped. Warnings are throttled,
+/// reporting the number of sections skipped since the previous one.
+fn warn_unknown_section(owner: &str) {
+    let mut unknown = match UNKNOWN_SECTIONS.lock() {
+        Ok(unknown) => unknown,
+        Err(_) => return,
+    };
+
+    unknown.0 += 1;
+    if unknown.1.map_or(true, |last| {
+        last.elapsed() >= UNKNOWN_SECTIONS_WARN_INTERVAL
+    }) {
+        warn!(
+            "Skipped {} section(s) of unknown owners, last from {}",
+            unknown.0, owner
+        );
+        *unknown = (0, Some(Instant::now()));
+    }
+}
+
+#[cfg(any(not(test), feature = "test_cap_bpf"))]
+fn events_stats_map() -> Result<libbpf_rs::Map> {
+    let opts = libbpf_sys::bpf_map_create_opts {
+        sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+        ..Default::default()
+    };
+
+    libbpf_rs::Map::create(
+        libbpf_rs::MapType::Array,
+        Some("events_stats_map"),
+        mem::size_of::<u32>() as u32,
+        mem::size_of::<u64>() as u32,
+        BPF_EVENTS_STATS_MAX,
+        &opts,
+    )
+    .or_else(|e| bail!("Failed to create events stats map: {}", e))
+}
+
+#[cfg(any(not(test), feature = "test_cap_bpf"))]
+fn event_counters_map() -> Result<libbpf_rs::Map> {
+    let opts = libbpf_sys::bpf_map_create_opts {
+        sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+        ..Default::default()
+    };
+
+    libbpf_rs::Map::create(
+        libbpf_rs::MapType::Hash,
+        Some("event_counters_map"),
+        (mem::size_of::<u32>() * 2) as u32,
+        mem::size_of::<u64>() as u32,
+        BPF_EVENT_COUNTERS_MAX,
+        &opts,
+    )
+    .or_else(|e| bail!("Failed to create event counters map: {}", e))
+}
+
+/// Name of an event counter given its category and value, e.g.
+/// "ethertype_0x0800". Unknown categories are not named.
+fn counter_name(counter: u32, value: u32) -> Option<String> {
+    Some(match counter {
+        BPF_COUNTER_DROP_REASON => format!("drop_reason_{}", value),
+        BPF_COUNTER_ETHERTYPE => format!("ethertype_{:#06x}", value),
+        BPF_COUNTER_L4_PROTO => format!("l4_proto_{}", value),
+        _ => return None,
+    })
+}
+
+#[cfg(any(not(test), feature = "test_cap_bpf"))]
+fn events_map() -> Result<libbpf_rs::Map> {
+    let opts = libbpf_sys::bpf_map_create_opts {
+        sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+        ..Default::default()
+    };
+
+    libbpf_rs::Map::create(
+        libbpf_rs::MapType::RingBuf,
+        Some("events_map"),
+        0,
+        0,
+        mem::size_of::<RawEvent>() as u32 * BPF_EVENTS_MAX,
+        &opts,
+    )
+    .or_else(|e| bail!("Failed to create events map: {}", e))
+}
+
 // We use a dummy implementation of BpfEvents to allow unit tests to pass.
-// This is fine as no function in the above can really be tested.
+// This is fine as no function in the above can really be tested. Synthetic
+// events can be injected to test the event flow. When BPF tests are enabled a
+// real events map is created, so BPF objects using it can be loaded.
 #[cfg(test)]
-pub(crate) struct BpfEvents;
+#[derive(Default)]
+pub(crate) struct BpfEvents {
+    injected: std::collections::VecDeque<Event>,
+    #[cfg(feature = "test_cap_bpf")]
+    map: Option<libbpf_rs::Map>,
+    #[cfg(feature = "test_cap_bpf")]
+    stats_map: Option<libbpf_rs::Map>,
+    #[cfg(feature = "test_cap_bpf")]
+    counters_map: Option<libbpf_rs::Map>,
+}
 
 #[cfg(test)]
 impl BpfEvents {
     pub(crate) fn new() -> Result<BpfEvents> {
-        Ok(BpfEvents {})
+        Ok(BpfEvents {
+            #[cfg(feature = "test_cap_bpf")]
+            map: Some(events_map()?),
+            #[cfg(feature = "test_cap_bpf")]
+            stats_map: Some(events_stats_map()?),
+            #[cfg(feature = "test_cap_bpf")]
+            counters_map: Some(event_counters_map()?),
+            ..Default::default()
+        })
+    }
+    /// Inject a synthetic event, to be returned by poll().
+    pub(crate) fn inject(&mut self, event: Event) {
+        self.injected.push_back(event);
     }
     pub(crate) fn register_unmarshaler(
         &mut self,
@@ -293,13 +701,41 @@ impl BpfEvents {
     ) -> Result<()> {
         Ok(())
     }
-    pub(crate) fn start_polling(&self) -> Result<()> {
+    pub(crate) fn start_polling(&self, _: usize, _: bool) -> Result<()> {
         Ok(())
     }
-    pub(crate) fn poll(&self) -> Result<Event> {
-        Ok(Event::new())
+    pub(crate) fn poll(&mut self, _: Option<Duration>) -> Result<Option<Event>> {
+        match self.injected.pop_front() {
+            Some(event) => Ok(Some(event)),
+            None => Ok(Some(Event::new())),
+        }
+    }
+    pub(crate) fn set_max_event_size(&mut self, _: usize) {}
+    pub(crate) fn stats(&self) -> BpfEventsStats {
+        BpfEventsStats::default()
+    }
+    pub(crate) fn stats_map_fd(&self) -> i32 {
+        #[cfg(feature = "test_cap_bpf")]
+        if let Some(map) = &self.stats_map {
+            return map.fd();
+        }
+        0
+    }
+    pub(crate) fn counters(&self) -> Vec<(String, u64)> {
+        Vec::new()
+    }
+    pub(crate) fn counters_map_fd(&self) -> i32 {
+        #[cfg(feature = "test_cap_bpf")]
+        if let Some(map) = &self.counters_map {
+            return map.fd();
+        }
+        0
     }
     pub(crate) fn map_fd(&self) -> i32 {
+        #[cfg(feature = "test_cap_bpf")]
+        if let Some(map) = &self.map {
+            return map.fd();
+        }
         0
     }
 }
@@ -310,7 +746,20 @@ pub(super) const BPF_EVENTS_MAX: u32 = 512;
 
 /// Size of the raw data buffer of a BPF event. Please keep synced with its BPF
 /// counterpart.
-pub(super) const BPF_RAW_EVENT_DATA_SIZE: usize = 1024 - 2 /* remove the size field */;
+pub(crate) const BPF_RAW_EVENT_DATA_SIZE: usize = 1024 - 2 /* remove the size field */;
+
+/// Event statistics, indexes in the events stats map. Please keep synced with
+/// their BPF counterpart.
+const BPF_EVENTS_STATS_LOST: u32 = 0;
+const BPF_EVENTS_STATS_OVERSIZED: u32 = 1;
+const BPF_EVENTS_STATS_MAX: u32 = 2;
+
+/// Event counter categories and maximum number of counters. Please keep synced
+/// with their BPF counterpart.
+const BPF_COUNTER_DROP_REASON: u32 = 1;
+const BPF_COUNTER_ETHERTYPE: u32 = 2;
+const BPF_COUNTER_L4_PROTO: u32 = 3;
+const BPF_EVENT_COUNTERS_MAX: u32 = 4096;
 
 /// Raw event format shared between the Rust and BPF part. Please keep in sync
 /// with its BPF counterpart.
@@ -337,11 +786,202 @@ pub(crate) struct BpfRawSection {
 pub(crate) struct BpfRawSectionHeader {
     pub(super) owner: u8,
     pub(crate) data_type: u8,
+    pub(super) version: u8,
     pub(crate) size: u16,
 }
 
 unsafe impl Plain for BpfRawSectionHeader {}
 
+/// Parse the data of a raw section into its typed representation, checking it
+/// has the expected size. Section data are defined as packed structures shared
+/// with the BPF part, which avoids parsing them using offsets.
+pub(crate) fn parse_raw_section<T: Default + Plain>(raw_section: &BpfRawSection) -> Result<T> {
+    if raw_section.data.len() != mem::size_of::<T>() {
+        bail!(
+            "Section data is not the expected size {} != {}",
+            raw_section.data.len(),
+            mem::size_of::<T>(),
+        );
+    }
+
+    let mut event = T::default();
+    plain::copy_from_bytes(&mut event, &raw_section.data)
+        .or_else(|_| bail!("Could not parse the raw section"))?;
+
+    Ok(event)
+}
+
+/// Parse the data of a variable size raw section, made of a fixed size header
+/// followed by variable length data. Returns the typed header and the data.
+pub(crate) fn parse_raw_section_var<T: Default + Plain>(
+    raw_section: &BpfRawSection,
+) -> Result<(T, &[u8])> {
+    if raw_section.data.len() < mem::size_of::<T>() {
+        bail!(
+            "Section data is smaller than its header {} < {}",
+            raw_section.data.len(),
+            mem::size_of::<T>(),
+        );
+    }
+
+    let (header, data) = raw_section.data.split_at(mem::size_of::<T>());
+    let mut event = T::default();
+    plain::copy_from_bytes(&mut event, header)
+        .or_else(|_| bail!("Could not parse the raw section"))?;
+
+    Ok((event, data))
+}
+
+/// Build a raw section of a given data type out of its typed representation.
+/// Used by unit tests to feed unmarshalers.
+#[cfg(test)]
+pub(crate) fn raw_section<T: Plain>(data_type: u8, event: &T) -> BpfRawSection {
+    let mut section = BpfRawSection::default();
+    section.header.data_type = data_type;
+    // Safety: section data are packed structures shared with the BPF part.
+    section.data = unsafe { plain::as_bytes(event) }.to_vec();
+    section
+}
+
+/// Build an event out of unmarshaled fields, inserted in `section`. All
+/// fields are checked to be registered. Used by unit tests.
+#[cfg(test)]
+pub(crate) fn to_event(section: &str, fields: Vec<EventField>) -> Result<Event> {
+    let mut event = Event::new();
+    fields.into_iter().for_each(|f| event.insert(section, f));
+    super::registry::FieldRegistry::get().check(event.fields())?;
+    Ok(event)
+}
+
+/// Unmarshal a raw section built out of `event` (see raw_section()) into an
+/// event (see to_event()). Used by unit tests.
+#[cfg(test)]
+pub(crate) fn unmarshal_section<T, F>(
+    section: &str,
+    data_type: u8,
+    event: &T,
+    unmarshaler: F,
+) -> Result<Event>
+where
+    T: Plain,
+    F: FnOnce(&BpfRawSection, &mut Vec<EventField>) -> Result<()>,
+{
+    let mut fields = Vec::new();
+    unmarshaler(&raw_section(data_type, event), &mut fields)?;
+    to_event(section, fields)
+}
+
+/// Common event. Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct CommonEvent {
+    symbol: u64,
+    timestamp: u64,
+    probe_type: u8,
+    timestamp_tai: u64,
+}
+
+unsafe impl Plain for CommonEvent {}
+
+// Type of the probe which generated an event. Please keep in sync with its BPF
+// counterpart in core::probe::kernel::bpf::include::common.h
+const PROBE_TYPE_KPROBE: u8 = 1;
+const PROBE_TYPE_RAW_TRACEPOINT: u8 = 2;
+
+/// Name the probe which generated an event, given its type and the symbol it
+/// is attached to, e.g. "kprobe:kfree_skb_reason" or
+/// "raw_tracepoint:net_dev_start_xmit".
+fn probe_name(probe_type: u8, symbol: &str) -> Option<String> {
+    match probe_type {
+        PROBE_TYPE_KPROBE => Some(format!("kprobe:{}", symbol)),
+        PROBE_TYPE_RAW_TRACEPOINT => Some(format!(
+            "raw_tracepoint:{}",
+            symbol.strip_prefix("__tracepoint_").unwrap_or(symbol)
+        )),
+        _ => None,
+    }
+}
+
+fn unmarshal_common(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<CommonEvent>(raw_section)?;
+
+    let symbol = kernel_symbols::get_symbol_name(event.symbol)?;
+    fields.push(event_field!("symbol", symbol.clone()));
+    fields.push(event_field!("timestamp", event.timestamp));
+    // Only reported when requested and supported, see --clock.
+    if event.timestamp_tai != 0 {
+        fields.push(event_field!("timestamp_tai", event.timestamp_tai));
+    }
+    if let Some(probe) = probe_name(event.probe_type, &symbol) {
+        fields.push(event_field!("probe", probe));
+    }
+    Ok(())
+}
+
+/// Execution context of an event, only reported when requested (see
+/// --context). Please keep in sync with its BPF counterpart.
+#[derive(Default)]
+#[repr(C, packed)]
+struct CommonContextEvent {
+    context: u8,
+    softirq: u8,
+    pid: u32,
+    comm: [u8; 16],
+}
+
+unsafe impl Plain for CommonContextEvent {}
+
+// Execution contexts. Please keep in sync with its BPF counterpart in
+// core::probe::kernel::bpf::include::common.h
+const EXEC_CONTEXT_TASK: u8 = 1;
+const EXEC_CONTEXT_SOFTIRQ: u8 = 2;
+const EXEC_CONTEXT_HARDIRQ: u8 = 3;
+const EXEC_CONTEXT_NMI: u8 = 4;
+
+/// Softirq vector names, see softirq_to_name in kernel/softirq.c
+const SOFTIRQ_NAMES: [&str; 10] = [
+    "HI", "TIMER", "NET_TX", "NET_RX", "BLOCK", "IRQ_POLL", "TASKLET", "SCHED", "HRTIMER", "RCU",
+];
+
+fn unmarshal_exec_context(raw_section: &BpfRawSection, fields: &mut Vec<EventField>) -> Result<()> {
+    let event = parse_raw_section::<CommonContextEvent>(raw_section)?;
+
+    let context = match event.context {
+        EXEC_CONTEXT_TASK => "task",
+        EXEC_CONTEXT_SOFTIRQ => "softirq",
+        EXEC_CONTEXT_HARDIRQ => "hardirq",
+        EXEC_CONTEXT_NMI => "nmi",
+        _ => "unknown",
+    };
+    fields.push(event_field!("context", context.to_string()));
+
+    match event.context {
+        // The task is only meaningful in process context, it is the one
+        // being interrupted otherwise.
+        EXEC_CONTEXT_TASK => {
+            let len = event.comm.iter().position(|c| *c == 0).unwrap_or(16);
+            fields.push(event_field!("pid", event.pid));
+            fields.push(event_field!(
+                "comm",
+                String::from_utf8_lossy(&event.comm[..len]).to_string()
+            ));
+        }
+        // The vector is 0 when unknown, e.g. when softirqs can't be tracked.
+        EXEC_CONTEXT_SOFTIRQ if event.softirq != 0 => {
+            let vec = (event.softirq - 1) as usize;
+            fields.push(event_field!(
+                "softirq",
+                match SOFTIRQ_NAMES.get(vec) {
+                    Some(name) => name.to_string(),
+                    None => vec.to_string(),
+                }
+            ));
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
 /// List of unique owner ids. Please keep in sync with its BPF counterpart. An
 /// owner is a module responsible of given sections types. The section "unique
 /// id" is (owner id, data type id).
@@ -349,6 +989,19 @@ unsafe impl Plain for BpfRawSectionHeader {}
 pub(crate) enum BpfEventOwner {
     Common = 1,
     CollectorSkbTracking = 2,
+    CollectorSkb = 3,
+    CollectorXfrm = 4,
+    CollectorBridge = 5,
+    CollectorCgroup = 6,
+    CollectorKprobe = 7,
+    CollectorXdp = 8,
+    CollectorSockfilter = 9,
+    CollectorTcpRetrans = 10,
+    CollectorSockdrop = 11,
+    CollectorNetfilter = 12,
+    CollectorTcBpf = 13,
+    CollectorDropmon = 14,
+    CollectorWireguard = 15,
 }
 
 impl BpfEventOwner {
@@ -357,16 +1010,56 @@ impl BpfEventOwner {
         let owner = match val {
             1 => Common,
             2 => CollectorSkbTracking,
+            3 => CollectorSkb,
+            4 => CollectorXfrm,
+            5 => CollectorBridge,
+            6 => CollectorCgroup,
+            7 => CollectorKprobe,
+            8 => CollectorXdp,
+            9 => CollectorSockfilter,
+            10 => CollectorTcpRetrans,
+            11 => CollectorSockdrop,
+            12 => CollectorNetfilter,
+            13 => CollectorTcBpf,
+            14 => CollectorDropmon,
+            15 => CollectorWireguard,
             x => bail!("Can't construct a BpfEventOwner from {}", x),
         };
         Ok(owner)
     }
 
+    /// Version of the sections format of an owner, checked before
+    /// unmarshaling a section. It must be bumped when the layout of one of the
+    /// owner sections changes. Please keep in sync with its BPF counterpart.
+    pub(super) fn version(&self) -> u8 {
+        match self {
+            BpfEventOwner::CollectorSkb => 6,
+            BpfEventOwner::Common => 3,
+            BpfEventOwner::CollectorTcpRetrans => 3,
+            BpfEventOwner::CollectorSkbTracking => 3,
+            BpfEventOwner::CollectorCgroup => 2,
+            _ => 1,
+        }
+    }
+
     pub(super) fn to_str_ref(&self) -> Result<&str> {
         use BpfEventOwner::*;
         let ret = match self {
             Common => "common",
             CollectorSkbTracking => "skb-tracking",
+            CollectorSkb => "skb",
+            CollectorXfrm => "xfrm",
+            CollectorBridge => "bridge",
+            CollectorCgroup => "cgroup",
+            CollectorKprobe => "kprobe",
+            CollectorXdp => "xdp",
+            CollectorSockfilter => "sockfilter",
+            CollectorTcpRetrans => "tcp-retrans",
+            CollectorSockdrop => "sockdrop",
+            CollectorNetfilter => "netfilter",
+            CollectorTcBpf => "tc-bpf",
+            CollectorDropmon => "dropmon",
+            CollectorWireguard => "wireguard",
         };
         Ok(ret)
     }
@@ -386,6 +1079,128 @@ mod tests {
     const DATA_TYPE_U64: u8 = 1;
     const DATA_TYPE_U128: u8 = 2;
 
+    // Common owner id and sections format version, for building raw events.
+    const COMMON: u8 = BpfEventOwner::Common as u8;
+    const V: u8 = 3;
+
+    #[test]
+    fn parse_section() -> Result<()> {
+        let mut section = BpfRawSection::default();
+        section.data = [42_u64.to_ne_bytes(), 1337_u64.to_ne_bytes()].concat();
+        section.data.push(PROBE_TYPE_KPROBE);
+        section.data.extend_from_slice(&37_u64.to_ne_bytes());
+
+        let event = parse_raw_section::<CommonEvent>(&section)?;
+        let (symbol, timestamp, tai) = (event.symbol, event.timestamp, event.timestamp_tai);
+        assert!(symbol == 42 && timestamp == 1337 && tai == 37);
+        assert!(event.probe_type == PROBE_TYPE_KPROBE);
+
+        // Under and over-sized sections are rejected.
+        section.data.pop();
+        assert!(parse_raw_section::<CommonEvent>(&section).is_err());
+        section.data.extend_from_slice(&[0, 0]);
+        assert!(parse_raw_section::<CommonEvent>(&section).is_err());
+
+        // Variable size sections only need to hold their header.
+        let (event, data) = parse_raw_section_var::<CommonEvent>(&section)?;
+        let symbol = event.symbol;
+        assert!(symbol == 42 && data == [0]);
+        section.data.truncate(24);
+        assert!(parse_raw_section_var::<CommonEvent>(&section).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn exec_context() -> Result<()> {
+        let unmarshal = |event: &CommonContextEvent| -> Result<Event> {
+            unmarshal_section("common", 2, event, unmarshal_exec_context)
+        };
+        let mut comm = [0; 16];
+        comm[..4].copy_from_slice(b"ping");
+
+        let event = unmarshal(&CommonContextEvent {
+            context: EXEC_CONTEXT_TASK,
+            pid: 42,
+            comm,
+            ..Default::default()
+        })?;
+        assert!(event.get::<String>("common", "context")? == Some(&"task".to_string()));
+        assert!(event.get::<u32>("common", "pid")? == Some(&42));
+        assert!(event.get::<String>("common", "comm")? == Some(&"ping".to_string()));
+
+        // The interrupted task is not reported in softirq context.
+        let event = unmarshal(&CommonContextEvent {
+            context: EXEC_CONTEXT_SOFTIRQ,
+            softirq: 4,
+            pid: 42,
+            comm,
+        })?;
+        assert!(event.get::<String>("common", "context")? == Some(&"softirq".to_string()));
+        assert!(event.get::<String>("common", "softirq")? == Some(&"NET_RX".to_string()));
+        assert!(event.get::<u32>("common", "pid")?.is_none());
+
+        let event = unmarshal(&CommonContextEvent {
+            context: EXEC_CONTEXT_SOFTIRQ,
+            ..Default::default()
+        })?;
+        assert!(event.get::<String>("common", "softirq")?.is_none());
+        let event = unmarshal(&CommonContextEvent {
+            context: EXEC_CONTEXT_HARDIRQ,
+            ..Default::default()
+        })?;
+        assert!(event.get::<String>("common", "context")? == Some(&"hardirq".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn truncate_raw_event() {
+        // Two sections of 2 and 1 bytes.
+        let data = [
+            13,
+            0,
+            COMMON,
+            DATA_TYPE_U64,
+            V,
+            2,
+            0,
+            1,
+            2,
+            COMMON,
+            DATA_TYPE_U64,
+            V,
+            1,
+            0,
+            3,
+        ];
+        assert!(super::truncate_raw_event(&data, 13).is_none());
+        assert!(super::truncate_raw_event(&data, 1022).is_none());
+
+        // Only whole sections are kept.
+        let first = [7, 0, COMMON, DATA_TYPE_U64, V, 2, 0, 1, 2];
+        assert!(super::truncate_raw_event(&data, 12).unwrap() == first);
+        assert!(super::truncate_raw_event(&data, 7).unwrap() == first);
+        assert!(super::truncate_raw_event(&data, 6).unwrap() == [0, 0]);
+
+        // Sections going past the buffer are dropped.
+        let data = [
+            13,
+            0,
+            COMMON,
+            DATA_TYPE_U64,
+            V,
+            2,
+            0,
+            1,
+            2,
+            COMMON,
+            DATA_TYPE_U64,
+            V,
+            4,
+            0,
+        ];
+        assert!(super::truncate_raw_event(&data, 12).unwrap() == first);
+    }
+
     #[test]
     fn parse_raw_event() {
         let mut unmarshalers = Unmarshalers::new();
@@ -446,56 +1261,67 @@ mod tests {
         assert!(super::parse_raw_event(&data, &unmarshalers).is_err());
 
         // Valid event with a single empty section. Section is ignored.
-        let data = [4, 0, BpfEventOwner::Common as u8, DATA_TYPE_U64, 0, 0];
+        let data = [5, 0, COMMON, DATA_TYPE_U64, V, 0, 0];
         assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
 
         // Valid event with a section too large. Section is ignored.
+        let data = [5, 0, COMMON, DATA_TYPE_U64, V, 4, 0, 42, 42];
+        assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
+        let data = [7, 0, COMMON, DATA_TYPE_U64, V, 4, 0, 42, 42];
+        assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
+
+        // Valid event with a section having an invalid owner.
+        let data = [5, 0, 0, DATA_TYPE_U64, V, 0, 0];
+        assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
+        let data = [5, 0, 255, DATA_TYPE_U64, V, 0, 0];
+        assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
+
+        // Unknown sections are skipped and accounted, known ones reported.
         let data = [
-            4,
+            19,
             0,
-            BpfEventOwner::Common as u8,
+            255,
             DATA_TYPE_U64,
-            4,
+            V,
+            1,
             0,
             42,
-            42,
-        ];
-        assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
-        let data = [
-            6,
-            0,
-            BpfEventOwner::Common as u8,
+            COMMON,
             DATA_TYPE_U64,
-            4,
+            V,
+            8,
             0,
             42,
-            42,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
         ];
-        assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
-
-        // Valid event with a section having an invalid owner.
-        let data = [4, 0, 0, DATA_TYPE_U64, 0, 0];
-        assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
-        let data = [4, 0, 255, DATA_TYPE_U64, 0, 0];
-        assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
+        let event = super::parse_raw_event(&data, &unmarshalers).unwrap();
+        assert!(event.get::<u64>("common", "field0").unwrap() == Some(&42));
+        assert!(event.get::<u64>("common", "unknown_sections").unwrap() == Some(&1));
 
         // Valid event with an invalid data type.
-        let data = [4, 0, BpfEventOwner::Common as u8, 0, 1, 0, 42];
+        let data = [5, 0, COMMON, 0, V, 1, 0, 42];
         assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
-        let data = [4, 0, BpfEventOwner::Common as u8, 255, 1, 0, 42];
+        let data = [5, 0, COMMON, 255, V, 1, 0, 42];
         assert!(super::parse_raw_event(&data, &unmarshalers).is_ok());
 
         // Valid event but invalid section (too small).
-        let data = [5, 0, BpfEventOwner::Common as u8, DATA_TYPE_U64, 1, 0, 42];
+        let data = [6, 0, COMMON, DATA_TYPE_U64, V, 1, 0, 42];
         let res = super::parse_raw_event(&data, &unmarshalers);
         assert!(res.unwrap().len() == 0);
 
         // Valid event, single section.
         let data = [
-            12,
+            13,
             0,
-            BpfEventOwner::Common as u8,
+            COMMON,
             DATA_TYPE_U64,
+            V,
             8,
             0,
             42,
@@ -511,13 +1337,36 @@ mod tests {
         let field = event.get::<u64>("common", "field0").unwrap();
         assert!(field == Some(&42));
 
+        // Valid event, single section with a different format version. The
+        // section is ignored.
+        let data = [
+            13,
+            0,
+            COMMON,
+            DATA_TYPE_U64,
+            V + 1,
+            8,
+            0,
+            42,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ];
+        let res = super::parse_raw_event(&data, &unmarshalers);
+        assert!(res.unwrap().len() == 0);
+
         // Valid event, multiple sections.
         let data = [
-            44,
+            47,
             0,
             // Section 1
-            BpfEventOwner::Common as u8,
+            COMMON,
             DATA_TYPE_U64,
+            V,
             8,
             0,
             42,
@@ -529,8 +1378,9 @@ mod tests {
             0,
             0,
             // Section 2
-            BpfEventOwner::Common as u8,
+            COMMON,
             DATA_TYPE_U64,
+            V,
             8,
             0,
             57,
@@ -542,8 +1392,9 @@ mod tests {
             0,
             0,
             // Section 3
-            BpfEventOwner::Common as u8,
+            COMMON,
             DATA_TYPE_U128,
+            V,
             16,
             0,
             42,
@@ -574,4 +1425,153 @@ mod tests {
         assert!(field.is_none());
         assert!(event.get::<i64>("common", "field1").is_err());
     }
+
+    fn seq_event(seq: u64) -> Option<Event> {
+        let mut event = Event::new();
+        event.insert("common", event_field!("seq", seq));
+        Some(event)
+    }
+
+    fn seqs(events: Vec<Event>) -> Vec<u64> {
+        events
+            .iter()
+            .map(|e| *e.get::<u64>("common", "seq").unwrap().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reorder_buffer() {
+        let mut reorder = ReorderBuffer::new(true);
+
+        assert!(seqs(reorder.push(1, seq_event(1))).is_empty());
+        assert!(seqs(reorder.push(2, seq_event(2))).is_empty());
+        assert!(seqs(reorder.push(0, seq_event(0))) == vec![0, 1, 2]);
+        assert!(seqs(reorder.push(3, seq_event(3))) == vec![3]);
+
+        // Missing events do not stall the buffer.
+        assert!(seqs(reorder.push(5, seq_event(5))).is_empty());
+        assert!(seqs(reorder.push(4, None)) == vec![5]);
+
+        let mut unordered = ReorderBuffer::new(false);
+        assert!(seqs(unordered.push(1, seq_event(1))) == vec![1]);
+        assert!(seqs(unordered.push(0, seq_event(0))) == vec![0]);
+        assert!(unordered.push(2, None).is_empty());
+    }
+
+    // Unmarshalers reporting the u64 of common sections as "seq", panicking on
+    // u64::MAX.
+    fn seq_unmarshalers() -> Arc<Unmarshalers> {
+        let mut unmarshalers = Unmarshalers::new();
+        unmarshalers.insert(
+            BpfEventOwner::Common,
+            Box::new(|raw_section, fields| {
+                let seq = u64::from_ne_bytes(raw_section.data[0..8].try_into()?);
+                if seq == u64::MAX {
+                    panic!("Deliberate panic");
+                }
+                fields.push(event_field!("seq", seq));
+                Ok(())
+            }),
+        );
+        Arc::new(unmarshalers)
+    }
+
+    fn seq_raw_event(seq: u64) -> Vec<u8> {
+        let mut data = vec![13, 0, COMMON, DATA_TYPE_U64, V, 8, 0];
+        data.extend_from_slice(&seq.to_ne_bytes());
+        data
+    }
+
+    #[test]
+    fn workers() {
+        let (txc, rxc) = mpsc::sync_channel(EVENTS_QUEUE_SIZE);
+        let mut workers = Workers::start(
+            4,
+            true,
+            seq_unmarshalers(),
+            BPF_RAW_EVENT_DATA_SIZE,
+            Arc::new(AtomicU64::new(0)),
+            txc,
+        );
+
+        // A panicking worker does not stall the ordering stage.
+        for seq in 0..1000 {
+            match seq {
+                500 => workers.dispatch(&seq_raw_event(u64::MAX)),
+                _ => workers.dispatch(&seq_raw_event(seq)),
+            }
+        }
+
+        let events: Vec<u64> = (0..999)
+            .map(|_| {
+                let event = rxc.recv_timeout(Duration::from_secs(5)).unwrap();
+                *event.get::<u64>("common", "seq").unwrap().unwrap()
+            })
+            .collect();
+        assert!(events == (0..1000).filter(|seq| *seq != 500).collect::<Vec<_>>());
+        assert!(rxc.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    // Throughput of the event processing workers, run with:
+    // cargo test workers_throughput -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn workers_throughput() {
+        const EVENTS: u64 = 1_000_000;
+
+        let data = seq_raw_event(42);
+        for n in [1, 2, 4, 8] {
+            for ordered in [true, false] {
+                let (txc, rxc) = mpsc::sync_channel(EVENTS_QUEUE_SIZE);
+                let mut workers = Workers::start(
+                    n,
+                    ordered,
+                    seq_unmarshalers(),
+                    BPF_RAW_EVENT_DATA_SIZE,
+                    Arc::new(AtomicU64::new(0)),
+                    txc,
+                );
+                let consumer = thread::spawn(move || {
+                    for _ in 0..EVENTS {
+                        rxc.recv().unwrap();
+                    }
+                });
+
+                let start = Instant::now();
+                for _ in 0..EVENTS {
+                    workers.dispatch(&data);
+                }
+                consumer.join().unwrap();
+                let elapsed = start.elapsed();
+
+                println!(
+                    "{} worker(s), ordered={}: {:.0} events/s",
+                    n,
+                    ordered,
+                    EVENTS as f64 / elapsed.as_secs_f64()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn probe() {
+        assert!(
+            probe_name(PROBE_TYPE_KPROBE, "kfree_skb_reason")
+                == Some("kprobe:kfree_skb_reason".to_string())
+        );
+        assert!(
+            probe_name(PROBE_TYPE_RAW_TRACEPOINT, "__tracepoint_net_dev_start_xmit")
+                == Some("raw_tracepoint:net_dev_start_xmit".to_string())
+        );
+        assert!(probe_name(0, "kfree_skb").is_none());
+    }
+
+    #[test]
+    fn counter() {
+        assert!(counter_name(BPF_COUNTER_DROP_REASON, 2) == Some("drop_reason_2".to_string()));
+        assert!(counter_name(BPF_COUNTER_ETHERTYPE, 0x800) == Some("ethertype_0x0800".to_string()));
+        assert!(counter_name(BPF_COUNTER_L4_PROTO, 17) == Some("l4_proto_17".to_string()));
+        assert!(counter_name(0, 1).is_none());
+    }
 }
@@ -11,8 +11,52 @@
 enum trace_event_owners {
 	COMMON = 1,
 	COLLECTOR_SKB_TRACKING = 2,
+	COLLECTOR_SKB = 3,
+	COLLECTOR_XFRM = 4,
+	COLLECTOR_BRIDGE = 5,
+	COLLECTOR_CGROUP = 6,
+	COLLECTOR_KPROBE = 7,
+	COLLECTOR_XDP = 8,
+	COLLECTOR_SOCKFILTER = 9,
+	COLLECTOR_TCP_RETRANS = 10,
+	COLLECTOR_SOCKDROP = 11,
+	COLLECTOR_NETFILTER = 12,
+	COLLECTOR_TC_BPF = 13,
+	COLLECTOR_DROPMON = 14,
+	COLLECTOR_WIREGUARD = 15,
 };
 
+/* Section format versions, per owner. Please bump an owner version when the
+ * layout of one of its sections changes, and keep in sync with its Rust
+ * counterpart in crate::core::events::bpf (BpfEventOwner::version).
+ */
+static __always_inline u8 section_version(u8 owner)
+{
+	switch (owner) {
+	case COLLECTOR_XFRM:
+	case COLLECTOR_BRIDGE:
+	case COLLECTOR_KPROBE:
+	case COLLECTOR_XDP:
+	case COLLECTOR_SOCKFILTER:
+	case COLLECTOR_SOCKDROP:
+	case COLLECTOR_NETFILTER:
+	case COLLECTOR_TC_BPF:
+	case COLLECTOR_DROPMON:
+	case COLLECTOR_WIREGUARD:
+		return 1;
+	case COLLECTOR_CGROUP:
+		return 2;
+	case COMMON:
+	case COLLECTOR_SKB_TRACKING:
+	case COLLECTOR_TCP_RETRANS:
+		return 3;
+	case COLLECTOR_SKB:
+		return 6;
+	default:
+		return 0;
+	}
+}
+
 struct trace_raw_event {
 	u16 size;
 	u8 data[RAW_EVENT_DATA_SIZE];
@@ -22,6 +66,7 @@ struct trace_raw_event {
 struct trace_raw_event_section_header {
 	u8 owner;
 	u8 data_type;
+	u8 version;
 	u16 size;
 } __attribute__((packed));
 
@@ -31,13 +76,90 @@ struct {
 	__uint(max_entries, sizeof(struct trace_raw_event) * EVENTS_MAX);
 } events_map SEC(".maps");
 
+/* Event statistics, indexes in events_stats_map. Please keep synced with their
+ * Rust counterpart in crate::core::events::bpf.
+ */
+enum events_stats {
+	/* Events lost, as the events map was full */
+	EVENTS_STATS_LOST = 0,
+	/* Sections not added to an event, as they did not fit in it */
+	EVENTS_STATS_OVERSIZED = 1,
+	EVENTS_STATS_MAX,
+};
+
+struct {
+	__uint(type, BPF_MAP_TYPE_ARRAY);
+	__uint(max_entries, EVENTS_STATS_MAX);
+	__type(key, u32);
+	__type(value, u64);
+} events_stats_map SEC(".maps");
+
+static __always_inline void events_stats_inc(u32 stat)
+{
+	u64 *count = bpf_map_lookup_elem(&events_stats_map, &stat);
+
+	if (count)
+		__sync_fetch_and_add(count, 1);
+}
+
+/* Event counters, reported in place of events when counting them (see
+ * count_events). Please keep synced with their Rust counterpart in
+ * crate::core::events::bpf.
+ */
+enum event_counters {
+	/* Packets dropped, by drop reason */
+	COUNTER_DROP_REASON = 1,
+	/* Packets seen, by ethertype */
+	COUNTER_ETHERTYPE = 2,
+	/* Packets seen, by L4 protocol */
+	COUNTER_L4_PROTO = 3,
+};
+
+#define EVENT_COUNTERS_MAX	4096
+
+/* Please keep synced with its Rust counterpart. */
+struct event_counter_key {
+	u32 counter;
+	u32 value;
+};
+
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, EVENT_COUNTERS_MAX);
+	__type(key, struct event_counter_key);
+	__type(value, u64);
+} event_counters_map SEC(".maps");
+
+static __always_inline void event_counter_inc(u32 counter, u32 value)
+{
+	struct event_counter_key key = {
+		.counter = counter,
+		.value = value,
+	};
+	u64 *count = bpf_map_lookup_elem(&event_counters_map, &key);
+
+	if (count) {
+		__sync_fetch_and_add(count, 1);
+	} else {
+		u64 one = 1;
+
+		/* Another CPU might have added the key in the meantime, in
+		 * which case a hit is lost. That's fine for counters.
+		 */
+		bpf_map_update_elem(&event_counters_map, &key, &one,
+				    BPF_NOEXIST);
+	}
+}
+
 static __always_inline struct trace_raw_event *get_event()
 {
 	struct trace_raw_event *event;
 
 	event = bpf_ringbuf_reserve(&events_map, sizeof(*event), 0);
-	if (!event)
+	if (!event) {
+		events_stats_inc(EVENTS_STATS_LOST);
 		return NULL;
+	}
 
 	event->size = 0;
 	return event;
@@ -60,12 +182,16 @@ static __always_inline void *get_event_section(struct trace_raw_event *event,
 	u16 left = RAW_EVENT_DATA_SIZE - event->size;
 	void *section;
 
-	if (sizeof(*header) + size > left || event->size > sizeof(event->data))
+	/* Events can't grow past their ring buffer slot. */
+	if (sizeof(*header) + size > left || event->size > sizeof(event->data)) {
+		events_stats_inc(EVENTS_STATS_OVERSIZED);
 		return NULL;
+	}
 
 	header = event->data + event->size;
 	header->owner = owner;
 	header->data_type = data_type;
+	header->version = section_version(owner);
 	header->size = size;
 
 	section = event->data + event->size + sizeof(*header);
@@ -77,6 +203,22 @@ static __always_inline void *get_event_section(struct trace_raw_event *event,
 struct common_event {
 	u64 symbol;
 	u64 timestamp;
+	u8 probe_type;
+	/* 0 if not requested (see clock_tai) */
+	u64 timestamp_tai;
+} __attribute__((packed));
+
+/* Execution context of an event, only reported when requested (see
+ * exec_context). Please keep in sync with its Rust counterpart in
+ * crate::core::events::bpf.
+ */
+struct common_context_event {
+	/* One of EXEC_CONTEXT_* */
+	u8 context;
+	/* Softirq vector + 1, 0 if not in a softirq or unknown */
+	u8 softirq;
+	u32 pid;
+	char comm[16];
 } __attribute__((packed));
 
 #endif /* __CORE_PROBE_KERNEL_BPF_EVENTS__ */
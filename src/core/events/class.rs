@@ -0,0 +1,158 @@
+//! # Event classification
+//!
+//! Collectors can classify the events they generate, by category (what
+//! happened to the packet) and severity, so downstream tools can route them,
+//! e.g. only alert on drops. Unmarshalers tag their section using
+//! [`classify`]; the classification is then moved to the "common" section as
+//! the `category` and `severity` fields. When multiple sections of an event
+//! are classified, the most severe classification wins. Events not classified
+//! by any collector do not have those fields.
+//!
+//! Those fields can be used in --where expressions, e.g. "category == drop".
+
+use super::{Event, EventField};
+use crate::event_field;
+
+/// Section the classification is reported in.
+const CLASS_SECTION: &str = "common";
+
+/// Reserved field keys, used to carry the classification from unmarshalers.
+const CATEGORY_KEY: &str = "category";
+const SEVERITY_KEY: &str = "severity";
+
+/// What happened to the packet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Category {
+    Info,
+    Forward,
+    Error,
+    Drop,
+}
+
+impl Category {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Category::Info => "info",
+            Category::Forward => "forward",
+            Category::Error => "error",
+            Category::Drop => "drop",
+        }
+    }
+
+    fn from_str(name: &str) -> Option<Category> {
+        Some(match name {
+            "info" => Category::Info,
+            "forward" => Category::Forward,
+            "error" => Category::Error,
+            "drop" => Category::Drop,
+            _ => return None,
+        })
+    }
+}
+
+/// Severity of an event, in increasing order.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub(crate) enum Severity {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Debug => "debug",
+            Severity::Info => "info",
+            Severity::Warn => "warn",
+            Severity::Error => "error",
+        }
+    }
+
+    fn from_str(name: &str) -> Option<Severity> {
+        Some(match name {
+            "debug" => Severity::Debug,
+            "info" => Severity::Info,
+            "warn" => Severity::Warn,
+            "error" => Severity::Error,
+            _ => return None,
+        })
+    }
+}
+
+/// Classify an event, from an unmarshaler.
+pub(crate) fn classify(fields: &mut Vec<EventField>, category: Category, severity: Severity) {
+    fields.push(event_field!(CATEGORY_KEY, category.as_str().to_string()));
+    fields.push(event_field!(SEVERITY_KEY, severity.as_str().to_string()));
+}
+
+/// Event classification being built from its sections.
+#[derive(Default)]
+pub(crate) struct Class(Option<(Category, Severity)>);
+
+impl Class {
+    /// Take the classification out of the fields of a section, if any.
+    pub(crate) fn take(&mut self, fields: &mut Vec<EventField>) {
+        let mut category = None;
+        let mut severity = None;
+        fields.retain(|f| {
+            let val = || f.to_json().as_str().map(String::from).unwrap_or_default();
+            match f.key() {
+                CATEGORY_KEY => category = Category::from_str(&val()),
+                SEVERITY_KEY => severity = Severity::from_str(&val()),
+                _ => return true,
+            }
+            false
+        });
+
+        if let (Some(category), Some(severity)) = (category, severity) {
+            if self.0.map_or(true, |(_, current)| severity > current) {
+                self.0 = Some((category, severity));
+            }
+        }
+    }
+
+    /// Report the classification in the event, if any.
+    pub(crate) fn apply(&self, event: &mut Event) {
+        if let Some((category, severity)) = self.0 {
+            event.insert(
+                CLASS_SECTION,
+                event_field!(CATEGORY_KEY, category.as_str().to_string()),
+            );
+            event.insert(
+                CLASS_SECTION,
+                event_field!(SEVERITY_KEY, severity.as_str().to_string()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn class() {
+        let mut class = Class::default();
+
+        let mut fields = vec![event_field!("len", 42u32)];
+        class.take(&mut fields);
+        assert!(fields.len() == 1 && class.0.is_none());
+
+        classify(&mut fields, Category::Forward, Severity::Info);
+        class.take(&mut fields);
+        assert!(fields.len() == 1 && class.0 == Some((Category::Forward, Severity::Info)));
+
+        // Most severe classification wins.
+        classify(&mut fields, Category::Drop, Severity::Warn);
+        class.take(&mut fields);
+        classify(&mut fields, Category::Info, Severity::Debug);
+        class.take(&mut fields);
+        assert!(class.0 == Some((Category::Drop, Severity::Warn)));
+
+        let mut event = Event::new();
+        class.apply(&mut event);
+        assert!(event.get::<String>("common", "category").unwrap() == Some(&"drop".to_string()));
+        assert!(event.get::<String>("common", "severity").unwrap() == Some(&"warn".to_string()));
+    }
+}
@@ -75,6 +75,20 @@ impl Event {
         Ok(None)
     }
 
+    /// Get an event field by its section and key. If no section is given, the
+    /// first section (in alphabetical order) having the key is used.
+    pub(crate) fn get_field(&self, section: Option<&str>, key: &str) -> Option<&EventField> {
+        match section {
+            Some(section) => self.0.get(section).and_then(|s| s.get(key)),
+            None => self
+                .0
+                .iter()
+                .filter_map(|(section, fields)| fields.get(key).map(|f| (section, f)))
+                .min_by(|a, b| a.0.cmp(b.0))
+                .map(|(_, f)| f),
+        }
+    }
+
     /// Insert a new event field into an event.
     pub(crate) fn insert(&mut self, key: &str, val: EventField) {
         let key = key.to_string();
@@ -88,9 +102,26 @@ impl Event {
         map.insert(val.key.clone(), val);
     }
 
+    /// Get the names of the event sections.
+    pub(crate) fn sections(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(|s| s.as_str())
+    }
+
     pub(crate) fn to_json(&self) -> serde_json::Value {
         self.into()
     }
+
+    /// Get all the event fields, along with the section they belong to,
+    /// sorted by section and key.
+    pub(crate) fn fields(&self) -> Vec<(&str, &EventField)> {
+        let mut fields: Vec<(&str, &EventField)> = self
+            .0
+            .iter()
+            .flat_map(|(section, fields)| fields.values().map(move |f| (section.as_str(), f)))
+            .collect();
+        fields.sort_by(|a, b| (a.0, &a.1.key).cmp(&(b.0, &b.1.key)));
+        fields
+    }
 }
 
 // This allows converting an Event to a serde_json::Value in the
@@ -126,6 +157,24 @@ impl EventField {
             val,
         }
     }
+
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Get the name of the field value type, e.g. "u64".
+    pub(crate) fn type_name(&self) -> &'static str {
+        self.val.name()
+    }
+
+    /// Get the field value, to be downcasted to its concrete type.
+    pub(crate) fn as_any(&self) -> &dyn Any {
+        self.val.as_any()
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        self.val.to_json()
+    }
 }
 
 /// Wrapper to easily create a new event field to insert into an event.
@@ -139,8 +188,9 @@ macro_rules! event_field {
 }
 
 /// Implementation of an event field type, used to hold the actual data and
-/// provide helpers to serialize/deserialize it.
-pub(crate) trait EventFieldType {
+/// provide helpers to serialize/deserialize it. Events are built and consumed
+/// from different threads, hence the Send requirement.
+pub(crate) trait EventFieldType: Send {
     fn name(&self) -> &'static str;
     fn as_any(&self) -> &dyn Any;
     fn to_json(&self) -> serde_json::Value;
@@ -174,8 +224,28 @@ macro_rules! event_field_type {
 }
 
 // Common types definition.
+event_field_type!(bool);
 event_field_type!(u32);
 event_field_type!(u64);
 event_field_type!(i32);
 event_field_type!(i64);
 event_field_type!(String);
+
+// Lists of structured values, e.g. a stack of headers.
+impl EventFieldType for Vec<serde_json::Value> {
+    fn name(&self) -> &'static str {
+        "list"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!(self)
+    }
+
+    fn from_json(from: serde_json::Value) -> Result<Self> {
+        Ok(serde_json::from_value(from)?)
+    }
+}
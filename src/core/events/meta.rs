@@ -0,0 +1,137 @@
+//! # Meta events
+//!
+//! Meta events are synthetic events, not generated by collectors, describing
+//! the collection itself. They go through the same path as other events and
+//! make captures self-describing. They hold a single "meta" section, whose
+//! "type" field tells which kind of meta event it is:
+//!
+//! - "start": emitted when the collection starts, describes the configuration
+//!   in effect (collectors, filters) as well as the tool and kernel versions.
+//! - "stop": emitted when the collection stops, holds summary counts.
+//! - "counters": emitted periodically and before the stop event when events
+//!   are counted instead of being reported (--count-only), holds the
+//!   cumulative counters.
+//! - "warning": emitted when something worth attention was found while
+//!   processing events, e.g. a forwarding loop. Its "warning" field tells which
+//!   kind of warning it is, other fields depending on it.
+
+use anyhow::Result;
+use nix::sys::utsname::uname;
+
+use super::{Event, EventField};
+use crate::event_field;
+
+/// Name of the section holding meta information.
+pub(crate) const META_SECTION: &str = "meta";
+
+/// Build a start meta event, given the list of enabled collectors and filters.
+pub(crate) fn start_event(collectors: &[&str], filters: &[String]) -> Result<Event> {
+    let mut event = Event::new();
+
+    event.insert(META_SECTION, event_field!("type", "start".to_string()));
+    event.insert(
+        META_SECTION,
+        event_field!("version", env!("CARGO_PKG_VERSION").to_string()),
+    );
+    event.insert(
+        META_SECTION,
+        event_field!("kernel", uname()?.release().to_string_lossy().to_string()),
+    );
+    event.insert(
+        META_SECTION,
+        event_field!("collectors", collectors.join(",")),
+    );
+    event.insert(META_SECTION, event_field!("filters", filters.join(",")));
+
+    Ok(event)
+}
+
+/// Build a stop meta event, holding summary counts.
+pub(crate) fn stop_event(events: u64, duration_ms: u64) -> Event {
+    let mut event = Event::new();
+
+    event.insert(META_SECTION, event_field!("type", "stop".to_string()));
+    event.insert(META_SECTION, event_field!("events", events));
+    event.insert(META_SECTION, event_field!("duration_ms", duration_ms));
+
+    event
+}
+
+/// Build a warning meta event of a given kind, to be completed by the caller.
+pub(crate) fn warning_event(kind: &str) -> Event {
+    let mut event = Event::new();
+
+    event.insert(META_SECTION, event_field!("type", "warning".to_string()));
+    event.insert(META_SECTION, event_field!("warning", kind.to_string()));
+
+    event
+}
+
+/// Build a counters meta event, holding one field per counter.
+pub(crate) fn counters_event(counters: &[(String, u64)]) -> Event {
+    let mut event = Event::new();
+
+    event.insert(META_SECTION, event_field!("type", "counters".to_string()));
+    for (name, count) in counters.iter() {
+        event.insert(META_SECTION, event_field!(name, *count));
+    }
+
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start() {
+        let event = start_event(&["skb", "ovs"], &["pid=42".to_string()]).unwrap();
+
+        assert!(event.get::<String>(META_SECTION, "type").unwrap() == Some(&"start".to_string()));
+        assert!(
+            event.get::<String>(META_SECTION, "collectors").unwrap()
+                == Some(&"skb,ovs".to_string())
+        );
+        assert!(
+            event.get::<String>(META_SECTION, "filters").unwrap() == Some(&"pid=42".to_string())
+        );
+        assert!(event
+            .get::<String>(META_SECTION, "kernel")
+            .unwrap()
+            .is_some());
+
+        let json = event.to_json();
+        assert!(json[META_SECTION]["version"] == env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn stop() {
+        let event = stop_event(1337, 42);
+
+        assert!(event.get::<String>(META_SECTION, "type").unwrap() == Some(&"stop".to_string()));
+        assert!(event.get::<u64>(META_SECTION, "events").unwrap() == Some(&1337));
+        assert!(event.get::<u64>(META_SECTION, "duration_ms").unwrap() == Some(&42));
+    }
+
+    #[test]
+    fn counters() {
+        let event = counters_event(&[
+            ("drop_reason_2".to_string(), 12),
+            ("l4_proto_17".to_string(), 42),
+        ]);
+
+        assert!(
+            event.get::<String>(META_SECTION, "type").unwrap() == Some(&"counters".to_string())
+        );
+        assert!(event.get::<u64>(META_SECTION, "drop_reason_2").unwrap() == Some(&12));
+        assert!(event.get::<u64>(META_SECTION, "l4_proto_17").unwrap() == Some(&42));
+    }
+
+    #[test]
+    fn warning() {
+        let event = warning_event("loop");
+
+        assert!(event.get::<String>(META_SECTION, "type").unwrap() == Some(&"warning".to_string()));
+        assert!(event.get::<String>(META_SECTION, "warning").unwrap() == Some(&"loop".to_string()));
+    }
+}
@@ -9,6 +9,20 @@
 //! https://nakryiko.com/posts/bpf-ringbuf/ and
 //! tools/testing/selftests/bpf/progs/test_ringbuf_multi.c (in the kernel source
 //! tree).
+//!
+//! Events are limited in size by their ring buffer slot (1022 bytes of data):
+//! sections not fitting are not added by the BPF part. A lower limit can be
+//! enforced in userspace (see --max-event-size), the last sections of larger
+//! events being dropped and the event flagged as "truncated". Events lost
+//! because the ring buffer was full, sections not fitting and truncated events
+//! are counted and reported in the stop event.
+//!
+//! Sections whose owner is unknown to userspace (e.g. when the BPF and
+//! userspace parts are not in sync) are skipped, the rest of the event being
+//! reported along with the number of skipped sections ("unknown_sections").
+//!
+//! Field names and types emitted by collectors are registered in a central
+//! registry, see registry::FieldRegistry.
 
 // Re-export events::events.
 #[allow(clippy::module_inception)]
@@ -16,3 +30,6 @@ pub(crate) mod events;
 pub(crate) use events::*;
 
 pub(crate) mod bpf;
+pub(crate) mod class;
+pub(crate) mod meta;
+pub(crate) mod registry;
@@ -0,0 +1,512 @@
+//! # Field registry
+//!
+//! Canonical names and types of the fields emitted by collectors, so a field
+//! name means the same thing whatever the collector it comes from: "saddr" is
+//! always a source IP address reported as a string, "ifindex" always an
+//! interface index, etc. Joining events of different collectors, selecting
+//! (--fields) or filtering (--where) fields can rely on it.
+//!
+//! Collectors must only emit registered fields, with their registered type;
+//! this is enforced by the collectors' unmarshaling tests (see
+//! FieldRegistry::check()). New fields must be added here, reusing an
+//! existing name when the meaning is the same.
+//!
+//! A few fields predating the registry have a different meaning in a given
+//! section and are registered for that section only, overriding the canonical
+//! one. Fields of the kprobe collector are named after the arguments of the
+//! probed functions and are not registered.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use once_cell::sync::Lazy;
+
+use super::EventField;
+
+/// Canonical fields: name, type (see EventFieldType::name()) and description.
+const FIELDS: &[(&str, &str, &str)] = &[
+    ("action", "String", "XDP action returned by the program"),
+    ("backlog_len", "u32", "Socket backlog length, in bytes"),
+    ("backlog_limit", "u32", "Socket backlog limit, in bytes"),
+    ("bridge", "u32", "Interface index of the bridge"),
+    ("ca_state", "String", "TCP congestion avoidance state"),
+    ("category", "String", "Event category, e.g. drop"),
+    (
+        "cc_algo",
+        "String",
+        "TCP congestion control algorithm, e.g. cubic",
+    ),
+    ("cgroup_id", "u64", "Cgroup id"),
+    (
+        "cgroup_path",
+        "String",
+        "Cgroup path, relative to the cgroup root",
+    ),
+    ("chain", "String", "Netfilter chain"),
+    ("cloned", "bool", "Whether the skb is a clone"),
+    (
+        "cloned_from",
+        "u64",
+        "Address of the skb this one was cloned from",
+    ),
+    ("comm", "String", "Command name of the current task"),
+    (
+        "context",
+        "String",
+        "Execution context: task, softirq, hardirq or nmi",
+    ),
+    ("cpu", "u32", "CPU the packet is processed on"),
+    (
+        "csum_absent",
+        "bool",
+        "Whether the packet has no L4 checksum",
+    ),
+    (
+        "csum_bad",
+        "String",
+        "Layers whose checksum is wrong, comma separated",
+    ),
+    (
+        "csum_ok",
+        "bool",
+        "Whether the verified checksums are correct",
+    ),
+    (
+        "ct_direction",
+        "String",
+        "Conntrack direction: original or reply",
+    ),
+    ("ct_mark", "u32", "Conntrack mark"),
+    ("ct_state", "String", "Conntrack state of the packet"),
+    (
+        "ct_status",
+        "String",
+        "Conntrack status flags, '|' separated",
+    ),
+    ("ct_zone", "u32", "Conntrack zone"),
+    ("daddr", "String", "Destination IP address"),
+    ("data_len", "u32", "Length of the paged data, in bytes"),
+    (
+        "data_offset",
+        "u32",
+        "Offset of the packet data in the skb buffer",
+    ),
+    ("dev_mtu", "u32", "MTU of the device"),
+    (
+        "direction",
+        "String",
+        "Traffic direction, e.g. ingress or egress",
+    ),
+    ("dport", "u32", "Destination port"),
+    (
+        "drop_reason",
+        "u32",
+        "Kernel drop reason, 0 when not dropped",
+    ),
+    ("end_offset", "u32", "Offset of the end of the skb buffer"),
+    ("endpoint", "String", "Remote endpoint, as addr:port"),
+    ("entries", "u32", "Number of entries of the netfilter hook"),
+    ("err", "i32", "Error code"),
+    (
+        "ext_headers_truncated",
+        "bool",
+        "Whether IPv6 extension headers were not all parsed",
+    ),
+    ("family", "String", "Address or protocol family, e.g. ipv4"),
+    ("fd", "i64", "File descriptor of the socket"),
+    ("fdb", "String", "Result of the FDB lookup: hit or miss"),
+    ("function", "String", "Code location, as symbol+offset"),
+    ("gso_segs", "u32", "Number of GSO segments"),
+    ("gso_size", "u32", "GSO segment size, in bytes"),
+    (
+        "gtp_inner_daddr",
+        "String",
+        "Destination address of the packet carried in a GTP-U tunnel",
+    ),
+    (
+        "gtp_inner_dport",
+        "u32",
+        "Destination port of the packet carried in a GTP-U tunnel",
+    ),
+    (
+        "gtp_inner_proto",
+        "u32",
+        "L4 protocol of the packet carried in a GTP-U tunnel",
+    ),
+    (
+        "gtp_inner_saddr",
+        "String",
+        "Source address of the packet carried in a GTP-U tunnel",
+    ),
+    (
+        "gtp_inner_sport",
+        "u32",
+        "Source port of the packet carried in a GTP-U tunnel",
+    ),
+    ("gtp_msg_type", "u32", "GTP-U message type"),
+    ("gtp_teid", "u32", "GTP-U tunnel endpoint identifier"),
+    (
+        "gtp_truncated",
+        "bool",
+        "Whether decoding the GTP-U inner packet stopped early",
+    ),
+    (
+        "has_fragment_header",
+        "bool",
+        "Whether the packet has an IPv6 fragment header",
+    ),
+    (
+        "has_hbh",
+        "bool",
+        "Whether the packet has an IPv6 hop-by-hop header",
+    ),
+    (
+        "has_ip_options",
+        "bool",
+        "Whether the packet has IPv4 options",
+    ),
+    (
+        "has_routing_header",
+        "bool",
+        "Whether the packet has an IPv6 routing header",
+    ),
+    ("hash", "u32", "Packet hash"),
+    ("hook", "String", "Netfilter hook"),
+    ("icmp_code", "u32", "ICMP code"),
+    ("icmp_name", "String", "ICMP type and code names"),
+    (
+        "icmp_orig_daddr",
+        "String",
+        "Destination IP address of the packet an ICMP error is about",
+    ),
+    (
+        "icmp_orig_dport",
+        "u32",
+        "Destination port of the packet an ICMP error is about",
+    ),
+    (
+        "icmp_orig_proto",
+        "u32",
+        "L4 protocol of the packet an ICMP error is about",
+    ),
+    (
+        "icmp_orig_saddr",
+        "String",
+        "Source IP address of the packet an ICMP error is about",
+    ),
+    (
+        "icmp_orig_sport",
+        "u32",
+        "Source port of the packet an ICMP error is about",
+    ),
+    ("icmp_type", "u32", "ICMP type"),
+    ("ifindex", "u32", "Interface index"),
+    ("index", "u32", "Index of the netfilter hook entry"),
+    ("ip_summed", "String", "Checksum status of the skb"),
+    ("kind", "String", "Kind of event, specific to the collector"),
+    (
+        "l4_hash",
+        "bool",
+        "Whether the packet hash covers the L4 ports",
+    ),
+    ("len", "u32", "Packet length, in bytes"),
+    ("location", "u64", "Code location address"),
+    (
+        "mac_header",
+        "u32",
+        "Offset of the MAC header in the skb buffer",
+    ),
+    ("mark", "u32", "Packet mark"),
+    ("mpls_labels", "list", "MPLS label stack, outermost first"),
+    ("mtu", "u32", "MTU the packet exceeded"),
+    (
+        "mtu_drop",
+        "bool",
+        "Whether the packet is dropped for exceeding the MTU",
+    ),
+    (
+        "network_header",
+        "u32",
+        "Offset of the network header in the skb buffer",
+    ),
+    (
+        "nohdr",
+        "bool",
+        "Whether the skb header is not to be modified",
+    ),
+    (
+        "offloaded",
+        "bool",
+        "Whether the checksum is computed by the device",
+    ),
+    (
+        "old_state",
+        "String",
+        "Previous TCP state, on state changes",
+    ),
+    ("orig_cpu", "u32", "CPU the packet was first seen on"),
+    (
+        "orig_head",
+        "u64",
+        "Address of the original skb data, identifying a packet",
+    ),
+    ("packet", "String", "Packet bytes, hex encoded"),
+    (
+        "packet_len",
+        "u32",
+        "Length of the packet the bytes were captured from",
+    ),
+    ("parse_error", "bool", "Whether parsing the packet failed"),
+    (
+        "parse_truncated",
+        "bool",
+        "Whether parsing the packet stopped early",
+    ),
+    (
+        "peer",
+        "String",
+        "WireGuard peer, as the base64 prefix of its public key",
+    ),
+    ("peer_id", "u64", "WireGuard internal peer id"),
+    ("pid", "u32", "Process id of the current task"),
+    ("pkt_type", "u32", "Packet type, see PACKET_* in the kernel"),
+    ("port", "u32", "Bridge port number"),
+    ("port_ifindex", "u32", "Interface index of the bridge port"),
+    ("priority", "i32", "Netfilter hook priority"),
+    ("prog_id", "u32", "BPF program id"),
+    ("probe", "String", "Probe the event was reported from"),
+    ("protocol", "u32", "L3 protocol (ethertype)"),
+    ("queue_mapping", "u32", "Queue the packet is mapped to"),
+    ("rcvbuf", "u32", "Socket receive buffer size, in bytes"),
+    (
+        "reason",
+        "String",
+        "Reason of a collector specific event, e.g. a drop",
+    ),
+    (
+        "retransmits",
+        "u32",
+        "Number of unrecovered retransmissions",
+    ),
+    (
+        "rmem_alloc",
+        "u32",
+        "Socket receive memory allocated, in bytes",
+    ),
+    (
+        "rttvar_us",
+        "u32",
+        "TCP RTT mean deviation, as estimated by the kernel, in us",
+    ),
+    ("saddr", "String", "Source IP address"),
+    ("secmark", "u32", "Security mark"),
+    (
+        "severity",
+        "String",
+        "Event severity: debug, info, warn or error",
+    ),
+    ("sk_cookie", "u64", "Socket cookie"),
+    ("sk_drops", "u32", "Number of packets dropped by the socket"),
+    (
+        "sk_rcvbuf",
+        "u32",
+        "Receive buffer size of the packet socket, in bytes",
+    ),
+    (
+        "sk_rcvbuf_full",
+        "bool",
+        "Whether the receive buffer of the packet socket is full",
+    ),
+    (
+        "sk_rmem_alloc",
+        "u32",
+        "Receive memory allocated by the packet socket, in bytes",
+    ),
+    ("skb", "u64", "Address of the skb"),
+    ("snd_cwnd", "u32", "TCP congestion window"),
+    ("snd_ssthresh", "u32", "TCP slow start threshold"),
+    (
+        "socket_cookie",
+        "u64",
+        "Cookie of the socket the packet is filtered on",
+    ),
+    ("socket_inode", "u64", "Inode of the socket"),
+    ("softirq", "String", "Softirq being run, e.g. NET_RX"),
+    ("spi", "u32", "IPsec security parameter index"),
+    ("sport", "u32", "Source port"),
+    (
+        "srtt_us",
+        "u32",
+        "TCP smoothed RTT, as estimated by the kernel, in us",
+    ),
+    ("state", "String", "TCP state"),
+    (
+        "steered",
+        "bool",
+        "Whether the packet was steered to another CPU",
+    ),
+    ("symbol", "String", "Symbol the event was reported from"),
+    ("table", "String", "Netfilter table"),
+    (
+        "tail_offset",
+        "u32",
+        "Offset of the end of the packet data in the skb buffer",
+    ),
+    ("timestamp", "u64", "Monotonic timestamp, in nanoseconds"),
+    ("timestamp_tai", "u64", "TAI timestamp, in nanoseconds"),
+    ("total_retrans", "u32", "Total number of retransmissions"),
+    (
+        "transport_header",
+        "u32",
+        "Offset of the transport header in the skb buffer",
+    ),
+    ("truesize", "u32", "Memory used by the packet, in bytes"),
+    (
+        "truncated",
+        "bool",
+        "Whether sections were dropped from the event",
+    ),
+    ("type", "String", "Type of event, specific to the collector"),
+    (
+        "unknown_sections",
+        "u64",
+        "Number of sections of unknown owners skipped",
+    ),
+    (
+        "v4_mapped",
+        "bool",
+        "Whether IPv4 addresses were mapped in IPv6 ones",
+    ),
+    (
+        "verdict",
+        "String",
+        "Bridge forwarding verdict: forward or flood",
+    ),
+];
+
+/// Fields specific to a section, overriding the canonical ones: section,
+/// name, type and description.
+const SECTION_FIELDS: &[(&str, &str, &str, &str)] =
+    &[("sockdrop", "protocol", "String", "L4 protocol, e.g. tcp")];
+
+/// Sections whose fields are not registered.
+const UNREGISTERED_SECTIONS: &[&str] = &["kprobe"];
+
+/// A registered field.
+#[derive(Debug)]
+pub(crate) struct FieldDesc {
+    /// Section the field is specific to, None for canonical fields.
+    pub(crate) section: Option<&'static str>,
+    pub(crate) name: &'static str,
+    /// Type of the field value, see EventFieldType::name().
+    pub(crate) r#type: &'static str,
+    pub(crate) description: &'static str,
+}
+
+pub(crate) struct FieldRegistry {
+    /// Registered fields, by section (None for canonical ones) and name.
+    fields: HashMap<(Option<&'static str>, &'static str), FieldDesc>,
+}
+
+static REGISTRY: Lazy<FieldRegistry> = Lazy::new(FieldRegistry::new);
+
+impl FieldRegistry {
+    fn new() -> FieldRegistry {
+        let canonical = FIELDS
+            .iter()
+            .map(|(name, r#type, description)| (None, *name, *r#type, *description));
+        let scoped = SECTION_FIELDS
+            .iter()
+            .map(|(section, name, r#type, description)| {
+                (Some(*section), *name, *r#type, *description)
+            });
+
+        FieldRegistry {
+            fields: canonical
+                .chain(scoped)
+                .map(|(section, name, r#type, description)| {
+                    (
+                        (section, name),
+                        FieldDesc {
+                            section,
+                            name,
+                            r#type,
+                            description,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Get the field registry.
+    pub(crate) fn get() -> &'static FieldRegistry {
+        &REGISTRY
+    }
+
+    /// Get the description of a field of a section, if registered.
+    pub(crate) fn lookup(&self, section: &str, name: &str) -> Option<&FieldDesc> {
+        self.fields
+            .get(&(Some(section), name))
+            .or_else(|| self.fields.get(&(None, name)))
+    }
+
+    /// Check fields, along with their section, are registered with their
+    /// type. Fields of unregistered sections are not checked.
+    pub(crate) fn check<'a>(
+        &self,
+        fields: impl IntoIterator<Item = (&'a str, &'a EventField)>,
+    ) -> Result<()> {
+        for (section, field) in fields {
+            if UNREGISTERED_SECTIONS.contains(&section) {
+                continue;
+            }
+            match self.lookup(section, field.key()) {
+                None => bail!("Field {}.{} is not registered", section, field.key()),
+                Some(desc) if desc.r#type != field.type_name() => bail!(
+                    "Field {}.{} is a {}, registered as a {}",
+                    section,
+                    field.key(),
+                    field.type_name(),
+                    desc.r#type
+                ),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::events::Event, event_field};
+
+    #[test]
+    fn registry() -> Result<()> {
+        let registry = FieldRegistry::get();
+
+        // Names are unique.
+        assert!(registry.fields.len() == FIELDS.len() + SECTION_FIELDS.len());
+        let types = ["bool", "u32", "u64", "i32", "i64", "String", "list"];
+        assert!(registry.fields.values().all(|f| types.contains(&f.r#type)));
+
+        // Section specific fields override the canonical ones.
+        assert!(registry.lookup("skb", "protocol").unwrap().r#type == "u32");
+        assert!(registry.lookup("sockdrop", "protocol").unwrap().r#type == "String");
+        assert!(registry.lookup("skb", "foo").is_none());
+
+        let mut event = Event::new();
+        event.insert("skb", event_field!("ifindex", 2u32));
+        event.insert("sockdrop", event_field!("protocol", "tcp".to_string()));
+        event.insert("kprobe", event_field!("size", 42u64));
+        assert!(registry.check(event.fields()).is_ok());
+
+        // Unregistered fields and wrong types are reported.
+        event.insert("skb", event_field!("foo", 1u32));
+        assert!(registry.check(event.fields()).is_err());
+        let field = event_field!("ifindex", 2u64);
+        assert!(registry.check([("skb", &field)]).is_err());
+        Ok(())
+    }
+}
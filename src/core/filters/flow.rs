@@ -0,0 +1,171 @@
+//! # Flow filter
+//!
+//! Restrict events to the packets of a single flow, in both directions. A
+//! flow is given as "<src>-<dst>[/<proto>]", where endpoints are
+//! "<addr>[:<port>]" (IPv6 addresses in brackets when a port is given) and
+//! both addresses and ports can be wildcards ("*"), e.g.
+//! "10.0.0.1:1234-10.0.0.2:443/tcp", "*-10.0.0.2:53/udp" or
+//! "[2001:db8::1]:22-*".
+//!
+//! The matching itself is done in the skb collector BPF hook.
+
+use std::{fmt, net::IpAddr};
+
+use anyhow::{bail, Result};
+
+/// One end of a flow. None values are wildcards.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct FlowEndpoint {
+    pub(crate) addr: Option<IpAddr>,
+    pub(crate) port: Option<u16>,
+}
+
+impl FlowEndpoint {
+    fn parse(endpoint: &str) -> Result<FlowEndpoint> {
+        let (addr, port) = match endpoint.strip_prefix('[') {
+            // Bracketed IPv6 address, optionally followed by a port.
+            Some(rest) => match rest.split_once(']') {
+                Some((addr, "")) => (addr, None),
+                Some((addr, port)) => match port.strip_prefix(':') {
+                    Some(port) => (addr, Some(port)),
+                    None => bail!("invalid endpoint '{}'", endpoint),
+                },
+                None => bail!("invalid endpoint '{}'", endpoint),
+            },
+            None => match endpoint.matches(':').count() {
+                0 => (endpoint, None),
+                1 => endpoint
+                    .split_once(':')
+                    .map(|(addr, port)| (addr, Some(port)))
+                    .unwrap(),
+                // IPv6 address without a port.
+                _ => (endpoint, None),
+            },
+        };
+
+        Ok(FlowEndpoint {
+            addr: match addr {
+                "*" | "" => None,
+                addr => Some(
+                    addr.parse()
+                        .or_else(|_| bail!("invalid address '{}'", addr))?,
+                ),
+            },
+            port: match port {
+                None | Some("*") => None,
+                Some(port) => match port.parse::<u16>() {
+                    Ok(port) if port != 0 => Some(port),
+                    _ => bail!("invalid port '{}'", port),
+                },
+            },
+        })
+    }
+}
+
+impl fmt::Display for FlowEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.addr {
+            Some(IpAddr::V6(addr)) => write!(f, "[{}]", addr)?,
+            Some(addr) => write!(f, "{}", addr)?,
+            None => write!(f, "*")?,
+        }
+        match self.port {
+            Some(port) => write!(f, ":{}", port),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Flow to filter on. Packets match in both directions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Flow {
+    pub(crate) src: FlowEndpoint,
+    pub(crate) dst: FlowEndpoint,
+    /// L4 protocol, None being a wildcard.
+    pub(crate) proto: Option<u8>,
+}
+
+impl Flow {
+    pub(crate) fn parse(flow: &str) -> Result<Flow> {
+        let (endpoints, proto) = match flow.rsplit_once('/') {
+            Some((endpoints, proto)) => (endpoints, Some(proto)),
+            None => (flow, None),
+        };
+
+        let (src, dst) = match endpoints.split_once('-') {
+            Some((src, dst)) => (FlowEndpoint::parse(src)?, FlowEndpoint::parse(dst)?),
+            None => bail!("missing '-' between the flow endpoints"),
+        };
+
+        if let (Some(saddr), Some(daddr)) = (src.addr, dst.addr) {
+            if saddr.is_ipv4() != daddr.is_ipv4() {
+                bail!("endpoints addresses are not of the same family");
+            }
+        }
+
+        let proto = match proto {
+            None | Some("*") => None,
+            Some("tcp") => Some(6),
+            Some("udp") => Some(17),
+            Some("sctp") => Some(132),
+            Some(x) => bail!("unknown protocol '{}' (expected tcp, udp or sctp)", x),
+        };
+
+        Ok(Flow { src, dst, proto })
+    }
+}
+
+// Allow describing the filter, e.g. in meta events.
+impl fmt::Display for Flow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "flow={}-{}", self.src, self.dst)?;
+        match self.proto {
+            Some(6) => write!(f, "/tcp"),
+            Some(17) => write!(f, "/udp"),
+            Some(132) => write!(f, "/sctp"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let flow = Flow::parse("10.0.0.1:1234-10.0.0.2:443/tcp")?;
+        assert!(flow.src.addr == Some("10.0.0.1".parse()?));
+        assert!(flow.src.port == Some(1234));
+        assert!(flow.dst.addr == Some("10.0.0.2".parse()?));
+        assert!(flow.dst.port == Some(443));
+        assert!(flow.proto == Some(6));
+        assert!(flow.to_string() == "flow=10.0.0.1:1234-10.0.0.2:443/tcp");
+
+        // Wildcards.
+        let flow = Flow::parse("*-10.0.0.2:53/udp")?;
+        assert!(flow.src == FlowEndpoint::default());
+        assert!(flow.dst.port == Some(53));
+        let flow = Flow::parse("10.0.0.1:*-*:443")?;
+        assert!(flow.src.port.is_none() && flow.dst.addr.is_none());
+        assert!(flow.proto.is_none());
+
+        // IPv6.
+        let flow = Flow::parse("[2001:db8::1]:22-2001:db8::2")?;
+        assert!(flow.src.addr == Some("2001:db8::1".parse()?));
+        assert!(flow.src.port == Some(22));
+        assert!(flow.dst.addr == Some("2001:db8::2".parse()?));
+        assert!(flow.dst.port.is_none());
+        assert!(flow.to_string() == "flow=[2001:db8::1]:22-[2001:db8::2]");
+
+        // Invalid flows.
+        assert!(Flow::parse("10.0.0.1:1234").is_err());
+        assert!(Flow::parse("10.0.0.1-10.0.0.2/icmp").is_err());
+        assert!(Flow::parse("10.0.0.1:70000-10.0.0.2").is_err());
+        assert!(Flow::parse("10.0.0.1:0-10.0.0.2").is_err());
+        assert!(Flow::parse("10.0.0.300-10.0.0.2").is_err());
+        assert!(Flow::parse("10.0.0.1-2001:db8::2").is_err());
+        assert!(Flow::parse("[2001:db8::1-*").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! # Interface filter
+//!
+//! Restrict events to packets attached to given network interfaces, by name
+//! (e.g. "lo") or glob pattern (e.g. "eth*"). Interfaces are resolved to their
+//! ifindex, in the current network namespace, and stored in a map the skb
+//! collector BPF hook looks packets up in; packets not attached to a device
+//! are not reported. When patterns are used, interfaces are rescanned
+//! periodically and the map updated, for interfaces created after the
+//! collection started to be matched.
+
+use std::{
+    collections::HashSet,
+    fmt, fs, mem,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use log::warn;
+use once_cell::sync::OnceCell;
+
+use super::pin::pinnable_map;
+use crate::{cli::cmd::collect::glob_match, core::workaround::SendableMap};
+
+/// Max number of interfaces we can filter on. Please keep in sync with its BPF
+/// counterpart in collector/skb/bpf/skb_hook.bpf.c
+pub(crate) const IFACE_FILTER_MAX: u32 = 256;
+
+/// Interfaces are rescanned every IFACE_FILTER_REFRESH_INTERVAL seconds when
+/// patterns are used.
+const IFACE_FILTER_REFRESH_INTERVAL: u64 = 1;
+
+static IFACE_FILTER: OnceCell<Arc<IfaceFilter>> = OnceCell::new();
+
+/// Sets the global interface filter, used by the skb collector.
+///
+/// Returns an error if it was already set.
+pub(crate) fn set_iface_filter(filter: Arc<IfaceFilter>) -> Result<()> {
+    IFACE_FILTER
+        .set(filter)
+        .or_else(|_| bail!("Interface filter was already set"))?;
+    Ok(())
+}
+
+/// Returns the global interface filter, if any.
+pub(crate) fn get_iface_filter() -> Option<&'static Arc<IfaceFilter>> {
+    IFACE_FILTER.get()
+}
+
+/// Interface filter handle. Keeps the filter map alive (and up to date when
+/// patterns are used) for the whole collection.
+pub(crate) struct IfaceFilter {
+    /// Interface names and patterns being targeted.
+    patterns: Vec<String>,
+    map_fd: i32,
+    /// Filter state, shared with the refresher thread.
+    state: Arc<Mutex<IfaceFilterState>>,
+    // The refresher thread is only kept alive here.
+    #[allow(dead_code)]
+    refresher: Option<thread::JoinHandle<()>>,
+}
+
+// Filter state, which can be updated at runtime.
+struct IfaceFilterState {
+    /// Ifindexes currently in the filter map.
+    known: HashSet<u32>,
+    map: SendableMap,
+}
+
+impl IfaceFilter {
+    /// Create a new interface filter targeting the given names and patterns.
+    /// Names which aren't patterns must be existing interfaces.
+    pub(crate) fn new(patterns: &[String]) -> Result<IfaceFilter> {
+        let interfaces = interfaces()?;
+        for name in patterns.iter().filter(|p| !is_pattern(p)) {
+            if !interfaces.iter().any(|(iface, _)| iface == name) {
+                bail!("Can't filter on interface {}: no such interface", name);
+            }
+        }
+
+        let map = SendableMap::from(pinnable_map("iface_filter_map", Self::iface_filter_map)?);
+        let map_fd = map.get().fd();
+
+        let mut state = IfaceFilterState {
+            known: HashSet::new(),
+            map,
+        };
+        state.update(&matching(patterns, &interfaces))?;
+
+        let mut filter = IfaceFilter {
+            patterns: patterns.to_vec(),
+            map_fd,
+            state: Arc::new(Mutex::new(state)),
+            refresher: None,
+        };
+
+        if patterns.iter().any(|p| is_pattern(p)) {
+            let state = Arc::clone(&filter.state);
+            let patterns = filter.patterns.clone();
+            filter.refresher = Some(thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(IFACE_FILTER_REFRESH_INTERVAL));
+
+                let res = interfaces()
+                    .and_then(|ifaces| state.lock().unwrap().update(&matching(&patterns, &ifaces)));
+                if let Err(e) = res {
+                    warn!("Could not refresh the interface filter: {}", e);
+                }
+            }));
+        }
+
+        Ok(filter)
+    }
+
+    /// Get the interface filter map fd for reuse by the skb collector hook.
+    pub(crate) fn map_fd(&self) -> i32 {
+        self.map_fd
+    }
+
+    fn iface_filter_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in
+        // collector/skb/bpf/skb_hook.bpf.c
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("iface_filter_map"),
+            mem::size_of::<u32>() as u32,
+            mem::size_of::<u8>() as u32,
+            IFACE_FILTER_MAX,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the interface filter map: {}", e))
+    }
+}
+
+impl IfaceFilterState {
+    /// Update the filter map so it contains the given ifindexes only.
+    fn update(&mut self, targets: &HashSet<u32>) -> Result<()> {
+        let map = self.map.get_mut();
+
+        // Remove interfaces which are gone first, to make room for new ones.
+        for ifindex in self.known.difference(targets) {
+            map.delete(&ifindex.to_ne_bytes()).ok();
+        }
+
+        let mut full = false;
+        for ifindex in targets.difference(&self.known) {
+            if map
+                .update(&ifindex.to_ne_bytes(), &[1], libbpf_rs::MapFlags::ANY)
+                .is_err()
+            {
+                full = true;
+            }
+        }
+
+        if full {
+            warn!(
+                "Interface filter map is full (max {} interfaces), some events will be missed",
+                IFACE_FILTER_MAX
+            );
+        }
+
+        self.known = targets.clone();
+        Ok(())
+    }
+}
+
+// Allow describing the filter, e.g. in meta events.
+impl fmt::Display for IfaceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interface={}", self.patterns.join(","))
+    }
+}
+
+fn is_pattern(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// List the interfaces of the current network namespace, with their ifindex.
+fn interfaces() -> Result<Vec<(String, u32)>> {
+    let mut interfaces = Vec::new();
+
+    for entry in fs::read_dir("/sys/class/net")? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // Interfaces can be removed while we're scanning them, ignore errors.
+        if let Ok(Ok(ifindex)) =
+            fs::read_to_string(entry.path().join("ifindex")).map(|x| x.trim().parse::<u32>())
+        {
+            interfaces.push((name, ifindex));
+        }
+    }
+    Ok(interfaces)
+}
+
+/// Ifindexes of the interfaces matching one of the names or patterns.
+fn matching(patterns: &[String], interfaces: &[(String, u32)]) -> HashSet<u32> {
+    interfaces
+        .iter()
+        .filter(|(name, _)| patterns.iter().any(|p| glob_match(p, name)))
+        .map(|(_, ifindex)| *ifindex)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_interfaces() {
+        let interfaces = vec![
+            ("lo".to_string(), 1),
+            ("eth0".to_string(), 2),
+            ("eth1".to_string(), 3),
+            ("veth42".to_string(), 7),
+        ];
+        let patterns = |p: &[&str]| p.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        assert!(matching(&patterns(&["eth*"]), &interfaces) == HashSet::from([2, 3]));
+        assert!(matching(&patterns(&["lo", "*eth4?"]), &interfaces) == HashSet::from([1, 7]));
+        assert!(matching(&patterns(&["eth"]), &interfaces).is_empty());
+
+        assert!(is_pattern("eth*"));
+        assert!(is_pattern("eth?"));
+        assert!(!is_pattern("eth0"));
+    }
+}
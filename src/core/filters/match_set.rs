@@ -0,0 +1,450 @@
+//! # Match set filter
+//!
+//! Restrict events to packets matching a set of 5-tuples and IP prefixes,
+//! loaded from a file. This scales to large sets (e.g. a list of addresses
+//! from a threat-intel feed) and the set can be replaced while the collection
+//! is running, using the control socket.
+//!
+//! The file has one entry per line; empty lines and lines starting with '#'
+//! are ignored. An entry is either:
+//! - An IP prefix, "<addr>[/<len>]", e.g. "10.0.0.0/8" or "2001:db8::1".
+//!   Packets having their source or destination address in the prefix match.
+//! - A 5-tuple, using the --flow syntax without wildcards, e.g.
+//!   "10.0.0.1:1234-10.0.0.2:443/tcp". Packets of the tuple match, in both
+//!   directions.
+//!
+//! The matching itself is done in the skb collector BPF hook, which looks up
+//! the packets in two maps: a hash of 5-tuples and an LPM trie of prefixes.
+//! Sets are double buffered to be replaced atomically: map keys carry a
+//! generation, the active one being stored in a third map. A new set is
+//! inserted using the inactive generation before being activated, and the old
+//! one removed afterwards; a packet is always matched against a full set.
+//! External tools updating pinned match set maps (see --pin-maps) must insert
+//! entries using the active generation.
+
+use std::{
+    fmt, fs, mem,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail, Result};
+use once_cell::sync::OnceCell;
+
+use super::{flow::Flow, pin::pinnable_map};
+use crate::core::workaround::SendableMap;
+
+/// Max number of tuples, and of prefixes, in a set. Please keep in sync with
+/// its BPF counterpart in collector/skb/bpf/skb_hook.bpf.c
+pub(crate) const MATCH_SET_MAX: u32 = 65536;
+
+// Address families. Please keep in sync with the FLOW_FAMILY_* BPF
+// counterparts in collector/skb/bpf/skb_hook.bpf.c
+const MATCH_FAMILY_IPV4: u8 = 4;
+const MATCH_FAMILY_IPV6: u8 = 6;
+
+/// Bits of the prefix key before the address (generation and family).
+const MATCH_PREFIX_KEY_HDR_BITS: u32 = 16;
+
+// Tuple map key. Please keep in sync with its BPF counterpart in
+// collector/skb/bpf/skb_hook.bpf.c
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+struct MatchTupleKey {
+    gen: u8,
+    pad: [u8; 3],
+    saddr: [u8; 16],
+    daddr: [u8; 16],
+    sport: u16,
+    dport: u16,
+    family: u8,
+    proto: u8,
+    pad2: [u8; 2],
+}
+unsafe impl plain::Plain for MatchTupleKey {}
+
+// Prefix map (LPM trie) key. Please keep in sync with its BPF counterpart in
+// collector/skb/bpf/skb_hook.bpf.c
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+struct MatchPrefixKey {
+    /// Number of significant bits, starting at gen.
+    prefixlen: u32,
+    gen: u8,
+    family: u8,
+    addr: [u8; 16],
+    pad: [u8; 2],
+}
+unsafe impl plain::Plain for MatchPrefixKey {}
+
+/// Entry of a match set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MatchEntry {
+    Tuple(MatchTupleKey),
+    Prefix(MatchPrefixKey),
+}
+
+impl MatchEntry {
+    fn parse(entry: &str) -> Result<MatchEntry> {
+        // IP addresses never contain '-', tuples always do.
+        if entry.contains('-') {
+            let flow = Flow::parse(entry)?;
+            let (saddr, daddr, sport, dport, proto) = match (
+                flow.src.addr,
+                flow.dst.addr,
+                flow.src.port,
+                flow.dst.port,
+                flow.proto,
+            ) {
+                (Some(saddr), Some(daddr), Some(sport), Some(dport), Some(proto)) => {
+                    (saddr, daddr, sport, dport, proto)
+                }
+                _ => bail!("5-tuples can't have wildcards"),
+            };
+
+            let (family, saddr) = raw_addr(saddr);
+            return Ok(MatchEntry::Tuple(MatchTupleKey {
+                saddr,
+                daddr: raw_addr(daddr).1,
+                sport,
+                dport,
+                family,
+                proto,
+                ..Default::default()
+            }));
+        }
+
+        let (addr, len) = match entry.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (entry, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .or_else(|_| bail!("invalid address '{}'", addr))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let len = match len {
+            Some(len) => match len.parse::<u32>() {
+                Ok(len) if len <= max => len,
+                _ => bail!("invalid prefix length '{}'", len),
+            },
+            None => max,
+        };
+
+        let (family, addr) = raw_addr(addr);
+        Ok(MatchEntry::Prefix(MatchPrefixKey {
+            prefixlen: MATCH_PREFIX_KEY_HDR_BITS + len,
+            family,
+            addr,
+            ..Default::default()
+        }))
+    }
+
+    /// Map key of the entry, for a given generation.
+    fn key(&self, gen: u8) -> Vec<u8> {
+        match self {
+            MatchEntry::Tuple(key) => {
+                let key = MatchTupleKey { gen, ..*key };
+                unsafe { plain::as_bytes(&key) }.to_vec()
+            }
+            MatchEntry::Prefix(key) => {
+                let key = MatchPrefixKey { gen, ..*key };
+                unsafe { plain::as_bytes(&key) }.to_vec()
+            }
+        }
+    }
+}
+
+/// Convert an address to its family and raw representation. IPv4 addresses
+/// only use the first 4 bytes.
+fn raw_addr(addr: IpAddr) -> (u8, [u8; 16]) {
+    let mut raw = [0; 16];
+    match addr {
+        IpAddr::V4(addr) => {
+            raw[..4].copy_from_slice(&addr.octets());
+            (MATCH_FAMILY_IPV4, raw)
+        }
+        IpAddr::V6(addr) => (MATCH_FAMILY_IPV6, addr.octets()),
+    }
+}
+
+/// Parse the content of a match set file.
+fn parse_entries(content: &str) -> Result<Vec<MatchEntry>> {
+    let mut entries = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        entries
+            .push(MatchEntry::parse(line).or_else(|e| bail!("line {}: {}: {}", i + 1, line, e))?);
+    }
+
+    let count = |tuple: bool| {
+        entries
+            .iter()
+            .filter(|e| matches!(e, MatchEntry::Tuple(_)) == tuple)
+            .count()
+    };
+    if count(true) > MATCH_SET_MAX as usize || count(false) > MATCH_SET_MAX as usize {
+        bail!(
+            "Can't have more than {} tuples or prefixes in a match set",
+            MATCH_SET_MAX
+        );
+    }
+    Ok(entries)
+}
+
+static MATCH_SET: OnceCell<Arc<MatchSet>> = OnceCell::new();
+
+/// Sets the global match set, used by the skb collector to filter packets.
+///
+/// Returns an error if it was already set.
+pub(crate) fn set_match_set(set: Arc<MatchSet>) -> Result<()> {
+    MATCH_SET
+        .set(set)
+        .or_else(|_| bail!("match_set was already set"))?;
+    Ok(())
+}
+
+/// Returns the global match set, if any.
+pub(crate) fn get_match_set() -> Option<&'static Arc<MatchSet>> {
+    MATCH_SET.get()
+}
+
+/// Match set handle. Keeps the maps alive for the whole collection.
+pub(crate) struct MatchSet {
+    tuple_map_fd: i32,
+    prefix_map_fd: i32,
+    gen_map_fd: i32,
+    /// Set state, which can be updated at runtime.
+    state: Mutex<MatchSetState>,
+}
+
+struct MatchSetState {
+    /// File the set was loaded from.
+    path: String,
+    /// Active generation.
+    gen: u8,
+    entries: Vec<MatchEntry>,
+    tuple_map: SendableMap,
+    prefix_map: SendableMap,
+    gen_map: SendableMap,
+}
+
+impl MatchSet {
+    /// Create a match set from a file, see the module documentation for its
+    /// format.
+    pub(crate) fn new(path: &str) -> Result<MatchSet> {
+        let tuple_map = SendableMap::from(Self::map(
+            libbpf_rs::MapType::Hash,
+            "match_tuple_map",
+            mem::size_of::<MatchTupleKey>(),
+            2 * MATCH_SET_MAX,
+        )?);
+        let prefix_map = SendableMap::from(Self::map(
+            libbpf_rs::MapType::LpmTrie,
+            "match_prefix_map",
+            mem::size_of::<MatchPrefixKey>(),
+            2 * MATCH_SET_MAX,
+        )?);
+        let gen_map = SendableMap::from(Self::map(
+            libbpf_rs::MapType::Array,
+            "match_gen_map",
+            mem::size_of::<u32>(),
+            1,
+        )?);
+
+        let set = MatchSet {
+            tuple_map_fd: tuple_map.get().fd(),
+            prefix_map_fd: prefix_map.get().fd(),
+            gen_map_fd: gen_map.get().fd(),
+            state: Mutex::new(MatchSetState {
+                path: path.to_string(),
+                gen: 0,
+                entries: Vec::new(),
+                tuple_map,
+                prefix_map,
+                gen_map,
+            }),
+        };
+        set.load(None)?;
+        Ok(set)
+    }
+
+    /// Replace the set with the content of a file, or of the one it was
+    /// loaded from. The previous set stays in place on errors.
+    pub(crate) fn load(&self, path: Option<&str>) -> Result<()> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|e| anyhow!("Could not lock the match set: {}", e))?;
+        let path = path.unwrap_or(&state.path).to_string();
+
+        let content = fs::read_to_string(&path)
+            .or_else(|e| bail!("Could not read match set {}: {}", path, e))?;
+        let entries =
+            parse_entries(&content).or_else(|e| bail!("Invalid match set {}: {}", path, e))?;
+
+        state.replace(entries)?;
+        state.path = path;
+        Ok(())
+    }
+
+    /// Number of tuples and prefixes in the set.
+    pub(crate) fn len(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        let tuples = state
+            .entries
+            .iter()
+            .filter(|e| matches!(e, MatchEntry::Tuple(_)))
+            .count();
+        (tuples, state.entries.len() - tuples)
+    }
+
+    /// Map fds, for reuse by the skb hook.
+    pub(crate) fn tuple_map_fd(&self) -> i32 {
+        self.tuple_map_fd
+    }
+
+    pub(crate) fn prefix_map_fd(&self) -> i32 {
+        self.prefix_map_fd
+    }
+
+    pub(crate) fn gen_map_fd(&self) -> i32 {
+        self.gen_map_fd
+    }
+
+    fn map(
+        r#type: libbpf_rs::MapType,
+        name: &str,
+        key_size: usize,
+        max_entries: u32,
+    ) -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            // Required by LPM tries, and avoids preallocating the entries of
+            // both generations.
+            map_flags: match r#type {
+                libbpf_rs::MapType::Array => 0,
+                _ => libbpf_sys::BPF_F_NO_PREALLOC,
+            },
+            ..Default::default()
+        };
+
+        pinnable_map(name, || {
+            // Please keep in sync with its BPF counterpart in
+            // collector/skb/bpf/skb_hook.bpf.c
+            libbpf_rs::Map::create(
+                r#type,
+                Some(name),
+                key_size as u32,
+                match r#type {
+                    libbpf_rs::MapType::Array => mem::size_of::<u32>(),
+                    _ => mem::size_of::<u8>(),
+                } as u32,
+                max_entries,
+                &opts,
+            )
+            .or_else(|e| bail!("Could not create the match set map {}: {}", name, e))
+        })
+    }
+}
+
+impl MatchSetState {
+    /// Atomically replace the entries of the set: insert the new entries using
+    /// the inactive generation, make it the active one, then remove the
+    /// previous entries.
+    fn replace(&mut self, entries: Vec<MatchEntry>) -> Result<()> {
+        let gen = self.gen ^ 1;
+
+        for (i, entry) in entries.iter().enumerate() {
+            let map = match entry {
+                MatchEntry::Tuple(_) => self.tuple_map.get_mut(),
+                MatchEntry::Prefix(_) => self.prefix_map.get_mut(),
+            };
+            if let Err(e) = map.update(&entry.key(gen), &[1], libbpf_rs::MapFlags::ANY) {
+                // Leave the active set untouched.
+                self.remove(&entries[..i], gen);
+                bail!("Could not update the match set: {}", e);
+            }
+        }
+
+        self.gen_map.get_mut().update(
+            &0_u32.to_ne_bytes(),
+            &(gen as u32).to_ne_bytes(),
+            libbpf_rs::MapFlags::ANY,
+        )?;
+
+        let old = mem::replace(&mut self.entries, entries);
+        self.remove(&old, self.gen);
+        self.gen = gen;
+        Ok(())
+    }
+
+    fn remove(&mut self, entries: &[MatchEntry], gen: u8) {
+        for entry in entries.iter() {
+            let map = match entry {
+                MatchEntry::Tuple(_) => self.tuple_map.get_mut(),
+                MatchEntry::Prefix(_) => self.prefix_map.get_mut(),
+            };
+            map.delete(&entry.key(gen)).ok();
+        }
+    }
+}
+
+// Allow describing the filter, e.g. in meta events.
+impl fmt::Display for MatchSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "match-set={}", self.state.lock().unwrap().path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries() -> Result<()> {
+        let entries = parse_entries(
+            "# Threat intel\n\
+             10.0.0.0/8\n\
+             \n\
+             2001:db8::1\n\
+             10.0.0.1:1234-10.0.0.2:443/tcp\n",
+        )?;
+        assert!(entries.len() == 3);
+
+        match entries[0] {
+            MatchEntry::Prefix(key) => {
+                assert!(key.prefixlen == 16 + 8 && key.family == MATCH_FAMILY_IPV4);
+                assert!(key.addr[..4] == [10, 0, 0, 0]);
+            }
+            _ => bail!("Expected a prefix"),
+        }
+        match entries[1] {
+            MatchEntry::Prefix(key) => {
+                assert!(key.prefixlen == 16 + 128 && key.family == MATCH_FAMILY_IPV6)
+            }
+            _ => bail!("Expected a prefix"),
+        }
+        match entries[2] {
+            MatchEntry::Tuple(key) => {
+                assert!(key.saddr[..4] == [10, 0, 0, 1] && key.daddr[..4] == [10, 0, 0, 2]);
+                assert!(key.sport == 1234 && key.dport == 443 && key.proto == 6);
+            }
+            _ => bail!("Expected a tuple"),
+        }
+
+        // The generation is part of the keys.
+        assert!(entries[2].key(1)[0] == 1 && entries[2].key(0)[0] == 0);
+        assert!(entries[0].key(1)[4] == 1);
+
+        // Invalid entries.
+        assert!(parse_entries("10.0.0.0/33").is_err());
+        assert!(parse_entries("10.0.0.300").is_err());
+        assert!(parse_entries("10.0.0.1:1234-*:443/tcp").is_err());
+        assert!(parse_entries("10.0.0.1:1234-10.0.0.2:443").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,12 @@
+//! # Filters
+//!
+//! Filters restrict the events being reported to the ones the user is
+//! interested in. Filtering is done as early as possible, in the BPF probes, to
+//! limit the overhead and the number of events sent to userspace. Filter maps
+//! can be pinned, to be updated by external tools (see pin).
+
+pub(crate) mod flow;
+pub(crate) mod iface;
+pub(crate) mod match_set;
+pub(crate) mod pid;
+pub(crate) mod pin;
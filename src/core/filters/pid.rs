@@ -0,0 +1,280 @@
+//! # Pid filter
+//!
+//! Restrict events to the ones generated in the context of a given process
+//! (tgid), and optionally of its descendants. The filtering is done in the BPF
+//! probes by looking up the current tgid in a map shared by all probes. When
+//! descendants are followed, the process tree is rescanned periodically and
+//! the map updated accordingly.
+//!
+//! Note: packets processed in softirq context (e.g. forwarded packets or most
+//! of the receive path) do not have a meaningful task context, the tgid seen
+//! there is the one of the task that was interrupted. Such events can't be
+//! attributed to a process and are always dropped under this filter, as are
+//! all events generated in interrupt context (see chain() in common.h).
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, mem,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use log::warn;
+
+use super::pin::pinnable_map;
+use crate::core::{probe::common::set_pid_filter, workaround::SendableMap};
+
+/// Max number of processes we can filter on. Please keep in sync with its BPF
+/// counterpart in core/probe/kernel/bpf/include/common.h
+pub(crate) const PID_FILTER_MAX: u32 = 1024;
+
+/// The process tree is rescanned every PID_FILTER_REFRESH_INTERVAL seconds to
+/// find new descendants (and remove the ones which exited).
+const PID_FILTER_REFRESH_INTERVAL: u64 = 1;
+
+/// Pid filter handle. Keeps the filter map alive (and up to date when
+/// descendants are followed) for the whole collection.
+pub(crate) struct PidFilter {
+    /// Are the process descendants targeted as well?
+    children: bool,
+    map_fd: i32,
+    /// Filter state, shared with the refresher thread.
+    state: Arc<Mutex<PidFilterState>>,
+    // The refresher thread is only kept alive here.
+    #[allow(dead_code)]
+    refresher: Option<thread::JoinHandle<()>>,
+}
+
+// Filter state, which can be updated at runtime.
+struct PidFilterState {
+    /// Process being targeted.
+    pid: u32,
+    /// Pids currently in the filter map.
+    known: HashSet<u32>,
+    /// Was the filter map full at the last update?
+    full: bool,
+    map: SendableMap,
+}
+
+impl PidFilter {
+    /// Create a new pid filter targeting `pid`, and its descendants if
+    /// `children` is set. The filter map is populated right away and the
+    /// probes are instructed to filter events.
+    pub(crate) fn new(pid: u32, children: bool) -> Result<PidFilter> {
+        Self::check_pid(pid)?;
+
+        let map = SendableMap::from(pinnable_map("pid_filter_map", Self::pid_filter_map)?);
+        let map_fd = map.get().fd();
+
+        let mut state = PidFilterState {
+            pid,
+            known: HashSet::new(),
+            full: false,
+            map,
+        };
+        state.update(children)?;
+
+        let mut filter = PidFilter {
+            children,
+            map_fd,
+            state: Arc::new(Mutex::new(state)),
+            refresher: None,
+        };
+
+        if children {
+            let state = Arc::clone(&filter.state);
+            filter.refresher = Some(thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(PID_FILTER_REFRESH_INTERVAL));
+
+                if let Err(e) = state.lock().unwrap().update(true) {
+                    warn!("Could not refresh the pid filter: {}", e);
+                }
+            }));
+        }
+
+        set_pid_filter(true)?;
+        Ok(filter)
+    }
+
+    /// Get the pid filter map fd for reuse by the probes.
+    pub(crate) fn map_fd(&self) -> i32 {
+        self.map_fd
+    }
+
+    /// Change the process being targeted, while the collection is running.
+    pub(crate) fn set_pid(&self, pid: u32) -> Result<()> {
+        Self::check_pid(pid)?;
+
+        let mut state = self.state.lock().unwrap();
+        state.pid = pid;
+        state.update(self.children)
+    }
+
+    fn check_pid(pid: u32) -> Result<()> {
+        if !Path::new(&format!("/proc/{}", pid)).exists() {
+            bail!("Can't filter on pid {}: no such process", pid);
+        }
+        Ok(())
+    }
+
+    fn pid_filter_map() -> Result<libbpf_rs::Map> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+
+        // Please keep in sync with its BPF counterpart in
+        // core/probe/kernel/bpf/include/common.h
+        libbpf_rs::Map::create(
+            libbpf_rs::MapType::Hash,
+            Some("pid_filter_map"),
+            mem::size_of::<u32>() as u32,
+            mem::size_of::<u8>() as u32,
+            PID_FILTER_MAX,
+            &opts,
+        )
+        .or_else(|e| bail!("Could not create the pid filter map: {}", e))
+    }
+}
+
+impl PidFilterState {
+    /// Update the filter map so it contains the target pid and, if requested,
+    /// its descendants.
+    fn update(&mut self, children: bool) -> Result<()> {
+        let targets = match children {
+            true => descendants(self.pid, &process_table()?),
+            false => HashSet::from([self.pid]),
+        };
+        let map = self.map.get_mut();
+
+        // Remove processes which are gone first, to make room for new ones.
+        for pid in self.known.difference(&targets) {
+            map.delete(&pid.to_ne_bytes()).ok();
+        }
+
+        // Only pids actually inserted are known, so the others are retried at
+        // the next update.
+        let mut known: HashSet<u32> = self.known.intersection(&targets).copied().collect();
+        let mut missed = 0;
+        for pid in targets.difference(&self.known) {
+            match map.update(&pid.to_ne_bytes(), &[1], libbpf_rs::MapFlags::ANY) {
+                Ok(()) => {
+                    known.insert(*pid);
+                }
+                Err(_) => missed += 1,
+            }
+        }
+
+        // Only warn when the map becomes full, not at every refresh.
+        if missed > 0 && !self.full {
+            warn!(
+                "Pid filter map is full (max {} processes), {} process(es) not filtered on: some events will be missed",
+                PID_FILTER_MAX, missed
+            );
+        }
+        self.full = missed > 0;
+
+        self.known = known;
+        Ok(())
+    }
+}
+
+// Allow describing the filter, e.g. in meta events.
+impl fmt::Display for PidFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pid = self.state.lock().unwrap().pid;
+        match self.children {
+            true => write!(f, "pid={}+children", pid),
+            false => write!(f, "pid={}", pid),
+        }
+    }
+}
+
+/// Build a pid -> parent pid table of all the processes currently running.
+fn process_table() -> Result<HashMap<u32, u32>> {
+    let mut table = HashMap::new();
+
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry.file_name().to_str().map(|x| x.parse::<u32>()) {
+            Some(Ok(pid)) => pid,
+            _ => continue,
+        };
+
+        // Processes can exit while we're scanning the tree, ignore errors.
+        if let Ok(stat) = fs::read_to_string(entry.path().join("stat")) {
+            if let Some(ppid) = parse_ppid(&stat) {
+                table.insert(pid, ppid);
+            }
+        }
+    }
+
+    Ok(table)
+}
+
+/// Retrieve the parent pid from the content of /proc/<pid>/stat. The command
+/// name (2nd field) can contain spaces and parentheses, so fields are parsed
+/// after the last closing parenthesis.
+fn parse_ppid(stat: &str) -> Option<u32> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    // Fields are now: state, ppid, ...
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Given a pid -> parent pid table, return the set made of a process and all
+/// its descendants.
+fn descendants(root: u32, table: &HashMap<u32, u32>) -> HashSet<u32> {
+    let mut set = HashSet::from([root]);
+
+    // Loop until no new descendant is found, as children can be seen before
+    // their parent while walking the table.
+    loop {
+        let len = set.len();
+        for (pid, ppid) in table.iter() {
+            if set.contains(ppid) {
+                set.insert(*pid);
+            }
+        }
+
+        if set.len() == len {
+            break;
+        }
+    }
+
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ppid() {
+        assert!(parse_ppid("42 (bash) S 1 42 42 0 -1") == Some(1));
+        assert!(parse_ppid("42 (my (weird) cmd) R 1337 42 42 0 -1") == Some(1337));
+        assert!(parse_ppid("42 (bash)").is_none());
+        assert!(parse_ppid("garbage").is_none());
+    }
+
+    #[test]
+    fn process_descendants() {
+        let table = HashMap::from([
+            (1, 0),
+            (10, 1),
+            (11, 10),
+            (12, 11),
+            (13, 11),
+            (20, 1),
+            (21, 20),
+        ]);
+
+        assert!(descendants(10, &table) == HashSet::from([10, 11, 12, 13]));
+        assert!(descendants(20, &table) == HashSet::from([20, 21]));
+        assert!(descendants(13, &table) == HashSet::from([13]));
+        assert!(descendants(42, &table) == HashSet::from([42]));
+        assert!(descendants(1, &table).len() == 7);
+    }
+}
@@ -0,0 +1,108 @@
+//! # Pinned filter maps
+//!
+//! Filter maps can be pinned in a bpffs directory (see --pin-maps), so other
+//! tools (e.g. bpftool) can update the filters while the collection is
+//! running, without using the control socket. Maps are pinned using their
+//! name, e.g. <dir>/pid_filter_map.
+//!
+//! A pin left by a previous run is reused when the map it points to has the
+//! expected definition (type, key and value sizes, maximum number of entries
+//! and flags), its content being reset first; otherwise it is replaced. Pins
+//! are not removed at exit, for external tools to keep a stable reference on
+//! the maps.
+
+use std::{fs, mem, path::Path};
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+use nix::{errno::Errno, libc};
+use once_cell::sync::OnceCell;
+
+static PIN_MAPS: OnceCell<String> = OnceCell::new();
+
+/// Sets the global directory filter maps are pinned to, creating it if needed.
+///
+/// Returns an error if it was already set or the directory could not be
+/// created.
+pub(crate) fn set_pin_maps(dir: &str) -> Result<()> {
+    fs::create_dir_all(dir).or_else(|e| bail!("Could not create {}: {}", dir, e))?;
+    PIN_MAPS
+        .set(dir.to_string())
+        .or_else(|_| bail!("pin_maps was already set"))?;
+    Ok(())
+}
+
+/// Returns the directory filter maps are pinned to, if any.
+pub(crate) fn get_pin_maps() -> Option<&'static str> {
+    PIN_MAPS.get().map(|dir| dir.as_str())
+}
+
+/// Get a filter map, pinned if requested. The map is created using `create`
+/// and, when maps are pinned, either pinned or replaced by the compatible map
+/// already pinned under its name.
+pub(crate) fn pinnable_map<F>(name: &str, create: F) -> Result<libbpf_rs::Map>
+where
+    F: FnOnce() -> Result<libbpf_rs::Map>,
+{
+    let mut map = create()?;
+    let dir = match get_pin_maps() {
+        Some(dir) => dir,
+        None => return Ok(map),
+    };
+
+    let path = Path::new(dir).join(name);
+    if path.exists() {
+        match libbpf_rs::Map::from_pinned_path(&path) {
+            Ok(mut pinned) if same_definition(&pinned, &map) => {
+                info!("Reusing pinned map {}", path.display());
+                reset(&mut pinned);
+                return Ok(pinned);
+            }
+            _ => {
+                warn!("Replacing incompatible pinned map {}", path.display());
+                fs::remove_file(&path)
+                    .or_else(|e| bail!("Could not unpin {}: {}", path.display(), e))?;
+            }
+        }
+    }
+
+    map.pin(&path)
+        .or_else(|e| bail!("Could not pin {}: {}", path.display(), e))?;
+    Ok(map)
+}
+
+/// Do two maps have the same definition? Maps whose definition can't be
+/// retrieved are considered different.
+fn same_definition(a: &libbpf_rs::Map, b: &libbpf_rs::Map) -> bool {
+    match (map_info(a.fd()), map_info(b.fd())) {
+        (Ok(a), Ok(b)) => {
+            a.type_ == b.type_
+                && a.key_size == b.key_size
+                && a.value_size == b.value_size
+                && a.max_entries == b.max_entries
+                && a.map_flags == b.map_flags
+        }
+        _ => false,
+    }
+}
+
+fn map_info(fd: i32) -> Result<libbpf_sys::bpf_map_info> {
+    let mut info = libbpf_sys::bpf_map_info::default();
+    let mut len = mem::size_of::<libbpf_sys::bpf_map_info>() as u32;
+    let ret = unsafe {
+        libbpf_sys::bpf_obj_get_info_by_fd(fd, &mut info as *mut _ as *mut libc::c_void, &mut len)
+    };
+    if ret < 0 {
+        bail!("Could not get map info: {}", Errno::from_i32(-ret));
+    }
+    Ok(info)
+}
+
+/// Remove the entries left in a reused map. Array entries can't be removed
+/// and are left as-is; they are expected to be overwritten by their user.
+fn reset(map: &mut libbpf_rs::Map) {
+    let keys: Vec<Vec<u8>> = map.keys().collect();
+    for key in keys.iter() {
+        map.delete(key).ok();
+    }
+}
@@ -0,0 +1,161 @@
+//! # Kernel config
+//!
+//! Information about the running kernel version and build configuration, used
+//! to check collectors requirements. The configuration is read from
+//! /proc/config.gz or from /boot/config-$(uname -r), which might not be
+//! available on all systems.
+
+use std::{collections::HashMap, fs, io::Read};
+
+use anyhow::{anyhow, bail, Result};
+use flate2::read::GzDecoder;
+use log::debug;
+use nix::sys::utsname::uname;
+use once_cell::sync::OnceCell;
+
+/// Kernel configuration options and their values (y, m, etc).
+pub(crate) type KernelConfig = HashMap<String, String>;
+
+static KCONFIG: OnceCell<Option<KernelConfig>> = OnceCell::new();
+
+/// Return the kernel (major, minor) version.
+pub(crate) fn kernel_version() -> Result<(u32, u32)> {
+    parse_release(&uname()?.release().to_string_lossy())
+}
+
+/// Return the running kernel configuration, if it can be found.
+pub(crate) fn kernel_config() -> Option<&'static KernelConfig> {
+    KCONFIG
+        .get_or_init(|| match read_kernel_config() {
+            Ok(config) => Some(config),
+            Err(e) => {
+                debug!("Could not read the kernel configuration: {}", e);
+                None
+            }
+        })
+        .as_ref()
+}
+
+fn read_kernel_config() -> Result<KernelConfig> {
+    if let Ok(file) = fs::File::open("/proc/config.gz") {
+        let mut config = String::new();
+        GzDecoder::new(file).read_to_string(&mut config)?;
+        return Ok(parse_config(&config));
+    }
+
+    let path = format!("/boot/config-{}", uname()?.release().to_string_lossy());
+    Ok(parse_config(&fs::read_to_string(path)?))
+}
+
+/// Parse a kernel release string, e.g. "6.0.8-300.fc37.x86_64".
+fn parse_release(release: &str) -> Result<(u32, u32)> {
+    let mut version = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|x| x.parse::<u32>());
+
+    match (version.next(), version.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => Ok((major, minor)),
+        _ => bail!("Invalid kernel release {}", release),
+    }
+}
+
+/// Parse a kernel configuration file. Options not set are left out.
+fn parse_config(config: &str) -> KernelConfig {
+    config
+        .lines()
+        .filter(|l| l.starts_with("CONFIG_"))
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.trim_matches('"').to_string()))
+        .collect()
+}
+
+/// Requirements a collector has on the running kernel.
+#[derive(Default)]
+pub(crate) struct Requirements {
+    /// Minimum kernel version, (major, minor).
+    pub(crate) kernel: Option<(u32, u32)>,
+    /// Kernel configuration options which must be set (built-in or module).
+    pub(crate) config: &'static [&'static str],
+}
+
+impl Requirements {
+    /// Check the requirements against a kernel version and configuration. When
+    /// the configuration isn't available, configuration options can't be
+    /// checked and are assumed to be set.
+    pub(crate) fn check(
+        &self,
+        name: &str,
+        version: (u32, u32),
+        config: Option<&KernelConfig>,
+    ) -> Result<()> {
+        if let Some(min) = self.kernel {
+            if version < min {
+                bail!(
+                    "{} requires Linux {}.{} or later (running {}.{})",
+                    name,
+                    min.0,
+                    min.1,
+                    version.0,
+                    version.1
+                );
+            }
+        }
+
+        if let Some(config) = config {
+            if let Some(option) = self
+                .config
+                .iter()
+                .find(|o| !matches!(config.get(**o).map(|v| v.as_str()), Some("y" | "m")))
+            {
+                bail!("{} requires {}", name, option);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the requirements against the running kernel.
+    pub(crate) fn check_running(&self, name: &str) -> Result<()> {
+        let version =
+            kernel_version().map_err(|e| anyhow!("Could not get the kernel version: {}", e))?;
+        self.check(name, version, kernel_config())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release() -> Result<()> {
+        assert!(parse_release("6.0.8-300.fc37.x86_64")? == (6, 0));
+        assert!(parse_release("5.14.0")? == (5, 14));
+        assert!(parse_release("foo").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn requirements() {
+        let config = parse_config(
+            "# CONFIG_XFRM is not set\n\
+             CONFIG_NF_CONNTRACK=m\n\
+             CONFIG_LOCALVERSION=\"\"\n",
+        );
+        assert!(config.get("CONFIG_NF_CONNTRACK") == Some(&"m".to_string()));
+        assert!(!config.contains_key("CONFIG_XFRM"));
+
+        let req = Requirements {
+            kernel: Some((5, 10)),
+            config: &["CONFIG_NF_CONNTRACK"],
+        };
+        assert!(req.check("ct", (5, 14), Some(&config)).is_ok());
+        assert!(req.check("ct", (5, 14), None).is_ok());
+        assert!(req.check("ct", (4, 18), Some(&config)).is_err());
+
+        let req = Requirements {
+            kernel: None,
+            config: &["CONFIG_XFRM"],
+        };
+        let err = req.check("xfrm", (6, 0), Some(&config)).unwrap_err();
+        assert!(err.to_string() == "xfrm requires CONFIG_XFRM");
+    }
+}
@@ -2,102 +2,361 @@
 //!
 //! Interface to query the kernel symbol addresses / name relationship, both
 //! ways. It does so by parsing /proc/kallsyms and by using a singleton
-//! initialized on-demand.
+//! initialized on-demand. Module symbols change when a module is reloaded, in
+//! which case the symbols can be parsed again using reload().
+//!
+//! Symbols found multiple times in kallsyms (e.g. static functions sharing a
+//! name in different compilation units) are ambiguous. Looking them up by name
+//! returns the first candidate, with a warning; a candidate can be picked
+//! explicitly using "<module>:<symbol>" or "<symbol>@<address>".
+//!
+//! Without the right privileges (see kptr_restrict) kallsyms reports all
+//! addresses as 0 and addresses can't be resolved. There is no fallback to
+//! module-relative names (e.g. "[module]+offset"): those need the base address
+//! of each module, which /proc/modules and /sys/module/*/sections hide the
+//! same way, and the addresses reported by the kernel are randomized (KASLR)
+//! so can't be matched against a module otherwise. Callers then report raw
+//! addresses.
 
 // Internal library, some helpers might not be used, that's fine.
 #![allow(dead_code)]
 
-use std::fs;
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, RwLock},
+};
 
 use anyhow::{anyhow, bail, Result};
 use bimap::BiHashMap;
-use once_cell::sync::OnceCell;
+use log::warn;
+
+/// Kernel symbols, as found in kallsyms.
+struct Symbols {
+    /// Bidirectional address <> name map.
+    map: BiHashMap<u64, String>,
+    /// Symbols found multiple times (e.g. static functions with the same name
+    /// in different compilation units), with all their candidates, in kallsyms
+    /// order.
+    duplicates: HashMap<String, Vec<Candidate>>,
+    /// Names of the text (function) symbols.
+    functions: Vec<String>,
+    /// Module of the symbols coming from one, by name.
+    modules: HashMap<String, String>,
+    /// All symbols, including ambiguous ones, sorted by address. Used to
+    /// resolve addresses using a binary search.
+    sorted: Vec<(u64, String)>,
+}
+
+/// Symbol candidate: address and module, if any.
+type Candidate = (u64, Option<String>);
 
-/// Kernel symbols bidirectional map. To retrieve it, please use:
+/// Kernel symbols. To retrieve them, please use:
 /// ```
 /// let symbols = get_symbols!().unwrap();
 /// ```
-static SYMBOLS: OnceCell<BiHashMap<u64, String>> = OnceCell::new();
+static SYMBOLS: RwLock<Option<Arc<Symbols>>> = RwLock::new(None);
 
-/// Return a reference to the symbol map and initialize it on first access. To
+/// Return a reference to the symbols and initialize them on first access. To
 /// set the initial values, KALLSYMS is parsed as it contains the kernel symbol
 /// addr<>name relationships.
 macro_rules! get_symbols {
-    () => {
-        SYMBOLS.get_or_try_init(|| {
-            // Use a different kallsyms file source when running tests to ensure
-            // 1) we use a known one so we can look up addresses 2) we have
-            // access to it.
-            #[cfg(not(test))]
-            let kallsyms_file = String::from("/proc/kallsyms");
-            #[cfg(test)]
-            let kallsyms_file = String::from("test_data/kallsyms");
-
-            let file = fs::read_to_string(kallsyms_file)?;
-            let mut map = BiHashMap::new();
-
-            for line in file.lines() {
-                let data: Vec<&str> = line.split(' ').collect();
-                if data.len() < 3 {
-                    bail!("Invalid kallsyms line: {}", line);
+    () => {{
+        let cached = SYMBOLS.read().unwrap().as_ref().map(Arc::clone);
+        match cached {
+            Some(symbols) => Ok::<Arc<Symbols>, anyhow::Error>(symbols),
+            None => {
+                let mut symbols = SYMBOLS.write().unwrap();
+                if symbols.is_none() {
+                    *symbols = Some(Arc::new(read_kallsyms()?));
                 }
+                Ok(Arc::clone(symbols.as_ref().unwrap()))
+            }
+        }
+    }};
+}
 
-                let symbol: &str = data[2]
-                    .split('\t')
-                    .next()
-                    .ok_or_else(|| anyhow!("Couldn't get symbol name for {}", data[0]))?;
+/// Read and parse the kallsyms file.
+fn read_kallsyms() -> Result<Symbols> {
+    // Use a different kallsyms file source when running tests to ensure
+    // 1) we use a known one so we can look up addresses 2) we have
+    // access to it.
+    #[cfg(not(test))]
+    let kallsyms_file = String::from("/proc/kallsyms");
+    #[cfg(test)]
+    let kallsyms_file = String::from("test_data/kallsyms");
 
-                map.insert(u64::from_str_radix(data[0], 16)?, String::from(symbol));
-            }
+    parse_kallsyms(&fs::read_to_string(kallsyms_file)?)
+}
 
-            Ok(map)
-        })
-    };
+/// Parse the kernel symbols again, e.g. after a module was reloaded. Lookups
+/// of addresses no longer valid fail afterwards.
+pub(crate) fn reload() -> Result<()> {
+    let symbols = read_kallsyms()?;
+    *SYMBOLS.write().unwrap() = Some(Arc::new(symbols));
+    Ok(())
+}
+
+/// Parse the content of a kallsyms file.
+fn parse_kallsyms(file: &str) -> Result<Symbols> {
+    let mut map = BiHashMap::new();
+    let mut duplicates: HashMap<String, Vec<Candidate>> = HashMap::new();
+    // Keep track of the module of all symbols while parsing, to report full
+    // candidates when finding duplicates.
+    let mut modules: HashMap<String, Option<String>> = HashMap::new();
+    let mut functions = Vec::new();
+    let mut symbol_modules = HashMap::new();
+    let mut sorted = Vec::new();
+
+    for line in file.lines() {
+        // Lines are "<addr> <type> <name>[\t[<module>]]".
+        let data: Vec<&str> = line.split_whitespace().collect();
+        if data.len() < 3 {
+            bail!("Invalid kallsyms line: {}", line);
+        }
+
+        let symbol = data[2];
+        let module = data
+            .get(3)
+            .map(|m| m.trim_matches(|c| c == '[' || c == ']').to_string());
+        let addr = u64::from_str_radix(data[0], 16)?;
+        sorted.push((addr, symbol.to_string()));
+
+        if let Some(prev) = map.get_by_right(symbol) {
+            duplicates
+                .entry(symbol.to_string())
+                .or_insert_with(|| vec![(*prev, modules.get(symbol).cloned().flatten())])
+                .push((addr, module));
+            continue;
+        }
+
+        if data[1] == "t" || data[1] == "T" {
+            functions.push(symbol.to_string());
+        }
+        if let Some(module) = &module {
+            symbol_modules.insert(symbol.to_string(), module.clone());
+        }
+        modules.insert(symbol.to_string(), module);
+        map.insert(addr, String::from(symbol));
+    }
+
+    // Without the right privileges (see kptr_restrict) all addresses are
+    // reported as 0 and can't be resolved.
+    if sorted.iter().all(|(addr, _)| *addr == 0) {
+        sorted.clear();
+    }
+    sorted.sort_unstable_by_key(|(addr, _)| *addr);
+
+    Ok(Symbols {
+        map,
+        duplicates,
+        functions,
+        modules: symbol_modules,
+        sorted,
+    })
 }
 
 /// Return a symbol name given its address, if a relationship is found.
+/// Addresses of all the candidates of an ambiguous symbol are resolved.
 pub(crate) fn get_symbol_name(addr: u64) -> Result<String> {
-    Ok(get_symbols!()?
-        .get_by_left(&addr)
-        .ok_or_else(|| anyhow!("Can't get symbol name for {}", addr))?
-        .clone())
+    let symbols = get_symbols!()?;
+
+    if let Some(name) = symbols.map.get_by_left(&addr) {
+        return Ok(name.clone());
+    }
+    symbols
+        .duplicates
+        .iter()
+        .find(|(_, candidates)| candidates.iter().any(|(a, _)| *a == addr))
+        .map(|(name, _)| name.clone())
+        .ok_or_else(|| anyhow!("Can't get symbol name for {}", addr))
 }
 
-/// Return a symbol address given its name, if a relationship is found.
-pub(crate) fn get_symbol_addr(name: &str) -> Result<u64> {
-    Ok(*get_symbols!()?
-        .get_by_right(name)
-        .ok_or_else(|| anyhow!("Can't get symbol address for {}", name))?)
+/// Return a symbol address given a target: its name, "<module>:<name>" or
+/// "<name>@<address>". Names found multiple times in kallsyms are ambiguous,
+/// the first candidate is returned and a warning lists all of them.
+pub(crate) fn get_symbol_addr(target: &str) -> Result<u64> {
+    get_symbols!()?.addr(target)
 }
 
-/// Given an address, try to find the nearest symbol, if any.
-pub(crate) fn find_nearest_symbol(target: u64) -> Result<u64> {
-    let (mut nearest, mut best_score) = (0, std::u64::MAX);
+/// Return the module a symbol is part of, if any, given a target (see
+/// get_symbol_addr()).
+pub(crate) fn get_symbol_module(target: &str) -> Result<Option<String>> {
+    let symbols = get_symbols!()?;
 
-    for addr in get_symbols!()?.left_values() {
-        // The target address has to be greater or equal to a symbol address to
-        // be considered near it (and part of it).
-        if target < *addr {
-            continue;
+    Ok(match parse_target(target)? {
+        (_, Selector::Module(module)) => Some(module.to_string()),
+        (name, Selector::Address(addr)) => symbols
+            .candidates(name)
+            .into_iter()
+            .find(|(a, _)| *a == addr)
+            .and_then(|(_, module)| module),
+        (name, Selector::First) => symbols.modules.get(name).cloned(),
+    })
+}
+
+/// Return the symbol name of a target (see get_symbol_addr()).
+pub(crate) fn symbol_name(target: &str) -> &str {
+    match parse_target(target) {
+        Ok((name, _)) => name,
+        Err(_) => target,
+    }
+}
+
+/// Does attaching to a target require using its address? That is the case
+/// for targets selecting an address and for ambiguous names, which the kernel
+/// might refuse or resolve to another candidate.
+pub(crate) fn attach_by_address(target: &str) -> Result<bool> {
+    Ok(match parse_target(target)? {
+        (_, Selector::Address(_)) => true,
+        (name, Selector::First) => get_symbols!()?.duplicates.contains_key(name),
+        (_, Selector::Module(_)) => false,
+    })
+}
+
+/// How to pick a symbol among its candidates.
+#[derive(Debug, PartialEq)]
+enum Selector<'a> {
+    /// First one, ambiguous names are reported.
+    First,
+    /// The one from a given module.
+    Module(&'a str),
+    /// The one at a given address.
+    Address(u64),
+}
+
+/// Split a target into a symbol name and a candidate selector.
+fn parse_target(target: &str) -> Result<(&str, Selector)> {
+    if let Some((name, addr)) = target.split_once('@') {
+        let addr = addr.trim_start_matches("0x");
+        return match u64::from_str_radix(addr, 16) {
+            Ok(addr) if !name.is_empty() => Ok((name, Selector::Address(addr))),
+            _ => bail!(
+                "Invalid symbol target {}, expected <symbol>@<address>",
+                target
+            ),
+        };
+    }
+    if let Some((module, name)) = target.split_once(':') {
+        if module.is_empty() || name.is_empty() {
+            bail!(
+                "Invalid symbol target {}, expected <module>:<symbol>",
+                target
+            );
         }
+        return Ok((name, Selector::Module(module)));
+    }
+    Ok((target, Selector::First))
+}
+
+/// Return the names of the function symbols matching a filter, sorted.
+/// Ambiguous symbols are left out: their name alone only selects their first
+/// candidate (see get_symbol_addr()), which might not be the expected one.
+pub(crate) fn matching_functions<F>(filter: F) -> Result<Vec<String>>
+where
+    F: Fn(&str) -> bool,
+{
+    let symbols = get_symbols!()?;
 
-        let score = target.abs_diff(*addr);
-        if score < best_score {
-            nearest = *addr;
-            best_score = score;
+    let mut functions: Vec<String> = symbols
+        .functions
+        .iter()
+        .filter(|f| !symbols.duplicates.contains_key(*f) && filter(f))
+        .cloned()
+        .collect();
+    functions.sort();
 
-            // Exact match; can't do better than that.
-            if score == 0 {
-                break;
+    Ok(functions)
+}
+
+impl Symbols {
+    /// All the candidates of a symbol, in kallsyms order.
+    fn candidates(&self, name: &str) -> Vec<Candidate> {
+        match self.duplicates.get(name) {
+            Some(candidates) => candidates.clone(),
+            None => match self.map.get_by_right(name) {
+                Some(addr) => vec![(*addr, self.modules.get(name).cloned())],
+                None => Vec::new(),
+            },
+        }
+    }
+
+    /// Look up the address of a target (see get_symbol_addr()).
+    fn addr(&self, target: &str) -> Result<u64> {
+        let (name, selector) = parse_target(target)?;
+        let candidates = self.candidates(name);
+        let list = || {
+            candidates
+                .iter()
+                .map(|(addr, module)| match module {
+                    Some(module) => format!("{:#x} [{}]", addr, module),
+                    None => format!("{:#x}", addr),
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let found = match selector {
+            Selector::First => {
+                if candidates.len() > 1 {
+                    warn!(
+                        "Symbol {} is ambiguous, found {} times in kallsyms ({}), using the first one; use <module>:{} or {}@<address> to pick another",
+                        name,
+                        candidates.len(),
+                        list(),
+                        name,
+                        name
+                    );
+                }
+                candidates.first()
             }
+            Selector::Module(module) => candidates
+                .iter()
+                .find(|(_, m)| m.as_deref() == Some(module)),
+            Selector::Address(addr) => candidates.iter().find(|(a, _)| *a == addr),
+        };
+
+        match found {
+            Some((addr, _)) => Ok(*addr),
+            None if candidates.is_empty() => bail!("Can't get symbol address for {}", name),
+            None => bail!(
+                "No candidate of {} matches {}, candidates: {}",
+                name,
+                target,
+                list()
+            ),
         }
     }
 
-    if best_score == std::u64::MAX {
-        bail!("Can't get a symbol near {}", target);
+    /// Find the symbol an address is part of, that is the one with the
+    /// greatest address lower or equal to it.
+    fn nearest(&self, target: u64) -> Result<&(u64, String)> {
+        if self.sorted.is_empty() {
+            bail!("Kernel symbol addresses are not available (see kptr_restrict)");
+        }
+
+        match self.sorted.partition_point(|(addr, _)| *addr <= target) {
+            0 => bail!("Can't get a symbol near {:#x}", target),
+            i => Ok(&self.sorted[i - 1]),
+        }
     }
+}
+
+/// Given an address, try to find the nearest symbol, if any.
+pub(crate) fn find_nearest_symbol(target: u64) -> Result<u64> {
+    Ok(get_symbols!()?.nearest(target)?.0)
+}
 
-    Ok(nearest)
+/// Resolve an address to the symbol it is part of and the offset in it, e.g.
+/// for symbolizing stack traces. Symbols are parsed once and looked up using a
+/// binary search.
+///
+/// Returns an error when kernel symbol addresses are hidden (see the module
+/// documentation), rather than a module-relative name.
+pub(crate) fn resolve(addr: u64) -> Result<(String, u64)> {
+    let symbols = get_symbols!()?;
+    let (sym, name) = symbols.nearest(addr)?;
+    Ok((name.clone(), addr - sym))
 }
 
 #[cfg(test)]
@@ -123,6 +382,83 @@ mod tests {
         assert!(symbol == name);
     }
 
+    #[test]
+    fn duplicate_symbol() -> Result<()> {
+        // The first candidate is used, others can be picked by address.
+        assert!(get_symbol_addr("BIT_initDStream")? == 0xffffffff95007a90);
+        assert!(get_symbol_addr("BIT_initDStream@0xffffffff9501e8f0")? == 0xffffffff9501e8f0);
+        assert!(get_symbol_addr("BIT_initDStream@ffffffff9501e8f0")? == 0xffffffff9501e8f0);
+        assert!(get_symbol_name(0xffffffff9501e8f0)? == "BIT_initDStream");
+        assert!(attach_by_address("BIT_initDStream")?);
+        assert!(!attach_by_address("consume_skb")?);
+
+        let err = get_symbol_addr("BIT_initDStream@0x42")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("0xffffffff95007a90") && err.contains("0xffffffff9501e8f0"));
+        assert!(get_symbol_addr("consume_skb@0xffffffff95617530")? == 0xffffffff95617530);
+        Ok(())
+    }
+
+    #[test]
+    fn symbol_targets() -> Result<()> {
+        let symbols = parse_kallsyms(
+            "ffffffff00000010 t foo\t[mod_a]\n\
+             ffffffff00000020 t foo\t[mod_b]\n\
+             ffffffff00000030 T bar",
+        )?;
+
+        assert!(symbols.addr("foo")? == 0xffffffff00000010);
+        assert!(symbols.addr("mod_b:foo")? == 0xffffffff00000020);
+        assert!(symbols.addr("foo@0xffffffff00000020")? == 0xffffffff00000020);
+        assert!(symbols.addr("bar")? == 0xffffffff00000030);
+        assert!(symbols.addr("mod_c:foo").is_err());
+        assert!(symbols.addr("mod_a:bar").is_err());
+        assert!(symbols.addr("baz").is_err());
+
+        assert!(parse_target("mod_a:foo")? == ("foo", Selector::Module("mod_a")));
+        assert!(parse_target("foo")? == ("foo", Selector::First));
+        assert!(parse_target("foo@zz").is_err());
+        assert!(parse_target(":foo").is_err());
+        assert!(symbol_name("mod_a:foo") == "foo");
+        assert!(symbol_name("foo@0x10") == "foo");
+        Ok(())
+    }
+
+    #[test]
+    fn functions() -> Result<()> {
+        let functions = matching_functions(|f| f.contains("kfree_skb_reason"))?;
+        assert!(functions.contains(&"kfree_skb_reason".to_string()));
+        // Data symbols are not functions.
+        assert!(!functions.contains(&"__ksymtab_kfree_skb_reason".to_string()));
+
+        // Ambiguous symbols are left out.
+        assert!(matching_functions(|f| f == "BIT_initDStream")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_modules() -> Result<()> {
+        let symbols = parse_kallsyms(
+            "ffffffff00000010 t foo\t[mod_a]\n\
+             ffffffff00000020 t foo\t[mod_b]\n\
+             ffffffff00000030 T bar\n\
+             ffffffff00000040 t baz\t[mod_a]",
+        )?;
+
+        assert!(symbols.map.get_by_right("bar") == Some(&0xffffffff00000030));
+        assert!(symbols.modules.get("bar").is_none());
+        assert!(symbols.modules.get("baz") == Some(&"mod_a".to_string()));
+        assert!(
+            symbols.duplicates.get("foo")
+                == Some(&vec![
+                    (0xffffffff00000010, Some("mod_a".to_string())),
+                    (0xffffffff00000020, Some("mod_b".to_string())),
+                ])
+        );
+        Ok(())
+    }
+
     #[test]
     fn nearest_symbol() {
         let addr = get_symbol_addr("consume_skb").unwrap();
@@ -131,4 +467,34 @@ mod tests {
         assert!(find_nearest_symbol(addr).unwrap() == addr);
         assert!(find_nearest_symbol(addr - 1).unwrap() != addr);
     }
+
+    #[test]
+    fn resolve_addr() -> Result<()> {
+        let addr = get_symbol_addr("consume_skb")?;
+
+        assert!(resolve(addr)? == ("consume_skb".to_string(), 0));
+        assert!(resolve(addr + 0x10)? == ("consume_skb".to_string(), 0x10));
+        assert!(resolve(0).is_err());
+
+        // Ambiguous symbols can be resolved from their address.
+        assert!(resolve(0xffffffff9501e8f0)?.0 == "BIT_initDStream");
+        Ok(())
+    }
+
+    #[test]
+    fn hidden_addresses() -> Result<()> {
+        let symbols = parse_kallsyms(
+            "0000000000000000 T foo\n\
+             0000000000000000 t bar\t[mod_a]",
+        )?;
+        assert!(symbols.sorted.is_empty());
+        assert!(symbols.modules.get("bar") == Some(&"mod_a".to_string()));
+        assert!(symbols.nearest(0x10).is_err());
+
+        // Lines with extra whitespace are accepted, incomplete ones are not.
+        assert!(parse_kallsyms("  ffffffff00000010  T foo ").is_ok());
+        assert!(parse_kallsyms("ffffffff00000010 T").is_err());
+        assert!(parse_kallsyms("zz T foo").is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,139 @@
+//! # Logger
+//!
+//! Initializes the logging facility. Logs are always displayed on the terminal,
+//! using stderr, and can in addition be written to a file, as structured (JSON)
+//! lines. This is useful when running as a daemon where the terminal output is
+//! discarded. Logs are never written to the same destination as events, which
+//! use stdout.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+use simplelog::{ColorChoice, CombinedLogger, Config, SharedLogger, TermLogger, TerminalMode};
+
+/// Initialize the logger, at a given level. If `quiet` is set, only errors are
+/// displayed on the terminal. If `log_file` is set, logs are also written to
+/// this file as JSON lines (regardless of `quiet`).
+pub(crate) fn init_logger(level: LevelFilter, quiet: bool, log_file: Option<&str>) -> Result<()> {
+    let term_level = match quiet {
+        true => level.min(LevelFilter::Error),
+        false => level,
+    };
+
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
+        term_level,
+        Config::default(),
+        TerminalMode::Stderr,
+        ColorChoice::Auto,
+    )];
+
+    if let Some(path) = log_file {
+        loggers.push(JsonLogger::new(level, path)?);
+    }
+
+    CombinedLogger::init(loggers).or_else(|e| bail!("Could not init the logger: {}", e))
+}
+
+/// Logger writing structured (JSON) lines to a file.
+struct JsonLogger {
+    level: LevelFilter,
+    config: Config,
+    file: Mutex<File>,
+}
+
+impl JsonLogger {
+    fn new(level: LevelFilter, path: &str) -> Result<Box<JsonLogger>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .or_else(|e| bail!("Could not open log file {}: {}", path, e))?;
+
+        Ok(Box::new(JsonLogger {
+            level,
+            config: Config::default(),
+            file: Mutex::new(file),
+        }))
+    }
+
+    /// Convert a log record to its JSON representation.
+    fn to_json(record: &Record) -> serde_json::Value {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_secs_f64())
+            .unwrap_or_default();
+
+        json!({
+            "timestamp": timestamp,
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        })
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Logging errors can't be reported anywhere, ignore them.
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(file, "{}", Self::to_json(record));
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+impl SharedLogger for JsonLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        Some(&self.config)
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn json_record() {
+        let json = JsonLogger::to_json(
+            &Record::builder()
+                .args(format_args!("Attaching probe to {}", "kfree_skb"))
+                .level(Level::Info)
+                .target("packet_tracer::core::probe")
+                .build(),
+        );
+
+        assert!(json["level"] == "INFO");
+        assert!(json["target"] == "packet_tracer::core::probe");
+        assert!(json["message"] == "Attaching probe to kfree_skb");
+        assert!(json["timestamp"].as_f64().unwrap() > 0.0);
+    }
+}
@@ -3,7 +3,18 @@
 //! Core module, providing tools and common logic that can be used by any module
 //! within the tool.
 
+pub(crate) mod bpf_trace;
+pub(crate) mod capabilities;
+pub(crate) mod control;
 pub(crate) mod events;
+pub(crate) mod filters;
+pub(crate) mod kernel_config;
 pub(crate) mod kernel_symbols;
+pub(crate) mod logger;
+pub(crate) mod privileges;
 pub(crate) mod probe;
+pub(crate) mod self_test;
+pub(crate) mod stats;
+pub(crate) mod tracefs;
 pub(crate) mod workaround;
+pub(crate) mod workload;
@@ -0,0 +1,121 @@
+//! # Privileges
+//!
+//! Early check of the privileges needed to load and attach BPF programs, so we
+//! can report what is missing and how to fix it instead of failing later with
+//! less actionable errors.
+
+use std::fs;
+
+use anyhow::{anyhow, bail, Result};
+use log::{debug, warn};
+use nix::sys::resource::{getrlimit, setrlimit, Resource, RLIM_INFINITY};
+
+use super::kernel_config::kernel_version;
+
+// Capabilities, see include/uapi/linux/capability.h
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_PERFMON: u32 = 38;
+const CAP_BPF: u32 = 39;
+
+/// Check the process has the capabilities needed to load and attach BPF
+/// programs, and raise the locked memory limit if needed.
+pub(crate) fn check_privileges() -> Result<()> {
+    let status = fs::read_to_string("/proc/self/status")?;
+    if let Some(missing) = missing_caps(parse_cap_eff(&status)?) {
+        bail!(
+            "Insufficient privileges: {}. Try running with sudo, or grant the capabilities with setcap",
+            missing
+        );
+    }
+
+    raise_memlock()
+}
+
+/// Parse the effective capabilities of a process, given its status file.
+fn parse_cap_eff(status: &str) -> Result<u64> {
+    let caps = status
+        .lines()
+        .find_map(|l| l.strip_prefix("CapEff:"))
+        .ok_or_else(|| anyhow!("Could not find the effective capabilities"))?;
+
+    Ok(u64::from_str_radix(caps.trim(), 16)?)
+}
+
+/// Return a description of the missing capabilities, if any. CAP_SYS_ADMIN
+/// grants everything we need; since Linux v5.8 CAP_BPF and CAP_PERFMON can be
+/// used instead.
+fn missing_caps(caps: u64) -> Option<String> {
+    let has = |cap: u32| caps & (1 << cap) != 0;
+
+    if has(CAP_SYS_ADMIN) || (has(CAP_BPF) && has(CAP_PERFMON)) {
+        return None;
+    }
+
+    let mut missing = Vec::new();
+    if !has(CAP_BPF) {
+        missing.push("CAP_BPF");
+    }
+    if !has(CAP_PERFMON) {
+        missing.push("CAP_PERFMON");
+    }
+
+    Some(format!(
+        "needs CAP_SYS_ADMIN, or {} (Linux >= 5.8)",
+        missing.join(" and ")
+    ))
+}
+
+/// BPF objects memory was accounted against RLIMIT_MEMLOCK before Linux v5.11.
+/// Raise the limit, as the default one is usually too low.
+fn raise_memlock() -> Result<()> {
+    if kernel_version()? >= (5, 11) {
+        return Ok(());
+    }
+
+    let (soft, hard) = getrlimit(Resource::RLIMIT_MEMLOCK)?;
+    if soft == RLIM_INFINITY {
+        return Ok(());
+    }
+
+    if let Err(e) = setrlimit(Resource::RLIMIT_MEMLOCK, RLIM_INFINITY, RLIM_INFINITY) {
+        // Not fatal, the current limit might be enough.
+        warn!(
+            "Could not raise the locked memory limit ({}); if loading BPF objects fails, try 'ulimit -l unlimited'",
+            e
+        );
+        return Ok(());
+    }
+
+    debug!(
+        "Raised the locked memory limit from {}/{} to unlimited",
+        soft, hard
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities() -> Result<()> {
+        let status = "Name:\tpacket-tracer\nCapEff:\t000001ffffffffff\n";
+        assert!(parse_cap_eff(status)? == 0x1ffffffffff);
+        assert!(parse_cap_eff("Name:\tpacket-tracer\n").is_err());
+
+        // Root.
+        assert!(missing_caps(0x1ffffffffff).is_none());
+        // CAP_BPF and CAP_PERFMON.
+        assert!(missing_caps((1 << CAP_BPF) | (1 << CAP_PERFMON)).is_none());
+        // Unprivileged.
+        assert!(
+            missing_caps(0).unwrap()
+                == "needs CAP_SYS_ADMIN, or CAP_BPF and CAP_PERFMON (Linux >= 5.8)"
+        );
+        assert!(
+            missing_caps(1 << CAP_BPF).unwrap()
+                == "needs CAP_SYS_ADMIN, or CAP_PERFMON (Linux >= 5.8)"
+        );
+        Ok(())
+    }
+}
@@ -29,3 +29,161 @@ pub(crate) fn get_ebpf_debug() -> bool {
         *EBPF_DEBUG.get_or_init(|| false)
     }
 }
+
+static DUMP_BPF: OnceCell<String> = OnceCell::new();
+
+/// Sets the global directory BPF programs are dumped to once loaded, for
+/// debugging purposes.
+///
+/// Returns an error if it was already set.
+pub(crate) fn set_dump_bpf(dir: &str) -> Result<()> {
+    DUMP_BPF
+        .set(dir.to_string())
+        .or_else(|_| bail!("dump_bpf was already set"))?;
+    Ok(())
+}
+
+/// Returns the directory BPF programs are dumped to, if any.
+pub(crate) fn get_dump_bpf() -> Option<&'static str> {
+    DUMP_BPF.get().map(|dir| dir.as_str())
+}
+
+static PID_FILTER: OnceCell<bool> = OnceCell::new();
+
+/// Sets the global pid filter flag, instructing probes to filter events using
+/// the pid filter map (see core::filters::pid).
+///
+/// Returns an error if it was already set.
+pub(crate) fn set_pid_filter(enabled: bool) -> Result<()> {
+    PID_FILTER
+        .set(enabled)
+        .or_else(|_| bail!("pid_filter was already set"))?;
+    Ok(())
+}
+
+/// Returns the current value of the global pid filter flag.
+///
+/// If called before [`set_pid_filter`] has been called, it will be set to false.
+pub(crate) fn get_pid_filter() -> bool {
+    *PID_FILTER.get_or_init(|| false)
+}
+
+static COUNT_ONLY: OnceCell<bool> = OnceCell::new();
+
+/// Sets the global counting mode flag, instructing probes to only count their
+/// hits (see Kernel::probe_hits()) instead of reporting events. Hooks are not
+/// run in this mode.
+///
+/// Returns an error if it was already set.
+pub(crate) fn set_count_only(enabled: bool) -> Result<()> {
+    COUNT_ONLY
+        .set(enabled)
+        .or_else(|_| bail!("count_only was already set"))?;
+    Ok(())
+}
+
+/// Returns the current value of the global counting mode flag.
+///
+/// If called before [`set_count_only`] has been called, it will be set to false.
+pub(crate) fn get_count_only() -> bool {
+    *COUNT_ONLY.get_or_init(|| false)
+}
+
+static COUNT_EVENTS: OnceCell<bool> = OnceCell::new();
+
+/// Sets the global counter mode flag, instructing hooks to increment
+/// per-category counters (see BpfEvents::counters()) instead of reporting
+/// events. Unlike the counting mode set by [`set_count_only`], hooks and
+/// filters are run.
+///
+/// Returns an error if it was already set.
+pub(crate) fn set_count_events(enabled: bool) -> Result<()> {
+    COUNT_EVENTS
+        .set(enabled)
+        .or_else(|_| bail!("count_events was already set"))?;
+    Ok(())
+}
+
+/// Returns the current value of the global counter mode flag.
+///
+/// If called before [`set_count_events`] has been called, it will be set to false.
+pub(crate) fn get_count_events() -> bool {
+    *COUNT_EVENTS.get_or_init(|| false)
+}
+
+static EXEC_CONTEXT: OnceCell<bool> = OnceCell::new();
+
+/// Sets the global execution context flag, instructing probes to also report
+/// the context events are generated in (task, softirq, etc).
+///
+/// Returns an error if it was already set.
+pub(crate) fn set_exec_context(enabled: bool) -> Result<()> {
+    EXEC_CONTEXT
+        .set(enabled)
+        .or_else(|_| bail!("exec_context was already set"))?;
+    Ok(())
+}
+
+/// Returns the current value of the global execution context flag.
+///
+/// If called before [`set_exec_context`] has been called, it will be set to false.
+pub(crate) fn get_exec_context() -> bool {
+    *EXEC_CONTEXT.get_or_init(|| false)
+}
+
+static PROBE_RATE: OnceCell<u64> = OnceCell::new();
+
+/// Sets the global per-probe rate limit, in events per second. Probes drop the
+/// events exceeding it before building them (see Kernel::probe_throttled()).
+/// 0 means no limit.
+///
+/// Returns an error if it was already set.
+pub(crate) fn set_probe_rate(rate: u64) -> Result<()> {
+    PROBE_RATE
+        .set(rate)
+        .or_else(|_| bail!("probe_rate was already set"))?;
+    Ok(())
+}
+
+/// Returns the current value of the global per-probe rate limit.
+///
+/// If called before [`set_probe_rate`] has been called, it will be set to 0.
+pub(crate) fn get_probe_rate() -> u64 {
+    *PROBE_RATE.get_or_init(|| 0)
+}
+
+/// bpf_ktime_get_tai_ns helper id, not known to libbpf-sys. Please keep in sync
+/// with its BPF counterpart in core::probe::kernel::bpf::include::common.h
+const BPF_FUNC_KTIME_GET_TAI_NS: u32 = 208;
+
+static CLOCK_TAI: OnceCell<bool> = OnceCell::new();
+
+/// Sets the global TAI clock flag, instructing probes to also report a TAI
+/// timestamp. Should only be enabled when [`tai_clock_available`].
+///
+/// Returns an error if it was already set.
+pub(crate) fn set_clock_tai(enabled: bool) -> Result<()> {
+    CLOCK_TAI
+        .set(enabled)
+        .or_else(|_| bail!("clock_tai was already set"))?;
+    Ok(())
+}
+
+/// Returns the current value of the global TAI clock flag.
+///
+/// If called before [`set_clock_tai`] has been called, it will be set to false.
+pub(crate) fn get_clock_tai() -> bool {
+    *CLOCK_TAI.get_or_init(|| false)
+}
+
+/// Check if probes can retrieve TAI timestamps on the running kernel (Linux
+/// v6.1 and later).
+pub(crate) fn tai_clock_available() -> bool {
+    unsafe {
+        libbpf_sys::libbpf_probe_bpf_helper(
+            libbpf_sys::BPF_PROG_TYPE_KPROBE,
+            BPF_FUNC_KTIME_GET_TAI_NS,
+            std::ptr::null(),
+        ) == 1
+    }
+}
@@ -3,6 +3,7 @@
 
 #include <vmlinux.h>
 #include <bpf/bpf_helpers.h>
+#include <bpf/bpf_core_read.h>
 
 #include "events.h"
 
@@ -15,6 +16,7 @@ struct trace_probe_offsets {
 	s8 skb_drop_reason;
 	s8 net_device;
 	s8 net;		/* netns */
+	s8 sock;
 };
 
 /* Per-probe configuration; keep in sync with its Rust counterpart in
@@ -22,6 +24,10 @@ struct trace_probe_offsets {
  */
 struct trace_probe_config {
 	struct trace_probe_offsets offsets;
+	/* Only count the probe hits, as in counting mode. Set while observing
+	 * the probes' rate before enabling them, see Kernel::set_observe().
+	 */
+	u8 count_hits;
 };
 
 /* Keep in sync with its Rust counterpart in crate::core::probe::kernel */
@@ -35,6 +41,100 @@ struct {
 	__type(value, struct trace_probe_config);
 } config_map SEC(".maps");
 
+/* Probe hit counters, used in counting mode; the key is the target symbol
+ * address.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, u64);
+} probe_hits_map SEC(".maps");
+
+/* Per-probe rate limiting state; keep in sync with its Rust counterpart in
+ * core::probe::kernel::config.
+ *
+ * window:  Start of the current one second window, in ns.
+ * count:   Number of events seen in the current window.
+ * dropped: Number of events dropped since the probe was attached.
+ */
+struct probe_throttle {
+	u64 window;
+	u64 count;
+	u64 dropped;
+};
+
+/* Per-probe rate limiting, used when probe_rate is set; the key is the target
+ * symbol address.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PROBE_MAX);
+	__type(key, u64);
+	__type(value, struct probe_throttle);
+} probe_throttle_map SEC(".maps");
+
+/* Keep in sync with its Rust counterpart in crate::core::filters::pid */
+#define PID_FILTER_MAX	1024
+
+/* Pid filter; the key is a tgid. When the pid filter is enabled only events
+ * generated in the context of a process found in this map are reported.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, PID_FILTER_MAX);
+	__type(key, u32);
+	__type(value, u8);
+} pid_filter_map SEC(".maps");
+
+/* Is the pid filter enabled? */
+const volatile u8 pid_filter = 0;
+
+/* Counting mode: probes only count their hits in probe_hits_map and do not
+ * build events nor run hooks. Used to measure the probes' rate at a low cost.
+ */
+const volatile u8 count_only = 0;
+
+/* Counter mode: hooks run as usual but increment per-category counters in
+ * event_counters_map (see event_counter_inc()) instead of filling the event,
+ * which is then never sent.
+ */
+const volatile u8 count_events = 0;
+
+/* Maximum number of events per second reported by each probe, 0 meaning no
+ * limit. Excess events are dropped before being built and accounted for in
+ * probe_throttle_map.
+ */
+const volatile u64 probe_rate = 0;
+
+/* Should events also report a TAI timestamp? Only set when the helper below is
+ * available on the running kernel.
+ */
+const volatile u8 clock_tai = 0;
+
+/* Should events also report their execution context (see
+ * get_exec_context())?
+ */
+const volatile u8 exec_context = 0;
+
+/* Softirq being run on each cpu: its vector + 1, 0 when not running a softirq.
+ * Filled by the softirq tracker (see core::probe::kernel::softirq) when
+ * exec_context is set. Please keep in sync with its counterpart in
+ * softirq.bpf.c.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+	__uint(max_entries, 1);
+	__type(key, u32);
+	__type(value, u8);
+} softirq_map SEC(".maps");
+
+/* bpf_ktime_get_tai_ns, available since Linux v6.1 and not known to our libbpf
+ * helper definitions. Please keep in sync with its Rust counterpart in
+ * crate::core::probe::common.
+ */
+static u64 (*ktime_get_tai_ns)(void) = (void *)208;
+
 /* Common representation of the register values provided to the probes, as this
  * is done in a per-probe type fashion.
  *
@@ -47,6 +147,95 @@ struct trace_regs {
 	u32 num;
 };
 
+/* Type of the probe which generated an event. Keep in sync with its Rust
+ * counterpart in crate::core::events::bpf.
+ */
+#define PROBE_TYPE_KPROBE		1
+#define PROBE_TYPE_RAW_TRACEPOINT	2
+
+/* Execution contexts. Please keep in sync with its Rust counterpart in
+ * crate::core::events::bpf.
+ */
+#define EXEC_CONTEXT_UNKNOWN	0
+#define EXEC_CONTEXT_TASK	1
+#define EXEC_CONTEXT_SOFTIRQ	2
+#define EXEC_CONTEXT_HARDIRQ	3
+#define EXEC_CONTEXT_NMI	4
+
+/* Preempt count masks, see include/linux/preempt.h */
+#define SOFTIRQ_OFFSET	(1U << 8)
+#define HARDIRQ_MASK	(0xfU << 16)
+#define NMI_MASK	(0xfU << 20)
+
+/* The preempt count is a per-cpu variable on x86, either on its own
+ * (__preempt_count) or in pcpu_hot (Linux v6.2 to v6.14), and is part of the
+ * task thread_info on other architectures. Those are not all part of our
+ * vmlinux.h, hence the local definitions.
+ */
+struct pcpu_hot___exec {
+	int preempt_count;
+} __attribute__((preserve_access_index));
+extern struct pcpu_hot___exec pcpu_hot __ksym __weak;
+extern const int __preempt_count __ksym __weak;
+
+struct thread_info___exec {
+	int preempt_count;
+} __attribute__((preserve_access_index));
+struct task_struct___exec {
+	struct thread_info___exec thread_info;
+} __attribute__((preserve_access_index));
+
+/* Retrieve the execution context of the running probe, one of EXEC_CONTEXT_*,
+ * from the preempt count. Softirqs are only reported while being served, not
+ * when bottom halves are disabled in task context.
+ */
+static __always_inline u8 get_exec_context(void)
+{
+	struct task_struct___exec *task;
+	u32 count;
+
+	if (&pcpu_hot) {
+		count = ((struct pcpu_hot___exec *)bpf_this_cpu_ptr(&pcpu_hot))->preempt_count;
+	} else if (&__preempt_count) {
+		count = *(int *)bpf_this_cpu_ptr(&__preempt_count);
+	} else if (bpf_core_field_exists(struct thread_info___exec, preempt_count)) {
+		task = (void *)bpf_get_current_task();
+		count = BPF_CORE_READ(task, thread_info.preempt_count);
+	} else {
+		return EXEC_CONTEXT_UNKNOWN;
+	}
+
+	if (count & NMI_MASK)
+		return EXEC_CONTEXT_NMI;
+	if (count & HARDIRQ_MASK)
+		return EXEC_CONTEXT_HARDIRQ;
+	if (count & SOFTIRQ_OFFSET)
+		return EXEC_CONTEXT_SOFTIRQ;
+	return EXEC_CONTEXT_TASK;
+}
+
+/* Fill the execution context section of an event. */
+static __always_inline void fill_exec_context(struct trace_raw_event *event)
+{
+	struct common_context_event *e;
+	u32 zero = 0;
+	u8 *softirq;
+
+	e = get_event_section(event, COMMON, 2, sizeof(*e));
+	if (!e)
+		return;
+
+	e->context = get_exec_context();
+	e->softirq = 0;
+	if (e->context == EXEC_CONTEXT_SOFTIRQ) {
+		softirq = bpf_map_lookup_elem(&softirq_map, &zero);
+		if (softirq)
+			e->softirq = *softirq;
+	}
+	e->pid = bpf_get_current_pid_tgid() >> 32;
+	bpf_get_current_comm(e->comm, sizeof(e->comm));
+}
+
 /* Common context information consumed by all hooks. It serves as an abstraction
  * as different probe types have different specific contexts. This information
  * will be used to provide helpers for hooks as well, e.g. to safely retrieve a
@@ -55,6 +244,8 @@ struct trace_regs {
  * timestamp: Timestamp of when the probe wall called, should be filled as early
  *            as possible in the probe specific part. Then it should be left
  *            untouched.
+ * timestamp_tai: Same, using the TAI clock. Only filled when clock_tai is set,
+ *            0 otherwise.
  * ksym:      Symbol address of the where the probe was hooked. Should also be
  *            filled in the probe specific part. It is quite handy as it is the
  *            only common way of understanding where a probe/hook is running.
@@ -62,10 +253,17 @@ struct trace_regs {
  *            can be used to retrieve parameters, and if the probe type allows,
  *            the returned value. Should be accessed using the get_param()
  *            helper.
+ * probe_type: Type of the probe, one of PROBE_TYPE_*. Should also be filled in
+ *            the probe specific part.
+ * count_events: Set when hooks should only increment counters and not fill
+ *            the event, which won't be sent. Filled in chain().
  */
 struct trace_context {
 	u64 timestamp;
+	u64 timestamp_tai;
 	u64 ksym;
+	u8 probe_type;
+	u8 count_events;
 	struct trace_probe_offsets offsets;
 	struct trace_regs regs;
 };
@@ -94,6 +292,8 @@ struct trace_context {
 	TRACE_GET(ctx, net_device, struct net_device *)
 #define trace_get_net(ctx)		\
 	TRACE_GET(ctx, net, struct net *)
+#define trace_get_sock(ctx)		\
+	TRACE_GET(ctx, sock, struct sock *)
 
 /* Helper to define a hook (mostly in collectors) while not having to duplicate
  * the common part everywhere. This also ensure hooks are doing the right thing
@@ -123,6 +323,13 @@ struct trace_context {
 		inst								\
 	}
 
+/* Hooks return value asking for the event being built to be discarded, e.g.
+ * when it does not match a filter. Hooks otherwise return 0. Hooks run in the
+ * order set by the Rust side (see Hook::priority()); discarding an event skips
+ * the hooks following the discarding one.
+ */
+#define HOOK_DISCARD	1
+
 /* Number of hooks installed, used to micro-optimize the call chain */
 const volatile u32 nhooks = 0;
 
@@ -151,6 +358,42 @@ HOOK(9)
 /* Keep in sync with its Rust counterpart in crate::core::probe::kernel */
 #define HOOK_MAX 10
 
+#define NSEC_PER_SEC	1000000000ULL
+
+/* Check if the probe reached its rate limit (see probe_rate) in the current
+ * one second window, in which case the event must be dropped. The window
+ * reset is not atomic: concurrent hits at a window boundary can let a few
+ * extra events through, which is fine for a rate limit.
+ */
+static __always_inline bool probe_throttled(struct trace_context *ctx)
+{
+	struct probe_throttle *t;
+
+	t = bpf_map_lookup_elem(&probe_throttle_map, &ctx->ksym);
+	if (!t) {
+		struct probe_throttle init = {
+			.window = ctx->timestamp,
+		};
+
+		bpf_map_update_elem(&probe_throttle_map, &ctx->ksym, &init,
+				    BPF_NOEXIST);
+		t = bpf_map_lookup_elem(&probe_throttle_map, &ctx->ksym);
+		if (!t)
+			return false;
+	}
+
+	if (ctx->timestamp - t->window >= NSEC_PER_SEC) {
+		t->window = ctx->timestamp;
+		t->count = 0;
+	}
+
+	if (__sync_fetch_and_add(&t->count, 1) >= probe_rate) {
+		__sync_fetch_and_add(&t->dropped, 1);
+		return true;
+	}
+	return false;
+}
+
 /* The chaining function, which contains all our core probe logic. This is
  * called from each probe specific part after filling the common context and
  * just before returning.
@@ -165,7 +408,40 @@ static __always_inline int chain(struct trace_context *ctx)
 	if (!cfg)
 		return 0;
 
+	/* In interrupt context (softirqs included) the current task is the one
+	 * which was interrupted, not one the event can be attributed to: such
+	 * events are skipped. If the context can't be retrieved, only the tgid
+	 * is checked.
+	 */
+	if (pid_filter) {
+		u32 tgid = bpf_get_current_pid_tgid() >> 32;
+		u8 context = get_exec_context();
+
+		if (context != EXEC_CONTEXT_TASK && context != EXEC_CONTEXT_UNKNOWN)
+			return 0;
+		if (!bpf_map_lookup_elem(&pid_filter_map, &tgid))
+			return 0;
+	}
+
+	if (count_only || cfg->count_hits) {
+		u64 *hits = bpf_map_lookup_elem(&probe_hits_map, &ctx->ksym);
+
+		if (hits) {
+			__sync_fetch_and_add(hits, 1);
+		} else {
+			u64 one = 1;
+
+			bpf_map_update_elem(&probe_hits_map, &ctx->ksym, &one,
+					    BPF_NOEXIST);
+		}
+		return 0;
+	}
+
+	if (probe_rate && probe_throttled(ctx))
+		return 0;
+
 	ctx->offsets = cfg->offsets;
+	ctx->count_events = count_events;
 
 	event = get_event();
 	if (!event)
@@ -179,10 +455,17 @@ static __always_inline int chain(struct trace_context *ctx)
 
 	e->symbol = ctx->ksym;
 	e->timestamp = ctx->timestamp;
+	e->timestamp_tai = ctx->timestamp_tai;
+	e->probe_type = ctx->probe_type;
+
+	if (exec_context)
+		fill_exec_context(event);
 
-#define CALL_HOOK(x)		\
-	if (x < nhooks)		\
-		hook##x(ctx, event);
+#define CALL_HOOK(x)						\
+	if (x < nhooks && hook##x(ctx, event) == HOOK_DISCARD) {	\
+		discard_event(event);				\
+		return 0;					\
+	}
 	CALL_HOOK(0)
 	CALL_HOOK(1)
 	CALL_HOOK(2)
@@ -194,6 +477,11 @@ static __always_inline int chain(struct trace_context *ctx)
 	CALL_HOOK(8)
 	CALL_HOOK(9)
 
+	if (count_events) {
+		discard_event(event);
+		return 0;
+	}
+
 	send_event(event);
 	return 0;
 }
@@ -20,6 +20,9 @@ int probe_kprobe(struct pt_regs *ctx)
 	struct trace_context context = {};
 
 	context.timestamp = bpf_ktime_get_ns();
+	if (clock_tai)
+		context.timestamp_tai = ktime_get_tai_ns();
+	context.probe_type = PROBE_TYPE_KPROBE;
 	context.ksym = PT_REGS_IP(ctx) - 1;
 	get_regs(&context.regs, ctx);
 
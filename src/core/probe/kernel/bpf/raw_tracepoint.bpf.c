@@ -47,6 +47,9 @@ int probe_raw_tracepoint(struct bpf_raw_tracepoint_args *ctx)
 	struct trace_context context = {};
 
 	context.timestamp = bpf_ktime_get_ns();
+	if (clock_tai)
+		context.timestamp_tai = ktime_get_tai_ns();
+	context.probe_type = PROBE_TYPE_RAW_TRACEPOINT;
 	context.ksym = ksym;
 	get_regs(&context.regs, ctx);
 
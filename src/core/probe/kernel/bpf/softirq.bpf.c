@@ -0,0 +1,39 @@
+#include <vmlinux.h>
+#include <bpf/bpf_helpers.h>
+
+/* Softirq being run on each cpu: its vector + 1, 0 when not running a softirq.
+ * Please keep in sync with its counterpart in include/common.h.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+	__uint(max_entries, 1);
+	__type(key, u32);
+	__type(value, u8);
+} softirq_map SEC(".maps");
+
+static __always_inline void set_softirq(u8 value)
+{
+	u32 zero = 0;
+	u8 *softirq;
+
+	softirq = bpf_map_lookup_elem(&softirq_map, &zero);
+	if (softirq)
+		*softirq = value;
+}
+
+/* Softirqs do not nest on a given cpu, a single slot is enough. */
+SEC("raw_tp/softirq_entry")
+int softirq_entry(struct bpf_raw_tracepoint_args *ctx)
+{
+	set_softirq((u8)ctx->args[0] + 1);
+	return 0;
+}
+
+SEC("raw_tp/softirq_exit")
+int softirq_exit(struct bpf_raw_tracepoint_args *ctx)
+{
+	set_softirq(0);
+	return 0;
+}
+
+char __license[] SEC("license") = "GPL";
@@ -0,0 +1,28 @@
+#include <vmlinux.h>
+
+#include <common.h>
+
+/* Number of times the test hooks ran, indexed by hook: 0 for the discarding
+ * one, 1 for the counting one. Reused from the test.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_ARRAY);
+	__uint(max_entries, 2);
+	__type(key, u32);
+	__type(value, u64);
+} hook_test_map SEC(".maps");
+
+/* Test hook only counting its runs, see the hook chaining test in
+ * core::probe::kernel.
+ */
+DEFINE_HOOK(
+	u32 index = 1;
+	u64 *runs;
+
+	runs = bpf_map_lookup_elem(&hook_test_map, &index);
+	if (runs)
+		__sync_fetch_and_add(runs, 1);
+	return 0;
+)
+
+char __license[] SEC("license") = "GPL";
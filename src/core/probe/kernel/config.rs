@@ -10,6 +10,7 @@ pub(super) struct ProbeOffsets {
     pub(super) skb_drop_reason: i8,
     pub(super) net_device: i8,
     pub(super) net: i8,
+    pub(super) sock: i8,
 }
 
 impl Default for ProbeOffsets {
@@ -20,6 +21,7 @@ impl Default for ProbeOffsets {
             skb_drop_reason: -1,
             net_device: -1,
             net: -1,
+            sock: -1,
         }
     }
 }
@@ -30,12 +32,58 @@ impl Default for ProbeOffsets {
 #[repr(C)]
 pub(super) struct ProbeConfig {
     pub(super) offsets: ProbeOffsets,
+    /// Only count the probe hits, see Kernel::set_observe().
+    pub(super) count_hits: u8,
 }
 
 unsafe impl plain::Plain for ProbeConfig {}
 
 #[allow(dead_code)] // When testing this isn't used as the config map is hidden.
 pub(super) fn init_config_map() -> Result<libbpf_rs::Map> {
+    init_map(
+        "config_map",
+        mem::size_of::<u64>() as u32,
+        mem::size_of::<ProbeConfig>() as u32,
+    )
+}
+
+/// Probe hit counters, filled in counting mode; keep in sync with its BPF
+/// counterpart in bpf/include/common.h
+#[allow(dead_code)] // When testing this isn't used as the hits map is hidden.
+pub(super) fn init_hits_map() -> Result<libbpf_rs::Map> {
+    init_map(
+        "probe_hits_map",
+        mem::size_of::<u64>() as u32,
+        mem::size_of::<u64>() as u32,
+    )
+}
+
+/// Per-probe rate limiting state; keep in sync with its BPF counterpart in
+/// bpf/include/common.h
+#[derive(Default)]
+#[repr(C)]
+pub(super) struct ProbeThrottle {
+    pub(super) window: u64,
+    pub(super) count: u64,
+    /// Number of events dropped since the probe was attached.
+    pub(super) dropped: u64,
+}
+
+unsafe impl plain::Plain for ProbeThrottle {}
+
+/// Per-probe rate limiting state, used when a probe rate is set; keep in sync
+/// with its BPF counterpart in bpf/include/common.h
+#[allow(dead_code)] // When testing this isn't used as the throttle map is hidden.
+pub(super) fn init_throttle_map() -> Result<libbpf_rs::Map> {
+    init_map(
+        "probe_throttle_map",
+        mem::size_of::<u64>() as u32,
+        mem::size_of::<ProbeThrottle>() as u32,
+    )
+}
+
+#[allow(dead_code)]
+fn init_map(name: &str, key_size: u32, value_size: u32) -> Result<libbpf_rs::Map> {
     let opts = libbpf_sys::bpf_map_create_opts {
         sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
         ..Default::default()
@@ -43,9 +91,9 @@ pub(super) fn init_config_map() -> Result<libbpf_rs::Map> {
 
     Ok(libbpf_rs::Map::create(
         libbpf_rs::MapType::Hash,
-        Some("config_map"),
-        mem::size_of::<u64>() as u32,
-        mem::size_of::<ProbeConfig>() as u32,
+        Some(name),
+        key_size,
+        value_size,
         super::PROBE_MAX as u32,
         &opts,
     )?)
@@ -0,0 +1,161 @@
+//! # BPF programs dump
+//!
+//! Developer helper writing the loaded BPF programs to a directory (see the
+//! collect --dump-bpf option), to inspect them when a probe or hook misbehaves
+//! on a given kernel. For each program two files are written:
+//!
+//! - `<name>.xlated`: the instructions as translated by the kernel, that is
+//!   after the CO-RE relocations were applied and the program verified. They
+//!   can be disassembled, e.g. with `llvm-objdump -d` once wrapped in an ELF
+//!   object, or compared with `bpftool prog dump xlated id <id>`.
+//! - `<name>.info`: the program id, type and tag, along with the maps it uses.
+//!
+//! Names are made of the probe type, its target and the program name, e.g.
+//! `raw_tracepoint:kfree_skb.hook0`.
+
+use std::{fmt::Write as _, fs, mem, os::raw::c_char, path::Path};
+
+use anyhow::{bail, Result};
+use log::{debug, warn};
+use nix::{errno::Errno, libc, unistd::close};
+
+use crate::core::probe::common::get_dump_bpf;
+
+/// Dump a loaded program, if requested. Failing to do so is not fatal, as this
+/// is only a debugging helper.
+pub(crate) fn dump_prog(fd: i32, name: &str) {
+    let dir = match get_dump_bpf() {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    if let Err(e) = dump_prog_to(fd, &file_name(name), Path::new(dir)) {
+        warn!("Could not dump BPF program {}: {}", name, e);
+    }
+}
+
+fn dump_prog_to(fd: i32, name: &str, dir: &Path) -> Result<()> {
+    // Retrieve the sizes first, then the instructions and map ids.
+    let mut info = libbpf_sys::bpf_prog_info::default();
+    prog_info(fd, &mut info)?;
+
+    let mut insns = vec![0u8; info.xlated_prog_len as usize];
+    let mut map_ids = vec![0u32; info.nr_map_ids as usize];
+    let mut full = libbpf_sys::bpf_prog_info {
+        xlated_prog_len: insns.len() as u32,
+        xlated_prog_insns: insns.as_mut_ptr() as u64,
+        nr_map_ids: map_ids.len() as u32,
+        map_ids: map_ids.as_mut_ptr() as u64,
+        ..Default::default()
+    };
+    prog_info(fd, &mut full)?;
+
+    let mut desc = String::new();
+    writeln!(desc, "name: {}", c_str(&info.name))?;
+    writeln!(desc, "id: {}", info.id)?;
+    writeln!(desc, "type: {}", info.type_)?;
+    writeln!(
+        desc,
+        "tag: {}",
+        info.tag
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )?;
+    writeln!(desc, "xlated_len: {}", insns.len())?;
+    for id in map_ids.iter() {
+        match map_info(*id) {
+            Ok(map) => writeln!(
+                desc,
+                "map: id={} name={} type={} key_size={} value_size={} max_entries={}",
+                map.id,
+                c_str(&map.name),
+                map.type_,
+                map.key_size,
+                map.value_size,
+                map.max_entries
+            )?,
+            Err(e) => writeln!(desc, "map: id={} ({})", id, e)?,
+        }
+    }
+
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(format!("{}.xlated", name)), &insns)?;
+    fs::write(dir.join(format!("{}.info", name)), desc)?;
+
+    debug!("Dumped BPF program {} to {}", name, dir.display());
+    Ok(())
+}
+
+fn prog_info(fd: i32, info: &mut libbpf_sys::bpf_prog_info) -> Result<()> {
+    let mut len = mem::size_of::<libbpf_sys::bpf_prog_info>() as u32;
+    let ret = unsafe {
+        libbpf_sys::bpf_obj_get_info_by_fd(fd, info as *mut _ as *mut libc::c_void, &mut len)
+    };
+    if ret < 0 {
+        bail!("Could not get program info: {}", Errno::from_i32(-ret));
+    }
+    Ok(())
+}
+
+fn map_info(id: u32) -> Result<libbpf_sys::bpf_map_info> {
+    let fd = unsafe { libbpf_sys::bpf_map_get_fd_by_id(id) };
+    if fd < 0 {
+        bail!("Could not get map: {}", Errno::from_i32(-fd));
+    }
+
+    let mut info = libbpf_sys::bpf_map_info::default();
+    let mut len = mem::size_of::<libbpf_sys::bpf_map_info>() as u32;
+    let ret = unsafe {
+        libbpf_sys::bpf_obj_get_info_by_fd(fd, &mut info as *mut _ as *mut libc::c_void, &mut len)
+    };
+    let _ = close(fd);
+
+    if ret < 0 {
+        bail!("Could not get map info: {}", Errno::from_i32(-ret));
+    }
+    Ok(info)
+}
+
+fn c_str(name: &[c_char]) -> String {
+    // Names are NULL terminated, unless they use the full buffer.
+    let bytes: Vec<u8> = name
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Make a name suitable for use as a file name.
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\0' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(file_name("raw_tracepoint:kfree_skb.hook0") == "raw_tracepoint:kfree_skb.hook0");
+        assert!(file_name("kprobe:foo/bar") == "kprobe:foo_bar");
+
+        let name = [
+            b'h' as c_char,
+            b'o' as c_char,
+            b'o' as c_char,
+            b'k' as c_char,
+            0,
+            0,
+        ];
+        assert!(c_str(&name) == "hook");
+        let name = [b'a' as c_char, b'b' as c_char];
+        assert!(c_str(&name) == "ab");
+    }
+}
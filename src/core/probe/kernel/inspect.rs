@@ -1,13 +1,17 @@
 #![allow(dead_code)] // FIXME
 
-use std::{collections::HashSet, fs};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{bail, Result};
 use btf_rs::{Btf, Type};
 use log::warn;
 
 use super::{config::ProbeConfig, ProbeType};
-use crate::core::kernel_symbols;
+use crate::core::{kernel_symbols, tracefs::read_tracefs};
 
 /// Holds the result of a kernel symbol inspection and describes it.
 #[derive(Default)]
@@ -27,6 +31,8 @@ pub(crate) struct Inspector {
     traceable_funcs: Option<HashSet<String>>,
     /// Set of traceable events (e.g. tracepoints).
     traceable_events: Option<HashSet<String>>,
+    /// Functions replaced by an enabled live patch, and the patch name.
+    livepatched: HashMap<String, String>,
 }
 
 impl Inspector {
@@ -38,27 +44,27 @@ impl Inspector {
             btf: Btf::from_file("test_data/vmlinux")?,
             // Not all functions we'll get from BTF/kallsyms are traceable. Use
             // the following, when available, to narrow down our checks.
-            traceable_funcs: Self::file_to_hashset(
-                "/sys/kernel/debug/tracing/available_filter_functions",
-            ),
+            traceable_funcs: Self::tracefs_to_hashset("available_filter_functions"),
             // We also filter tracepoints using BTF but this doesn't include the
             // group part. Use the following, when available, to narrow down our
             // checks for a better ux.
-            traceable_events: Self::file_to_hashset("/sys/kernel/debug/tracing/available_events"),
+            traceable_events: Self::tracefs_to_hashset("available_events"),
+            livepatched: livepatched_functions(Path::new("/sys/kernel/livepatch")),
         };
 
         if inspector.traceable_funcs.is_none() || inspector.traceable_events.is_none() {
             warn!(
-                "Consider mounting debugfs to /sys/kernel/debug to better filter available probes"
+                "Consider mounting tracefs to /sys/kernel/tracing to better filter available probes"
             );
         }
 
         Ok(inspector)
     }
 
-    /// Convert a file containing a list of str (one per line) into a HashSet.
-    fn file_to_hashset(target: &str) -> Option<HashSet<String>> {
-        if let Ok(file) = fs::read_to_string(target) {
+    /// Convert a tracefs file containing a list of str (one per line) into a
+    /// HashSet.
+    fn tracefs_to_hashset(target: &str) -> Option<HashSet<String>> {
+        if let Ok(file) = read_tracefs(target) {
             let mut set = HashSet::new();
             for line in file.lines() {
                 set.insert(line.to_string());
@@ -106,9 +112,10 @@ impl Inspector {
         // Some probe types might need to change the target format.
         Ok(match r#type {
             ProbeType::Kprobe => {
-                // Kprobes are using directly the target function definition, no
-                // change to make to the target format and the prototype
-                // resolution is straightforward: Func -> FuncProto.
+                // Kprobes are using directly the target function definition,
+                // once stripped from its candidate selector if any, and the
+                // prototype resolution is straightforward: Func -> FuncProto.
+                let target = kernel_symbols::symbol_name(target);
                 let func = match self.btf.resolve_type_by_name(target)? {
                     Type::Func(func) => func,
                     _ => bail!("{} is not a function", target),
@@ -215,11 +222,23 @@ impl Inspector {
             ProbeType::Max => bail!("Invalid probe type"),
         };
 
+        // Kprobe targets can select a symbol candidate (see kernel_symbols),
+        // the function name alone is traceable.
+        let name = match r#type {
+            ProbeType::Kprobe => kernel_symbols::symbol_name(target),
+            _ => target,
+        };
         // If we got the list of functions/events available for tracing, which
         // is a subset of what kernel_symbols below supports, use it to bail out
         // early if a function isn't traceable.
-        if let Some(res) = self.is_symbol_traceable(r#type, target) {
+        if let Some(res) = self.is_symbol_traceable(r#type, name) {
             if !res {
+                if let ProbeType::RawTracepoint = r#type {
+                    bail!(
+                        "{} isn't traceable (see packet-tracer list-tracepoints)",
+                        target
+                    );
+                }
                 bail!("{} isn't traceable", target);
             }
         }
@@ -248,6 +267,18 @@ impl Inspector {
 
     /// Inspect a target using BTF and fill its description.
     pub(super) fn inspect_target(&self, r#type: &ProbeType, target: &str) -> Result<TargetDesc> {
+        // Live patched functions are redirected to their new version at entry;
+        // the probe still fires but what follows is the patched code.
+        if let (ProbeType::Kprobe, Some(patch)) = (
+            r#type,
+            self.livepatched.get(kernel_symbols::symbol_name(target)),
+        ) {
+            warn!(
+                "{} is live patched by '{}', events reflect the behavior of the patched version",
+                target, patch
+            );
+        }
+
         // First look at the symbol address.
         let mut desc = TargetDesc {
             ksym: self.get_ksym(r#type, target)?,
@@ -278,6 +309,10 @@ impl Inspector {
                 desc.probe_cfg.offsets.net_device = (offset - fix) as i8;
             } else if self.is_param_type(param, "struct net *")? {
                 desc.probe_cfg.offsets.net = (offset - fix) as i8;
+            } else if self.is_param_type(param, "struct sock *")?
+                || self.is_param_type(param, "const struct sock *")?
+            {
+                desc.probe_cfg.offsets.sock = (offset - fix) as i8;
             }
         }
 
@@ -285,10 +320,68 @@ impl Inspector {
     }
 }
 
+/// List the functions replaced by enabled live patches, using the livepatch
+/// sysfs interface: <root>/<patch>/<object>/<function,sympos>. Returns an
+/// empty list if live patching isn't available.
+fn livepatched_functions(root: &Path) -> HashMap<String, String> {
+    let mut functions = HashMap::new();
+    let subdirs = |dir: &Path| -> Vec<(String, PathBuf)> {
+        match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().is_dir())
+                .filter_map(|e| Some((e.file_name().to_str()?.to_string(), e.path())))
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    };
+
+    for (patch, patch_path) in subdirs(root) {
+        // Patches being disabled are still listed.
+        if let Ok(enabled) = fs::read_to_string(patch_path.join("enabled")) {
+            if enabled.trim() != "1" {
+                continue;
+            }
+        }
+
+        for (_, object_path) in subdirs(&patch_path) {
+            for (function, _) in subdirs(&object_path) {
+                let name = match function.split_once(',') {
+                    Some((name, _)) => name.to_string(),
+                    None => function,
+                };
+                functions.insert(name, patch.clone());
+            }
+        }
+    }
+    functions
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn livepatch() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("pt-livepatch-{}", std::process::id()));
+        fs::create_dir_all(root.join("kpatch_fix/vmlinux/tcp_v4_rcv,1"))?;
+        fs::create_dir_all(root.join("kpatch_fix/nf_conntrack/nf_ct_delete,1"))?;
+        fs::write(root.join("kpatch_fix/enabled"), "1\n")?;
+        fs::create_dir_all(root.join("kpatch_old/vmlinux/ip_rcv,1"))?;
+        fs::write(root.join("kpatch_old/enabled"), "0\n")?;
+
+        let functions = livepatched_functions(&root);
+        fs::remove_dir_all(&root)?;
+
+        assert!(functions.len() == 2);
+        assert!(functions.get("tcp_v4_rcv") == Some(&"kpatch_fix".to_string()));
+        assert!(functions.get("nf_ct_delete") == Some(&"kpatch_fix".to_string()));
+        assert!(functions.get("ip_rcv").is_none());
+
+        assert!(livepatched_functions(Path::new("/nonexistent")).is_empty());
+        Ok(())
+    }
+
     #[test]
     fn parameter_offset() {
         let inspect = Inspector::new().unwrap();
@@ -346,5 +439,6 @@ mod tests {
         assert!(desc.probe_cfg.offsets.skb_drop_reason == 2);
         assert!(desc.probe_cfg.offsets.net_device == -1);
         assert!(desc.probe_cfg.offsets.net == -1);
+        assert!(desc.probe_cfg.offsets.sock == -1);
     }
 }
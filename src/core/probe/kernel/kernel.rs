@@ -1,34 +1,78 @@
 #![allow(dead_code)] // FIXME
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, bail, Result};
-use log::info;
+use log::{info, warn};
 
 #[cfg(not(test))]
-use super::config::init_config_map;
+use super::config::{init_config_map, init_hits_map, init_throttle_map, ProbeThrottle};
 use super::{
+    dump,
     inspect::{Inspector, TargetDesc},
-    kprobe, raw_tracepoint,
+    kprobe,
+    modules::{ModuleEvent, ModuleWatcher},
+    raw_tracepoint, relo,
+};
+use crate::core::{
+    events::bpf::BpfEvents,
+    kernel_symbols,
+    probe::{get_count_only, get_probe_rate},
 };
-use crate::core::events::bpf::BpfEvents;
 
-/// Probes types supported by this crate.
+/// Probes types supported by this crate. Their value is used as an index in
+/// per probe type arrays, Max being the number of types.
 #[allow(dead_code)]
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub(crate) enum ProbeType {
     Kprobe,
     RawTracepoint,
     Max,
 }
 
+impl ProbeType {
+    /// All valid probe types, in order. Its size ensures no type is missing.
+    pub(crate) const ALL: [ProbeType; ProbeType::Max as usize] =
+        [ProbeType::Kprobe, ProbeType::RawTracepoint];
+
+    /// Index of the probe type in per probe type arrays.
+    fn index(&self) -> Result<usize> {
+        match self {
+            ProbeType::Max => bail!("Invalid probe type"),
+            r#type => Ok(*r#type as usize),
+        }
+    }
+
+    pub(crate) fn to_str(&self) -> &'static str {
+        match self {
+            ProbeType::Kprobe => "kprobe",
+            ProbeType::RawTracepoint => "raw_tracepoint",
+            ProbeType::Max => "invalid",
+        }
+    }
+}
+
 /// Hook provided by modules for registering them on kernel probes.
+///
+/// Hooks attached to a probe are chained: they run one after the other, by
+/// increasing priority (see priority()), targeted hooks running before generic
+/// ones of the same priority. A hook returning HOOK_DISCARD drops the event
+/// being built and short-circuits the hooks following it; this allows filtering
+/// hooks to run before enrichment ones.
 #[derive(Clone)]
 pub(crate) struct Hook {
     /// Hook BPF binary data.
     bpf_prog: &'static [u8],
     /// HashMap of maps names and their fd, for reuse by the hook.
     maps: HashMap<String, i32>,
+    /// CO-RE relocation targets the hook can do without, see optional_relo().
+    optional_relos: Vec<String>,
+    /// Position of the hook in the chain, lower priorities running first.
+    priority: i32,
 }
 
 impl Hook {
@@ -37,9 +81,19 @@ impl Hook {
         Hook {
             bpf_prog,
             maps: HashMap::new(),
+            optional_relos: Vec::new(),
+            priority: 0,
         }
     }
 
+    /// Set the hook priority, defining its position in the chain of hooks
+    /// running on a probe. Lower priorities run first; hooks of the same
+    /// priority keep their registration order. Defaults to 0.
+    pub(crate) fn priority(&mut self, priority: i32) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
     /// Request to reuse a map specifically in the hook. For maps being globally
     /// reused please use Kernel::reuse_map() instead.
     pub(crate) fn reuse_map(&mut self, name: &str, fd: i32) -> Result<&mut Self> {
@@ -52,6 +106,27 @@ impl Hook {
         self.maps.insert(name, fd);
         Ok(self)
     }
+
+    /// Mark the CO-RE relocations of a type (e.g. "net_bridge_port") or of one
+    /// of its fields (e.g. "net_bridge_port.port_no") as optional. If the hook
+    /// can't be loaded only because of optional relocations not matching the
+    /// running kernel, the hook is skipped (disabling the feature depending on
+    /// it) instead of failing the probe.
+    pub(crate) fn optional_relo(&mut self, target: &str) -> &mut Self {
+        self.optional_relos.push(target.to_string());
+        self
+    }
+}
+
+/// Attach status of a single probe.
+#[derive(Clone)]
+pub(crate) struct ProbeStatus {
+    /// Probe, as "type:target".
+    pub(crate) probe: String,
+    /// Time it took to attach the probe.
+    pub(crate) duration: Duration,
+    /// Error, if the probe could not be attached.
+    pub(crate) error: Option<String>,
 }
 
 /// Main object representing the kernel probes and providing an API for
@@ -65,8 +140,28 @@ pub(crate) struct Kernel {
     targeted_probes: Vec<ProbeSet>,
     maps: HashMap<String, i32>,
     hooks: Vec<Hook>,
+    /// Targets for which probes are disabled; requests to probe them are
+    /// ignored.
+    disabled: HashSet<String>,
+    /// Delay between two probe attachments, to stagger them.
+    probe_delay: Duration,
+    /// Whether probes only count their hits until upgrade() is called, see
+    /// set_observe().
+    observe: bool,
+    /// Attach status of all probes, filled by attach().
+    report: Vec<ProbeStatus>,
+    /// Module of the kprobe targets part of one, see watch_modules().
+    module_targets: HashMap<String, String>,
+    /// Watcher of the modules in module_targets.
+    modules: Option<ModuleWatcher>,
     #[cfg(not(test))]
     config_map: libbpf_rs::Map,
+    /// Probe hit counters, only filled in counting mode.
+    #[cfg(not(test))]
+    hits_map: libbpf_rs::Map,
+    /// Per-probe rate limiting state, only used when a probe rate is set.
+    #[cfg(not(test))]
+    throttle_map: libbpf_rs::Map,
     pub(crate) inspect: Inspector,
 }
 
@@ -74,11 +169,32 @@ pub(crate) struct Kernel {
 pub(crate) const PROBE_MAX: usize = 128; // TODO add checks on probe registration.
 pub(super) const HOOK_MAX: usize = 10;
 
+/// Error messages (from libbpf or the kernel) of attach failures caused by the
+/// BTF of a target not matching what the program expects.
+const BTF_MISMATCH_ERRORS: &[&str] = &["BTF type ID", "BTF ID", "btf_id"];
+
+/// Maps of the probes automatically shared with their hooks.
+const HOOK_SHARED_MAPS: [&str; 2] = ["events_stats_map", "event_counters_map"];
+
+/// Probes, hooks and maps registered at a given point in time, see
+/// Kernel::snapshot() and Kernel::rollback().
+pub(crate) struct KernelSnapshot {
+    /// Targets of the generic probes, per probe type.
+    targets: Vec<HashSet<String>>,
+    /// Number of hooks of each targeted probe.
+    targeted_hooks: Vec<usize>,
+    hooks: usize,
+    maps: HashSet<String>,
+}
+
 struct ProbeSet {
     r#type: ProbeType,
     builder: Box<dyn ProbeBuilder>,
     targets: HashMap<String, TargetDesc>,
     hooks: Vec<Hook>,
+    /// Whether the builder was initialized, i.e. its programs and hooks
+    /// loaded.
+    loaded: bool,
 }
 
 impl ProbeSet {
@@ -88,20 +204,21 @@ impl ProbeSet {
             builder,
             targets: HashMap::new(),
             hooks: Vec::new(),
+            loaded: false,
         }
     }
 }
 
 impl Kernel {
     pub(crate) fn new(events: &BpfEvents) -> Result<Kernel> {
-        // Keep synced with the order of ProbeType!
-        let probes: [ProbeSet; ProbeType::Max as usize] = [
-            ProbeSet::new(ProbeType::Kprobe, Box::new(kprobe::KprobeBuilder::new())),
-            ProbeSet::new(
-                ProbeType::RawTracepoint,
-                Box::new(raw_tracepoint::RawTracepointBuilder::new()),
-            ),
-        ];
+        let probes = ProbeType::ALL.map(|r#type| {
+            let builder: Box<dyn ProbeBuilder> = match r#type {
+                ProbeType::Kprobe => Box::new(kprobe::KprobeBuilder::new()),
+                ProbeType::RawTracepoint => Box::new(raw_tracepoint::RawTracepointBuilder::new()),
+                ProbeType::Max => unreachable!("Max is not a valid probe type"),
+            };
+            ProbeSet::new(r#type, builder)
+        });
 
         // When testing the kernel object is not modified later to reuse the
         // config map is this map is hidden.
@@ -111,8 +228,18 @@ impl Kernel {
             targeted_probes: Vec::new(),
             maps: HashMap::new(),
             hooks: Vec::new(),
+            disabled: HashSet::new(),
+            probe_delay: Duration::ZERO,
+            observe: false,
+            report: Vec::new(),
+            module_targets: HashMap::new(),
+            modules: None,
             #[cfg(not(test))]
             config_map: init_config_map()?,
+            #[cfg(not(test))]
+            hits_map: init_hits_map()?,
+            #[cfg(not(test))]
+            throttle_map: init_throttle_map()?,
             inspect: Inspector::new()?,
         };
 
@@ -120,9 +247,23 @@ impl Kernel {
         kernel
             .maps
             .insert("config_map".to_string(), kernel.config_map.fd());
+        #[cfg(not(test))]
+        kernel
+            .maps
+            .insert("probe_hits_map".to_string(), kernel.hits_map.fd());
+        #[cfg(not(test))]
+        kernel
+            .maps
+            .insert("probe_throttle_map".to_string(), kernel.throttle_map.fd());
         kernel
             .maps
             .insert("events_map".to_string(), events.map_fd());
+        kernel
+            .maps
+            .insert("events_stats_map".to_string(), events.stats_map_fd());
+        kernel
+            .maps
+            .insert("event_counters_map".to_string(), events.counters_map_fd());
 
         Ok(kernel)
     }
@@ -134,10 +275,15 @@ impl Kernel {
     /// kernel.add_probe(ProbeType::RawTracepoint, "kfree_skb").unwrap();
     /// ```
     pub(crate) fn add_probe(&mut self, r#type: ProbeType, target: &str) -> Result<()> {
+        if self.is_disabled(target) {
+            return Ok(());
+        }
+
         let target = target.to_string();
+        let index = r#type.index()?;
 
         // First check if it is already in the generic probe list.
-        let set = &mut self.probes[r#type as usize];
+        let set = &mut self.probes[index];
         if set.targets.contains_key(&target) {
             return Ok(());
         }
@@ -155,7 +301,7 @@ impl Kernel {
 
         // Yes, we do it twice, because of the other mut ref for
         // self.inspect_target.
-        let set = &mut self.probes[r#type as usize];
+        let set = &mut self.probes[index];
         set.targets.insert(target, desc);
 
         Ok(())
@@ -221,11 +367,15 @@ impl Kernel {
         r#type: ProbeType,
         target: &str,
     ) -> Result<()> {
+        if self.is_disabled(target) {
+            return Ok(());
+        }
+
         let target = target.to_string();
 
         // First check if the target isn't already registered to the generic
         // probes list. If so, remove it from there.
-        let target_set = &mut self.probes[r#type as usize];
+        let target_set = &mut self.probes[r#type.index()?];
         target_set.targets.remove(&target);
 
         // Now check if we already have a targeted probe for this. If so, append
@@ -262,8 +412,146 @@ impl Kernel {
         Ok(())
     }
 
-    /// Attach all probes.
+    /// Return the probes which will be attached, as "type:target", along with
+    /// the number of hooks specific to them. Sorted, for display purposes.
+    pub(crate) fn probes(&self) -> Vec<(String, usize)> {
+        let mut probes: Vec<(String, usize)> = self
+            .probes
+            .iter()
+            .flat_map(|set| {
+                set.targets
+                    .keys()
+                    .map(|t| (format!("{}:{}", set.r#type.to_str(), t), 0))
+            })
+            .chain(self.targeted_probes.iter().flat_map(|set| {
+                set.targets
+                    .keys()
+                    .map(|t| (format!("{}:{}", set.r#type.to_str(), t), set.hooks.len()))
+            }))
+            .collect();
+        probes.sort();
+        probes
+    }
+
+    /// Return the number of probes registered so far.
+    pub(crate) fn probe_count(&self) -> usize {
+        self.probes
+            .iter()
+            .chain(self.targeted_probes.iter())
+            .map(|set| set.targets.len())
+            .sum()
+    }
+
+    /// Return the number of hooks attached to all probes.
+    pub(crate) fn generic_hooks(&self) -> usize {
+        self.hooks.len()
+    }
+
+    /// Take a snapshot of the probes, hooks and maps registered so far, for
+    /// the ones registered later to be removed using rollback().
+    pub(crate) fn snapshot(&self) -> KernelSnapshot {
+        KernelSnapshot {
+            targets: self
+                .probes
+                .iter()
+                .map(|set| set.targets.keys().cloned().collect())
+                .collect(),
+            targeted_hooks: self
+                .targeted_probes
+                .iter()
+                .map(|set| set.hooks.len())
+                .collect(),
+            hooks: self.hooks.len(),
+            maps: self.maps.keys().cloned().collect(),
+        }
+    }
+
+    /// Remove the probes, hooks and maps registered since a snapshot was
+    /// taken. This must be called before probes are loaded.
+    pub(crate) fn rollback(&mut self, snapshot: KernelSnapshot) {
+        // Generic probes turned into targeted ones (see register_hook_to())
+        // are moved back.
+        for set in self.targeted_probes.drain(snapshot.targeted_hooks.len()..) {
+            let index = set.r#type as usize;
+            for (target, desc) in set.targets.into_iter() {
+                if snapshot.targets[index].contains(&target) {
+                    self.probes[index].targets.insert(target, desc);
+                }
+            }
+        }
+        for (set, hooks) in self
+            .targeted_probes
+            .iter_mut()
+            .zip(snapshot.targeted_hooks.iter())
+        {
+            set.hooks.truncate(*hooks);
+        }
+
+        for (set, targets) in self.probes.iter_mut().zip(snapshot.targets.iter()) {
+            set.targets.retain(|target, _| targets.contains(target));
+        }
+        self.hooks.truncate(snapshot.hooks);
+        self.maps.retain(|name, _| snapshot.maps.contains(name));
+    }
+
+    /// Disable probing a target: later requests to probe it, from any
+    /// collector, are ignored. This must be called before collectors register
+    /// their probes.
+    ///
+    /// ```
+    /// kernel.disable_probe("kfree_skb_reason");
+    /// ```
+    pub(crate) fn disable_probe(&mut self, target: &str) {
+        self.disabled.insert(target.to_string());
+    }
+
+    fn is_disabled(&self, target: &str) -> bool {
+        if self.disabled.contains(target) {
+            warn!("Not probing {}: probe is disabled", target);
+            return true;
+        }
+        false
+    }
+
+    /// Load the programs and hooks of all probes in the kernel, without
+    /// attaching them. Generic programs are loaded even when no probe uses
+    /// them, as long as hooks are registered, so all hooks are verified.
+    /// Loaded probes are later attached by attach().
+    pub(crate) fn load(&mut self) -> Result<()> {
+        for set in self.probes.iter_mut() {
+            if set.targets.is_empty() && self.hooks.is_empty() {
+                continue;
+            }
+            Self::load_set(set, self.maps.clone(), order_hooks(self.hooks.clone()))?;
+        }
+
+        for set in self.targeted_probes.iter_mut() {
+            let hooks = order_hooks([set.hooks.clone(), self.hooks.clone()].concat());
+            Self::load_set(set, self.maps.clone(), hooks)?;
+        }
+        Ok(())
+    }
+
+    fn load_set(set: &mut ProbeSet, maps: HashMap<String, i32>, hooks: Vec<Hook>) -> Result<()> {
+        if set.loaded {
+            return Ok(());
+        }
+
+        // Initialize the probe builder, only once for all targets.
+        let map_fds = maps.into_iter().collect();
+        set.builder
+            .init(map_fds, hooks)
+            .or_else(|e| bail!("Could not load {} programs: {}", set.r#type.to_str(), e))?;
+        set.loaded = true;
+        Ok(())
+    }
+
+    /// Attach all probes. All probes are tried, and their status reported,
+    /// before returning an error if some could not be attached.
     pub(crate) fn attach(&mut self) -> Result<()> {
+        let start = Instant::now();
+        self.report.clear();
+
         // Take care of generic probes first.
         for set in self.probes.iter_mut() {
             Self::attach_set(
@@ -271,22 +559,292 @@ impl Kernel {
                 #[cfg(not(test))]
                 &mut self.config_map,
                 self.maps.clone(),
-                self.hooks.clone(),
+                order_hooks(self.hooks.clone()),
+                self.probe_delay,
+                self.observe,
+                &mut self.report,
             )?;
         }
 
         // Then take care of targeted probes.
         for set in self.targeted_probes.iter_mut() {
-            let hooks = [set.hooks.clone(), self.hooks.clone()].concat();
+            let hooks = order_hooks([set.hooks.clone(), self.hooks.clone()].concat());
             Self::attach_set(
                 set,
                 #[cfg(not(test))]
                 &mut self.config_map,
                 self.maps.clone(),
                 hooks,
+                self.probe_delay,
+                self.observe,
+                &mut self.report,
             )?;
         }
 
+        // Raw tracepoints can't be downgraded to a kprobe: the tracepoint
+        // iterator (__traceiter_<name>) only runs once the tracepoint is
+        // enabled, and might even be bypassed by a static call. Make the
+        // failure explicit instead.
+        for status in self.report.iter_mut() {
+            if let (true, Some(e)) = (
+                status.probe.starts_with("raw_tracepoint:"),
+                status.error.as_mut(),
+            ) {
+                if is_btf_mismatch(e) {
+                    *e = format!(
+                        "{} (the tracepoint arguments do not match what the probe expects, this kernel is not supported)",
+                        e
+                    );
+                }
+            }
+        }
+
+        let failed: Vec<&ProbeStatus> = self.report.iter().filter(|s| s.error.is_some()).collect();
+        info!(
+            "Attached {} probes in {:?}",
+            self.report.len() - failed.len(),
+            start.elapsed()
+        );
+        for status in self.report.iter() {
+            match &status.error {
+                None => info!("  {}: attached in {:?}", status.probe, status.duration),
+                Some(e) => warn!("  {}: failed: {}", status.probe, e),
+            }
+        }
+
+        if !failed.is_empty() {
+            bail!(
+                "Could not attach {} probe(s): {}",
+                failed.len(),
+                failed
+                    .iter()
+                    .map(|s| s.probe.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Watch the modules kprobe targets are part of, to pause and reattach
+    /// their probes when a module is unloaded and loaded again (see
+    /// check_modules()). Must be called once probes are attached.
+    pub(crate) fn watch_modules(&mut self) -> Result<()> {
+        self.module_targets.clear();
+        for set in self.probes.iter().chain(self.targeted_probes.iter()) {
+            if set.r#type != ProbeType::Kprobe {
+                continue;
+            }
+            for target in set.targets.keys() {
+                if let Some(module) = kernel_symbols::get_symbol_module(target)? {
+                    self.module_targets.insert(target.clone(), module);
+                }
+            }
+        }
+
+        let modules: HashSet<String> = self.module_targets.values().cloned().collect();
+        if !modules.is_empty() {
+            let mut names: Vec<&str> = modules.iter().map(|m| m.as_str()).collect();
+            names.sort();
+            info!("Watching module(s) {} for reloads", names.join(", "));
+        }
+        self.modules = Some(ModuleWatcher::new(modules)?);
+        Ok(())
+    }
+
+    /// Are modules watched, see watch_modules()?
+    #[cfg(test)]
+    pub(crate) fn watching_modules(&self) -> bool {
+        self.modules.is_some()
+    }
+
+    /// Check for watched modules being unloaded or loaded again. The probes of
+    /// an unloaded module are paused (detached) until it is loaded again, at
+    /// which point they are reattached at the new symbol addresses. Checks are
+    /// rate limited, this can be called often.
+    pub(crate) fn check_modules(&mut self) -> Result<()> {
+        let events = match &mut self.modules {
+            Some(watcher) => watcher.poll()?,
+            None => return Ok(()),
+        };
+
+        for event in events.iter() {
+            match event {
+                ModuleEvent::Unloaded(module) => {
+                    let count = self.reattach_module(module, false);
+                    warn!("Module {} was unloaded, paused {} probe(s)", module, count);
+                }
+                ModuleEvent::Loaded(module) => {
+                    // Symbols of the module moved.
+                    kernel_symbols::reload()?;
+                    let count = self.reattach_module(module, true);
+                    info!(
+                        "Module {} was loaded, reattached {} probe(s)",
+                        module, count
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Detach the kprobes on functions of a module and, if it is loaded,
+    /// attach them again. Returns the number of probes handled; failures to
+    /// reattach are logged.
+    fn reattach_module(&mut self, module: &str, loaded: bool) -> usize {
+        let mut count = 0;
+
+        for set in self
+            .probes
+            .iter_mut()
+            .chain(self.targeted_probes.iter_mut())
+        {
+            if set.r#type != ProbeType::Kprobe || !set.loaded {
+                continue;
+            }
+
+            for (target, desc) in set.targets.iter_mut() {
+                if self.module_targets.get(target).map(|m| m.as_str()) != Some(module) {
+                    continue;
+                }
+
+                // Probes were already detached if the module was seen unloaded
+                // before being loaded again.
+                let _ = set.builder.detach(target);
+                #[cfg(not(test))]
+                let _ = self.config_map.delete(&desc.ksym.to_ne_bytes());
+                if !loaded {
+                    count += 1;
+                    continue;
+                }
+
+                let mut attach = || -> Result<()> {
+                    desc.ksym = self.inspect.get_ksym(&ProbeType::Kprobe, target)?;
+                    #[cfg(not(test))]
+                    self.config_map.update(
+                        &desc.ksym.to_ne_bytes(),
+                        unsafe { plain::as_bytes(&desc.probe_cfg) },
+                        libbpf_rs::MapFlags::ANY,
+                    )?;
+                    set.builder.attach(target, desc)
+                };
+                match attach() {
+                    Ok(()) => count += 1,
+                    Err(e) => warn!("Could not reattach probe to {}: {}", target, e),
+                }
+            }
+        }
+
+        count
+    }
+
+    /// Return the attach status of all probes, once attach() was called.
+    pub(crate) fn attach_report(&self) -> &[ProbeStatus] {
+        &self.report
+    }
+
+    /// Return the number of hits of all probes, as "type:target", since they
+    /// were attached. Only available in counting mode (see
+    /// probe::common::set_count_only()). Sorted, for display purposes.
+    pub(crate) fn probe_hits(&self) -> Result<Vec<(String, u64)>> {
+        if !get_count_only() && !self.observe {
+            bail!("Probe hits are only counted in counting mode");
+        }
+
+        let mut hits = Vec::new();
+        for set in self.probes.iter().chain(self.targeted_probes.iter()) {
+            for (target, _desc) in set.targets.iter() {
+                #[cfg(not(test))]
+                let count = match self
+                    .hits_map
+                    .lookup(&_desc.ksym.to_ne_bytes(), libbpf_rs::MapFlags::ANY)?
+                {
+                    Some(val) => u64::from_ne_bytes(val[..8].try_into()?),
+                    None => 0,
+                };
+                #[cfg(test)]
+                let count = 0;
+
+                hits.push((format!("{}:{}", set.r#type.to_str(), target), count));
+            }
+        }
+        hits.sort();
+        Ok(hits)
+    }
+
+    /// Return the number of events dropped by each probe, as "type:target",
+    /// because of the per-probe rate limit (see
+    /// probe::common::set_probe_rate()). Probes without drops are not
+    /// reported. Sorted by decreasing number of drops.
+    pub(crate) fn probe_throttled(&self) -> Result<Vec<(String, u64)>> {
+        let mut dropped = Vec::new();
+        if get_probe_rate() == 0 {
+            return Ok(dropped);
+        }
+
+        for set in self.probes.iter().chain(self.targeted_probes.iter()) {
+            for (target, _desc) in set.targets.iter() {
+                #[cfg(not(test))]
+                let count = match self
+                    .throttle_map
+                    .lookup(&_desc.ksym.to_ne_bytes(), libbpf_rs::MapFlags::ANY)?
+                {
+                    Some(val) => {
+                        let mut throttle = ProbeThrottle::default();
+                        plain::copy_from_bytes(&mut throttle, &val)
+                            .or_else(|_| bail!("Could not parse the probe throttle state"))?;
+                        throttle.dropped
+                    }
+                    None => 0,
+                };
+                #[cfg(test)]
+                let count = 0;
+
+                if count > 0 {
+                    dropped.push((format!("{}:{}", set.r#type.to_str(), target), count));
+                }
+            }
+        }
+        dropped.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(dropped)
+    }
+
+    /// Wait for `delay` between two probe attachments, to avoid attaching
+    /// all of them at once on sensitive systems.
+    pub(crate) fn set_probe_delay(&mut self, delay: Duration) {
+        self.probe_delay = delay;
+    }
+
+    /// Attach probes in an observation phase: as in counting mode they only
+    /// count their hits (see probe_hits()), but their hooks are loaded so they
+    /// can later be enabled in place by upgrade(), without reloading them.
+    pub(crate) fn set_observe(&mut self) {
+        self.observe = true;
+    }
+
+    /// End the observation phase (see set_observe()): probes start running
+    /// their hooks and reporting events.
+    pub(crate) fn upgrade(&mut self) -> Result<()> {
+        if !self.observe {
+            return Ok(());
+        }
+
+        for set in self
+            .probes
+            .iter_mut()
+            .chain(self.targeted_probes.iter_mut())
+        {
+            for desc in set.targets.values_mut() {
+                desc.probe_cfg.count_hits = 0;
+                #[cfg(not(test))]
+                self.config_map.update(
+                    &desc.ksym.to_ne_bytes(),
+                    unsafe { plain::as_bytes(&desc.probe_cfg) },
+                    libbpf_rs::MapFlags::ANY,
+                )?;
+            }
+        }
+        self.observe = false;
         Ok(())
     }
 
@@ -295,34 +853,76 @@ impl Kernel {
         #[cfg(not(test))] config_map: &mut libbpf_rs::Map,
         maps: HashMap<String, i32>,
         hooks: Vec<Hook>,
+        delay: Duration,
+        observe: bool,
+        report: &mut Vec<ProbeStatus>,
     ) -> Result<()> {
         if set.targets.is_empty() {
             return Ok(());
         }
 
-        // Initialize the probe builder, only once for all targets.
-        let map_fds = maps.into_iter().collect();
-        set.builder.init(map_fds, hooks)?;
+        // Hooks do not run in counting mode, do not bother loading them.
+        let hooks = match get_count_only() {
+            true => Vec::new(),
+            false => hooks,
+        };
+        Self::load_set(set, maps, hooks)?;
 
         // Then handle all targets in the set.
-        for (target, desc) in set.targets.iter() {
-            // First load the probe configuration.
-            #[cfg(not(test))]
-            let config = unsafe { plain::as_bytes(&desc.probe_cfg) };
-            #[cfg(not(test))]
-            config_map.update(
-                &desc.ksym.to_ne_bytes(),
-                config,
-                libbpf_rs::MapFlags::NO_EXIST,
-            )?;
+        for (target, desc) in set.targets.iter_mut() {
+            desc.probe_cfg.count_hits = observe as u8;
 
-            // Finally attach a probe to the target.
-            info!("Attaching probe to {}", target);
-            set.builder.attach(target, desc)?;
+            // Stagger attachments, if requested.
+            if !report.is_empty() && !delay.is_zero() {
+                thread::sleep(delay);
+            }
+
+            let start = Instant::now();
+            let error = Self::attach_target(
+                set.builder.as_mut(),
+                #[cfg(not(test))]
+                config_map,
+                target,
+                desc,
+            )
+            .err()
+            .map(|e| e.to_string());
+
+            report.push(ProbeStatus {
+                probe: format!("{}:{}", set.r#type.to_str(), target),
+                duration: start.elapsed(),
+                error,
+            });
         }
 
         Ok(())
     }
+
+    fn attach_target(
+        builder: &mut dyn ProbeBuilder,
+        #[cfg(not(test))] config_map: &mut libbpf_rs::Map,
+        target: &str,
+        desc: &TargetDesc,
+    ) -> Result<()> {
+        // First load the probe configuration.
+        #[cfg(not(test))]
+        let config = unsafe { plain::as_bytes(&desc.probe_cfg) };
+        #[cfg(not(test))]
+        config_map.update(
+            &desc.ksym.to_ne_bytes(),
+            config,
+            libbpf_rs::MapFlags::NO_EXIST,
+        )?;
+
+        // Finally attach a probe to the target.
+        info!("Attaching probe to {}", target);
+        builder.attach(target, desc)
+    }
+}
+
+/// Check if a probe attach error is caused by a BTF mismatch.
+fn is_btf_mismatch(error: &str) -> bool {
+    BTF_MISMATCH_ERRORS.iter().any(|e| error.contains(e))
 }
 
 /// Trait representing the interface used to create and handle probes. We use a
@@ -339,6 +939,8 @@ pub(super) trait ProbeBuilder {
     fn init(&mut self, map_fds: Vec<(String, i32)>, hooks: Vec<Hook>) -> Result<()>;
     /// Attach a probe to a given target (function, tracepoint, etc).
     fn attach(&mut self, target: &str, desc: &TargetDesc) -> Result<()>;
+    /// Detach the probe of a given target, previously attached.
+    fn detach(&mut self, target: &str) -> Result<()>;
 }
 
 pub(super) fn reuse_map_fds(
@@ -354,7 +956,24 @@ pub(super) fn reuse_map_fds(
     Ok(())
 }
 
-pub(super) fn replace_hooks(fd: i32, hooks: &[Hook]) -> Result<Vec<libbpf_rs::Link>> {
+/// Order hooks by priority, keeping the registration order of hooks of the
+/// same priority.
+fn order_hooks(mut hooks: Vec<Hook>) -> Vec<Hook> {
+    // sort_by_key is stable.
+    hooks.sort_by_key(|h| h.priority);
+    hooks
+}
+
+/// Replace the hook placeholders of a loaded probe program with actual hooks.
+/// `name` identifies the probe, e.g. "raw_tracepoint:kfree_skb", and is used
+/// when dumping the hooks. Maps shared with hooks (HOOK_SHARED_MAPS) are
+/// reused from the probe ones, when the hook defines them.
+pub(super) fn replace_hooks(
+    fd: i32,
+    hooks: &[Hook],
+    map_fds: &[(String, i32)],
+    name: &str,
+) -> Result<Vec<libbpf_rs::Link>> {
     let mut links = Vec::new();
 
     for (i, hook) in hooks.iter().enumerate() {
@@ -365,8 +984,16 @@ pub(super) fn replace_hooks(fd: i32, hooks: &[Hook]) -> Result<Vec<libbpf_rs::Li
 
         // We have to explicitly use a Vec below to avoid having an unknown size
         // at build time.
-        let map_fds: Vec<(String, i32)> = hook.maps.clone().into_iter().collect();
-        reuse_map_fds(&open_obj, &map_fds)?;
+        let mut maps: Vec<(String, i32)> = hook.maps.clone().into_iter().collect();
+        for (map, fd) in map_fds.iter() {
+            if HOOK_SHARED_MAPS.contains(&map.as_str())
+                && !hook.maps.contains_key(map)
+                && open_obj.map(map).is_some()
+            {
+                maps.push((map.clone(), *fd));
+            }
+        }
+        reuse_map_fds(&open_obj, &maps)?;
 
         let open_prog = open_obj
             .prog_mut("hook")
@@ -375,12 +1002,33 @@ pub(super) fn replace_hooks(fd: i32, hooks: &[Hook]) -> Result<Vec<libbpf_rs::Li
         open_prog.set_prog_type(libbpf_rs::ProgramType::Ext);
         open_prog.set_attach_target(fd, Some(target))?;
 
-        let mut obj = open_obj.load()?;
-        links.push(
-            obj.prog_mut("hook")
-                .ok_or_else(|| anyhow!("Couldn't get hook program"))?
-                .attach_trace()?,
-        );
+        let mut obj = match relo::load_capture(|| Ok(open_obj.load()?)) {
+            Ok(obj) => obj,
+            Err((e, failures)) => {
+                if !failures.is_empty()
+                    && failures
+                        .iter()
+                        .all(|f| hook.optional_relos.iter().any(|o| f.matches(o)))
+                {
+                    warn!(
+                        "Skipping hook {}, optional CO-RE relocation(s) not matching the running kernel: {}",
+                        i,
+                        failures
+                            .iter()
+                            .map(|f| f.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                    continue;
+                }
+                return Err(relo::with_failures(e, &failures));
+            }
+        };
+        let prog = obj
+            .prog_mut("hook")
+            .ok_or_else(|| anyhow!("Couldn't get hook program"))?;
+        dump::dump_prog(prog.fd(), &format!("{}.hook{}", name, i));
+        links.push(prog.attach_trace()?);
     }
 
     Ok(links)
@@ -388,11 +1036,23 @@ pub(super) fn replace_hooks(fd: i32, hooks: &[Hook]) -> Result<Vec<libbpf_rs::Li
 
 #[cfg(test)]
 mod tests {
+    use std::{mem, net::UdpSocket};
+
     use super::*;
+    use crate::core::probe::kernel::config::{init_config_map, ProbeConfig};
 
     // Dummy hook.
     const HOOK: &[u8] = &[0];
 
+    // Test hooks counting their runs in hook_test_map, the first discarding
+    // all events.
+    mod discard_hook {
+        include!("bpf/test/.out/discard_hook.rs");
+    }
+    mod count_hook {
+        include!("bpf/test/.out/count_hook.rs");
+    }
+
     #[test]
     fn add_probe() {
         let events = BpfEvents::new().unwrap();
@@ -412,6 +1072,25 @@ mod tests {
             .is_ok());
     }
 
+    #[test]
+    fn probe_types() {
+        let events = BpfEvents::new().unwrap();
+        let mut kernel = Kernel::new(&events).unwrap();
+
+        // Probe types are contiguous and each has its probe set.
+        for (i, r#type) in ProbeType::ALL.iter().enumerate() {
+            assert!(r#type.index().unwrap() == i);
+            assert!(kernel.probes[i].r#type == *r#type);
+            assert!(r#type.to_str() != ProbeType::Max.to_str());
+        }
+
+        assert!(ProbeType::Max.index().is_err());
+        assert!(kernel.add_probe(ProbeType::Max, "consume_skb").is_err());
+        assert!(kernel
+            .register_hook_to(Hook::from(HOOK), ProbeType::Max, "consume_skb")
+            .is_err());
+    }
+
     #[test]
     fn register_hooks() {
         let events = BpfEvents::new().unwrap();
@@ -457,6 +1136,292 @@ mod tests {
             .is_err());
     }
 
+    #[test]
+    fn hooks_order() {
+        static FIRST: &[u8] = &[1];
+        static SECOND: &[u8] = &[2];
+        static THIRD: &[u8] = &[3];
+        static FOURTH: &[u8] = &[4];
+
+        let hooks = order_hooks(vec![
+            Hook::from(THIRD),
+            Hook::from(SECOND).priority(-1).to_owned(),
+            Hook::from(FOURTH).priority(1).to_owned(),
+            Hook::from(FIRST).priority(-10).to_owned(),
+        ]);
+        assert!(hooks.iter().map(|h| h.bpf_prog[0]).collect::<Vec<_>>() == vec![1, 2, 3, 4]);
+
+        // Hooks of the same priority keep their registration order.
+        let hooks = order_hooks(vec![
+            Hook::from(SECOND),
+            Hook::from(FIRST),
+            Hook::from(THIRD).priority(1).to_owned(),
+        ]);
+        assert!(hooks.iter().map(|h| h.bpf_prog[0]).collect::<Vec<_>>() == vec![2, 1, 3]);
+    }
+
+    // Run the discarding and counting test hooks on skb:kfree_skb, with the
+    // given priorities, and return their number of runs.
+    fn run_hooks(discard: i32, count: i32) -> Result<(u64, u64)> {
+        let opts = libbpf_sys::bpf_map_create_opts {
+            sz: mem::size_of::<libbpf_sys::bpf_map_create_opts>() as libbpf_sys::size_t,
+            ..Default::default()
+        };
+        let runs = libbpf_rs::Map::create(
+            libbpf_rs::MapType::Array,
+            Some("hook_test_map"),
+            mem::size_of::<u32>() as u32,
+            mem::size_of::<u64>() as u32,
+            2,
+            &opts,
+        )?;
+
+        // The config map is not set up by the kernel object when testing.
+        let mut config_map = init_config_map()?;
+        config_map.update(
+            &0u64.to_ne_bytes(),
+            unsafe { plain::as_bytes(&ProbeConfig::default()) },
+            libbpf_rs::MapFlags::ANY,
+        )?;
+
+        let mut hooks = vec![
+            Hook::from(discard_hook::DATA).priority(discard).to_owned(),
+            Hook::from(count_hook::DATA).priority(count).to_owned(),
+        ];
+        for hook in hooks.iter_mut() {
+            hook.reuse_map("hook_test_map", runs.fd())?;
+        }
+
+        let mut builder = raw_tracepoint::RawTracepointBuilder::new();
+        builder.init(
+            vec![("config_map".to_string(), config_map.fd())],
+            order_hooks(hooks),
+        )?;
+        builder.attach("skb:kfree_skb", &TargetDesc::default())?;
+
+        // Packets sent to a closed port are dropped, using kfree_skb.
+        let closed = UdpSocket::bind("127.0.0.1:0")?.local_addr()?;
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        for _ in 0..10 {
+            socket.send_to(b"packet-tracer", closed)?;
+        }
+        builder.detach("skb:kfree_skb")?;
+
+        let get = |index: u32| -> Result<u64> {
+            let val = runs
+                .lookup(&index.to_ne_bytes(), libbpf_rs::MapFlags::ANY)?
+                .ok_or_else(|| anyhow!("No run count for hook {}", index))?;
+            Ok(u64::from_ne_bytes(val[..8].try_into()?))
+        };
+        Ok((get(0)?, get(1)?))
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "test_cap_bpf"), ignore)]
+    fn hooks_discard() -> Result<()> {
+        // A hook discarding the event short-circuits the hooks following it.
+        let (discarded, counted) = run_hooks(-1, 0)?;
+        assert!(discarded > 0 && counted == 0);
+
+        // Hooks running before it are not affected.
+        let (discarded, counted) = run_hooks(1, 0)?;
+        assert!(discarded > 0 && counted > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn disable_probe() {
+        let events = BpfEvents::new().unwrap();
+        let mut kernel = Kernel::new(&events).unwrap();
+
+        kernel.disable_probe("kfree_skb_reason");
+        assert!(kernel
+            .add_probe(ProbeType::Kprobe, "kfree_skb_reason")
+            .is_ok());
+        assert!(kernel
+            .register_hook_to(Hook::from(HOOK), ProbeType::Kprobe, "kfree_skb_reason")
+            .is_ok());
+        assert!(kernel.add_probe(ProbeType::Kprobe, "consume_skb").is_ok());
+
+        assert!(kernel.probes[ProbeType::Kprobe as usize].targets.len() == 1);
+        assert!(kernel.targeted_probes.is_empty());
+    }
+
+    #[test]
+    fn probes() {
+        let events = BpfEvents::new().unwrap();
+        let mut kernel = Kernel::new(&events).unwrap();
+
+        kernel.add_probe(ProbeType::Kprobe, "consume_skb").unwrap();
+        kernel
+            .register_hook_to(Hook::from(HOOK), ProbeType::Kprobe, "kfree_skb_reason")
+            .unwrap();
+        kernel.register_hook(Hook::from(HOOK)).unwrap();
+
+        assert!(
+            kernel.probes()
+                == vec![
+                    ("kprobe:consume_skb".to_string(), 0),
+                    ("kprobe:kfree_skb_reason".to_string(), 1)
+                ]
+        );
+        assert!(kernel.generic_hooks() == 1);
+    }
+
+    #[test]
+    fn rollback() {
+        let events = BpfEvents::new().unwrap();
+        let mut kernel = Kernel::new(&events).unwrap();
+
+        kernel.add_probe(ProbeType::Kprobe, "consume_skb").unwrap();
+        kernel
+            .add_probe(ProbeType::Kprobe, "kfree_skb_reason")
+            .unwrap();
+        kernel.register_hook(Hook::from(HOOK)).unwrap();
+        let probes = kernel.probes();
+        let maps = kernel.maps.len();
+
+        let snapshot = kernel.snapshot();
+        kernel
+            .add_probe(ProbeType::RawTracepoint, "skb:kfree_skb")
+            .unwrap();
+        kernel
+            .register_hook_to(Hook::from(HOOK), ProbeType::Kprobe, "kfree_skb_reason")
+            .unwrap();
+        kernel.register_hook(Hook::from(HOOK)).unwrap();
+        kernel.reuse_map("dummy_map", 0).unwrap();
+        kernel.rollback(snapshot);
+
+        // Probes turned into targeted ones are generic again.
+        assert!(kernel.probes() == probes);
+        assert!(kernel.targeted_probes.is_empty());
+        assert!(kernel.generic_hooks() == 1);
+        assert!(kernel.maps.len() == maps);
+    }
+
+    // Probe builder failing to attach to a given target.
+    struct FailingBuilder(&'static str);
+
+    impl ProbeBuilder for FailingBuilder {
+        fn new() -> Self {
+            FailingBuilder("")
+        }
+
+        fn init(&mut self, _: Vec<(String, i32)>, _: Vec<Hook>) -> Result<()> {
+            Ok(())
+        }
+
+        fn attach(&mut self, target: &str, _: &TargetDesc) -> Result<()> {
+            if target == self.0 {
+                bail!("Deliberate failure");
+            }
+            Ok(())
+        }
+
+        fn detach(&mut self, _: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn attach_report() {
+        let events = BpfEvents::new().unwrap();
+        let mut kernel = Kernel::new(&events).unwrap();
+        kernel.probes[ProbeType::Kprobe as usize] =
+            ProbeSet::new(ProbeType::Kprobe, Box::new(FailingBuilder("consume_skb")));
+
+        kernel.add_probe(ProbeType::Kprobe, "consume_skb").unwrap();
+        kernel
+            .add_probe(ProbeType::Kprobe, "kfree_skb_reason")
+            .unwrap();
+
+        // All probes are tried, the failing one is reported.
+        assert!(kernel.attach().is_err());
+
+        let mut report = kernel.attach_report().to_vec();
+        report.sort_by(|a, b| a.probe.cmp(&b.probe));
+        assert!(report.len() == 2);
+        assert!(report[0].probe == "kprobe:consume_skb");
+        assert!(report[0].error.as_deref() == Some("Deliberate failure"));
+        assert!(report[1].probe == "kprobe:kfree_skb_reason");
+        assert!(report[1].error.is_none());
+    }
+
+    // Probe builder failing as if the BTF of all targets did not match.
+    struct BtfMismatchBuilder;
+
+    impl ProbeBuilder for BtfMismatchBuilder {
+        fn new() -> Self {
+            BtfMismatchBuilder
+        }
+
+        fn init(&mut self, _: Vec<(String, i32)>, _: Vec<Hook>) -> Result<()> {
+            Ok(())
+        }
+
+        fn attach(&mut self, target: &str, _: &TargetDesc) -> Result<()> {
+            bail!("failed to find kernel BTF type ID of '{}': -3", target);
+        }
+
+        fn detach(&mut self, _: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn btf_mismatch() {
+        let events = BpfEvents::new().unwrap();
+        let mut kernel = Kernel::new(&events).unwrap();
+        kernel.probes[ProbeType::RawTracepoint as usize] =
+            ProbeSet::new(ProbeType::RawTracepoint, Box::new(BtfMismatchBuilder));
+
+        kernel
+            .add_probe(ProbeType::RawTracepoint, "skb:kfree_skb")
+            .unwrap();
+
+        // The raw tracepoint is not silently replaced by a probe which would
+        // never fire, but reported as failed with an explicit error.
+        assert!(kernel.attach().is_err());
+        let report = kernel.attach_report();
+        assert!(report.len() == 1);
+        assert!(report[0].probe == "raw_tracepoint:skb:kfree_skb");
+        assert!(report[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("this kernel is not supported"));
+        assert!(kernel.probes[ProbeType::Kprobe as usize].targets.is_empty());
+
+        assert!(is_btf_mismatch(
+            "failed to find kernel BTF type ID of 'kfree_skb': -3"
+        ));
+        assert!(!is_btf_mismatch("Deliberate failure"));
+    }
+
+    #[test]
+    fn observe() {
+        let events = BpfEvents::new().unwrap();
+        let mut kernel = Kernel::new(&events).unwrap();
+        kernel.probes[ProbeType::Kprobe as usize] =
+            ProbeSet::new(ProbeType::Kprobe, Box::new(FailingBuilder("")));
+        kernel.add_probe(ProbeType::Kprobe, "consume_skb").unwrap();
+
+        // Probes only count their hits until upgraded.
+        kernel.set_observe();
+        assert!(kernel.attach().is_ok());
+        assert!(kernel.probe_hits().is_ok());
+        let count_hits = |kernel: &Kernel| {
+            kernel.probes[ProbeType::Kprobe as usize].targets["consume_skb"]
+                .probe_cfg
+                .count_hits
+        };
+        assert!(count_hits(&kernel) == 1);
+
+        assert!(kernel.upgrade().is_ok());
+        assert!(count_hits(&kernel) == 0);
+        assert!(kernel.probe_hits().is_err());
+    }
+
     #[test]
     fn reuse_map() {
         let events = BpfEvents::new().unwrap();
@@ -4,10 +4,19 @@
 //! in two parts, the Rust code (here) and the eBPF one (bpf/kprobe.bpf.c and
 //! its auto-generated part in bpf/.out/).
 
+use std::{collections::HashMap, fs, io, mem};
+
 use anyhow::{anyhow, bail, Result};
+use nix::libc;
 
-use super::{inspect::TargetDesc, *};
-use crate::core::probe::get_ebpf_debug;
+use super::{dump, inspect::TargetDesc, relo, *};
+use crate::core::{
+    kernel_symbols,
+    probe::{
+        get_clock_tai, get_count_events, get_count_only, get_ebpf_debug, get_exec_context,
+        get_pid_filter, get_probe_rate,
+    },
+};
 
 mod kprobe_bpf {
     include!("bpf/.out/kprobe.skel.rs");
@@ -17,7 +26,10 @@ use kprobe_bpf::KprobeSkelBuilder;
 #[derive(Default)]
 pub(super) struct KprobeBuilder {
     obj: Option<libbpf_rs::Object>,
-    links: Vec<libbpf_rs::Link>,
+    /// Links of the hooks, shared by all targets.
+    hook_links: Vec<libbpf_rs::Link>,
+    /// Links of the probes, by target.
+    links: HashMap<String, libbpf_rs::Link>,
 }
 
 impl ProbeBuilder for KprobeBuilder {
@@ -34,35 +46,116 @@ impl ProbeBuilder for KprobeBuilder {
         skel.obj_builder.debug(get_ebpf_debug());
         let mut skel = skel.open()?;
         skel.rodata().nhooks = hooks.len() as u32;
+        skel.rodata().pid_filter = get_pid_filter() as u8;
+        skel.rodata().clock_tai = get_clock_tai() as u8;
+        skel.rodata().exec_context = get_exec_context() as u8;
+        skel.rodata().count_only = get_count_only() as u8;
+        skel.rodata().count_events = get_count_events() as u8;
+        skel.rodata().probe_rate = get_probe_rate();
 
         let open_obj = skel.obj;
         reuse_map_fds(&open_obj, &map_fds)?;
 
-        let obj = open_obj.load()?;
+        let obj = relo::load_with_report(|| Ok(open_obj.load()?))?;
         let fd = obj
             .prog("probe_kprobe")
             .ok_or_else(|| anyhow!("Couldn't get program"))?
             .fd();
-        let mut links = replace_hooks(fd, &hooks)?;
-        self.links.append(&mut links);
+        // The program and its hooks are shared by all kprobes of the set.
+        let mut links = replace_hooks(fd, &hooks, &map_fds, "kprobe")?;
+        self.hook_links.append(&mut links);
 
         self.obj = Some(obj);
         Ok(())
     }
 
-    fn attach(&mut self, target: &str, _: &TargetDesc) -> Result<()> {
+    fn attach(&mut self, target: &str, desc: &TargetDesc) -> Result<()> {
         let obj = match &mut self.obj {
             Some(obj) => obj,
             _ => bail!("Kprobe builder is uninitialized"),
         };
 
-        self.links.push(
-            obj.prog_mut("probe_kprobe")
-                .ok_or_else(|| anyhow!("Couldn't get program"))?
-                .attach_kprobe(false, target)?,
-        );
+        let prog = obj
+            .prog_mut("probe_kprobe")
+            .ok_or_else(|| anyhow!("Couldn't get program"))?;
+        dump::dump_prog(prog.fd(), &format!("kprobe:{}.probe_kprobe", target));
+        // Ambiguous symbols are attached using the address of the candidate
+        // they were resolved to, the kernel would otherwise pick one or refuse.
+        let link = match kernel_symbols::attach_by_address(target)? {
+            true => attach_addr(prog, desc.ksym)?,
+            false => prog.attach_kprobe(false, target)?,
+        };
+        self.links.insert(target.to_string(), link);
         Ok(())
     }
+
+    fn detach(&mut self, target: &str) -> Result<()> {
+        match self.links.remove(target) {
+            Some(_) => Ok(()),
+            None => bail!("No kprobe attached to {}", target),
+        }
+    }
+}
+
+/// Start of struct perf_event_attr, up to config2 (PERF_ATTR_SIZE_VER1), which
+/// is all a kprobe perf event needs.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    r#type: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    /// kprobe_func, unused when attaching by address.
+    config1: u64,
+    /// kprobe_addr.
+    config2: u64,
+}
+
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+/// Attach a kprobe program to a kernel address, using a kprobe perf event.
+fn attach_addr(prog: &mut libbpf_rs::Program, addr: u64) -> Result<libbpf_rs::Link> {
+    let pmu = fs::read_to_string("/sys/bus/event_source/devices/kprobe/type")
+        .or_else(|e| bail!("Could not get the kprobe PMU type: {}", e))?;
+    let attr = PerfEventAttr {
+        r#type: pmu.trim().parse()?,
+        size: mem::size_of::<PerfEventAttr>() as u32,
+        config2: addr,
+        ..Default::default()
+    };
+
+    // Safety: attr is a valid perf_event_attr of the size it advertises, which
+    // outlives the call. Kprobes are system wide: no pid, any cpu.
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const PerfEventAttr,
+            -1,
+            0,
+            -1,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        bail!(
+            "Could not open a kprobe at {:#x}: {}",
+            addr,
+            io::Error::last_os_error()
+        );
+    }
+
+    // The link owns the perf event once attached.
+    prog.attach_perf_event(fd as i32).or_else(|e| {
+        // Safety: fd was opened above and is not used elsewhere.
+        unsafe { libc::close(fd as i32) };
+        bail!("Could not attach a kprobe at {:#x}: {}", addr, e)
+    })
 }
 
 #[cfg(test)]
@@ -82,4 +175,10 @@ mod tests {
         assert!(builder.attach("consume_skb", &desc).is_ok());
         assert!(builder.attach("foobar", &desc).is_err());
     }
+
+    #[test]
+    fn perf_event_attr() {
+        // PERF_ATTR_SIZE_VER1.
+        assert!(mem::size_of::<PerfEventAttr>() == 72);
+    }
 }
@@ -17,6 +17,13 @@
 //!
 //! 2. Targeted hooks, attached to a specific probe, using the
 //!    register_hook_to() API.
+//!
+//! The softirq tracker (see softirq.rs) complements the probes when events
+//! report their execution context.
+//!
+//! Kprobes on module functions are paused while their module is unloaded and
+//! reattached once it is loaded again, see watch_modules() and
+//! check_modules().
 
 // Re-export kernel.rs
 #[allow(clippy::module_inception)]
@@ -24,6 +31,10 @@ pub(crate) mod kernel;
 pub(crate) use kernel::*;
 
 mod config;
+pub(crate) mod dump;
 mod inspect;
 mod kprobe;
+mod modules;
 mod raw_tracepoint;
+pub(crate) mod relo;
+pub(crate) mod softirq;
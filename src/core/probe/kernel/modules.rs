@@ -0,0 +1,156 @@
+//! # Kernel modules
+//!
+//! Kprobes on functions of a module do not survive it being unloaded: they
+//! stop firing and are not re-armed once the module is loaded again, at a
+//! different address. Modules targeted by probes are watched by polling
+//! /proc/modules to detect unloads and reloads, a module instance being
+//! identified by its load address. Raw tracepoints hold a reference on their
+//! module, which can't be unloaded while they are attached.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+
+/// Interval at which the loaded modules are checked.
+const MODULES_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Change in the state of a watched module.
+#[derive(Debug, Eq, PartialEq)]
+pub(super) enum ModuleEvent {
+    Unloaded(String),
+    Loaded(String),
+}
+
+pub(super) struct ModuleWatcher {
+    /// Watched modules and their load address, None when not loaded.
+    modules: HashMap<String, Option<String>>,
+    /// Last time the loaded modules were checked.
+    last_check: Instant,
+}
+
+impl ModuleWatcher {
+    pub(super) fn new(modules: HashSet<String>) -> Result<ModuleWatcher> {
+        let mut watcher = ModuleWatcher {
+            modules: modules.into_iter().map(|m| (m, None)).collect(),
+            last_check: Instant::now(),
+        };
+        watcher.update(&loaded_modules()?);
+        Ok(watcher)
+    }
+
+    /// Check for changes in the watched modules. Checks are rate limited,
+    /// no event is reported between them.
+    pub(super) fn poll(&mut self) -> Result<Vec<ModuleEvent>> {
+        if self.modules.is_empty() || self.last_check.elapsed() < MODULES_CHECK_INTERVAL {
+            return Ok(Vec::new());
+        }
+        self.last_check = Instant::now();
+
+        Ok(self.update(&loaded_modules()?))
+    }
+
+    /// Update the state of the watched modules given the loaded ones. A module
+    /// found at a different address was reloaded and is reported as unloaded
+    /// then loaded.
+    fn update(&mut self, loaded: &HashMap<String, String>) -> Vec<ModuleEvent> {
+        let mut events = Vec::new();
+
+        for (module, addr) in self.modules.iter_mut() {
+            let current = loaded.get(module);
+            if addr.as_ref() == current {
+                continue;
+            }
+
+            if addr.is_some() {
+                events.push(ModuleEvent::Unloaded(module.clone()));
+            }
+            if current.is_some() {
+                events.push(ModuleEvent::Loaded(module.clone()));
+            }
+            *addr = current.cloned();
+        }
+
+        events
+    }
+}
+
+/// Retrieve the modules currently loaded.
+fn loaded_modules() -> Result<HashMap<String, String>> {
+    match fs::read_to_string("/proc/modules") {
+        Ok(content) => parse_modules(&content),
+        Err(e) => bail!("Could not read /proc/modules: {}", e),
+    }
+}
+
+/// Parse the content of /proc/modules, returning the live modules and their
+/// load address. Modules being loaded or unloaded are left out.
+fn parse_modules(content: &str) -> Result<HashMap<String, String>> {
+    let mut modules = HashMap::new();
+
+    for line in content.lines() {
+        // name size refcount deps state address [taint]
+        let data: Vec<&str> = line.split_whitespace().collect();
+        if data.len() < 6 {
+            bail!("Invalid /proc/modules line: {}", line);
+        }
+
+        if data[4] == "Live" {
+            modules.insert(data[0].to_string(), data[5].to_string());
+        }
+    }
+
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modules() -> Result<()> {
+        let loaded = parse_modules(
+            "openvswitch 192512 2 - Live 0xffffffffc0a00000\n\
+             nf_conntrack 188416 1 openvswitch, Live 0xffffffffc0900000\n\
+             veth 36864 0 - Unloading 0xffffffffc0800000 (E)",
+        )?;
+        assert!(loaded.len() == 2);
+        assert!(loaded.get("openvswitch") == Some(&"0xffffffffc0a00000".to_string()));
+        assert!(parse_modules("openvswitch 192512").is_err());
+
+        let mut watcher = ModuleWatcher {
+            modules: HashMap::from([
+                ("openvswitch".to_string(), None),
+                ("nf_conntrack".to_string(), None),
+            ]),
+            last_check: Instant::now(),
+        };
+        watcher.update(&loaded);
+
+        // Unchanged.
+        assert!(watcher.update(&loaded).is_empty());
+
+        // Unloaded, then loaded again.
+        let mut reloaded = loaded.clone();
+        reloaded.remove("openvswitch");
+        assert!(
+            watcher.update(&reloaded) == vec![ModuleEvent::Unloaded("openvswitch".to_string())]
+        );
+        reloaded.insert("openvswitch".to_string(), "0xffffffffc0b00000".to_string());
+        assert!(watcher.update(&reloaded) == vec![ModuleEvent::Loaded("openvswitch".to_string())]);
+
+        // Reloaded between two checks.
+        reloaded.insert("nf_conntrack".to_string(), "0xffffffffc0c00000".to_string());
+        assert!(
+            watcher.update(&reloaded)
+                == vec![
+                    ModuleEvent::Unloaded("nf_conntrack".to_string()),
+                    ModuleEvent::Loaded("nf_conntrack".to_string()),
+                ]
+        );
+        Ok(())
+    }
+}
@@ -5,10 +5,15 @@
 //! in two parts, the Rust code (here) and the eBPF one
 //! (bpf/raw_tracepoint.bpf.c and its auto-generated part in bpf/.out/).
 
+use std::collections::HashMap;
+
 use anyhow::{anyhow, bail, Result};
 
-use super::{inspect::TargetDesc, *};
-use crate::core::probe::get_ebpf_debug;
+use super::{dump, inspect::TargetDesc, relo, *};
+use crate::core::probe::{
+    get_clock_tai, get_count_events, get_count_only, get_ebpf_debug, get_exec_context,
+    get_pid_filter, get_probe_rate,
+};
 
 mod raw_tracepoint_bpf {
     include!("bpf/.out/raw_tracepoint.skel.rs");
@@ -17,7 +22,9 @@ use raw_tracepoint_bpf::RawTracepointSkelBuilder;
 
 #[derive(Default)]
 pub(super) struct RawTracepointBuilder {
-    links: Vec<libbpf_rs::Link>,
+    /// Links of the probes and of their hooks, by target. Each raw tracepoint
+    /// has its own program and hooks.
+    links: HashMap<String, Vec<libbpf_rs::Link>>,
     map_fds: Vec<(String, i32)>,
     hooks: Vec<Hook>,
 }
@@ -33,11 +40,11 @@ impl ProbeBuilder for RawTracepointBuilder {
         Ok(())
     }
 
-    fn attach(&mut self, target: &str, desc: &TargetDesc) -> Result<()> {
+    fn attach(&mut self, full_target: &str, desc: &TargetDesc) -> Result<()> {
         // Raw tracepoints should have a group:target format.
-        let target = match target.split_once(':') {
+        let target = match full_target.split_once(':') {
             Some((_, tgt)) => tgt,
-            None => bail!("Invalid tracepoint format for {}", target),
+            None => bail!("Invalid tracepoint format for {}", full_target),
         };
 
         let mut skel = RawTracepointSkelBuilder::default();
@@ -47,21 +54,36 @@ impl ProbeBuilder for RawTracepointBuilder {
         skel.rodata().ksym = desc.ksym;
         skel.rodata().nargs = desc.nargs;
         skel.rodata().nhooks = self.hooks.len() as u32;
+        skel.rodata().pid_filter = get_pid_filter() as u8;
+        skel.rodata().clock_tai = get_clock_tai() as u8;
+        skel.rodata().exec_context = get_exec_context() as u8;
+        skel.rodata().count_only = get_count_only() as u8;
+        skel.rodata().count_events = get_count_events() as u8;
+        skel.rodata().probe_rate = get_probe_rate();
 
         let open_obj = skel.obj;
         reuse_map_fds(&open_obj, &self.map_fds)?;
 
-        let mut obj = open_obj.load()?;
+        let mut obj = relo::load_with_report(|| Ok(open_obj.load()?))?;
         let prog = obj
             .prog_mut("probe_raw_tracepoint")
             .ok_or_else(|| anyhow!("Couldn't get program"))?;
 
-        let mut links = replace_hooks(prog.fd(), &self.hooks)?;
-        self.links.append(&mut links);
+        let name = format!("raw_tracepoint:{}", target);
+        dump::dump_prog(prog.fd(), &format!("{}.probe_raw_tracepoint", name));
+        let mut links = replace_hooks(prog.fd(), &self.hooks, &self.map_fds, &name)?;
+        links.push(prog.attach_raw_tracepoint(target)?);
 
-        self.links.push(prog.attach_raw_tracepoint(target)?);
+        self.links.insert(full_target.to_string(), links);
         Ok(())
     }
+
+    fn detach(&mut self, target: &str) -> Result<()> {
+        match self.links.remove(target) {
+            Some(_) => Ok(()),
+            None => bail!("No raw tracepoint attached to {}", target),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,190 @@
+//! # CO-RE relocations
+//!
+//! When a CO-RE relocation can't be resolved against the running kernel (e.g.
+//! a field was moved or renamed), libbpf poisons the relocated instruction and
+//! the program fails to load. The failure itself only says the program was
+//! rejected; the relocation details are part of the libbpf log. This module
+//! captures the log while loading objects and extracts the failed relocations
+//! from it, so they can be reported (and, for hooks, be marked as optional).
+
+use std::{fmt, sync::Mutex};
+
+use anyhow::{anyhow, Result};
+use libbpf_rs::PrintLevel;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// libbpf messages captured while loading an object.
+static CAPTURED: Lazy<Mutex<Vec<(PrintLevel, String)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Failed CO-RE relocation.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ReloFailure {
+    /// Relocation kind, e.g. "byte_off" or "enumval_value".
+    pub(crate) kind: String,
+    /// Relocated type and field, e.g. "sk_buff.nfct", as named in the
+    /// program's BTF.
+    pub(crate) target: String,
+}
+
+impl ReloFailure {
+    /// Check if the relocation is covered by an optional target, being either
+    /// a type or one of its fields.
+    pub(crate) fn matches(&self, optional: &str) -> bool {
+        match self.target.strip_prefix(optional) {
+            Some(rest) => rest.is_empty() || rest.starts_with('.') || rest.starts_with("::"),
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for ReloFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.target, self.kind)
+    }
+}
+
+fn level(level: PrintLevel) -> u8 {
+    match level {
+        PrintLevel::Warn => 0,
+        PrintLevel::Info => 1,
+        PrintLevel::Debug => 2,
+    }
+}
+
+fn capture_cb(level: PrintLevel, msg: String) {
+    if let Ok(mut captured) = CAPTURED.lock() {
+        captured.push((level, msg));
+    }
+}
+
+/// Run a load operation while capturing the libbpf log. On failure, the
+/// failed CO-RE relocations found in the log are returned. The previous
+/// libbpf print callback, if any, still receives the messages.
+pub(crate) fn load_capture<T, F>(load: F) -> Result<T, (anyhow::Error, Vec<ReloFailure>)>
+where
+    F: FnOnce() -> Result<T>,
+{
+    if let Ok(mut captured) = CAPTURED.lock() {
+        captured.clear();
+    }
+
+    // Failed relocations are reported as warnings, but keep capturing more
+    // verbose messages if the previous callback wanted them.
+    let prev = libbpf_rs::set_print(Some((PrintLevel::Warn, capture_cb)));
+    if let Some((prev_level, _)) = prev {
+        if level(prev_level) > level(PrintLevel::Warn) {
+            libbpf_rs::set_print(Some((prev_level, capture_cb)));
+        }
+    }
+    let ret = load();
+    libbpf_rs::set_print(prev);
+
+    let captured = match CAPTURED.lock() {
+        Ok(mut captured) => captured.drain(..).collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+
+    if let Some((prev_level, cb)) = prev {
+        captured
+            .iter()
+            .filter(|(l, _)| level(*l) <= level(prev_level))
+            .for_each(|(l, msg)| cb(*l, msg.clone()));
+    }
+
+    ret.map_err(|e| {
+        let log = captured
+            .iter()
+            .map(|(_, msg)| msg.as_str())
+            .collect::<String>();
+        (e, parse_failures(&log))
+    })
+}
+
+/// Wrap load_capture, adding the failed relocations to the error.
+pub(crate) fn load_with_report<T, F>(load: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    load_capture(load).map_err(|(e, failures)| with_failures(e, &failures))
+}
+
+/// Add failed relocations to an error.
+pub(crate) fn with_failures(e: anyhow::Error, failures: &[ReloFailure]) -> anyhow::Error {
+    if failures.is_empty() {
+        return e;
+    }
+    anyhow!(
+        "{}: CO-RE relocation(s) not matching the running kernel: {}",
+        e,
+        failures
+            .iter()
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// Extract failed CO-RE relocations from a libbpf log. libbpf reports them,
+/// within the verifier log, as e.g.:
+///
+/// "failed to resolve CO-RE relocation <byte_off> [7] struct sk_buff.nfct (0:83 @ offset 216)"
+fn parse_failures(log: &str) -> Vec<ReloFailure> {
+    static RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r"failed to resolve CO-RE relocation <([a-z_]+)> \[\d+\] (?:(?:struct|union|enum|typedef) )?([^ \n]+)",
+        )
+        .unwrap()
+    });
+
+    let mut failures: Vec<ReloFailure> = Vec::new();
+    for cap in RE.captures_iter(log) {
+        let failure = ReloFailure {
+            kind: cap[1].to_string(),
+            target: cap[2].to_string(),
+        };
+        if !failures.contains(&failure) {
+            failures.push(failure);
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let log = "libbpf: prog 'hook': BPF program load failed: Invalid argument\n\
+                   libbpf: prog 'hook': -- BEGIN PROG LOAD LOG --\n\
+                   123: <invalid CO-RE relocation>\n\
+                   failed to resolve CO-RE relocation <byte_off> [7] struct sk_buff.nfct (0:83 @ offset 216)\n\
+                   140: <invalid CO-RE relocation>\n\
+                   failed to resolve CO-RE relocation <byte_off> [7] struct sk_buff.nfct (0:83 @ offset 216)\n\
+                   151: <invalid CO-RE relocation>\n\
+                   failed to resolve CO-RE relocation <enumval_value> [42] enum skb_drop_reason::SKB_DROP_REASON_FOO = 3\n\
+                   -- END PROG LOAD LOG --\n";
+
+        let failures = parse_failures(log);
+        assert!(failures.len() == 2);
+        assert!(failures[0].kind == "byte_off" && failures[0].target == "sk_buff.nfct");
+        assert!(failures[1].kind == "enumval_value");
+        assert!(failures[1].target == "skb_drop_reason::SKB_DROP_REASON_FOO");
+        assert!(failures[0].to_string() == "sk_buff.nfct (byte_off)");
+
+        assert!(parse_failures("libbpf: failed to open object").is_empty());
+
+        assert!(failures[0].matches("sk_buff"));
+        assert!(failures[0].matches("sk_buff.nfct"));
+        assert!(!failures[0].matches("sk_buff.len"));
+        assert!(!failures[0].matches("sk_buf"));
+        assert!(failures[1].matches("skb_drop_reason"));
+
+        let e = with_failures(anyhow!("Could not load hook"), &failures[..1]);
+        assert!(
+            e.to_string()
+                == "Could not load hook: CO-RE relocation(s) not matching the running kernel: sk_buff.nfct (byte_off)"
+        );
+    }
+}
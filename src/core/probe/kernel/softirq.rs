@@ -0,0 +1,58 @@
+//! # Softirq tracker
+//!
+//! Keep track of the softirq being run on each cpu, for probes to report the
+//! softirq vector (e.g. NET_RX) of events generated in softirq context (see
+//! --context). This uses two raw tracepoints, irq:softirq_entry and
+//! irq:softirq_exit, updating a per-cpu map shared with the probes.
+
+use anyhow::{anyhow, Result};
+
+use crate::core::probe::get_ebpf_debug;
+
+mod softirq_bpf {
+    include!("bpf/.out/softirq.skel.rs");
+}
+use softirq_bpf::SoftirqSkelBuilder;
+
+// Programs attached to the softirq tracepoints.
+const SOFTIRQ_PROGS: [(&str, &str); 2] = [
+    ("softirq_entry", "softirq_entry"),
+    ("softirq_exit", "softirq_exit"),
+];
+
+/// Softirq tracker handle. Programs stay attached for as long as it is alive.
+pub(crate) struct SoftirqTracker {
+    obj: libbpf_rs::Object,
+    // Links are only kept alive here.
+    #[allow(dead_code)]
+    links: Vec<libbpf_rs::Link>,
+}
+
+impl SoftirqTracker {
+    /// Load and attach the tracker programs.
+    pub(crate) fn new() -> Result<SoftirqTracker> {
+        let mut skel = SoftirqSkelBuilder::default();
+        skel.obj_builder.debug(get_ebpf_debug());
+        let mut obj = skel.open()?.obj.load()?;
+
+        let mut links = Vec::new();
+        for (name, target) in SOFTIRQ_PROGS.iter() {
+            links.push(
+                obj.prog_mut(name)
+                    .ok_or_else(|| anyhow!("Couldn't get program {}", name))?
+                    .attach_raw_tracepoint(target)?,
+            );
+        }
+
+        Ok(SoftirqTracker { obj, links })
+    }
+
+    /// File descriptor of the softirq map, to be reused by the probes.
+    pub(crate) fn map_fd(&self) -> Result<i32> {
+        Ok(self
+            .obj
+            .map("softirq_map")
+            .ok_or_else(|| anyhow!("Couldn't get map softirq_map"))?
+            .fd())
+    }
+}
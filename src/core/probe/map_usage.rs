@@ -0,0 +1,156 @@
+//! # BPF map usage
+//!
+//! Some BPF maps have a capacity which, once reached, silently degrades the
+//! collection: new entries can't be added (e.g. skb tracking information) or
+//! older ones are evicted (e.g. flows in LRU maps). Maps of interest are
+//! registered using track_map() and their usage can then be retrieved at any
+//! time with map_usage(), e.g. to be reported in the periodic statistics.
+//!
+//! The number of elements of a map isn't exposed by the kernel; it is computed
+//! by walking the map keys, which is not atomic and has a cost proportional to
+//! the number of elements. Usage should be retrieved at a low rate.
+
+use std::{mem, sync::Mutex};
+
+use anyhow::{bail, Result};
+use log::debug;
+use nix::{errno::Errno, libc, unistd::dup};
+use once_cell::sync::Lazy;
+
+/// Usage ratio above which a map is reported as nearly full.
+pub(crate) const MAP_USAGE_WARN: f64 = 0.9;
+
+/// Maps being tracked: their name and a file descriptor on them. Descriptors
+/// are duplicated to keep the maps alive, regardless of their owner.
+static TRACKED_MAPS: Lazy<Mutex<Vec<(String, i32)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Usage of a map.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MapUsage {
+    pub(crate) name: String,
+    /// Number of elements in the map.
+    pub(crate) entries: u32,
+    pub(crate) max_entries: u32,
+}
+
+impl MapUsage {
+    /// Usage ratio, between 0 and 1.
+    pub(crate) fn ratio(&self) -> f64 {
+        match self.max_entries {
+            0 => 0.0,
+            max => self.entries as f64 / max as f64,
+        }
+    }
+}
+
+/// Track the usage of a map. Only maps having keys to walk (e.g. hash maps,
+/// LPM tries) are meaningful, arrays are always full.
+pub(crate) fn track_map(name: &str, fd: i32) -> Result<()> {
+    let fd = dup(fd).or_else(|e| bail!("Could not track map {}: {}", name, e))?;
+    TRACKED_MAPS.lock().unwrap().push((name.to_string(), fd));
+    Ok(())
+}
+
+/// Retrieve the usage of all tracked maps. Maps whose usage can't be retrieved
+/// are not reported.
+pub(crate) fn map_usage() -> Vec<MapUsage> {
+    TRACKED_MAPS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(name, fd)| match usage(name, *fd) {
+            Ok(usage) => Some(usage),
+            Err(e) => {
+                debug!("Could not retrieve the usage of map {}: {}", name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn usage(name: &str, fd: i32) -> Result<MapUsage> {
+    let mut info = libbpf_sys::bpf_map_info::default();
+    let mut len = mem::size_of::<libbpf_sys::bpf_map_info>() as u32;
+    let ret = unsafe {
+        libbpf_sys::bpf_obj_get_info_by_fd(fd, &mut info as *mut _ as *mut libc::c_void, &mut len)
+    };
+    if ret < 0 {
+        bail!("Could not get map info: {}", Errno::from_i32(-ret));
+    }
+
+    Ok(MapUsage {
+        name: name.to_string(),
+        entries: count_entries(fd, info.key_size as usize, info.max_entries)?,
+        max_entries: info.max_entries,
+    })
+}
+
+/// Count the elements of a map by walking its keys. The walk is bounded by the
+/// map capacity, as elements can be added and removed concurrently.
+fn count_entries(fd: i32, key_size: usize, max_entries: u32) -> Result<u32> {
+    let mut prev = vec![0u8; key_size];
+    let mut next = vec![0u8; key_size];
+    let mut count = 0;
+
+    while count < max_entries {
+        let prev_ptr = match count {
+            0 => std::ptr::null(),
+            _ => prev.as_ptr() as *const libc::c_void,
+        };
+        let ret = unsafe {
+            libbpf_sys::bpf_map_get_next_key(fd, prev_ptr, next.as_mut_ptr() as *mut libc::c_void)
+        };
+        if ret < 0 {
+            match Errno::from_i32(-ret) {
+                Errno::ENOENT => break,
+                e => bail!("Could not walk the map: {}", e),
+            }
+        }
+
+        count += 1;
+        mem::swap(&mut prev, &mut next);
+    }
+    Ok(count)
+}
+
+/// Describe the usage of maps, e.g. "tracking_map=4096/8192(50%)".
+pub(crate) fn format_usage(usage: &[MapUsage]) -> String {
+    usage
+        .iter()
+        .map(|u| {
+            format!(
+                "{}={}/{}({:.0}%)",
+                u.name,
+                u.entries,
+                u.max_entries,
+                u.ratio() * 100.0
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage() {
+        let usage = vec![
+            MapUsage {
+                name: "tracking_map".to_string(),
+                entries: 4096,
+                max_entries: 8192,
+            },
+            MapUsage {
+                name: "flow_head_map".to_string(),
+                entries: 0,
+                max_entries: 0,
+            },
+        ];
+
+        assert!(usage[0].ratio() == 0.5);
+        assert!(usage[1].ratio() == 0.0);
+        assert!(format_usage(&usage) == "tracking_map=4096/8192(50%),flow_head_map=0/0(0%)");
+    }
+}
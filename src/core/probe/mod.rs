@@ -3,8 +3,12 @@
 //! Module providing a public API to attach to various types of probes.
 
 pub(crate) mod common;
-pub(crate) use common::get_ebpf_debug;
+pub(crate) use common::{
+    get_clock_tai, get_count_events, get_count_only, get_ebpf_debug, get_exec_context,
+    get_pid_filter, get_probe_rate,
+};
 
 pub(crate) mod kernel;
+pub(crate) mod map_usage;
 // Re-export kernel::Kernel.
 pub(crate) use kernel::Kernel;
@@ -0,0 +1,213 @@
+//! # Self-test
+//!
+//! End-to-end check of the collection pipeline, used by the self-test
+//! subcommand. A regular collection is set up using the skb collector,
+//! filtering on a UDP flow between two loopback sockets owned by the process.
+//! Packets are then sent over this flow and the events reported are checked
+//! to describe them: the probes, hooks, event retrieval and unmarshaling all
+//! have to work for the test to pass.
+
+use std::{
+    fmt,
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+
+use super::events::Event;
+use crate::collector::Group;
+
+/// Payload of the test packets, small enough to be fully captured.
+const PAYLOAD: &[u8] = b"pt-self-test";
+
+/// Functions probed, on top of the ones added by the collectors. Loopback
+/// packets go through both.
+const PROBES: &str = "^(ip_rcv|udp_rcv)$";
+
+/// Interval at which test packets are sent, in case the first ones are missed.
+const SEND_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Poll timeout while waiting for events.
+const POLL_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Test traffic: a UDP flow between two loopback sockets.
+pub(crate) struct TestTraffic {
+    sender: UdpSocket,
+    receiver: UdpSocket,
+}
+
+impl TestTraffic {
+    pub(crate) fn new() -> Result<TestTraffic> {
+        let bind = || {
+            UdpSocket::bind("127.0.0.1:0")
+                .or_else(|e| bail!("Could not create the test socket: {}", e))
+        };
+        let (sender, receiver) = (bind()?, bind()?);
+        // Packets are never read, only traced.
+        receiver.set_nonblocking(true)?;
+
+        Ok(TestTraffic { sender, receiver })
+    }
+
+    /// Collect command line tracing the test flow, see the collect
+    /// subcommand.
+    pub(crate) fn collect_args(&self) -> Result<Vec<String>> {
+        Ok(vec![
+            "packet-tracer".to_string(),
+            "collect".to_string(),
+            "--collectors".to_string(),
+            "skb".to_string(),
+            "--flow".to_string(),
+            format!(
+                "{}-{}/udp",
+                self.sender.local_addr()?,
+                self.receiver.local_addr()?
+            ),
+            "--kprobe-regex".to_string(),
+            PROBES.to_string(),
+        ])
+    }
+
+    /// Send test packets and wait for their events, until one is valid or the
+    /// timeout expires. The collection must have been started.
+    pub(crate) fn run(&self, group: &mut Group, timeout: Duration) -> Result<TestReport> {
+        let mut report = TestReport::default();
+        let dst = self.receiver.local_addr()?;
+        let deadline = Instant::now() + timeout;
+        let mut next_send = Instant::now();
+
+        while Instant::now() < deadline {
+            if Instant::now() >= next_send {
+                self.sender.send_to(PAYLOAD, dst)?;
+                report.sent += 1;
+                next_send = Instant::now() + SEND_INTERVAL;
+            }
+
+            let event = match group.poll_event(Some(POLL_TIMEOUT))? {
+                Some(event) => event,
+                None => continue,
+            };
+            report.events += 1;
+
+            match check_event(&event)? {
+                Ok(probe) => {
+                    report.captured = Some(probe);
+                    break;
+                }
+                Err(e) => report.errors.push(e),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Check an event reports a test packet. Returns the probe which reported it,
+/// or why the event is invalid.
+fn check_event(event: &Event) -> Result<Result<String, String>> {
+    let probe = event
+        .get::<String>("common", "probe")?
+        .cloned()
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let len = match event.get::<u32>("skb", "len")? {
+        Some(len) => *len,
+        None => return Ok(Err(format!("{}: no skb length reported", probe))),
+    };
+    // The packet at least contains the IPv4 and UDP headers.
+    if (len as usize) < PAYLOAD.len() + 28 {
+        return Ok(Err(format!("{}: unexpected skb length {}", probe, len)));
+    }
+
+    if let Some(packet) = event.get::<String>("skb", "packet")? {
+        let payload: String = PAYLOAD.iter().map(|b| format!("{:02x}", b)).collect();
+        if !packet.contains(&payload) {
+            return Ok(Err(format!("{}: payload not found in the packet", probe)));
+        }
+    }
+
+    Ok(Ok(probe))
+}
+
+/// Self-test outcome.
+#[derive(Default)]
+pub(crate) struct TestReport {
+    /// Number of test packets sent.
+    pub(crate) sent: u64,
+    /// Number of events retrieved.
+    pub(crate) events: u64,
+    /// Probe which reported a valid event, if any.
+    pub(crate) captured: Option<String>,
+    /// Invalid events found.
+    pub(crate) errors: Vec<String>,
+}
+
+impl TestReport {
+    pub(crate) fn passed(&self) -> bool {
+        self.captured.is_some()
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Self-test {}: {} packet(s) sent, {} event(s) retrieved",
+            if self.passed() { "passed" } else { "failed" },
+            self.sent,
+            self.events
+        )?;
+        if let Some(probe) = &self.captured {
+            writeln!(f, "  packet reported by {}", probe)?;
+        }
+        for e in self.errors.iter() {
+            writeln!(f, "  invalid event: {}", e)?;
+        }
+        if !self.passed() && self.events == 0 {
+            writeln!(f, "  no event was reported")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_field;
+
+    #[test]
+    fn check() -> Result<()> {
+        let payload: String = PAYLOAD.iter().map(|b| format!("{:02x}", b)).collect();
+        let event = |len: u32, packet: &str| {
+            let mut event = Event::new();
+            event.insert(
+                "common",
+                event_field!("probe", "kprobe:udp_rcv".to_string()),
+            );
+            event.insert("skb", event_field!("len", len));
+            event.insert("skb", event_field!("packet", packet.to_string()));
+            event
+        };
+
+        assert!(
+            check_event(&event(54, &format!("4500{}", payload)))?
+                == Ok("kprobe:udp_rcv".to_string())
+        );
+        assert!(check_event(&event(20, &payload))?.is_err());
+        assert!(check_event(&event(54, "4500"))?.is_err());
+        assert!(check_event(&Event::new())?.is_err());
+
+        let report = TestReport {
+            sent: 1,
+            events: 2,
+            captured: None,
+            errors: vec!["kprobe:ip_rcv: no skb length reported".to_string()],
+        };
+        assert!(!report.passed());
+        assert!(report
+            .to_string()
+            .starts_with("Self-test failed: 1 packet(s) sent, 2 event(s) retrieved\n"));
+        Ok(())
+    }
+}
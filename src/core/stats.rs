@@ -0,0 +1,173 @@
+//! # Periodic statistics
+//!
+//! Optionally report collection statistics at a regular interval while the
+//! collection is running: number of events retrieved per collector, events
+//! not written (filtered out or throttled) and the event rate over the last
+//! interval. Statistics are logged, and thus written to stderr, so they never
+//! mix with events; they are silenced by --quiet.
+//!
+//! The usage of BPF maps having a bounded capacity is reported too, and a
+//! warning is emitted when one of them becomes nearly full, as entries are
+//! then lost or evicted (see core::probe::map_usage).
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use super::{
+    events::{meta::META_SECTION, Event},
+    probe::map_usage::{format_usage, map_usage, MapUsage, MAP_USAGE_WARN},
+};
+
+/// Section found in all collector events, not accounted as a collector.
+const COMMON_SECTION: &str = "common";
+
+pub(crate) struct Stats {
+    /// Interval at which statistics are reported.
+    interval: Duration,
+    /// Last time statistics were reported.
+    last: Instant,
+    /// Number of events retrieved, in total and when last reported.
+    events: u64,
+    last_events: u64,
+    /// Number of events retrieved, per collector section.
+    per_collector: BTreeMap<String, u64>,
+    /// Maps reported as nearly full, to only warn once until their usage
+    /// decreases.
+    full_maps: HashSet<String>,
+}
+
+impl Stats {
+    pub(crate) fn new(interval: Duration) -> Stats {
+        Stats {
+            interval,
+            last: Instant::now(),
+            events: 0,
+            last_events: 0,
+            per_collector: BTreeMap::new(),
+            full_maps: HashSet::new(),
+        }
+    }
+
+    /// Account for a retrieved event.
+    pub(crate) fn account(&mut self, event: &Event) {
+        self.events += 1;
+        event
+            .sections()
+            .filter(|s| *s != COMMON_SECTION && *s != META_SECTION)
+            .for_each(|s| *self.per_collector.entry(s.to_string()).or_insert(0) += 1);
+    }
+
+    /// Report the statistics if the interval elapsed since the last report.
+    /// `dropped` is the number of events not written so far.
+    pub(crate) fn tick(&mut self, dropped: u64) {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.last);
+        if elapsed < self.interval {
+            return;
+        }
+
+        let maps = map_usage();
+        for map in self.check_maps(&maps) {
+            warn!(
+                "Map {} is {:.0}% full ({}/{}), entries might be lost or evicted",
+                map.name,
+                map.ratio() * 100.0,
+                map.entries,
+                map.max_entries
+            );
+        }
+
+        info!("{}", self.report(elapsed, dropped, &maps));
+        self.last = now;
+        self.last_events = self.events;
+    }
+
+    /// Returns the maps which became nearly full since the last check.
+    fn check_maps<'a>(&mut self, maps: &'a [MapUsage]) -> Vec<&'a MapUsage> {
+        let mut full = Vec::new();
+        for map in maps.iter() {
+            if map.ratio() < MAP_USAGE_WARN {
+                self.full_maps.remove(&map.name);
+            } else if self.full_maps.insert(map.name.clone()) {
+                full.push(map);
+            }
+        }
+        full
+    }
+
+    fn report(&self, elapsed: Duration, dropped: u64, maps: &[MapUsage]) -> String {
+        let eps = (self.events - self.last_events) as f64 / elapsed.as_secs_f64().max(0.001);
+        let collectors = self
+            .per_collector
+            .iter()
+            .map(|(name, count)| format!("{}={}", name, count))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut report = format!(
+            "stats: events={} dropped={} eps={:.1} collectors=[{}]",
+            self.events, dropped, eps, collectors
+        );
+        if !maps.is_empty() {
+            report.push_str(&format!(" maps=[{}]", format_usage(maps)));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::events::EventField, event_field};
+
+    #[test]
+    fn report() {
+        let mut stats = Stats::new(Duration::from_secs(5));
+
+        let mut event = Event::new();
+        event.insert("common", event_field!("timestamp", 42u64));
+        event.insert("skb", event_field!("len", 64u32));
+        stats.account(&event);
+        stats.account(&event);
+
+        let mut event = Event::new();
+        event.insert("ovs", event_field!("dp", "ovs-system".to_string()));
+        stats.account(&event);
+
+        assert!(
+            stats.report(Duration::from_secs(2), 1, &[])
+                == "stats: events=3 dropped=1 eps=1.5 collectors=[ovs=1,skb=2]"
+        );
+
+        stats.last_events = stats.events;
+        assert!(
+            stats.report(Duration::from_secs(5), 1, &[])
+                == "stats: events=3 dropped=1 eps=0.0 collectors=[ovs=1,skb=2]"
+        );
+    }
+
+    #[test]
+    fn maps() {
+        let mut stats = Stats::new(Duration::from_secs(5));
+        let map = |entries| MapUsage {
+            name: "tracking_map".to_string(),
+            entries,
+            max_entries: 8192,
+        };
+
+        assert!(stats
+            .report(Duration::from_secs(5), 0, &[map(4096)])
+            .ends_with(" maps=[tracking_map=4096/8192(50%)]"));
+
+        // Only warn once while the map is nearly full.
+        assert!(stats.check_maps(&[map(4096)]).is_empty());
+        assert!(stats.check_maps(&[map(8000)]).len() == 1);
+        assert!(stats.check_maps(&[map(8192)]).is_empty());
+        assert!(stats.check_maps(&[map(100)]).is_empty());
+        assert!(stats.check_maps(&[map(8100)]).len() == 1);
+    }
+}
@@ -0,0 +1,81 @@
+//! # Tracefs
+//!
+//! Helpers to read information exposed by tracefs, e.g. the list of available
+//! tracepoints. Tracefs is usually mounted at /sys/kernel/tracing, or at
+//! /sys/kernel/debug/tracing on older systems when debugfs is mounted.
+
+use std::fs::{self, File};
+
+use anyhow::{bail, Result};
+
+/// Known tracefs mount points, in order of preference.
+const TRACEFS_PATHS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// Read a tracefs file, e.g. "available_events".
+pub(crate) fn read_tracefs(file: &str) -> Result<String> {
+    for path in TRACEFS_PATHS.iter() {
+        if let Ok(content) = fs::read_to_string(format!("{}/{}", path, file)) {
+            return Ok(content);
+        }
+    }
+    bail!(
+        "Could not read {} from tracefs, is it mounted? (mount -t tracefs nodev /sys/kernel/tracing)",
+        file
+    );
+}
+
+/// Open a tracefs file, e.g. "trace_pipe".
+pub(crate) fn open_tracefs(file: &str) -> Result<File> {
+    for path in TRACEFS_PATHS.iter() {
+        if let Ok(file) = File::open(format!("{}/{}", path, file)) {
+            return Ok(file);
+        }
+    }
+    bail!(
+        "Could not open {} from tracefs, is it mounted? (mount -t tracefs nodev /sys/kernel/tracing)",
+        file
+    );
+}
+
+/// Return the sorted list of available tracepoints ("subsystem:name"),
+/// optionally restricted to a given subsystem.
+pub(crate) fn tracepoints(subsystem: Option<&str>) -> Result<Vec<String>> {
+    Ok(filter_tracepoints(
+        &read_tracefs("available_events")?,
+        subsystem,
+    ))
+}
+
+fn filter_tracepoints(events: &str, subsystem: Option<&str>) -> Vec<String> {
+    let mut tracepoints: Vec<String> = events
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| match (subsystem, l.split_once(':')) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(subsystem), Some((subsys, _))) => subsys == subsystem,
+        })
+        .map(|l| l.to_string())
+        .collect();
+    tracepoints.sort();
+    tracepoints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter() {
+        let events = "skb:kfree_skb\nnet:net_dev_xmit\n\nskb:consume_skb\ninvalid\n";
+
+        assert!(
+            filter_tracepoints(events, None)
+                == vec!["net:net_dev_xmit", "skb:consume_skb", "skb:kfree_skb"]
+        );
+        assert!(
+            filter_tracepoints(events, Some("skb")) == vec!["skb:consume_skb", "skb:kfree_skb"]
+        );
+        assert!(filter_tracepoints(events, Some("xdp")).is_empty());
+    }
+}
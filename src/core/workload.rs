@@ -0,0 +1,288 @@
+//! # Workload
+//!
+//! Run a command for the duration of a collection (see --while-running). The
+//! command is started right away, so its pid is known early (e.g. to set up the
+//! pid filter), but is held until released: it only runs once the probes are
+//! attached and does not generate events the collection would miss.
+//!
+//! The command is run by `/bin/sh`, which first waits for the release on a pipe
+//! (inherited as fd 3) and then evaluates the command in place; the shell
+//! process is the workload, its exit code being the one of the command. The
+//! workload runs in its own process group so all the processes it started can
+//! be stopped together. This is a background process group: a workload reading
+//! from the terminal is stopped by SIGTTIN.
+//!
+//! The workload is stopped with SIGTERM, and SIGKILL if it did not exit after
+//! STOP_GRACE. It is stopped when dropped, so it does not outlive us when the
+//! collection ends on an error.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    os::unix::{
+        io::{FromRawFd, RawFd},
+        process::{CommandExt, ExitStatusExt},
+    },
+    process::{Child, Command, ExitStatus},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use nix::{
+    fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
+    sys::signal::{killpg, Signal},
+    unistd::{close, dup2, pipe2, setpgid, Pid},
+};
+
+/// File descriptor the release pipe is inherited as in the workload.
+const GATE_FD: RawFd = 3;
+/// Exit code of the workload when it was never released.
+const GATE_CLOSED: i32 = 125;
+/// Time the workload is given to exit after SIGTERM, before being killed.
+const STOP_GRACE: Duration = Duration::from_secs(3);
+/// Interval at which the workload is checked for exiting while stopping it.
+const STOP_POLL: Duration = Duration::from_millis(50);
+
+/// Command run for the duration of a collection.
+pub(crate) struct Workload {
+    child: Child,
+    /// Write end of the release pipe, until the workload is released.
+    gate: Option<File>,
+    /// Exit code, once the workload exited.
+    code: Option<i32>,
+}
+
+impl Workload {
+    /// Start `cmd` in a shell, held until `release()` is called.
+    pub(crate) fn spawn(cmd: &str) -> Result<Workload> {
+        let (read, write) = pipe2(OFlag::O_CLOEXEC)?;
+        // Safety: the write end was just created and is owned by us.
+        let gate = unsafe { File::from_raw_fd(write) };
+
+        let script = format!(
+            "read -r _ <&{fd} || exit {code}; exec {fd}<&-; eval \"$1\"",
+            fd = GATE_FD,
+            code = GATE_CLOSED
+        );
+        let mut command = Command::new("/bin/sh");
+        command.args(["-c", &script, "sh", cmd]);
+        // Safety: only async-signal-safe calls are made in the child before
+        // exec.
+        unsafe {
+            command.pre_exec(move || {
+                setpgid(Pid::from_raw(0), Pid::from_raw(0))?;
+                // The read end must survive exec, dup2() clears the
+                // close-on-exec flag of the new descriptor but is a no-op if
+                // both are the same.
+                match read == GATE_FD {
+                    true => fcntl(read, FcntlArg::F_SETFD(FdFlag::empty())).map(|_| ()),
+                    false => dup2(read, GATE_FD).map(|_| ()),
+                }
+                .map_err(io::Error::from)
+            });
+        }
+
+        let child = command.spawn();
+        close(read)?;
+        let child = match child {
+            Ok(child) => child,
+            Err(e) => bail!("Could not run '{}': {}", cmd, e),
+        };
+
+        Ok(Workload {
+            child,
+            gate: Some(gate),
+            code: None,
+        })
+    }
+
+    /// Pid of the workload.
+    pub(crate) fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Let the workload run.
+    pub(crate) fn release(&mut self) -> Result<()> {
+        if let Some(mut gate) = self.gate.take() {
+            gate.write_all(b"\n")
+                .or_else(|e| bail!("Could not release the workload: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Check if the workload exited, without blocking. Returns its exit code
+    /// if so.
+    pub(crate) fn try_wait(&mut self) -> Result<Option<i32>> {
+        if self.code.is_none() {
+            self.code = self.child.try_wait()?.map(exit_code);
+        }
+        Ok(self.code)
+    }
+
+    /// Stop the workload, and all processes in its group, if it is still
+    /// running and wait for it to exit. Returns its exit code. The group is
+    /// killed if the workload did not exit within STOP_GRACE.
+    pub(crate) fn stop(&mut self) -> Result<i32> {
+        if let Some(code) = self.try_wait()? {
+            return Ok(code);
+        }
+
+        // Not released yet, the workload exits on its own.
+        self.gate = None;
+        // The workload can exit in the meantime, ignore errors.
+        let pgid = Pid::from_raw(self.child.id() as i32);
+        killpg(pgid, Signal::SIGTERM).ok();
+
+        let start = Instant::now();
+        while start.elapsed() < STOP_GRACE {
+            if let Some(code) = self.try_wait()? {
+                return Ok(code);
+            }
+            thread::sleep(STOP_POLL);
+        }
+        killpg(pgid, Signal::SIGKILL).ok();
+
+        let code = exit_code(self.child.wait()?);
+        self.code = Some(code);
+        Ok(code)
+    }
+}
+
+impl Drop for Workload {
+    fn drop(&mut self) {
+        // Nothing can be done about errors at this point.
+        self.stop().ok();
+    }
+}
+
+/// Convert an exit status to an exit code, the way shells do: processes
+/// killed by a signal exit with 128 + the signal number.
+fn exit_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path, process};
+
+    use nix::sys::signal::kill;
+
+    use super::*;
+
+    fn wait(workload: &mut Workload) -> Result<i32> {
+        for _ in 0..100 {
+            if let Some(code) = workload.try_wait()? {
+                return Ok(code);
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        bail!("Workload did not exit");
+    }
+
+    #[test]
+    fn exit_codes() -> Result<()> {
+        let mut workload = Workload::spawn("exit 3")?;
+        assert!(workload.pid() != 0);
+        workload.release()?;
+        assert!(wait(&mut workload)? == 3);
+        assert!(workload.stop()? == 3);
+
+        let mut workload = Workload::spawn("true && kill -TERM $$")?;
+        workload.release()?;
+        assert!(wait(&mut workload)? == 128 + 15);
+        Ok(())
+    }
+
+    #[test]
+    fn held() -> Result<()> {
+        let file = env::temp_dir().join(format!("workload-held-{}", process::id()));
+        let cmd = format!("touch {}", file.display());
+
+        // Not released: the command never runs.
+        let mut workload = Workload::spawn(&cmd)?;
+        thread::sleep(Duration::from_millis(100));
+        assert!(workload.try_wait()?.is_none());
+        workload.stop()?;
+        assert!(!Path::new(&file).exists());
+
+        let mut workload = Workload::spawn(&cmd)?;
+        workload.release()?;
+        assert!(wait(&mut workload)? == 0);
+        assert!(Path::new(&file).exists());
+        fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn dropped() -> Result<()> {
+        let workload = Workload::spawn("sleep 30")?;
+        let pid = Pid::from_raw(workload.pid() as i32);
+        assert!(kill(pid, None).is_ok());
+
+        // The workload is stopped and reaped when dropped, even if released.
+        drop(workload);
+        assert!(kill(pid, None).is_err());
+
+        let mut workload = Workload::spawn("sleep 30")?;
+        workload.release()?;
+        let pid = Pid::from_raw(workload.pid() as i32);
+        drop(workload);
+        assert!(kill(pid, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn killed() -> Result<()> {
+        let mut workload = Workload::spawn("trap '' TERM; sleep 30")?;
+        workload.release()?;
+        // Let the shell ignore SIGTERM.
+        thread::sleep(Duration::from_millis(200));
+
+        // Workloads not exiting on SIGTERM are killed after a grace period.
+        let start = Instant::now();
+        assert!(workload.stop()? == 128 + 9);
+        assert!(start.elapsed() >= STOP_GRACE);
+        Ok(())
+    }
+
+    #[test]
+    fn group() -> Result<()> {
+        let file = env::temp_dir().join(format!("workload-group-{}", process::id()));
+        let cmd = format!("sleep 30 & echo $! > {}; wait", file.display());
+
+        let mut workload = Workload::spawn(&cmd)?;
+        workload.release()?;
+        let mut pid = None;
+        for _ in 0..100 {
+            if let Some(p) = fs::read_to_string(&file)
+                .ok()
+                .and_then(|s| s.trim().parse::<i32>().ok())
+            {
+                pid = Some(Pid::from_raw(p));
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        fs::remove_file(&file)?;
+        let pid = pid.unwrap();
+        assert!(kill(pid, None).is_ok());
+
+        // Processes started by the workload are stopped with it.
+        assert!(workload.stop()? == 128 + 15);
+        let mut stopped = false;
+        for _ in 0..100 {
+            if kill(pid, None).is_err() {
+                stopped = true;
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(stopped);
+        Ok(())
+    }
+}
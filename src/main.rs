@@ -1,33 +1,230 @@
-use anyhow::Result;
-use log::error;
-use simplelog::{Config, LevelFilter, SimpleLogger};
+use std::{
+    process::ExitCode,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use log::{error, LevelFilter};
+use nix::sys::signal;
 
 mod cli;
 mod collector;
 mod core;
-use cli::get_cli;
-use collector::get_collectors;
+mod output;
+use crate::core::{
+    capabilities::capabilities, control::get_control_socket, logger::init_logger,
+    privileges::check_privileges, self_test::TestTraffic, stats::Stats, tracefs::tracepoints,
+    workload::Workload,
+};
+use cli::{
+    cmd::{collect::Collect, list_tracepoints::ListTracepoints, self_test::SelfTest},
+    get_cli,
+};
+use collector::{get_collectors, set_probe_config};
+use output::get_output;
+
+/// Timeout when polling for events, so we can regularly check if the
+/// collection should be stopped.
+const EVENT_POLL_TIMEOUT_MS: u64 = 200;
+/// Time given to the events of a workload to be retrieved once it exited, see
+/// --while-running.
+const WORKLOAD_GRACE_MS: u64 = 1000;
+
+/// Is the collection running? Cleared when a termination signal is received.
+static RUNNING: AtomicBool = AtomicBool::new(true);
+
+extern "C" fn handle_stop(_: i32) {
+    RUNNING.store(false, Ordering::Relaxed);
+}
+
+/// Install handlers for termination signals so we can stop gracefully.
+fn set_stop_handler() -> Result<()> {
+    let action = signal::SigAction::new(
+        signal::SigHandler::Handler(handle_stop),
+        signal::SaFlags::empty(),
+        signal::SigSet::empty(),
+    );
+
+    // Safety: the handler only sets an atomic boolean.
+    unsafe {
+        signal::sigaction(signal::SIGINT, &action)?;
+        signal::sigaction(signal::SIGTERM, &action)?;
+    }
+    Ok(())
+}
 
-fn main() -> Result<()> {
-    let _ = SimpleLogger::init(LevelFilter::Debug, Config::default());
+fn main() -> Result<ExitCode> {
     let mut cli = get_cli()?.build()?;
 
+    let main_config = cli.get_main_config()?;
+    init_logger(
+        LevelFilter::from_str(&main_config.log_level)?,
+        main_config.quiet,
+        main_config.log_file.as_deref(),
+    )?;
+
     let command = cli.get_subcommand_mut()?;
     match command.name() {
         "collect" => {
+            // Check privileges early, as collectors start creating BPF
+            // objects when registered.
+            check_privileges()?;
+
             let mut collectors = get_collectors()?;
             collectors.register_cli(command.dynamic_mut().unwrap())?;
             let config = cli.run()?;
+            let mut output = get_output(&config)?;
+
+            let args = config
+                .subcommand
+                .as_any()
+                .downcast_ref::<Collect>()
+                .ok_or_else(|| anyhow!("wrong subcommand"))?
+                .args()?;
+            // The workload is started early so its pid is known when setting
+            // up the filters, but is held until probes are attached.
+            let mut workload = match &args.while_running {
+                Some(cmd) => Some(Workload::spawn(cmd)?),
+                None => None,
+            };
+            if let (Some(workload), true) = (&workload, args.filter_command) {
+                collectors.filter_workload(workload.pid());
+            }
+            set_probe_config(&config)?;
+            collectors.init(&config)?;
+
+            if args.list_probes {
+                print!("{}", collectors.probe_plan());
+                return Ok(ExitCode::SUCCESS);
+            }
+            if args.estimate_overhead {
+                let window = Duration::from_secs(args.estimate_window.unwrap_or(5));
+                print!("{}", collectors.estimate_overhead(window)?);
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            collectors.start(&config)?;
+            set_stop_handler()?;
+            let _control = get_control_socket(&config, &collectors, &output)?;
+
+            output.output_meta(&mut collectors.start_event()?)?;
+            if let Some(workload) = &mut workload {
+                workload.release()?;
+            }
+            let deadline = args
+                .duration
+                .map(|secs| Instant::now() + Duration::from_secs(secs));
+            let mut stats = args
+                .stats_interval
+                .map(|secs| Stats::new(Duration::from_secs(secs)));
+            let count_interval = Duration::from_secs(args.count_interval.unwrap_or(10));
+            let mut next_count = args.count_only.then(|| Instant::now() + count_interval);
+            // Once the workload exited, events still in flight are retrieved
+            // until none is left or the grace period expires.
+            let mut grace = None;
+            while RUNNING.load(Ordering::Relaxed) {
+                if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                    break;
+                }
+                if let (Some(workload), None) = (&mut workload, grace) {
+                    if workload.try_wait()?.is_some() {
+                        grace = Some(Instant::now() + Duration::from_millis(WORKLOAD_GRACE_MS));
+                    }
+                }
+                match collectors.poll_event(Some(Duration::from_millis(EVENT_POLL_TIMEOUT_MS)))? {
+                    Some(event) => {
+                        if let Some(stats) = &mut stats {
+                            stats.account(&event);
+                        }
+                        output.output(event)?;
+                    }
+                    None if grace.is_some() => break,
+                    None => (),
+                }
+                if grace.map_or(false, |grace| Instant::now() >= grace) {
+                    break;
+                }
+                output.idle()?;
+                if let Some(stats) = &mut stats {
+                    stats.tick(output.dropped());
+                }
+                if let Some(next) = &mut next_count {
+                    if Instant::now() >= *next {
+                        output.output_meta(&mut collectors.counters_event())?;
+                        *next = Instant::now() + count_interval;
+                    }
+                }
+            }
+
+            collectors.stop();
+            if args.count_only {
+                output.output_meta(&mut collectors.counters_event())?;
+            }
+            output.output_warnings()?;
+            let mut stop = collectors.stop_event();
+            output.summary(&mut stop);
+            output.output_meta(&mut stop)?;
+            output.finish()?;
+
+            // Propagate the exit code of the workload, returning it so all
+            // collectors and outputs are properly torn down.
+            if let Some(workload) = &mut workload {
+                let code = workload.stop()?;
+                return Ok(ExitCode::from(u8::try_from(code).unwrap_or(1)));
+            }
+        }
+        "capabilities" => {
+            // Collectors are checked once allocated, which creates BPF
+            // objects: the same privileges as for collecting are needed.
+            check_privileges()
+                .or_else(|e| bail!("Could not check the collectors' capabilities: {}", e))?;
+            let collectors = get_collectors()?;
+            cli.run()?;
+            println!("{}", capabilities(&collectors.check())?);
+        }
+        "list-tracepoints" => {
+            let config = cli.run()?;
+            let subsystem = config
+                .subcommand
+                .as_any()
+                .downcast_ref::<ListTracepoints>()
+                .and_then(|cmd| cmd.subsystem.clone());
+            for tracepoint in tracepoints(subsystem.as_deref())? {
+                println!("{}", tracepoint);
+            }
+        }
+        "self-test" => {
+            check_privileges()?;
+            let config = cli.run()?;
+            let timeout = config
+                .subcommand
+                .as_any()
+                .downcast_ref::<SelfTest>()
+                .ok_or_else(|| anyhow!("wrong subcommand"))?
+                .timeout;
+
+            // Run a regular collection, tracing the test traffic.
+            let traffic = TestTraffic::new()?;
+            let mut cli = get_cli()?.build_from(traffic.collect_args()?, true)?;
+            let mut collectors = get_collectors()?;
+            collectors.register_cli(cli.get_subcommand_mut()?.dynamic_mut().unwrap())?;
+            let config = cli.run()?;
+            set_probe_config(&config)?;
             collectors.init(&config)?;
             collectors.start(&config)?;
-            loop {
-                let event = collectors.poll_event()?;
-                println!("{}", event.to_json());
+
+            let report = traffic.run(&mut collectors, Duration::from_secs(timeout))?;
+            collectors.stop();
+            print!("{}", report);
+            if !report.passed() {
+                bail!("Self-test failed");
             }
         }
         _ => {
             error!("not implemented");
         }
     }
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
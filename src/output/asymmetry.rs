@@ -0,0 +1,264 @@
+//! # Asymmetric routing
+//!
+//! Detects flows whose two directions go through different interfaces, a
+//! common cause of drops by stateful firewalls and conntrack which only see
+//! one side of the connection. Flows are identified by their protocol and
+//! addresses, decoded from the packet bytes (see --capture-bytes), and the
+//! interfaces by the skb collector ifindex field; the direction of the first
+//! packet seen is the forward one.
+//!
+//! A flow is asymmetric when both its directions were seen and not on the same
+//! set of interfaces. Asymmetric flows are reported, once the collection
+//! ended, as "asymmetric_route" warning meta events listing the interfaces
+//! seen per direction, and their count in the stop event.
+//!
+//! Under a memory budget (see --max-memory), the flows seen on the fewest
+//! interfaces are evicted first, after the packet paths; their count is
+//! reported in the stop event.
+
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::Result;
+use serde_json::Value;
+
+use super::{packet::decode_packet, EventOutput};
+use crate::{
+    core::events::{
+        meta::{warning_event, META_SECTION},
+        Event, EventField,
+    },
+    event_field,
+};
+
+/// Estimated memory used by a flow, and by an interface of a flow.
+const FLOW_SIZE: usize = 160;
+const IFACE_SIZE: usize = 16;
+
+/// Interfaces a flow was seen on, per direction.
+#[derive(Default)]
+struct Flow {
+    /// Source and destination addresses of the forward direction.
+    forward: (String, String),
+    forward_ifaces: BTreeSet<u64>,
+    reverse_ifaces: BTreeSet<u64>,
+}
+
+impl Flow {
+    fn is_asymmetric(&self) -> bool {
+        !self.forward_ifaces.is_empty()
+            && !self.reverse_ifaces.is_empty()
+            && self.forward_ifaces != self.reverse_ifaces
+    }
+}
+
+pub(super) struct Asymmetry {
+    /// Flows, by protocol and ordered pair of addresses.
+    flows: HashMap<(String, String, String), Flow>,
+    /// Number of asymmetric flows, once reported.
+    asymmetric: u64,
+    /// Number of flows evicted under the memory budget.
+    evicted: u64,
+    memory: usize,
+}
+
+impl Asymmetry {
+    pub(super) fn new() -> Asymmetry {
+        Asymmetry {
+            flows: HashMap::new(),
+            asymmetric: 0,
+            evicted: 0,
+            memory: 0,
+        }
+    }
+}
+
+impl EventOutput for Asymmetry {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        let json = event.to_json();
+        let field = |key: &str| json.get("skb").and_then(|s| s.get(key));
+
+        let (ifindex, (protocol, saddr, daddr)) = match (
+            field("ifindex").and_then(Value::as_u64),
+            field("packet")
+                .and_then(Value::as_str)
+                .and_then(decode_packet),
+        ) {
+            (Some(ifindex), Some(packet)) => (
+                ifindex,
+                (
+                    packet.protocol_name(),
+                    packet.saddr.to_string(),
+                    packet.daddr.to_string(),
+                ),
+            ),
+            _ => return Ok(()),
+        };
+
+        let key = match saddr < daddr {
+            true => (protocol, saddr.clone(), daddr.clone()),
+            false => (protocol, daddr.clone(), saddr.clone()),
+        };
+        let flow = self.flows.entry(key).or_insert_with(|| {
+            self.memory += FLOW_SIZE;
+            Flow {
+                forward: (saddr.clone(), daddr),
+                ..Default::default()
+            }
+        });
+
+        let ifaces = match flow.forward.0 == saddr {
+            true => &mut flow.forward_ifaces,
+            false => &mut flow.reverse_ifaces,
+        };
+        if ifaces.insert(ifindex) {
+            self.memory += IFACE_SIZE;
+        }
+        Ok(())
+    }
+
+    fn summary(&self, event: &mut Event) {
+        event.insert(
+            META_SECTION,
+            event_field!("asymmetric_flows", self.asymmetric),
+        );
+        if self.evicted > 0 {
+            event.insert(
+                META_SECTION,
+                event_field!("asymmetry_evicted", self.evicted),
+            );
+        }
+    }
+
+    fn warnings(&mut self) -> Vec<Event> {
+        let ifaces = |ifaces: &BTreeSet<u64>| {
+            ifaces
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        let mut flows: Vec<_> = self
+            .flows
+            .iter()
+            .filter(|(_, flow)| flow.is_asymmetric())
+            .collect();
+        flows.sort_by(|a, b| a.0.cmp(b.0));
+
+        let warnings: Vec<_> = flows
+            .into_iter()
+            .map(|((protocol, _, _), flow)| {
+                let mut event = warning_event("asymmetric_route");
+                event.insert(
+                    META_SECTION,
+                    event_field!(
+                        "flow",
+                        format!("{} {} > {}", protocol, flow.forward.0, flow.forward.1)
+                    ),
+                );
+                event.insert(
+                    META_SECTION,
+                    event_field!("forward_ifindexes", ifaces(&flow.forward_ifaces)),
+                );
+                event.insert(
+                    META_SECTION,
+                    event_field!("reverse_ifindexes", ifaces(&flow.reverse_ifaces)),
+                );
+                event
+            })
+            .collect();
+        self.asymmetric = warnings.len() as u64;
+        warnings
+    }
+
+    fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Evict the flows seen on the fewest interfaces, which are the least
+    /// likely to be reported.
+    fn evict(&mut self, bytes: usize) -> usize {
+        let mut keys: Vec<_> = self
+            .flows
+            .iter()
+            .map(|(key, flow)| {
+                (
+                    flow.forward_ifaces.len() + flow.reverse_ifaces.len(),
+                    key.clone(),
+                )
+            })
+            .collect();
+        keys.sort();
+
+        let mut freed = 0;
+        for (ifaces, key) in keys.into_iter() {
+            if freed >= bytes {
+                break;
+            }
+            self.flows.remove(&key);
+            freed += FLOW_SIZE + ifaces * IFACE_SIZE;
+            self.evicted += 1;
+        }
+        self.memory -= freed;
+        freed
+    }
+
+    fn eviction_order(&self) -> u8 {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ethernet + IPv4/UDP headers, 10.0.0.1 > 10.0.0.2 and back.
+    const FORWARD: &str = "00112233445566778899aabb08004500001c000040004011000\
+                           00a0000010a000002";
+    const REVERSE: &str = "00112233445566778899aabb08004500001c000040004011000\
+                           00a0000020a000001";
+
+    fn event(packet: &str, ifindex: u32) -> Event {
+        let mut event = Event::new();
+        event.insert("skb", event_field!("packet", packet.to_string()));
+        event.insert("skb", event_field!("ifindex", ifindex));
+        event
+    }
+
+    #[test]
+    fn detect() -> Result<()> {
+        let mut asymmetry = Asymmetry::new();
+
+        // Symmetric so far: in on 2, out on 3, and back.
+        for (packet, ifindex) in [(FORWARD, 2), (FORWARD, 3), (REVERSE, 3), (REVERSE, 2)] {
+            asymmetry.output(&event(packet, ifindex))?;
+        }
+        assert!(asymmetry.warnings().is_empty());
+
+        // Replies coming back through another interface.
+        asymmetry.output(&event(REVERSE, 4))?;
+        let warnings = asymmetry.warnings();
+        assert!(warnings.len() == 1);
+        let warning = warnings[0].to_json();
+        assert!(warning[META_SECTION]["warning"] == "asymmetric_route");
+        assert!(warning[META_SECTION]["flow"] == "udp 10.0.0.1 > 10.0.0.2");
+        assert!(warning[META_SECTION]["forward_ifindexes"] == "2,3");
+        assert!(warning[META_SECTION]["reverse_ifindexes"] == "2,3,4");
+
+        let mut stop = Event::new();
+        asymmetry.summary(&mut stop);
+        assert!(stop.to_json()[META_SECTION]["asymmetric_flows"] == 1);
+
+        // A single direction is never asymmetric, events without packet or
+        // device are ignored.
+        let mut asymmetry = Asymmetry::new();
+        asymmetry.output(&event(FORWARD, 2))?;
+        asymmetry.output(&event(FORWARD, 3))?;
+        asymmetry.output(&Event::new())?;
+        assert!(asymmetry.warnings().is_empty());
+
+        assert!(asymmetry.evict(1) == FLOW_SIZE + 2 * IFACE_SIZE);
+        assert!(asymmetry.flows.is_empty() && asymmetry.memory() == 0);
+        Ok(())
+    }
+}
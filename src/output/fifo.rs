@@ -0,0 +1,181 @@
+//! # Fifo
+//!
+//! Writes events to a named pipe (FIFO), one per line, for lightweight local
+//! integrations. Writing never blocks: events are dropped and accounted for
+//! while no reader is attached or while the pipe is full. Readers can attach
+//! and detach at any time, the pipe being reopened once a new reader shows
+//! up; the format header, if any, is written again for each of them.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Write},
+    os::unix::fs::{FileTypeExt, OpenOptionsExt},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+use nix::{libc, sys::stat::Mode, unistd::mkfifo};
+
+use super::{format::Format, EventOutput};
+use crate::{
+    core::events::{meta::META_SECTION, Event, EventField},
+    event_field,
+};
+
+pub(super) struct FifoSink {
+    path: String,
+    format: Format,
+    /// Write end of the pipe, while a reader is attached.
+    pipe: Option<File>,
+    /// Part of the last line which could not be written yet, as lines must
+    /// not be interleaved or cut.
+    pending: Vec<u8>,
+    /// Number of events which could not be written.
+    dropped: u64,
+}
+
+impl FifoSink {
+    /// Create a FIFO output, creating the named pipe if it does not exist.
+    pub(super) fn new(path: &str, format: Format) -> Result<FifoSink> {
+        match Path::new(path).metadata() {
+            Ok(meta) if !meta.file_type().is_fifo() => bail!("{} is not a named pipe", path),
+            Ok(_) => (),
+            Err(_) => mkfifo(path, Mode::from_bits_truncate(0o600))
+                .or_else(|e| bail!("Could not create named pipe {}: {}", path, e))?,
+        }
+
+        Ok(FifoSink {
+            path: path.to_string(),
+            format,
+            pipe: None,
+            pending: Vec::new(),
+            dropped: 0,
+        })
+    }
+
+    /// Open the write end of the pipe, which fails (ENXIO) when no reader is
+    /// attached.
+    fn open(&mut self) -> bool {
+        let pipe = match OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+        {
+            Ok(pipe) => pipe,
+            Err(_) => return false,
+        };
+
+        self.pipe = Some(pipe);
+        self.pending.clear();
+        if let Some(header) = self.format.header() {
+            self.pending = format!("{}\n", header).into_bytes();
+        }
+        true
+    }
+
+    /// Write as much of the pending data as the pipe accepts. Returns true
+    /// once all of it was written.
+    fn write_pending(&mut self) -> bool {
+        let pipe = match &mut self.pipe {
+            Some(pipe) => pipe,
+            None => return false,
+        };
+
+        while !self.pending.is_empty() {
+            match pipe.write(&self.pending) {
+                Ok(len) => {
+                    self.pending.drain(..len);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return false,
+                // The reader detached (EPIPE) or the pipe is unusable, reopen
+                // it once a reader attaches again.
+                Err(_) => {
+                    self.pipe = None;
+                    self.pending.clear();
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+impl EventOutput for FifoSink {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        let line = match self.format.format(event) {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+
+        if (self.pipe.is_none() && !self.open()) || !self.write_pending() {
+            self.dropped += 1;
+            return Ok(());
+        }
+
+        self.pending = format!("{}\n", line).into_bytes();
+        // A partially written line is completed before writing the next one.
+        // The event is still accounted as written, unless its reader detached.
+        if !self.write_pending() && self.pipe.is_none() {
+            self.dropped += 1;
+        }
+        Ok(())
+    }
+
+    fn summary(&self, event: &mut Event) {
+        event.insert(META_SECTION, event_field!("fifo_dropped", self.dropped));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn reader(path: &str) -> Result<File> {
+        Ok(OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?)
+    }
+
+    #[test]
+    fn fifo() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("pt-output-{}.fifo", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let mut sink = FifoSink::new(path, Format::Json)?;
+        let mut event = Event::new();
+        event.insert("test", event_field!("id", 42_u64));
+
+        // No reader, events are dropped.
+        sink.output(&event)?;
+        assert!(sink.dropped == 1);
+
+        let mut buf = [0; 64];
+        let mut rx = reader(path)?;
+        sink.output(&event)?;
+        let len = rx.read(&mut buf)?;
+        assert!(&buf[..len] == b"{\"test\":{\"id\":42}}\n");
+
+        // The reader detaches, then a new one attaches.
+        drop(rx);
+        sink.output(&event)?;
+        assert!(sink.dropped == 2);
+        let mut rx = reader(path)?;
+        sink.output(&event)?;
+        let len = rx.read(&mut buf)?;
+        assert!(&buf[..len] == b"{\"test\":{\"id\":42}}\n");
+        assert!(sink.dropped == 2);
+
+        // Not a named pipe.
+        std::fs::remove_file(path)?;
+        File::create(path)?;
+        assert!(FifoSink::new(path, Format::Json).is_err());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
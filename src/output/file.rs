@@ -0,0 +1,152 @@
+//! # File
+//!
+//! Writes events to a file, one per line, optionally compressed.
+//! The compression is chosen explicitly or based on the file extension (.gz,
+//! .zst).
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use anyhow::{bail, Result};
+use flate2::{write::GzEncoder, Compression as GzCompression};
+
+use super::{format::Format, EventOutput};
+use crate::core::events::Event;
+
+/// Compression algorithms supported by the file output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Get a compression algorithm by its name, or based on a file extension
+    /// if no name is given.
+    pub(super) fn parse(name: Option<&str>, path: &str) -> Result<Compression> {
+        Ok(match name {
+            Some("none") => Compression::None,
+            Some("gzip") => Compression::Gzip,
+            Some("zstd") => Compression::Zstd,
+            Some(x) => bail!("Unknown compression {}", x),
+            None if path.ends_with(".gz") => Compression::Gzip,
+            None if path.ends_with(".zst") => Compression::Zstd,
+            None => Compression::None,
+        })
+    }
+
+    /// Check a compression level is valid for the algorithm.
+    pub(super) fn check_level(&self, level: u32) -> Result<()> {
+        let range = match self {
+            Compression::None => bail!("A compression level was given without compression"),
+            Compression::Gzip => 0..=9,
+            Compression::Zstd => 1..=22,
+        };
+        if !range.contains(&level) {
+            bail!(
+                "Invalid {:?} compression level {} (expected {}-{})",
+                self,
+                level,
+                range.start(),
+                range.end()
+            );
+        }
+        Ok(())
+    }
+}
+
+pub(super) struct FileSink {
+    writer: Box<dyn Write>,
+    format: Format,
+}
+
+impl FileSink {
+    /// Create a file output. The compression level is algorithm specific and
+    /// the default one is used if not given.
+    pub(super) fn new(
+        path: &str,
+        format: Format,
+        compression: Compression,
+        level: Option<u32>,
+    ) -> Result<FileSink> {
+        if let Some(level) = level {
+            compression.check_level(level)?;
+        }
+        let file = BufWriter::new(File::create(path)?);
+
+        // Encoders finish the compressed stream when dropped.
+        let mut writer: Box<dyn Write> = match compression {
+            Compression::None => Box::new(file),
+            Compression::Gzip => Box::new(GzEncoder::new(
+                file,
+                level.map_or(GzCompression::default(), GzCompression::new),
+            )),
+            Compression::Zstd => Box::new(
+                zstd::stream::write::Encoder::new(file, level.unwrap_or(0) as i32)?.auto_finish(),
+            ),
+        };
+
+        if let Some(header) = format.header() {
+            writeln!(writer, "{}", header)?;
+        }
+
+        Ok(FileSink { writer, format })
+    }
+}
+
+impl EventOutput for FileSink {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        if let Some(line) = self.format.format(event) {
+            writeln!(self.writer, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Read};
+
+    use super::*;
+
+    #[test]
+    fn compression() -> Result<()> {
+        assert!(Compression::parse(None, "events.jsonl")? == Compression::None);
+        assert!(Compression::parse(None, "events.jsonl.gz")? == Compression::Gzip);
+        assert!(Compression::parse(None, "events.jsonl.zst")? == Compression::Zstd);
+        assert!(Compression::parse(Some("none"), "events.jsonl.zst")? == Compression::None);
+        assert!(Compression::parse(Some("lz4"), "events.jsonl").is_err());
+
+        assert!(Compression::Gzip.check_level(9).is_ok());
+        assert!(Compression::Gzip.check_level(10).is_err());
+        assert!(Compression::Zstd.check_level(22).is_ok());
+        assert!(Compression::Zstd.check_level(0).is_err());
+        assert!(Compression::None.check_level(1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compressed_file() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("pt-output-{}.jsonl.gz", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut sink = FileSink::new(path, Format::Json, Compression::Gzip, Some(9))?;
+        sink.output(&Event::new())?;
+        drop(sink);
+
+        let mut content = String::new();
+        flate2::read::GzDecoder::new(fs::File::open(path)?).read_to_string(&mut content)?;
+        assert!(content == "{}\n");
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+//! # Filter
+//!
+//! Userspace predicate on event fields, e.g.
+//! "drop_reason == NO_SOCKET && (l4proto == 17 || dport >= 1024)". Unlike
+//! the BPF filters it is evaluated after events are retrieved, on their
+//! decoded fields, which makes it usable on any field but does not lower the
+//! probing overhead.
+//!
+//! Comparisons are written "field op value", op being one of ==, !=, <, <=,
+//! >, >=. Fields are referenced either by their key alone (the first section
+//! having it is used) or as "section.key". Values are numbers, quoted strings
+//! or bare words. Comparisons can be combined using && and ||, && having the
+//! precedence, and grouped using parentheses. An event not having a field
+//! never matches a comparison on it.
+
+use std::cmp::Ordering;
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use crate::core::events::{Event, EventField};
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    const OPS: [&str; 8] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">"];
+
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    'next: while let Some(c) = rest.chars().next() {
+        for op in OPS {
+            if let Some(r) = rest.strip_prefix(op) {
+                tokens.push(Token::Op(op));
+                rest = r.trim_start();
+                continue 'next;
+            }
+        }
+
+        match c {
+            '(' => {
+                tokens.push(Token::LParen);
+                rest = &rest[1..];
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                rest = &rest[1..];
+            }
+            '"' | '\'' => match rest[1..].find(c) {
+                Some(end) => {
+                    tokens.push(Token::Str(rest[1..end + 1].to_string()));
+                    rest = &rest[end + 2..];
+                }
+                None => bail!("Unterminated string in '{}'", expr),
+            },
+            _ => {
+                let end = rest
+                    .find(|c: char| !(c.is_alphanumeric() || "_-.:".contains(c)))
+                    .unwrap_or(rest.len());
+                if end == 0 {
+                    bail!("Unexpected character '{}' in '{}'", c, expr);
+                }
+                tokens.push(Token::Word(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cmp {
+        section: Option<String>,
+        key: String,
+        op: &'static str,
+        value: String,
+    },
+}
+
+/// Recursive descent parser over the tokens, following the grammar:
+///
+/// or   := and ("||" and)*
+/// and  := term ("&&" term)*
+/// term := "(" or ")" | word op (word | str)
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn peek_op(&self, op: &str) -> bool {
+        matches!(self.tokens.get(self.pos), Some(Token::Op(o)) if *o == op)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek_op("||") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.term()?;
+        while self.peek_op("&&") {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let field = match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                if self.next() != Some(&Token::RParen) {
+                    bail!("Missing closing parenthesis");
+                }
+                return Ok(expr);
+            }
+            Some(Token::Word(field)) => field.clone(),
+            token => bail!("Expected a field, found {:?}", token),
+        };
+        let op = match self.next() {
+            Some(Token::Op(op)) if !matches!(*op, "&&" | "||") => *op,
+            token => bail!("Expected a comparison after '{}', found {:?}", field, token),
+        };
+        let value = match self.next() {
+            Some(Token::Word(value)) | Some(Token::Str(value)) => value.clone(),
+            token => bail!(
+                "Expected a value after '{} {}', found {:?}",
+                field,
+                op,
+                token
+            ),
+        };
+
+        let (section, key) = split_field(&field);
+        Ok(Expr::Cmp {
+            section: section.map(String::from),
+            key: key.to_string(),
+            op,
+            value,
+        })
+    }
+}
+
+/// Get a field of an event, given as JSON, by its section and key. If no
+/// section is given, the first section having the key is used.
+pub(super) fn get_field<'a>(
+    json: &'a Value,
+    section: Option<&str>,
+    key: &str,
+) -> Option<&'a Value> {
+    match section {
+        Some(section) => json.get(section).and_then(|s| s.get(key)),
+        None => json
+            .as_object()
+            .and_then(|sections| sections.values().find_map(|s| s.get(key))),
+    }
+}
+
+/// Split a field reference, "key" or "section.key", into its section and key.
+pub(super) fn split_field(field: &str) -> (Option<&str>, &str) {
+    match field.split_once('.') {
+        Some((section, key)) => (Some(section), key),
+        None => (None, field),
+    }
+}
+
+/// Compare an event field value to a filter value. Numbers are compared
+/// numerically, everything else using the textual representation. Lists can't
+/// be compared.
+pub(super) fn compare(field: &EventField, value: &str) -> Option<Ordering> {
+    let val = field.as_any();
+    let int = if let Some(v) = val.downcast_ref::<u64>() {
+        Some(*v as i128)
+    } else if let Some(v) = val.downcast_ref::<u32>() {
+        Some(*v as i128)
+    } else if let Some(v) = val.downcast_ref::<i64>() {
+        Some(*v as i128)
+    } else {
+        val.downcast_ref::<i32>().map(|v| *v as i128)
+    };
+
+    if let Some(a) = int {
+        return match value.parse::<i128>() {
+            Ok(b) => Some(a.cmp(&b)),
+            Err(_) => (a as f64).partial_cmp(&value.parse::<f64>().ok()?),
+        };
+    }
+    if let Some(s) = val.downcast_ref::<String>() {
+        return Some(s.as_str().cmp(value));
+    }
+    val.downcast_ref::<bool>()
+        .map(|b| b.to_string().as_str().cmp(value))
+}
+
+impl Expr {
+    fn eval(&self, event: &Event) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(event) && b.eval(event),
+            Expr::Or(a, b) => a.eval(event) || b.eval(event),
+            Expr::Cmp {
+                section,
+                key,
+                op,
+                value,
+            } => {
+                let ord = match event
+                    .get_field(section.as_deref(), key)
+                    .and_then(|f| compare(f, value))
+                {
+                    Some(ord) => ord,
+                    None => return false,
+                };
+                match *op {
+                    "==" => ord == Ordering::Equal,
+                    "!=" => ord != Ordering::Equal,
+                    "<" => ord == Ordering::Less,
+                    "<=" => ord != Ordering::Greater,
+                    ">" => ord == Ordering::Greater,
+                    ">=" => ord != Ordering::Less,
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+/// Userspace filter, only letting through events matching an expression.
+pub(super) struct Filter {
+    expr: Expr,
+    /// Number of events which were filtered out.
+    pub(super) filtered: u64,
+}
+
+impl Filter {
+    pub(super) fn parse(def: &str) -> Result<Filter> {
+        let mut parser = Parser {
+            tokens: tokenize(def)?,
+            pos: 0,
+        };
+        let expr = parser
+            .or()
+            .or_else(|e| bail!("Invalid filter '{}': {}", def, e))?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            bail!("Invalid filter '{}': unexpected {:?}", def, token);
+        }
+
+        Ok(Filter { expr, filtered: 0 })
+    }
+
+    /// Does an event match the filter? Events not matching are accounted for.
+    pub(super) fn matches(&mut self, event: &Event) -> bool {
+        let matches = self.expr.eval(event);
+        if !matches {
+            self.filtered += 1;
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_field;
+
+    #[test]
+    fn parse() {
+        assert!(Filter::parse("l4proto == 17").is_ok());
+        assert!(Filter::parse("skb.l4proto==17&&(a.b != \"x y\" || c>=-1)").is_ok());
+
+        assert!(Filter::parse("").is_err());
+        assert!(Filter::parse("l4proto").is_err());
+        assert!(Filter::parse("l4proto ==").is_err());
+        assert!(Filter::parse("l4proto == 17 &&").is_err());
+        assert!(Filter::parse("(l4proto == 17").is_err());
+        assert!(Filter::parse("l4proto == 17)").is_err());
+        assert!(Filter::parse("l4proto && 17").is_err());
+        assert!(Filter::parse("name == \"foo").is_err());
+        assert!(Filter::parse("l4proto = 17").is_err());
+    }
+
+    #[test]
+    fn matches() -> Result<()> {
+        let mut event = Event::new();
+        event.insert("skb", event_field!("l4proto", 17u32));
+        event.insert("skb", event_field!("dport", 53u32));
+        event.insert(
+            "skb-tracking",
+            event_field!("drop_reason", "NO_SOCKET".to_string()),
+        );
+
+        let check = |def: &str| -> Result<bool> { Ok(Filter::parse(def)?.matches(&event)) };
+
+        assert!(check("l4proto == 17")?);
+        assert!(check("skb.l4proto == 17")?);
+        assert!(!check("skb-tracking.l4proto == 17")?);
+        assert!(check("drop_reason == NO_SOCKET && l4proto == 17")?);
+        assert!(check("drop_reason == 'NO_SOCKET'")?);
+        assert!(!check("drop_reason != NO_SOCKET")?);
+        assert!(check(
+            "dport < 1024 && dport >= 53 && dport > 52 && dport <= 53"
+        )?);
+        assert!(check("l4proto == 6 || dport == 53")?);
+        assert!(!check("l4proto == 6 && (dport == 53 || dport == 54)")?);
+        assert!(!check("missing == 0")?);
+        assert!(!check("missing != 0")?);
+        assert!(check("dport > -1 && dport < 53.5")?);
+
+        let mut filter = Filter::parse("l4proto == 6")?;
+        assert!(!filter.matches(&event));
+        assert!(filter.filtered == 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,752 @@
+//! # Format
+//!
+//! Formats events before they are written by a sink. Each sink has its own
+//! format, allowing e.g. human readable events on the standard output while
+//! JSON ones are written to a file.
+//!
+//! The CSV format writes a projection of the events (see --fields), one
+//! column per field. Stream sinks (standard output, files) start with a header
+//! row; meta events are not written in this format.
+//!
+//! Fields of JSON events can be renamed to match an external schema (see
+//! --rename), without changing their internal name. Combined with --fields,
+//! JSON events are written as a flat object made of the given fields, in
+//! order. Meta events are written as-is.
+//!
+//! JSON events, meta ones included, can also be wrapped in an envelope (see
+//! --envelope) holding the host name, the capture labels, the tool version and
+//! the event time at the top level, the event itself being nested under
+//! "event".
+//!
+//! The text format can write event timestamps relative to the capture start
+//! (see --relative-time), e.g. "+0.001234s", other formats always write
+//! absolute ones.
+
+use anyhow::{bail, Result};
+use nix::{
+    time::{clock_gettime, ClockId},
+    unistd::gethostname,
+};
+use serde_json::{Map, Value};
+
+use crate::core::events::{meta::META_SECTION, Event};
+
+/// Fields written in CSV when no projection is given, always first.
+const CSV_COMMON_FIELDS: &[&str] = &["common.timestamp", "common.probe"];
+
+/// Fields written in CSV when no projection is given, per collector.
+const CSV_COLLECTOR_FIELDS: &[(&str, &[&str])] = &[
+    ("skb", &["skb.len", "skb.mark", "skb.hash"]),
+    (
+        "skb-tracking",
+        &[
+            "skb-tracking.orig_head",
+            "skb-tracking.timestamp",
+            "skb-tracking.drop_reason",
+        ],
+    ),
+    ("xfrm", &["xfrm.direction", "xfrm.spi"]),
+    (
+        "bridge",
+        &["bridge.bridge", "bridge.port", "bridge.verdict"],
+    ),
+    (
+        "cgroup",
+        &[
+            "cgroup.type",
+            "cgroup.cgroup_id",
+            "cgroup.len",
+            "cgroup.daddr",
+            "cgroup.dport",
+        ],
+    ),
+    ("xdp", &["xdp.ifindex", "xdp.prog_id", "xdp.action"]),
+    (
+        "sockfilter",
+        &[
+            "sockfilter.fd",
+            "sockfilter.ifindex",
+            "sockfilter.len",
+            "sockfilter.protocol",
+        ],
+    ),
+    (
+        "tcp-retrans",
+        &[
+            "tcp-retrans.saddr",
+            "tcp-retrans.sport",
+            "tcp-retrans.daddr",
+            "tcp-retrans.dport",
+            "tcp-retrans.state",
+            "tcp-retrans.retransmits",
+        ],
+    ),
+    (
+        "sockdrop",
+        &[
+            "sockdrop.kind",
+            "sockdrop.saddr",
+            "sockdrop.sport",
+            "sockdrop.daddr",
+            "sockdrop.dport",
+        ],
+    ),
+    (
+        "netfilter",
+        &[
+            "netfilter.family",
+            "netfilter.hook",
+            "netfilter.table",
+            "netfilter.chain",
+        ],
+    ),
+    (
+        "tc-bpf",
+        &[
+            "tc-bpf.direction",
+            "tc-bpf.ifindex",
+            "tc-bpf.len",
+            "tc-bpf.protocol",
+        ],
+    ),
+    ("dropmon", &["dropmon.function"]),
+    (
+        "wireguard",
+        &["wireguard.kind", "wireguard.peer", "wireguard.endpoint"],
+    ),
+];
+
+/// Formats events can be written in.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Format {
+    /// One JSON object per event.
+    Json,
+    /// One JSON object per event, with renamed and possibly projected fields.
+    JsonMapped(JsonMapping),
+    /// One line per event, made of the event sections and their key=value
+    /// fields.
+    Text,
+    /// Same as Text, with timestamps relative to a start time (monotonic
+    /// clock, in ns).
+    TextRelative(u64),
+    /// One line per event field, with its section, key, type and value. Events
+    /// are separated by an empty line. Mostly useful for debugging.
+    Verbose,
+    /// One CSV row per event, made of the given fields ("section.key").
+    Csv(Vec<String>),
+    /// A JSON format, with events wrapped in an envelope.
+    Enveloped(Box<Format>, Envelope),
+}
+
+impl Format {
+    pub(super) fn parse(name: &str) -> Result<Format> {
+        Ok(match name {
+            "json" => Format::Json,
+            "text" => Format::Text,
+            "verbose" => Format::Verbose,
+            // Columns are set once all formats are parsed, see with_columns().
+            "csv" => Format::Csv(Vec::new()),
+            x => bail!(
+                "Unknown output format '{}' (expected json, text, verbose or csv)",
+                x
+            ),
+        })
+    }
+
+    /// Set the columns of the CSV format; other formats are left untouched.
+    pub(super) fn with_columns(self, columns: &[String]) -> Format {
+        match self {
+            Format::Csv(_) => Format::Csv(columns.to_vec()),
+            format => format,
+        }
+    }
+
+    /// Set the mapping of the JSON format, if any; other formats are left
+    /// untouched.
+    pub(super) fn with_mapping(self, mapping: &Option<JsonMapping>) -> Format {
+        match (self, mapping) {
+            (Format::Json, Some(mapping)) => Format::JsonMapped(mapping.clone()),
+            (format, _) => format,
+        }
+    }
+
+    /// Wrap JSON events in an envelope, if given; other formats are left
+    /// untouched.
+    pub(super) fn with_envelope(self, envelope: &Option<Envelope>) -> Format {
+        match (self, envelope) {
+            (format @ (Format::Json | Format::JsonMapped(_)), Some(envelope)) => {
+                Format::Enveloped(Box::new(format), envelope.clone())
+            }
+            (format, _) => format,
+        }
+    }
+
+    /// Write timestamps relative to a start time in the text format, if
+    /// given; other formats are left untouched.
+    pub(super) fn with_relative_time(self, start: Option<u64>) -> Format {
+        match (self, start) {
+            (Format::Text, Some(start)) => Format::TextRelative(start),
+            (format, _) => format,
+        }
+    }
+
+    /// Header to write before the first event, if any.
+    pub(super) fn header(&self) -> Option<String> {
+        match self {
+            Format::Csv(columns) => Some(
+                columns
+                    .iter()
+                    .map(|c| csv_escape(c))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Format an event. Sinks write a new line after each formatted event.
+    /// Returns None if the event is not written in this format.
+    pub(super) fn format(&self, event: &Event) -> Option<String> {
+        Some(match self {
+            Format::Json => event.to_json().to_string(),
+            Format::JsonMapped(mapping) => mapping.format(event),
+            Format::Text => to_text(&event.to_json(), None),
+            Format::TextRelative(start) => to_text(&event.to_json(), Some(*start)),
+            Format::Verbose => to_verbose(event),
+            Format::Csv(columns) => {
+                if event.sections().any(|s| s == META_SECTION) {
+                    return None;
+                }
+                to_csv(&event.to_json(), columns)
+            }
+            Format::Enveloped(format, envelope) => envelope.wrap(event, &format.format(event)?),
+        })
+    }
+}
+
+/// Fields of the envelope JSON events can be wrapped in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EnvelopeField {
+    Host,
+    Labels,
+    ToolVersion,
+    Timestamp,
+}
+
+impl EnvelopeField {
+    fn parse(name: &str) -> Result<EnvelopeField> {
+        Ok(match name {
+            "host" => EnvelopeField::Host,
+            "labels" => EnvelopeField::Labels,
+            "tool_version" => EnvelopeField::ToolVersion,
+            "timestamp" => EnvelopeField::Timestamp,
+            x => bail!(
+                "Unknown envelope field '{}' (expected host, labels, tool_version or timestamp)",
+                x
+            ),
+        })
+    }
+}
+
+/// Envelope JSON events are wrapped in.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Envelope {
+    /// Fields of the envelope, in order, and their names.
+    fields: Vec<(EnvelopeField, String)>,
+    host: String,
+    /// Capture labels, comma separated. Not written if empty.
+    labels: String,
+    /// Offset to convert event timestamps (monotonic clock) to Unix time.
+    realtime_offset: u64,
+}
+
+impl Envelope {
+    /// Build the envelope given its fields as "<field>[=<name>]", all fields
+    /// being written under their own name if none is given.
+    pub(super) fn new(fields: &[String], labels: &[String]) -> Result<Envelope> {
+        let fields = match fields.is_empty() {
+            true => ["host", "labels", "tool_version", "timestamp"]
+                .iter()
+                .map(|f| Ok((EnvelopeField::parse(f)?, f.to_string())))
+                .collect::<Result<Vec<_>>>()?,
+            false => fields
+                .iter()
+                .map(|f| {
+                    let (field, name) = f.split_once('=').unwrap_or((f, f));
+                    if name.is_empty() || name == "event" {
+                        bail!("Invalid envelope field name '{}'", name);
+                    }
+                    Ok((EnvelopeField::parse(field)?, name.to_string()))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+
+        let ns = |id| -> Result<u64> {
+            let ts = clock_gettime(id)?;
+            Ok(ts.tv_sec() as u64 * 1_000_000_000 + ts.tv_nsec() as u64)
+        };
+
+        Ok(Envelope {
+            fields,
+            host: gethostname()?.to_string_lossy().to_string(),
+            labels: labels.join(","),
+            realtime_offset: ns(ClockId::CLOCK_REALTIME)?
+                .saturating_sub(ns(ClockId::CLOCK_MONOTONIC)?),
+        })
+    }
+
+    /// Wrap a formatted JSON event. Its time is the event timestamp, or the
+    /// current time for events without one (e.g. meta events).
+    fn wrap(&self, event: &Event, json: &str) -> String {
+        let mut out = String::from("{");
+        for (field, name) in self.fields.iter() {
+            let val = match field {
+                EnvelopeField::Host => Value::from(self.host.as_str()),
+                EnvelopeField::Labels if self.labels.is_empty() => continue,
+                EnvelopeField::Labels => Value::from(self.labels.as_str()),
+                EnvelopeField::ToolVersion => Value::from(env!("CARGO_PKG_VERSION")),
+                EnvelopeField::Timestamp => {
+                    let ns = match event.get::<u64>("common", "timestamp") {
+                        Ok(Some(ts)) => ts + self.realtime_offset,
+                        _ => clock_gettime(ClockId::CLOCK_REALTIME).map_or(0, |ts| {
+                            ts.tv_sec() as u64 * 1_000_000_000 + ts.tv_nsec() as u64
+                        }),
+                    };
+                    Value::from(rfc3339(ns))
+                }
+            };
+            out.push_str(&format!("{}:{},", Value::from(name.as_str()), val));
+        }
+        format!("{}\"event\":{}}}", out, json)
+    }
+}
+
+/// Format a Unix time, in ns, as a RFC 3339 UTC date, e.g.
+/// "2023-11-14T22:13:20.000000001Z".
+fn rfc3339(ns: u64) -> String {
+    let secs = ns / 1_000_000_000;
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+
+    // Civil date from the number of days since the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        ns % 1_000_000_000
+    )
+}
+
+/// Renaming and projection of the fields of JSON events.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct JsonMapping {
+    /// Fields to write, in order, as "section.key". All fields are written,
+    /// in their sections, when empty.
+    fields: Vec<String>,
+    /// Renamed fields: their key, optionally prefixed by their section, and
+    /// their new name.
+    renames: Vec<(String, String)>,
+}
+
+impl JsonMapping {
+    /// Build the mapping given renames as "<field>=<name>", <field> being a
+    /// key or a "section.key", and fields to project on. Renamed fields must
+    /// be part of the projection, if any. No mapping is needed without
+    /// renames.
+    pub(super) fn new(renames: &[String], fields: &[String]) -> Result<Option<JsonMapping>> {
+        if renames.is_empty() {
+            return Ok(None);
+        }
+
+        let renames = renames
+            .iter()
+            .map(|rename| match rename.split_once('=') {
+                Some((from, to)) if !from.is_empty() && !to.is_empty() && !to.contains('=') => {
+                    if !fields.is_empty() && !fields.iter().any(|f| field_matches(f, from)) {
+                        bail!("Renamed field '{}' is not part of --fields", from);
+                    }
+                    Ok((from.to_string(), to.to_string()))
+                }
+                _ => bail!("Invalid rename '{}': expected \"<field>=<name>\"", rename),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(JsonMapping {
+            fields: fields.to_vec(),
+            renames,
+        }))
+    }
+
+    /// New name of a field, given as "section.key", if renamed. Renames
+    /// given with a section take precedence.
+    fn rename(&self, field: &str) -> Option<&str> {
+        self.renames
+            .iter()
+            .find(|(from, _)| from == field)
+            .or_else(|| {
+                self.renames
+                    .iter()
+                    .find(|(from, _)| field_matches(field, from))
+            })
+            .map(|(_, to)| to.as_str())
+    }
+
+    fn format(&self, event: &Event) -> String {
+        let json = event.to_json();
+        if event.sections().any(|s| s == META_SECTION) {
+            return json.to_string();
+        }
+
+        // Without projection, keys are renamed in their sections.
+        if self.fields.is_empty() {
+            let sections = match json.as_object() {
+                Some(sections) => sections,
+                None => return json.to_string(),
+            };
+            let mapped: Map<String, Value> = sections
+                .iter()
+                .map(|(name, section)| {
+                    let section = match section.as_object() {
+                        Some(fields) => Value::Object(
+                            fields
+                                .iter()
+                                .map(|(key, val)| {
+                                    let key = self
+                                        .rename(&format!("{}.{}", name, key))
+                                        .unwrap_or(key)
+                                        .to_string();
+                                    (key, val.clone())
+                                })
+                                .collect(),
+                        ),
+                        None => section.clone(),
+                    };
+                    (name.clone(), section)
+                })
+                .collect();
+            return Value::Object(mapped).to_string();
+        }
+
+        // JSON objects are not ordered, build the projection by hand.
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .filter_map(|field| {
+                let (section, key) = field.split_once('.')?;
+                let val = json.get(section)?.get(key)?;
+                let name = self.rename(field).unwrap_or(field);
+                Some(format!("{}:{}", Value::from(name), val))
+            })
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// Check if a field, as "section.key", is designated by a name: its key or
+/// its full name.
+fn field_matches(field: &str, name: &str) -> bool {
+    field == name || field.split_once('.').map_or(false, |(_, key)| key == name)
+}
+
+/// Columns of the CSV format: the requested fields, or the default ones of the
+/// requested collectors.
+pub(super) fn csv_columns(fields: &[String], collectors: &[String]) -> Vec<String> {
+    if !fields.is_empty() {
+        return fields.to_vec();
+    }
+
+    CSV_COMMON_FIELDS
+        .iter()
+        .chain(
+            collectors
+                .iter()
+                .filter_map(|c| CSV_COLLECTOR_FIELDS.iter().find(|(name, _)| name == c))
+                .flat_map(|(_, fields)| fields.iter()),
+        )
+        .map(|f| f.to_string())
+        .collect()
+}
+
+/// Quote a CSV value if needed, doubling its quotes.
+fn csv_escape(val: &str) -> String {
+    match val.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        true => format!("\"{}\"", val.replace('"', "\"\"")),
+        false => val.to_string(),
+    }
+}
+
+fn to_csv(json: &Value, columns: &[String]) -> String {
+    columns
+        .iter()
+        .map(|column| {
+            let val = match column.split_once('.') {
+                Some((section, key)) => json.get(section).and_then(|s| s.get(key)),
+                None => None,
+            };
+            match val {
+                // Missing fields are left empty.
+                None => String::new(),
+                Some(Value::String(s)) => csv_escape(s),
+                Some(val) => csv_escape(&val.to_string()),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn to_verbose(event: &Event) -> String {
+    event
+        .fields()
+        .iter()
+        .map(|(section, field)| {
+            format!(
+                "[{}] {} ({}): {}\n",
+                section,
+                field.key(),
+                field.type_name(),
+                field.to_json()
+            )
+        })
+        .collect()
+}
+
+/// Format a timestamp relative to a start time, in seconds, e.g. "+0.001234s".
+fn relative_time(timestamp: u64, start: u64) -> String {
+    let delta = timestamp.saturating_sub(start);
+    format!(
+        "+{}.{:06}s",
+        delta / 1_000_000_000,
+        delta % 1_000_000_000 / 1000
+    )
+}
+
+fn to_text(json: &Value, start: Option<u64>) -> String {
+    let sections = match json.as_object() {
+        Some(sections) => sections,
+        None => return json.to_string(),
+    };
+
+    sections
+        .iter()
+        .map(|(name, section)| {
+            let fields = match section.as_object() {
+                Some(fields) => fields
+                    .iter()
+                    .map(|(key, val)| match (val, start) {
+                        (Value::Number(ts), Some(start))
+                            if name == "common" && key == "timestamp" =>
+                        {
+                            match ts.as_u64() {
+                                Some(ts) => format!(" {}={}", key, relative_time(ts, start)),
+                                None => format!(" {}={}", key, val),
+                            }
+                        }
+                        (Value::String(s), _) => format!(" {}={}", key, s),
+                        (val, _) => format!(" {}={}", key, val),
+                    })
+                    .collect::<String>(),
+                None => format!(" {}", section),
+            };
+            format!("[{}]{}", name, fields)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::EventField;
+    use crate::event_field;
+
+    #[test]
+    fn format() -> Result<()> {
+        assert!(Format::parse("json")? == Format::Json);
+        assert!(Format::parse("text")? == Format::Text);
+        assert!(Format::parse("verbose")? == Format::Verbose);
+        assert!(Format::parse("csv")? == Format::Csv(Vec::new()));
+        assert!(Format::parse("binary").is_err());
+
+        let mut event = Event::new();
+        event.insert("common", event_field!("symbol", "kfree_skb".to_string()));
+        event.insert("skb", event_field!("len", 84u32));
+        event.insert("skb", event_field!("mark", 1u32));
+
+        assert!(
+            Format::Json.format(&event).unwrap()
+                == r#"{"common":{"symbol":"kfree_skb"},"skb":{"len":84,"mark":1}}"#
+        );
+        assert!(
+            Format::Text.format(&event).unwrap() == "[common] symbol=kfree_skb [skb] len=84 mark=1"
+        );
+        assert!(
+            Format::Verbose.format(&event).unwrap()
+                == "[common] symbol (String): \"kfree_skb\"\n\
+                    [skb] len (u32): 84\n\
+                    [skb] mark (u32): 1\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn csv() -> Result<()> {
+        let columns = vec![
+            "common.symbol".to_string(),
+            "skb.len".to_string(),
+            "skb.mark".to_string(),
+        ];
+        let format = Format::parse("csv")?.with_columns(&columns);
+        assert!(format.header().unwrap() == "common.symbol,skb.len,skb.mark");
+        assert!(Format::Json.with_columns(&columns) == Format::Json);
+
+        // Missing fields are empty, strings are quoted when needed.
+        let mut event = Event::new();
+        event.insert("common", event_field!("symbol", "a,\"b\"".to_string()));
+        event.insert("skb", event_field!("len", 84u32));
+        assert!(format.format(&event).unwrap() == r#""a,""b""",84,"#);
+
+        // Meta events are not written.
+        let mut event = Event::new();
+        event.insert(META_SECTION, event_field!("type", "start".to_string()));
+        assert!(format.format(&event).is_none());
+
+        // Default columns, per requested collector.
+        let collectors = vec!["xfrm".to_string(), "kprobe".to_string()];
+        assert!(
+            csv_columns(&[], &collectors)
+                == vec![
+                    "common.timestamp",
+                    "common.probe",
+                    "xfrm.direction",
+                    "xfrm.spi"
+                ]
+        );
+        assert!(csv_columns(&columns, &collectors) == columns);
+        Ok(())
+    }
+
+    #[test]
+    fn text_relative() {
+        let mut event = Event::new();
+        event.insert("common", event_field!("timestamp", 5_001_234_567u64));
+        event.insert("skb", event_field!("len", 84u32));
+
+        let format = Format::Text.with_relative_time(Some(4_000_000_000));
+        assert!(format.format(&event).unwrap() == "[common] timestamp=+1.001234s [skb] len=84");
+        assert!(
+            Format::Text
+                .with_relative_time(None)
+                .format(&event)
+                .unwrap()
+                == "[common] timestamp=5001234567 [skb] len=84"
+        );
+        assert!(Format::Json.with_relative_time(Some(0)) == Format::Json);
+
+        // Events preceding the start are not reported in the future.
+        assert!(relative_time(1, 2) == "+0.000000s");
+    }
+
+    #[test]
+    fn json_mapping() -> Result<()> {
+        let renames = vec!["saddr=src_ip".to_string(), "tcp.daddr=dst_ip".to_string()];
+        assert!(JsonMapping::new(&[], &[])?.is_none());
+        assert!(JsonMapping::new(&["saddr".to_string()], &[]).is_err());
+        assert!(JsonMapping::new(&["=src_ip".to_string()], &[]).is_err());
+        assert!(JsonMapping::new(&renames, &["skb.len".to_string()]).is_err());
+
+        let mut event = Event::new();
+        event.insert("tcp", event_field!("saddr", "10.0.0.1".to_string()));
+        event.insert("tcp", event_field!("daddr", "10.0.0.2".to_string()));
+        event.insert("skb", event_field!("len", 84u32));
+
+        // Renamed in their sections.
+        let format = Format::Json.with_mapping(&JsonMapping::new(&renames, &[])?);
+        assert!(
+            format.format(&event).unwrap()
+                == r#"{"skb":{"len":84},"tcp":{"dst_ip":"10.0.0.2","src_ip":"10.0.0.1"}}"#
+        );
+        assert!(Format::Text.with_mapping(&JsonMapping::new(&renames, &[])?) == Format::Text);
+
+        // Projected, in order.
+        let fields = vec![
+            "skb.len".to_string(),
+            "tcp.daddr".to_string(),
+            "tcp.saddr".to_string(),
+            "tcp.dport".to_string(),
+        ];
+        let format = Format::Json.with_mapping(&JsonMapping::new(&renames, &fields)?);
+        assert!(
+            format.format(&event).unwrap()
+                == r#"{"skb.len":84,"dst_ip":"10.0.0.2","src_ip":"10.0.0.1"}"#
+        );
+
+        // Meta events are not mapped.
+        let mut event = Event::new();
+        event.insert(META_SECTION, event_field!("type", "start".to_string()));
+        assert!(format.format(&event).unwrap() == r#"{"meta":{"type":"start"}}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn envelope() -> Result<()> {
+        assert!(Envelope::new(&[], &[])?.fields.len() == 4);
+        assert!(Envelope::new(&["hostname".to_string()], &[]).is_err());
+        assert!(Envelope::new(&["host=event".to_string()], &[]).is_err());
+        assert!(Format::Text.with_envelope(&Some(Envelope::new(&[], &[])?)) == Format::Text);
+
+        let mut envelope = Envelope::new(
+            &[
+                "host=hostname".to_string(),
+                "timestamp=@timestamp".to_string(),
+                "labels".to_string(),
+            ],
+            &["incident-4567".to_string()],
+        )?;
+        envelope.host = "node-a".to_string();
+        envelope.realtime_offset = 1_700_000_000_000_000_000;
+
+        let mut event = Event::new();
+        event.insert("common", event_field!("timestamp", 42u64));
+        let format = Format::Json.with_envelope(&Some(envelope.clone()));
+        assert!(
+            format.format(&event).unwrap()
+                == r#"{"hostname":"node-a","@timestamp":"2023-11-14T22:13:20.000000042Z","labels":"incident-4567","event":{"common":{"timestamp":42}}}"#
+        );
+
+        // Labels are omitted when none were given, mapped events are wrapped
+        // once mapped.
+        envelope.labels = String::new();
+        let format = Format::Json
+            .with_mapping(&JsonMapping::new(
+                &["timestamp=ts".to_string()],
+                &["common.timestamp".to_string()],
+            )?)
+            .with_envelope(&Some(envelope));
+        assert!(format
+            .format(&event)
+            .unwrap()
+            .ends_with(r#"Z","event":{"ts":42}}"#));
+        Ok(())
+    }
+
+    #[test]
+    fn dates() {
+        assert!(rfc3339(0) == "1970-01-01T00:00:00.000000000Z");
+        assert!(rfc3339(951_782_400_000_000_001) == "2000-02-29T00:00:00.000000001Z");
+        assert!(rfc3339(1_700_000_000_123_000_000) == "2023-11-14T22:13:20.123000000Z");
+    }
+}
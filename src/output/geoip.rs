@@ -0,0 +1,604 @@
+//! # GeoIP
+//!
+//! Annotate public addresses with their country and autonomous system, using
+//! local databases in the MaxMind DB format (e.g. GeoLite2-Country and
+//! GeoLite2-ASN, see --geoip-db). No external service is queried. Annotations
+//! are reported in fields named after the address one, "saddr" and "daddr"
+//! being shortened to "src" and "dst": e.g. src_country, dst_asn and
+//! dst_as_org. Private, loopback, link-local and multicast addresses are
+//! skipped, as are addresses not found in the databases.
+//!
+//! Lookups are cached, the cache being cleared once it holds CACHE_MAX
+//! addresses. Only the parts of the format needed for lookups are supported.
+//! Malformed databases are rejected when possible, otherwise their malformed
+//! entries are handled as missing ones.
+
+use std::{collections::HashMap, fs, net::IpAddr};
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+
+use super::{geoip_fields, ADDRESS_FIELDS};
+use crate::{
+    core::events::{Event, EventField},
+    event_field,
+};
+
+/// Maximum number of cached addresses.
+const CACHE_MAX: usize = 4096;
+
+/// Marker found right before the database metadata.
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+/// Size of the separator between the search tree and the data section.
+const DATA_SEPARATOR_SIZE: usize = 16;
+/// Maximum nesting of decoded values, to cope with malformed databases.
+const DECODE_DEPTH_MAX: usize = 32;
+
+// Data types, see the MaxMind DB specification.
+const TYPE_EXTENDED: u8 = 0;
+const TYPE_POINTER: u8 = 1;
+const TYPE_STRING: u8 = 2;
+const TYPE_DOUBLE: u8 = 3;
+const TYPE_BYTES: u8 = 4;
+const TYPE_UINT16: u8 = 5;
+const TYPE_UINT32: u8 = 6;
+const TYPE_MAP: u8 = 7;
+const TYPE_INT32: u8 = 8;
+const TYPE_UINT64: u8 = 9;
+const TYPE_UINT128: u8 = 10;
+const TYPE_ARRAY: u8 = 11;
+const TYPE_BOOLEAN: u8 = 14;
+const TYPE_FLOAT: u8 = 15;
+
+/// Read a big endian unsigned integer of up to 8 bytes.
+fn read_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| acc << 8 | *b as u64)
+}
+
+/// Decoder of the values of a data section (or of the metadata).
+struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| anyhow!("Truncated data at offset {}", offset))
+    }
+
+    /// Decode the value at `offset`, returning it along with the offset
+    /// following it. Unsupported values (bytes, 128 bits integers) are
+    /// decoded as null.
+    fn decode(&self, offset: usize, depth: usize) -> Result<(Value, usize)> {
+        if depth > DECODE_DEPTH_MAX {
+            bail!("Data nested too deeply at offset {}", offset);
+        }
+
+        let ctrl = self.bytes(offset, 1)?[0];
+        let mut offset = offset + 1;
+        let mut r#type = ctrl >> 5;
+
+        if r#type == TYPE_POINTER {
+            let len = ((ctrl >> 3) & 0x3) as usize + 1;
+            let (raw, high) = (read_uint(self.bytes(offset, len)?), (ctrl & 0x7) as u64);
+            let target = match len {
+                1 => high << 8 | raw,
+                2 => (high << 16 | raw) + 2048,
+                3 => (high << 24 | raw) + 526336,
+                _ => raw,
+            };
+            let (value, _) = self.decode(target as usize, depth + 1)?;
+            return Ok((value, offset + len));
+        }
+        if r#type == TYPE_EXTENDED {
+            r#type = 7 + self.bytes(offset, 1)?[0];
+            offset += 1;
+        }
+
+        let mut size = (ctrl & 0x1f) as usize;
+        if size >= 29 {
+            let len = size - 28;
+            let raw = read_uint(self.bytes(offset, len)?) as usize;
+            size = match len {
+                1 => 29 + raw,
+                2 => 285 + raw,
+                _ => 65821 + raw,
+            };
+            offset += len;
+        }
+
+        let value = match r#type {
+            TYPE_STRING => {
+                Value::String(String::from_utf8_lossy(self.bytes(offset, size)?).to_string())
+            }
+            TYPE_DOUBLE | TYPE_FLOAT => {
+                let raw = self.bytes(offset, size)?;
+                match (r#type, raw.len()) {
+                    (TYPE_DOUBLE, 8) => Value::from(f64::from_bits(read_uint(raw))),
+                    (TYPE_FLOAT, 4) => Value::from(f32::from_bits(read_uint(raw) as u32)),
+                    _ => bail!("Invalid floating point size {} at offset {}", size, offset),
+                }
+            }
+            TYPE_UINT16 | TYPE_UINT32 | TYPE_UINT64 | TYPE_INT32 => {
+                if size > 8 {
+                    bail!("Invalid integer size {} at offset {}", size, offset);
+                }
+                let raw = read_uint(self.bytes(offset, size)?);
+                match r#type {
+                    TYPE_INT32 => Value::from(raw as u32 as i32),
+                    _ => Value::from(raw),
+                }
+            }
+            TYPE_BYTES | TYPE_UINT128 => {
+                self.bytes(offset, size)?;
+                Value::Null
+            }
+            TYPE_MAP => {
+                let mut map = Map::new();
+                for _ in 0..size {
+                    let (key, next) = self.decode(offset, depth + 1)?;
+                    let (value, next) = self.decode(next, depth + 1)?;
+                    match key {
+                        Value::String(key) => map.insert(key, value),
+                        _ => bail!("Invalid map key at offset {}", offset),
+                    };
+                    offset = next;
+                }
+                return Ok((Value::Object(map), offset));
+            }
+            TYPE_ARRAY => {
+                let mut array = Vec::new();
+                for _ in 0..size {
+                    let (value, next) = self.decode(offset, depth + 1)?;
+                    array.push(value);
+                    offset = next;
+                }
+                return Ok((Value::Array(array), offset));
+            }
+            // The size holds the value.
+            TYPE_BOOLEAN => return Ok((Value::Bool(size != 0), offset)),
+            x => bail!("Unsupported data type {} at offset {}", x, offset),
+        };
+        Ok((value, offset + size))
+    }
+}
+
+/// MaxMind DB database, fully loaded in memory.
+struct GeoDb {
+    buf: Vec<u8>,
+    node_count: u32,
+    /// Size of a search tree record, in bits.
+    record_size: u16,
+    ip_version: u16,
+    /// Size of the search tree, the data section following it (after the
+    /// separator) up to the metadata marker.
+    tree_size: usize,
+    data_end: usize,
+    /// Node IPv4 addresses lookups start at in IPv6 databases.
+    ipv4_start: u32,
+}
+
+impl GeoDb {
+    fn open(path: &str) -> Result<GeoDb> {
+        let buf = fs::read(path).or_else(|e| bail!("Could not read {}: {}", path, e))?;
+        Self::parse(buf).or_else(|e| bail!("Invalid GeoIP database {}: {}", path, e))
+    }
+
+    fn parse(buf: Vec<u8>) -> Result<GeoDb> {
+        let data_end = buf
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+            .ok_or_else(|| anyhow!("No metadata found"))?;
+
+        let metadata = Decoder {
+            data: &buf[data_end + METADATA_MARKER.len()..],
+        }
+        .decode(0, 0)?
+        .0;
+        let get = |key: &str| {
+            metadata
+                .get(key)
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| anyhow!("Invalid or missing metadata {}", key))
+        };
+
+        let node_count = get("node_count")? as u32;
+        let record_size = get("record_size")? as u16;
+        let ip_version = get("ip_version")? as u16;
+        if !matches!(record_size, 24 | 28 | 32) {
+            bail!("Unsupported record size {}", record_size);
+        }
+        if !matches!(ip_version, 4 | 6) {
+            bail!("Unsupported IP version {}", ip_version);
+        }
+
+        let tree_size = record_size as usize / 4 * node_count as usize;
+        if tree_size + DATA_SEPARATOR_SIZE > data_end {
+            bail!("Truncated search tree");
+        }
+
+        let mut db = GeoDb {
+            buf,
+            node_count,
+            record_size,
+            ip_version,
+            tree_size,
+            data_end,
+            ipv4_start: 0,
+        };
+        // IPv4 addresses are stored as ::a.b.c.d in IPv6 databases.
+        if ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = db.record(node, false)?;
+            }
+            db.ipv4_start = node;
+        }
+        Ok(db)
+    }
+
+    /// Retrieve the left or right record of a search tree node.
+    fn record(&self, node: u32, right: bool) -> Result<u32> {
+        let node_size = self.record_size as usize / 4;
+        let start = node as usize * node_size;
+        let raw = self
+            .buf
+            .get(start..start + node_size)
+            .filter(|_| start + node_size <= self.tree_size)
+            .ok_or_else(|| anyhow!("Invalid node {}", node))?;
+
+        let record = match (self.record_size, right) {
+            (28, false) => (raw[3] as u64 & 0xf0) << 20 | read_uint(&raw[..3]),
+            (28, true) => (raw[3] as u64 & 0x0f) << 24 | read_uint(&raw[4..]),
+            (_, false) => read_uint(&raw[..node_size / 2]),
+            (_, true) => read_uint(&raw[node_size / 2..]),
+        };
+        Ok(record as u32)
+    }
+
+    /// Look an address up, returning its data if found.
+    fn lookup(&self, addr: &IpAddr) -> Result<Option<Value>> {
+        let (bytes, mut node) = match addr {
+            IpAddr::V4(v4) => (v4.octets().to_vec(), self.ipv4_start),
+            IpAddr::V6(_) if self.ip_version == 4 => return Ok(None),
+            IpAddr::V6(v6) => (v6.octets().to_vec(), 0),
+        };
+
+        for i in 0..bytes.len() * 8 {
+            if node >= self.node_count {
+                break;
+            }
+            node = self.record(node, (bytes[i / 8] >> (7 - i % 8)) & 1 == 1)?;
+        }
+        if node <= self.node_count {
+            return Ok(None);
+        }
+
+        let decoder = Decoder {
+            data: &self.buf[self.tree_size + DATA_SEPARATOR_SIZE..self.data_end],
+        };
+        // Data pointers are offset by the separator size, pointing into it
+        // means the database is corrupted.
+        let offset = ((node - self.node_count) as usize)
+            .checked_sub(DATA_SEPARATOR_SIZE)
+            .ok_or_else(|| anyhow!("Invalid data pointer {} in node", node))?;
+        Ok(Some(decoder.decode(offset, 0)?.0))
+    }
+}
+
+/// Annotations of an address.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct GeoInfo {
+    /// ISO 3166-1 country code.
+    country: Option<String>,
+    asn: Option<u32>,
+    as_org: Option<String>,
+}
+
+impl GeoInfo {
+    /// Merge the data found in a database.
+    fn merge(&mut self, data: &Value) {
+        let str_at = |path: &[&str]| {
+            path.iter()
+                .try_fold(data, |v, key| v.get(key))
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+        };
+
+        if self.country.is_none() {
+            self.country = str_at(&["country", "iso_code"])
+                .or_else(|| str_at(&["registered_country", "iso_code"]));
+        }
+        if self.asn.is_none() {
+            self.asn = data
+                .get("autonomous_system_number")
+                .and_then(|v| v.as_u64())
+                .map(|asn| asn as u32);
+        }
+        if self.as_org.is_none() {
+            self.as_org = str_at(&["autonomous_system_organization"]);
+        }
+    }
+}
+
+pub(super) struct GeoIp {
+    dbs: Vec<GeoDb>,
+    /// Lookup results, None for addresses not found in any database.
+    cache: HashMap<IpAddr, Option<GeoInfo>>,
+}
+
+impl GeoIp {
+    pub(super) fn new(paths: &[String]) -> Result<GeoIp> {
+        Ok(GeoIp {
+            dbs: paths
+                .iter()
+                .map(|path| GeoDb::open(path))
+                .collect::<Result<_>>()?,
+            cache: HashMap::new(),
+        })
+    }
+
+    /// Annotate the public addresses of an event found in the databases.
+    pub(super) fn annotate(&mut self, event: &mut Event) {
+        for (section, key) in ADDRESS_FIELDS.iter() {
+            let addr = match event.get::<String>(section, key) {
+                Ok(Some(addr)) => match addr.parse::<IpAddr>() {
+                    Ok(addr) => addr,
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+
+            let info = match self.info(addr) {
+                Some(info) => info,
+                None => continue,
+            };
+            let [country_key, asn_key, as_org_key] = geoip_fields(key);
+            if let Some(country) = info.country {
+                event.insert(section, event_field!(&country_key, country));
+            }
+            if let Some(asn) = info.asn {
+                event.insert(section, event_field!(&asn_key, asn));
+            }
+            if let Some(as_org) = info.as_org {
+                event.insert(section, event_field!(&as_org_key, as_org));
+            }
+        }
+    }
+
+    fn info(&mut self, addr: IpAddr) -> Option<GeoInfo> {
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(addr),
+            addr => addr,
+        };
+        if !is_public(&addr) {
+            return None;
+        }
+
+        if let Some(info) = self.cache.get(&addr) {
+            return info.clone();
+        }
+
+        let mut info = GeoInfo::default();
+        // Malformed entries are handled as missing ones.
+        self.dbs
+            .iter()
+            .filter_map(|db| db.lookup(&addr).ok().flatten())
+            .for_each(|data| info.merge(&data));
+        let info = (info != GeoInfo::default()).then_some(info);
+
+        if self.cache.len() >= CACHE_MAX {
+            self.cache.clear();
+        }
+        self.cache.insert(addr, info.clone());
+        info
+    }
+}
+
+/// Is an address globally routable? Private, shared (CGNAT), loopback,
+/// link-local, multicast and unspecified addresses are not.
+fn is_public(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => {
+            let octets = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_multicast()
+                || v4.is_broadcast()
+                || v4.is_unspecified()
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_multicast()
+                || v6.is_unspecified()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut raw = vec![TYPE_STRING << 5 | s.len() as u8];
+        raw.extend_from_slice(s.as_bytes());
+        raw
+    }
+
+    fn uint(r#type: u8, val: u32, size: usize) -> Vec<u8> {
+        let mut raw = vec![r#type << 5 | size as u8];
+        raw.extend_from_slice(&val.to_be_bytes()[4 - size..]);
+        raw
+    }
+
+    fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut raw = vec![TYPE_MAP << 5 | entries.len() as u8];
+        entries.iter().for_each(|(key, value)| {
+            raw.extend(string(key));
+            raw.extend_from_slice(value);
+        });
+        raw
+    }
+
+    // IPv4 database with a single 1.0.0.0/8 network.
+    fn database() -> Vec<u8> {
+        let node_count = 8;
+
+        // Nodes follow the bits of 00000001, other branches are empty.
+        let mut buf = Vec::new();
+        for node in 0..node_count {
+            let next = match node {
+                7 => node_count + DATA_SEPARATOR_SIZE as u32,
+                _ => node + 1,
+            };
+            let (left, right) = match node {
+                7 => (node_count, next),
+                _ => (next, node_count),
+            };
+            buf.extend_from_slice(&left.to_be_bytes()[1..]);
+            buf.extend_from_slice(&right.to_be_bytes()[1..]);
+        }
+        buf.extend_from_slice(&[0; DATA_SEPARATOR_SIZE]);
+
+        // The AS organization points to the country code.
+        let country = string("AU");
+        let mut data = vec![TYPE_MAP << 5 | 3];
+        data.extend(string("country"));
+        let iso_code = data.len() + 1 + "iso_code".len() + 1;
+        data.extend(map(&[("iso_code", country)]));
+        data.extend(string("autonomous_system_number"));
+        data.extend(uint(TYPE_UINT32, 13335, 2));
+        data.extend(string("autonomous_system_organization"));
+        data.extend_from_slice(&[TYPE_POINTER << 5, iso_code as u8]);
+        buf.extend(data);
+
+        buf.extend_from_slice(METADATA_MARKER);
+        buf.extend(map(&[
+            ("node_count", uint(TYPE_UINT32, node_count, 1)),
+            ("record_size", uint(TYPE_UINT16, 24, 1)),
+            ("ip_version", uint(TYPE_UINT16, 4, 1)),
+        ]));
+        buf
+    }
+
+    #[test]
+    fn lookup() -> Result<()> {
+        let db = GeoDb::parse(database())?;
+        let data = db.lookup(&"1.1.1.1".parse()?)?.unwrap();
+        assert!(data["country"]["iso_code"] == "AU");
+        assert!(data["autonomous_system_number"] == 13335);
+        assert!(data["autonomous_system_organization"] == "AU");
+
+        assert!(db.lookup(&"2.1.1.1".parse()?)?.is_none());
+        assert!(db.lookup(&"2001:db8::1".parse()?)?.is_none());
+
+        assert!(GeoDb::parse(b"not a database".to_vec()).is_err());
+        let mut truncated = database();
+        truncated.drain(..100);
+        assert!(GeoDb::parse(truncated).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt() -> Result<()> {
+        // Offset of the data section (8 nodes of 6 bytes and the separator),
+        // which is 83 bytes long.
+        let data = 48 + DATA_SEPARATOR_SIZE;
+        let addr = "1.1.1.1".parse()?;
+        let corrupt = |f: &dyn Fn(&mut Vec<u8>)| {
+            let mut buf = database();
+            f(&mut buf);
+            buf
+        };
+
+        // Data pointer into the separator.
+        let db = GeoDb::parse(corrupt(&|buf| buf[45..48].copy_from_slice(&[0, 0, 9])))?;
+        assert!(db.lookup(&addr).is_err());
+
+        // Truncated data section.
+        let db = GeoDb::parse(corrupt(&|buf| {
+            buf.drain(data + 60..data + 83);
+        }))?;
+        assert!(db.lookup(&addr).is_err());
+
+        // Value size past the end of the data.
+        let db = GeoDb::parse(corrupt(&|buf| buf[data] = TYPE_STRING << 5 | 31))?;
+        assert!(db.lookup(&addr).is_err());
+
+        // Pointer to itself.
+        let db = GeoDb::parse(corrupt(&|buf| {
+            buf[data..data + 2].copy_from_slice(&[TYPE_POINTER << 5, 0])
+        }))?;
+        assert!(db.lookup(&addr).is_err());
+
+        // Search tree larger than the database.
+        assert!(GeoDb::parse(corrupt(&|buf| {
+            buf.drain(10..data + 83);
+        }))
+        .is_err());
+
+        // Malformed entries are handled as missing ones.
+        let mut geoip = GeoIp {
+            dbs: vec![GeoDb::parse(corrupt(&|buf| {
+                buf.drain(data + 60..data + 83);
+            }))?],
+            cache: HashMap::new(),
+        };
+        assert!(geoip.info(addr).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn annotate() -> Result<()> {
+        let mut geoip = GeoIp {
+            dbs: vec![GeoDb::parse(database())?],
+            cache: HashMap::new(),
+        };
+
+        let mut event = Event::new();
+        event.insert("tcp-retrans", event_field!("saddr", "10.0.0.1".to_string()));
+        event.insert("tcp-retrans", event_field!("daddr", "1.1.1.1".to_string()));
+        event.insert(
+            "cgroup",
+            event_field!("daddr", "::ffff:1.0.0.1".to_string()),
+        );
+        geoip.annotate(&mut event);
+
+        let get = |section, key| event.get::<String>(section, key);
+        assert!(get("tcp-retrans", "dst_country")? == Some(&"AU".to_string()));
+        assert!(event.get::<u32>("tcp-retrans", "dst_asn")? == Some(&13335));
+        assert!(get("tcp-retrans", "dst_as_org")? == Some(&"AU".to_string()));
+        assert!(get("cgroup", "dst_country")? == Some(&"AU".to_string()));
+        // Private addresses are skipped.
+        assert!(get("tcp-retrans", "src_country")?.is_none());
+        assert!(geoip.cache.len() == 1);
+        Ok(())
+    }
+
+    #[test]
+    fn public() {
+        for addr in [
+            "10.1.2.3",
+            "100.64.0.1",
+            "127.0.0.1",
+            "169.254.0.1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(!is_public(&addr.parse().unwrap()));
+        }
+        for addr in ["1.1.1.1", "100.128.0.1", "2606:4700::1111"] {
+            assert!(is_public(&addr.parse().unwrap()));
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! # Histogram
+//!
+//! Aggregates numeric event fields (e.g. latencies or lengths) into log2
+//! histograms instead of writing events, and writes the histograms once the
+//! collection ends, as text or JSON. Histograms can be grouped by the value of
+//! another field (e.g. per datapath). Events not having a numeric value for a
+//! field are not accounted for in its histogram.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use super::{
+    filter::{get_field, split_field},
+    EventOutput,
+};
+use crate::core::events::{meta::META_SECTION, Event};
+
+/// Number of buckets: one for 0, then one per power of 2.
+const BUCKETS: usize = 65;
+/// Width of the text histogram bars.
+const BAR_WIDTH: u64 = 40;
+
+/// Output format of the histograms.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum HistogramFormat {
+    Text,
+    Json,
+}
+
+impl HistogramFormat {
+    pub(super) fn parse(name: &str) -> Result<HistogramFormat> {
+        Ok(match name {
+            "text" => HistogramFormat::Text,
+            "json" => HistogramFormat::Json,
+            x => bail!("Unknown histogram format {}", x),
+        })
+    }
+}
+
+/// Bucket of a value: 0 for 0, then i for values in [2^(i-1), 2^i).
+fn bucket(value: u64) -> usize {
+    (u64::BITS - value.leading_zeros()) as usize
+}
+
+/// Lower (inclusive) and upper (exclusive) bounds of a bucket. The upper bound
+/// of the last bucket does not fit in an u64 and is not reported.
+fn bucket_range(bucket: usize) -> (u64, Option<u64>) {
+    match bucket {
+        0 => (0, Some(1)),
+        b => (1 << (b - 1), 1u64.checked_shl(b as u32)),
+    }
+}
+
+/// Log2 histogram of a single field, for a single group.
+struct Hist([u64; BUCKETS]);
+
+impl Hist {
+    /// First non-empty bucket.
+    fn first(&self) -> usize {
+        self.0.iter().position(|c| *c != 0).unwrap_or(0)
+    }
+
+    /// Used buckets, from the first to the last non-empty one.
+    fn used(&self) -> &[u64] {
+        let last = self.0.iter().rposition(|c| *c != 0).unwrap_or(0);
+        &self.0[self.first()..=last]
+    }
+
+    fn to_json(&self) -> Value {
+        let first = self.first();
+        Value::Array(
+            self.used()
+                .iter()
+                .enumerate()
+                .map(|(i, count)| {
+                    let (min, max) = bucket_range(first + i);
+                    json!({ "min": min, "max": max, "count": count })
+                })
+                .collect(),
+        )
+    }
+
+    fn to_text(&self) -> String {
+        let first = self.first();
+        let max = self.0.iter().max().copied().unwrap_or(0).max(1);
+
+        self.used()
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let range = match bucket_range(first + i) {
+                    (0, _) => "[0]".to_string(),
+                    (1, _) => "[1]".to_string(),
+                    (min, Some(max)) => format!("[{}, {})", min, max),
+                    (min, None) => format!("[{}, ...)", min),
+                };
+                let bar = "@".repeat((count * BAR_WIDTH / max) as usize);
+                format!(
+                    "{:<24} {:>10} |{:<width$}|\n",
+                    range,
+                    count,
+                    bar,
+                    width = BAR_WIDTH as usize
+                )
+            })
+            .collect()
+    }
+}
+
+pub(super) struct Histograms {
+    /// Fields to aggregate, as given by the user.
+    fields: Vec<String>,
+    /// Optional field to group the histograms by.
+    group_by: Option<String>,
+    format: HistogramFormat,
+    /// Histograms, by field then by group.
+    hists: BTreeMap<String, BTreeMap<String, Hist>>,
+}
+
+impl Histograms {
+    pub(super) fn new(
+        fields: Vec<String>,
+        group_by: Option<String>,
+        format: HistogramFormat,
+    ) -> Histograms {
+        Histograms {
+            fields,
+            group_by,
+            format,
+            hists: BTreeMap::new(),
+        }
+    }
+
+    fn report_json(&self) -> Value {
+        Value::Array(
+            self.hists
+                .iter()
+                .flat_map(|(field, groups)| {
+                    groups
+                        .iter()
+                        .map(move |(group, hist)| match &self.group_by {
+                            Some(_) => json!({
+                                "field": field,
+                                "group": group,
+                                "buckets": hist.to_json(),
+                            }),
+                            None => json!({ "field": field, "buckets": hist.to_json() }),
+                        })
+                })
+                .collect(),
+        )
+    }
+
+    fn report_text(&self) -> String {
+        let mut report = String::new();
+        for (field, groups) in self.hists.iter() {
+            for (group, hist) in groups.iter() {
+                match &self.group_by {
+                    Some(_) => report.push_str(&format!("@{}[{}]:\n", field, group)),
+                    None => report.push_str(&format!("@{}:\n", field)),
+                }
+                report.push_str(&hist.to_text());
+                report.push('\n');
+            }
+        }
+        report
+    }
+}
+
+impl EventOutput for Histograms {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        let json = event.to_json();
+        // Meta events are not accounted for.
+        if json.get(META_SECTION).is_some() {
+            return Ok(());
+        }
+
+        let group = match &self.group_by {
+            Some(group_by) => {
+                let (section, key) = split_field(group_by);
+                match get_field(&json, section, key) {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(val) => val.to_string(),
+                    None => return Ok(()),
+                }
+            }
+            None => String::new(),
+        };
+
+        for field in self.fields.iter() {
+            let (section, key) = split_field(field);
+            if let Some(value) = get_field(&json, section, key).and_then(|v| v.as_u64()) {
+                self.hists
+                    .entry(field.clone())
+                    .or_default()
+                    .entry(group.clone())
+                    .or_insert(Hist([0; BUCKETS]))
+                    .0[bucket(value)] += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let mut stdout = io::stdout();
+        match self.format {
+            HistogramFormat::Text => write!(stdout, "{}", self.report_text())?,
+            HistogramFormat::Json => writeln!(stdout, "{}", self.report_json())?,
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::EventField;
+    use crate::event_field;
+
+    #[test]
+    fn buckets() {
+        assert!(bucket(0) == 0);
+        assert!(bucket(1) == 1);
+        assert!(bucket(2) == 2 && bucket(3) == 2);
+        assert!(bucket(1024) == 11);
+        assert!(bucket(u64::MAX) == 64);
+
+        assert!(bucket_range(0) == (0, Some(1)));
+        assert!(bucket_range(2) == (2, Some(4)));
+        assert!(bucket_range(64) == (1 << 63, None));
+    }
+
+    #[test]
+    fn histograms() -> Result<()> {
+        let mut hists = Histograms::new(
+            vec!["ovs.latency".to_string()],
+            Some("dp".to_string()),
+            HistogramFormat::Json,
+        );
+
+        for (latency, dp) in [(3u64, "a"), (2, "a"), (9, "a"), (100, "b")] {
+            let mut event = Event::new();
+            event.insert("ovs", event_field!("latency", latency));
+            event.insert("ovs", event_field!("dp", dp.to_string()));
+            hists.output(&event)?;
+        }
+        // Events without the field or the group are not accounted for.
+        hists.output(&Event::new())?;
+
+        assert!(
+            hists.report_json()
+                == json!([
+                    {
+                        "field": "ovs.latency",
+                        "group": "a",
+                        "buckets": [
+                            { "min": 2, "max": 4, "count": 2 },
+                            { "min": 4, "max": 8, "count": 0 },
+                            { "min": 8, "max": 16, "count": 1 },
+                        ],
+                    },
+                    {
+                        "field": "ovs.latency",
+                        "group": "b",
+                        "buckets": [{ "min": 64, "max": 128, "count": 1 }],
+                    },
+                ])
+        );
+
+        let text = hists.report_text();
+        assert!(text.starts_with("@ovs.latency[a]:\n[2, 4)"));
+        assert!(text.contains(&format!("|{}|", "@".repeat(BAR_WIDTH as usize))));
+        Ok(())
+    }
+}
@@ -0,0 +1,528 @@
+//! # IPFIX export
+//!
+//! Aggregates events into unidirectional flow records and exports them as
+//! IPFIX (RFC 7011) messages over UDP, for flow collectors and NMS tools.
+//! Flows are identified by their 5-tuple, decoded from the packet bytes (see
+//! --capture-bytes); they hold their packet and byte (skb length) counts, the
+//! time of their first and last packets and the TCP flags seen.
+//!
+//! Packets are identified using the skb-tracking collector and accounted for
+//! once even when seen by multiple probes, as long as those are within the
+//! same IPFIX_SCAN_INTERVAL; events without tracking information are each
+//! accounted for as a packet.
+//!
+//! Flows are exported once inactive for the idle timeout, shortly after a TCP
+//! FIN or RST was seen, and when the collection ends; the reason being
+//! reported in the record (flowEndReason). Under a memory budget (see
+//! --max-memory), the least recently active flows are exported first.
+//!
+//! Records are sent in batches of IPFIX_BATCH, each message also holding the
+//! templates as required over UDP. Failing to send a message is not fatal: its
+//! records are dropped and counted in the stop event.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use log::warn;
+use nix::time::{clock_gettime, ClockId};
+use serde_json::Value;
+
+use super::{
+    packet::{decode_packet, Packet},
+    EventOutput,
+};
+use crate::{
+    core::events::{meta::META_SECTION, Event, EventField},
+    event_field,
+};
+
+/// IPFIX collector port, when the endpoint does not give one.
+const IPFIX_PORT: u16 = 4739;
+/// Interval at which inactive and ended flows are looked for.
+const IPFIX_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of records sent per message, keeping messages under a 1500 bytes
+/// MTU with both templates.
+const IPFIX_BATCH: usize = 16;
+/// Estimated memory used by a flow.
+const FLOW_SIZE: usize = 128;
+
+/// IPFIX protocol version and template set id.
+const IPFIX_VERSION: u16 = 10;
+const IPFIX_TEMPLATE_SET: u16 = 2;
+/// Templates of IPv4 and IPv6 flow records.
+const TEMPLATE_IPV4: u16 = 256;
+const TEMPLATE_IPV6: u16 = 257;
+
+/// Information elements of the records (IANA id and length), in order. The
+/// addresses are the IPv4 ones, replaced by their IPv6 counterparts
+/// (sourceIPv6Address 27, destinationIPv6Address 28) in IPv6 records.
+const TEMPLATE_FIELDS: [(u16, u16); 11] = [
+    (8, 4),   // sourceIPv4Address
+    (12, 4),  // destinationIPv4Address
+    (7, 2),   // sourceTransportPort
+    (11, 2),  // destinationTransportPort
+    (4, 1),   // protocolIdentifier
+    (6, 2),   // tcpControlBits
+    (2, 8),   // packetDeltaCount
+    (1, 8),   // octetDeltaCount
+    (152, 8), // flowStartMilliseconds
+    (153, 8), // flowEndMilliseconds
+    (136, 1), // flowEndReason
+];
+
+/// TCP FIN and RST flags.
+const TCP_FIN: u8 = 0x01;
+const TCP_RST: u8 = 0x04;
+
+/// Why a flow was exported (flowEndReason values).
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EndReason {
+    IdleTimeout = 1,
+    EndOfFlow = 3,
+    ForcedEnd = 4,
+    LackOfResources = 5,
+}
+
+/// Flow 5-tuple.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct FlowKey {
+    saddr: IpAddr,
+    daddr: IpAddr,
+    sport: u16,
+    dport: u16,
+    protocol: u8,
+}
+
+impl From<&Packet> for FlowKey {
+    fn from(packet: &Packet) -> FlowKey {
+        FlowKey {
+            saddr: packet.saddr,
+            daddr: packet.daddr,
+            sport: packet.sport,
+            dport: packet.dport,
+            protocol: packet.protocol,
+        }
+    }
+}
+
+/// Flow record being built.
+struct Flow {
+    packets: u64,
+    bytes: u64,
+    /// TCP flags seen, OR-ed.
+    tcp_flags: u8,
+    /// Timestamps (monotonic, in ns) of the first and last packets.
+    start: u64,
+    end: u64,
+    /// When the flow was last updated, for the idle timeout.
+    updated: Instant,
+}
+
+pub(super) struct IpfixSink {
+    socket: UdpSocket,
+    idle_timeout: Duration,
+    /// Offset to convert monotonic timestamps to real time ones, in ns.
+    realtime_offset: u64,
+    flows: HashMap<FlowKey, Flow>,
+    /// Packets (tracking ids) already accounted for since the last scan.
+    seen: HashSet<(u64, u64)>,
+    last_scan: Instant,
+    /// Number of data records sent, used as the message sequence number.
+    sequence: u32,
+    exported: u64,
+    dropped: u64,
+}
+
+impl IpfixSink {
+    /// Create a new IPFIX sink exporting to `endpoint` ("host[:port]").
+    pub(super) fn new(endpoint: &str, idle_timeout: Duration) -> Result<IpfixSink> {
+        let addr = match endpoint.to_socket_addrs() {
+            Ok(mut addrs) => addrs.next(),
+            // No port given, use the IPFIX one.
+            Err(_) => (endpoint.trim_matches(|c| c == '[' || c == ']'), IPFIX_PORT)
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next()),
+        };
+        let addr = match addr {
+            Some(addr) => addr,
+            None => bail!("Invalid IPFIX collector '{}'", endpoint),
+        };
+
+        let socket = match addr {
+            SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+            SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+        };
+        socket
+            .connect(addr)
+            .or_else(|e| bail!("Could not use IPFIX collector {}: {}", addr, e))?;
+
+        let ns = |id| -> Result<u64> {
+            let ts = clock_gettime(id)?;
+            Ok(ts.tv_sec() as u64 * 1_000_000_000 + ts.tv_nsec() as u64)
+        };
+
+        Ok(IpfixSink {
+            socket,
+            idle_timeout,
+            realtime_offset: ns(ClockId::CLOCK_REALTIME)?
+                .saturating_sub(ns(ClockId::CLOCK_MONOTONIC)?),
+            flows: HashMap::new(),
+            seen: HashSet::new(),
+            last_scan: Instant::now(),
+            sequence: 0,
+            exported: 0,
+            dropped: 0,
+        })
+    }
+
+    /// Export the flows matching `expired`, given the reason it returns.
+    fn expire<F>(&mut self, expired: F)
+    where
+        F: Fn(&FlowKey, &Flow) -> Option<EndReason>,
+    {
+        let keys: Vec<_> = self
+            .flows
+            .iter()
+            .filter_map(|(key, flow)| expired(key, flow).map(|reason| (key.clone(), reason)))
+            .collect();
+        let flows: Vec<_> = keys
+            .into_iter()
+            .filter_map(|(key, reason)| Some((self.flows.remove(&key)?, key, reason)))
+            .collect();
+        let records = flows
+            .iter()
+            .map(|(flow, key, reason)| self.record(key, flow, *reason))
+            .collect();
+        self.send(records);
+    }
+
+    /// Look for inactive and ended flows, at most every IPFIX_SCAN_INTERVAL.
+    fn scan(&mut self) {
+        if self.last_scan.elapsed() < IPFIX_SCAN_INTERVAL {
+            return;
+        }
+        self.last_scan = Instant::now();
+        self.seen.clear();
+
+        let idle_timeout = self.idle_timeout;
+        self.expire(|key, flow| {
+            if key.protocol == 6 && flow.tcp_flags & (TCP_FIN | TCP_RST) != 0 {
+                Some(EndReason::EndOfFlow)
+            } else if flow.updated.elapsed() >= idle_timeout {
+                Some(EndReason::IdleTimeout)
+            } else {
+                None
+            }
+        });
+    }
+
+    /// Encode the data record of a flow, along with the template it uses.
+    fn record(&self, key: &FlowKey, flow: &Flow, reason: EndReason) -> (u16, Vec<u8>) {
+        let ms = |ts: u64| (ts + self.realtime_offset) / 1_000_000;
+
+        let mut record = Vec::new();
+        let template = match (key.saddr, key.daddr) {
+            (IpAddr::V4(saddr), IpAddr::V4(daddr)) => {
+                record.extend_from_slice(&saddr.octets());
+                record.extend_from_slice(&daddr.octets());
+                TEMPLATE_IPV4
+            }
+            (saddr, daddr) => {
+                record.extend_from_slice(&ipv6(saddr).octets());
+                record.extend_from_slice(&ipv6(daddr).octets());
+                TEMPLATE_IPV6
+            }
+        };
+        record.extend_from_slice(&key.sport.to_be_bytes());
+        record.extend_from_slice(&key.dport.to_be_bytes());
+        record.push(key.protocol);
+        record.extend_from_slice(&(flow.tcp_flags as u16).to_be_bytes());
+        record.extend_from_slice(&flow.packets.to_be_bytes());
+        record.extend_from_slice(&flow.bytes.to_be_bytes());
+        record.extend_from_slice(&ms(flow.start).to_be_bytes());
+        record.extend_from_slice(&ms(flow.end).to_be_bytes());
+        record.push(reason as u8);
+        (template, record)
+    }
+
+    /// Send records to the collector, in batches of IPFIX_BATCH.
+    fn send(&mut self, records: Vec<(u16, Vec<u8>)>) {
+        for batch in records.chunks(IPFIX_BATCH) {
+            let export_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as u32);
+            let msg = message(export_time, self.sequence, batch);
+
+            match self.socket.send(&msg) {
+                Ok(_) => {
+                    self.sequence = self.sequence.wrapping_add(batch.len() as u32);
+                    self.exported += batch.len() as u64;
+                }
+                Err(e) => {
+                    warn!("Could not export {} flow records: {}", batch.len(), e);
+                    self.dropped += batch.len() as u64;
+                }
+            }
+        }
+    }
+}
+
+impl EventOutput for IpfixSink {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        let json = event.to_json();
+        // Meta events are not accounted for.
+        if json.get(META_SECTION).is_some() {
+            return Ok(());
+        }
+
+        let field = |section: &str, key: &str| json.get(section).and_then(|s| s.get(key));
+        let (key, tcp_flags) = match field("skb", "packet")
+            .and_then(Value::as_str)
+            .and_then(decode_packet)
+        {
+            Some(packet) => (FlowKey::from(&packet), packet.tcp_flags),
+            None => {
+                self.scan();
+                return Ok(());
+            }
+        };
+
+        // Packets already seen are not accounted for again.
+        let new = match (
+            field("skb-tracking", "orig_head").and_then(Value::as_u64),
+            field("skb-tracking", "timestamp").and_then(Value::as_u64),
+        ) {
+            (Some(head), Some(ts)) => self.seen.insert((head, ts)),
+            _ => true,
+        };
+        if new {
+            let bytes = field("skb", "len")
+                .or_else(|| field("skb", "packet_len"))
+                .and_then(Value::as_u64)
+                .unwrap_or(0);
+            let ts = field("common", "timestamp")
+                .and_then(Value::as_u64)
+                .unwrap_or(0);
+
+            let flow = self.flows.entry(key).or_insert_with(|| Flow {
+                packets: 0,
+                bytes: 0,
+                tcp_flags: 0,
+                start: ts,
+                end: ts,
+                updated: Instant::now(),
+            });
+            flow.packets += 1;
+            flow.bytes += bytes;
+            flow.tcp_flags |= tcp_flags;
+            flow.start = flow.start.min(ts);
+            flow.end = flow.end.max(ts);
+            flow.updated = Instant::now();
+        }
+
+        self.scan();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.scan();
+        Ok(())
+    }
+
+    fn summary(&self, event: &mut Event) {
+        event.insert(META_SECTION, event_field!("ipfix_flows", self.exported));
+        if self.dropped > 0 {
+            event.insert(META_SECTION, event_field!("ipfix_dropped", self.dropped));
+        }
+    }
+
+    fn memory(&self) -> usize {
+        self.flows.len() * FLOW_SIZE
+    }
+
+    /// Export the least recently active flows early.
+    fn evict(&mut self, bytes: usize) -> usize {
+        let mut flows: Vec<_> = self
+            .flows
+            .iter()
+            .map(|(key, flow)| (flow.updated, key.clone()))
+            .collect();
+        flows.sort_by_key(|(updated, _)| *updated);
+
+        let evicted: HashSet<_> = flows
+            .into_iter()
+            .take((bytes + FLOW_SIZE - 1) / FLOW_SIZE)
+            .map(|(_, key)| key)
+            .collect();
+        self.expire(|key, _| evicted.contains(key).then_some(EndReason::LackOfResources));
+        evicted.len() * FLOW_SIZE
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.expire(|_, _| Some(EndReason::ForcedEnd));
+        Ok(())
+    }
+}
+
+/// IPv6 address of a flow endpoint, mapping IPv4 ones.
+fn ipv6(addr: IpAddr) -> Ipv6Addr {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    }
+}
+
+/// Build an IPFIX message holding the templates and a set of data records.
+fn message(export_time: u32, sequence: u32, records: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let set = |id: u16, content: &[u8]| {
+        let mut set = Vec::with_capacity(content.len() + 4);
+        set.extend_from_slice(&id.to_be_bytes());
+        set.extend_from_slice(&(content.len() as u16 + 4).to_be_bytes());
+        set.extend_from_slice(content);
+        set
+    };
+
+    let mut templates = Vec::new();
+    for (id, v6) in [(TEMPLATE_IPV4, false), (TEMPLATE_IPV6, true)] {
+        templates.extend_from_slice(&id.to_be_bytes());
+        templates.extend_from_slice(&(TEMPLATE_FIELDS.len() as u16).to_be_bytes());
+        for (i, (mut ie, mut len)) in TEMPLATE_FIELDS.iter().copied().enumerate() {
+            if v6 && i < 2 {
+                ie = 27 + i as u16;
+                len = 16;
+            }
+            templates.extend_from_slice(&ie.to_be_bytes());
+            templates.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+    let mut body = set(IPFIX_TEMPLATE_SET, &templates);
+
+    // One data set per template.
+    for template in [TEMPLATE_IPV4, TEMPLATE_IPV6] {
+        let data: Vec<u8> = records
+            .iter()
+            .filter(|(t, _)| *t == template)
+            .flat_map(|(_, record)| record.iter().copied())
+            .collect();
+        if !data.is_empty() {
+            body.extend_from_slice(&set(template, &data));
+        }
+    }
+
+    let mut msg = Vec::with_capacity(body.len() + 16);
+    msg.extend_from_slice(&IPFIX_VERSION.to_be_bytes());
+    msg.extend_from_slice(&(body.len() as u16 + 16).to_be_bytes());
+    msg.extend_from_slice(&export_time.to_be_bytes());
+    msg.extend_from_slice(&sequence.to_be_bytes());
+    // Observation domain id.
+    msg.extend_from_slice(&0u32.to_be_bytes());
+    msg.extend_from_slice(&body);
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ethernet + IPv4/TCP headers, 10.0.0.1:1234 > 10.0.0.2:80, without the
+    // flags byte.
+    const TCP: &str = "00112233445566778899aabb080045000028000040004006000\
+                       00a0000010a00000204d20050000000000000000050";
+    // Ethernet + IPv6/UDP headers, 2001:db8::1:53 > 2001:db8::2:5353.
+    const UDP6: &str = "00112233445566778899aabb86dd6000000000081140\
+                        20010db8000000000000000000000001\
+                        20010db8000000000000000000000002\
+                        003514e900080000";
+
+    fn event(packet: &str, head: u64, ts: u64) -> Event {
+        let mut event = Event::new();
+        event.insert("common", event_field!("timestamp", ts));
+        event.insert("skb-tracking", event_field!("orig_head", head));
+        event.insert("skb-tracking", event_field!("timestamp", head));
+        event.insert("skb", event_field!("packet", packet.to_string()));
+        event.insert("skb", event_field!("len", 100u32));
+        event
+    }
+
+    #[test]
+    fn export() -> Result<()> {
+        let collector = UdpSocket::bind("127.0.0.1:0")?;
+        collector.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut sink = IpfixSink::new(
+            &collector.local_addr()?.to_string(),
+            Duration::from_secs(60),
+        )?;
+
+        // A packet seen by two probes, another one, and an IPv6 flow.
+        let syn = format!("{}{}", TCP, "02");
+        sink.output(&event(&syn, 1, 1_000_000))?;
+        sink.output(&event(&syn, 1, 2_000_000))?;
+        sink.output(&event(&format!("{}{}", TCP, "10"), 2, 5_000_000))?;
+        sink.output(&event(UDP6, 3, 6_000_000))?;
+        assert!(sink.flows.len() == 2);
+        let key = FlowKey::from(&decode_packet(&syn).unwrap());
+        let flow = &sink.flows[&key];
+        assert!(flow.packets == 2 && flow.bytes == 200 && flow.tcp_flags == 0x12);
+        assert!(flow.start == 1_000_000 && flow.end == 5_000_000);
+
+        sink.finish()?;
+        let mut stop = Event::new();
+        sink.summary(&mut stop);
+        assert!(stop.to_json()[META_SECTION]["ipfix_flows"] == 2);
+
+        let mut buf = [0; 1500];
+        let len = collector.recv(&mut buf)?;
+        let msg = &buf[..len];
+        assert!(u16::from_be_bytes([msg[0], msg[1]]) == IPFIX_VERSION);
+        assert!(u16::from_be_bytes([msg[2], msg[3]]) as usize == len);
+
+        // Template set: header, then two templates of 11 fields.
+        let templates_len = 4 + 2 * (4 + TEMPLATE_FIELDS.len() * 4);
+        assert!(u16::from_be_bytes([msg[16], msg[17]]) == IPFIX_TEMPLATE_SET);
+        assert!(u16::from_be_bytes([msg[18], msg[19]]) as usize == templates_len);
+
+        // IPv4 data set, holding a 48 bytes record.
+        let set = &msg[16 + templates_len..];
+        assert!(u16::from_be_bytes([set[0], set[1]]) == TEMPLATE_IPV4);
+        assert!(u16::from_be_bytes([set[2], set[3]]) == 4 + 48);
+        let record = &set[4..52];
+        assert!(record[..8] == [10, 0, 0, 1, 10, 0, 0, 2]);
+        assert!(record[12] == 6 && record[14] == 0x12);
+        assert!(u64::from_be_bytes(record[15..23].try_into()?) == 2);
+        assert!(u64::from_be_bytes(record[23..31].try_into()?) == 200);
+        assert!(record[47] == EndReason::ForcedEnd as u8);
+
+        // IPv6 data set, holding a 72 bytes record.
+        let set = &set[52..];
+        assert!(u16::from_be_bytes([set[0], set[1]]) == TEMPLATE_IPV6);
+        assert!(u16::from_be_bytes([set[2], set[3]]) == 4 + 72);
+        assert!(set.len() == 76);
+        Ok(())
+    }
+
+    #[test]
+    fn expire() -> Result<()> {
+        let collector = UdpSocket::bind("127.0.0.1:0")?;
+        let mut sink =
+            IpfixSink::new(&collector.local_addr()?.to_string(), Duration::from_secs(0))?;
+
+        // Ended TCP flows are exported as such, others once idle.
+        sink.output(&event(&format!("{}{}", TCP, "11"), 1, 0))?;
+        sink.output(&event(UDP6, 2, 0))?;
+        sink.last_scan -= IPFIX_SCAN_INTERVAL;
+        sink.flush()?;
+        assert!(sink.flows.is_empty() && sink.exported == 2);
+
+        // Evicted flows are exported early.
+        sink.output(&event(UDP6, 3, 0))?;
+        assert!(sink.memory() == FLOW_SIZE);
+        assert!(sink.evict(1) == FLOW_SIZE);
+        assert!(sink.flows.is_empty() && sink.exported == 3);
+        Ok(())
+    }
+}
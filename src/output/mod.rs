@@ -0,0 +1,41 @@
+//! # Output
+//!
+//! Output module, handling how events are written once retrieved. Events are
+//! written to one or more sinks, implementing the EventOutput trait. This is
+//! also where userspace-side policies applying to all events before they are
+//! written (e.g. filtering, rate limiting, triggers, redaction) are enforced.
+//! The last events can also be kept in memory only, to be dumped on request
+//! (see recorder).
+
+// Re-export output.rs
+#[allow(clippy::module_inception)]
+pub(crate) mod output;
+pub(crate) use output::*;
+
+mod asymmetry;
+mod fifo;
+mod file;
+mod filter;
+mod format;
+#[cfg(feature = "geoip")]
+mod geoip;
+mod histogram;
+mod ipfix;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod packet;
+mod path;
+pub(crate) mod recorder;
+mod redact;
+mod resolve;
+mod ring;
+mod ring_dir;
+mod socket;
+mod stdout;
+mod summary;
+#[cfg(feature = "syslog")]
+mod syslog;
+mod throttle;
+mod top;
+mod trigger;
+mod veth;
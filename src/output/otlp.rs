@@ -0,0 +1,597 @@
+//! # OpenTelemetry export
+//!
+//! Exports the path of tracked packets (see --path) as OpenTelemetry traces,
+//! so they can be looked at alongside application traces (e.g. in Jaeger).
+//! Each packet is a trace, its id being made of the packet tracking id
+//! (skb-tracking orig_head and timestamp), with a root span covering the whole
+//! path and a child span per probe the packet went through. A probe span lasts
+//! until the packet reaches the next probe; spans of probes where the packet
+//! was dropped have an error status.
+//!
+//! A packet path is considered complete, and exported, once no event was seen
+//! for it during OTLP_IDLE_TIMEOUT; remaining paths are exported when the
+//! collection ends. Under a memory budget (see --max-memory), the least
+//! recently active paths are exported first. Traces are sent in batches of
+//! OTLP_BATCH packets using OTLP over HTTP with the JSON encoding.
+//!
+//! The HTTP client is a minimal one, as no HTTP client is part of our
+//! dependencies: only plain http:// endpoints are supported (no TLS, no
+//! authentication headers, no compression) and failed exports are not retried.
+//! Requests are sent from a worker thread, fed through a queue of OTLP_QUEUE
+//! batches so a slow or unreachable endpoint does not hold the output thread;
+//! batches are dropped while the queue is full. Packets of a batch dropped or
+//! failing to export are accounted for in the stop event; those exported once
+//! the collection ends are only reported in the logs, as the stop event is
+//! already written.
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{sync_channel, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use log::{info, warn};
+use nix::time::{clock_gettime, ClockId};
+use serde_json::{json, Value};
+
+use super::{
+    path::{step_size, tracked_step, Step, PATH_SIZE},
+    EventOutput,
+};
+use crate::{
+    core::events::{meta::META_SECTION, Event},
+    event_field,
+};
+
+/// Number of packets (traces) sent per export request.
+const OTLP_BATCH: usize = 256;
+/// Number of batches waiting to be exported before new ones are dropped.
+const OTLP_QUEUE: usize = 16;
+/// Path traces are posted to, when the endpoint does not give one.
+const OTLP_TRACES_PATH: &str = "/v1/traces";
+/// Timeout of the export requests, applying to connecting, sending and
+/// receiving each.
+const OTLP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time after which a packet path without new events is considered complete.
+const OTLP_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval at which completed packet paths are looked for.
+const OTLP_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// OTLP span kind and status code values.
+const SPAN_KIND_INTERNAL: u32 = 1;
+const STATUS_CODE_ERROR: u32 = 2;
+
+/// OTLP/HTTP endpoint.
+#[derive(Debug, PartialEq)]
+struct Endpoint {
+    /// "host:port".
+    addr: String,
+    host: String,
+    path: String,
+}
+
+impl Endpoint {
+    /// Parse an endpoint, e.g. "http://localhost:4318".
+    fn parse(endpoint: &str) -> Result<Endpoint> {
+        let rest = match endpoint.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => bail!(
+                "Invalid OTLP endpoint '{}': only http:// endpoints are supported",
+                endpoint
+            ),
+        };
+
+        let (host, path) = match rest.find('/') {
+            Some(i) if rest[i..].len() > 1 => (&rest[..i], &rest[i..]),
+            Some(i) => (&rest[..i], OTLP_TRACES_PATH),
+            None => (rest, OTLP_TRACES_PATH),
+        };
+        if host.is_empty() {
+            bail!("Invalid OTLP endpoint '{}': missing host", endpoint);
+        }
+
+        // Default to the OTLP/HTTP port. IPv6 addresses are in brackets.
+        let addr = match host.rsplit_once(':') {
+            Some((_, port)) if !port.contains(']') => host.to_string(),
+            _ => format!("{}:4318", host),
+        };
+
+        Ok(Endpoint {
+            addr,
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// Connect to the first reachable address the endpoint resolves to.
+    fn connect(&self) -> Result<TcpStream> {
+        let mut err = None;
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, OTLP_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => err = Some(e),
+            }
+        }
+        Err(match err {
+            Some(e) => e.into(),
+            None => anyhow!("{} did not resolve to any address", self.addr),
+        })
+    }
+
+    /// Post an export request to the endpoint.
+    fn export(&self, body: &Value) -> Result<()> {
+        let body = body.to_string();
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(Some(OTLP_TIMEOUT))?;
+        stream.set_write_timeout(Some(OTLP_TIMEOUT))?;
+
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        )?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let status = response.lines().next().unwrap_or_default();
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => bail!("unexpected response '{}'", status),
+        }
+    }
+}
+
+/// Number of packets (traces) exported, and dropped as failing to or as the
+/// queue was full. Shared with the export worker.
+#[derive(Default)]
+struct Counters {
+    exported: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Export worker, posting the request bodies it receives along with their
+/// number of packets.
+struct Worker {
+    tx: SyncSender<(Value, u64)>,
+    handle: JoinHandle<()>,
+}
+
+impl Worker {
+    fn start(endpoint: Endpoint, counters: Arc<Counters>) -> Result<Worker> {
+        let (tx, rx) = sync_channel::<(Value, u64)>(OTLP_QUEUE);
+        let handle = thread::Builder::new()
+            .name("otlp".to_string())
+            .spawn(move || {
+                while let Ok((body, packets)) = rx.recv() {
+                    match endpoint.export(&body) {
+                        Ok(()) => counters.exported.fetch_add(packets, Ordering::Relaxed),
+                        Err(e) => {
+                            warn!("Could not export {} packet traces: {}", packets, e);
+                            counters.dropped.fetch_add(packets, Ordering::Relaxed)
+                        }
+                    };
+                }
+            })?;
+        Ok(Worker { tx, handle })
+    }
+}
+
+/// Path of a packet being built.
+struct Trace {
+    steps: Vec<Step>,
+    /// When the path was last updated, for the idle timeout.
+    updated: Instant,
+}
+
+pub(super) struct OtlpSink {
+    service: String,
+    /// Offset to convert event timestamps (monotonic clock) to Unix time.
+    realtime_offset: u64,
+    /// Paths, by packet tracking id: (orig_head, timestamp).
+    paths: BTreeMap<(u64, u64), Trace>,
+    /// Estimated memory used by the paths, in bytes.
+    memory: usize,
+    last_scan: Instant,
+    counters: Arc<Counters>,
+    /// Export worker, until the collection ends.
+    worker: Option<Worker>,
+}
+
+impl OtlpSink {
+    pub(super) fn new(endpoint: &str, service: &str) -> Result<OtlpSink> {
+        let ns = |id| -> Result<u64> {
+            let ts = clock_gettime(id)?;
+            Ok(ts.tv_sec() as u64 * 1_000_000_000 + ts.tv_nsec() as u64)
+        };
+
+        let counters = Arc::new(Counters::default());
+        let worker = Worker::start(Endpoint::parse(endpoint)?, Arc::clone(&counters))?;
+
+        Ok(OtlpSink {
+            service: service.to_string(),
+            realtime_offset: ns(ClockId::CLOCK_REALTIME)?
+                .saturating_sub(ns(ClockId::CLOCK_MONOTONIC)?),
+            paths: BTreeMap::new(),
+            memory: 0,
+            last_scan: Instant::now(),
+            counters,
+            worker: Some(worker),
+        })
+    }
+
+    /// Export and remove the paths matching `expired`. Batches are dropped if
+    /// the export queue is full, unless `wait` is set.
+    fn expire<F>(&mut self, expired: F, wait: bool)
+    where
+        F: Fn(&(u64, u64), &Trace) -> bool,
+    {
+        let ids: Vec<_> = self
+            .paths
+            .iter()
+            .filter(|(id, trace)| expired(id, trace))
+            .map(|(id, _)| *id)
+            .collect();
+        let mut paths: Vec<_> = ids
+            .into_iter()
+            .filter_map(|id| Some((id, self.paths.remove(&id)?.steps)))
+            .collect();
+
+        for (_, steps) in paths.iter_mut() {
+            self.memory = self
+                .memory
+                .saturating_sub(PATH_SIZE + steps.iter().map(step_size).sum::<usize>());
+            // Events can be retrieved out of order.
+            steps.sort_by_key(|s| s.timestamp);
+        }
+
+        for batch in paths.chunks(OTLP_BATCH) {
+            let spans = batch
+                .iter()
+                .flat_map(|(id, steps)| self.spans(*id, steps))
+                .collect();
+            let packets = batch.len() as u64;
+            let worker = match &self.worker {
+                Some(worker) => worker,
+                None => {
+                    self.counters.dropped.fetch_add(packets, Ordering::Relaxed);
+                    continue;
+                }
+            };
+
+            let request = (self.request(spans), packets);
+            let sent = match wait {
+                true => worker.tx.send(request).is_ok(),
+                false => match worker.tx.try_send(request) {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) => {
+                        warn!("Export queue full, dropping {} packet traces", packets);
+                        false
+                    }
+                    Err(TrySendError::Disconnected(_)) => false,
+                },
+            };
+            if !sent {
+                self.counters.dropped.fetch_add(packets, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Look for completed paths, at most every OTLP_SCAN_INTERVAL.
+    fn scan(&mut self) {
+        if self.last_scan.elapsed() < OTLP_SCAN_INTERVAL {
+            return;
+        }
+        self.last_scan = Instant::now();
+        self.expire(
+            |_, trace| trace.updated.elapsed() >= OTLP_IDLE_TIMEOUT,
+            false,
+        );
+    }
+
+    /// Build the spans of a packet path: a root span and one per step.
+    fn spans(&self, (orig_head, tracking_ts): (u64, u64), steps: &[Step]) -> Vec<Value> {
+        let trace_id = format!("{:016x}{:016x}", orig_head, tracking_ts);
+        let span_id = |i: usize| format!("{:016x}", i + 1);
+        let time = |ts: u64| (ts + self.realtime_offset).to_string();
+
+        let first = steps.first().map_or(0, |s| s.timestamp);
+        let last = steps.last().map_or(0, |s| s.timestamp);
+        let dropped = steps.iter().any(|s| s.drop_reason != 0);
+
+        let mut root = json!({
+            "traceId": trace_id,
+            "spanId": span_id(0),
+            "name": format!("packet {:#x}", orig_head),
+            "kind": SPAN_KIND_INTERNAL,
+            "startTimeUnixNano": time(first),
+            "endTimeUnixNano": time(last),
+            "attributes": [
+                { "key": "orig_head", "value": { "stringValue": format!("{:#x}", orig_head) } },
+            ],
+        });
+        if dropped {
+            root["status"] = json!({ "code": STATUS_CODE_ERROR, "message": "dropped" });
+        }
+
+        let mut spans = vec![root];
+        for (i, step) in steps.iter().enumerate() {
+            let end = steps.get(i + 1).map_or(step.timestamp, |s| s.timestamp);
+            let mut span = json!({
+                "traceId": trace_id,
+                "spanId": span_id(i + 1),
+                "parentSpanId": span_id(0),
+                "name": step.probe,
+                "kind": SPAN_KIND_INTERNAL,
+                "startTimeUnixNano": time(step.timestamp),
+                "endTimeUnixNano": time(end),
+            });
+            if step.drop_reason != 0 {
+                span["attributes"] = json!([
+                    { "key": "drop_reason", "value": { "intValue": step.drop_reason.to_string() } },
+                ]);
+                span["status"] = json!({ "code": STATUS_CODE_ERROR, "message": "dropped" });
+            }
+            spans.push(span);
+        }
+        spans
+    }
+
+    /// Build an OTLP export request body from a set of spans.
+    fn request(&self, spans: Vec<Value>) -> Value {
+        json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [
+                        { "key": "service.name", "value": { "stringValue": self.service } },
+                    ],
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "packet-tracer", "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }],
+            }],
+        })
+    }
+}
+
+impl EventOutput for OtlpSink {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        if let Some((id, step)) = tracked_step(event) {
+            let trace = self.paths.entry(id).or_insert_with(|| {
+                self.memory += PATH_SIZE;
+                Trace {
+                    steps: Vec::new(),
+                    updated: Instant::now(),
+                }
+            });
+            self.memory += step_size(&step);
+            trace.steps.push(step);
+            trace.updated = Instant::now();
+        }
+
+        self.scan();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.scan();
+        Ok(())
+    }
+
+    fn summary(&self, event: &mut Event) {
+        let exported = self.counters.exported.load(Ordering::Relaxed);
+        let dropped = self.counters.dropped.load(Ordering::Relaxed);
+        event.insert(META_SECTION, event_field!("otlp_traces", exported));
+        if dropped > 0 {
+            event.insert(META_SECTION, event_field!("otlp_dropped", dropped));
+        }
+    }
+
+    fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Export the least recently active paths early.
+    fn evict(&mut self, bytes: usize) -> usize {
+        let mut paths: Vec<_> = self
+            .paths
+            .iter()
+            .map(|(id, trace)| {
+                let size = PATH_SIZE + trace.steps.iter().map(step_size).sum::<usize>();
+                (trace.updated, size, *id)
+            })
+            .collect();
+        paths.sort_by_key(|(updated, _, id)| (*updated, *id));
+
+        let mut freed = 0;
+        let mut evicted = HashSet::new();
+        for (_, size, id) in paths.into_iter() {
+            if freed >= bytes {
+                break;
+            }
+            freed += size;
+            evicted.insert(id);
+        }
+
+        self.expire(|id, _| evicted.contains(id), false);
+        freed
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.expire(|_, _| true, true);
+
+        // Wait for the pending exports to complete.
+        if let Some(worker) = self.worker.take() {
+            drop(worker.tx);
+            if worker.handle.join().is_err() {
+                bail!("OTLP export worker panicked");
+            }
+            info!(
+                "Exported {} packet traces ({} dropped)",
+                self.counters.exported.load(Ordering::Relaxed),
+                self.counters.dropped.load(Ordering::Relaxed)
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::BufRead, io::BufReader, net::TcpListener, thread};
+
+    use super::*;
+    use crate::event_field;
+
+    #[test]
+    fn endpoint() -> Result<()> {
+        let endpoint = Endpoint::parse("http://localhost:4318")?;
+        assert!(endpoint.addr == "localhost:4318" && endpoint.path == "/v1/traces");
+
+        let endpoint = Endpoint::parse("http://collector/")?;
+        assert!(endpoint.addr == "collector:4318" && endpoint.host == "collector");
+
+        let endpoint = Endpoint::parse("http://[::1]:4000/otlp/v1/traces")?;
+        assert!(endpoint.addr == "[::1]:4000" && endpoint.path == "/otlp/v1/traces");
+        assert!(Endpoint::parse("http://[::1]")?.addr == "[::1]:4318");
+
+        assert!(Endpoint::parse("https://localhost:4318").is_err());
+        assert!(Endpoint::parse("http:///v1/traces").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn spans() -> Result<()> {
+        let mut sink = OtlpSink::new("http://localhost:4318", "test")?;
+        sink.realtime_offset = 1000;
+
+        let step = |timestamp, probe: &str, drop_reason| Step {
+            timestamp,
+            probe: probe.to_string(),
+            drop_reason,
+            ifindex: None,
+            netfilter: None,
+            veth_peer: None,
+        };
+        let spans = sink.spans(
+            (0x10, 0x20),
+            &[
+                step(100, "kprobe:ip_rcv", 0),
+                step(250, "kprobe:kfree_skb_reason", 2),
+            ],
+        );
+        assert!(spans.len() == 3);
+
+        let root = &spans[0];
+        assert!(root["traceId"] == "00000000000000100000000000000020");
+        assert!(root["spanId"] == "0000000000000001" && root.get("parentSpanId").is_none());
+        assert!(root["startTimeUnixNano"] == "1100" && root["endTimeUnixNano"] == "1250");
+        assert!(root["status"]["code"] == STATUS_CODE_ERROR);
+
+        assert!(
+            spans[1]["name"] == "kprobe:ip_rcv" && spans[1]["parentSpanId"] == "0000000000000001"
+        );
+        assert!(spans[1]["endTimeUnixNano"] == "1250" && spans[1].get("status").is_none());
+        assert!(spans[2]["attributes"][0]["value"]["intValue"] == "2");
+
+        let request = sink.request(spans);
+        assert!(
+            request["resourceSpans"][0]["scopeSpans"][0]["spans"][2]["spanId"]
+                == "0000000000000003"
+        );
+        Ok(())
+    }
+
+    // Serve `count` export requests, returning the number of spans of each.
+    fn server(count: usize) -> Result<(String, thread::JoinHandle<Vec<usize>>)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+
+        let handle = thread::spawn(move || {
+            let mut spans = Vec::new();
+            for stream in listener.incoming().take(count) {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).unwrap();
+                    if let Some(val) = line.strip_prefix("Content-Length: ") {
+                        len = val.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; len];
+                stream.read_exact(&mut body).unwrap();
+
+                let body: Value = serde_json::from_slice(&body).unwrap();
+                spans.push(
+                    body["resourceSpans"][0]["scopeSpans"][0]["spans"]
+                        .as_array()
+                        .unwrap()
+                        .len(),
+                );
+                write!(stream.get_mut(), "HTTP/1.1 200 OK\r\n\r\n").unwrap();
+            }
+            spans
+        });
+        Ok((endpoint, handle))
+    }
+
+    fn tracked(orig_head: u64, probe: &str) -> Event {
+        let mut event = Event::new();
+        event.insert("common", event_field!("probe", probe.to_string()));
+        event.insert("skb-tracking", event_field!("orig_head", orig_head));
+        event.insert("skb-tracking", event_field!("timestamp", 1000u64));
+        event
+    }
+
+    #[test]
+    fn export() -> Result<()> {
+        let (endpoint, server) = server(2)?;
+        let mut sink = OtlpSink::new(&endpoint, "test")?;
+
+        sink.output(&tracked(0x10, "kprobe:ip_rcv"))?;
+        sink.output(&tracked(0x10, "kprobe:ip_forward"))?;
+        sink.output(&tracked(0x20, "kprobe:ip_rcv"))?;
+        let memory = sink.memory();
+        assert!(memory > 0);
+
+        // The least recently active paths are exported first.
+        assert!(sink.evict(1) > 0);
+        assert!(sink.paths.len() == 1 && sink.paths.contains_key(&(0x20, 1000)));
+        assert!(sink.memory() < memory);
+
+        // Remaining paths are exported once the collection ends.
+        sink.finish()?;
+        assert!(sink.paths.is_empty() && sink.memory() == 0);
+        assert!(server.join().unwrap() == vec![3, 2]);
+
+        let mut event = Event::new();
+        sink.summary(&mut event);
+        assert!(event.get::<u64>(META_SECTION, "otlp_traces")? == Some(&2));
+        assert!(event.get::<u64>(META_SECTION, "otlp_dropped")?.is_none());
+
+        // Failing exports are accounted for.
+        let (endpoint, server) = server(0)?;
+        server.join().unwrap();
+        let mut sink = OtlpSink::new(&endpoint, "test")?;
+        sink.output(&tracked(0x10, "kprobe:ip_rcv"))?;
+        sink.finish()?;
+        let counters = &sink.counters;
+        assert!(counters.exported.load(Ordering::Relaxed) == 0);
+        assert!(counters.dropped.load(Ordering::Relaxed) == 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,934 @@
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use nix::{
+    libc,
+    time::{clock_gettime, ClockId},
+};
+
+#[cfg(feature = "geoip")]
+use super::geoip::GeoIp;
+#[cfg(feature = "otlp")]
+use super::otlp::OtlpSink;
+#[cfg(feature = "syslog")]
+use super::syslog::{parse_facility, Severity, SyslogSink};
+use super::{
+    asymmetry::Asymmetry,
+    fifo::FifoSink,
+    file::{Compression, FileSink},
+    filter::Filter,
+    format::{csv_columns, Envelope, Format, JsonMapping},
+    histogram::{HistogramFormat, Histograms},
+    ipfix::IpfixSink,
+    path::{PathFormat, Paths},
+    recorder::{set_recorder, Recorder, RecorderSink},
+    redact::{RedactMode, Redactor},
+    resolve::NameResolver,
+    ring_dir::RingDir,
+    socket::SocketSink,
+    stdout::StdoutSink,
+    summary::Summary,
+    throttle::TokenBucket,
+    top::{Top, TopSort},
+    trigger::{Trigger, TriggerCondition},
+    veth::VethPeers,
+};
+use crate::{
+    cli::{cmd::collect::Collect, CliConfig},
+    core::events::{meta::META_SECTION, Event, EventField},
+    event_field,
+};
+
+/// Fields holding IP addresses (as strings), by section. Used by the
+/// annotations applying to addresses, e.g. --resolve-names and --geoip-db.
+pub(super) const ADDRESS_FIELDS: &[(&str, &str)] = &[
+    ("skb", "icmp_orig_saddr"),
+    ("skb", "icmp_orig_daddr"),
+    ("cgroup", "daddr"),
+    ("sockdrop", "saddr"),
+    ("sockdrop", "daddr"),
+    ("tcp-retrans", "saddr"),
+    ("tcp-retrans", "daddr"),
+];
+
+/// Names of the fields holding the country, ASN and AS organization of an
+/// address field (see --geoip-db). Known regardless of the "geoip" build
+/// feature, so these fields are redacted along with their address.
+pub(super) fn geoip_fields(key: &str) -> [String; 3] {
+    let prefix = key.replace("saddr", "src").replace("daddr", "dst");
+    [
+        format!("{}_country", prefix),
+        format!("{}_asn", prefix),
+        format!("{}_as_org", prefix),
+    ]
+}
+
+/// Interface names, by ifindex, looked up once per interface. Used by the
+/// outputs aggregating packets per interface.
+#[derive(Default)]
+pub(super) struct IfNames(HashMap<u64, Option<String>>);
+
+impl IfNames {
+    /// Name of an interface, if it can be found in our network namespace.
+    pub(super) fn get(&mut self, ifindex: u64) -> Option<&str> {
+        self.0
+            .entry(ifindex)
+            .or_insert_with(|| {
+                let mut buf = [0; libc::IF_NAMESIZE];
+                // Safety: buf is IF_NAMESIZE long, as required.
+                match unsafe { libc::if_indextoname(ifindex as u32, buf.as_mut_ptr()) }.is_null() {
+                    true => None,
+                    // Safety: the name was written as a NUL-terminated string.
+                    false => Some(
+                        unsafe { CStr::from_ptr(buf.as_ptr()) }
+                            .to_string_lossy()
+                            .to_string(),
+                    ),
+                }
+            })
+            .as_deref()
+    }
+}
+
+/// Percentage of the memory budget to evict down to, once over it.
+const MEMORY_LOW_WATERMARK: usize = 90;
+
+/// Generic trait representing an output sink. All sinks are required to
+/// implement this, as they'll be manipulated through this trait.
+pub(crate) trait EventOutput {
+    /// Write an event to the sink.
+    fn output(&mut self, event: &Event) -> Result<()>;
+    /// Flush the sink, if it is buffered.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// Add the sink summary counts, if any, to a (stop) meta event.
+    fn summary(&self, _: &mut Event) {}
+    /// Warning meta events found by the sink, once the collection ended.
+    /// Called before summary().
+    fn warnings(&mut self) -> Vec<Event> {
+        Vec::new()
+    }
+    /// Estimated memory used by the sink state, in bytes, for sinks keeping
+    /// events or aggregates until the collection ends.
+    fn memory(&self) -> usize {
+        0
+    }
+    /// Evict state to free at least `bytes`, when over the memory budget.
+    /// Returns the number of bytes freed.
+    fn evict(&mut self, _: usize) -> usize {
+        0
+    }
+    /// Order in which sinks are evicted from, lower first. Sinks whose state
+    /// is the least critical to the final report come first.
+    fn eviction_order(&self) -> u8 {
+        0
+    }
+    /// Finish writing events, once the collection ended.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Output state shared with the control socket, updated as events are
+/// written.
+#[derive(Default)]
+pub(crate) struct OutputStatus {
+    /// Number of events which were not written so far, see Output::dropped().
+    pub(crate) dropped: AtomicU64,
+    /// Number of times the trigger fired.
+    pub(crate) triggered: AtomicU64,
+}
+
+/// Group of output sinks, used to write events to all of them while applying
+/// common policies.
+pub(crate) struct Output {
+    sinks: Vec<Box<dyn EventOutput>>,
+    /// Optional userspace filter, events not matching it are not written.
+    filter: Option<Filter>,
+    /// Optional global rate limit, in events per second.
+    throttle: Option<TokenBucket>,
+    /// Number of events which were dropped by the rate limit.
+    throttled: u64,
+    /// Optional trigger, deferring writing events until it fires.
+    trigger: Option<Trigger>,
+    /// Optional resolver, annotating addresses with their host name.
+    resolver: Option<NameResolver>,
+    /// Optional veth peers table, annotating events on veth interfaces with
+    /// their peer.
+    veth: Option<VethPeers>,
+    /// Optional GeoIP databases, annotating addresses with their country and
+    /// autonomous system.
+    #[cfg(feature = "geoip")]
+    geoip: Option<GeoIp>,
+    /// Optional redaction of private fields, applied last before events are
+    /// written.
+    redactor: Option<Redactor>,
+    /// Maximum time written events can stay buffered in the sinks.
+    flush_interval: Duration,
+    /// Last time the sinks were flushed.
+    last_flush: Instant,
+    /// Were events written since the last flush?
+    pending: bool,
+    /// Capture labels added to all events, comma separated.
+    labels: Option<String>,
+    /// Optional memory budget of the userspace caches and aggregates, in
+    /// bytes.
+    max_memory: Option<usize>,
+    /// State shared with the control socket.
+    status: Arc<OutputStatus>,
+}
+
+impl Output {
+    fn new() -> Output {
+        Output {
+            sinks: Vec::new(),
+            filter: None,
+            throttle: None,
+            throttled: 0,
+            trigger: None,
+            resolver: None,
+            veth: None,
+            #[cfg(feature = "geoip")]
+            geoip: None,
+            redactor: None,
+            flush_interval: Duration::ZERO,
+            last_flush: Instant::now(),
+            pending: false,
+            labels: None,
+            max_memory: None,
+            status: Arc::new(OutputStatus::default()),
+        }
+    }
+
+    /// Add a sink to the group.
+    fn add(&mut self, sink: Box<dyn EventOutput>) -> &mut Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Only write events matching a filter.
+    fn set_filter(&mut self, filter: Filter) -> &mut Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Limit the number of events written per second. Excess events are
+    /// dropped and accounted for.
+    fn set_max_eps(&mut self, max_eps: u64) -> &mut Self {
+        self.throttle = Some(TokenBucket::new(max_eps));
+        self
+    }
+
+    /// Only write events once a trigger fires, along with the `pre` events
+    /// preceding it, and for `post` after it.
+    fn set_trigger(
+        &mut self,
+        condition: TriggerCondition,
+        pre: usize,
+        post: Duration,
+    ) -> &mut Self {
+        self.trigger = Some(Trigger::new(condition, pre, post));
+        self
+    }
+
+    /// Stamp all events, including meta ones, with capture labels.
+    fn set_labels(&mut self, labels: &[String]) -> &mut Self {
+        self.labels = Some(labels.join(","));
+        self
+    }
+
+    /// Bound the estimated memory used by the userspace caches and aggregates
+    /// (name resolver cache, sink aggregates). See enforce_memory.
+    fn set_max_memory(&mut self, bytes: usize) -> &mut Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Flush the sinks at most every `interval`, batching writes in between.
+    /// A zero interval flushes the sinks after each event.
+    fn set_flush_interval(&mut self, interval: Duration) -> &mut Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Write an event to all sinks, unless it is filtered out, held by the
+    /// trigger or throttled.
+    pub(crate) fn output(&mut self, mut event: Event) -> Result<()> {
+        if let Some(labels) = &self.labels {
+            event.insert("common", event_field!("labels", labels.clone()));
+        }
+
+        if let Some(filter) = &mut self.filter {
+            if !filter.matches(&event) {
+                return Ok(());
+            }
+        }
+
+        let events = match &mut self.trigger {
+            Some(trigger) => trigger.process(event),
+            None => vec![event],
+        };
+
+        for mut event in events.into_iter() {
+            if let Some(throttle) = &mut self.throttle {
+                if !throttle.take() {
+                    self.throttled += 1;
+                    continue;
+                }
+            }
+
+            if let Some(resolver) = &mut self.resolver {
+                resolver.annotate(&mut event);
+            }
+            if let Some(veth) = &mut self.veth {
+                veth.annotate(&mut event);
+            }
+            #[cfg(feature = "geoip")]
+            if let Some(geoip) = &mut self.geoip {
+                geoip.annotate(&mut event);
+            }
+            if let Some(redactor) = &self.redactor {
+                redactor.redact(&mut event);
+            }
+            self.write(&event)?;
+        }
+        self.update_status();
+        self.enforce_memory();
+        self.flush_pending()
+    }
+
+    /// Get a handle on the output state, for inspecting it while the
+    /// collection is running.
+    pub(crate) fn status(&self) -> Arc<OutputStatus> {
+        Arc::clone(&self.status)
+    }
+
+    fn update_status(&self) {
+        self.status.dropped.store(self.dropped(), Ordering::Relaxed);
+        if let Some(trigger) = &self.trigger {
+            self.status
+                .triggered
+                .store(trigger.fired, Ordering::Relaxed);
+        }
+    }
+
+    /// Flush buffered events if they were kept for too long. To be called
+    /// regularly, including when no event is retrieved, so events are not
+    /// kept buffered when the collection is idle.
+    pub(crate) fn idle(&mut self) -> Result<()> {
+        self.flush_pending()
+    }
+
+    /// Write a meta event to all sinks. Meta events are never throttled, but
+    /// are redacted.
+    pub(crate) fn output_meta(&mut self, event: &mut Event) -> Result<()> {
+        if let Some(labels) = &self.labels {
+            event.insert(META_SECTION, event_field!("labels", labels.clone()));
+        }
+        if let Some(redactor) = &self.redactor {
+            redactor.redact(event);
+        }
+        self.write(event)
+    }
+
+    /// Number of events which were not written so far, as they were filtered
+    /// out or throttled.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.filter.as_ref().map_or(0, |f| f.filtered) + self.throttled
+    }
+
+    /// Write the warning meta events found by the sinks, to all of them. To be
+    /// called once the collection ended, before building the stop event.
+    pub(crate) fn output_warnings(&mut self) -> Result<()> {
+        let mut warnings = Vec::new();
+        for sink in self.sinks.iter_mut() {
+            warnings.append(&mut sink.warnings());
+        }
+        for mut warning in warnings.into_iter() {
+            self.output_meta(&mut warning)?;
+        }
+        Ok(())
+    }
+
+    /// Add the output summary counts to a (stop) meta event.
+    pub(crate) fn summary(&self, event: &mut Event) {
+        if let Some(filter) = &self.filter {
+            event.insert(META_SECTION, event_field!("filtered", filter.filtered));
+        }
+        event.insert(META_SECTION, event_field!("throttled", self.throttled));
+        if let Some(trigger) = &self.trigger {
+            event.insert(META_SECTION, event_field!("triggered", trigger.fired));
+        }
+        if let (Some(resolver), Some(_)) = (&self.resolver, self.max_memory) {
+            event.insert(
+                META_SECTION,
+                event_field!("resolver_evicted", resolver.evicted),
+            );
+        }
+        for sink in self.sinks.iter() {
+            sink.summary(event);
+        }
+    }
+
+    /// Flush all sinks.
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.flush()?;
+        }
+        self.last_flush = Instant::now();
+        self.pending = false;
+        Ok(())
+    }
+
+    /// Finish writing events to all sinks and flush them. To be called once
+    /// the collection ended.
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.finish()?;
+        }
+        self.flush()
+    }
+
+    /// Estimated memory used by the userspace caches and aggregates, in bytes.
+    fn memory(&self) -> usize {
+        self.resolver.as_ref().map_or(0, |r| r.memory())
+            + self.sinks.iter().map(|s| s.memory()).sum::<usize>()
+    }
+
+    /// Evict entries once over the memory budget, down to MEMORY_LOW_WATERMARK
+    /// percent of it so evictions are batched. The least critical structures
+    /// are evicted from first: the name resolver cache (evicted names are
+    /// looked up again), then the sinks by eviction order (packet paths, then
+    /// summary aggregates). If the budget can't be met, e.g. when all sinks
+    /// are already empty, events are still processed.
+    fn enforce_memory(&mut self) {
+        let max = match self.max_memory {
+            Some(max) => max,
+            None => return,
+        };
+        let used = self.memory();
+        if used <= max {
+            return;
+        }
+
+        let mut excess = used - max / 100 * MEMORY_LOW_WATERMARK;
+        if let Some(resolver) = &mut self.resolver {
+            excess = excess.saturating_sub(resolver.evict(excess));
+        }
+
+        let mut sinks: Vec<_> = self.sinks.iter_mut().filter(|s| s.memory() > 0).collect();
+        sinks.sort_by_key(|s| s.eviction_order());
+        for sink in sinks.into_iter() {
+            if excess == 0 {
+                break;
+            }
+            excess = excess.saturating_sub(sink.evict(excess));
+        }
+    }
+
+    fn flush_pending(&mut self) -> Result<()> {
+        if self.pending && self.last_flush.elapsed() >= self.flush_interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, event: &Event) -> Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.output(event)?;
+        }
+        self.pending = true;
+        Ok(())
+    }
+}
+
+/// Sink of an output spec.
+#[derive(Debug, PartialEq)]
+enum SinkSpec {
+    Stdout,
+    File(String),
+    Socket(String),
+    Fifo(String),
+}
+
+/// Output spec, as given by the user: "<sink>[:<path>][:<format>]", e.g.
+/// "stdout:text" or "file:events.json". The format defaults to JSON.
+#[derive(Debug, PartialEq)]
+struct OutputSpec {
+    sink: SinkSpec,
+    format: Format,
+}
+
+impl OutputSpec {
+    fn parse(spec: &str) -> Result<OutputSpec> {
+        let (sink, args) = spec.split_once(':').unwrap_or((spec, ""));
+
+        // Paths can't be empty, the format is optional and always the last
+        // part of the spec.
+        let path_and_format = |args: &str| -> Result<(String, Format)> {
+            let (path, format) = match args.rsplit_once(':') {
+                Some((path, format)) => (path, Format::parse(format)?),
+                None => (args, Format::Json),
+            };
+            if path.is_empty() {
+                bail!("missing path");
+            }
+            Ok((path.to_string(), format))
+        };
+
+        let spec = match sink {
+            "stdout" => OutputSpec {
+                sink: SinkSpec::Stdout,
+                format: match args {
+                    "" => Format::Json,
+                    format => Format::parse(format)?,
+                },
+            },
+            "file" | "socket" | "fifo" => {
+                let (path, format) = path_and_format(args)?;
+                OutputSpec {
+                    sink: match sink {
+                        "file" => SinkSpec::File(path),
+                        "socket" => SinkSpec::Socket(path),
+                        _ => SinkSpec::Fifo(path),
+                    },
+                    format,
+                }
+            }
+            x => bail!(
+                "unknown sink '{}' (expected stdout, file, socket or fifo)",
+                x
+            ),
+        };
+        Ok(spec)
+    }
+}
+
+/// Allocate the output sinks given the cli configuration and retrieve a group
+/// containing them. This is the primary entry point for writing events.
+pub(crate) fn get_output(cli: &CliConfig) -> Result<Output> {
+    let collect = cli
+        .subcommand
+        .as_any()
+        .downcast_ref::<Collect>()
+        .ok_or_else(|| anyhow!("wrong subcommand"))?;
+
+    let mut output = Output::new();
+    let args = collect.args()?;
+    // Columns of the CSV format, shared by all sinks using it.
+    if let Some(field) = args.fields.iter().find(|f| !f.contains('.')) {
+        bail!("Invalid field '{}': expected \"section.key\"", field);
+    }
+    let columns = csv_columns(&args.fields, &args.collectors);
+    let mapping = JsonMapping::new(&args.rename, &args.fields)?;
+    let envelope = match &args.envelope {
+        Some(fields) => Some(Envelope::new(fields, &args.label)?),
+        None => None,
+    };
+    // Timestamps are relative to the time the output is set up, just before
+    // the collection starts.
+    let start = match args.relative_time {
+        true => {
+            let now = clock_gettime(ClockId::CLOCK_MONOTONIC)?;
+            Some(now.tv_sec() as u64 * 1_000_000_000 + now.tv_nsec() as u64)
+        }
+        false => None,
+    };
+    let format = |name: &str| -> Result<Format> {
+        Ok(Format::parse(name)?
+            .with_columns(&columns)
+            .with_mapping(&mapping)
+            .with_envelope(&envelope)
+            .with_relative_time(start))
+    };
+    if let Some(path) = &args.output_file {
+        let compression = Compression::parse(args.compress.as_deref(), path)?;
+        output.add(Box::new(FileSink::new(
+            path,
+            format(args.format.as_deref().unwrap_or("json"))?,
+            compression,
+            args.compress_level,
+        )?));
+    }
+    if let Some(dir) = &args.ring_dir {
+        output.add(Box::new(RingDir::new(
+            dir,
+            args.ring_size.unwrap_or(1024 * 1024 * 1024),
+            format(args.format.as_deref().unwrap_or("json"))?,
+        )?));
+    }
+    if let Some(path) = &args.output_socket {
+        output.add(Box::new(SocketSink::new(
+            path,
+            format(args.format.as_deref().unwrap_or("json"))?,
+        )?));
+    }
+    if let Some(path) = &args.output_fifo {
+        output.add(Box::new(FifoSink::new(
+            path,
+            format(args.format.as_deref().unwrap_or("json"))?,
+        )?));
+    }
+    if args.output_syslog {
+        #[cfg(feature = "syslog")]
+        output.add(Box::new(SyslogSink::new(
+            parse_facility(args.syslog_facility.as_deref().unwrap_or("user"))?,
+            Severity::parse(args.syslog_severity.as_deref().unwrap_or("info"))?,
+            format(args.format.as_deref().unwrap_or("json"))?,
+        )));
+        #[cfg(not(feature = "syslog"))]
+        bail!("Syslog output is not supported, please build with the \"syslog\" feature");
+    }
+    for spec in args.output.iter() {
+        let OutputSpec { sink, format } =
+            OutputSpec::parse(spec).or_else(|e| bail!("Invalid output spec '{}': {}", spec, e))?;
+        let format = format
+            .with_columns(&columns)
+            .with_mapping(&mapping)
+            .with_envelope(&envelope)
+            .with_relative_time(start);
+
+        match sink {
+            SinkSpec::Stdout => output.add(Box::new(StdoutSink::new(format)?)),
+            SinkSpec::File(path) => {
+                let compression = Compression::parse(args.compress.as_deref(), &path)?;
+                output.add(Box::new(FileSink::new(
+                    &path,
+                    format,
+                    compression,
+                    args.compress_level,
+                )?))
+            }
+            SinkSpec::Socket(path) => output.add(Box::new(SocketSink::new(&path, format)?)),
+            SinkSpec::Fifo(path) => output.add(Box::new(FifoSink::new(&path, format)?)),
+        };
+    }
+    if args.summary {
+        output.add(Box::new(Summary::new(args.summary_top.unwrap_or(10))));
+    }
+    if args.top {
+        output.add(Box::new(Top::new(
+            TopSort::parse(args.top_by.as_deref().unwrap_or("packets"))?,
+            args.summary_top.unwrap_or(10),
+        )));
+    }
+    if !args.histogram.is_empty() {
+        output.add(Box::new(Histograms::new(
+            args.histogram.clone(),
+            args.histogram_by.clone(),
+            HistogramFormat::parse(args.histogram_format.as_deref().unwrap_or("text"))?,
+        )));
+    }
+    if let Some(packet) = &args.path {
+        output.add(Box::new(Paths::new(
+            packet,
+            PathFormat::parse(args.path_format.as_deref().unwrap_or("text"))?,
+        )?));
+    }
+    if let Some(endpoint) = &args.output_otlp {
+        #[cfg(feature = "otlp")]
+        output.add(Box::new(OtlpSink::new(
+            endpoint,
+            args.otlp_service.as_deref().unwrap_or("packet-tracer"),
+        )?));
+        #[cfg(not(feature = "otlp"))]
+        bail!(
+            "OpenTelemetry export to {} is not supported, please build with the \"otlp\" feature",
+            endpoint
+        );
+    }
+    if let Some(collector) = &args.output_ipfix {
+        output.add(Box::new(IpfixSink::new(
+            collector,
+            Duration::from_secs(args.ipfix_idle_timeout.unwrap_or(15)),
+        )?));
+    }
+    if let Some(capacity) = args.recorder {
+        if args.control_socket.is_none() {
+            bail!("The flight recorder (--recorder) requires a control socket to dump events");
+        }
+        let recorder = Arc::new(Recorder::new(capacity as usize));
+        set_recorder(Arc::clone(&recorder))?;
+        output.add(Box::new(RecorderSink::new(recorder)));
+    }
+    if !args.label.is_empty() {
+        if let Some(label) = args.label.iter().find(|l| l.is_empty() || l.contains(',')) {
+            bail!(
+                "Invalid label '{}': labels can't be empty or contain ','",
+                label
+            );
+        }
+        output.set_labels(&args.label);
+    }
+    // Default to the standard output if no other sink was requested.
+    if output.sinks.is_empty() {
+        output.add(Box::new(StdoutSink::new(format(
+            args.format.as_deref().unwrap_or("json"),
+        )?)?));
+    }
+    // Asymmetric routing detection doesn't replace the standard output.
+    if args.detect_asymmetry {
+        output.add(Box::new(Asymmetry::new()));
+    }
+
+    output.set_flush_interval(Duration::from_millis(args.flush_interval.unwrap_or(100)));
+
+    if let Some(filter) = &args.r#where {
+        output.set_filter(Filter::parse(filter)?);
+    }
+
+    if args.resolve_names {
+        output.resolver = Some(NameResolver::new());
+    }
+    // Packet paths follow packets across veth pairs.
+    if args.veth_peers || args.path.is_some() {
+        output.veth = Some(VethPeers::new());
+    }
+    if !args.geoip_db.is_empty() {
+        #[cfg(feature = "geoip")]
+        {
+            output.geoip = Some(GeoIp::new(&args.geoip_db)?);
+        }
+        #[cfg(not(feature = "geoip"))]
+        bail!("GeoIP annotations are not supported, please build with the \"geoip\" feature");
+    }
+
+    if !args.redact.is_empty() {
+        output.redactor = Some(Redactor::new(
+            &args.redact,
+            RedactMode::parse(args.redact_mode.as_deref().unwrap_or("hash"))?,
+            args.redact_salt.as_deref(),
+        )?);
+    }
+
+    if let Some(max_memory) = args.max_memory {
+        output.set_max_memory(max_memory as usize * 1024 * 1024);
+    }
+
+    if let Some(max_eps) = args.max_eps {
+        output.set_max_eps(max_eps);
+    }
+
+    if let Some(trigger) = &args.trigger {
+        output.set_trigger(
+            TriggerCondition::parse(trigger)?,
+            args.trigger_pre.unwrap_or(100),
+            Duration::from_secs(args.trigger_post.unwrap_or(5)),
+        );
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    struct DummySink(Rc<RefCell<u64>>);
+
+    impl EventOutput for DummySink {
+        fn output(&mut self, _: &Event) -> Result<()> {
+            *self.0.borrow_mut() += 1;
+            Ok(())
+        }
+    }
+
+    struct FlushSink(Rc<RefCell<u64>>);
+
+    impl EventOutput for FlushSink {
+        fn output(&mut self, _: &Event) -> Result<()> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            *self.0.borrow_mut() += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_flush() -> Result<()> {
+        let flushes = Rc::new(RefCell::new(0));
+        let mut output = Output::new();
+        output.add(Box::new(FlushSink(flushes.clone())));
+
+        // Sinks are flushed after each event by default.
+        output.output(Event::new())?;
+        output.output(Event::new())?;
+        assert!(*flushes.borrow() == 2);
+
+        // Nothing to flush.
+        output.idle()?;
+        assert!(*flushes.borrow() == 2);
+
+        // Events are batched when an interval is set.
+        output.set_flush_interval(Duration::from_secs(3600));
+        output.output(Event::new())?;
+        output.output(Event::new())?;
+        output.idle()?;
+        assert!(*flushes.borrow() == 2);
+
+        output.set_flush_interval(Duration::ZERO);
+        output.idle()?;
+        assert!(*flushes.borrow() == 3);
+        Ok(())
+    }
+
+    #[test]
+    fn output_spec() -> Result<()> {
+        assert!(
+            OutputSpec::parse("stdout")?
+                == OutputSpec {
+                    sink: SinkSpec::Stdout,
+                    format: Format::Json
+                }
+        );
+        assert!(
+            OutputSpec::parse("stdout:text")?
+                == OutputSpec {
+                    sink: SinkSpec::Stdout,
+                    format: Format::Text
+                }
+        );
+        assert!(
+            OutputSpec::parse("file:/tmp/events.json")?
+                == OutputSpec {
+                    sink: SinkSpec::File("/tmp/events.json".to_string()),
+                    format: Format::Json
+                }
+        );
+        assert!(
+            OutputSpec::parse("socket:/run/pt.sock:text")?
+                == OutputSpec {
+                    sink: SinkSpec::Socket("/run/pt.sock".to_string()),
+                    format: Format::Text
+                }
+        );
+        assert!(
+            OutputSpec::parse("fifo:/run/pt.fifo:csv")?
+                == OutputSpec {
+                    sink: SinkSpec::Fifo("/run/pt.fifo".to_string()),
+                    format: Format::Csv(Vec::new())
+                }
+        );
+
+        assert!(OutputSpec::parse("").is_err());
+        assert!(OutputSpec::parse("stderr").is_err());
+        assert!(OutputSpec::parse("stdout:yaml").is_err());
+        assert!(OutputSpec::parse("file").is_err());
+        assert!(OutputSpec::parse("file::text").is_err());
+        assert!(OutputSpec::parse("file:events.bin:binary").is_err());
+        Ok(())
+    }
+
+    // Sink keeping the events written, as JSON.
+    struct JsonSink(Rc<RefCell<Vec<serde_json::Value>>>);
+
+    impl EventOutput for JsonSink {
+        fn output(&mut self, event: &Event) -> Result<()> {
+            self.0.borrow_mut().push(event.to_json());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_labels() -> Result<()> {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut output = Output::new();
+        output
+            .add(Box::new(JsonSink(events.clone())))
+            .set_labels(&["incident-4567".to_string(), "node-a".to_string()]);
+
+        output.output_meta(&mut Event::new())?;
+        output.output(Event::new())?;
+
+        let events = events.borrow();
+        assert!(events[0][META_SECTION]["labels"] == "incident-4567,node-a");
+        assert!(events[1]["common"]["labels"] == "incident-4567,node-a");
+        Ok(())
+    }
+
+    #[test]
+    fn output_throttle() -> Result<()> {
+        let count = Rc::new(RefCell::new(0));
+        let mut output = Output::new();
+        output
+            .add(Box::new(DummySink(count.clone())))
+            .set_max_eps(10);
+
+        for _ in 0..15 {
+            output.output(Event::new())?;
+        }
+        // Meta events are not throttled.
+        output.output_meta(&mut Event::new())?;
+
+        assert!(*count.borrow() == 11);
+
+        let mut event = Event::new();
+        output.summary(&mut event);
+        assert!(event.get::<u64>(META_SECTION, "throttled")? == Some(&5));
+        Ok(())
+    }
+
+    /// Sink whose state is only its estimated memory, freeing what is asked.
+    struct MemorySink(Rc<RefCell<usize>>, u8);
+
+    impl EventOutput for MemorySink {
+        fn output(&mut self, _: &Event) -> Result<()> {
+            *self.0.borrow_mut() += 100;
+            Ok(())
+        }
+
+        fn memory(&self) -> usize {
+            *self.0.borrow()
+        }
+
+        fn evict(&mut self, bytes: usize) -> usize {
+            let freed = bytes.min(*self.0.borrow());
+            *self.0.borrow_mut() -= freed;
+            freed
+        }
+
+        fn eviction_order(&self) -> u8 {
+            self.1
+        }
+    }
+
+    #[test]
+    fn output_memory() -> Result<()> {
+        let (critical, other) = (Rc::new(RefCell::new(0)), Rc::new(RefCell::new(0)));
+        let mut output = Output::new();
+        output
+            .add(Box::new(MemorySink(critical.clone(), 2)))
+            .add(Box::new(MemorySink(other.clone(), 1)))
+            .set_max_memory(1000);
+
+        for _ in 0..5 {
+            output.output(Event::new())?;
+        }
+        assert!(*critical.borrow() == 500 && *other.borrow() == 500);
+
+        // Over the budget, evict down to the low watermark starting with the
+        // least critical sink.
+        output.output(Event::new())?;
+        assert!(*critical.borrow() == 600 && *other.borrow() == 300);
+
+        // Not enough to evict from the least critical sink.
+        for _ in 0..3 {
+            output.output(Event::new())?;
+        }
+        assert!(*critical.borrow() == 900 && *other.borrow() == 0);
+        Ok(())
+    }
+}
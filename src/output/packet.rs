@@ -0,0 +1,273 @@
+//! # Packet
+//!
+//! Decodes the packet bytes reported by the skb collector (see
+//! --capture-bytes) into their 5-tuple and TCP flags, for the outputs
+//! aggregating packets: --summary, --top, --detect-asymmetry and
+//! --output-ipfix.
+//!
+//! Packets can start at their Ethernet or IP header. VLAN tags, MPLS label
+//! stacks and IPv6 extension headers (including the authentication header)
+//! are followed, up to VLAN_MAX, MPLS_MAX and IPV6_EXT_MAX of them
+//! respectively; packets with more of those are not decoded.
+
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Maximum number of VLAN tags followed when decoding packets.
+const VLAN_MAX: usize = 2;
+/// Maximum number of IPv6 extension headers followed when decoding packets.
+const IPV6_EXT_MAX: usize = 8;
+/// Maximum number of MPLS labels followed when decoding packets.
+const MPLS_MAX: usize = 8;
+
+/// Decoded packet.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Packet {
+    pub(super) saddr: IpAddr,
+    pub(super) daddr: IpAddr,
+    pub(super) protocol: u8,
+    /// Ports, 0 for protocols other than TCP, UDP and SCTP, for non-first
+    /// fragments and when the L4 header is truncated.
+    pub(super) sport: u16,
+    pub(super) dport: u16,
+    /// TCP flags, 0 for other protocols.
+    pub(super) tcp_flags: u8,
+}
+
+impl Packet {
+    /// Name of the L4 protocol, or its number when not a well-known one.
+    pub(super) fn protocol_name(&self) -> String {
+        match self.protocol {
+            1 => "icmp".to_string(),
+            6 => "tcp".to_string(),
+            17 => "udp".to_string(),
+            58 => "icmpv6".to_string(),
+            132 => "sctp".to_string(),
+            x => x.to_string(),
+        }
+    }
+}
+
+/// Decode a packet given as an hex string.
+pub(super) fn decode_packet(hex: &str) -> Option<Packet> {
+    let bytes = (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let be16 = |off: usize| Some(u16::from_be_bytes([*bytes.get(off)?, *bytes.get(off + 1)?]));
+
+    // Look for an Ethernet header first, possibly followed by VLAN tags.
+    let mut off = 12;
+    let mut tags = 0;
+    while matches!(be16(off), Some(0x8100) | Some(0x88a8)) {
+        if tags == VLAN_MAX {
+            return None;
+        }
+        tags += 1;
+        off += 4;
+    }
+    let ip = match be16(off) {
+        Some(0x0800) | Some(0x86dd) => off + 2,
+        Some(0x8847) | Some(0x8848) => mpls_end(&bytes, off + 2)?,
+        // Not an Ethernet header we know of, but VLAN tags were found.
+        _ if tags > 0 => return None,
+        _ => 0,
+    };
+
+    let (saddr, daddr, protocol, l4) = match bytes.get(ip)? >> 4 {
+        4 => {
+            let hdr = bytes.get(ip..ip + 20)?;
+            let first = be16(ip + 6)? & 0x1fff == 0;
+            (
+                IpAddr::from(Ipv4Addr::new(hdr[12], hdr[13], hdr[14], hdr[15])),
+                IpAddr::from(Ipv4Addr::new(hdr[16], hdr[17], hdr[18], hdr[19])),
+                hdr[9],
+                first.then_some(ip + (hdr[0] & 0xf) as usize * 4),
+            )
+        }
+        6 => {
+            let hdr = bytes.get(ip..ip + 40)?;
+            let (protocol, l4) = ipv6_l4(&bytes, hdr[6], ip + 40)?;
+            (
+                IpAddr::from(<[u8; 16]>::try_from(&hdr[8..24]).ok()?),
+                IpAddr::from(<[u8; 16]>::try_from(&hdr[24..40]).ok()?),
+                protocol,
+                l4,
+            )
+        }
+        _ => return None,
+    };
+
+    let mut packet = Packet {
+        saddr,
+        daddr,
+        protocol,
+        sport: 0,
+        dport: 0,
+        tcp_flags: 0,
+    };
+    if let (Some(off), 6 | 17 | 132) = (l4, protocol) {
+        packet.sport = be16(off).unwrap_or(0);
+        packet.dport = be16(off + 2).unwrap_or(0);
+        if protocol == 6 {
+            packet.tcp_flags = bytes.get(off + 13).copied().unwrap_or(0);
+        }
+    }
+    Some(packet)
+}
+
+/// Get the offset following an MPLS label stack, starting at `off`.
+fn mpls_end(bytes: &[u8], off: usize) -> Option<usize> {
+    (0..MPLS_MAX)
+        .map(|i| off + i * 4)
+        // Bottom of stack bit.
+        .find(|off| bytes.get(off + 2).map_or(false, |b| b & 0x1 != 0))
+        .map(|off| off + 4)
+}
+
+/// Follow the IPv6 extension headers starting at `off`, `nexthdr` being the
+/// type of the first one. Returns the L4 protocol and the offset of its header,
+/// if this is the first fragment.
+fn ipv6_l4(bytes: &[u8], mut nexthdr: u8, mut off: usize) -> Option<(u8, Option<usize>)> {
+    let is_ext = |nexthdr: u8| matches!(nexthdr, 0 | 43 | 44 | 51 | 60);
+
+    let mut first = true;
+    for _ in 0..IPV6_EXT_MAX {
+        if !is_ext(nexthdr) {
+            return Some((nexthdr, first.then_some(off)));
+        }
+        let hdrlen = *bytes.get(off + 1)? as usize;
+        let len = match nexthdr {
+            // Fragment header, fixed size. Only the first fragment holds the
+            // L4 header.
+            44 => {
+                let frag_off = u16::from_be_bytes([*bytes.get(off + 2)?, *bytes.get(off + 3)?]);
+                first = frag_off & 0xfff8 == 0;
+                8
+            }
+            // Authentication header, in 4-octet units.
+            51 => (hdrlen + 2) * 4,
+            _ => (hdrlen + 1) * 8,
+        };
+        nexthdr = *bytes.get(off)?;
+        off += len;
+    }
+    match is_ext(nexthdr) {
+        true => None,
+        false => Some((nexthdr, first.then_some(off))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ethernet + IPv4/UDP header, 10.0.0.1 > 10.0.0.2.
+    const PACKET: &str = "00112233445566778899aabb08004500001c000040004011000\
+                          00a0000010a000002";
+    // Ethernet + IPv4/TCP headers, 10.0.0.1:1234 > 10.0.0.2:80, without the
+    // flags byte.
+    const TCP: &str = "00112233445566778899aabb080045000028000040004006000\
+                       00a0000010a00000204d20050000000000000000050";
+    // Ethernet + IPv6/UDP headers, 2001:db8::1:53 > 2001:db8::2:5353.
+    const UDP6: &str = "00112233445566778899aabb86dd6000000000081140\
+                        20010db8000000000000000000000001\
+                        20010db8000000000000000000000002\
+                        003514e900080000";
+
+    #[test]
+    fn decode() {
+        let packet = decode_packet(PACKET).unwrap();
+        assert!(packet.protocol_name() == "udp");
+        assert!(
+            packet.saddr == IpAddr::from([10, 0, 0, 1])
+                && packet.daddr == IpAddr::from([10, 0, 0, 2])
+        );
+        // Packets starting at their IP header.
+        assert!(decode_packet(&PACKET[28..]) == Some(packet));
+        // Truncated packets.
+        assert!(decode_packet(&PACKET[..60]).is_none());
+        assert!(decode_packet("zz").is_none());
+
+        // VLAN stacks, up to VLAN_MAX tags.
+        let tagged = |tags: usize| {
+            format!(
+                "{}{}{}",
+                &PACKET[..24],
+                "8100000a".repeat(tags),
+                &PACKET[24..]
+            )
+        };
+        assert!(decode_packet(&tagged(2)).is_some());
+        assert!(decode_packet(&tagged(3)).is_none());
+        assert!(decode_packet(&tagged(1000)).is_none());
+
+        // MPLS stacks, up to MPLS_MAX labels. The last one is the bottom of
+        // stack.
+        let labeled = |labels: usize| {
+            format!(
+                "{}8847{}{}{}",
+                &PACKET[..24],
+                "00064040".repeat(labels - 1),
+                "00065140",
+                &PACKET[28..]
+            )
+        };
+        assert!(decode_packet(&labeled(1)).map(|p| p.saddr) == Some(IpAddr::from([10, 0, 0, 1])));
+        assert!(decode_packet(&labeled(MPLS_MAX)).is_some());
+        assert!(decode_packet(&labeled(MPLS_MAX + 1)).is_none());
+        assert!(decode_packet(&format!("{}8847{}", &PACKET[..24], "00064040")).is_none());
+    }
+
+    #[test]
+    fn decode_l4() {
+        let packet = decode_packet(&format!("{}{}", TCP, "02")).unwrap();
+        assert!(packet.sport == 1234 && packet.dport == 80);
+        assert!(packet.protocol == 6 && packet.tcp_flags == 0x2);
+
+        let packet = decode_packet(UDP6).unwrap();
+        assert!(packet.saddr == "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert!(packet.sport == 53 && packet.dport == 5353);
+        assert!(packet.protocol == 17 && packet.tcp_flags == 0);
+
+        // Truncated L4 headers still give a packet, not IP ones.
+        let packet = decode_packet(&TCP[..74]).unwrap();
+        assert!(packet.protocol == 6 && packet.sport == 1234 && packet.dport == 0);
+        assert!(decode_packet(&TCP[..40]).is_none());
+
+        // Non-first IPv4 fragments do not hold the L4 header.
+        let fragment = format!("{}2001{}", &TCP[..40], &TCP[44..]);
+        let packet = decode_packet(&fragment).unwrap();
+        assert!(packet.protocol == 6 && packet.sport == 0);
+    }
+
+    #[test]
+    fn decode_ipv6_ext() {
+        // IPv6 header, followed by extension headers.
+        let packet = |nexthdr: &str, exts: &str| {
+            format!(
+                "600000000000{}40{}{}{}",
+                nexthdr,
+                "20010db8000000000000000000000001",
+                "20010db8000000000000000000000002",
+                exts
+            )
+        };
+        // Hop-by-hop, then a fragment header, then UDP.
+        let ok = packet("00", "2c000000000000001100000000000000003514e9");
+        let decoded = decode_packet(&ok).unwrap();
+        assert!(decoded.protocol_name() == "udp" && decoded.sport == 53);
+        // Same, but not the first fragment.
+        let fragment = packet("00", "2c000000000000001100010000000000003514e9");
+        let decoded = decode_packet(&fragment).unwrap();
+        assert!(decoded.protocol_name() == "udp" && decoded.sport == 0);
+        // Authentication header (in 4-octet units), then TCP.
+        let ah = packet("33", &format!("0601{}04d20050", "0".repeat(20)));
+        let decoded = decode_packet(&ah).unwrap();
+        assert!(decoded.protocol == 6 && decoded.sport == 1234 && decoded.dport == 80);
+
+        // Destination options headers pointing to each other, in a loop.
+        let looping = packet("3c", &"3c00000000000000".repeat(IPV6_EXT_MAX + 1));
+        assert!(decode_packet(&looping).is_none());
+        // Same, but with a length running past the end of the packet.
+        assert!(decode_packet(&packet("3c", "3cff")).is_none());
+    }
+}
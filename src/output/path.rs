@@ -0,0 +1,589 @@
+//! # Packet paths
+//!
+//! Renders the path of tracked packets instead of writing events: the probes
+//! each packet went through, in order, along with the time elapsed since the
+//! first one and where it was dropped. Packets are identified using the
+//! skb-tracking collector, events without a tracking section are ignored.
+//! Paths are written once the collection ends, as indented text or as a DOT
+//! graph (e.g. for rendering with `dot -Tsvg`).
+//!
+//! Forwarding loops are flagged: a packet going through the same probe on the
+//! same device (see the skb collector ifindex field) at least
+//! PATH_LOOP_VISITS times is considered looping. Loops are shown in the paths
+//! and reported as "loop" warning meta events, with the repeated segment and
+//! the number of visits.
+//!
+//! Netfilter traversals (see the netfilter collector) are shown in the steps,
+//! e.g. "[nf filter/FORWARD]", to tell which table or chain a packet was
+//! dropped in.
+//!
+//! Packets crossing a veth pair (see the skb.veth_peer annotation) are shown
+//! as a single hop: the first step on the peer device is marked with the
+//! device the packet came from, e.g. "[veth from if4]", the peer being
+//! possibly in another network namespace.
+//!
+//! Under a memory budget (see --max-memory), the paths of the oldest packets
+//! are evicted as a whole, before the summary aggregates; their count is
+//! reported in the stop event.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+    mem,
+};
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use super::EventOutput;
+use crate::{
+    core::events::{
+        meta::{warning_event, META_SECTION},
+        Event, EventField,
+    },
+    event_field,
+};
+
+const TRACKING_SECTION: &str = "skb-tracking";
+
+/// Number of visits of a probe on a device from which a path is a loop.
+const PATH_LOOP_VISITS: usize = 3;
+
+/// Output format of the paths.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum PathFormat {
+    Text,
+    Dot,
+}
+
+impl PathFormat {
+    pub(super) fn parse(name: &str) -> Result<PathFormat> {
+        Ok(match name {
+            "text" => PathFormat::Text,
+            "dot" => PathFormat::Dot,
+            x => bail!("Unknown path format {}", x),
+        })
+    }
+}
+
+/// Single step of a packet path.
+pub(super) struct Step {
+    pub(super) timestamp: u64,
+    pub(super) probe: String,
+    pub(super) drop_reason: u64,
+    /// Device the packet was attached to, if known.
+    pub(super) ifindex: Option<u64>,
+    /// Netfilter table and chain (or family and hook) traversed, if any.
+    pub(super) netfilter: Option<String>,
+    /// Peer of the device, if it is a veth one.
+    pub(super) veth_peer: Option<u64>,
+}
+
+impl Step {
+    /// Probe and device, e.g. "kprobe:ip_rcv@if2".
+    fn point(&self) -> String {
+        match self.ifindex {
+            Some(ifindex) => format!("{}@if{}", self.probe, ifindex),
+            None => self.probe.clone(),
+        }
+    }
+
+    /// Label of the step, given the timestamp of the first step of the path
+    /// and the previous step, if any.
+    fn label(&self, first: u64, prev: Option<&Step>) -> String {
+        let mut label = format!(
+            "{} (+{:.3}us)",
+            self.point(),
+            self.timestamp.saturating_sub(first) as f64 / 1000.0
+        );
+        // The packet crossed a veth pair, from the previous step device.
+        if let Some(prev) = prev {
+            if prev.veth_peer.is_some() && prev.veth_peer == self.ifindex {
+                // Unwrap can't fail as a device has a peer.
+                label.push_str(&format!(" [veth from if{}]", prev.ifindex.unwrap()));
+            }
+        }
+        if let Some(netfilter) = &self.netfilter {
+            label.push_str(&format!(" [nf {}]", netfilter));
+        }
+        if self.drop_reason != 0 {
+            label.push_str(&format!(" [dropped, reason {}]", self.drop_reason));
+        }
+        label
+    }
+}
+
+/// Loop found in a packet path.
+#[derive(Debug, PartialEq)]
+struct PathLoop {
+    /// Steps from the first visit of the repeated one, up to its next visit
+    /// (excluded), as probe@device.
+    segment: Vec<String>,
+    /// Number of visits of the repeated step.
+    visits: usize,
+}
+
+impl PathLoop {
+    fn describe(&self) -> String {
+        format!("{} ({} visits)", self.segment.join(" -> "), self.visits)
+    }
+}
+
+/// Find a loop in a path, whose steps are sorted: the earliest step on a
+/// device visited at least PATH_LOOP_VISITS times. Steps without a device
+/// can't be told apart and are not considered.
+fn find_loop(steps: &[Step]) -> Option<PathLoop> {
+    let mut visits: HashMap<(&str, u64), Vec<usize>> = HashMap::new();
+    for (i, step) in steps.iter().enumerate() {
+        if let Some(ifindex) = step.ifindex {
+            visits.entry((&step.probe, ifindex)).or_default().push(i);
+        }
+    }
+
+    let looping = visits
+        .values()
+        .filter(|v| v.len() >= PATH_LOOP_VISITS)
+        .min_by_key(|v| v[0])?;
+
+    Some(PathLoop {
+        segment: steps[looping[0]..looping[1]]
+            .iter()
+            .map(|s| s.point())
+            .collect(),
+        visits: looping.len(),
+    })
+}
+
+pub(super) struct Paths {
+    /// Only render the path of this packet (skb-tracking.orig_head), if set.
+    packet: Option<u64>,
+    format: PathFormat,
+    /// Paths, by packet tracking id: (orig_head, timestamp).
+    paths: BTreeMap<(u64, u64), Vec<Step>>,
+    /// Estimated memory used by the paths, in bytes.
+    memory: usize,
+    /// Number of paths evicted to stay within the memory budget.
+    evicted: u64,
+    /// Number of looping paths found.
+    loops: u64,
+}
+
+/// Estimated size of a path, not including its steps.
+pub(super) const PATH_SIZE: usize = mem::size_of::<((u64, u64), Vec<Step>)>() + 16;
+
+/// Estimated size of a step.
+pub(super) fn step_size(step: &Step) -> usize {
+    mem::size_of::<Step>() + step.probe.len() + step.netfilter.as_ref().map_or(0, |nf| nf.len())
+}
+
+impl Paths {
+    /// Create a path renderer, given a packet id (orig_head) or "all".
+    pub(super) fn new(packet: &str, format: PathFormat) -> Result<Paths> {
+        let packet = match packet {
+            "all" => None,
+            id => Some(
+                match id.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => id.parse(),
+                }
+                .or_else(|_| bail!("Invalid packet id '{}'", id))?,
+            ),
+        };
+
+        Ok(Paths {
+            packet,
+            format,
+            paths: BTreeMap::new(),
+            memory: 0,
+            evicted: 0,
+            loops: 0,
+        })
+    }
+
+    /// Sort the steps of all paths by time. Events can be retrieved out of
+    /// order, e.g. when not using ordered processing.
+    fn sort_steps(&mut self) {
+        self.paths
+            .values_mut()
+            .for_each(|steps| steps.sort_by_key(|s| s.timestamp));
+    }
+
+    fn report_text(&self) -> String {
+        let mut report = String::new();
+        for ((orig_head, timestamp), steps) in self.paths.iter() {
+            report.push_str(&format!("packet {:#x} ({})\n", orig_head, timestamp));
+            for (i, step) in steps.iter().enumerate() {
+                let branch = match i == steps.len() - 1 {
+                    true => "└─",
+                    false => "├─",
+                };
+                let prev = i.checked_sub(1).map(|p| &steps[p]);
+                report.push_str(&format!(
+                    "{} {}\n",
+                    branch,
+                    step.label(steps[0].timestamp, prev)
+                ));
+            }
+            if let Some(path_loop) = find_loop(steps) {
+                report.push_str(&format!("loop: {}\n", path_loop.describe()));
+            }
+            report.push('\n');
+        }
+        report
+    }
+
+    fn report_dot(&self) -> String {
+        let mut report = String::from("digraph paths {\n\tnode [shape=box];\n");
+        for (p, ((orig_head, timestamp), steps)) in self.paths.iter().enumerate() {
+            let path_loop = match find_loop(steps) {
+                Some(path_loop) => format!(", loop: {}", path_loop.describe()),
+                None => String::new(),
+            };
+            report.push_str(&format!(
+                "\tsubgraph cluster_{} {{\n\t\tlabel=\"packet {:#x} ({}){}\";\n",
+                p, orig_head, timestamp, path_loop
+            ));
+            for (i, step) in steps.iter().enumerate() {
+                report.push_str(&format!(
+                    "\t\tp{}_{} [label=\"{}\"{}];\n",
+                    p,
+                    i,
+                    step.label(steps[0].timestamp, i.checked_sub(1).map(|p| &steps[p]))
+                        .replace('"', "\\\""),
+                    match step.drop_reason {
+                        0 => "",
+                        _ => ", color=red",
+                    }
+                ));
+                if i > 0 {
+                    report.push_str(&format!("\t\tp{}_{} -> p{}_{};\n", p, i - 1, p, i));
+                }
+            }
+            report.push_str("\t}\n");
+        }
+        report.push_str("}\n");
+        report
+    }
+}
+
+/// Retrieve the path step of a tracked packet from an event, along with the
+/// packet tracking id: (orig_head, timestamp). Meta and untracked events are
+/// not part of any path.
+pub(super) fn tracked_step(event: &Event) -> Option<((u64, u64), Step)> {
+    let json = event.to_json();
+    if json.get(META_SECTION).is_some() {
+        return None;
+    }
+
+    let tracking = json.get(TRACKING_SECTION)?;
+    let u64_field = |section: &Value, key: &str| section.get(key).and_then(|v| v.as_u64());
+
+    let orig_head = u64_field(tracking, "orig_head")?;
+    let tracking_ts = u64_field(tracking, "timestamp")?;
+
+    let common = json.get("common").unwrap_or(&Value::Null);
+    let skb = json.get("skb").unwrap_or(&Value::Null);
+    let probe = match common.get("probe").or_else(|| common.get("symbol")) {
+        Some(Value::String(probe)) => probe.clone(),
+        _ => "?".to_string(),
+    };
+
+    let netfilter = json.get("netfilter").and_then(|nf| {
+        let str_field = |key: &str| nf.get(key).and_then(|v| v.as_str());
+        match (str_field("table"), str_field("chain")) {
+            (Some(table), Some(chain)) => Some(format!("{}/{}", table, chain)),
+            _ => Some(format!("{} {}", str_field("family")?, str_field("hook")?)),
+        }
+    });
+
+    Some((
+        (orig_head, tracking_ts),
+        Step {
+            timestamp: u64_field(common, "timestamp").unwrap_or(0),
+            probe,
+            drop_reason: u64_field(tracking, "drop_reason").unwrap_or(0),
+            ifindex: u64_field(skb, "ifindex"),
+            netfilter,
+            veth_peer: u64_field(skb, "veth_peer"),
+        },
+    ))
+}
+
+impl EventOutput for Paths {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        let ((orig_head, tracking_ts), step) = match tracked_step(event) {
+            Some(step) => step,
+            None => return Ok(()),
+        };
+        if self.packet.map_or(false, |packet| packet != orig_head) {
+            return Ok(());
+        }
+
+        self.memory += step_size(&step);
+        self.paths
+            .entry((orig_head, tracking_ts))
+            .or_insert_with(|| {
+                self.memory += PATH_SIZE;
+                Vec::new()
+            })
+            .push(step);
+        Ok(())
+    }
+
+    fn summary(&self, event: &mut Event) {
+        if self.evicted > 0 {
+            event.insert(META_SECTION, event_field!("path_evicted", self.evicted));
+        }
+        if self.loops > 0 {
+            event.insert(META_SECTION, event_field!("path_loops", self.loops));
+        }
+    }
+
+    fn warnings(&mut self) -> Vec<Event> {
+        self.sort_steps();
+
+        let mut warnings = Vec::new();
+        for ((orig_head, _), steps) in self.paths.iter() {
+            if let Some(path_loop) = find_loop(steps) {
+                let mut event = warning_event("loop");
+                event.insert(META_SECTION, event_field!("packet", *orig_head));
+                event.insert(
+                    META_SECTION,
+                    event_field!("segment", path_loop.segment.join(" -> ")),
+                );
+                event.insert(META_SECTION, event_field!("hops", path_loop.visits as u64));
+                warnings.push(event);
+            }
+        }
+        self.loops = warnings.len() as u64;
+        warnings
+    }
+
+    fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Evict the paths of the oldest packets, by tracking timestamp.
+    fn evict(&mut self, bytes: usize) -> usize {
+        let mut keys: Vec<_> = self.paths.keys().copied().collect();
+        keys.sort_by_key(|(orig_head, ts)| (*ts, *orig_head));
+
+        let mut freed = 0;
+        for key in keys.iter() {
+            if freed >= bytes {
+                break;
+            }
+            if let Some(steps) = self.paths.remove(key) {
+                freed += PATH_SIZE + steps.iter().map(step_size).sum::<usize>();
+                self.evicted += 1;
+            }
+        }
+        self.memory -= freed;
+        freed
+    }
+
+    fn eviction_order(&self) -> u8 {
+        1
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.sort_steps();
+
+        let mut stdout = io::stdout();
+        match self.format {
+            PathFormat::Text => write!(stdout, "{}", self.report_text())?,
+            PathFormat::Dot => write!(stdout, "{}", self.report_dot())?,
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::EventField;
+    use crate::event_field;
+
+    fn tracked(orig_head: u64, probe: &str, timestamp: u64, drop_reason: u32) -> Event {
+        let mut event = Event::new();
+        event.insert("common", event_field!("probe", probe.to_string()));
+        event.insert("common", event_field!("timestamp", timestamp));
+        event.insert(TRACKING_SECTION, event_field!("orig_head", orig_head));
+        event.insert(TRACKING_SECTION, event_field!("timestamp", 1000u64));
+        event.insert(TRACKING_SECTION, event_field!("drop_reason", drop_reason));
+        event
+    }
+
+    fn paths(packet: &str, format: PathFormat) -> Result<Paths> {
+        let mut paths = Paths::new(packet, format)?;
+        paths.output(&tracked(0x10, "kprobe:ip_forward", 2500, 0))?;
+        paths.output(&tracked(0x10, "kprobe:ip_rcv", 1000, 0))?;
+        paths.output(&tracked(0x10, "kprobe:kfree_skb_reason", 4000, 2))?;
+        paths.output(&tracked(0x20, "raw_tracepoint:net_dev_start_xmit", 5000, 0))?;
+        // Untracked events are ignored.
+        paths.output(&Event::new())?;
+        paths.sort_steps();
+        Ok(paths)
+    }
+
+    #[test]
+    fn text() -> Result<()> {
+        assert!(
+            paths("all", PathFormat::Text)?.report_text()
+                == "packet 0x10 (1000)\n\
+                    ├─ kprobe:ip_rcv (+0.000us)\n\
+                    ├─ kprobe:ip_forward (+1.500us)\n\
+                    └─ kprobe:kfree_skb_reason (+3.000us) [dropped, reason 2]\n\
+                    \n\
+                    packet 0x20 (1000)\n\
+                    └─ raw_tracepoint:net_dev_start_xmit (+0.000us)\n\
+                    \n"
+        );
+
+        // Single packet.
+        let paths = paths("0x20", PathFormat::Text)?;
+        assert!(paths.paths.len() == 1);
+        assert!(paths.paths.contains_key(&(0x20, 1000)));
+        assert!(Paths::new("foo", PathFormat::Text).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn dot() -> Result<()> {
+        let report = paths("16", PathFormat::Dot)?.report_dot();
+        assert!(report.starts_with("digraph paths {\n"));
+        assert!(report.contains("\t\tp0_0 [label=\"kprobe:ip_rcv (+0.000us)\"];\n"));
+        assert!(report.contains(
+            "\t\tp0_2 [label=\"kprobe:kfree_skb_reason (+3.000us) [dropped, reason 2]\", color=red];\n"
+        ));
+        assert!(report.contains("\t\tp0_0 -> p0_1;\n\t\tp0_1 -> p0_2;\n"));
+        assert!(!report.contains("net_dev_start_xmit"));
+        Ok(())
+    }
+
+    #[test]
+    fn evict() -> Result<()> {
+        let mut paths = paths("all", PathFormat::Text)?;
+        paths.output(&tracked(0x30, "kprobe:ip_rcv", 6000, 0))?;
+        let memory = paths.memory();
+
+        // Oldest packets are evicted first, by tracking timestamp then id.
+        assert!(paths.evict(1) > 0);
+        assert!(paths.paths.len() == 2 && !paths.paths.contains_key(&(0x10, 1000)));
+        assert!(paths.memory() < memory);
+
+        assert!(paths.evict(usize::MAX) > 0);
+        assert!(paths.paths.is_empty() && paths.memory() == 0);
+
+        let mut event = Event::new();
+        paths.summary(&mut event);
+        assert!(event.get::<u64>(META_SECTION, "path_evicted")? == Some(&3));
+        Ok(())
+    }
+
+    #[test]
+    fn loops() -> Result<()> {
+        let step = |probe: &str, ifindex: u32, timestamp| {
+            let mut event = tracked(0x10, probe, timestamp, 0);
+            event.insert("skb", event_field!("ifindex", ifindex));
+            event
+        };
+
+        let mut paths = Paths::new("all", PathFormat::Text)?;
+        // Visiting a probe twice on a device is not a loop.
+        for (probe, ifindex) in [("kprobe:ip_rcv", 2), ("kprobe:ip_forward", 2)] {
+            paths.output(&step(probe, ifindex, 0))?;
+            paths.output(&step(probe, ifindex, 0))?;
+        }
+        assert!(paths.warnings().is_empty());
+
+        let mut paths = Paths::new("all", PathFormat::Text)?;
+        let mut timestamp = 0;
+        for _ in 0..4 {
+            for (probe, ifindex) in [("kprobe:ip_rcv", 2), ("kprobe:ip_forward", 3)] {
+                timestamp += 1000;
+                paths.output(&step(probe, ifindex, timestamp))?;
+            }
+        }
+        // Steps without a device are not considered.
+        for _ in 0..PATH_LOOP_VISITS {
+            paths.output(&tracked(0x10, "kprobe:consume_skb", 9000, 0))?;
+        }
+
+        let warnings = paths.warnings();
+        assert!(warnings.len() == 1);
+        let warning = &warnings[0];
+        assert!(warning.get::<String>(META_SECTION, "warning")? == Some(&"loop".to_string()));
+        assert!(warning.get::<u64>(META_SECTION, "packet")? == Some(&0x10));
+        assert!(
+            warning.get::<String>(META_SECTION, "segment")?
+                == Some(&"kprobe:ip_rcv@if2 -> kprobe:ip_forward@if3".to_string())
+        );
+        assert!(warning.get::<u64>(META_SECTION, "hops")? == Some(&4));
+        assert!(paths
+            .report_text()
+            .contains("loop: kprobe:ip_rcv@if2 -> kprobe:ip_forward@if3 (4 visits)\n"));
+
+        let mut event = Event::new();
+        paths.summary(&mut event);
+        assert!(event.get::<u64>(META_SECTION, "path_loops")? == Some(&1));
+        Ok(())
+    }
+
+    #[test]
+    fn netfilter() -> Result<()> {
+        let mut paths = Paths::new("all", PathFormat::Text)?;
+        let mut event = tracked(0x10, "kprobe:nf_hook_slow", 1000, 0);
+        event.insert("netfilter", event_field!("family", "ipv4".to_string()));
+        event.insert("netfilter", event_field!("hook", "FORWARD".to_string()));
+        paths.output(&event)?;
+        let mut event = tracked(0x10, "kprobe:ipt_do_table", 2000, 0);
+        event.insert("netfilter", event_field!("table", "filter".to_string()));
+        event.insert("netfilter", event_field!("chain", "FORWARD".to_string()));
+        paths.output(&event)?;
+        paths.output(&tracked(0x10, "kprobe:kfree_skb_reason", 3000, 3))?;
+        paths.sort_steps();
+
+        assert!(
+            paths.report_text()
+                == "packet 0x10 (1000)\n\
+                    ├─ kprobe:nf_hook_slow (+0.000us) [nf ipv4 FORWARD]\n\
+                    ├─ kprobe:ipt_do_table (+1.000us) [nf filter/FORWARD]\n\
+                    └─ kprobe:kfree_skb_reason (+2.000us) [dropped, reason 3]\n\
+                    \n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn veth() -> Result<()> {
+        let mut paths = Paths::new("all", PathFormat::Text)?;
+        // Sent on veth0 (4), whose peer (2) is in another namespace.
+        for (probe, ifindex, peer, timestamp) in [
+            ("raw_tracepoint:net_dev_start_xmit", 4u32, Some(2u32), 1000),
+            ("kprobe:veth_xmit", 4, Some(2), 2000),
+            ("kprobe:__netif_receive_skb", 2, None, 3000),
+            ("kprobe:ip_rcv", 2, None, 4000),
+        ] {
+            let mut event = tracked(0x10, probe, timestamp, 0);
+            event.insert("skb", event_field!("ifindex", ifindex));
+            if let Some(peer) = peer {
+                event.insert("skb", event_field!("veth_peer", peer));
+            }
+            paths.output(&event)?;
+        }
+        paths.sort_steps();
+
+        assert!(
+            paths.report_text()
+                == "packet 0x10 (1000)\n\
+                    ├─ raw_tracepoint:net_dev_start_xmit@if4 (+0.000us)\n\
+                    ├─ kprobe:veth_xmit@if4 (+1.000us)\n\
+                    ├─ kprobe:__netif_receive_skb@if2 (+2.000us) [veth from if4]\n\
+                    └─ kprobe:ip_rcv@if2 (+3.000us)\n\
+                    \n"
+        );
+        Ok(())
+    }
+}
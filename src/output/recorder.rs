@@ -0,0 +1,130 @@
+//! # Recorder
+//!
+//! Flight recorder: the last events are kept in memory, without being written
+//! anywhere, and can be dumped on request using the control socket once an
+//! incident is noticed. This allows an always-on collection with a low
+//! footprint. Events are stored formatted as JSON, which is the dump format.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Result};
+use once_cell::sync::OnceCell;
+
+use super::{output::EventOutput, ring::Ring};
+use crate::core::events::Event;
+
+static RECORDER: OnceCell<Arc<Recorder>> = OnceCell::new();
+
+/// Sets the global recorder, to be accessed by the control socket.
+///
+/// Returns an error if it was already set.
+pub(super) fn set_recorder(recorder: Arc<Recorder>) -> Result<()> {
+    RECORDER
+        .set(recorder)
+        .or_else(|_| bail!("recorder was already set"))?;
+    Ok(())
+}
+
+/// Returns the global recorder, if any.
+pub(crate) fn get_recorder() -> Option<&'static Arc<Recorder>> {
+    RECORDER.get()
+}
+
+/// Ring of the last events, shared between the recorder sink and the control
+/// socket.
+pub(crate) struct Recorder {
+    ring: Mutex<Ring<String>>,
+}
+
+impl Recorder {
+    pub(crate) fn new(capacity: usize) -> Recorder {
+        Recorder {
+            ring: Mutex::new(Ring::new(capacity)),
+        }
+    }
+
+    fn record(&self, event: &Event) {
+        self.ring.lock().unwrap().push(event.to_json().to_string());
+    }
+
+    /// Recorded events as JSON values, oldest first.
+    pub(crate) fn dump(&self) -> Result<Vec<serde_json::Value>> {
+        self.ring
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| Ok(serde_json::from_str(e)?))
+            .collect()
+    }
+
+    /// Write the recorded events to a file, as JSON lines, oldest first.
+    /// Returns the number of events written.
+    pub(crate) fn dump_to(&self, path: &str) -> Result<usize> {
+        let file = File::create(path).or_else(|e| bail!("Could not create {}: {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+
+        let ring = self.ring.lock().unwrap();
+        for event in ring.iter() {
+            writeln!(writer, "{}", event)?;
+        }
+        writer.flush()?;
+        Ok(ring.len())
+    }
+}
+
+/// Sink feeding the recorder.
+pub(super) struct RecorderSink(Arc<Recorder>);
+
+impl RecorderSink {
+    pub(super) fn new(recorder: Arc<Recorder>) -> RecorderSink {
+        RecorderSink(recorder)
+    }
+}
+
+impl EventOutput for RecorderSink {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        self.0.record(event);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::core::events::EventField;
+    use crate::event_field;
+
+    #[test]
+    fn recorder() -> Result<()> {
+        let recorder = Arc::new(Recorder::new(2));
+        let mut sink = RecorderSink::new(Arc::clone(&recorder));
+        for id in 0..3u64 {
+            let mut event = Event::new();
+            event.insert("test", event_field!("id", id));
+            sink.output(&event)?;
+        }
+
+        let events = recorder.dump()?;
+        assert!(events.len() == 2);
+        assert!(events[0]["test"]["id"] == 1 && events[1]["test"]["id"] == 2);
+
+        let path = std::env::temp_dir().join(format!("pt-recorder-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert!(recorder.dump_to(path)? == 2);
+        let content = fs::read_to_string(path)?;
+        fs::remove_file(path)?;
+        assert!(content.lines().count() == 2);
+        assert!(
+            serde_json::from_str::<serde_json::Value>(content.lines().last().unwrap())?["test"]
+                ["id"]
+                == 2
+        );
+        Ok(())
+    }
+}
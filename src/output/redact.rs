@@ -0,0 +1,250 @@
+//! # Redact
+//!
+//! Redacts event fields which may contain private information (e.g.
+//! addresses) before events are written, so captures can be shared. Values are
+//! replaced by a keyed hash, preserving correlation (the same value always
+//! gives the same hash within a capture), or by a fixed placeholder. Redacted
+//! values are strings, whatever the type of the original field.
+//!
+//! Hashes are HMAC-SHA256, keyed with a salt and truncated to 128 bits.
+//! Without a salt, a random key is used and hashes can't be correlated across
+//! captures. With one, they can, across captures sharing it; the salt must
+//! then be kept private, as small value spaces (e.g. IPv4 addresses) could
+//! otherwise be enumerated.
+//!
+//! Some fields are always replaced by the placeholder when redacting, whatever
+//! the fields selected, as they embed values which can't be redacted
+//! individually: raw packet bytes and meta event fields summarizing flows or
+//! filters. Annotations derived from a redacted address field (its name,
+//! country, etc. see --resolve-names and --geoip-db) are redacted with it.
+
+use std::{fs::File, io::Read};
+
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::{geoip_fields, resolve::name_field, ADDRESS_FIELDS};
+use crate::{
+    core::events::{meta::META_SECTION, Event, EventField},
+    event_field,
+};
+
+/// Value replacing redacted fields, in placeholder mode.
+const REDACTED: &str = "REDACTED";
+/// Fields embedding private information which can't be redacted on their own
+/// and are always replaced by the placeholder, as (section, key).
+const ALWAYS_REDACTED: &[(&str, &str)] = &[
+    // Raw packet bytes.
+    ("skb", "packet"),
+    // Flow of asymmetry warnings, filters (e.g. addresses) of start events.
+    (META_SECTION, "flow"),
+    (META_SECTION, "filters"),
+];
+/// Size of the random key used when no salt is given.
+const KEY_SIZE: usize = 32;
+/// Size of the redaction hashes, in bytes.
+const HASH_SIZE: usize = 16;
+
+/// How redacted values are replaced.
+#[derive(Debug, PartialEq)]
+pub(super) enum RedactMode {
+    Hash,
+    Placeholder,
+}
+
+impl RedactMode {
+    pub(super) fn parse(mode: &str) -> Result<RedactMode> {
+        Ok(match mode {
+            "hash" => RedactMode::Hash,
+            "placeholder" => RedactMode::Placeholder,
+            x => bail!("Unknown redaction mode {}", x),
+        })
+    }
+}
+
+pub(super) struct Redactor {
+    /// Fields to redact, as an optional section and a key.
+    fields: Vec<(Option<String>, String)>,
+    mode: RedactMode,
+    /// Keyed hash, the key being the salt if one was given and random
+    /// otherwise.
+    mac: Hmac<Sha256>,
+}
+
+impl Redactor {
+    /// Create a redactor given the fields to redact, as "<key>" (in all
+    /// sections) or "<section>.<key>".
+    pub(super) fn new(fields: &[String], mode: RedactMode, salt: Option<&str>) -> Result<Redactor> {
+        let fields = fields
+            .iter()
+            .map(|f| match f.split_once('.') {
+                Some((_, "")) | Some(("", _)) => bail!("Invalid redacted field '{}'", f),
+                Some((section, key)) => Ok((Some(section.to_string()), key.to_string())),
+                None if f.is_empty() => bail!("Invalid redacted field '{}'", f),
+                None => Ok((None, f.to_string())),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let key = match salt {
+            Some(salt) => salt.as_bytes().to_vec(),
+            None => {
+                let mut key = vec![0; KEY_SIZE];
+                File::open("/dev/urandom")
+                    .and_then(|mut f| f.read_exact(&mut key))
+                    .or_else(|e| bail!("Could not generate the redaction key: {}", e))?;
+                key
+            }
+        };
+
+        let mac = Hmac::<Sha256>::new_from_slice(&key)
+            .or_else(|e| bail!("Could not initialize the redaction hash: {}", e))?;
+
+        Ok(Redactor { fields, mode, mac })
+    }
+
+    /// Redact the matching fields of an event, in place. Applies to regular
+    /// and meta events.
+    pub(super) fn redact(&self, event: &mut Event) {
+        let redacted: Vec<(String, String, String)> = event
+            .fields()
+            .into_iter()
+            .filter_map(|(section, field)| {
+                let value = match ALWAYS_REDACTED.contains(&(section, field.key())) {
+                    true => REDACTED.to_string(),
+                    false
+                        if self.selected(section, field.key())
+                            || self.derived(section, field.key()) =>
+                    {
+                        self.value(&field.to_json())
+                    }
+                    false => return None,
+                };
+                Some((section.to_string(), field.key().to_string(), value))
+            })
+            .collect();
+
+        for (section, key, value) in redacted.into_iter() {
+            event.insert(&section, event_field!(&key, value));
+        }
+    }
+
+    /// Is a field selected for redaction?
+    fn selected(&self, section: &str, key: &str) -> bool {
+        self.fields
+            .iter()
+            .any(|(s, k)| k == key && s.as_ref().map_or(true, |s| s == section))
+    }
+
+    /// Is a field an annotation derived from an address field selected for
+    /// redaction?
+    fn derived(&self, section: &str, key: &str) -> bool {
+        ADDRESS_FIELDS.iter().any(|(s, addr)| {
+            *s == section
+                && self.selected(section, addr)
+                && (name_field(addr) == key || geoip_fields(addr).iter().any(|f| f == key))
+        })
+    }
+
+    /// Replacement of a redacted value.
+    fn value(&self, val: &serde_json::Value) -> String {
+        if self.mode == RedactMode::Placeholder {
+            return REDACTED.to_string();
+        }
+
+        let mut mac = self.mac.clone();
+        mac.update(val.to_string().as_bytes());
+        mac.finalize().into_bytes()[..HASH_SIZE]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Event {
+        let mut event = Event::new();
+        event.insert("skb", event_field!("saddr", "10.0.0.1".to_string()));
+        event.insert("skb", event_field!("daddr", "10.0.0.2".to_string()));
+        event.insert("skb", event_field!("sport", 42000_u32));
+        event.insert("sockdrop", event_field!("saddr", "10.0.0.1".to_string()));
+        event
+    }
+
+    #[test]
+    fn redact() -> Result<()> {
+        let fields = vec!["saddr".to_string(), "skb.sport".to_string()];
+        let redactor = Redactor::new(&fields, RedactMode::Hash, Some("secret"))?;
+        let mut event = sample();
+        redactor.redact(&mut event);
+
+        let get = |section: &str, key: &str| event.get::<String>(section, key).unwrap().cloned();
+        let saddr = get("skb", "saddr").unwrap();
+        assert!(saddr != "10.0.0.1" && saddr.len() == HASH_SIZE * 2);
+        // The same value gives the same hash, in all sections.
+        assert!(get("sockdrop", "saddr") == Some(saddr.clone()));
+        assert!(get("skb", "daddr") == Some("10.0.0.2".to_string()));
+        // Non-string fields are redacted too.
+        assert!(get("skb", "sport").is_some());
+
+        // Hashes depend on the salt.
+        let mut other = sample();
+        Redactor::new(&fields, RedactMode::Hash, Some("other"))?.redact(&mut other);
+        assert!(other.get::<String>("skb", "saddr")? != Some(&saddr));
+        let mut same = sample();
+        Redactor::new(&fields, RedactMode::Hash, Some("secret"))?.redact(&mut same);
+        assert!(same.get::<String>("skb", "saddr")? == Some(&saddr));
+
+        let redactor = Redactor::new(&fields, RedactMode::Placeholder, None)?;
+        let mut event = sample();
+        redactor.redact(&mut event);
+        assert!(event.get::<String>("skb", "saddr")? == Some(&REDACTED.to_string()));
+
+        assert!(Redactor::new(&["skb.".to_string()], RedactMode::Hash, None).is_err());
+        assert!(Redactor::new(&["".to_string()], RedactMode::Hash, None).is_err());
+        assert!(RedactMode::parse("rot13").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn derived() -> Result<()> {
+        let mut event = Event::new();
+        event.insert("tcp-retrans", event_field!("saddr", "1.1.1.1".to_string()));
+        event.insert("tcp-retrans", event_field!("saddr_name", "one".to_string()));
+        event.insert("tcp-retrans", event_field!("src_country", "AU".to_string()));
+        event.insert("tcp-retrans", event_field!("src_asn", 13335_u32));
+        event.insert("tcp-retrans", event_field!("daddr", "8.8.8.8".to_string()));
+        event.insert("tcp-retrans", event_field!("dst_country", "US".to_string()));
+
+        Redactor::new(&["saddr".to_string()], RedactMode::Placeholder, None)?.redact(&mut event);
+        let get = |key: &str| event.get::<String>("tcp-retrans", key).unwrap().cloned();
+        for key in ["saddr", "saddr_name", "src_country", "src_asn"] {
+            assert!(get(key) == Some(REDACTED.to_string()));
+        }
+        assert!(get("dst_country") == Some("US".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn always_redacted() -> Result<()> {
+        let redactor = Redactor::new(&["saddr".to_string()], RedactMode::Hash, None)?;
+        let mut event = sample();
+        event.insert("skb", event_field!("packet", "deadbeef".to_string()));
+        redactor.redact(&mut event);
+        assert!(event.get::<String>("skb", "packet")? == Some(&REDACTED.to_string()));
+
+        let mut event = Event::new();
+        event.insert(META_SECTION, event_field!("type", "warning".to_string()));
+        event.insert(
+            META_SECTION,
+            event_field!("flow", "tcp 10.0.0.1:42000 > 10.0.0.2:80".to_string()),
+        );
+        redactor.redact(&mut event);
+        assert!(event.get::<String>(META_SECTION, "flow")? == Some(&REDACTED.to_string()));
+        assert!(event.get::<String>(META_SECTION, "type")? == Some(&"warning".to_string()));
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! # Resolve
+//!
+//! Annotate address fields with their host name, using reverse DNS lookups.
+//! Names are reported in a field named after the address one, with a "_name"
+//! suffix.
+//! Lookups are done in a dedicated thread and their results cached, so the
+//! event pipeline is never blocked: events are annotated only once the name
+//! of an address is known, and addresses without a name are left as-is.
+//!
+//! At most PENDING_MAX lookups are queued at a time, addresses seen while the
+//! queue is full are not looked up (until seen again once it is not). Under a
+//! memory budget (see --max-memory), the cache is the first structure evicted
+//! from, oldest lookups first: evicted names are looked up again when needed.
+//!
+//! Lookups use the system resolver, whose own traffic is not excluded and
+//! might show up in the collected events.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CStr,
+    mem,
+    net::IpAddr,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use nix::libc;
+
+use super::ADDRESS_FIELDS;
+use crate::{
+    core::events::{Event, EventField},
+    event_field,
+};
+
+/// Time a lookup result, successful or not, is kept.
+const CACHE_TTL: Duration = Duration::from_secs(300);
+/// Maximum number of cached addresses.
+const CACHE_MAX: usize = 4096;
+/// Estimated size of a cache entry, including the host name.
+const CACHE_ENTRY_SIZE: usize = mem::size_of::<(IpAddr, (Option<String>, Instant))>() + 32;
+/// Maximum number of addresses being looked up.
+const PENDING_MAX: usize = CACHE_MAX;
+/// Estimated size of a pending lookup, in the pending set and request queue.
+const PENDING_ENTRY_SIZE: usize = 2 * mem::size_of::<IpAddr>() + 16;
+
+/// Reverse lookup function, returning the name of an address if any.
+type Lookup = fn(&IpAddr) -> Option<String>;
+
+pub(super) struct NameResolver {
+    /// Lookup results, and when they were retrieved.
+    cache: HashMap<IpAddr, (Option<String>, Instant)>,
+    /// Addresses being looked up.
+    pending: HashSet<IpAddr>,
+    requests: mpsc::SyncSender<IpAddr>,
+    results: mpsc::Receiver<(IpAddr, Option<String>)>,
+    /// Number of cache entries evicted to stay within the memory budget.
+    pub(super) evicted: u64,
+}
+
+impl NameResolver {
+    pub(super) fn new() -> NameResolver {
+        Self::with_lookup(reverse_lookup)
+    }
+
+    fn with_lookup(lookup: Lookup) -> NameResolver {
+        let (requests, rx) = mpsc::sync_channel::<IpAddr>(PENDING_MAX);
+        let (tx, results) = mpsc::channel();
+
+        // The thread stops once the resolver is dropped.
+        thread::spawn(move || {
+            for addr in rx.iter() {
+                if tx.send((addr, lookup(&addr))).is_err() {
+                    break;
+                }
+            }
+        });
+
+        NameResolver {
+            cache: HashMap::new(),
+            pending: HashSet::new(),
+            requests,
+            results,
+            evicted: 0,
+        }
+    }
+
+    /// Annotate the address fields of an event whose name is known, and
+    /// request lookups for the others.
+    pub(super) fn annotate(&mut self, event: &mut Event) {
+        // Retrieve the results of the lookups done so far.
+        while let Ok((addr, name)) = self.results.try_recv() {
+            self.pending.remove(&addr);
+            self.insert(addr, name);
+        }
+
+        for (section, key) in ADDRESS_FIELDS.iter() {
+            let addr = match event.get::<String>(section, key) {
+                Ok(Some(addr)) => match addr.parse::<IpAddr>() {
+                    Ok(addr) => addr,
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+
+            match self.cache.get(&addr) {
+                Some((name, at)) if at.elapsed() < CACHE_TTL => {
+                    if let Some(name) = name {
+                        event.insert(section, event_field!(&name_field(key), name.clone()));
+                    }
+                }
+                _ => {
+                    if self.pending.len() >= PENDING_MAX || !self.pending.insert(addr) {
+                        continue;
+                    }
+                    if self.requests.try_send(addr).is_err() {
+                        self.pending.remove(&addr);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Estimated memory used by the cache and the pending lookups, in bytes.
+    pub(super) fn memory(&self) -> usize {
+        self.cache.len() * CACHE_ENTRY_SIZE + self.pending.len() * PENDING_ENTRY_SIZE
+    }
+
+    /// Evict the oldest cache entries to free at least `bytes`. Returns the
+    /// number of bytes freed.
+    pub(super) fn evict(&mut self, bytes: usize) -> usize {
+        let mut entries: Vec<_> = self
+            .cache
+            .iter()
+            .map(|(addr, (_, at))| (*at, *addr))
+            .collect();
+        entries.sort();
+
+        let count = ((bytes + CACHE_ENTRY_SIZE - 1) / CACHE_ENTRY_SIZE).min(entries.len());
+        entries.iter().take(count).for_each(|(_, addr)| {
+            self.cache.remove(addr);
+        });
+        self.evicted += count as u64;
+        count * CACHE_ENTRY_SIZE
+    }
+
+    fn insert(&mut self, addr: IpAddr, name: Option<String>) {
+        if self.cache.len() >= CACHE_MAX {
+            self.cache.retain(|_, (_, at)| at.elapsed() < CACHE_TTL);
+        }
+        // Still full, evict the oldest entry.
+        if self.cache.len() >= CACHE_MAX {
+            if let Some(oldest) = self
+                .cache
+                .iter()
+                .min_by_key(|(_, (_, at))| *at)
+                .map(|(addr, _)| *addr)
+            {
+                self.cache.remove(&oldest);
+            }
+        }
+        self.cache.insert(addr, (name, Instant::now()));
+    }
+}
+
+/// Reverse lookup of an address using the system resolver.
+fn reverse_lookup(addr: &IpAddr) -> Option<String> {
+    // Safety: sockaddr_storage can hold any address family and is valid when
+    // zeroed.
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        IpAddr::V4(v4) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_addr.s_addr = u32::from_ne_bytes(v4.octets());
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        IpAddr::V6(v6) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_addr.s6_addr = v6.octets();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+    // Safety: the address and host buffers are valid for the given lengths.
+    let ret = unsafe {
+        libc::getnameinfo(
+            &storage as *const _ as *const libc::sockaddr,
+            len as libc::socklen_t,
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+
+    // Safety: getnameinfo NUL terminates the host name on success.
+    let name = unsafe { CStr::from_ptr(host.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Name of the field holding the name of an address field.
+pub(super) fn name_field(key: &str) -> String {
+    format!("{}_name", key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(addr: &IpAddr) -> Option<String> {
+        match addr.to_string().as_str() {
+            "10.0.0.1" => Some("host-a".to_string()),
+            _ => None,
+        }
+    }
+
+    fn event() -> Event {
+        let mut event = Event::new();
+        event.insert("cgroup", event_field!("daddr", "10.0.0.1".to_string()));
+        event.insert(
+            "skb",
+            event_field!("icmp_orig_daddr", "10.0.0.2".to_string()),
+        );
+        event
+    }
+
+    #[test]
+    fn annotate() {
+        let mut resolver = NameResolver::with_lookup(lookup);
+
+        // Names are not known yet, events are not delayed.
+        let mut first = event();
+        resolver.annotate(&mut first);
+        assert!(first.len() == 2);
+
+        // Wait for the lookups to complete.
+        let mut annotated = None;
+        for _ in 0..100 {
+            let mut event = event();
+            resolver.annotate(&mut event);
+            if event.len() > 2 {
+                annotated = Some(event);
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let event = annotated.unwrap();
+        assert!(
+            event.get::<String>("cgroup", "daddr_name").unwrap() == Some(&"host-a".to_string())
+        );
+        assert!(event
+            .get::<String>("skb", "icmp_orig_daddr_name")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn cache_bound() {
+        let mut resolver = NameResolver::with_lookup(lookup);
+        for i in 0..CACHE_MAX as u32 + 10 {
+            resolver.insert(IpAddr::from(i.to_be_bytes()), None);
+        }
+        assert!(resolver.cache.len() == CACHE_MAX);
+    }
+
+    #[test]
+    fn pending_bound() {
+        fn slow(_: &IpAddr) -> Option<String> {
+            thread::sleep(Duration::from_secs(1));
+            None
+        }
+
+        let mut resolver = NameResolver::with_lookup(slow);
+        for i in 0..PENDING_MAX as u32 + 10 {
+            let mut event = Event::new();
+            let addr = IpAddr::from(i.to_be_bytes()).to_string();
+            event.insert("cgroup", event_field!("daddr", addr));
+            resolver.annotate(&mut event);
+        }
+        assert!(resolver.pending.len() == PENDING_MAX);
+        assert!(resolver.memory() == PENDING_MAX * PENDING_ENTRY_SIZE);
+    }
+}
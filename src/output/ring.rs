@@ -0,0 +1,68 @@
+//! # Ring
+//!
+//! Bounded ring of items, the oldest ones being evicted once it is full. Used
+//! to keep the last events around, e.g. until a trigger fires or for the
+//! flight recorder.
+
+use std::collections::VecDeque;
+
+pub(super) struct Ring<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> Ring<T> {
+    pub(super) fn new(capacity: usize) -> Ring<T> {
+        Ring {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Add an item, evicting the oldest one if the ring is full. Nothing is
+    /// kept in a ring of zero capacity.
+    pub(super) fn push(&mut self, item: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back(item);
+    }
+
+    /// Remove all the items, oldest first.
+    pub(super) fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.items.drain(..)
+    }
+
+    /// Iterate over the items, oldest first.
+    pub(super) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.items.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring() {
+        let mut ring = Ring::new(2);
+        for i in 0..3 {
+            ring.push(i);
+        }
+        assert!(ring.len() == 2);
+        assert!(ring.iter().copied().collect::<Vec<_>>() == vec![1, 2]);
+        assert!(ring.drain().collect::<Vec<_>>() == vec![1, 2]);
+        assert!(ring.len() == 0);
+
+        let mut ring = Ring::new(0);
+        ring.push(0);
+        assert!(ring.len() == 0);
+    }
+}
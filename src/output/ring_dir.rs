@@ -0,0 +1,364 @@
+//! # Ring directory
+//!
+//! Writes events continuously to a directory, as a ring of zstd compressed
+//! files (segments) holding one event per line, bounded by a size budget: a
+//! new segment is started once the current one reaches its share of the
+//! budget (1/RING_SEGMENTS), and the oldest segments are deleted once the
+//! directory total exceeds it. The directory thus always holds a rolling
+//! window of the recent history. As compressed sizes are only known once
+//! flushed, segments can exceed their share by up to RING_FLUSH_BYTES worth of
+//! compressed events.
+//!
+//! A time index (RING_INDEX) lists the segments, oldest first, with the
+//! timestamps of their first and last events, as "<file> <first> <last>".
+//! It is updated each time a segment is started or closed; segments it lists
+//! without a last timestamp (the one being written, e.g. before a crash) or
+//! doesn't list at all are read again when the directory is opened, so the
+//! ring can be resumed on restart.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use log::warn;
+
+use super::{format::Format, EventOutput};
+use crate::core::events::Event;
+
+/// Number of segments the size budget is divided into.
+const RING_SEGMENTS: u64 = 16;
+/// Maximum number of (uncompressed) bytes written between two flushes of the
+/// compressed stream.
+const RING_FLUSH_BYTES: u64 = 128 * 1024;
+/// Name of the time index file.
+const RING_INDEX: &str = "index";
+/// Prefix and suffix of the segment file names, around their sequence number.
+const SEGMENT_PREFIX: &str = "events-";
+const SEGMENT_SUFFIX: &str = ".jsonl.zst";
+
+/// Writer counting the bytes written to the underlying file, i.e. the
+/// segment size once compressed.
+struct Counter {
+    file: BufWriter<File>,
+    written: u64,
+}
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.file.write(buf)?;
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// A segment of the ring, as listed in the time index.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Segment {
+    seq: u64,
+    /// Size on disk, in bytes.
+    size: u64,
+    /// Timestamps of the first and last events, if any.
+    first: Option<u64>,
+    last: Option<u64>,
+}
+
+impl Segment {
+    fn name(&self) -> String {
+        format!("{}{:010}{}", SEGMENT_PREFIX, self.seq, SEGMENT_SUFFIX)
+    }
+
+    /// Get the sequence number of a segment from its file name.
+    fn parse_name(name: &str) -> Option<u64> {
+        name.strip_prefix(SEGMENT_PREFIX)?
+            .strip_suffix(SEGMENT_SUFFIX)?
+            .parse()
+            .ok()
+    }
+
+    fn account(&mut self, timestamp: u64) {
+        self.first.get_or_insert(timestamp);
+        self.last = Some(timestamp);
+    }
+}
+
+pub(super) struct RingDir {
+    dir: PathBuf,
+    /// Size budget of the directory, in bytes.
+    size: u64,
+    format: Format,
+    /// Closed segments, oldest first.
+    segments: Vec<Segment>,
+    /// Segment being written, if any, and its writer.
+    current: Segment,
+    writer: Option<zstd::stream::write::Encoder<'static, Counter>>,
+    /// Uncompressed bytes written since the last flush.
+    pending: u64,
+}
+
+impl RingDir {
+    /// Open a ring directory, creating it if needed, and resume it from the
+    /// segments it already holds.
+    pub(super) fn new(dir: &str, size: u64, format: Format) -> Result<RingDir> {
+        if size < RING_SEGMENTS {
+            bail!("Ring size is too small ({} bytes)", size);
+        }
+        fs::create_dir_all(dir).or_else(|e| bail!("Could not create {}: {}", dir, e))?;
+
+        let segments = recover(Path::new(dir))?;
+        Ok(RingDir {
+            dir: PathBuf::from(dir),
+            size,
+            format,
+            current: Segment {
+                seq: segments.last().map_or(0, |s| s.seq + 1),
+                ..Default::default()
+            },
+            segments,
+            writer: None,
+            pending: 0,
+        })
+    }
+
+    /// Start writing the current segment, deleting the oldest ones if needed.
+    fn start(&mut self) -> Result<()> {
+        let file = File::create(self.dir.join(self.current.name()))?;
+        self.writer = Some(zstd::stream::write::Encoder::new(
+            Counter {
+                file: BufWriter::new(file),
+                written: 0,
+            },
+            0,
+        )?);
+
+        // The new segment can use up to its share of the budget.
+        let budget = self.size - self.size / RING_SEGMENTS;
+        while self.segments.iter().map(|s| s.size).sum::<u64>() > budget {
+            let oldest = self.segments.remove(0);
+            if let Err(e) = fs::remove_file(self.dir.join(oldest.name())) {
+                warn!("Could not remove ring segment {}: {}", oldest.name(), e);
+            }
+        }
+
+        self.write_index()
+    }
+
+    /// Finish the current segment, making it part of the closed ones.
+    fn close(&mut self) -> Result<()> {
+        let writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        let mut counter = writer.finish()?;
+        counter.flush()?;
+
+        let next = Segment {
+            seq: self.current.seq + 1,
+            ..Default::default()
+        };
+        let mut segment = std::mem::replace(&mut self.current, next);
+        segment.size = counter.written;
+        self.segments.push(segment);
+        self.pending = 0;
+        self.write_index()
+    }
+
+    /// Write the time index, atomically, listing the closed segments and the
+    /// current one if it was started.
+    fn write_index(&self) -> Result<()> {
+        let current = self.writer.as_ref().map(|_| &self.current);
+        let mut index = String::new();
+        for segment in self.segments.iter().chain(current) {
+            let ts = |t: Option<u64>| t.map_or("-".to_string(), |t| t.to_string());
+            index.push_str(&format!(
+                "{} {} {}\n",
+                segment.name(),
+                ts(segment.first),
+                ts(segment.last)
+            ));
+        }
+
+        let tmp = self.dir.join(format!("{}.tmp", RING_INDEX));
+        fs::write(&tmp, index)?;
+        fs::rename(&tmp, self.dir.join(RING_INDEX))?;
+        Ok(())
+    }
+}
+
+impl EventOutput for RingDir {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        let line = match self.format.format(event) {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        if self.writer.is_none() {
+            self.start()?;
+        }
+        let share = self.size / RING_SEGMENTS;
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => bail!("Ring segment was not started"),
+        };
+        writeln!(writer, "{}", line)?;
+
+        if let Ok(Some(timestamp)) = event.get::<u64>("common", "timestamp") {
+            self.current.account(*timestamp);
+        }
+
+        self.pending += line.len() as u64 + 1;
+        if self.pending >= share.min(RING_FLUSH_BYTES) {
+            writer.flush()?;
+            self.pending = 0;
+        }
+        if writer.get_ref().written >= share {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.close()
+    }
+}
+
+/// Scan a ring directory for existing segments, oldest first. Their
+/// timestamps are taken from the time index, or read again from the
+/// segments it doesn't list.
+fn recover(dir: &Path) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(seq) = Segment::parse_name(&name) {
+            segments.push(Segment {
+                seq,
+                size: entry.metadata()?.len(),
+                ..Default::default()
+            });
+        }
+    }
+    segments.sort_by_key(|s| s.seq);
+
+    let index = fs::read_to_string(dir.join(RING_INDEX)).unwrap_or_default();
+    for segment in segments.iter_mut() {
+        let name = segment.name();
+        let entry = index
+            .lines()
+            .map(|l| l.split(' ').collect::<Vec<_>>())
+            .find(|l| l.len() == 3 && l[0] == name);
+
+        match entry {
+            Some(entry) if entry[2] != "-" => {
+                segment.first = entry[1].parse().ok();
+                segment.last = entry[2].parse().ok();
+            }
+            // Segments being written when the index was last updated are
+            // listed without their last timestamp.
+            _ => scan(&dir.join(&name), segment),
+        }
+    }
+    Ok(segments)
+}
+
+/// Read the timestamps of a segment. Segments can be truncated, if they were
+/// not finished, in which case the events read so far are used.
+fn scan(path: &Path, segment: &mut Segment) {
+    let reader = match File::open(path).and_then(zstd::stream::read::Decoder::new) {
+        Ok(reader) => reader,
+        Err(e) => {
+            warn!("Could not read ring segment {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    for line in BufReader::new(reader).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if let Some(timestamp) = serde_json::from_str::<serde_json::Value>(&line)
+            .ok()
+            .and_then(|e| e.get("common")?.get("timestamp")?.as_u64())
+        {
+            segment.account(timestamp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::events::EventField, event_field};
+
+    fn event(timestamp: u64) -> Event {
+        let mut event = Event::new();
+        event.insert("common", event_field!("timestamp", timestamp));
+        event.insert(
+            "skb",
+            event_field!("packet", format!("{:0>512}", timestamp)),
+        );
+        event
+    }
+
+    #[test]
+    fn ring() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("pt-ring-dir-{}", std::process::id()));
+        let path = dir.to_str().unwrap();
+        let size = 16 * 1024;
+
+        let mut ring = RingDir::new(path, size, Format::Json)?;
+        for ts in 0..2000 {
+            ring.output(&event(ts))?;
+        }
+        ring.finish()?;
+        drop(ring);
+
+        // Old segments were deleted, and the directory is within its budget
+        // (give or take a flush).
+        let segments = recover(&dir)?;
+        assert!(segments.len() > 1 && segments[0].seq > 0);
+        assert!(segments.iter().map(|s| s.size).sum::<u64>() <= size + size / RING_SEGMENTS);
+        assert!(segments.last().unwrap().last == Some(1999));
+        assert!(segments.windows(2).all(|w| w[0].last < w[1].first));
+
+        // The ring is resumed after its last segment, and unindexed segments
+        // are read again.
+        fs::remove_file(dir.join(RING_INDEX))?;
+        let mut ring = RingDir::new(path, size, Format::Json)?;
+        assert!(ring.segments == segments);
+        assert!(ring.current.seq == segments.last().unwrap().seq + 1);
+        ring.output(&event(2000))?;
+        ring.finish()?;
+        assert!(ring.segments.last().unwrap().first == Some(2000));
+
+        let index = fs::read_to_string(dir.join(RING_INDEX))?;
+        assert!(index.lines().count() == segments.len() + 1);
+        assert!(index.ends_with(" 2000 2000\n"));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn segment_name() {
+        let segment = Segment {
+            seq: 42,
+            ..Default::default()
+        };
+        assert!(Segment::parse_name(&segment.name()) == Some(42));
+        assert!(Segment::parse_name("events-42.jsonl").is_none());
+        assert!(Segment::parse_name(RING_INDEX).is_none());
+    }
+}
@@ -0,0 +1,86 @@
+//! # Socket
+//!
+//! Sends events to a Unix datagram socket, one per datagram. This allows
+//! streaming events to a local agent. Sending never blocks: events are dropped
+//! and accounted for when the socket is absent or full.
+
+use std::os::unix::net::UnixDatagram;
+
+use anyhow::Result;
+
+use super::{format::Format, EventOutput};
+use crate::{
+    core::events::{meta::META_SECTION, Event, EventField},
+    event_field,
+};
+
+pub(super) struct SocketSink {
+    socket: UnixDatagram,
+    path: String,
+    format: Format,
+    /// Number of events which could not be sent.
+    dropped: u64,
+}
+
+impl SocketSink {
+    pub(super) fn new(path: &str, format: Format) -> Result<SocketSink> {
+        let socket = UnixDatagram::unbound()?;
+        socket.set_nonblocking(true)?;
+
+        Ok(SocketSink {
+            socket,
+            path: path.to_string(),
+            format,
+            dropped: 0,
+        })
+    }
+}
+
+impl EventOutput for SocketSink {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        let data = match self.format.format(event) {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+
+        if self.socket.send_to(data.as_bytes(), &self.path).is_err() {
+            self.dropped += 1;
+        }
+        Ok(())
+    }
+
+    fn summary(&self, event: &mut Event) {
+        event.insert(META_SECTION, event_field!("socket_dropped", self.dropped));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("pt-output-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let mut sink = SocketSink::new(path, Format::Json)?;
+
+        // The socket is absent, events are dropped.
+        sink.output(&Event::new())?;
+        assert!(sink.dropped == 1);
+
+        let receiver = UnixDatagram::bind(path)?;
+        let mut event = Event::new();
+        event.insert("test", event_field!("id", 42_u64));
+        sink.output(&event)?;
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf)?;
+        assert!(&buf[..len] == br#"{"test":{"id":42}}"#);
+        assert!(sink.dropped == 1);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+//! # Stdout
+//!
+//! Writes events to the standard output, one per line.
+
+use std::io::{self, BufWriter, Write};
+
+use anyhow::Result;
+
+use super::{format::Format, EventOutput};
+use crate::core::events::Event;
+
+/// Events are buffered, to batch writes under load; the buffer is flushed
+/// periodically by the output group.
+pub(super) struct StdoutSink {
+    stdout: BufWriter<io::Stdout>,
+    format: Format,
+}
+
+impl StdoutSink {
+    pub(super) fn new(format: Format) -> Result<StdoutSink> {
+        let mut stdout = BufWriter::new(io::stdout());
+        if let Some(header) = format.header() {
+            writeln!(stdout, "{}", header)?;
+        }
+
+        Ok(StdoutSink { stdout, format })
+    }
+}
+
+impl EventOutput for StdoutSink {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        if let Some(line) = self.format.format(event) {
+            writeln!(self.stdout, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stdout.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+//! # Summary
+//!
+//! Aggregates events instead of writing them, and writes a report once the
+//! collection ends: events per probed symbol, drops per reason, events and
+//! bytes per interface (skb collector ifindex, and name when found) and, for
+//! events reporting packet bytes (see --capture-bytes), events and bytes per
+//! protocol and per source/destination pair (top talkers).
+//!
+//! Counts are in events: a packet seen by multiple probes is accounted for
+//! each time.
+//!
+//! Under a memory budget (see --max-memory), the entries with the fewest
+//! events are evicted first, starting with the talkers, protocols and
+//! interfaces; the reported top entries are then approximate, their count of
+//! evicted entries being reported in the stop event.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    mem,
+};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use super::{packet::decode_packet, EventOutput, IfNames};
+use crate::{
+    core::events::{meta::META_SECTION, Event, EventField},
+    event_field,
+};
+
+/// Estimated size of an aggregate entry, not including its key.
+const ENTRY_SIZE: usize = mem::size_of::<(String, (u64, u64))>() + 16;
+
+/// Number of events and bytes, per aggregation key.
+#[derive(Default)]
+struct Aggregate {
+    entries: HashMap<String, (u64, u64)>,
+    /// Estimated memory used by the entries, in bytes.
+    memory: usize,
+}
+
+impl Aggregate {
+    fn add(&mut self, key: String, bytes: u64) {
+        let entry = match self.entries.get_mut(&key) {
+            Some(entry) => entry,
+            None => {
+                self.memory += ENTRY_SIZE + key.len();
+                self.entries.entry(key).or_default()
+            }
+        };
+        entry.0 += 1;
+        entry.1 += bytes;
+    }
+
+    /// Evict the entries with the fewest events to free at least `bytes`.
+    /// Returns the number of bytes freed and of entries evicted.
+    fn evict(&mut self, bytes: usize) -> (usize, u64) {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(key, (count, _))| (*count, key.clone()))
+            .collect();
+        entries.sort();
+
+        let (mut freed, mut evicted) = (0, 0);
+        for (_, key) in entries.into_iter() {
+            if freed >= bytes {
+                break;
+            }
+            self.entries.remove(&key);
+            freed += ENTRY_SIZE + key.len();
+            evicted += 1;
+        }
+        self.memory -= freed;
+        (freed, evicted)
+    }
+
+    /// Return the `top` keys with the most events, as JSON.
+    fn top(&self, top: usize, with_bytes: bool) -> Value {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then_with(|| a.0.cmp(b.0)));
+
+        Value::Array(
+            entries
+                .into_iter()
+                .take(top)
+                .map(|(key, (count, bytes))| match with_bytes {
+                    true => json!({ "key": key, "events": count, "bytes": bytes }),
+                    false => json!({ "key": key, "events": count }),
+                })
+                .collect(),
+        )
+    }
+}
+
+pub(super) struct Summary {
+    /// Number of entries reported per aggregation.
+    top: usize,
+    events: u64,
+    symbols: Aggregate,
+    drop_reasons: Aggregate,
+    protocols: Aggregate,
+    talkers: Aggregate,
+    interfaces: Aggregate,
+    ifnames: IfNames,
+    /// Number of entries evicted to stay within the memory budget.
+    evicted: u64,
+}
+
+impl Summary {
+    pub(super) fn new(top: usize) -> Summary {
+        Summary {
+            top,
+            events: 0,
+            symbols: Aggregate::default(),
+            drop_reasons: Aggregate::default(),
+            protocols: Aggregate::default(),
+            talkers: Aggregate::default(),
+            interfaces: Aggregate::default(),
+            ifnames: IfNames::default(),
+            evicted: 0,
+        }
+    }
+
+    fn report(&self) -> Value {
+        json!({
+            "events": self.events,
+            "symbols": self.symbols.top(self.top, false),
+            "drop_reasons": self.drop_reasons.top(self.top, false),
+            "protocols": self.protocols.top(self.top, true),
+            "talkers": self.talkers.top(self.top, true),
+            "interfaces": self.interfaces.top(self.top, true),
+        })
+    }
+}
+
+impl EventOutput for Summary {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        let json = event.to_json();
+        // Meta events are not accounted for.
+        if json.get(META_SECTION).is_some() {
+            return Ok(());
+        }
+
+        let field = |section: &str, key: &str| json.get(section).and_then(|s| s.get(key));
+        let to_string = |val: &Value| match val {
+            Value::String(s) => s.clone(),
+            val => val.to_string(),
+        };
+
+        self.events += 1;
+        if let Some(symbol) = field("common", "symbol") {
+            self.symbols.add(to_string(symbol), 0);
+        }
+        if let Some(reason) = field("skb-tracking", "drop_reason") {
+            self.drop_reasons.add(to_string(reason), 0);
+        }
+        if let Some(packet) = field("skb", "packet")
+            .and_then(|p| p.as_str())
+            .and_then(decode_packet)
+        {
+            let bytes = field("skb", "packet_len")
+                .and_then(|l| l.as_u64())
+                .unwrap_or(0);
+            self.protocols.add(packet.protocol_name(), bytes);
+            self.talkers
+                .add(format!("{} > {}", packet.saddr, packet.daddr), bytes);
+        }
+        if let Some(ifindex) = field("skb", "ifindex").and_then(|i| i.as_u64()) {
+            let bytes = field("skb", "len")
+                .or_else(|| field("skb", "packet_len"))
+                .and_then(|l| l.as_u64())
+                .unwrap_or(0);
+            let key = match self.ifnames.get(ifindex) {
+                Some(name) => format!("{}: {}", ifindex, name),
+                None => ifindex.to_string(),
+            };
+            self.interfaces.add(key, bytes);
+        }
+        Ok(())
+    }
+
+    fn summary(&self, event: &mut Event) {
+        if self.evicted > 0 {
+            event.insert(META_SECTION, event_field!("summary_evicted", self.evicted));
+        }
+    }
+
+    fn memory(&self) -> usize {
+        self.talkers.memory
+            + self.protocols.memory
+            + self.interfaces.memory
+            + self.symbols.memory
+            + self.drop_reasons.memory
+    }
+
+    fn evict(&mut self, bytes: usize) -> usize {
+        let mut freed = 0;
+        for aggregate in [
+            &mut self.talkers,
+            &mut self.protocols,
+            &mut self.interfaces,
+            &mut self.symbols,
+            &mut self.drop_reasons,
+        ] {
+            if freed >= bytes {
+                break;
+            }
+            let (bytes, evicted) = aggregate.evict(bytes - freed);
+            freed += bytes;
+            self.evicted += evicted;
+        }
+        freed
+    }
+
+    fn eviction_order(&self) -> u8 {
+        2
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let mut stdout = io::stdout();
+        writeln!(stdout, "{}", serde_json::to_string_pretty(&self.report())?)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::EventField;
+    use crate::event_field;
+
+    // Ethernet + IPv4/UDP header, 10.0.0.1 > 10.0.0.2.
+    const PACKET: &str = "00112233445566778899aabb08004500001c000040004011000\
+                          00a0000010a000002";
+
+    #[test]
+    fn aggregate() -> Result<()> {
+        let mut summary = Summary::new(1);
+
+        for (symbol, reason) in [
+            ("kfree_skb", "NO_SOCKET"),
+            ("kfree_skb", "OTHER"),
+            ("tcp_v4_rcv", ""),
+        ] {
+            let mut event = Event::new();
+            event.insert("common", event_field!("symbol", symbol.to_string()));
+            if !reason.is_empty() {
+                event.insert(
+                    "skb-tracking",
+                    event_field!("drop_reason", reason.to_string()),
+                );
+            }
+            event.insert("skb", event_field!("packet", PACKET.to_string()));
+            event.insert("skb", event_field!("packet_len", 100u32));
+            event.insert("skb", event_field!("ifindex", 1u64));
+            event.insert("skb", event_field!("len", 120u32));
+            summary.output(&event)?;
+        }
+        let mut meta = Event::new();
+        meta.insert(META_SECTION, event_field!("throttled", 0u64));
+        summary.output(&meta)?;
+
+        let report = summary.report();
+        assert!(report["events"] == 3);
+        assert!(report["symbols"] == json!([{ "key": "kfree_skb", "events": 2 }]));
+        assert!(report["drop_reasons"] == json!([{ "key": "NO_SOCKET", "events": 1 }]));
+        assert!(report["protocols"] == json!([{ "key": "udp", "events": 3, "bytes": 300 }]));
+        assert!(
+            report["talkers"]
+                == json!([{ "key": "10.0.0.1 > 10.0.0.2", "events": 3, "bytes": 300 }])
+        );
+        // Interfaces are reported with their name, here the loopback one.
+        assert!(report["interfaces"] == json!([{ "key": "1: lo", "events": 3, "bytes": 360 }]));
+        Ok(())
+    }
+}
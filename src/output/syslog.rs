@@ -0,0 +1,173 @@
+//! # Syslog
+//!
+//! Sends events to the local syslog daemon, one message per event, with a
+//! given facility and severity. This allows feeding events to existing log
+//! pipelines (e.g. journald). Syslog being unavailable is not fatal: events
+//! are dropped and accounted for, and connecting is retried at most every
+//! RECONNECT_INTERVAL.
+//!
+//! Only available when built with the "syslog" feature.
+
+use std::time::{Duration, Instant};
+
+use ::syslog::{Facility, Formatter3164, Logger, LoggerBackend};
+use anyhow::{bail, Result};
+use log::warn;
+
+use super::{format::Format, EventOutput};
+use crate::{
+    core::events::{meta::META_SECTION, Event, EventField},
+    event_field,
+};
+
+/// Minimum time between two connection attempts to syslog.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Syslog message severity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Severity {
+    Emerg,
+    Alert,
+    Crit,
+    Err,
+    Warning,
+    Notice,
+    Info,
+    Debug,
+}
+
+impl Severity {
+    pub(super) fn parse(name: &str) -> Result<Severity> {
+        Ok(match name {
+            "emerg" => Severity::Emerg,
+            "alert" => Severity::Alert,
+            "crit" => Severity::Crit,
+            "err" => Severity::Err,
+            "warning" => Severity::Warning,
+            "notice" => Severity::Notice,
+            "info" => Severity::Info,
+            "debug" => Severity::Debug,
+            x => bail!("Unknown syslog severity '{}'", x),
+        })
+    }
+}
+
+/// Parse a syslog facility name, e.g. "daemon" or "local0".
+pub(super) fn parse_facility(name: &str) -> Result<Facility> {
+    Ok(match name {
+        "user" => Facility::LOG_USER,
+        "daemon" => Facility::LOG_DAEMON,
+        "local0" => Facility::LOG_LOCAL0,
+        "local1" => Facility::LOG_LOCAL1,
+        "local2" => Facility::LOG_LOCAL2,
+        "local3" => Facility::LOG_LOCAL3,
+        "local4" => Facility::LOG_LOCAL4,
+        "local5" => Facility::LOG_LOCAL5,
+        "local6" => Facility::LOG_LOCAL6,
+        "local7" => Facility::LOG_LOCAL7,
+        x => bail!(
+            "Unknown syslog facility '{}' (expected user, daemon or local0-7)",
+            x
+        ),
+    })
+}
+
+pub(super) struct SyslogSink {
+    logger: Option<Logger<LoggerBackend, Formatter3164>>,
+    facility: Facility,
+    severity: Severity,
+    format: Format,
+    /// Last connection attempt.
+    last_connect: Instant,
+    /// Number of events which could not be sent.
+    dropped: u64,
+}
+
+impl SyslogSink {
+    pub(super) fn new(facility: Facility, severity: Severity, format: Format) -> SyslogSink {
+        let mut sink = SyslogSink {
+            logger: None,
+            facility,
+            severity,
+            format,
+            last_connect: Instant::now(),
+            dropped: 0,
+        };
+        sink.connect();
+        sink
+    }
+
+    fn connect(&mut self) {
+        self.last_connect = Instant::now();
+
+        let formatter = Formatter3164 {
+            facility: self.facility,
+            hostname: None,
+            process: env!("CARGO_PKG_NAME").to_string(),
+            pid: std::process::id(),
+        };
+        match ::syslog::unix(formatter) {
+            Ok(logger) => self.logger = Some(logger),
+            Err(e) => warn!("Could not connect to syslog, dropping events: {}", e),
+        }
+    }
+
+    fn send(&mut self, msg: String) -> bool {
+        let logger = match &mut self.logger {
+            Some(logger) => logger,
+            None => return false,
+        };
+
+        let ret = match self.severity {
+            Severity::Emerg => logger.emerg(msg),
+            Severity::Alert => logger.alert(msg),
+            Severity::Crit => logger.crit(msg),
+            Severity::Err => logger.err(msg),
+            Severity::Warning => logger.warning(msg),
+            Severity::Notice => logger.notice(msg),
+            Severity::Info => logger.info(msg),
+            Severity::Debug => logger.debug(msg),
+        };
+        if ret.is_err() {
+            // Reconnect on the next events, e.g. if syslog was restarted.
+            self.logger = None;
+            return false;
+        }
+        true
+    }
+}
+
+impl EventOutput for SyslogSink {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        if self.logger.is_none() && self.last_connect.elapsed() >= RECONNECT_INTERVAL {
+            self.connect();
+        }
+
+        let msg = match self.format.format(event) {
+            Some(msg) => msg,
+            None => return Ok(()),
+        };
+
+        if !self.send(msg) {
+            self.dropped += 1;
+        }
+        Ok(())
+    }
+
+    fn summary(&self, event: &mut Event) {
+        event.insert(META_SECTION, event_field!("syslog_dropped", self.dropped));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert!(Severity::parse("warning").unwrap() == Severity::Warning);
+        assert!(Severity::parse("warn").is_err());
+        assert!(parse_facility("local3").is_ok());
+        assert!(parse_facility("kern").is_err());
+    }
+}
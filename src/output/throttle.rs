@@ -0,0 +1,77 @@
+//! # Throttle
+//!
+//! Token bucket used to limit the rate of events being written. This is a last
+//! resort safety valve, acting in userspace after events were retrieved.
+//!
+//! Rates limited per probe are enforced by the probes themselves, see
+//! probe::common::set_probe_rate().
+
+use std::time::Instant;
+
+/// Token bucket allowing `rate` events per second, with bursts up to `rate`
+/// events.
+pub(super) struct TokenBucket {
+    rate: u64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub(super) fn new(rate: u64) -> TokenBucket {
+        TokenBucket {
+            rate,
+            tokens: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Try taking a token. Returns false if the bucket is empty, in which case
+    /// the event should be dropped.
+    pub(super) fn take(&mut self) -> bool {
+        self.take_at(Instant::now())
+    }
+
+    fn take_at(&mut self, now: Instant) -> bool {
+        // Refill the bucket given the time elapsed since the last call.
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.last = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let mut bucket = TokenBucket::new(10);
+        let start = bucket.last;
+
+        for _ in 0..10 {
+            assert!(bucket.take_at(start));
+        }
+        assert!(!bucket.take_at(start));
+
+        // 150ms later, a single token is available.
+        let now = start + Duration::from_millis(150);
+        assert!(bucket.take_at(now));
+        assert!(!bucket.take_at(now));
+
+        // Bursts are bounded to the rate.
+        let now = now + Duration::from_secs(10);
+        for _ in 0..10 {
+            assert!(bucket.take_at(now));
+        }
+        assert!(!bucket.take_at(now));
+    }
+}
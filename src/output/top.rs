@@ -0,0 +1,279 @@
+//! # Top
+//!
+//! Aggregates events instead of writing them, and renders a live view of the
+//! flows, interfaces and protocols with the highest rates, refreshed every
+//! TOP_INTERVAL. Rates are computed over the last interval only.
+//!
+//! Packets are identified using the skb-tracking collector, and accounted for
+//! once per interval even when seen by multiple probes; events without
+//! tracking information are each accounted for as a packet. Flows and
+//! protocols are based on the packet bytes (see --capture-bytes), bytes on the
+//! skb length and drops on the event classification.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use super::{packet::decode_packet, EventOutput, IfNames};
+use crate::core::events::{meta::META_SECTION, Event};
+
+/// Interval at which the view is refreshed.
+const TOP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Clear the terminal and move the cursor to its top left corner.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Counter the entries are ranked by.
+#[derive(Clone, Copy, PartialEq)]
+pub(super) enum TopSort {
+    Bytes,
+    Packets,
+    Drops,
+}
+
+impl TopSort {
+    pub(super) fn parse(name: &str) -> Result<TopSort> {
+        Ok(match name {
+            "bytes" => TopSort::Bytes,
+            "packets" => TopSort::Packets,
+            "drops" => TopSort::Drops,
+            x => bail!("Unknown top ranking {}", x),
+        })
+    }
+}
+
+/// Counters of an entry, over the current interval.
+#[derive(Clone, Copy, Default)]
+struct Counters {
+    packets: u64,
+    bytes: u64,
+    drops: u64,
+}
+
+impl Counters {
+    fn get(&self, sort: TopSort) -> u64 {
+        match sort {
+            TopSort::Bytes => self.bytes,
+            TopSort::Packets => self.packets,
+            TopSort::Drops => self.drops,
+        }
+    }
+}
+
+pub(super) struct Top {
+    sort: TopSort,
+    /// Number of entries rendered per table.
+    rows: usize,
+    /// Start of the current interval.
+    start: Instant,
+    flows: HashMap<String, Counters>,
+    interfaces: HashMap<String, Counters>,
+    protocols: HashMap<String, Counters>,
+    /// Packets (tracking ids) already accounted for in the current interval.
+    seen: HashSet<(u64, u64)>,
+    /// Interface names, by ifindex.
+    ifnames: IfNames,
+}
+
+impl Top {
+    pub(super) fn new(sort: TopSort, rows: usize) -> Top {
+        Top {
+            sort,
+            rows,
+            start: Instant::now(),
+            flows: HashMap::new(),
+            interfaces: HashMap::new(),
+            protocols: HashMap::new(),
+            seen: HashSet::new(),
+            ifnames: IfNames::default(),
+        }
+    }
+
+    /// Render the current interval, with rates computed over `elapsed`.
+    fn render(&self, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut out = String::new();
+
+        for (title, entries) in [
+            ("FLOW", &self.flows),
+            ("INTERFACE", &self.interfaces),
+            ("PROTOCOL", &self.protocols),
+        ] {
+            let mut entries: Vec<_> = entries.iter().collect();
+            entries.sort_by(|a, b| {
+                b.1.get(self.sort)
+                    .cmp(&a.1.get(self.sort))
+                    .then_with(|| a.0.cmp(b.0))
+            });
+
+            writeln!(
+                out,
+                "{:<48} {:>12} {:>12} {:>12}",
+                title, "PKTS/s", "BYTES/s", "DROPS/s"
+            )
+            .ok();
+            for (key, c) in entries.into_iter().take(self.rows) {
+                writeln!(
+                    out,
+                    "{:<48} {:>12.1} {:>12.1} {:>12.1}",
+                    key,
+                    c.packets as f64 / secs,
+                    c.bytes as f64 / secs,
+                    c.drops as f64 / secs
+                )
+                .ok();
+            }
+            writeln!(out).ok();
+        }
+        out
+    }
+
+    /// Render the view and start a new interval.
+    fn refresh(&mut self) -> Result<()> {
+        let mut stdout = io::stdout();
+        write!(
+            stdout,
+            "{}{}",
+            CLEAR_SCREEN,
+            self.render(self.start.elapsed())
+        )?;
+        stdout.flush()?;
+
+        self.flows.clear();
+        self.interfaces.clear();
+        self.protocols.clear();
+        self.seen.clear();
+        self.start = Instant::now();
+        Ok(())
+    }
+}
+
+impl EventOutput for Top {
+    fn output(&mut self, event: &Event) -> Result<()> {
+        let json = event.to_json();
+        // Meta events are not accounted for.
+        if json.get(META_SECTION).is_some() {
+            return Ok(());
+        }
+
+        let field = |section: &str, key: &str| json.get(section).and_then(|s| s.get(key));
+
+        // Packets already seen in this interval are only accounted for drops.
+        let new = match (
+            field("skb-tracking", "orig_head").and_then(Value::as_u64),
+            field("skb-tracking", "timestamp").and_then(Value::as_u64),
+        ) {
+            (Some(head), Some(ts)) => self.seen.insert((head, ts)),
+            _ => true,
+        };
+        let bytes = field("skb", "len")
+            .or_else(|| field("skb", "packet_len"))
+            .and_then(Value::as_u64)
+            .unwrap_or(0);
+        let counters = Counters {
+            packets: new as u64,
+            bytes: if new { bytes } else { 0 },
+            drops: (field("common", "category").and_then(Value::as_str) == Some("drop")) as u64,
+        };
+
+        let mut keys = Vec::new();
+        if let Some(packet) = field("skb", "packet")
+            .and_then(Value::as_str)
+            .and_then(decode_packet)
+        {
+            let protocol = packet.protocol_name();
+            keys.push((
+                0,
+                format!("{} {} > {}", protocol, packet.saddr, packet.daddr),
+            ));
+            keys.push((2, protocol));
+        }
+        if let Some(ifindex) = field("skb", "ifindex").and_then(Value::as_u64) {
+            let name = match self.ifnames.get(ifindex) {
+                Some(name) => name.to_string(),
+                None => format!("ifindex {}", ifindex),
+            };
+            keys.push((1, name));
+        }
+
+        for (table, key) in keys.into_iter() {
+            let table = match table {
+                0 => &mut self.flows,
+                1 => &mut self.interfaces,
+                _ => &mut self.protocols,
+            };
+            let entry = table.entry(key).or_default();
+            entry.packets += counters.packets;
+            entry.bytes += counters.bytes;
+            entry.drops += counters.drops;
+        }
+
+        if self.start.elapsed() >= TOP_INTERVAL {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.start.elapsed() >= TOP_INTERVAL {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.refresh()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::EventField;
+    use crate::event_field;
+
+    // Ethernet + IPv4/UDP header, 10.0.0.1 > 10.0.0.2.
+    const PACKET: &str = "00112233445566778899aabb08004500001c000040004011000\
+                          00a0000010a000002";
+
+    #[test]
+    fn rank() -> Result<()> {
+        let mut top = Top::new(TopSort::Packets, 10);
+
+        // The same packet seen by two probes, then dropped; and another one.
+        for (head, drop) in [(1u64, false), (1, true), (2, false)] {
+            let mut event = Event::new();
+            event.insert("skb-tracking", event_field!("orig_head", head));
+            event.insert("skb-tracking", event_field!("timestamp", 42u64));
+            event.insert("skb", event_field!("packet", PACKET.to_string()));
+            event.insert("skb", event_field!("len", 100u32));
+            if drop {
+                event.insert("common", event_field!("category", "drop".to_string()));
+            }
+            top.output(&event)?;
+        }
+
+        let flow = top.flows["udp 10.0.0.1 > 10.0.0.2"];
+        assert!(flow.packets == 2 && flow.bytes == 200 && flow.drops == 1);
+        assert!(top.protocols["udp"].packets == 2);
+        assert!(top.interfaces.is_empty());
+
+        let view = top.render(Duration::from_secs(2));
+        assert!(view.contains("udp 10.0.0.1 > 10.0.0.2"));
+        assert!(view
+            .lines()
+            .nth(1)
+            .unwrap()
+            .ends_with("1.0        100.0          0.5"));
+
+        assert!(TopSort::parse("drops").is_ok());
+        assert!(TopSort::parse("flows").is_err());
+        Ok(())
+    }
+}
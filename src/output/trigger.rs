@@ -0,0 +1,163 @@
+//! # Trigger
+//!
+//! Defer writing events until a trigger condition is met, e.g. a drop with a
+//! given reason. Until then the last events are kept in a pre-trigger ring, so
+//! the moments leading to the trigger can be reported. Once triggered, the
+//! ring is flushed and events are written for a given duration, after which
+//! the trigger is re-armed.
+
+use std::{
+    cmp::Ordering,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+
+use super::{filter::compare, ring::Ring};
+use crate::core::events::Event;
+
+/// Condition on an event field, "section.key=value".
+pub(super) struct TriggerCondition {
+    section: String,
+    key: String,
+    value: String,
+}
+
+impl TriggerCondition {
+    pub(super) fn parse(def: &str) -> Result<TriggerCondition> {
+        let (field, value) = match def.split_once('=') {
+            Some((field, value)) => (field.trim(), value.trim()),
+            None => bail!("Invalid trigger '{}': expected section.key=value", def),
+        };
+        let (section, key) = match field.split_once('.') {
+            Some((section, key)) if !section.is_empty() && !key.is_empty() => (section, key),
+            _ => bail!("Invalid trigger '{}': expected section.key=value", def),
+        };
+
+        Ok(TriggerCondition {
+            section: section.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    /// Does an event match the condition? Numbers are compared numerically,
+    /// other values using their textual representation.
+    fn matches(&self, event: &Event) -> bool {
+        event
+            .get_field(Some(&self.section), &self.key)
+            .and_then(|f| compare(f, &self.value))
+            == Some(Ordering::Equal)
+    }
+}
+
+pub(super) struct Trigger {
+    condition: TriggerCondition,
+    /// Pre-trigger ring, holding the last events.
+    ring: Ring<Event>,
+    /// Duration events are written after a trigger.
+    post: Duration,
+    /// When set, the trigger fired and events are written until then.
+    until: Option<Instant>,
+    /// Number of times the trigger fired.
+    pub(super) fired: u64,
+}
+
+impl Trigger {
+    pub(super) fn new(condition: TriggerCondition, capacity: usize, post: Duration) -> Trigger {
+        Trigger {
+            condition,
+            ring: Ring::new(capacity),
+            post,
+            until: None,
+            fired: 0,
+        }
+    }
+
+    /// Process an event and return the events to write, if any.
+    pub(super) fn process(&mut self, event: Event) -> Vec<Event> {
+        self.process_at(event, Instant::now())
+    }
+
+    fn process_at(&mut self, event: Event, now: Instant) -> Vec<Event> {
+        if let Some(until) = self.until {
+            if now < until {
+                return vec![event];
+            }
+            self.until = None;
+        }
+
+        if self.condition.matches(&event) {
+            self.until = Some(now + self.post);
+            self.fired += 1;
+
+            let mut events: Vec<Event> = self.ring.drain().collect();
+            events.push(event);
+            return events;
+        }
+
+        self.ring.push(event);
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::events::EventField;
+    use crate::event_field;
+
+    fn event(id: u64, reason: &str) -> Event {
+        let mut event = Event::new();
+        event.insert("test", event_field!("id", id));
+        event.insert("test", event_field!("reason", reason.to_string()));
+        event
+    }
+
+    fn ids(events: &[Event]) -> Vec<u64> {
+        events
+            .iter()
+            .map(|e| *e.get::<u64>("test", "id").unwrap().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn condition() -> Result<()> {
+        assert!(TriggerCondition::parse("test.reason=NO_SOCKET")?.matches(&event(0, "NO_SOCKET")));
+        assert!(TriggerCondition::parse("test.id=42")?.matches(&event(42, "")));
+        assert!(!TriggerCondition::parse("test.id=42")?.matches(&event(41, "")));
+        assert!(!TriggerCondition::parse("other.id=42")?.matches(&event(42, "")));
+
+        assert!(TriggerCondition::parse("test.id").is_err());
+        assert!(TriggerCondition::parse("id=42").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn trigger() -> Result<()> {
+        let mut trigger = Trigger::new(
+            TriggerCondition::parse("test.reason=NO_SOCKET")?,
+            2,
+            Duration::from_secs(1),
+        );
+        let start = Instant::now();
+
+        // Events are held until the trigger fires, only the last ones are
+        // kept.
+        for id in 0..3 {
+            assert!(trigger.process_at(event(id, ""), start).is_empty());
+        }
+        let events = trigger.process_at(event(3, "NO_SOCKET"), start);
+        assert!(ids(&events) == vec![1, 2, 3]);
+
+        // Events are written during the post-trigger duration.
+        let now = start + Duration::from_millis(500);
+        assert!(ids(&trigger.process_at(event(4, ""), now)) == vec![4]);
+
+        // Then the trigger is re-armed.
+        let now = start + Duration::from_secs(2);
+        assert!(trigger.process_at(event(5, ""), now).is_empty());
+        assert!(trigger.fired == 1);
+        Ok(())
+    }
+}
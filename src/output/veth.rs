@@ -0,0 +1,198 @@
+//! # Veth peers
+//!
+//! Annotate events of packets on veth interfaces (see the skb collector
+//! ifindex field) with the ifindex of the other end of the pair, as
+//! `skb.veth_peer`. A packet sent on one end shows up on the other end next,
+//! which allows following it across the pair, e.g. from a container to the
+//! host, as a single hop (see --path).
+//!
+//! Peers are found using /sys/class/net/<if>/iflink, for interfaces of the
+//! current network namespace using the veth driver. The peer ifindex is the
+//! one in the peer's own namespace, and can thus designate an interface of
+//! another namespace: this is what events generated there report. Interfaces
+//! are rescanned, at most every VETH_RESCAN_INTERVAL, when an unknown ifindex
+//! shows up.
+
+use std::{
+    collections::HashMap,
+    fs, mem,
+    time::{Duration, Instant},
+};
+
+use nix::libc;
+
+use crate::{
+    core::events::{Event, EventField},
+    event_field,
+};
+
+/// Minimum interval between two scans of the interfaces.
+const VETH_RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interfaces scanning function, returning the peer of each interface if it
+/// is a veth one.
+type Scan = fn() -> HashMap<u32, Option<u32>>;
+
+pub(super) struct VethPeers {
+    /// Peer ifindex of the known interfaces, if they are veth ones.
+    peers: HashMap<u32, Option<u32>>,
+    /// When the interfaces were last scanned.
+    scanned: Option<Instant>,
+    scan: Scan,
+}
+
+impl VethPeers {
+    pub(super) fn new() -> VethPeers {
+        Self::with_scan(scan_interfaces)
+    }
+
+    fn with_scan(scan: Scan) -> VethPeers {
+        VethPeers {
+            peers: HashMap::new(),
+            scanned: None,
+            scan,
+        }
+    }
+
+    pub(super) fn annotate(&mut self, event: &mut Event) {
+        let ifindex = match event.get::<u32>("skb", "ifindex") {
+            Ok(Some(ifindex)) => *ifindex,
+            _ => return,
+        };
+
+        if !self.peers.contains_key(&ifindex)
+            && self
+                .scanned
+                .map_or(true, |at| at.elapsed() >= VETH_RESCAN_INTERVAL)
+        {
+            self.peers = (self.scan)();
+            self.scanned = Some(Instant::now());
+        }
+
+        if let Some(Some(peer)) = self.peers.get(&ifindex) {
+            event.insert("skb", event_field!("veth_peer", *peer));
+        }
+    }
+}
+
+/// Scan the interfaces of the current network namespace, returning the peer
+/// ifindex of veth ones.
+fn scan_interfaces() -> HashMap<u32, Option<u32>> {
+    let mut peers = HashMap::new();
+
+    let entries = match fs::read_dir("/sys/class/net") {
+        Ok(entries) => entries,
+        Err(_) => return peers,
+    };
+    // Interfaces can be removed while we're scanning them, ignore errors.
+    for entry in entries.flatten() {
+        let read = |file: &str| {
+            fs::read_to_string(entry.path().join(file))
+                .ok()
+                .and_then(|x| x.trim().parse::<u32>().ok())
+        };
+        let (ifindex, iflink) = match (read("ifindex"), read("iflink")) {
+            (Some(ifindex), Some(iflink)) => (ifindex, iflink),
+            _ => continue,
+        };
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        let peer = match iflink != ifindex && driver(&name).as_deref() == Some("veth") {
+            true => Some(iflink),
+            false => None,
+        };
+        peers.insert(ifindex, peer);
+    }
+    peers
+}
+
+/// Get the driver of an interface, using the ethtool API.
+fn driver(name: &str) -> Option<String> {
+    // Please keep in sync with its kernel counterpart (struct ethtool_drvinfo)
+    // in include/uapi/linux/ethtool.h
+    #[repr(C)]
+    struct DrvInfo {
+        cmd: u32,
+        driver: [u8; 32],
+        version: [u8; 32],
+        fw_version: [u8; 32],
+        bus_info: [u8; 32],
+        erom_version: [u8; 32],
+        reserved2: [u8; 12],
+        n_priv_flags: u32,
+        n_stats: u32,
+        testinfo_len: u32,
+        eedump_len: u32,
+        regdump_len: u32,
+    }
+    // Please keep in sync with its kernel counterpart (struct ifreq) in
+    // include/uapi/linux/if.h
+    #[repr(C)]
+    struct IfReq {
+        name: [u8; libc::IFNAMSIZ],
+        data: *mut DrvInfo,
+        _pad: [u8; 16],
+    }
+    const SIOCETHTOOL: libc::c_ulong = 0x8946;
+    const ETHTOOL_GDRVINFO: u32 = 0x3;
+
+    if name.len() >= libc::IFNAMSIZ {
+        return None;
+    }
+
+    // Safety: both structures are plain old data, for which zero is valid.
+    let mut info: DrvInfo = unsafe { mem::zeroed() };
+    info.cmd = ETHTOOL_GDRVINFO;
+    let mut req: IfReq = unsafe { mem::zeroed() };
+    req.name[..name.len()].copy_from_slice(name.as_bytes());
+    req.data = &mut info;
+
+    // Safety: the request points to valid structures for the duration of the
+    // call, and the socket is closed right after.
+    let ret = unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return None;
+        }
+        let ret = libc::ioctl(fd, SIOCETHTOOL as _, &mut req);
+        libc::close(fd);
+        ret
+    };
+    if ret < 0 {
+        return None;
+    }
+
+    let len = info.driver.iter().position(|c| *c == 0).unwrap_or(32);
+    Some(String::from_utf8_lossy(&info.driver[..len]).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotate() {
+        // veth0 (2) <-> veth1 (3), veth2 (4) with a peer in another namespace
+        // and a non-veth interface (1).
+        let mut peers = VethPeers::with_scan(|| {
+            HashMap::from([(1, None), (2, Some(3)), (3, Some(2)), (4, Some(2))])
+        });
+
+        let event = |ifindex: u32| {
+            let mut event = Event::new();
+            event.insert("skb", event_field!("ifindex", ifindex));
+            event
+        };
+
+        for (ifindex, peer) in [(1, None), (2, Some(3)), (4, Some(2)), (5, None)] {
+            let mut event = event(ifindex);
+            peers.annotate(&mut event);
+            assert!(event.get::<u32>("skb", "veth_peer").unwrap().copied() == peer);
+        }
+
+        // Events without a device are left as-is.
+        let mut event = Event::new();
+        peers.annotate(&mut event);
+        assert!(event.get::<u32>("skb", "veth_peer").unwrap().is_none());
+    }
+}